@@ -0,0 +1,438 @@
+// PTP/IP相机模拟器 - 在主机上把一个图片目录模拟成相机，供固件和客户端做端到端测试
+mod ptpip;
+mod store;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use ptpip::{packet, Dataset, OperationRequest};
+use store::{ObjectStore, FORMAT_EXIF_JPEG, STORAGE_ID};
+
+/// PTP/IP默认端口
+const DEFAULT_PORT: u16 = 15740;
+
+/// 目录扫描间隔
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 操作码
+mod op {
+    pub const GET_DEVICE_INFO: u16 = 0x1001;
+    pub const OPEN_SESSION: u16 = 0x1002;
+    pub const CLOSE_SESSION: u16 = 0x1003;
+    pub const GET_STORAGE_IDS: u16 = 0x1004;
+    pub const GET_STORAGE_INFO: u16 = 0x1005;
+    pub const GET_NUM_OBJECTS: u16 = 0x1006;
+    pub const GET_OBJECT_HANDLES: u16 = 0x1007;
+    pub const GET_OBJECT_INFO: u16 = 0x1008;
+    pub const GET_OBJECT: u16 = 0x1009;
+    pub const GET_THUMB: u16 = 0x100A;
+    pub const INITIATE_CAPTURE: u16 = 0x100E;
+
+    pub const SUPPORTED: [u16; 11] = [
+        GET_DEVICE_INFO,
+        OPEN_SESSION,
+        CLOSE_SESSION,
+        GET_STORAGE_IDS,
+        GET_STORAGE_INFO,
+        GET_NUM_OBJECTS,
+        GET_OBJECT_HANDLES,
+        GET_OBJECT_INFO,
+        GET_OBJECT,
+        GET_THUMB,
+        INITIATE_CAPTURE,
+    ];
+}
+
+/// 响应码
+mod rc {
+    pub const OK: u16 = 0x2001;
+    pub const GENERAL_ERROR: u16 = 0x2002;
+    pub const SESSION_NOT_OPEN: u16 = 0x2003;
+    pub const OPERATION_NOT_SUPPORTED: u16 = 0x2005;
+    pub const INVALID_STORAGE_ID: u16 = 0x2008;
+    pub const INVALID_OBJECT_HANDLE: u16 = 0x2009;
+    pub const STORE_FULL: u16 = 0x200C;
+    pub const NO_THUMBNAIL_PRESENT: u16 = 0x2010;
+    pub const SESSION_ALREADY_OPEN: u16 = 0x201E;
+}
+
+/// 事件码
+mod ev {
+    pub const OBJECT_ADDED: u16 = 0x4002;
+    pub const CAPTURE_COMPLETE: u16 = 0x400D;
+
+    pub const SUPPORTED: [u16; 2] = [OBJECT_ADDED, CAPTURE_COMPLETE];
+}
+
+struct Options {
+    dir: PathBuf,
+    port: u16,
+    name: String,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = env::args().skip(1);
+    let mut dir = None;
+    let mut port = DEFAULT_PORT;
+    let mut name = "Fake Camera".to_string();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .ok_or("--port 需要一个端口号")?;
+            }
+            "--name" => name = args.next().ok_or("--name 需要一个名称")?,
+            "-h" | "--help" => return Err(String::new()),
+            _ if dir.is_none() => dir = Some(PathBuf::from(arg)),
+            _ => return Err(format!("未知参数: {}", arg)),
+        }
+    }
+    Ok(Options {
+        dir: dir.ok_or("缺少图片目录")?,
+        port,
+        name,
+    })
+}
+
+/// 服务端共享状态
+struct Shared {
+    name: String,
+    guid: [u8; 16],
+    store: Mutex<ObjectStore>,
+    event_streams: Mutex<Vec<TcpStream>>,
+}
+
+impl Shared {
+    /// 向所有事件通道广播事件，写失败的连接被移除
+    fn broadcast(&self, code: u16, tid: u32, params: &[u32]) {
+        let mut streams = self.event_streams.lock().unwrap();
+        streams.retain_mut(|s| ptpip::write_event(s, code, tid, params).is_ok());
+    }
+}
+
+fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}", e);
+            }
+            eprintln!("用法: fake-camera <图片目录> [--port 端口] [--name 设备名]");
+            process::exit(2);
+        }
+    };
+
+    let store = match ObjectStore::open(&options.dir) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("无法打开目录 {}: {}", options.dir.display(), e);
+            process::exit(1);
+        }
+    };
+    println!("已加载 {} 个对象，目录 {}", store.objects().len(), options.dir.display());
+
+    // 以进程号和时间构造固定格式的GUID，足以区分同一主机上的多个实例
+    let mut guid = [0u8; 16];
+    guid[..4].copy_from_slice(&process::id().to_le_bytes());
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    guid[4..12].copy_from_slice(&nanos.to_le_bytes());
+
+    let shared = Arc::new(Shared {
+        name: options.name,
+        guid,
+        store: Mutex::new(store),
+        event_streams: Mutex::new(Vec::new()),
+    });
+
+    // 目录监视线程：新文件作为ObjectAdded事件上报
+    let watcher = Arc::clone(&shared);
+    thread::spawn(move || loop {
+        thread::sleep(SCAN_INTERVAL);
+        let added = match watcher.store.lock().unwrap().rescan() {
+            Ok(added) => added,
+            Err(e) => {
+                eprintln!("扫描目录失败: {}", e);
+                continue;
+            }
+        };
+        for handle in added {
+            println!("新对象 0x{:08x}", handle);
+            watcher.broadcast(ev::OBJECT_ADDED, ptpip::NO_TRANSACTION, &[handle]);
+        }
+    });
+
+    let listener = match TcpListener::bind(("0.0.0.0", options.port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("无法监听端口 {}: {}", options.port, e);
+            process::exit(1);
+        }
+    };
+    println!("PTP/IP响应端已启动，端口 {}", options.port);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("接受连接失败: {}", e);
+                continue;
+            }
+        };
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+            if let Err(e) = handle_connection(stream, &shared) {
+                eprintln!("连接 {} 出错: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// 根据第一个报文区分命令通道和事件通道
+fn handle_connection(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let first = match ptpip::read_packet(&mut stream)? {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    match first.kind {
+        packet::INIT_COMMAND_REQUEST => {
+            let (initiator, _) = ptpip::read_utf16z(first.payload.get(16..).unwrap_or_default());
+            println!("发起端已连接: {}", initiator);
+
+            let mut ack = Vec::new();
+            ack.extend_from_slice(&1u32.to_le_bytes()); // 连接编号
+            ack.extend_from_slice(&shared.guid);
+            ptpip::put_utf16z(&mut ack, &shared.name);
+            ack.extend_from_slice(&ptpip::PROTOCOL_VERSION.to_le_bytes());
+            ptpip::write_packet(&mut stream, packet::INIT_COMMAND_ACK, &ack)?;
+            command_loop(stream, shared)
+        }
+        packet::INIT_EVENT_REQUEST => {
+            ptpip::write_packet(&mut stream, packet::INIT_EVENT_ACK, &[])?;
+            shared.event_streams.lock().unwrap().push(stream.try_clone()?);
+            println!("事件通道已建立");
+            // 事件通道上只需要应答探测请求
+            while let Some(p) = ptpip::read_packet(&mut stream)? {
+                if p.kind == packet::PROBE_REQUEST {
+                    ptpip::write_packet(&mut stream, packet::PROBE_RESPONSE, &[])?;
+                }
+            }
+            Ok(())
+        }
+        other => {
+            ptpip::write_packet(&mut stream, packet::INIT_FAIL, &1u32.to_le_bytes())?;
+            Err(io::Error::new(io::ErrorKind::InvalidData, format!("意外的首个报文类型: {}", other)))
+        }
+    }
+}
+
+/// 命令通道主循环
+fn command_loop(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let mut session_open = false;
+    while let Some(p) = ptpip::read_packet(&mut stream)? {
+        match p.kind {
+            packet::OPERATION_REQUEST => {
+                let req = OperationRequest::parse(&p.payload)?;
+                if req.data_phase == OperationRequest::DATA_OUT {
+                    drain_data_phase(&mut stream)?;
+                }
+                let mut params = Vec::new();
+                let code = handle_operation(&mut stream, shared, &req, &mut session_open, &mut params)?;
+                ptpip::write_response(&mut stream, code, req.tid, &params)?;
+            }
+            packet::PROBE_REQUEST => ptpip::write_packet(&mut stream, packet::PROBE_RESPONSE, &[])?,
+            packet::CANCEL => {}
+            other => eprintln!("忽略命令通道上的报文类型 {}", other),
+        }
+    }
+    println!("发起端已断开");
+    Ok(())
+}
+
+/// 丢弃发起端发来的数据阶段（本模拟器不支持写入操作）
+fn drain_data_phase(stream: &mut TcpStream) -> io::Result<()> {
+    while let Some(p) = ptpip::read_packet(stream)? {
+        if p.kind == packet::END_DATA || p.kind == packet::CANCEL {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// 执行一个操作，需要数据阶段的操作在此直接发送数据
+/// 返回响应码，响应参数写入 `params`
+fn handle_operation(
+    stream: &mut TcpStream,
+    shared: &Shared,
+    req: &OperationRequest,
+    session_open: &mut bool,
+    params: &mut Vec<u32>,
+) -> io::Result<u16> {
+    match req.code {
+        op::GET_DEVICE_INFO => {
+            ptpip::write_data_phase(stream, req.tid, &device_info(shared))?;
+            return Ok(rc::OK);
+        }
+        op::OPEN_SESSION => {
+            if *session_open {
+                return Ok(rc::SESSION_ALREADY_OPEN);
+            }
+            *session_open = true;
+            return Ok(rc::OK);
+        }
+        _ if !*session_open => return Ok(rc::SESSION_NOT_OPEN),
+        _ => {}
+    }
+
+    let mut store = shared.store.lock().unwrap();
+    match req.code {
+        op::CLOSE_SESSION => {
+            *session_open = false;
+            Ok(rc::OK)
+        }
+        op::GET_STORAGE_IDS => {
+            let mut data = Dataset::default();
+            data.u32_array(&[STORAGE_ID]);
+            ptpip::write_data_phase(stream, req.tid, &data.buf)?;
+            Ok(rc::OK)
+        }
+        op::GET_STORAGE_INFO => {
+            if req.param(0) != STORAGE_ID {
+                return Ok(rc::INVALID_STORAGE_ID);
+            }
+            let capacity: u64 = 64 * 1024 * 1024 * 1024;
+            let mut data = Dataset::default();
+            data.u16(0x0004) // 可移动RAM
+                .u16(0x0002) // 通用层级文件系统
+                .u16(0x0000) // 可读写
+                .u64(capacity)
+                .u64(capacity.saturating_sub(store.used_bytes()))
+                .u32(0xFFFF_FFFF)
+                .str("SD")
+                .str(&store.dir().display().to_string());
+            ptpip::write_data_phase(stream, req.tid, &data.buf)?;
+            Ok(rc::OK)
+        }
+        op::GET_NUM_OBJECTS | op::GET_OBJECT_HANDLES => {
+            let storage = req.param(0);
+            if storage != 0xFFFF_FFFF && storage != STORAGE_ID {
+                return Ok(rc::INVALID_STORAGE_ID);
+            }
+            let format = req.param(1) as u16;
+            let handles: Vec<u32> = store
+                .objects()
+                .iter()
+                .filter(|o| format == 0 || o.format() == format)
+                .map(|o| o.handle)
+                .collect();
+            if req.code == op::GET_NUM_OBJECTS {
+                params.push(handles.len() as u32);
+                return Ok(rc::OK);
+            }
+            let mut data = Dataset::default();
+            data.u32_array(&handles);
+            ptpip::write_data_phase(stream, req.tid, &data.buf)?;
+            Ok(rc::OK)
+        }
+        op::GET_OBJECT_INFO => {
+            let object = match store.get(req.param(0)) {
+                Some(o) => o,
+                None => return Ok(rc::INVALID_OBJECT_HANDLE),
+            };
+            let is_jpeg = object.format() == FORMAT_EXIF_JPEG;
+            let date = object.date_string();
+            let mut data = Dataset::default();
+            data.u32(STORAGE_ID)
+                .u16(object.format())
+                .u16(0) // 无保护
+                .u32(object.size.min(u32::MAX as u64) as u32)
+                .u16(if is_jpeg { FORMAT_EXIF_JPEG } else { 0 })
+                .u32(if is_jpeg { object.size.min(u32::MAX as u64) as u32 } else { 0 })
+                .u32(0)
+                .u32(0)
+                .u32(0)
+                .u32(0)
+                .u32(0)
+                .u32(0) // 父对象: 根目录
+                .u16(0)
+                .u32(0)
+                .u32(0)
+                .str(&object.filename)
+                .str(&date)
+                .str(&date)
+                .str("");
+            ptpip::write_data_phase(stream, req.tid, &data.buf)?;
+            Ok(rc::OK)
+        }
+        op::GET_OBJECT | op::GET_THUMB => {
+            let object = match store.get(req.param(0)) {
+                Some(o) => o.clone(),
+                None => return Ok(rc::INVALID_OBJECT_HANDLE),
+            };
+            // 缩略图直接使用原JPEG，客户端自行缩放
+            if req.code == op::GET_THUMB && object.format() != FORMAT_EXIF_JPEG {
+                return Ok(rc::NO_THUMBNAIL_PRESENT);
+            }
+            drop(store);
+            match fs::read(&object.path) {
+                Ok(bytes) => {
+                    ptpip::write_data_phase(stream, req.tid, &bytes)?;
+                    Ok(rc::OK)
+                }
+                Err(e) => {
+                    eprintln!("读取 {} 失败: {}", object.path.display(), e);
+                    Ok(rc::GENERAL_ERROR)
+                }
+            }
+        }
+        op::INITIATE_CAPTURE => match store.capture() {
+            Ok(Some(handle)) => {
+                drop(store);
+                println!("模拟拍摄，新对象 0x{:08x}", handle);
+                // 拍摄产生的事件带InitiateCapture的事务ID，CaptureComplete没有参数
+                shared.broadcast(ev::OBJECT_ADDED, req.tid, &[handle]);
+                shared.broadcast(ev::CAPTURE_COMPLETE, req.tid, &[]);
+                Ok(rc::OK)
+            }
+            Ok(None) => Ok(rc::STORE_FULL),
+            Err(e) => {
+                eprintln!("模拟拍摄失败: {}", e);
+                Ok(rc::GENERAL_ERROR)
+            }
+        },
+        _ => Ok(rc::OPERATION_NOT_SUPPORTED),
+    }
+}
+
+/// DeviceInfo数据集
+fn device_info(shared: &Shared) -> Vec<u8> {
+    let mut data = Dataset::default();
+    data.u16(100) // PTP 1.00
+        .u32(0) // 无厂商扩展
+        .u16(0)
+        .str("")
+        .u16(0)
+        .u16_array(&op::SUPPORTED)
+        .u16_array(&ev::SUPPORTED)
+        .u16_array(&[])
+        .u16_array(&[FORMAT_EXIF_JPEG])
+        .u16_array(&[FORMAT_EXIF_JPEG, store::FORMAT_PNG, store::FORMAT_TIFF, store::FORMAT_UNDEFINED])
+        .str("r-camera")
+        .str(&shared.name)
+        .str(env!("CARGO_PKG_VERSION"))
+        .str("FAKE0001");
+    data.buf
+}
@@ -0,0 +1,214 @@
+// PTP/IP报文编解码 - 所有整数均为小端序
+use std::io::{self, Read, Write};
+
+/// PTP/IP报文类型
+pub mod packet {
+    pub const INIT_COMMAND_REQUEST: u32 = 1;
+    pub const INIT_COMMAND_ACK: u32 = 2;
+    pub const INIT_EVENT_REQUEST: u32 = 3;
+    pub const INIT_EVENT_ACK: u32 = 4;
+    pub const INIT_FAIL: u32 = 5;
+    pub const OPERATION_REQUEST: u32 = 6;
+    pub const OPERATION_RESPONSE: u32 = 7;
+    pub const EVENT: u32 = 8;
+    pub const START_DATA: u32 = 9;
+    pub const DATA: u32 = 10;
+    pub const CANCEL: u32 = 11;
+    pub const END_DATA: u32 = 12;
+    pub const PROBE_REQUEST: u32 = 13;
+    pub const PROBE_RESPONSE: u32 = 14;
+}
+
+/// PTP/IP协议版本 1.0
+pub const PROTOCOL_VERSION: u32 = 0x0001_0000;
+
+/// 与任何操作无关的事件使用的事务ID
+pub const NO_TRANSACTION: u32 = 0xFFFF_FFFF;
+
+/// 单个Data报文的最大负载
+const MAX_DATA_CHUNK: usize = 64 * 1024;
+
+/// 报文长度上限，防止异常长度导致大量分配
+const MAX_PACKET_LEN: usize = 1024 * 1024;
+
+/// 一个PTP/IP报文
+#[derive(Debug)]
+pub struct Packet {
+    pub kind: u32,
+    pub payload: Vec<u8>,
+}
+
+/// 读取一个报文，连接关闭时返回None
+pub fn read_packet(stream: &mut impl Read) -> io::Result<Option<Packet>> {
+    let mut header = [0u8; 8];
+    match stream.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let kind = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    if !(8..=MAX_PACKET_LEN).contains(&len) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("报文长度无效: {}", len)));
+    }
+    let mut payload = vec![0u8; len - 8];
+    stream.read_exact(&mut payload)?;
+    Ok(Some(Packet { kind, payload }))
+}
+
+/// 写出一个报文
+pub fn write_packet(stream: &mut impl Write, kind: u32, payload: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(8 + payload.len());
+    buf.extend_from_slice(&((8 + payload.len()) as u32).to_le_bytes());
+    buf.extend_from_slice(&kind.to_le_bytes());
+    buf.extend_from_slice(payload);
+    stream.write_all(&buf)
+}
+
+/// 发送数据阶段: StartData，若干Data，最后EndData
+pub fn write_data_phase(stream: &mut impl Write, tid: u32, data: &[u8]) -> io::Result<()> {
+    let mut start = Vec::with_capacity(12);
+    start.extend_from_slice(&tid.to_le_bytes());
+    start.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    write_packet(stream, packet::START_DATA, &start)?;
+
+    let mut chunks = data.chunks(MAX_DATA_CHUNK).peekable();
+    if chunks.peek().is_none() {
+        return write_packet(stream, packet::END_DATA, &tid.to_le_bytes());
+    }
+    while let Some(chunk) = chunks.next() {
+        let kind = if chunks.peek().is_some() { packet::DATA } else { packet::END_DATA };
+        let mut buf = Vec::with_capacity(4 + chunk.len());
+        buf.extend_from_slice(&tid.to_le_bytes());
+        buf.extend_from_slice(chunk);
+        write_packet(stream, kind, &buf)?;
+    }
+    Ok(())
+}
+
+/// 发送操作响应
+pub fn write_response(stream: &mut impl Write, code: u16, tid: u32, params: &[u32]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(6 + params.len() * 4);
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&tid.to_le_bytes());
+    for p in params {
+        buf.extend_from_slice(&p.to_le_bytes());
+    }
+    write_packet(stream, packet::OPERATION_RESPONSE, &buf)
+}
+
+/// 发送事件，`tid`为引起该事件的操作的事务ID，与操作无关的事件使用`NO_TRANSACTION`
+pub fn write_event(stream: &mut impl Write, code: u16, tid: u32, params: &[u32]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(6 + params.len() * 4);
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&tid.to_le_bytes());
+    for p in params {
+        buf.extend_from_slice(&p.to_le_bytes());
+    }
+    write_packet(stream, packet::EVENT, &buf)
+}
+
+/// 操作请求
+#[derive(Debug)]
+pub struct OperationRequest {
+    pub data_phase: u32, // 1: 无数据或数据由响应端发出, 2: 数据由发起端发出
+    pub code: u16,
+    pub tid: u32,
+    pub params: Vec<u32>,
+}
+
+impl OperationRequest {
+    /// 数据阶段由发起端发送
+    pub const DATA_OUT: u32 = 2;
+
+    pub fn parse(payload: &[u8]) -> io::Result<Self> {
+        if payload.len() < 10 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "操作请求过短"));
+        }
+        let data_phase = u32_at(payload, 0);
+        let code = u16::from_le_bytes([payload[4], payload[5]]);
+        let tid = u32_at(payload, 6);
+        let params = payload[10..].chunks_exact(4).map(|c| u32_at(c, 0)).collect();
+        Ok(OperationRequest { data_phase, code, tid, params })
+    }
+
+    /// 第n个参数，缺省为0
+    pub fn param(&self, n: usize) -> u32 {
+        self.params.get(n).copied().unwrap_or(0)
+    }
+}
+
+pub fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+/// 读取以0结尾的UTF-16LE字符串，返回字符串和消耗的字节数
+pub fn read_utf16z(buf: &[u8]) -> (String, usize) {
+    let units: Vec<u16> = buf
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|u| *u != 0)
+        .collect();
+    let consumed = ((units.len() + 1) * 2).min(buf.len());
+    (String::from_utf16_lossy(&units), consumed)
+}
+
+/// 写入以0结尾的UTF-16LE字符串（PTP/IP握手使用，无长度前缀）
+pub fn put_utf16z(buf: &mut Vec<u8>, s: &str) {
+    for u in s.encode_utf16().chain(std::iter::once(0)) {
+        buf.extend_from_slice(&u.to_le_bytes());
+    }
+}
+
+/// PTP数据集编码器
+#[derive(Default)]
+pub struct Dataset {
+    pub buf: Vec<u8>,
+}
+
+impl Dataset {
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    /// PTP字符串: 字符数(含结尾0, u8) + UTF-16LE；空字符串只写一个0
+    pub fn str(&mut self, s: &str) -> &mut Self {
+        let units: Vec<u16> = s.encode_utf16().take(254).collect();
+        if units.is_empty() {
+            self.buf.push(0);
+            return self;
+        }
+        self.buf.push((units.len() + 1) as u8);
+        for u in units.iter().chain(std::iter::once(&0)) {
+            self.buf.extend_from_slice(&u.to_le_bytes());
+        }
+        self
+    }
+
+    pub fn u16_array(&mut self, values: &[u16]) -> &mut Self {
+        self.u32(values.len() as u32);
+        for v in values {
+            self.u16(*v);
+        }
+        self
+    }
+
+    pub fn u32_array(&mut self, values: &[u32]) -> &mut Self {
+        self.u32(values.len() as u32);
+        for v in values {
+            self.u32(*v);
+        }
+        self
+    }
+}
@@ -0,0 +1,147 @@
+// 对象存储 - 把目录中的文件映射为PTP对象
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 唯一的存储ID
+pub const STORAGE_ID: u32 = 0x0001_0001;
+
+/// 对象格式
+pub const FORMAT_UNDEFINED: u16 = 0x3000;
+pub const FORMAT_EXIF_JPEG: u16 = 0x3801;
+pub const FORMAT_PNG: u16 = 0x380B;
+pub const FORMAT_TIFF: u16 = 0x380D;
+
+/// 一个对象
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub handle: u32,
+    pub path: PathBuf,
+    pub filename: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+impl Object {
+    /// 按扩展名推断对象格式
+    pub fn format(&self) -> u16 {
+        let ext = self
+            .filename
+            .rsplit_once('.')
+            .map(|(_, ext)| ext.to_ascii_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "jpg" | "jpeg" => FORMAT_EXIF_JPEG,
+            "png" => FORMAT_PNG,
+            "tif" | "tiff" => FORMAT_TIFF,
+            _ => FORMAT_UNDEFINED,
+        }
+    }
+
+    /// PTP日期字符串 YYYYMMDDThhmmss (UTC)
+    pub fn date_string(&self) -> String {
+        let secs = self.modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+        // 由天数换算公历日期 (Howard Hinnant的civil_from_days算法)
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}",
+            year,
+            month,
+            day,
+            rem / 3600,
+            rem % 3600 / 60,
+            rem % 60
+        )
+    }
+}
+
+/// 目录对象存储
+pub struct ObjectStore {
+    dir: PathBuf,
+    objects: Vec<Object>,
+    next_handle: u32,
+}
+
+impl ObjectStore {
+    /// 打开目录并建立初始对象列表
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let mut store = ObjectStore {
+            dir: dir.to_path_buf(),
+            objects: Vec::new(),
+            next_handle: 1,
+        };
+        store.rescan()?;
+        Ok(store)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// 重新扫描目录，返回新出现对象的句柄
+    /// 已删除的文件从列表中移除，句柄不会被复用
+    pub fn rescan(&mut self) -> io::Result<Vec<u32>> {
+        let mut files: Vec<(PathBuf, fs::Metadata)> = fs::read_dir(&self.dir)?
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.metadata().ok().map(|m| (e.path(), m)))
+            .filter(|(path, m)| {
+                m.is_file() && path.file_name().is_some_and(|n| !n.to_string_lossy().starts_with('.'))
+            })
+            .collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        self.objects.retain(|o| files.iter().any(|(path, _)| *path == o.path));
+
+        let mut added = Vec::new();
+        for (path, meta) in files {
+            if self.objects.iter().any(|o| o.path == path) {
+                continue;
+            }
+            let object = Object {
+                handle: self.next_handle,
+                filename: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                path,
+                size: meta.len(),
+                modified: meta.modified().unwrap_or(UNIX_EPOCH),
+            };
+            self.next_handle += 1;
+            added.push(object.handle);
+            self.objects.push(object);
+        }
+        Ok(added)
+    }
+
+    pub fn objects(&self) -> &[Object] {
+        &self.objects
+    }
+
+    pub fn get(&self, handle: u32) -> Option<&Object> {
+        self.objects.iter().find(|o| o.handle == handle)
+    }
+
+    /// 模拟拍摄：复制最新的JPEG为新文件，返回新对象句柄
+    pub fn capture(&mut self) -> io::Result<Option<u32>> {
+        let source = match self.objects.iter().rev().find(|o| o.format() == FORMAT_EXIF_JPEG) {
+            Some(o) => o.path.clone(),
+            None => return Ok(None),
+        };
+        let name = format!("CAP_{:04}.JPG", self.next_handle);
+        fs::copy(&source, self.dir.join(name))?;
+        Ok(self.rescan()?.last().copied())
+    }
+
+    /// 已用空间
+    pub fn used_bytes(&self) -> u64 {
+        self.objects.iter().map(|o| o.size).sum()
+    }
+}
@@ -0,0 +1,381 @@
+// 配置导入导出 - 把完整的设备配置导出为一个JSON文件(密钥被省略或用口令加密)，
+// 导入时先校验再整体替换，并保留上一份配置用于回滚，避免写入一半的配置导致设备无法启动
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Write as _;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::{DeviceConfig, S3Auth, SftpAuth};
+use crate::console::CONSOLE_CLIENT_ID;
+use crate::control::{AuditAction, AuditLog};
+use crate::persist::KvStore;
+
+/// 导出文件的格式标识
+pub const BUNDLE_FORMAT: &str = "rcamera-config";
+/// 导出文件的格式版本，版本2起密钥改用ChaCha20-Poly1305加密
+pub const BUNDLE_VERSION: u32 = 2;
+/// 省略的密钥在导出文件中的占位值
+pub const OMITTED: &str = "<omitted>";
+/// 保存设备配置的NVS命名空间
+pub const NVS_NAMESPACE: &str = "config";
+
+/// 两个配置槽位，保存时写入未使用的槽位再切换指针
+const SLOT_KEYS: [&str; 2] = ["cfg_a", "cfg_b"];
+/// 当前配置所在槽位的编号，单个键的写入是原子的
+const SLOT_POINTER_KEY: &str = "cfg_slot";
+const ENCRYPTED_PREFIX: &str = "enc:";
+const KDF_ITERATIONS: u32 = 4096;
+const NONCE_LEN: usize = 12;
+
+/// 导出时对密钥的处理方式
+#[derive(Debug, Clone, Copy)]
+pub enum SecretMode<'a> {
+    Omit,             // 替换为占位值，导入时沿用设备上现有的密钥
+    Encrypt(&'a str), // 用口令加密，导入时需要同一口令
+}
+
+/// 导出文件
+#[derive(Debug, Serialize, Deserialize)]
+struct Bundle {
+    format: String,
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>, // 加密密钥时的口令盐
+    config: DeviceConfig,
+}
+
+/// 依次访问配置中的所有密钥字段，路径用于区分字段
+fn for_each_secret(
+    config: &mut DeviceConfig,
+    mut f: impl FnMut(&str, &mut String) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    for (i, webhook) in config.webhooks.iter_mut().enumerate() {
+        if let Some(authorization) = &mut webhook.authorization {
+            f(&format!("webhooks.{}.authorization", i), authorization)?;
+        }
+    }
+    if let Some(cloud) = &mut config.cloud {
+        match &mut cloud.auth {
+            S3Auth::PresignedUrl(url) => f("cloud.presigned_url", url)?,
+            S3Auth::Static { secret_key, .. } => f("cloud.secret_key", secret_key)?,
+        }
+        if let Some(key) = &mut cloud.tls.client_key_pem {
+            f("cloud.tls.client_key_pem", key)?;
+        }
+    }
+    if let Some(http) = config.cloud_upload.as_mut().and_then(|upload| upload.http.as_mut()) {
+        if let Some(authorization) = &mut http.authorization {
+            f("cloud_upload.http.authorization", authorization)?;
+        }
+        if let Some(key) = &mut http.tls.client_key_pem {
+            f("cloud_upload.http.tls.client_key_pem", key)?;
+        }
+    }
+    if let Some(key) = config.link_tls.as_mut().and_then(|tls| tls.client_key_pem.as_mut()) {
+        f("link_tls.client_key_pem", key)?;
+    }
+    if let Some(sftp) = &mut config.sftp {
+        match &mut sftp.auth {
+            SftpAuth::Password(password) => f("sftp.password", password)?,
+            SftpAuth::PrivateKey { key_pem, passphrase } => {
+                f("sftp.key_pem", key_pem)?;
+                if let Some(passphrase) = passphrase {
+                    f("sftp.passphrase", passphrase)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 由口令和盐派生加密密钥(PBKDF2-HMAC-SHA256)
+fn derive_cipher(passphrase: &str, salt: &str) -> ChaCha20Poly1305 {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt.as_bytes(), KDF_ITERATIONS, &mut key);
+    ChaCha20Poly1305::new(&key.into())
+}
+
+/// 加密一个密钥字段，字段路径作为附加数据，密文不能被挪到其他字段
+fn encrypt_secret(cipher: &ChaCha20Poly1305, path: &str, plain: &str) -> Result<String, Box<dyn Error>> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher
+        .encrypt(&nonce, Payload { msg: plain.as_bytes(), aad: path.as_bytes() })
+        .map_err(|_| format!("{} 加密失败", path))?;
+    let mut out = String::from(ENCRYPTED_PREFIX);
+    for b in nonce.iter().chain(&sealed) {
+        let _ = write!(out, "{:02x}", b);
+    }
+    Ok(out)
+}
+
+fn decrypt_secret(cipher: &ChaCha20Poly1305, path: &str, value: &str) -> Result<String, Box<dyn Error>> {
+    let hex = value.strip_prefix(ENCRYPTED_PREFIX).ok_or_else(|| format!("{} 不是加密的值", path))?;
+    let data = decode_hex(hex).ok_or_else(|| format!("{} 的密文格式错误", path))?;
+    if data.len() < NONCE_LEN {
+        return Err(format!("{} 的密文格式错误", path).into());
+    }
+    let (nonce, sealed) = data.split_at(NONCE_LEN);
+    let plain = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: path.as_bytes() })
+        .map_err(|_| format!("{} 解密失败，口令错误或文件被修改", path))?;
+    Ok(String::from_utf8(plain)?)
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// 把配置导出为JSON
+pub fn export(config: &DeviceConfig, mode: SecretMode) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut config = config.clone();
+    let salt = match mode {
+        SecretMode::Omit => {
+            for_each_secret(&mut config, |_, secret| {
+                *secret = OMITTED.to_string();
+                Ok(())
+            })?;
+            None
+        }
+        SecretMode::Encrypt(passphrase) => {
+            if passphrase.is_empty() {
+                return Err("加密口令不能为空".into());
+            }
+            let salt = uuid::Uuid::new_v4().simple().to_string();
+            let cipher = derive_cipher(passphrase, &salt);
+            for_each_secret(&mut config, |path, secret| {
+                *secret = encrypt_secret(&cipher, path, secret)?;
+                Ok(())
+            })?;
+            Some(salt)
+        }
+    };
+    let bundle = Bundle {
+        format: BUNDLE_FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        salt,
+        config,
+    };
+    Ok(serde_json::to_vec_pretty(&bundle)?)
+}
+
+/// 解析导出文件并还原密钥：省略的密钥沿用`current`中同一字段的值，加密的密钥用`passphrase`解密
+pub fn parse(data: &[u8], passphrase: Option<&str>, current: &DeviceConfig) -> Result<DeviceConfig, Box<dyn Error>> {
+    let bundle: Bundle = serde_json::from_slice(data).map_err(|e| format!("配置文件格式错误: {}", e))?;
+    if bundle.format != BUNDLE_FORMAT {
+        return Err(format!("不是设备配置文件: {}", bundle.format).into());
+    }
+    if bundle.version > BUNDLE_VERSION {
+        return Err(format!("不支持的配置文件版本: {}", bundle.version).into());
+    }
+    if bundle.version < 2 && bundle.salt.is_some() {
+        return Err("版本1的配置文件使用了旧的密钥加密方式，请用新固件重新导出".into());
+    }
+
+    let mut existing = HashMap::new();
+    for_each_secret(&mut current.clone(), |path, secret| {
+        existing.insert(path.to_string(), secret.clone());
+        Ok(())
+    })?;
+    let cipher = match (&bundle.salt, passphrase) {
+        (Some(salt), Some(passphrase)) => Some(derive_cipher(passphrase, salt)),
+        _ => None,
+    };
+
+    let mut config = bundle.config;
+    for_each_secret(&mut config, |path, secret| {
+        if secret == OMITTED {
+            *secret = existing
+                .get(path)
+                .cloned()
+                .ok_or_else(|| format!("{} 在文件中被省略，设备上也没有现有的值", path))?;
+        } else if secret.starts_with(ENCRYPTED_PREFIX) {
+            let cipher = cipher.as_ref().ok_or_else(|| format!("{} 已加密，需要提供口令", path))?;
+            *secret = decrypt_secret(cipher, path, secret)?;
+        }
+        Ok(())
+    })?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// 持久化的设备配置，保留上一份配置用于回滚
+///
+/// 配置轮流写入两个槽位，写完新槽位后再切换指针：掉电时指针仍指向完整的旧配置，
+/// 切换后另一个槽位就是回滚用的上一份配置
+pub struct ConfigStore {
+    store: Box<dyn KvStore>,
+    config: DeviceConfig,
+    active: usize, // 当前配置所在的槽位
+}
+
+impl ConfigStore {
+    /// 打开配置存储：当前槽位损坏时使用另一个槽位，都没有时使用默认配置
+    pub fn open(store: Box<dyn KvStore>) -> Result<Self, Box<dyn Error>> {
+        let pointer = match store.get(SLOT_POINTER_KEY)?.as_deref() {
+            Some([slot]) if (*slot as usize) < SLOT_KEYS.len() => *slot as usize,
+            _ => 0,
+        };
+        for active in [pointer, 1 - pointer] {
+            let Some(data) = store.get(SLOT_KEYS[active])? else {
+                continue;
+            };
+            match serde_json::from_slice::<DeviceConfig>(&data) {
+                Ok(config) => return Ok(ConfigStore { store, config, active }),
+                Err(e) => warn!("配置 {} 无法解析: {}", SLOT_KEYS[active], e),
+            }
+        }
+        Ok(ConfigStore {
+            store,
+            config: DeviceConfig::default(),
+            active: pointer,
+        })
+    }
+
+    /// 当前配置
+    pub fn config(&self) -> &DeviceConfig {
+        &self.config
+    }
+
+    /// 校验并保存新配置，原配置成为回滚用的备份
+    pub fn save(&mut self, config: DeviceConfig) -> Result<(), Box<dyn Error>> {
+        config.validate()?;
+        let data = serde_json::to_vec(&config)?;
+        let next = 1 - self.active;
+        self.store.set(SLOT_KEYS[next], &data)?;
+        self.store.flush()?;
+        self.store.set(SLOT_POINTER_KEY, &[next as u8])?;
+        self.store.flush()?;
+        self.active = next;
+        self.config = config;
+        Ok(())
+    }
+
+    /// 导出当前配置
+    pub fn export(&self, mode: SecretMode) -> Result<Vec<u8>, Box<dyn Error>> {
+        export(&self.config, mode)
+    }
+
+    /// 导入配置文件，任何一步失败时设备上的配置保持不变
+    pub fn import(&mut self, data: &[u8], passphrase: Option<&str>) -> Result<&DeviceConfig, Box<dyn Error>> {
+        let config = parse(data, passphrase, &self.config)?;
+        self.save(config)?;
+        info!("已导入设备配置: {}", self.config.device_name);
+        Ok(&self.config)
+    }
+
+    /// 恢复上一份配置，当前配置成为新的备份
+    pub fn rollback(&mut self) -> Result<&DeviceConfig, Box<dyn Error>> {
+        let previous = 1 - self.active;
+        let data = self.store.get(SLOT_KEYS[previous])?.ok_or("没有可回滚的配置")?;
+        let config: DeviceConfig = serde_json::from_slice(&data)?;
+        config.validate()?;
+        self.store.set(SLOT_POINTER_KEY, &[previous as u8])?;
+        self.store.flush()?;
+        self.active = previous;
+        self.config = config;
+        info!("已回滚到上一份设备配置");
+        Ok(&self.config)
+    }
+}
+
+/// 注册控制台命令 `config`，在SD卡等本地文件系统上导入导出配置，导入和回滚记入审计日志
+pub fn register_console_command(
+    console: &mut crate::console::Console,
+    store: std::sync::Arc<std::sync::Mutex<ConfigStore>>,
+    audit: std::sync::Arc<std::sync::Mutex<AuditLog>>,
+) {
+    const USAGE: &str = "用法: config export <文件> [口令] | config import <文件> [口令] | config rollback";
+    console.register(
+        "config",
+        "设备配置: config export <文件> [口令] | config import <文件> [口令] | config rollback",
+        Box::new(move |args| {
+            let mut store = store.lock().unwrap();
+            match args {
+                ["export", path] | ["export", path, _] => {
+                    let mode = match args.get(2) {
+                        Some(passphrase) => SecretMode::Encrypt(passphrase),
+                        None => SecretMode::Omit,
+                    };
+                    match store.export(mode).and_then(|data| Ok(std::fs::write(path, data)?)) {
+                        Ok(()) => format!("配置已导出到 {}", path),
+                        Err(e) => format!("导出失败: {}", e),
+                    }
+                }
+                ["import", path] | ["import", path, _] => {
+                    let result = std::fs::read(path)
+                        .map_err(|e| e.into())
+                        .and_then(|data| store.import(&data, args.get(2).copied()).map(|c| c.device_name.clone()));
+                    record_change(&audit, result.as_ref().map(|_| ()).map_err(|e| e.as_ref()));
+                    match result {
+                        Ok(name) => format!("已导入配置 {}，重启后生效", name),
+                        Err(e) => format!("导入失败，配置未改变: {}", e),
+                    }
+                }
+                ["rollback"] => {
+                    let result = store.rollback().map(|config| config.device_name.clone());
+                    record_change(&audit, result.as_ref().map(|_| ()).map_err(|e| e.as_ref()));
+                    match result {
+                        Ok(name) => format!("已回滚到配置 {}，重启后生效", name),
+                        Err(e) => format!("回滚失败: {}", e),
+                    }
+                }
+                _ => USAGE.to_string(),
+            }
+        }),
+    );
+}
+
+/// 控制台修改配置的审计记录
+fn record_change(audit: &std::sync::Mutex<AuditLog>, result: Result<(), &dyn Error>) {
+    if let Err(e) = audit.lock().unwrap().record(AuditAction::ConfigChange, 0, CONSOLE_CLIENT_ID, result) {
+        warn!("写入审计日志失败: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WebhookConfig;
+    use crate::persist::MemoryStore;
+
+    fn with_secret() -> DeviceConfig {
+        let mut config = DeviceConfig::default();
+        config.webhooks.push(WebhookConfig {
+            url: "https://example.com/hook".to_string(),
+            events: Vec::new(),
+            authorization: Some("Bearer secret".to_string()),
+            timeout_ms: 1000,
+        });
+        config
+    }
+
+    #[test]
+    fn encrypted_secret_round_trips_with_passphrase() {
+        let config = with_secret();
+        let data = export(&config, SecretMode::Encrypt("pass")).unwrap();
+        assert!(!String::from_utf8_lossy(&data).contains("Bearer secret"));
+        let parsed = parse(&data, Some("pass"), &DeviceConfig::default()).unwrap();
+        assert_eq!(parsed.webhooks[0].authorization.as_deref(), Some("Bearer secret"));
+        assert!(parse(&data, Some("wrong"), &DeviceConfig::default()).is_err());
+    }
+
+    #[test]
+    fn save_switches_slot_and_rollback_restores_previous() {
+        let mut store = ConfigStore::open(Box::new(MemoryStore::new())).unwrap();
+        store.save(DeviceConfig { device_name: "first".into(), ..Default::default() }).unwrap();
+        store.save(DeviceConfig { device_name: "second".into(), ..Default::default() }).unwrap();
+        assert_eq!(store.config().device_name, "second");
+        assert_eq!(store.rollback().unwrap().device_name, "first");
+    }
+}
@@ -0,0 +1,910 @@
+// 设备配置模块 - 集中保存运行时可调整的设备设置
+pub mod bundle;
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::data_transfer::{Impairment, LinkQualityThresholds, PostTransferAction, StageSpec};
+use crate::i18n::{self, Language};
+use crate::orchestrator::mode::OperatingMode;
+pub use crate::ptp_mtp::CameraQuirk;
+use crate::ptp_mtp::{register_quirks, Calibration, QuirkEntry, StorageThresholds, TransactionTimeouts, DEFAULT_SESSION_ID};
+use crate::runtime::{self, TaskOverride};
+
+/// 设备名称的最大长度(蓝牙广播名和AP名称的限制)
+const MAX_DEVICE_NAME_LEN: usize = 32;
+/// 实时取景的最高帧率，更高时USB和无线链路都跟不上
+const MAX_LIVE_VIEW_FPS: u8 = 30;
+/// S3分片的最小大小
+const MIN_S3_PART_SIZE: usize = 5 * 1024 * 1024;
+/// SoftAP的DHCP服务器最多管理的地址数(lwIP dhcpserver的限制)
+const MAX_DHCP_POOL_SIZE: u32 = 100;
+/// WPA2密码长度范围
+const WPA2_PASSWORD_LEN: std::ops::RangeInclusive<usize> = 8..=63;
+/// SoftAP同时连接的客户端上限(ESP-IDF的限制)
+const MAX_AP_CLIENTS: u16 = 10;
+/// WiFi网络名称的最大字节数
+const MAX_SSID_LEN: usize = 32;
+/// 企业级网络身份、用户名和密码的最大字节数
+const MAX_EAP_FIELD_LEN: usize = 128;
+/// TCP服务端同时连接的客户端上限(lwIP的套接字数量有限，还要留给HTTP等)
+const MAX_TCP_SERVER_CLIENTS: usize = 6;
+/// TCP服务端每个客户端发送队列的最小字节数，至少能放下一个对象分块
+const MIN_TCP_SERVER_QUEUE: usize = 64 * 1024;
+/// ESP-NOW分片发送失败时的最大重试次数
+const MAX_ESPNOW_RETRIES: u8 = 10;
+/// FTP服务端同时登录的会话上限，每个会话占用一个任务
+const MAX_FTP_SESSIONS: usize = 4;
+/// WiFi恢复后切回批量传输前等待稳定的最长时间
+const MAX_TRANSPORT_SETTLE_SECS: u32 = 300;
+/// 云端上传单个对象失败后的最大重试次数
+const MAX_CLOUD_RETRIES: u8 = 10;
+/// HTTPS端点分块上传的块大小范围，每块在内存中缓存后整块提交
+const CLOUD_CHUNK_SIZE: std::ops::RangeInclusive<usize> = 16 * 1024..=1024 * 1024;
+/// 默认在与相机的链路空闲一分钟后发送保活
+const DEFAULT_SESSION_KEEPALIVE_SECS: u32 = 60;
+
+/// Webhook配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,                   // 接收POST请求的地址
+    pub events: Vec<String>,           // 订阅的事件名称，为空表示全部
+    pub authorization: Option<String>, // 可选的Authorization请求头
+    pub timeout_ms: u64,               // 请求超时(毫秒)
+}
+
+impl WebhookConfig {
+    /// 是否订阅了该事件
+    pub fn wants(&self, event_name: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|e| e == event_name)
+    }
+}
+
+/// S3兼容存储的鉴权方式
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum S3Auth {
+    /// 预签名的PUT地址（只能整对象上传）
+    PresignedUrl(String),
+    /// 静态访问密钥（使用SigV4签名，支持分片上传）
+    Static {
+        access_key: String,
+        secret_key: String,
+        region: String,
+    },
+}
+
+/// 出站TLS连接的证书设置
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsSettings {
+    pub ca_pem: Option<String>,          // 信任的CA证书(PEM)，None表示使用内置的根证书包
+    pub client_cert_pem: Option<String>, // 双向认证的客户端证书(PEM)
+    pub client_key_pem: Option<String>,  // 客户端证书的私钥(PEM)
+}
+
+impl TlsSettings {
+    /// 检查证书是否为PEM格式，客户端证书和私钥必须同时提供
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let fields = [("CA证书", &self.ca_pem), ("客户端证书", &self.client_cert_pem), ("客户端私钥", &self.client_key_pem)];
+        for (name, pem) in fields {
+            if pem.as_ref().is_some_and(|pem| !pem.trim_start().starts_with("-----BEGIN ")) {
+                return Err(format!("{}不是PEM格式", name).into());
+            }
+        }
+        if self.client_cert_pem.is_some() != self.client_key_pem.is_some() {
+            return Err("客户端证书和私钥必须同时配置".into());
+        }
+        Ok(())
+    }
+}
+
+/// S3兼容存储配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    pub endpoint: String, // 例如 https://s3.example.com
+    pub bucket: String,   // 存储桶
+    pub prefix: String,   // 对象键前缀
+    pub auth: S3Auth,     // 鉴权方式
+    pub part_size: usize, // 分片大小(字节)，S3要求除最后一片外不小于5MB
+    #[serde(default)]
+    pub tls: TlsSettings, // https端点使用的证书
+}
+
+/// 以分块PUT接收对象的HTTPS端点
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpUploadConfig {
+    pub url: String,                   // 对象地址的前缀，后接对象名，例如 https://upload.example.com/camera/
+    pub authorization: Option<String>, // 可选的Authorization请求头
+    pub chunk_size: usize,             // 每个请求上传的字节数
+    #[serde(default)]
+    pub tls: TlsSettings,              // 端点使用的证书
+}
+
+/// 云端上传，手机一直没有连接时也把拍摄完成的对象送到云端
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloudUploadConfig {
+    pub http: Option<HttpUploadConfig>, // 上传到HTTPS端点，None表示上传到`cloud`配置的S3存储桶
+    pub max_retries: u8,                // 每个对象连续失败的重试次数，超过后移到队尾
+    pub retry_delay_ms: u64,            // 第一次重试前的等待时间，之后每次翻倍
+}
+
+impl CloudUploadConfig {
+    /// `s3`为设备配置中的`cloud`，未配置HTTPS端点时必须提供
+    pub fn validate(&self, s3: Option<&S3Config>) -> Result<(), Box<dyn Error>> {
+        match &self.http {
+            Some(http) => {
+                if !http.url.starts_with("https://") {
+                    return Err(format!("云端上传地址必须是https地址: {}", http.url).into());
+                }
+                if !CLOUD_CHUNK_SIZE.contains(&http.chunk_size) {
+                    return Err(format!(
+                        "云端上传块大小必须在{}到{}字节之间",
+                        CLOUD_CHUNK_SIZE.start(),
+                        CLOUD_CHUNK_SIZE.end()
+                    )
+                    .into());
+                }
+                http.tls.validate()?;
+            }
+            None => match s3 {
+                None => return Err("云端上传未指定HTTPS端点，也没有配置S3存储".into()),
+                // 预签名地址只对应一个对象键，无法上传之后拍摄的对象
+                Some(S3Config { auth: S3Auth::PresignedUrl(_), .. }) => {
+                    return Err("云端上传需要使用访问密钥的S3存储，预签名地址只能上传一个对象".into())
+                }
+                Some(_) => {}
+            },
+        }
+        if self.max_retries > MAX_CLOUD_RETRIES {
+            return Err(format!("云端上传重试次数不能超过{}", MAX_CLOUD_RETRIES).into());
+        }
+        Ok(())
+    }
+}
+
+/// SFTP认证方式
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SftpAuth {
+    Password(String),                                        // 密码
+    PrivateKey { key_pem: String, passphrase: Option<String> }, // 私钥
+}
+
+/// SFTP接收目录配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SftpConfig {
+    pub host: String,             // 服务器地址
+    pub port: u16,                // 端口，通常为22
+    pub username: String,         // 用户名
+    pub auth: SftpAuth,           // 认证方式
+    pub remote_dir: String,       // 接收目录
+    pub trust_on_first_use: bool, // 首次连接时是否自动固定主机密钥
+}
+
+/// 以太网PHY
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EthernetPhy {
+    /// SPI接口的W5500模块，引脚为GPIO编号
+    W5500 {
+        sclk: i32,
+        mosi: i32,
+        miso: i32,
+        cs: i32,
+        int: i32,
+        rst: Option<i32>,
+        baudrate_hz: u32, // SPI时钟，W5500最高约33MHz
+    },
+    /// 片上EMAC + RMII PHY（仅ESP32等带EMAC的芯片）
+    Rmii {
+        mdc: i32,
+        mdio: i32,
+        phy_addr: Option<u32>, // 为None时自动探测
+    },
+}
+
+/// 以太网配置，地址由DHCP分配
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EthernetConfig {
+    pub phy: EthernetPhy,
+}
+
+/// 承载TCP/HTTP发送器的网络接口
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetworkInterface {
+    #[default]
+    Wireless,                 // 由编排器在WiFi/蓝牙中选择
+    Ethernet(EthernetConfig), // 有线以太网，适合影棚等需要稳定高带宽的场景
+}
+
+/// SoftAP的WiFi信道选择
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChannelPolicy {
+    Fixed(u8),                  // 固定信道(1-13)
+    Auto { hop_on_loss: bool }, // 启动时扫描并选择最不拥挤的信道，可选在持续丢包时切换
+}
+
+impl Default for ChannelPolicy {
+    fn default() -> Self {
+        ChannelPolicy::Auto { hop_on_loss: false }
+    }
+}
+
+/// WiFi工作模式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WirelessMode {
+    #[default]
+    Ap,    // ESP32作为接入点，手机直连
+    Sta,   // 连接已有网络
+    ApSta, // 作为接入点的同时连接已有网络
+}
+
+/// 连接已有网络时的认证方式
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WifiAuth {
+    Open,             // 开放网络，密码必须为空
+    #[default]
+    Wpa2Personal,
+    Wpa3Personal,
+    Wpa2Wpa3Personal, // WPA2/WPA3过渡模式
+    Wpa2Enterprise {  // PEAP/TTLS，密码为该用户名的密码
+        #[serde(default)]
+        identity: String, // 外层身份，为空时使用用户名
+        username: String,
+    },
+}
+
+/// 取景画面的UDP推送目标
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LiveViewUdp {
+    pub target: String, // 接收端地址:端口，例如 192.168.4.2:5004
+    #[serde(default)]
+    pub rtp: bool,      // 使用RTP/MJPEG封装，否则使用原始分片
+}
+
+/// 多客户端TCP服务端，手机主动连接设备并按订阅接收数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TcpServerConfig {
+    pub port: u16,          // 监听端口
+    pub max_clients: usize, // 同时连接的客户端上限，超出的连接被拒绝
+    pub queue_bytes: usize, // 每个客户端发送队列的字节上限
+}
+
+impl Default for TcpServerConfig {
+    fn default() -> Self {
+        TcpServerConfig {
+            port: 5555,
+            max_clients: 4,
+            queue_bytes: 256 * 1024,
+        }
+    }
+}
+
+impl TcpServerConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.port == 0 {
+            return Err("TCP服务端端口不能为0".into());
+        }
+        if !(1..=MAX_TCP_SERVER_CLIENTS).contains(&self.max_clients) {
+            return Err(format!("TCP服务端客户端上限必须在1到{}之间", MAX_TCP_SERVER_CLIENTS).into());
+        }
+        if self.queue_bytes < MIN_TCP_SERVER_QUEUE {
+            return Err(format!("TCP服务端发送队列不能小于{}字节", MIN_TCP_SERVER_QUEUE).into());
+        }
+        Ok(())
+    }
+}
+
+/// WiFi和蓝牙同时开启：蓝牙一直保持控制通道，批量数据在WiFi可用时走WiFi，
+/// WiFi断开后蓝牙改为传输缩略图和元数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DualTransportConfig {
+    pub settle_secs: u32, // WiFi恢复后持续可用这么久才把批量数据切回WiFi，避免链路抖动时反复切换
+}
+
+impl Default for DualTransportConfig {
+    fn default() -> Self {
+        DualTransportConfig { settle_secs: 10 }
+    }
+}
+
+impl DualTransportConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.settle_secs > MAX_TRANSPORT_SETTLE_SECS {
+            return Err(format!("WiFi恢复后的等待时间不能超过{}秒", MAX_TRANSPORT_SETTLE_SECS).into());
+        }
+        Ok(())
+    }
+}
+
+/// FTP服务端，桌面工具和联机拍摄软件通过FTP(S)直接读取相机中的文件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FtpConfig {
+    pub port: u16,                    // 控制连接端口
+    pub username: String,             // 登录用户名
+    pub password: String,
+    pub allow_anonymous: bool,        // 用户名为空时允许匿名登录，默认必须配置用户名和密码
+    pub passive_port: u16,            // 被动模式数据连接的起始端口
+    pub passive_ports: u16,           // 被动模式可用的端口数
+    pub max_sessions: usize,          // 同时连接的会话上限
+    pub tls_cert_pem: Option<String>, // 服务端证书(PEM)，与私钥同时配置时支持AUTH TLS(显式FTPS)
+    pub tls_key_pem: Option<String>,  // 服务端证书的私钥(PEM)
+    pub require_tls: bool,            // 拒绝未加密的登录和数据传输
+}
+
+impl Default for FtpConfig {
+    fn default() -> Self {
+        FtpConfig {
+            port: 21,
+            username: String::new(),
+            password: String::new(),
+            allow_anonymous: false,
+            passive_port: 50000,
+            passive_ports: 16,
+            max_sessions: 2,
+            tls_cert_pem: None,
+            tls_key_pem: None,
+            require_tls: false,
+        }
+    }
+}
+
+impl FtpConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.port == 0 || self.passive_port == 0 || self.passive_ports == 0 {
+            return Err("FTP端口不能为0".into());
+        }
+        let passive = self.passive_port as u32..self.passive_port as u32 + self.passive_ports as u32;
+        if passive.end > 65536 || passive.contains(&(self.port as u32)) {
+            return Err("FTP被动模式端口范围无效或包含控制端口".into());
+        }
+        if !(1..=MAX_FTP_SESSIONS).contains(&self.max_sessions) {
+            return Err(format!("FTP会话上限必须在1到{}之间", MAX_FTP_SESSIONS).into());
+        }
+        if self.username.trim().is_empty() {
+            if !self.allow_anonymous {
+                return Err("FTP必须配置用户名和密码，或显式允许匿名登录".into());
+            }
+            if !self.password.is_empty() {
+                return Err("FTP允许匿名登录时不能设置密码".into());
+            }
+        } else if self.password.is_empty() {
+            return Err("FTP密码不能为空".into());
+        }
+        for (name, pem) in [("FTP证书", &self.tls_cert_pem), ("FTP证书私钥", &self.tls_key_pem)] {
+            if pem.as_ref().is_some_and(|pem| !pem.trim_start().starts_with("-----BEGIN ")) {
+                return Err(format!("{}不是PEM格式", name).into());
+            }
+        }
+        if self.tls_cert_pem.is_some() != self.tls_key_pem.is_some() {
+            return Err("FTP证书和私钥必须同时配置".into());
+        }
+        if self.require_tls && self.tls_cert_pem.is_none() {
+            return Err("要求FTPS时必须配置证书".into());
+        }
+        Ok(())
+    }
+}
+
+/// ESP-NOW中继中本设备的角色
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EspNowRole {
+    Gateway,                  // 网关，接收各节点的数据包后按本机的客户端发送
+    Node { gateway: String }, // 节点，把数据包发给网关(MAC地址，例如 24:6f:28:aa:bb:cc)
+}
+
+/// 多台ESP32之间通过ESP-NOW中继数据包，例如每台相机一个节点，全部汇总到一个网关，不需要WiFi网络
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EspNowConfig {
+    pub role: EspNowRole,
+    #[serde(default)]
+    pub peers: Vec<String>,     // 接受其数据包的节点MAC，为空时接受任何节点
+    #[serde(default)]
+    pub relay: bool,            // 节点也接收其他节点的数据包并转发给自己的网关(多跳)
+    #[serde(default)]
+    pub retries: Option<u8>,    // 分片未被对端确认时的重试次数，None使用默认值
+}
+
+impl EspNowConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let mut peers = HashSet::new();
+        for peer in &self.peers {
+            if !peers.insert(parse_mac(peer)?) {
+                return Err(format!("ESP-NOW节点重复: {}", peer).into());
+            }
+        }
+        if let EspNowRole::Node { gateway } = &self.role {
+            let gateway = parse_mac(gateway)?;
+            if gateway[0] & 0x01 != 0 {
+                return Err("ESP-NOW网关必须是单播地址".into());
+            }
+        }
+        if self.retries.is_some_and(|r| r > MAX_ESPNOW_RETRIES) {
+            return Err(format!("ESP-NOW重试次数不能超过{}", MAX_ESPNOW_RETRIES).into());
+        }
+        Ok(())
+    }
+}
+
+/// 解析冒号或短横线分隔的MAC地址
+pub fn parse_mac(mac: &str) -> Result<[u8; 6], Box<dyn Error>> {
+    let invalid = || format!("无效的MAC地址: {}", mac);
+    let mut bytes = [0u8; 6];
+    let mut parts = mac.trim().split([':', '-']);
+    for byte in bytes.iter_mut() {
+        let part = parts.next().filter(|p| p.len() == 2).ok_or_else(invalid)?;
+        *byte = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+    }
+    if parts.next().is_some() {
+        return Err(invalid().into());
+    }
+    Ok(bytes)
+}
+
+/// WiFi工作在STA或AP+STA模式时连接的网络
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpstreamWifi {
+    pub ssid: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default)]
+    pub auth: WifiAuth,
+    #[serde(default)]
+    pub address: StaAddress, // STA接口的地址来源
+}
+
+impl UpstreamWifi {
+    /// 校验SSID、密码长度是否符合认证方式的要求，以及固定地址是否有效
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if !(1..=MAX_SSID_LEN).contains(&self.ssid.len()) {
+            return Err(format!("WiFi网络名称必须为1到{}个字节", MAX_SSID_LEN).into());
+        }
+        self.address.validate()?;
+        match &self.auth {
+            WifiAuth::Open if !self.password.is_empty() => Err("开放网络不能设置密码".into()),
+            WifiAuth::Open => Ok(()),
+            WifiAuth::Wpa2Personal | WifiAuth::Wpa3Personal | WifiAuth::Wpa2Wpa3Personal => {
+                // 64个字符时只能是十六进制的预共享密钥
+                let psk = self.password.len() == 64 && self.password.chars().all(|c| c.is_ascii_hexdigit());
+                if WPA2_PASSWORD_LEN.contains(&self.password.len()) || psk {
+                    Ok(())
+                } else {
+                    Err("WiFi密码必须为8到63个字符或64位十六进制密钥".into())
+                }
+            }
+            WifiAuth::Wpa2Enterprise { identity, username } => {
+                if username.is_empty() || self.password.is_empty() {
+                    return Err("企业级网络需要用户名和密码".into());
+                }
+                if [identity, username, &self.password].iter().any(|v| v.len() > MAX_EAP_FIELD_LEN) {
+                    return Err(format!("企业级网络的身份、用户名和密码不能超过{}个字节", MAX_EAP_FIELD_LEN).into());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// STA接口的IPv4地址来源
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StaAddress {
+    #[default]
+    Dhcp,             // 由上级网络的DHCP服务器分配
+    Static(StaticIp), // 固定地址
+}
+
+/// 固定的IPv4地址
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticIp {
+    pub ip: Ipv4Addr,
+    pub prefix_len: u8, // 子网掩码长度，例如24表示255.255.255.0
+    pub gateway: Ipv4Addr,
+    #[serde(default)]
+    pub dns: Option<Ipv4Addr>,
+    #[serde(default)]
+    pub secondary_dns: Option<Ipv4Addr>,
+}
+
+impl StaAddress {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let StaAddress::Static(ip) = self else {
+            return Ok(());
+        };
+        validate_host(ip.ip, ip.prefix_len)?;
+        if !same_subnet(ip.ip, ip.gateway, ip.prefix_len) || ip.gateway == ip.ip {
+            return Err(format!("网关 {} 不在 {}/{} 所在的子网中", ip.gateway, ip.ip, ip.prefix_len).into());
+        }
+        Ok(())
+    }
+}
+
+/// SoftAP的地址和DHCP地址池，不配置时使用ESP-IDF的默认网段
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApAddress {
+    pub ip: Ipv4Addr,         // SoftAP自身的地址，同时作为客户端的网关
+    pub prefix_len: u8,
+    pub pool_start: Ipv4Addr, // DHCP地址池的起止地址(含)
+    pub pool_end: Ipv4Addr,
+    #[serde(default)]
+    pub dns: Option<Ipv4Addr>, // 下发给客户端的DNS服务器；开启流量转发时改为上级网络的DNS
+}
+
+impl ApAddress {
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        validate_host(self.ip, self.prefix_len)?;
+        let (start, end) = (u32::from(self.pool_start), u32::from(self.pool_end));
+        if start > end {
+            return Err("DHCP地址池的起始地址不能大于结束地址".into());
+        }
+        if end - start >= MAX_DHCP_POOL_SIZE {
+            return Err(format!("DHCP地址池不能超过{}个地址", MAX_DHCP_POOL_SIZE).into());
+        }
+        for addr in [self.pool_start, self.pool_end] {
+            if !same_subnet(self.ip, addr, self.prefix_len) {
+                return Err(format!("DHCP地址 {} 不在 {}/{} 所在的子网中", addr, self.ip, self.prefix_len).into());
+            }
+            validate_host(addr, self.prefix_len)?;
+        }
+        if (start..=end).contains(&u32::from(self.ip)) {
+            return Err(format!("DHCP地址池不能包含SoftAP自身的地址 {}", self.ip).into());
+        }
+        Ok(())
+    }
+}
+
+/// 子网掩码
+fn netmask(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+fn same_subnet(a: Ipv4Addr, b: Ipv4Addr, prefix_len: u8) -> bool {
+    let mask = netmask(prefix_len);
+    u32::from(a) & mask == u32::from(b) & mask
+}
+
+/// 检查地址可以分配给主机：掩码长度合理，且不是网络地址或广播地址
+fn validate_host(ip: Ipv4Addr, prefix_len: u8) -> Result<(), Box<dyn Error>> {
+    if !(8..=30).contains(&prefix_len) {
+        return Err(format!("子网掩码长度必须在8到30之间: {}", prefix_len).into());
+    }
+    let host = u32::from(ip) & !netmask(prefix_len);
+    if ip.is_unspecified() || ip.is_multicast() || host == 0 || host == !netmask(prefix_len) {
+        return Err(format!("{}/{} 不是可用的主机地址", ip, prefix_len).into());
+    }
+    Ok(())
+}
+
+/// 按链路类型的传输配额(MB/小时)，None表示不限制；链路是按流量计费的手机热点时使用
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransferQuotas {
+    pub ble_mb_per_hour: Option<u32>,
+    pub wifi_mb_per_hour: Option<u32>,
+}
+
+/// RAW文件的传输策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RawPolicy {
+    #[default]
+    Transfer, // 传输RAW
+    Skip,     // 跳过RAW，只传JPEG等
+}
+
+/// RAW文件扩展名
+const RAW_EXTENSIONS: [&str; 9] = ["cr2", "cr3", "nef", "nrw", "arw", "raf", "rw2", "orf", "dng"];
+
+/// 按文件扩展名判断是否为RAW文件
+pub fn is_raw_filename(filename: &str) -> bool {
+    filename
+        .rsplit_once('.')
+        .map(|(_, ext)| RAW_EXTENSIONS.iter().any(|raw| ext.eq_ignore_ascii_case(raw)))
+        .unwrap_or(false)
+}
+
+/// 按机身序列号区分的设置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BodyProfile {
+    pub serial_number: String,     // PtpDeviceInfo中的序列号
+    pub label: String,             // 显示名称，例如 "A机"
+    pub name_prefix: String,       // 文件名前缀，例如 "A-"
+    pub raw_policy: RawPolicy,     // RAW传输策略
+    pub quirks: Vec<CameraQuirk>,  // 兼容性处理，识别机身后合并到机型兼容表的结果中
+    #[serde(default)]
+    pub calibration: Option<Calibration>, // 最近一次链路校准的结果
+}
+
+impl BodyProfile {
+    /// 只有序列号的默认设置
+    pub fn new(serial_number: &str) -> Self {
+        BodyProfile {
+            serial_number: serial_number.trim().to_string(),
+            label: serial_number.trim().to_string(),
+            name_prefix: String::new(),
+            raw_policy: RawPolicy::default(),
+            quirks: Vec::new(),
+            calibration: None,
+        }
+    }
+
+    /// 是否需要某项兼容性处理
+    pub fn has_quirk(&self, quirk: CameraQuirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+
+    /// 给文件名加上机身前缀
+    pub fn object_name(&self, filename: &str) -> String {
+        format!("{}{}", self.name_prefix, filename)
+    }
+
+    /// 按RAW策略判断是否传输该文件的原图
+    pub fn transfers_original(&self, filename: &str) -> bool {
+        self.raw_policy == RawPolicy::Transfer || !is_raw_filename(filename)
+    }
+}
+
+/// 按相机型号区分的事务超时，部分机型读取大文件时数据阶段明显更慢
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelTimeouts {
+    pub model: String,                 // PtpDeviceInfo中的型号，例如 "Canon EOS R6"
+    pub timeouts: TransactionTimeouts, // 该型号使用的分阶段超时
+}
+
+/// 设备配置
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    pub device_name: String,         // 设备名称（蓝牙广播名/AP名称）
+    pub language: Language,          // 用户可见消息的默认语言
+    pub webhooks: Vec<WebhookConfig>, // 事件Webhook
+    pub cloud: Option<S3Config>,      // 云存储直传
+    pub cloud_upload: Option<CloudUploadConfig>, // 后台把完成的对象上传到云端，None表示不上传
+    pub sftp: Option<SftpConfig>,     // SFTP投递
+    pub bodies: Vec<BodyProfile>,     // 按机身序列号区分的设置
+    pub network: NetworkInterface,    // 数据链路使用的网络接口
+    pub wifi_mode: WirelessMode,      // WiFi工作模式
+    pub wifi_channel: ChannelPolicy,  // SoftAP的信道选择
+    pub ap_password: String,          // SoftAP的密码，为空表示开放网络
+    pub ap_max_clients: u16,          // SoftAP同时连接的客户端上限
+    pub ap_address: Option<ApAddress>, // SoftAP的地址和DHCP地址池，None表示使用默认网段
+    pub upstream_wifi: Option<UpstreamWifi>, // STA或AP+STA模式连接的网络
+    pub ap_bridge: bool,              // AP+STA模式下把手机的流量转发到上级网络(NAPT)
+    pub http_port: u16,               // HTTP接口的端口，mDNS广播的也是这个端口
+    pub link_tls: Option<TlsSettings>, // 手机数据链路使用TLS，None表示明文
+    pub tcp_server: Option<TcpServerConfig>, // 接受多个手机连接的TCP服务端，None表示不监听
+    pub ftp: Option<FtpConfig>,       // 只读FTP服务端，None表示不监听
+    pub espnow: Option<EspNowConfig>, // 与其他ESP32之间的ESP-NOW中继，None表示不使用
+    pub dual_transport: Option<DualTransportConfig>, // 同时开启WiFi和蓝牙控制通道，None表示只用一种无线连接
+    pub quotas: TransferQuotas,       // 按链路类型的传输配额
+    pub model_timeouts: Vec<ModelTimeouts>, // 按型号覆盖的事务超时
+    pub impairment: Option<Impairment>, // 调试用链路劣化注入，None表示关闭
+    pub capture_stream: bool,         // 启动后立即把发送流抓包到SD卡
+    pub pipeline: Vec<StageSpec>,     // 按顺序执行的数据流水线阶段，为空时直接发送
+    pub post_transfer: PostTransferAction, // 客户端确认送达后对相机上原文件的处理
+    pub mode: OperatingMode,          // 启动时的工作模式
+    pub mode_button: Option<i32>,     // 切换工作模式的按键GPIO，None表示没有按键
+    pub storage_alert: StorageThresholds, // 相机存储剩余空间的告警阈值
+    pub session_id: u32,              // OpenSession使用的会话ID，同一相机连接多个主机时需要区分
+    pub session_keepalive_secs: u32,  // 与相机的链路空闲多久后发送保活，0表示不保活
+    pub tasks: Vec<TaskOverride>,     // 按任务名覆盖后台任务的栈大小和优先级
+    pub live_view_fps: u8,            // 实时取景的帧率
+    pub live_view_udp: Option<LiveViewUdp>, // 取景画面改走UDP，None表示与其他数据一起走TCP
+    pub link_tiers: Option<LinkQualityThresholds>, // 按链路质量自动降级发送内容，None表示总是发送全部数据
+    pub camera_quirks: Vec<QuirkEntry>, // 按VID/PID登记的相机兼容性问题
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        DeviceConfig {
+            device_name: "ESP32Camera".to_string(),
+            language: Language::ZhCn,
+            webhooks: Vec::new(),
+            cloud: None,
+            cloud_upload: None,
+            sftp: None,
+            bodies: Vec::new(),
+            network: NetworkInterface::default(),
+            wifi_mode: WirelessMode::default(),
+            wifi_channel: ChannelPolicy::default(),
+            ap_password: "12345678".to_string(),
+            ap_max_clients: 4,
+            ap_address: None,
+            upstream_wifi: None,
+            ap_bridge: true,
+            http_port: 80,
+            link_tls: None,
+            tcp_server: None,
+            ftp: None,
+            espnow: None,
+            dual_transport: None,
+            quotas: TransferQuotas::default(),
+            model_timeouts: Vec::new(),
+            impairment: None,
+            capture_stream: false,
+            pipeline: Vec::new(),
+            post_transfer: PostTransferAction::Keep,
+            mode: OperatingMode::default(),
+            mode_button: None,
+            storage_alert: StorageThresholds::default(),
+            session_id: DEFAULT_SESSION_ID,
+            session_keepalive_secs: DEFAULT_SESSION_KEEPALIVE_SECS,
+            tasks: Vec::new(),
+            live_view_fps: 10,
+            live_view_udp: None,
+            link_tiers: Some(LinkQualityThresholds::default()),
+            camera_quirks: Vec::new(),
+        }
+    }
+}
+
+impl DeviceConfig {
+    /// 应用配置中的全局设置
+    pub fn apply(&self) {
+        i18n::set_default_language(self.language);
+        runtime::configure(&self.tasks);
+        for entry in &self.camera_quirks {
+            register_quirks(*entry);
+        }
+    }
+
+    /// 查找机身设置，序列号比较忽略首尾空白（部分机身会在序列号后补空格）
+    pub fn body_for(&self, serial_number: &str) -> Option<&BodyProfile> {
+        let serial_number = serial_number.trim();
+        self.bodies.iter().find(|b| b.serial_number.trim() == serial_number)
+    }
+
+    /// 查找型号对应的事务超时，未配置的型号返回None；型号比较忽略大小写和首尾空白
+    pub fn timeouts_for(&self, model: &str) -> Option<TransactionTimeouts> {
+        let model = model.trim();
+        self.model_timeouts
+            .iter()
+            .find(|m| m.model.trim().eq_ignore_ascii_case(model))
+            .map(|m| m.timeouts)
+    }
+
+    /// 该机身使用的事务超时：有校准结果时使用校准值，否则按型号查找；都没有时返回None
+    pub fn body_timeouts(&self, serial_number: &str, model: &str) -> Option<TransactionTimeouts> {
+        self.body_for(serial_number)
+            .and_then(|b| b.calibration)
+            .map(|c| c.timeouts)
+            .or_else(|| self.timeouts_for(model))
+    }
+
+    /// 把校准结果保存到机身设置中，没有该机身的设置时新建一条
+    pub fn record_calibration(&mut self, serial_number: &str, calibration: Calibration) {
+        let trimmed = serial_number.trim();
+        match self.bodies.iter_mut().find(|b| b.serial_number.trim() == trimmed) {
+            Some(body) => body.calibration = Some(calibration),
+            None => self.bodies.push(BodyProfile {
+                calibration: Some(calibration),
+                ..BodyProfile::new(serial_number)
+            }),
+        }
+    }
+
+    /// 检查配置是否可用，导入或保存前调用，避免设备以无效配置启动
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let name = self.device_name.trim();
+        if name.is_empty() || name.len() > MAX_DEVICE_NAME_LEN {
+            return Err(format!("设备名称为空或超过 {} 字节", MAX_DEVICE_NAME_LEN).into());
+        }
+        for webhook in &self.webhooks {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                return Err(format!("无效的Webhook地址: {}", webhook.url).into());
+            }
+        }
+        if let Some(cloud) = &self.cloud {
+            if cloud.bucket.is_empty() {
+                return Err("云存储未指定存储桶".into());
+            }
+            if cloud.part_size < MIN_S3_PART_SIZE {
+                return Err(format!("S3分片大小不能小于 {} 字节", MIN_S3_PART_SIZE).into());
+            }
+            cloud.tls.validate()?;
+        }
+        if let Some(upload) = &self.cloud_upload {
+            upload.validate(self.cloud.as_ref())?;
+        }
+        if let Some(sftp) = &self.sftp {
+            if !cfg!(feature = "sftp") {
+                return Err("固件未包含SFTP投递(sftp feature)".into());
+            }
+            if sftp.host.is_empty() || sftp.port == 0 {
+                return Err("SFTP服务器地址或端口无效".into());
+            }
+        }
+        let mut serials = HashSet::new();
+        for body in &self.bodies {
+            if !serials.insert(body.serial_number.trim()) {
+                return Err(format!("机身序列号重复: {}", body.serial_number).into());
+            }
+        }
+        if self.impairment.is_some_and(|i| i.loss_percent > 100) {
+            return Err("丢包率不能超过100%".into());
+        }
+        if let ChannelPolicy::Fixed(channel) = self.wifi_channel {
+            if !(1..=13).contains(&channel) {
+                return Err(format!("无效的WiFi信道: {}", channel).into());
+            }
+        }
+        if !self.ap_password.is_empty() && !WPA2_PASSWORD_LEN.contains(&self.ap_password.len()) {
+            return Err("SoftAP密码必须为空或8到63个字符".into());
+        }
+        if !(1..=MAX_AP_CLIENTS).contains(&self.ap_max_clients) {
+            return Err(format!("SoftAP客户端上限必须在1到{}之间", MAX_AP_CLIENTS).into());
+        }
+        if self.wifi_mode != WirelessMode::Ap && self.upstream_wifi.as_ref().map_or(true, |u| u.ssid.trim().is_empty()) {
+            return Err("STA和AP+STA模式需要配置要连接的WiFi网络".into());
+        }
+        if let Some(upstream) = &self.upstream_wifi {
+            upstream.validate()?;
+        }
+        if let Some(address) = &self.ap_address {
+            address.validate()?;
+        }
+        if self.http_port == 0 {
+            return Err("HTTP端口不能为0".into());
+        }
+        if self.quotas.ble_mb_per_hour == Some(0) || self.quotas.wifi_mb_per_hour == Some(0) {
+            return Err("传输配额不能为0，不限制时不要设置".into());
+        }
+        if self.session_id == 0 {
+            return Err("会话ID不能为0".into());
+        }
+        if self.pipeline.iter().any(|s| s.name.trim().is_empty()) {
+            return Err("流水线阶段名称不能为空".into());
+        }
+        for task in &self.tasks {
+            task.validate()?;
+        }
+        if !(1..=MAX_LIVE_VIEW_FPS).contains(&self.live_view_fps) {
+            return Err(format!("实时取景帧率必须在1到{}之间", MAX_LIVE_VIEW_FPS).into());
+        }
+        if let Some(link_tiers) = &self.link_tiers {
+            link_tiers.validate()?;
+        }
+        if let Some(tls) = &self.link_tls {
+            tls.validate()?;
+        }
+        if let Some(server) = &self.tcp_server {
+            server.validate()?;
+            if server.port == self.http_port {
+                return Err("TCP服务端端口不能与HTTP端口相同".into());
+            }
+        }
+        if let Some(ftp) = &self.ftp {
+            if !cfg!(any(feature = "wifi", feature = "ethernet")) {
+                return Err("固件未包含FTP服务端(需要wifi或ethernet feature)".into());
+            }
+            ftp.validate()?;
+            if ftp.port == self.http_port || self.tcp_server.as_ref().is_some_and(|server| server.port == ftp.port) {
+                return Err("FTP端口不能与HTTP或TCP服务端端口相同".into());
+            }
+        }
+        if let Some(espnow) = &self.espnow {
+            espnow.validate()?;
+            // ESP-NOW只能与同一信道上的设备通信，自动选择信道时各节点可能选到不同的信道
+            if self.wifi_mode == WirelessMode::Ap && matches!(self.wifi_channel, ChannelPolicy::Auto { .. }) {
+                return Err("使用ESP-NOW中继时SoftAP必须使用固定信道，且所有节点一致".into());
+            }
+        }
+        if let Some(dual) = &self.dual_transport {
+            dual.validate()?;
+            if self.network != NetworkInterface::Wireless {
+                return Err("WiFi与蓝牙双通道只能用于无线连接".into());
+            }
+        }
+        if let Some(udp) = &self.live_view_udp {
+            udp.target
+                .parse::<std::net::SocketAddr>()
+                .map_err(|_| format!("无效的UDP取景目标: {}", udp.target))?;
+        }
+        Ok(())
+    }
+}
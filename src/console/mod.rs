@@ -0,0 +1,89 @@
+// 调试控制台模块 - 通过串口读取命令行并分发给已注册的命令
+use std::collections::BTreeMap;
+use std::io::BufRead;
+
+use log::{info, warn};
+
+/// 审计日志中记录的控制台操作者
+pub const CONSOLE_CLIENT_ID: &str = "console";
+
+/// 命令处理函数，参数为去掉命令名后的参数列表，返回输出文本
+pub type CommandHandler = Box<dyn Fn(&[&str]) -> String + Send>;
+
+/// 已注册的命令
+struct ConsoleCommand {
+    help: &'static str,
+    handler: CommandHandler,
+}
+
+/// 串口调试控制台
+pub struct Console {
+    commands: BTreeMap<&'static str, ConsoleCommand>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Console {
+    /// 创建新的控制台
+    pub fn new() -> Self {
+        Console {
+            commands: BTreeMap::new(),
+        }
+    }
+
+    /// 注册命令
+    pub fn register(&mut self, name: &'static str, help: &'static str, handler: CommandHandler) {
+        if self.commands.contains_key(name) {
+            warn!("控制台命令 {} 被重复注册", name);
+        }
+        self.commands.insert(name, ConsoleCommand { help, handler });
+    }
+
+    /// 执行一行命令，返回输出文本
+    pub fn execute(&self, line: &str) -> String {
+        let mut parts = line.split_whitespace();
+        let name = match parts.next() {
+            Some(name) => name,
+            None => return String::new(),
+        };
+        let args: Vec<&str> = parts.collect();
+
+        if name == "help" {
+            return self
+                .commands
+                .iter()
+                .map(|(name, cmd)| format!("{:<12} {}", name, cmd.help))
+                .collect::<Vec<_>>()
+                .join("\n");
+        }
+
+        match self.commands.get(name) {
+            Some(cmd) => (cmd.handler)(&args),
+            None => format!("未知命令: {}，输入 help 查看可用命令", name),
+        }
+    }
+
+    /// 阻塞读取串口输入并执行命令，直到输入结束
+    pub fn run_blocking(&self) {
+        info!("调试控制台已启动");
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    let output = self.execute(line.trim());
+                    if !output.is_empty() {
+                        println!("{}", output);
+                    }
+                }
+                Err(e) => {
+                    warn!("控制台读取失败: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+// 审计日志 - 记录删除、格式化、配置修改和OTA等破坏性操作
+// 日志以环形缓冲区形式保存在持久化存储中，写满后覆盖最旧的记录
+use std::error::Error;
+use std::fmt;
+use std::io::Cursor;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use log::{debug, warn};
+
+use super::ControlCommand;
+use crate::persist::KvStore;
+
+/// 保存审计日志的NVS命名空间
+pub const NVS_NAMESPACE: &str = "audit";
+/// 默认保留的记录条数
+pub const DEFAULT_CAPACITY: u32 = 64;
+
+/// 保存写入位置和记录条数的键
+const HEAD_KEY: &str = "audit_head";
+
+/// 破坏性操作类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum AuditAction {
+    DeleteObject = 1, // 删除对象
+    FormatStore = 2,  // 格式化存储
+    ConfigChange = 3, // 修改配置
+    Ota = 4,          // 固件升级
+}
+
+impl AuditAction {
+    fn from_u8(v: u8) -> Option<AuditAction> {
+        match v {
+            1 => Some(AuditAction::DeleteObject),
+            2 => Some(AuditAction::FormatStore),
+            3 => Some(AuditAction::ConfigChange),
+            4 => Some(AuditAction::Ota),
+            _ => None,
+        }
+    }
+
+    /// 从控制命令推导审计操作，非破坏性命令返回None
+    pub fn from_command(command: &ControlCommand) -> Option<(AuditAction, u32)> {
+        match command {
+            ControlCommand::DeleteObject(handle) => Some((AuditAction::DeleteObject, *handle)),
+            // 只申请确认码不算执行
+            ControlCommand::FormatStore { storage_id, confirmation: Some(_) } => {
+                Some((AuditAction::FormatStore, *storage_id))
+            }
+            ControlCommand::ImportConfig => Some((AuditAction::ConfigChange, 0)),
+            _ => None,
+        }
+    }
+}
+
+/// 一条审计记录
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: u64,    // Unix时间戳(秒)
+    pub action: AuditAction, // 操作类型
+    pub target: u32,       // 操作对象(对象句柄/存储ID等)
+    pub client_id: String, // 发起请求的客户端
+    pub success: bool,     // 执行结果
+    pub detail: String,    // 附加说明(错误信息等)
+}
+
+impl AuditEntry {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(16 + self.client_id.len() + self.detail.len());
+        out.write_u64::<LittleEndian>(self.timestamp).ok();
+        out.write_u8(self.action as u8).ok();
+        out.write_u32::<LittleEndian>(self.target).ok();
+        out.write_u8(self.success as u8).ok();
+        write_str(&mut out, &self.client_id);
+        write_str(&mut out, &self.detail);
+        out
+    }
+
+    fn decode(buf: &[u8]) -> Result<AuditEntry, Box<dyn Error>> {
+        let mut cur = Cursor::new(buf);
+        let timestamp = cur.read_u64::<LittleEndian>()?;
+        let action = AuditAction::from_u8(cur.read_u8()?).ok_or("无效的审计操作类型")?;
+        let target = cur.read_u32::<LittleEndian>()?;
+        let success = cur.read_u8()? != 0;
+        let client_id = read_str(&mut cur)?;
+        let detail = read_str(&mut cur)?;
+        Ok(AuditEntry {
+            timestamp,
+            action,
+            target,
+            client_id,
+            success,
+            detail,
+        })
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?} 0x{:08x} 客户端={} {}",
+            self.timestamp,
+            self.action,
+            self.target,
+            self.client_id,
+            if self.success { "成功" } else { "失败" }
+        )?;
+        if !self.detail.is_empty() {
+            write!(f, " ({})", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+// 写入长度前缀的短字符串(最长255字节)
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    let bytes = &s.as_bytes()[..s.len().min(255)];
+    out.write_u8(bytes.len() as u8).ok();
+    out.extend_from_slice(bytes);
+}
+
+fn read_str(cur: &mut Cursor<&[u8]>) -> Result<String, Box<dyn Error>> {
+    let len = cur.read_u8()? as usize;
+    let start = cur.position() as usize;
+    let data = cur.get_ref().get(start..start + len).ok_or("审计记录被截断")?;
+    cur.set_position((start + len) as u64);
+    Ok(String::from_utf8_lossy(data).into_owned())
+}
+
+/// 持久化的审计日志环形缓冲区
+pub struct AuditLog {
+    store: Box<dyn KvStore>,
+    capacity: u32,
+    next: u32, // 下一条记录写入的槽位
+    len: u32,  // 保存的记录条数，不超过capacity
+}
+
+impl AuditLog {
+    /// 打开审计日志，capacity为保留的最大记录条数
+    pub fn open(store: Box<dyn KvStore>, capacity: u32) -> Result<Self, Box<dyn Error>> {
+        let capacity = capacity.max(1);
+        let (next, len) = match store.get(HEAD_KEY)? {
+            Some(data) if data.len() == 8 => {
+                let mut cur = Cursor::new(data);
+                (cur.read_u32::<LittleEndian>()?, cur.read_u32::<LittleEndian>()?)
+            }
+            _ => (0, 0),
+        };
+        // 容量变小后超出范围的槽位不再使用
+        let (next, len) = if next < capacity { (next, len.min(capacity)) } else { (0, 0) };
+        debug!("审计日志已打开，共 {} 条历史记录", len);
+        Ok(AuditLog { store, capacity, next, len })
+    }
+
+    /// 记录一次破坏性操作
+    pub fn record(
+        &mut self,
+        action: AuditAction,
+        target: u32,
+        client_id: &str,
+        result: Result<(), &dyn Error>,
+    ) -> Result<(), Box<dyn Error>> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let entry = AuditEntry {
+            timestamp,
+            action,
+            target,
+            client_id: client_id.to_string(),
+            success: result.is_ok(),
+            detail: result.err().map(|e| e.to_string()).unwrap_or_default(),
+        };
+        if !entry.success {
+            warn!("审计: {}", entry);
+        }
+
+        self.store.set(&slot_key(self.next), &entry.encode())?;
+        self.next = (self.next + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+        let mut head_buf = Vec::with_capacity(8);
+        head_buf.write_u32::<LittleEndian>(self.next).ok();
+        head_buf.write_u32::<LittleEndian>(self.len).ok();
+        self.store.set(HEAD_KEY, &head_buf)?;
+        Ok(())
+    }
+
+    /// 读取最近的记录，按时间从新到旧排列
+    pub fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let mut entries = Vec::new();
+        for i in 0..self.len.min(limit as u32) {
+            let slot = (self.next + self.capacity - 1 - i) % self.capacity;
+            match self.store.get(&slot_key(slot)) {
+                Ok(Some(data)) => match AuditEntry::decode(&data) {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => warn!("审计记录 {} 解码失败: {}", slot, e),
+                },
+                Ok(None) => {}
+                Err(e) => warn!("读取审计记录 {} 失败: {}", slot, e),
+            }
+        }
+        entries
+    }
+}
+
+fn slot_key(slot: u32) -> String {
+    format!("audit_{}", slot)
+}
+
+/// 在调试控制台注册 `audit [条数]` 命令
+pub fn register_console_command(
+    console: &mut crate::console::Console,
+    log: std::sync::Arc<std::sync::Mutex<AuditLog>>,
+) {
+    console.register(
+        "audit",
+        "显示最近的破坏性操作记录: audit [条数]",
+        Box::new(move |args| {
+            let limit = args.first().and_then(|n| n.parse().ok()).unwrap_or(20);
+            let entries = log.lock().unwrap().recent(limit);
+            if entries.is_empty() {
+                return "暂无审计记录".to_string();
+            }
+            entries
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::MemoryStore;
+
+    #[test]
+    fn ring_keeps_newest_entries_after_wrapping() {
+        let mut log = AuditLog::open(Box::new(MemoryStore::new()), 3).unwrap();
+        for handle in 1..=5 {
+            log.record(AuditAction::DeleteObject, handle, "phone", Ok(())).unwrap();
+        }
+        let targets: Vec<u32> = log.recent(10).iter().map(|e| e.target).collect();
+        assert_eq!(targets, vec![5, 4, 3]);
+    }
+}
@@ -0,0 +1,289 @@
+// 控制平面鉴权 - HTTP接口、蓝牙、TCP控制通道和WebSocket共用
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use super::{ControlChannel, ControlCommand};
+
+/// 权限等级，数值越大权限越高
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AuthLevel {
+    Read = 1,  // 读取状态、浏览和下载
+    Write = 2, // 删除对象等修改操作
+    Admin = 3, // 格式化存储、修改配置
+}
+
+/// 已通过鉴权的客户端
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub client_id: String, // 客户端标识
+    pub level: AuthLevel,  // 权限等级
+}
+
+/// 鉴权错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    /// 令牌无效或已过期
+    Unauthenticated,
+
+    /// 权限不足
+    Forbidden {
+        required: AuthLevel,
+        actual: AuthLevel,
+    },
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Unauthenticated => write!(f, "未认证或令牌已过期"),
+            AuthError::Forbidden { required, actual } => {
+                write!(f, "权限不足: 需要 {:?}，当前 {:?}", required, actual)
+            }
+        }
+    }
+}
+
+impl ::std::error::Error for AuthError {}
+
+/// 鉴权器特性，可替换为其他鉴权方式
+pub trait Authenticator: Send {
+    /// 校验会话令牌，成功时返回对应的客户端
+    fn authenticate(&mut self, token: &str) -> Option<Principal>;
+
+    /// 用长期凭证(如配对得到的预共享令牌)换取会话令牌
+    fn login(&mut self, credential: &str) -> Option<String>;
+
+    /// 注销会话令牌
+    fn logout(&mut self, token: &str);
+
+    /// 登记长期凭证(配对成功时调用)，之后可以用它登录
+    fn enroll(&mut self, credential: &str, principal: Principal);
+}
+
+/// 会话令牌记录
+struct SessionEntry {
+    principal: Principal,
+    expires_at: Instant,
+}
+
+/// 基于令牌的鉴权器
+/// 配对时得到预共享令牌，客户端用它换取有时效的会话令牌，会话令牌可轮换
+pub struct TokenAuthenticator {
+    pre_shared: HashMap<String, Principal>,
+    sessions: HashMap<String, SessionEntry>,
+    session_ttl: Duration,
+}
+
+impl TokenAuthenticator {
+    /// 创建新的令牌鉴权器
+    pub fn new(session_ttl: Duration) -> Self {
+        TokenAuthenticator {
+            pre_shared: HashMap::new(),
+            sessions: HashMap::new(),
+            session_ttl,
+        }
+    }
+
+    /// 添加预共享令牌
+    pub fn add_pre_shared_token(&mut self, token: &str, principal: Principal) {
+        debug!("添加预共享令牌: {}", principal.client_id);
+        self.pre_shared.insert(token.to_string(), principal);
+    }
+
+    /// 撤销预共享令牌
+    pub fn revoke_pre_shared_token(&mut self, token: &str) -> bool {
+        self.pre_shared.remove(token).is_some()
+    }
+
+    /// 轮换会话令牌，旧令牌立即失效
+    pub fn rotate(&mut self, session_token: &str) -> Option<String> {
+        self.purge_expired();
+        let entry = self.sessions.remove(session_token)?;
+        Some(self.issue_session(entry.principal))
+    }
+
+    fn issue_session(&mut self, principal: Principal) -> String {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        self.sessions.insert(
+            token.clone(),
+            SessionEntry {
+                principal,
+                expires_at: Instant::now() + self.session_ttl,
+            },
+        );
+        token
+    }
+
+    fn purge_expired(&mut self) {
+        let now = Instant::now();
+        self.sessions.retain(|_, entry| entry.expires_at > now);
+    }
+}
+
+impl Authenticator for TokenAuthenticator {
+    /// 只接受会话令牌，预共享令牌必须先通过`login`换取会话
+    fn authenticate(&mut self, token: &str) -> Option<Principal> {
+        self.purge_expired();
+        self.sessions.get(token).map(|entry| entry.principal.clone())
+    }
+
+    /// 使用预共享令牌登录，返回新的会话令牌
+    fn login(&mut self, pre_shared_token: &str) -> Option<String> {
+        let principal = self.pre_shared.get(pre_shared_token)?.clone();
+        info!("客户端登录: {}", principal.client_id);
+        Some(self.issue_session(principal))
+    }
+
+    fn logout(&mut self, session_token: &str) {
+        self.sessions.remove(session_token);
+    }
+
+    fn enroll(&mut self, pre_shared_token: &str, principal: Principal) {
+        self.add_pre_shared_token(pre_shared_token, principal);
+    }
+}
+
+/// 鉴权守卫 - 各控制通道在执行命令前调用
+pub struct AuthGuard {
+    authenticator: Box<dyn Authenticator>,
+}
+
+impl AuthGuard {
+    /// 使用指定的鉴权器创建守卫
+    pub fn new(authenticator: Box<dyn Authenticator>) -> Self {
+        AuthGuard { authenticator }
+    }
+
+    /// 替换鉴权器
+    pub fn set_authenticator(&mut self, authenticator: Box<dyn Authenticator>) {
+        self.authenticator = authenticator;
+    }
+
+    /// 用长期凭证登录，返回会话令牌
+    pub fn login(&mut self, credential: &str) -> Option<String> {
+        let session = self.authenticator.login(credential);
+        if session.is_none() {
+            warn!("登录失败: 凭证无效");
+        }
+        session
+    }
+
+    /// 注销会话令牌
+    pub fn logout(&mut self, token: &str) {
+        self.authenticator.logout(token);
+    }
+
+    /// 登记配对得到的长期凭证
+    pub fn enroll(&mut self, credential: &str, principal: Principal) {
+        self.authenticator.enroll(credential, principal);
+    }
+
+    /// 检查令牌是否有权执行命令
+    pub fn authorize(
+        &mut self,
+        channel: ControlChannel,
+        token: &str,
+        command: &ControlCommand,
+    ) -> Result<Principal, AuthError> {
+        let principal = match self.authenticator.authenticate(token) {
+            Some(p) => p,
+            None => {
+                warn!("{:?} 通道鉴权失败: {:?}", channel, command);
+                return Err(AuthError::Unauthenticated);
+            }
+        };
+
+        let required = command.required_level();
+        if principal.level < required {
+            warn!(
+                "客户端 {} 权限不足，拒绝执行 {:?}",
+                principal.client_id, command
+            );
+            return Err(AuthError::Forbidden {
+                required,
+                actual: principal.level,
+            });
+        }
+
+        Ok(principal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn principal(client_id: &str, level: AuthLevel) -> Principal {
+        Principal { client_id: client_id.into(), level }
+    }
+
+    fn guard_with(client_id: &str, level: AuthLevel) -> AuthGuard {
+        let mut authenticator = TokenAuthenticator::new(TTL);
+        authenticator.add_pre_shared_token("psk", principal(client_id, level));
+        AuthGuard::new(Box::new(authenticator))
+    }
+
+    #[test]
+    fn login_issues_session_for_pre_shared_token() {
+        let mut authenticator = TokenAuthenticator::new(TTL);
+        authenticator.add_pre_shared_token("psk", principal("phone", AuthLevel::Read));
+        assert_eq!(authenticator.login("wrong"), None);
+        let session = authenticator.login("psk").unwrap();
+        assert_eq!(authenticator.authenticate(&session), Some(principal("phone", AuthLevel::Read)));
+        // 预共享令牌本身不能当作会话令牌使用
+        assert_eq!(authenticator.authenticate("psk"), None);
+        authenticator.logout(&session);
+        assert_eq!(authenticator.authenticate(&session), None);
+    }
+
+    #[test]
+    fn expired_session_is_rejected() {
+        let mut authenticator = TokenAuthenticator::new(Duration::ZERO);
+        authenticator.add_pre_shared_token("psk", principal("phone", AuthLevel::Read));
+        let session = authenticator.login("psk").unwrap();
+        assert_eq!(authenticator.authenticate(&session), None);
+        assert_eq!(authenticator.rotate(&session), None);
+    }
+
+    #[test]
+    fn rotate_invalidates_old_session() {
+        let mut authenticator = TokenAuthenticator::new(TTL);
+        authenticator.add_pre_shared_token("psk", principal("phone", AuthLevel::Write));
+        let old = authenticator.login("psk").unwrap();
+        let new = authenticator.rotate(&old).unwrap();
+        assert_ne!(old, new);
+        assert_eq!(authenticator.authenticate(&old), None);
+        assert_eq!(authenticator.authenticate(&new), Some(principal("phone", AuthLevel::Write)));
+    }
+
+    #[test]
+    fn authorize_checks_required_level() {
+        let mut guard = guard_with("phone", AuthLevel::Write);
+        let session = guard.login("psk").unwrap();
+        let principal = guard.authorize(ControlChannel::Http, &session, &ControlCommand::DeleteObject(1)).unwrap();
+        assert_eq!(principal.client_id, "phone");
+        let command = ControlCommand::FormatStore { storage_id: 0x0001_0001, confirmation: None };
+        assert_eq!(
+            guard.authorize(ControlChannel::Http, &session, &command),
+            Err(AuthError::Forbidden { required: AuthLevel::Admin, actual: AuthLevel::Write })
+        );
+        assert_eq!(
+            guard.authorize(ControlChannel::Http, "", &ControlCommand::GetStatus),
+            Err(AuthError::Unauthenticated)
+        );
+    }
+
+    #[test]
+    fn enrolled_credential_can_log_in() {
+        let mut guard = AuthGuard::new(Box::new(TokenAuthenticator::new(TTL)));
+        assert_eq!(guard.login("paired"), None);
+        guard.enroll("paired", principal("tablet", AuthLevel::Read));
+        let session = guard.login("paired").unwrap();
+        assert!(guard.authorize(ControlChannel::WebSocket, &session, &ControlCommand::GetStatus).is_ok());
+    }
+}
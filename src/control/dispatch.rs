@@ -0,0 +1,156 @@
+// 控制命令调度 - 控制命令由独立的高优先级任务接收并排队，取消、快门等紧急命令插到队首；
+// 数据发送在每个数据包之间检查是否有待处理的紧急命令并让出，批量传输占满链路时控制命令的等待时间仍有上限
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use serde::Serialize;
+
+use super::ControlCommand;
+use crate::ptp_mtp::CancelToken;
+use crate::runtime;
+
+/// 控制命令从收到到开始执行的等待时间统计，随实时指标推送，字段名尽量短
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ControlLatency {
+    #[serde(rename = "n")]
+    pub count: u64, // 已调度的命令数
+    #[serde(rename = "last")]
+    pub last_us: u64, // 最近一条命令的等待时间(微秒)
+    #[serde(rename = "max")]
+    pub max_us: u64, // 最长等待时间
+    #[serde(rename = "umax")]
+    pub urgent_max_us: u64, // 紧急命令的最长等待时间
+    #[serde(skip)]
+    total_us: u64,
+}
+
+impl ControlLatency {
+    /// 平均等待时间(微秒)
+    pub fn avg_us(&self) -> u64 {
+        self.total_us.checked_div(self.count).unwrap_or(0)
+    }
+
+    fn record(&mut self, waited: Duration, urgent: bool) {
+        let us = waited.as_micros() as u64;
+        self.count += 1;
+        self.total_us += us;
+        self.last_us = us;
+        self.max_us = self.max_us.max(us);
+        if urgent {
+            self.urgent_max_us = self.urgent_max_us.max(us);
+        }
+    }
+}
+
+/// 交给各控制通道的发送端，命令附带通过鉴权的客户端ID，审计日志按它记录
+pub type CommandSender = Sender<(ControlCommand, String)>;
+
+struct Queued {
+    command: ControlCommand,
+    client_id: String,
+    received: Instant,
+}
+
+struct Shared {
+    queue: Mutex<VecDeque<Queued>>,
+    ready: Condvar,
+    urgent: AtomicUsize, // 队列中的紧急命令数，数据发送时无锁检查
+    closed: AtomicBool,  // 所有发送端都已释放
+    latency: Mutex<ControlLatency>,
+}
+
+/// 待执行的控制命令队列，由执行命令的一方(通常是主循环)取出
+#[derive(Clone)]
+pub struct ControlQueue {
+    shared: Arc<Shared>,
+}
+
+/// 启动控制调度任务，返回交给各控制通道的发送端和待执行队列
+/// `cancel`为相机的取消令牌时，取消命令在调度任务中立即生效，正在进行的对象读取在下一个块之前中止
+pub fn spawn(cancel: Option<CancelToken>) -> Result<(CommandSender, ControlQueue), Box<dyn Error>> {
+    let (tx, rx) = mpsc::channel::<(ControlCommand, String)>();
+    let queue = ControlQueue {
+        shared: Arc::new(Shared {
+            queue: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
+            urgent: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            latency: Mutex::new(ControlLatency::default()),
+        }),
+    };
+    let dispatcher = queue.clone();
+    runtime::spawn(runtime::CONTROL, move || {
+        for (command, client_id) in rx {
+            if command == ControlCommand::CancelTransfer {
+                if let Some(token) = &cancel {
+                    token.cancel();
+                }
+            }
+            dispatcher.push(command, client_id);
+        }
+        dispatcher.shared.closed.store(true, Ordering::SeqCst);
+        dispatcher.shared.ready.notify_all();
+        info!("控制调度任务已退出");
+    })?;
+    Ok((tx, queue))
+}
+
+impl ControlQueue {
+    fn push(&self, command: ControlCommand, client_id: String) {
+        let urgent = command.is_urgent();
+        let queued = Queued {
+            command,
+            client_id,
+            received: Instant::now(),
+        };
+        let mut queue = self.shared.queue.lock().unwrap();
+        if urgent {
+            // 紧急命令排在已有的紧急命令之后、普通命令之前
+            let at = self.shared.urgent.fetch_add(1, Ordering::SeqCst).min(queue.len());
+            queue.insert(at, queued);
+        } else {
+            queue.push_back(queued);
+        }
+        self.shared.ready.notify_one();
+    }
+
+    /// 是否有等待执行的紧急命令，数据发送在每个数据包之前检查
+    pub fn has_urgent(&self) -> bool {
+        self.shared.urgent.load(Ordering::SeqCst) > 0
+    }
+
+    /// 取出下一条命令和发出它的客户端，最多等待`timeout`；所有发送端释放且队列为空时返回Disconnected
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<(ControlCommand, String), RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.shared.queue.lock().unwrap();
+        loop {
+            if let Some(Queued { command, client_id, received }) = queue.pop_front() {
+                let urgent = command.is_urgent();
+                if urgent {
+                    self.shared.urgent.fetch_sub(1, Ordering::SeqCst);
+                }
+                let waited = received.elapsed();
+                self.shared.latency.lock().unwrap().record(waited, urgent);
+                debug!("客户端 {} 的控制命令 {:?} 等待 {}us", client_id, command, waited.as_micros());
+                return Ok((command, client_id));
+            }
+            if self.shared.closed.load(Ordering::SeqCst) {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return Err(RecvTimeoutError::Timeout);
+            };
+            queue = self.shared.ready.wait_timeout(queue, remaining).unwrap().0;
+        }
+    }
+
+    /// 命令等待时间统计
+    pub fn latency(&self) -> ControlLatency {
+        *self.shared.latency.lock().unwrap()
+    }
+}
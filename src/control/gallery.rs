@@ -0,0 +1,192 @@
+// 相册分页查询 - 按句柄顺序分页列出台账中的对象，附带批量读取的对象摘要和对象信息缓存中的格式、拍摄时间和尺寸；
+// 游标只编码上一页最后一个句柄，不保存服务端状态，客户端重连后可以从原位置继续滚动
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::data_transfer::ObjectLedger;
+use crate::ptp_mtp::{self, MtpCamera, MtpObjectSummary, ObjectInfoCache, PtpDateTime};
+
+/// 每页的最大条目数
+pub const MAX_PAGE_SIZE: usize = 200;
+
+/// 查询过滤条件，各条件同时满足
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GalleryFilter {
+    #[serde(default)]
+    pub album: Option<String>, // 只列出带该标签的对象
+    #[serde(default)]
+    pub pending_for: Option<String>, // 只列出该客户端尚未确认接收的对象
+    #[serde(default)]
+    pub format: Option<u16>, // 只列出该对象格式，需要对象摘要或对象信息缓存
+}
+
+/// 相册中的一项
+#[derive(Debug, Clone, Serialize)]
+pub struct GalleryItem {
+    pub handle: u32,
+    pub name: String,
+    pub size: u64,
+    pub hash: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captured: Option<PtpDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// 一页查询结果
+#[derive(Debug, Clone, Serialize)]
+pub struct GalleryPage {
+    pub items: Vec<GalleryItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>, // 为None表示已到末尾
+}
+
+/// 全部存储
+const ALL_STORAGES: u32 = 0xFFFFFFFF;
+
+/// 相机上全部对象的摘要，按句柄索引；相机新增或删除对象后标记为过期，由主循环空闲时重新读取
+#[derive(Debug)]
+pub struct SummaryIndex {
+    summaries: HashMap<u32, MtpObjectSummary>,
+    stale: bool,
+}
+
+/// 主循环和HTTP接口共用的摘要索引
+pub type SummaryHandle = Arc<Mutex<SummaryIndex>>;
+
+/// 创建尚未读取的摘要索引
+pub fn summary_handle() -> SummaryHandle {
+    Arc::new(Mutex::new(SummaryIndex::new()))
+}
+
+impl SummaryIndex {
+    pub fn new() -> Self {
+        SummaryIndex {
+            summaries: HashMap::new(),
+            stale: true,
+        }
+    }
+
+    /// 对象的摘要
+    pub fn get(&self, handle: u32) -> Option<&MtpObjectSummary> {
+        self.summaries.get(&handle)
+    }
+
+    /// 是否需要重新读取
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// 相机上的对象有变化，下次空闲时重新读取
+    pub fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    /// 读取全部存储的对象摘要，支持GetObjectPropList的相机只需一次事务，返回对象数
+    pub async fn refresh(&mut self, camera: &mut MtpCamera, timeout: Option<Duration>) -> Result<usize, ptp_mtp::Error> {
+        let summaries = camera.list_object_summaries(ALL_STORAGES, timeout).await?;
+        self.summaries = summaries.into_iter().map(|s| (s.handle, s)).collect();
+        self.stale = false;
+        debug!("相册摘要已更新，共 {} 个对象", self.summaries.len());
+        Ok(self.summaries.len())
+    }
+}
+
+impl Default for SummaryIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 把句柄编码为游标
+pub fn encode_cursor(handle: u32) -> String {
+    format!("{:08x}", handle)
+}
+
+/// 解析游标
+pub fn decode_cursor(cursor: &str) -> Result<u32, Box<dyn Error>> {
+    u32::from_str_radix(cursor.trim(), 16).map_err(|_| format!("无效的游标: {}", cursor).into())
+}
+
+/// 列出`after_cursor`之后满足条件的最多`limit`个对象
+///
+/// 对象按句柄升序排列，只读取填满一页所需的台账条目
+pub fn list(
+    ledger: &ObjectLedger,
+    summaries: Option<&SummaryIndex>,
+    info_cache: Option<&ObjectInfoCache>,
+    after_cursor: Option<&str>,
+    limit: usize,
+    filter: &GalleryFilter,
+) -> Result<GalleryPage, Box<dyn Error>> {
+    let after = after_cursor.map(decode_cursor).transpose()?;
+    let limit = limit.clamp(1, MAX_PAGE_SIZE);
+    if filter.format.is_some() && summaries.is_none() && info_cache.is_none() {
+        return Err("按格式筛选需要对象摘要或对象信息缓存".into());
+    }
+    let album = filter.album.as_deref().map(str::trim);
+
+    let mut handles: Vec<u32> = ledger
+        .handles()
+        .iter()
+        .copied()
+        .filter(|h| after.map_or(true, |a| *h > a))
+        .collect();
+    handles.sort_unstable();
+
+    let mut items: Vec<GalleryItem> = Vec::with_capacity(limit);
+    let mut next_cursor = None;
+    for handle in handles {
+        let Some(entry) = ledger.get(handle)? else {
+            continue;
+        };
+        if album.is_some_and(|tag| !entry.has_tag(tag)) {
+            continue;
+        }
+        if let Some(client_id) = &filter.pending_for {
+            if ledger.receipts(handle)?.iter().any(|r| &r.client_id == client_id) {
+                continue;
+            }
+        }
+        let summary = summaries.and_then(|s| s.get(handle));
+        let info = match info_cache {
+            Some(cache) => cache.peek(handle)?,
+            None => None,
+        };
+        let format = summary.map(|s| s.format).or(info.as_ref().map(|i| i.ObjectFormat));
+        if filter.format.is_some() && format != filter.format {
+            continue;
+        }
+        // 多找到一个满足条件的对象说明还有下一页
+        if items.len() == limit {
+            next_cursor = items.last().map(|i| encode_cursor(i.handle));
+            break;
+        }
+        items.push(GalleryItem {
+            handle,
+            name: entry.name,
+            size: entry.size,
+            hash: entry.hash,
+            tags: entry.tags,
+            format,
+            captured: summary
+                .and_then(|s| s.date)
+                .or_else(|| info.as_ref().and_then(|i| i.capture_date()))
+                .or(entry.captured),
+            width: info.as_ref().map(|i| i.ImagePixWidth).filter(|w| *w > 0),
+            height: info.as_ref().map(|i| i.ImagePixHeight).filter(|h| *h > 0),
+        });
+    }
+    Ok(GalleryPage { items, next_cursor })
+}
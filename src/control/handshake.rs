@@ -0,0 +1,147 @@
+// 握手与版本协商 - 交换固件版本、协议版本和启用的功能，对旧客户端降级而不是在传输中途失败
+use std::fmt;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::orchestrator;
+
+/// 固件版本
+pub const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// 当前协议版本
+pub const PROTOCOL_VERSION: u16 = 2;
+
+/// 仍然兼容的最低协议版本
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// 引入压缩和加密的协议版本，更旧的客户端会被降级
+const COMPRESSION_SINCE: u16 = 2;
+const ENCRYPTION_SINCE: u16 = 2;
+
+/// 传输层可选能力
+pub const CAP_COMPRESSION: &str = "compression";
+pub const CAP_ENCRYPTION: &str = "encryption";
+
+/// 当前链路实际提供的传输能力，握手只声明和协商这些能力
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkCapabilities {
+    pub compression: bool, // 数据流水线包含压缩阶段
+    pub encryption: bool,  // 手机数据链路使用TLS
+}
+
+impl LinkCapabilities {
+    /// 握手中声明的能力名称
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.compression {
+            names.push(CAP_COMPRESSION);
+        }
+        if self.encryption {
+            names.push(CAP_ENCRYPTION);
+        }
+        names
+    }
+}
+
+/// 设备在握手中发送的信息
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceHello {
+    pub firmware: &'static str,       // 固件版本
+    pub protocol: u16,                // 协议版本
+    pub min_protocol: u16,            // 兼容的最低协议版本
+    pub features: Vec<&'static str>,  // 编译进固件的功能
+    pub capabilities: Vec<&'static str>, // 当前链路可协商的传输能力
+}
+
+impl DeviceHello {
+    /// 生成本设备的握手信息
+    pub fn current(link: LinkCapabilities) -> Self {
+        DeviceHello {
+            firmware: FIRMWARE_VERSION,
+            protocol: PROTOCOL_VERSION,
+            min_protocol: MIN_PROTOCOL_VERSION,
+            features: orchestrator::enabled_subsystems()
+                .iter()
+                .map(|s| s.feature_name())
+                .collect(),
+            capabilities: link.names(),
+        }
+    }
+}
+
+/// 客户端在握手中发送的信息
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientHello {
+    pub client_id: String,
+    #[serde(default = "default_client_protocol")]
+    pub protocol: u16, // 未声明协议版本的旧客户端视为版本1
+    #[serde(default)]
+    pub app_version: Option<String>,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub profile: Option<String>, // 客户端类型，见 `ClientProfile::from_name`
+}
+
+fn default_client_protocol() -> u16 {
+    1
+}
+
+/// 握手失败原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    /// 客户端协议版本过旧，无法降级兼容
+    ClientTooOld { client: u16, minimum: u16 },
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::ClientTooOld { client, minimum } => {
+                write!(f, "客户端协议版本 {} 过旧，最低要求 {}", client, minimum)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+/// 协商结果
+#[derive(Debug, Clone, Serialize)]
+pub struct NegotiatedSession {
+    pub protocol: u16,     // 双方共同使用的协议版本
+    pub compression: bool, // 是否启用压缩
+    pub encryption: bool,  // 是否启用加密
+    pub downgraded: bool,  // 是否因客户端较旧而降级
+}
+
+/// 根据客户端握手协商会话参数，链路不提供的能力即使客户端请求也不启用
+pub fn negotiate(client: &ClientHello, link: LinkCapabilities) -> Result<NegotiatedSession, HandshakeError> {
+    if client.protocol < MIN_PROTOCOL_VERSION {
+        warn!("拒绝客户端 {}: 协议版本 {}", client.client_id, client.protocol);
+        return Err(HandshakeError::ClientTooOld {
+            client: client.protocol,
+            minimum: MIN_PROTOCOL_VERSION,
+        });
+    }
+
+    let protocol = client.protocol.min(PROTOCOL_VERSION);
+    let offers = |cap: &str| client.capabilities.iter().any(|c| c == cap);
+    let compression = link.compression && protocol >= COMPRESSION_SINCE && offers(CAP_COMPRESSION);
+    let encryption = link.encryption && protocol >= ENCRYPTION_SINCE && offers(CAP_ENCRYPTION);
+    let session = NegotiatedSession {
+        protocol,
+        compression,
+        encryption,
+        downgraded: protocol < PROTOCOL_VERSION,
+    };
+
+    if session.downgraded {
+        info!(
+            "客户端 {} 使用旧协议版本 {}，已关闭压缩和加密",
+            client.client_id, client.protocol
+        );
+    }
+    Ok(session)
+}
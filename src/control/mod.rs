@@ -0,0 +1,207 @@
+// 控制平面模块 - 定义来自客户端的控制命令，以及各控制通道共用的鉴权
+pub mod audit;
+pub(crate) mod auth;
+pub mod dispatch;
+pub mod gallery;
+pub mod handshake;
+pub mod pairing;
+
+pub use audit::{AuditAction, AuditEntry, AuditLog};
+pub use auth::{AuthError, AuthGuard, AuthLevel, Authenticator, Principal, TokenAuthenticator};
+pub use dispatch::{CommandSender, ControlLatency, ControlQueue};
+pub use gallery::{GalleryFilter, GalleryItem, GalleryPage};
+pub use handshake::{ClientHello, DeviceHello, HandshakeError, LinkCapabilities, NegotiatedSession};
+pub use pairing::{PairingError, PairingManager, PairingPayload};
+
+use crate::data_transfer::{ByteRange, DownloadDestination};
+use crate::orchestrator::mode::OperatingMode;
+
+/// 控制命令来源通道
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlChannel {
+    Http,      // HTTP接口
+    Tcp,       // TCP控制通道
+    WebSocket, // WebSocket
+    Ble,       // 蓝牙
+}
+
+/// 客户端控制命令
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    GetStatus,           // 读取状态
+    Handshake,           // 协商协议版本和链路能力
+    ListObjects,         // 列出对象
+    DownloadObject(u32), // 下载对象(句柄)
+    Download { handle: u32, range: ByteRange, destination: DownloadDestination }, // 不经过自动同步队列，立即下载对象或其中一段
+    DeleteObject(u32),   // 删除对象(句柄)
+    FormatStore { storage_id: u32, confirmation: Option<u32> }, // 格式化存储，不带确认码时只申请确认码
+    TagObject { handle: u32, tag: String },   // 给对象添加标签/相册
+    UntagObject { handle: u32, tag: String }, // 移除对象的标签
+    ListAlbum(String),   // 列出相册中的对象
+    ListGallery { after: Option<String>, limit: usize, filter: GalleryFilter }, // 分页列出对象，after为上一页返回的游标
+    AcknowledgeSync(String), // 为客户端签发已同步对象的回执(客户端ID)
+    TriggerCapture,      // 触发相机快门
+    TerminateCapture,    // 结束开放式拍摄
+    SetMode(OperatingMode), // 切换工作模式
+    CancelTransfer,      // 中止正在从相机读取的对象
+    ExportConfig,        // 导出设备配置
+    ImportConfig,        // 导入设备配置
+    SetProperty { code: u16, value: Vec<u8> }, // 设置相机属性，value为按属性数据类型编码的PTP数据
+    StartLiveView,       // 开始实时取景
+    StopLiveView,        // 停止实时取景
+}
+
+/// 蓝牙接收特征值上的命令字节，参数紧跟在命令字节之后，整数均为小端
+pub mod ble_opcode {
+    pub const TRIGGER_CAPTURE: u8 = 0x01;
+    pub const TERMINATE_CAPTURE: u8 = 0x02;
+    pub const SET_MODE: u8 = 0x03; // 第二个字节为模式编号
+    pub const CANCEL_TRANSFER: u8 = 0x04;
+    pub const LIST_OBJECTS: u8 = 0x05;
+    pub const REQUEST_OBJECT: u8 = 0x06; // 句柄u32，可选偏移u64和长度u64
+    pub const SET_PROPERTY: u8 = 0x07; // 属性代码u16，其后为属性值
+    pub const START_LIVE_VIEW: u8 = 0x08;
+    pub const STOP_LIVE_VIEW: u8 = 0x09;
+    /// 参数为配对得到的预共享令牌(UTF-8)，成功后该连接上的其他命令按登录的客户端鉴权
+    pub const LOGIN: u8 = 0x0A;
+    pub const DELETE_OBJECT: u8 = 0x0B; // 句柄u32
+    /// 存储ID u32，可选确认码u32；不带确认码时应答中返回确认码，带上它再发一次才会格式化
+    pub const FORMAT_STORE: u8 = 0x0C;
+    /// 参数为二维码中的一次性配对令牌(UTF-8)，成功时应答中返回该客户端的预共享令牌，之后用LOGIN登录
+    pub const PAIR: u8 = 0x0D;
+    /// 应答的命令字节为请求的命令字节加上该位
+    pub const RESPONSE: u8 = 0x80;
+}
+
+/// 蓝牙命令应答的状态字节
+pub mod ble_status {
+    pub const OK: u8 = 0x00;
+    pub const FAILED: u8 = 0x01; // 其后为UTF-8错误信息
+}
+
+/// 通过蓝牙请求下载的对象推送给该客户端
+pub const BLE_CLIENT_ID: &str = "ble";
+
+impl ControlCommand {
+    /// 执行该命令所需的权限等级
+    pub fn required_level(&self) -> AuthLevel {
+        match self {
+            ControlCommand::GetStatus
+            | ControlCommand::Handshake
+            | ControlCommand::ListObjects
+            | ControlCommand::DownloadObject(_)
+            | ControlCommand::Download { destination: DownloadDestination::Client(_), .. }
+            | ControlCommand::ListAlbum(_)
+            | ControlCommand::ListGallery { .. }
+            | ControlCommand::AcknowledgeSync(_) => AuthLevel::Read,
+            ControlCommand::DeleteObject(_)
+            | ControlCommand::Download { destination: DownloadDestination::File(_), .. }
+            | ControlCommand::TagObject { .. }
+            | ControlCommand::UntagObject { .. }
+            | ControlCommand::TriggerCapture
+            | ControlCommand::TerminateCapture
+            | ControlCommand::SetMode(_)
+            | ControlCommand::CancelTransfer
+            | ControlCommand::SetProperty { .. }
+            | ControlCommand::StartLiveView
+            | ControlCommand::StopLiveView => AuthLevel::Write,
+            ControlCommand::FormatStore { .. } | ControlCommand::ExportConfig | ControlCommand::ImportConfig => {
+                AuthLevel::Admin
+            }
+        }
+    }
+
+    /// 是否需要优先于排队的命令和数据发送处理
+    pub fn is_urgent(&self) -> bool {
+        matches!(
+            self,
+            ControlCommand::TriggerCapture | ControlCommand::TerminateCapture | ControlCommand::CancelTransfer
+        )
+    }
+
+    /// 解析蓝牙接收特征值上重组后的命令，首字节为命令码；命令未知或参数不完整时返回None
+    pub fn from_ble(value: &[u8]) -> Option<ControlCommand> {
+        let (&opcode, args) = value.split_first()?;
+        let u32_at = |i: usize| Some(u32::from_le_bytes(args.get(i..i + 4)?.try_into().ok()?));
+        let u64_at = |i: usize| Some(u64::from_le_bytes(args.get(i..i + 8)?.try_into().ok()?));
+        match opcode {
+            ble_opcode::TRIGGER_CAPTURE => Some(ControlCommand::TriggerCapture),
+            ble_opcode::TERMINATE_CAPTURE => Some(ControlCommand::TerminateCapture),
+            ble_opcode::SET_MODE => OperatingMode::from_index(*args.first()?).map(ControlCommand::SetMode),
+            ble_opcode::CANCEL_TRANSFER => Some(ControlCommand::CancelTransfer),
+            ble_opcode::LIST_OBJECTS => Some(ControlCommand::ListObjects),
+            ble_opcode::REQUEST_OBJECT => {
+                let range = match args.len() {
+                    4 => ByteRange::FULL,
+                    12 => ByteRange { offset: u64_at(4)?, length: None },
+                    20 => ByteRange { offset: u64_at(4)?, length: Some(u64_at(12)?) },
+                    _ => return None,
+                };
+                Some(ControlCommand::Download {
+                    handle: u32_at(0)?,
+                    range,
+                    destination: DownloadDestination::Client(BLE_CLIENT_ID.to_string()),
+                })
+            }
+            ble_opcode::SET_PROPERTY => Some(ControlCommand::SetProperty {
+                code: u16::from_le_bytes(args.get(..2)?.try_into().ok()?),
+                value: args.get(2..).filter(|v| !v.is_empty())?.to_vec(),
+            }),
+            ble_opcode::START_LIVE_VIEW => Some(ControlCommand::StartLiveView),
+            ble_opcode::STOP_LIVE_VIEW => Some(ControlCommand::StopLiveView),
+            ble_opcode::DELETE_OBJECT => Some(ControlCommand::DeleteObject(u32_at(0)?)),
+            ble_opcode::FORMAT_STORE => Some(ControlCommand::FormatStore {
+                storage_id: u32_at(0)?,
+                confirmation: match args.len() {
+                    4 => None,
+                    8 => Some(u32_at(4)?),
+                    _ => return None,
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    /// 该命令在蓝牙上的命令字节，不能通过蓝牙发出的命令返回None
+    pub fn ble_opcode(&self) -> Option<u8> {
+        match self {
+            ControlCommand::TriggerCapture => Some(ble_opcode::TRIGGER_CAPTURE),
+            ControlCommand::TerminateCapture => Some(ble_opcode::TERMINATE_CAPTURE),
+            ControlCommand::SetMode(_) => Some(ble_opcode::SET_MODE),
+            ControlCommand::CancelTransfer => Some(ble_opcode::CANCEL_TRANSFER),
+            ControlCommand::ListObjects => Some(ble_opcode::LIST_OBJECTS),
+            ControlCommand::Download { destination: DownloadDestination::Client(client), .. } if client == BLE_CLIENT_ID => {
+                Some(ble_opcode::REQUEST_OBJECT)
+            }
+            ControlCommand::SetProperty { .. } => Some(ble_opcode::SET_PROPERTY),
+            ControlCommand::StartLiveView => Some(ble_opcode::START_LIVE_VIEW),
+            ControlCommand::StopLiveView => Some(ble_opcode::STOP_LIVE_VIEW),
+            ControlCommand::DeleteObject(_) => Some(ble_opcode::DELETE_OBJECT),
+            ControlCommand::FormatStore { .. } => Some(ble_opcode::FORMAT_STORE),
+            _ => None,
+        }
+    }
+}
+
+/// 编码蓝牙命令的应答：命令字节|RESPONSE、状态字节，成功时其后为结果数据，失败时为错误信息
+pub fn ble_response(opcode: u8, result: Result<&[u8], &str>) -> Vec<u8> {
+    let (status, payload) = match result {
+        Ok(data) => (ble_status::OK, data),
+        Err(message) => (ble_status::FAILED, message.as_bytes()),
+    };
+    let mut response = Vec::with_capacity(2 + payload.len());
+    response.push(opcode | ble_opcode::RESPONSE);
+    response.push(status);
+    response.extend_from_slice(payload);
+    response
+}
+
+/// LIST_OBJECTS应答的结果数据：对象数u32，其后为各对象句柄u32
+pub fn encode_handles(handles: &[u32]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 + handles.len() * 4);
+    data.extend_from_slice(&(handles.len() as u32).to_le_bytes());
+    for handle in handles {
+        data.extend_from_slice(&handle.to_le_bytes());
+    }
+    data
+}
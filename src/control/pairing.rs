@@ -0,0 +1,228 @@
+// 配对流程 - 生成一次性配对信息（可渲染为二维码），客户端提交令牌后成为受信任客户端
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use super::auth::{AuthGuard, AuthLevel, Principal};
+
+/// 配对信息格式版本
+const PAYLOAD_VERSION: u8 = 1;
+
+/// 配对信息，编码后作为二维码内容
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairingPayload {
+    pub device_id: String,        // 设备ID(MAC地址)
+    pub ble_addr: Option<String>, // 蓝牙地址
+    pub ap_ssid: Option<String>,  // 设备热点SSID
+    pub psk: Option<String>,      // 设备热点密码
+    pub token: String,            // 一次性配对令牌
+}
+
+impl PairingPayload {
+    /// 编码为二维码文本，例如 `RCAM:1;id=...;ble=...;ssid=...;psk=...;tok=...`
+    pub fn to_qr_string(&self) -> String {
+        let mut out = format!("RCAM:{};id={}", PAYLOAD_VERSION, escape(&self.device_id));
+        if let Some(addr) = &self.ble_addr {
+            out.push_str(&format!(";ble={}", escape(addr)));
+        }
+        if let Some(ssid) = &self.ap_ssid {
+            out.push_str(&format!(";ssid={}", escape(ssid)));
+        }
+        if let Some(psk) = &self.psk {
+            out.push_str(&format!(";psk={}", escape(psk)));
+        }
+        out.push_str(&format!(";tok={}", escape(&self.token)));
+        out
+    }
+}
+
+// 转义分隔符，避免SSID/密码中的 ';' '=' 破坏格式
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '%' | ';' | '=' => out.push_str(&format!("%{:02X}", c as u32)),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// 配对错误
+#[derive(Debug, Clone, PartialEq)]
+pub enum PairingError {
+    NotPending,   // 当前没有进行中的配对
+    Expired,      // 配对令牌已过期
+    InvalidToken, // 配对令牌不匹配
+}
+
+impl fmt::Display for PairingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PairingError::NotPending => write!(f, "当前没有进行中的配对"),
+            PairingError::Expired => write!(f, "配对令牌已过期"),
+            PairingError::InvalidToken => write!(f, "配对令牌无效"),
+        }
+    }
+}
+
+impl ::std::error::Error for PairingError {}
+
+/// 等待确认的配对
+struct PendingPairing {
+    token: String,
+    expires_at: Instant,
+}
+
+/// 配对管理器
+pub struct PairingManager {
+    pending: Option<PendingPairing>,
+    validity: Duration,
+    trusted_level: AuthLevel,
+}
+
+impl PairingManager {
+    /// 创建配对管理器，validity为配对令牌有效期
+    pub fn new(validity: Duration) -> Self {
+        PairingManager {
+            pending: None,
+            validity,
+            trusted_level: AuthLevel::Write,
+        }
+    }
+
+    /// 设置配对成功的客户端获得的权限等级
+    pub fn set_trusted_level(&mut self, level: AuthLevel) {
+        self.trusted_level = level;
+    }
+
+    /// 开始配对，生成新的一次性配对信息，之前未完成的配对失效
+    pub fn begin(
+        &mut self,
+        ble_addr: Option<String>,
+        ap_ssid: Option<String>,
+        psk: Option<String>,
+    ) -> PairingPayload {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+        self.pending = Some(PendingPairing {
+            token: token.clone(),
+            expires_at: Instant::now() + self.validity,
+        });
+        info!("已生成配对信息，{} 秒内有效", self.validity.as_secs());
+
+        PairingPayload {
+            device_id: device_id(),
+            ble_addr,
+            ap_ssid,
+            psk,
+            token,
+        }
+    }
+
+    /// 取消进行中的配对
+    pub fn cancel(&mut self) {
+        self.pending = None;
+    }
+
+    /// 客户端通过蓝牙(PAIR命令)或HTTP(POST /pair)提交配对令牌
+    /// 成功后返回该客户端的预共享令牌，并登记到鉴权器
+    pub fn accept(
+        &mut self,
+        token: &str,
+        client_id: &str,
+        auth: &mut AuthGuard,
+    ) -> Result<String, PairingError> {
+        let pending = self.pending.as_ref().ok_or(PairingError::NotPending)?;
+        if Instant::now() > pending.expires_at {
+            self.pending = None;
+            return Err(PairingError::Expired);
+        }
+        if pending.token != token {
+            warn!("客户端 {} 提交了错误的配对令牌", client_id);
+            return Err(PairingError::InvalidToken);
+        }
+
+        // 配对令牌只能使用一次
+        self.pending = None;
+
+        let pre_shared = uuid::Uuid::new_v4().simple().to_string();
+        auth.enroll(
+            &pre_shared,
+            Principal {
+                client_id: client_id.to_string(),
+                level: self.trusted_level,
+            },
+        );
+        info!("客户端 {} 配对成功", client_id);
+        Ok(pre_shared)
+    }
+}
+
+/// 设备ID，取自出厂MAC地址
+pub fn device_id() -> String {
+    #[allow(unused_mut)]
+    let mut mac = [0u8; 6];
+    #[cfg(target_os = "espidf")]
+    unsafe {
+        esp_idf_svc::sys::esp_efuse_mac_get_default(mac.as_mut_ptr());
+    }
+    mac.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// 蓝牙地址，ESP32的蓝牙MAC由出厂MAC推算；固件未启用蓝牙时返回None
+pub fn ble_address() -> Option<String> {
+    #[cfg(all(target_os = "espidf", feature = "ble"))]
+    let address = {
+        let mut mac = [0u8; 6];
+        unsafe {
+            esp_idf_svc::sys::esp_read_mac(mac.as_mut_ptr(), esp_idf_svc::sys::esp_mac_type_t_ESP_MAC_BT);
+        }
+        let octets: Vec<String> = mac.iter().map(|b| format!("{:02X}", b)).collect();
+        Some(octets.join(":"))
+    };
+    #[cfg(not(all(target_os = "espidf", feature = "ble")))]
+    let address = None;
+    address
+}
+
+/// 在调试控制台注册 `pair` 命令，输出可渲染为二维码的配对信息
+pub fn register_console_command(
+    console: &mut crate::console::Console,
+    pairing: std::sync::Arc<std::sync::Mutex<PairingManager>>,
+    ap_ssid: Option<String>,
+    psk: Option<String>,
+) {
+    console.register(
+        "pair",
+        "生成一次性配对信息(二维码内容)",
+        Box::new(move |_args| {
+            let payload = pairing
+                .lock()
+                .unwrap()
+                .begin(ble_address(), ap_ssid.clone(), psk.clone());
+            payload.to_qr_string()
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::auth::TokenAuthenticator;
+    use crate::control::{ControlChannel, ControlCommand};
+
+    #[test]
+    fn accepted_token_enrolls_client_once() {
+        let mut guard = AuthGuard::new(Box::new(TokenAuthenticator::new(Duration::from_secs(60))));
+        let mut pairing = PairingManager::new(Duration::from_secs(60));
+        let payload = pairing.begin(None, None, None);
+        assert_eq!(pairing.accept("wrong", "phone", &mut guard), Err(PairingError::InvalidToken));
+
+        let pre_shared = pairing.accept(&payload.token, "phone", &mut guard).unwrap();
+        let session = guard.login(&pre_shared).unwrap();
+        let principal = guard.authorize(ControlChannel::Http, &session, &ControlCommand::GetStatus).unwrap();
+        assert_eq!(principal.client_id, "phone");
+        assert_eq!(pairing.accept(&payload.token, "phone", &mut guard), Err(PairingError::NotPending));
+    }
+}
@@ -0,0 +1,169 @@
+// 多客户端下载仲裁 - 每个客户端独立的任务队列，轮询调度共享的USB链路，
+// 多个客户端请求同一对象时只从相机读取一次
+use std::collections::{HashMap, VecDeque};
+
+use log::{debug, warn};
+
+/// 入队结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnqueueOutcome {
+    Queued,        // 已加入该客户端的队列
+    Joined,        // 对象正在下载，已加入共享读取
+    AlreadyQueued, // 该客户端已请求过此对象
+    Started,       // 按需下载已占用下载名额，由调用方立即读取
+}
+
+/// 一次从相机读取对象的任务
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadJob {
+    pub handle: u32,          // 对象句柄
+    pub clients: Vec<String>, // 等待此对象的客户端
+}
+
+/// 下载仲裁器
+pub struct DownloadArbiter {
+    queues: HashMap<String, VecDeque<u32>>,
+    round_robin: VecDeque<String>,
+    in_flight: HashMap<u32, Vec<String>>,
+    max_queue_per_client: usize,
+    max_in_flight: usize,
+}
+
+impl DownloadArbiter {
+    /// 创建仲裁器，max_queue_per_client限制每个客户端排队的对象数
+    pub fn new(max_queue_per_client: usize) -> Self {
+        DownloadArbiter {
+            queues: HashMap::new(),
+            round_robin: VecDeque::new(),
+            in_flight: HashMap::new(),
+            max_queue_per_client,
+            max_in_flight: usize::MAX,
+        }
+    }
+
+    /// 限制同时进行的下载任务数(通常来自工作模式的资源预算)，已开始的任务不受影响
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+        self.max_in_flight = max_in_flight;
+    }
+
+    /// 客户端请求下载对象
+    pub fn enqueue(&mut self, client_id: &str, handle: u32) -> Result<EnqueueOutcome, String> {
+        // 正在下载的对象直接共享读取结果
+        if let Some(waiting) = self.in_flight.get_mut(&handle) {
+            if waiting.iter().any(|c| c == client_id) {
+                return Ok(EnqueueOutcome::AlreadyQueued);
+            }
+            waiting.push(client_id.to_string());
+            debug!("客户端 {} 共享正在下载的对象 0x{:08x}", client_id, handle);
+            return Ok(EnqueueOutcome::Joined);
+        }
+
+        let queue = self.queues.entry(client_id.to_string()).or_default();
+        if queue.contains(&handle) {
+            return Ok(EnqueueOutcome::AlreadyQueued);
+        }
+        if queue.len() >= self.max_queue_per_client {
+            warn!("客户端 {} 的下载队列已满", client_id);
+            return Err(format!("客户端 {} 的下载队列已满", client_id));
+        }
+        queue.push_back(handle);
+
+        if !self.round_robin.iter().any(|c| c == client_id) {
+            self.round_robin.push_back(client_id.to_string());
+        }
+        Ok(EnqueueOutcome::Queued)
+    }
+
+    /// 按需下载：不进入客户端队列，直接占用一个下载名额，结束后需调用`complete`
+    /// `shareable`为true(整个对象发给客户端)时，对象正在下载则加入共享读取；
+    /// 否则对象正在下载或名额已满时返回错误，由客户端稍后重试
+    pub fn begin_direct(&mut self, client_id: &str, handle: u32, shareable: bool) -> Result<EnqueueOutcome, String> {
+        if let Some(waiting) = self.in_flight.get_mut(&handle) {
+            if !shareable {
+                return Err(format!("对象 0x{:08x} 正在下载", handle));
+            }
+            if !waiting.iter().any(|c| c == client_id) {
+                waiting.push(client_id.to_string());
+            }
+            return Ok(EnqueueOutcome::Joined);
+        }
+        if self.in_flight.len() >= self.max_in_flight {
+            return Err("下载名额已满".to_string());
+        }
+        // 该客户端排队中的同一对象由这次下载满足
+        if let Some(queue) = self.queues.get_mut(client_id) {
+            queue.retain(|h| *h != handle);
+        }
+        debug!("客户端 {} 按需下载对象 0x{:08x}", client_id, handle);
+        self.in_flight.insert(handle, vec![client_id.to_string()]);
+        Ok(EnqueueOutcome::Started)
+    }
+
+    /// 按轮询顺序取出下一个下载任务，进行中的任务数已达上限时返回None
+    /// 其他客户端队列中的同一对象会合并到该任务中
+    pub fn next_job(&mut self) -> Option<DownloadJob> {
+        if self.in_flight.len() >= self.max_in_flight {
+            return None;
+        }
+        for _ in 0..self.round_robin.len() {
+            let client_id = self.round_robin.pop_front()?;
+            let handle = match self.queues.get_mut(&client_id).and_then(|q| q.pop_front()) {
+                Some(handle) => handle,
+                None => {
+                    // 队列已空的客户端退出轮询
+                    self.queues.remove(&client_id);
+                    continue;
+                }
+            };
+            self.round_robin.push_back(client_id.clone());
+
+            let mut clients = vec![client_id];
+            for (other, queue) in self.queues.iter_mut() {
+                if let Some(pos) = queue.iter().position(|h| *h == handle) {
+                    queue.remove(pos);
+                    if !clients.contains(other) {
+                        clients.push(other.clone());
+                    }
+                }
+            }
+
+            self.in_flight.insert(handle, clients.clone());
+            return Some(DownloadJob { handle, clients });
+        }
+        None
+    }
+
+    /// 对象下载完成，返回应接收该对象的客户端
+    pub fn complete(&mut self, handle: u32) -> Vec<String> {
+        self.in_flight.remove(&handle).unwrap_or_default()
+    }
+
+    /// 客户端断开，清除其所有请求
+    pub fn remove_client(&mut self, client_id: &str) {
+        self.queues.remove(client_id);
+        self.round_robin.retain(|c| c != client_id);
+        for waiting in self.in_flight.values_mut() {
+            waiting.retain(|c| c != client_id);
+        }
+    }
+
+    /// 客户端排队中的对象数
+    pub fn queue_len(&self, client_id: &str) -> usize {
+        self.queues.get(client_id).map(|q| q.len()).unwrap_or(0)
+    }
+
+    /// 所有客户端排队中的对象总数
+    pub fn total_queued(&self) -> usize {
+        self.queues.values().map(|q| q.len()).sum()
+    }
+
+    /// 是否有正在下载的对象
+    pub fn is_busy(&self) -> bool {
+        !self.in_flight.is_empty()
+    }
+
+    /// 没有排队和正在下载的对象，可以进行预取等后台工作
+    pub fn is_idle(&self) -> bool {
+        !self.is_busy() && self.total_queued() == 0
+    }
+}
@@ -0,0 +1,220 @@
+// 发送流抓包 - 把交给发送器的每一段数据连同时间戳原样写入SD卡上的抓包文件，可在运行时开关，
+// 客户端开发者据此拿到出问题的会话中设备实际发出的逐字节记录
+//
+// 抓包文件格式(小端序):
+//   文件头: "RCSTREAM" 版本(u16) 开始时间(u64, Unix毫秒)
+//   记录:   类型(u8) 相对时间(u64, 微秒) 客户端ID长度(u8) 客户端ID 长度(u32) 数据
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use log::{info, warn};
+
+use crate::ptp_mtp::DataPacket;
+use crate::wireless::{DataSender, SenderFuture, SenderState};
+
+const CAPTURE_MAGIC: &[u8; 8] = b"RCSTREAM";
+const CAPTURE_VERSION: u16 = 1;
+
+/// 默认的抓包目录
+pub const DEFAULT_CAPTURE_DIR: &str = "/sdcard/captures";
+
+/// 记录类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum RecordKind {
+    Data = 1,      // 交给发送器的数据
+    SendError = 2, // 发送失败(数据为错误描述)
+    Close = 3,     // 发送器被关闭
+}
+
+/// 正在写入的抓包文件
+struct ActiveCapture {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    started: Instant,
+    records: usize,
+    bytes: u64,
+}
+
+/// 运行时可开关的抓包状态，由所有被包装的发送器共享
+#[derive(Default)]
+pub struct StreamCapture {
+    active: Option<ActiveCapture>,
+}
+
+pub type CaptureHandle = Arc<Mutex<StreamCapture>>;
+
+/// 创建未开始抓包的共享状态
+pub fn handle() -> CaptureHandle {
+    Arc::new(Mutex::new(StreamCapture::default()))
+}
+
+impl StreamCapture {
+    /// 开始抓包到指定文件，已在抓包时先结束当前文件
+    pub fn start(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        self.stop()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        writer.write_all(CAPTURE_MAGIC)?;
+        writer.write_u16::<LittleEndian>(CAPTURE_VERSION)?;
+        writer.write_u64::<LittleEndian>(now_ms)?;
+        info!("开始抓取发送流到 {}", path.display());
+        self.active = Some(ActiveCapture {
+            path: path.to_path_buf(),
+            writer,
+            started: Instant::now(),
+            records: 0,
+            bytes: 0,
+        });
+        Ok(())
+    }
+
+    /// 在目录中按当前时间生成文件名并开始抓包，返回文件路径
+    pub fn start_in(&mut self, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let path = dir.join(format!("capture_{}.rcap", secs));
+        self.start(&path)?;
+        Ok(path)
+    }
+
+    /// 结束抓包并刷新文件
+    pub fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut capture) = self.active.take() {
+            capture.writer.flush()?;
+            info!(
+                "发送流抓包已结束: {} ({} 条记录, {} 字节)",
+                capture.path.display(),
+                capture.records,
+                capture.bytes
+            );
+        }
+        Ok(())
+    }
+
+    /// 是否正在抓包
+    pub fn is_active(&self) -> bool {
+        self.active.is_some()
+    }
+
+    fn record(&mut self, kind: RecordKind, client_id: &str, data: &[u8]) {
+        let Some(capture) = self.active.as_mut() else {
+            return;
+        };
+        let elapsed = capture.started.elapsed().as_micros() as u64;
+        let id = &client_id.as_bytes()[..client_id.len().min(u8::MAX as usize)];
+        let result = (|| -> std::io::Result<()> {
+            let w = &mut capture.writer;
+            w.write_u8(kind as u8)?;
+            w.write_u64::<LittleEndian>(elapsed)?;
+            w.write_u8(id.len() as u8)?;
+            w.write_all(id)?;
+            w.write_u32::<LittleEndian>(data.len() as u32)?;
+            w.write_all(data)
+        })();
+        match result {
+            Ok(()) => {
+                capture.records += 1;
+                capture.bytes += data.len() as u64;
+            }
+            // 抓包失败(例如SD卡已满)不影响发送，只停止抓包
+            Err(e) => {
+                warn!("写入抓包文件失败，停止抓包: {}", e);
+                self.active = None;
+            }
+        }
+    }
+}
+
+impl Drop for StreamCapture {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// 抓包发送器 - 原样转发给内部发送器，同时把数据写入抓包文件
+pub struct CapturingSender {
+    inner: Box<dyn DataSender>,
+    client_id: String,
+    capture: CaptureHandle,
+}
+
+impl CapturingSender {
+    pub fn new(inner: Box<dyn DataSender>, client_id: &str, capture: CaptureHandle) -> Self {
+        CapturingSender {
+            inner,
+            client_id: client_id.to_string(),
+            capture,
+        }
+    }
+}
+
+impl DataSender for CapturingSender {
+    fn connect(&mut self) -> SenderFuture<'_, ()> {
+        self.inner.connect()
+    }
+
+    fn state(&self) -> SenderState {
+        self.inner.state()
+    }
+
+    fn ready(&mut self) -> SenderFuture<'_, ()> {
+        self.inner.ready()
+    }
+
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move {
+            self.capture.lock().unwrap().record(RecordKind::Data, &self.client_id, &packet.data);
+            self.inner.send(packet).await.inspect_err(|e| {
+                self.capture.lock().unwrap().record(RecordKind::SendError, &self.client_id, e.to_string().as_bytes());
+            })
+        })
+    }
+
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        self.capture.lock().unwrap().record(RecordKind::Close, &self.client_id, &[]);
+        self.inner.close()
+    }
+}
+
+/// 注册控制台命令 `capture`
+pub fn register_console_command(console: &mut crate::console::Console, capture: CaptureHandle) {
+    console.register(
+        "capture",
+        "抓取发送流到SD卡: capture start [文件] | capture stop | capture",
+        Box::new(move |args| {
+            let mut capture = capture.lock().unwrap();
+            match args {
+                ["start"] => match capture.start_in(Path::new(DEFAULT_CAPTURE_DIR)) {
+                    Ok(path) => format!("开始抓包: {}", path.display()),
+                    Err(e) => format!("无法开始抓包: {}", e),
+                },
+                ["start", path] => match capture.start(Path::new(path)) {
+                    Ok(()) => format!("开始抓包: {}", path),
+                    Err(e) => format!("无法开始抓包: {}", e),
+                },
+                ["stop"] => match capture.stop() {
+                    Ok(()) => "抓包已结束".to_string(),
+                    Err(e) => format!("结束抓包失败: {}", e),
+                },
+                [] => match &capture.active {
+                    Some(active) => format!(
+                        "正在抓包: {} ({} 条记录, {} 字节)",
+                        active.path.display(),
+                        active.records,
+                        active.bytes
+                    ),
+                    None => "未在抓包".to_string(),
+                },
+                _ => "用法: capture start [文件] | capture stop | capture".to_string(),
+            }
+        }),
+    );
+}
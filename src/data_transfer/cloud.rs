@@ -0,0 +1,460 @@
+// 云端上传 - 把拍摄完成的对象从相机直接上传到HTTPS端点或S3兼容存储桶，手机一直不连接时照片也能离开设备
+//
+// 待上传队列和队首对象的续传标记保存在键值存储(NVS)中，重启后从上次确认的位置继续：
+// HTTPS端点按块PUT同一地址，每块带Content-Range，端点确认一块后标记前移；S3使用分片上传，每完成一个分片保存一次进度。
+// 续传标记随块频繁更新，经BatchedStore合并写入，只在对象完成或出队时立即提交。
+// 上传失败时按指数退避重试，连续失败超过上限的对象移到队尾并丢弃续传标记，不挡住后面的对象
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use embassy_futures::block_on;
+use embedded_svc::http::client::Connection;
+use embedded_svc::http::Method;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::client::EspHttpConnection;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CloudUploadConfig, HttpUploadConfig, S3Config};
+use crate::persist::{BatchedStore, KvStore};
+use crate::ptp_mtp::{read_shared_object, SharedCamera};
+use crate::runtime;
+use crate::wireless::s3::{uri_encode, S3Sender, UploadProgress};
+use crate::wireless::tls::TlsMaterial;
+use crate::wireless::DataSender;
+
+/// 上传队列和续传标记使用的NVS命名空间
+pub const NVS_NAMESPACE: &str = "cloud";
+
+const QUEUE_KEY: &str = "cloud_queue";
+const MARKER_KEY: &str = "cloud_mark";
+
+/// 队列为空时后台任务等待新对象的时间
+const IDLE_POLL: Duration = Duration::from_secs(1);
+
+/// 重试间隔上限
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// 等待上传的对象
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingUpload {
+    pub handle: u32,  // 相机对象句柄
+    pub name: String, // 文件名，作为上传地址或对象键的最后一段
+    pub size: u64,    // 字节数
+}
+
+impl PendingUpload {
+    /// 读取相机对象的文件名和大小，文件夹返回None
+    pub fn lookup(camera: &SharedCamera, handle: u32, timeout: Option<Duration>) -> Result<Option<Self>, Box<dyn Error>> {
+        let mut camera = camera.lock().unwrap();
+        let camera = camera.ptp();
+        block_on(async {
+            let info = camera.get_objectinfo(handle, timeout).await?;
+            if info.format().is_association() {
+                return Ok(None);
+            }
+            let size = camera.get_object_size(handle, &info, timeout).await?;
+            Ok(Some(PendingUpload {
+                handle,
+                name: info.Filename,
+                size,
+            }))
+        })
+    }
+}
+
+/// 队首对象的续传标记
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ResumeMarker {
+    handle: u32,
+    name: String, // 句柄可能被相机重新分配，文件名和大小都一致才续传
+    size: u64,
+    offset: u64, // HTTPS端点已确认的字节数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    s3: Option<UploadProgress>, // S3分片上传进度
+}
+
+impl ResumeMarker {
+    fn new(item: &PendingUpload) -> Self {
+        ResumeMarker {
+            handle: item.handle,
+            name: item.name.clone(),
+            size: item.size,
+            offset: 0,
+            s3: None,
+        }
+    }
+
+    /// 标记是否属于该对象
+    fn belongs_to(&self, item: &PendingUpload) -> bool {
+        self.handle == item.handle && self.name == item.name && self.size == item.size
+    }
+}
+
+/// 按块PUT接收对象的HTTPS端点
+struct HttpsTarget {
+    config: HttpUploadConfig,
+    tls: TlsMaterial,
+}
+
+impl HttpsTarget {
+    fn object_url(&self, name: &str) -> String {
+        format!("{}{}", self.config.url, uri_encode(name, true))
+    }
+
+    /// 上传对象中从`offset`开始的一块，空对象以`bytes */0`提交
+    fn put_chunk(&self, url: &str, offset: u64, data: &[u8], total: u64) -> Result<(), Box<dyn Error>> {
+        let mut conn = EspHttpConnection::new(&self.tls.http_configuration())?;
+        let length = data.len().to_string();
+        let range = match data.len() {
+            0 => format!("bytes */{}", total),
+            n => format!("bytes {}-{}/{}", offset, offset + n as u64 - 1, total),
+        };
+        let mut headers = vec![
+            ("Content-Type", "application/octet-stream"),
+            ("Content-Length", length.as_str()),
+            ("Content-Range", range.as_str()),
+        ];
+        if let Some(authorization) = &self.config.authorization {
+            headers.push(("Authorization", authorization.as_str()));
+        }
+        conn.initiate_request(Method::Put, url, &headers)?;
+        conn.write_all(data)?;
+        conn.initiate_response()?;
+        // 308表示端点收到了这一块、还在等待后续数据(可续传上传的约定)
+        let status = conn.status();
+        if !(200..300).contains(&status) && status != 308 {
+            return Err(format!("上传 {} 失败，HTTP状态码 {}", range, status).into());
+        }
+        Ok(())
+    }
+}
+
+/// 上传目标
+enum Target {
+    Https(HttpsTarget),
+    S3(Box<S3Sender>),
+}
+
+/// 云端上传器
+pub struct CloudUploader {
+    target: Target,
+    store: BatchedStore,
+    queue: VecDeque<PendingUpload>,
+    marker: Option<ResumeMarker>,
+    max_retries: u8,
+    retry_delay: Duration,
+    timeout: Option<Duration>, // 读取相机对象的事务超时
+}
+
+impl CloudUploader {
+    /// 创建上传器并读取上次保存的队列和续传标记；`s3`为设备配置中的`cloud`
+    pub fn open(
+        config: &CloudUploadConfig,
+        s3: Option<&S3Config>,
+        store: Box<dyn KvStore>,
+        timeout: Option<Duration>,
+    ) -> Result<Self, Box<dyn Error>> {
+        config.validate(s3)?;
+        let target = match (&config.http, s3) {
+            (Some(http), _) => {
+                let tls = TlsMaterial::load(&http.tls)?;
+                tls.install_global_ca()?;
+                Target::Https(HttpsTarget {
+                    config: http.clone(),
+                    tls,
+                })
+            }
+            (None, Some(s3)) => Target::S3(Box::new(S3Sender::new(s3.clone())?)),
+            (None, None) => unreachable!("validate要求至少配置一种上传目标"),
+        };
+
+        let store = BatchedStore::open(store)?;
+        let queue: VecDeque<PendingUpload> = load_json(&store, QUEUE_KEY).unwrap_or_default();
+        let marker: Option<ResumeMarker> = load_json(&store, MARKER_KEY);
+        // 标记只属于队首对象，队首已经变化时作废
+        let marker = marker.filter(|m| queue.front().is_some_and(|item| m.belongs_to(item)));
+        if !queue.is_empty() {
+            info!(
+                "云端上传队列中有 {} 个对象{}",
+                queue.len(),
+                if marker.is_some() { "，队首对象将续传" } else { "" }
+            );
+        }
+
+        Ok(CloudUploader {
+            target,
+            store,
+            queue,
+            marker,
+            max_retries: config.max_retries,
+            retry_delay: Duration::from_millis(config.retry_delay_ms),
+            timeout,
+        })
+    }
+
+    /// 等待上传的对象数
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// 把下载完成的对象加入上传队列，已在队列中时返回false
+    pub fn enqueue(&mut self, upload: PendingUpload) -> Result<bool, Box<dyn Error>> {
+        if self.queue.iter().any(|item| item.handle == upload.handle) {
+            return Ok(false);
+        }
+        debug!("对象 {} 加入云端上传队列", upload.name);
+        self.queue.push_back(upload);
+        self.save_queue()?;
+        Ok(true)
+    }
+
+    /// 从队列中移除对象(例如相机上的对象已被删除)，返回是否在队列中
+    pub fn forget(&mut self, handle: u32) -> Result<bool, Box<dyn Error>> {
+        let before = self.queue.len();
+        self.queue.retain(|item| item.handle != handle);
+        if self.queue.len() == before {
+            return Ok(false);
+        }
+        if self.marker.as_ref().is_some_and(|m| m.handle == handle) {
+            self.clear_marker()?;
+        }
+        self.save_queue()?;
+        Ok(true)
+    }
+
+    /// 上传队首对象，失败时按退避间隔重试；返回上传完成的对象，队列为空时返回None
+    /// 重试用尽后对象移到队尾并返回最后一次的错误
+    pub fn upload_next(&mut self, camera: &SharedCamera) -> Result<Option<PendingUpload>, Box<dyn Error>> {
+        let item = match self.queue.front() {
+            Some(item) => item.clone(),
+            None => return Ok(None),
+        };
+
+        let mut attempt = 0u8;
+        loop {
+            match self.try_upload(&item, camera) {
+                Ok(()) => break,
+                Err(e) if attempt < self.max_retries => {
+                    let delay = self.backoff(attempt);
+                    attempt += 1;
+                    warn!("上传对象 {} 失败({}/{})，{:?}后重试: {}", item.name, attempt, self.max_retries, delay, e);
+                    thread::sleep(delay);
+                }
+                Err(e) => {
+                    warn!("对象 {} 重试 {} 次后仍未上传成功，移到队尾: {}", item.name, attempt, e);
+                    self.queue.rotate_left(1);
+                    self.clear_marker()?;
+                    self.save_queue()?;
+                    return Err(e);
+                }
+            }
+        }
+
+        info!("对象 {} 已上传到云端 ({} 字节)", item.name, item.size);
+        self.queue.pop_front();
+        self.clear_marker()?;
+        self.save_queue()?;
+        Ok(Some(item))
+    }
+
+    /// 第`attempt`次重试之前的等待时间，每次翻倍直到上限
+    fn backoff(&self, attempt: u8) -> Duration {
+        let factor = 1u32.checked_shl(attempt as u32).unwrap_or(u32::MAX);
+        self.retry_delay.saturating_mul(factor).min(MAX_RETRY_DELAY)
+    }
+
+    /// 从续传标记处继续上传一次
+    fn try_upload(&mut self, item: &PendingUpload, camera: &SharedCamera) -> Result<(), Box<dyn Error>> {
+        let CloudUploader {
+            target,
+            store,
+            marker,
+            timeout,
+            ..
+        } = self;
+        if !marker.as_ref().is_some_and(|m| m.belongs_to(item)) {
+            *marker = Some(ResumeMarker::new(item));
+        }
+        let marker = marker.as_mut().unwrap();
+        match target {
+            Target::Https(https) => upload_https(https, store, marker, item, camera, *timeout),
+            Target::S3(sender) => upload_s3(sender, store, marker, item, camera, *timeout),
+        }
+    }
+
+    /// 保存队列并提交缓存的写入，与续传标记的清除一起生效
+    fn save_queue(&mut self) -> Result<(), Box<dyn Error>> {
+        self.store.set(QUEUE_KEY, &serde_json::to_vec(&self.queue)?)?;
+        self.store.flush()
+    }
+
+    /// 在后台任务中按顺序上传队列中的对象；相机上新增的对象经返回的句柄加入队列，句柄释放后任务退出
+    pub fn spawn(mut self, camera: SharedCamera) -> Result<CloudUploadTask, Box<dyn Error>> {
+        let (tx, rx) = mpsc::channel();
+        runtime::spawn(runtime::CLOUD_UPLOAD, move || {
+            while self.receive(&rx, &camera) {
+                // 失败的对象已经移到队尾，继续上传下一个
+                if let Err(e) = self.upload_next(&camera) {
+                    warn!("云端上传失败: {}", e);
+                }
+            }
+            info!("云端上传任务退出，{} 个对象留待下次启动", self.queue.len());
+        })?;
+        Ok(CloudUploadTask { tx })
+    }
+
+    /// 把新增对象加入队列，队列为空时等待；所有句柄释放后返回false
+    fn receive(&mut self, rx: &Receiver<u32>, camera: &SharedCamera) -> bool {
+        let mut next = if self.queue.is_empty() {
+            rx.recv_timeout(IDLE_POLL)
+        } else {
+            rx.try_recv().map_err(|e| match e {
+                mpsc::TryRecvError::Empty => RecvTimeoutError::Timeout,
+                mpsc::TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+            })
+        };
+        loop {
+            match next {
+                Ok(handle) => match PendingUpload::lookup(camera, handle, self.timeout) {
+                    Ok(Some(item)) => {
+                        if let Err(e) = self.enqueue(item) {
+                            warn!("保存云端上传队列失败: {}", e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => warn!("读取对象 0x{:08x} 的信息失败，不上传: {}", handle, e),
+                },
+                Err(RecvTimeoutError::Timeout) => return true,
+                Err(RecvTimeoutError::Disconnected) => return false,
+            }
+            next = rx.try_recv().map_err(|_| RecvTimeoutError::Timeout);
+        }
+    }
+
+    fn clear_marker(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.marker.take().is_some() {
+            self.store.remove(MARKER_KEY)?;
+        }
+        Ok(())
+    }
+}
+
+/// 后台上传任务的句柄
+pub struct CloudUploadTask {
+    tx: Sender<u32>,
+}
+
+impl CloudUploadTask {
+    /// 相机上新增的对象加入上传队列，文件名和大小由后台任务读取
+    pub fn object_added(&self, handle: u32) {
+        if self.tx.send(handle).is_err() {
+            warn!("云端上传任务已退出，对象 0x{:08x} 不上传", handle);
+        }
+    }
+}
+
+/// 读取JSON格式的值，不存在或损坏时返回None
+fn load_json<T: serde::de::DeserializeOwned>(store: &dyn KvStore, key: &str) -> Option<T> {
+    match store.get(key) {
+        Ok(Some(raw)) => serde_json::from_slice(&raw)
+            .map_err(|e| warn!("云端上传记录 {} 损坏，已忽略: {}", key, e))
+            .ok(),
+        Ok(None) => None,
+        Err(e) => {
+            warn!("读取云端上传记录 {} 失败: {}", key, e);
+            None
+        }
+    }
+}
+
+/// 续传标记交给批量存储，按时间间隔提交，掉电最多从前几块重新上传
+fn save_marker(store: &mut BatchedStore, marker: &ResumeMarker) -> Result<(), Box<dyn Error>> {
+    store.set(MARKER_KEY, &serde_json::to_vec(marker)?)
+}
+
+/// 按块PUT到HTTPS端点，每块确认后保存标记
+fn upload_https(
+    target: &HttpsTarget,
+    store: &mut BatchedStore,
+    marker: &mut ResumeMarker,
+    item: &PendingUpload,
+    camera: &SharedCamera,
+    timeout: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    let url = target.object_url(&item.name);
+    if item.size == 0 {
+        return target.put_chunk(&url, 0, &[], 0);
+    }
+    if marker.offset > 0 {
+        info!("对象 {} 从 {}/{} 字节处续传", item.name, marker.offset, item.size);
+    }
+
+    let chunk_size = target.config.chunk_size;
+    let mut buf = Vec::with_capacity(chunk_size);
+    let mut commit = |buf: &mut Vec<u8>, marker: &mut ResumeMarker| -> Result<(), Box<dyn Error>> {
+        target.put_chunk(&url, marker.offset, buf, item.size)?;
+        marker.offset += buf.len() as u64;
+        buf.clear();
+        save_marker(store, marker)
+    };
+
+    if marker.offset < item.size {
+        read_shared_object(camera, item.handle, marker.offset, item.size, timeout, &mut |mut data| {
+            while !data.is_empty() {
+                let n = (chunk_size - buf.len()).min(data.len());
+                buf.extend_from_slice(&data[..n]);
+                data = &data[n..];
+                if buf.len() == chunk_size {
+                    commit(&mut buf, marker)?;
+                }
+            }
+            Ok(())
+        })?;
+        if !buf.is_empty() {
+            commit(&mut buf, marker)?;
+        }
+    }
+    if marker.offset != item.size {
+        return Err(format!("对象 {} 数据不完整: {}/{}", item.name, marker.offset, item.size).into());
+    }
+    Ok(())
+}
+
+/// 分片上传到S3，每完成一个分片保存进度；预签名地址不支持续传，每次从头上传
+fn upload_s3(
+    sender: &mut S3Sender,
+    store: &mut BatchedStore,
+    marker: &mut ResumeMarker,
+    item: &PendingUpload,
+    camera: &SharedCamera,
+    timeout: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    // 丢弃上一次失败时未完成的请求
+    block_on(sender.close())?;
+    match marker.s3.clone() {
+        Some(progress) if progress.upload_id.is_some() => sender.resume_object(progress)?,
+        _ => {
+            sender.begin_object(&item.name, item.size)?;
+            marker.s3 = sender.progress().cloned();
+            save_marker(store, marker)?;
+        }
+    }
+
+    let offset = sender.written();
+    if offset < item.size {
+        read_shared_object(camera, item.handle, offset, item.size, timeout, &mut |data| {
+            sender.write_object_data(data)?;
+            let parts = sender.progress().map_or(0, |p| p.parts.len());
+            if marker.s3.as_ref().is_some_and(|p| p.parts.len() != parts) {
+                marker.s3 = sender.progress().cloned();
+                save_marker(store, marker)?;
+            }
+            Ok(())
+        })?;
+    }
+    sender.finish_object()
+}
@@ -0,0 +1,132 @@
+// 增量同步 - 设备发送对象清单，目标端报告已有的摘要，设备只重发缺失的对象
+use std::collections::HashSet;
+use std::error::Error;
+
+use embassy_futures::block_on;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::ledger::{LedgerEntry, ObjectLedger};
+use super::send_packet;
+use crate::ptp_mtp::{DataPacket, PacketType};
+use crate::wireless::DataSender;
+
+/// 发给目标端的对象清单
+#[derive(Debug, Clone, Serialize)]
+pub struct Manifest {
+    pub device_id: String,
+    pub objects: Vec<LedgerEntry>,
+}
+
+impl Manifest {
+    /// 由台账生成清单；指定相册时只包含带该标签的对象
+    pub fn from_ledger(device_id: &str, ledger: &ObjectLedger, album: Option<&str>) -> Self {
+        let objects = match album {
+            Some(tag) => ledger.album(tag),
+            None => ledger.entries(),
+        };
+        Manifest {
+            device_id: device_id.to_string(),
+            objects,
+        }
+    }
+}
+
+/// 目标端对清单的回复：已经持有的对象摘要
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TargetInventory {
+    pub have: Vec<String>,
+}
+
+/// 增量同步目标
+pub trait DeltaTarget {
+    /// 发送清单并取回目标端已有对象的摘要
+    fn exchange_manifest(&mut self, manifest: &Manifest) -> Result<TargetInventory, Box<dyn Error>>;
+}
+
+/// 比对结果
+#[derive(Debug, Clone, Default)]
+pub struct DeltaPlan {
+    pub missing: Vec<LedgerEntry>, // 需要重发的对象
+    pub present: usize,            // 目标端已有的对象数
+}
+
+impl DeltaPlan {
+    /// 对比清单和目标端回复
+    pub fn compute(manifest: &Manifest, inventory: &TargetInventory) -> Self {
+        let have: HashSet<&str> = inventory.have.iter().map(|h| h.as_str()).collect();
+        let (present, missing): (Vec<_>, Vec<_>) = manifest
+            .objects
+            .iter()
+            .cloned()
+            .partition(|entry| have.contains(entry.hash.as_str()));
+        DeltaPlan { missing, present: present.len() }
+    }
+
+    /// 需要重发的字节数
+    pub fn missing_bytes(&self) -> u64 {
+        self.missing.iter().map(|e| e.size).sum()
+    }
+}
+
+/// 同步结果
+#[derive(Debug, Clone, Default)]
+pub struct DeltaReport {
+    pub sent: usize,    // 已重发的对象数
+    pub skipped: usize, // 目标端已有而跳过的对象数
+    pub failed: usize,  // 读取或发送失败的对象数
+    pub bytes: u64,     // 重发的字节数
+}
+
+/// 执行一次增量同步
+/// `album` 限定只同步某个相册；`fetch` 按句柄重新读取对象数据，读到的数据摘要与台账不一致时不发送
+pub fn sync<F>(
+    device_id: &str,
+    ledger: &ObjectLedger,
+    album: Option<&str>,
+    target: &mut dyn DeltaTarget,
+    sender: &mut dyn DataSender,
+    mut fetch: F,
+) -> Result<DeltaReport, Box<dyn Error>>
+where
+    F: FnMut(u32) -> Result<Vec<u8>, Box<dyn Error>>,
+{
+    let manifest = Manifest::from_ledger(device_id, ledger, album);
+    let inventory = target.exchange_manifest(&manifest)?;
+    let plan = DeltaPlan::compute(&manifest, &inventory);
+    info!(
+        "增量同步: 共 {} 个对象，目标已有 {}，需重发 {} ({} 字节)",
+        manifest.objects.len(),
+        plan.present,
+        plan.missing.len(),
+        plan.missing_bytes()
+    );
+
+    let mut report = DeltaReport { skipped: plan.present, ..Default::default() };
+    for entry in &plan.missing {
+        let data = match fetch(entry.handle) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("读取对象 {} 失败: {}", entry.name, e);
+                report.failed += 1;
+                continue;
+            }
+        };
+        if super::ledger::hash_object(&data) != entry.hash {
+            warn!("对象 {} 的内容与台账不一致，跳过", entry.name);
+            report.failed += 1;
+            continue;
+        }
+        match block_on(send_packet(sender, &DataPacket::new(PacketType::Image, data))) {
+            Ok(n) => {
+                report.sent += 1;
+                report.bytes += n as u64;
+            }
+            Err(e) => {
+                warn!("发送对象 {} 失败: {}", entry.name, e);
+                report.failed += 1;
+            }
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,418 @@
+// 传输自检 - 在内存中把模拟相机、按脚本丢包/重复/延迟的发送器和TransferManager连起来，
+// 在故障注入下检查端到端的完整性、断线续传和顺序。结果完全由随机种子决定，只在测试中编译
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use log::{debug, info};
+
+use super::ledger::hash_object;
+use super::rng::XorShift;
+use super::{ClientProfile, TransferManager, TransferStatus};
+use crate::ptp_mtp::{DataListener, DataPacket, PacketType};
+use crate::wireless::{DataSender, SenderFuture};
+
+/// 帧头: 对象ID(u32) 序号(u32) 分片总数(u32)
+const FRAME_HEADER_LEN: usize = 12;
+
+const HARNESS_CLIENT_ID: &str = "selftest";
+
+/// 故障注入脚本
+#[derive(Debug, Clone)]
+pub struct FaultPlan {
+    pub seed: u32,
+    pub drop_percent: u32,         // 丢弃帧的概率
+    pub duplicate_percent: u32,    // 重复发送帧的概率
+    pub delay_percent: u32,        // 延迟帧(被后面的帧超过)的概率
+    pub disconnect_after: Option<usize>, // 发送这么多帧后模拟一次断线
+}
+
+impl Default for FaultPlan {
+    fn default() -> Self {
+        FaultPlan {
+            seed: 0x5EED,
+            drop_percent: 5,
+            duplicate_percent: 5,
+            delay_percent: 10,
+            disconnect_after: Some(40),
+        }
+    }
+}
+
+/// 自检场景
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub objects: u32,      // 模拟相机上的对象数
+    pub max_size: usize,   // 单个对象的最大字节数
+    pub chunk_size: usize, // 每帧负载字节数
+    pub faults: FaultPlan,
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Scenario {
+            objects: 8,
+            max_size: 16 * 1024,
+            chunk_size: 1024,
+            faults: FaultPlan::default(),
+        }
+    }
+}
+
+/// 接收端收到的帧
+#[derive(Default)]
+struct ReceiverLog {
+    frames: Vec<Vec<u8>>,
+}
+
+/// 按脚本注入故障的发送器
+struct ScriptedSender {
+    plan: FaultPlan,
+    rng: XorShift,
+    sent: usize,
+    delayed: Vec<Vec<u8>>,
+    receiver: Arc<Mutex<ReceiverLog>>,
+    stats: Arc<Mutex<FaultStats>>,
+}
+
+/// 实际注入的故障次数
+#[derive(Debug, Clone, Default)]
+struct FaultStats {
+    dropped: usize,
+    duplicated: usize,
+    delayed: usize,
+    disconnects: usize,
+    out_of_order: usize,          // TransferManager交给发送器的帧没有按序号递增
+    last_seq: BTreeMap<u32, u32>, // 本轮每个对象最后交给发送器的序号
+}
+
+impl FaultStats {
+    /// 在注入故障之前检查TransferManager交来的帧的顺序
+    fn check_order(&mut self, frame: &[u8]) {
+        if let Some((handle, seq, _, _)) = decode_frame(frame) {
+            if self.last_seq.insert(handle, seq).is_some_and(|prev| seq <= prev) {
+                self.out_of_order += 1;
+            }
+        }
+    }
+}
+
+impl ScriptedSender {
+    fn new(plan: FaultPlan, receiver: Arc<Mutex<ReceiverLog>>, stats: Arc<Mutex<FaultStats>>) -> Self {
+        let rng = XorShift::new(plan.seed);
+        ScriptedSender {
+            plan,
+            rng,
+            sent: 0,
+            delayed: Vec::new(),
+            receiver,
+            stats,
+        }
+    }
+}
+
+impl ScriptedSender {
+    /// 按脚本投递一帧
+    fn deliver(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>> {
+        if let Some(limit) = self.plan.disconnect_after {
+            if self.sent == limit {
+                // 只断一次，重连后继续按脚本注入其他故障
+                self.plan.disconnect_after = None;
+                self.stats.lock().unwrap().disconnects += 1;
+                return Err("模拟断线".into());
+            }
+        }
+        self.sent += 1;
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.check_order(data);
+        let mut receiver = self.receiver.lock().unwrap();
+        if self.rng.chance(self.plan.drop_percent) {
+            stats.dropped += 1;
+        } else if self.rng.chance(self.plan.delay_percent) {
+            stats.delayed += 1;
+            self.delayed.push(data.to_vec());
+        } else {
+            receiver.frames.push(data.to_vec());
+            // 被延迟的帧排在当前帧之后到达
+            receiver.frames.append(&mut self.delayed);
+            if self.rng.chance(self.plan.duplicate_percent) {
+                stats.duplicated += 1;
+                receiver.frames.push(data.to_vec());
+            }
+        }
+        Ok(data.len())
+    }
+}
+
+impl DataSender for ScriptedSender {
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move { self.deliver(&packet.data) })
+    }
+
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            self.receiver.lock().unwrap().frames.append(&mut self.delayed);
+            // 续传轮只发送缺失的帧，序号从头计算
+            self.stats.lock().unwrap().last_seq.clear();
+            Ok(())
+        })
+    }
+}
+
+/// 内存中的模拟相机，对象内容由种子决定
+struct SimulatedCamera {
+    objects: BTreeMap<u32, Vec<u8>>,
+}
+
+impl SimulatedCamera {
+    fn new(scenario: &Scenario) -> Self {
+        let mut rng = XorShift::new(scenario.faults.seed ^ 0xCA3E_4A00);
+        let objects = (1..=scenario.objects)
+            .map(|handle| {
+                let size = 1 + rng.next() as usize % scenario.max_size.max(1);
+                let data = (0..size).map(|_| rng.next() as u8).collect();
+                (handle, data)
+            })
+            .collect();
+        SimulatedCamera { objects }
+    }
+
+    /// 把对象切成带帧头的数据包
+    fn frames(&self, handle: u32, chunk_size: usize) -> Vec<DataPacket> {
+        let data = &self.objects[&handle];
+        let total = data.len().div_ceil(chunk_size) as u32;
+        data.chunks(chunk_size)
+            .enumerate()
+            .map(|(seq, chunk)| DataPacket {
+                data: encode_frame(handle, seq as u32, total, chunk),
+                timestamp: SystemTime::now(),
+                packet_type: PacketType::Image,
+            })
+            .collect()
+    }
+}
+
+fn encode_frame(handle: u32, seq: u32, total: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&handle.to_le_bytes());
+    frame.extend_from_slice(&seq.to_le_bytes());
+    frame.extend_from_slice(&total.to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn decode_frame(frame: &[u8]) -> Option<(u32, u32, u32, &[u8])> {
+    if frame.len() < FRAME_HEADER_LEN {
+        return None;
+    }
+    let word = |i: usize| u32::from_le_bytes([frame[i], frame[i + 1], frame[i + 2], frame[i + 3]]);
+    Some((word(0), word(4), word(8), &frame[FRAME_HEADER_LEN..]))
+}
+
+/// 接收端重组状态
+#[derive(Default)]
+struct Reassembly {
+    chunks: BTreeMap<u32, (u32, BTreeMap<u32, Vec<u8>>)>, // 对象ID -> (分片总数, 序号 -> 负载)
+    reordered: usize,
+    malformed: usize,
+}
+
+impl Reassembly {
+    fn ingest(&mut self, frames: &[Vec<u8>]) {
+        let mut last_seq: BTreeMap<u32, u32> = BTreeMap::new();
+        for frame in frames {
+            let Some((handle, seq, total, payload)) = decode_frame(frame) else {
+                self.malformed += 1;
+                continue;
+            };
+            if let Some(prev) = last_seq.insert(handle, seq) {
+                if seq < prev {
+                    self.reordered += 1;
+                }
+            }
+            let entry = self.chunks.entry(handle).or_insert_with(|| (total, BTreeMap::new()));
+            entry.1.insert(seq, payload.to_vec());
+        }
+    }
+
+    /// 缺失的分片(对象ID, 序号)
+    fn missing(&self, camera: &SimulatedCamera, chunk_size: usize) -> Vec<(u32, u32)> {
+        let mut missing = Vec::new();
+        for (handle, data) in &camera.objects {
+            let total = data.len().div_ceil(chunk_size) as u32;
+            let have: BTreeSet<u32> = self
+                .chunks
+                .get(handle)
+                .map(|(_, c)| c.keys().copied().collect())
+                .unwrap_or_default();
+            missing.extend((0..total).filter(|seq| !have.contains(seq)).map(|seq| (*handle, seq)));
+        }
+        missing
+    }
+
+    fn assemble(&self, handle: u32) -> Option<Vec<u8>> {
+        let (total, chunks) = self.chunks.get(&handle)?;
+        if chunks.len() as u32 != *total {
+            return None;
+        }
+        Some(chunks.values().flatten().copied().collect())
+    }
+}
+
+/// 自检结果
+#[derive(Debug, Clone, Default)]
+pub struct HarnessReport {
+    pub objects: usize,
+    pub frames: usize,
+    pub dropped: usize,
+    pub duplicated: usize,
+    pub delayed: usize,
+    pub disconnects: usize,
+    pub resent: usize,
+    pub resume_rounds: usize,
+    pub reordered: usize,     // 接收端看到的乱序，由注入的延迟造成，接收端按序号重组
+    pub out_of_order: usize,  // TransferManager没有按顺序交给发送器的帧
+    pub malformed: usize,     // 无法解析的帧
+    pub corrupted: Vec<u32>,  // 内容与源不一致的对象
+    pub incomplete: Vec<u32>, // 重试后仍不完整的对象
+}
+
+impl HarnessReport {
+    /// 检查所有对象是否完整且内容一致
+    pub fn verify(&self) -> Result<(), String> {
+        if self.malformed > 0 {
+            return Err(format!("收到 {} 个损坏的帧", self.malformed));
+        }
+        if self.out_of_order > 0 {
+            return Err(format!("{} 个帧没有按顺序发出", self.out_of_order));
+        }
+        if !self.incomplete.is_empty() {
+            return Err(format!("对象未完整送达: {:?}", self.incomplete));
+        }
+        if !self.corrupted.is_empty() {
+            return Err(format!("对象内容不一致: {:?}", self.corrupted));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for HarnessReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "对象 {} 帧 {} | 丢弃 {} 重复 {} 延迟 {} 断线 {} | 续传 {} 轮共 {} 帧 | 到达乱序 {} 发送乱序 {} | {}",
+            self.objects,
+            self.frames,
+            self.dropped,
+            self.duplicated,
+            self.delayed,
+            self.disconnects,
+            self.resume_rounds,
+            self.resent,
+            self.reordered,
+            self.out_of_order,
+            match self.verify() {
+                Ok(()) => "通过".to_string(),
+                Err(e) => format!("失败: {}", e),
+            }
+        )
+    }
+}
+
+/// 续传轮数上限，丢包率过高时避免无限重试
+const MAX_RESUME_ROUNDS: usize = 16;
+
+/// 运行一次自检
+pub fn run(scenario: &Scenario) -> Result<HarnessReport, Box<dyn Error>> {
+    let camera = SimulatedCamera::new(scenario);
+    let receiver = Arc::new(Mutex::new(ReceiverLog::default()));
+    let stats = Arc::new(Mutex::new(FaultStats::default()));
+
+    let mut transfer = TransferManager::new(scenario.objects as usize * 4);
+    transfer.add_client(
+        HARNESS_CLIENT_ID,
+        ClientProfile::FullIngest,
+        Box::new(ScriptedSender::new(scenario.faults.clone(), receiver.clone(), stats.clone())),
+    );
+    transfer.start()?;
+
+    let mut report = HarnessReport {
+        objects: camera.objects.len(),
+        ..Default::default()
+    };
+
+    // 第一轮：按顺序推送所有对象；断线后恢复传输并继续
+    let mut queue: Vec<DataPacket> = camera
+        .objects
+        .keys()
+        .flat_map(|handle| camera.frames(*handle, scenario.chunk_size))
+        .collect();
+    report.frames = queue.len();
+
+    let mut reassembly = Reassembly::default();
+    loop {
+        for packet in queue.drain(..) {
+            transfer.on_data_received(&packet);
+            if transfer.get_status() == TransferStatus::Error {
+                debug!("传输中断，恢复后继续");
+                transfer.reset();
+                transfer.start()?;
+            }
+        }
+        // 刷出被延迟的帧，并按接收端的缺失列表续传
+        transfer.stop()?;
+        reassembly.ingest(&std::mem::take(&mut receiver.lock().unwrap().frames));
+        let missing = reassembly.missing(&camera, scenario.chunk_size);
+        if missing.is_empty() || report.resume_rounds == MAX_RESUME_ROUNDS {
+            break;
+        }
+        report.resume_rounds += 1;
+        report.resent += missing.len();
+        for (handle, seq) in missing {
+            queue.push(camera.frames(handle, scenario.chunk_size).swap_remove(seq as usize));
+        }
+        transfer.start()?;
+    }
+
+    for (handle, data) in &camera.objects {
+        match reassembly.assemble(*handle) {
+            Some(received) if hash_object(&received) == hash_object(data) => {}
+            Some(_) => report.corrupted.push(*handle),
+            None => report.incomplete.push(*handle),
+        }
+    }
+
+    let stats = stats.lock().unwrap();
+    report.dropped = stats.dropped;
+    report.duplicated = stats.duplicated;
+    report.delayed = stats.delayed;
+    report.disconnects = stats.disconnects;
+    report.reordered = reassembly.reordered;
+    report.out_of_order = stats.out_of_order;
+    report.malformed = reassembly.malformed;
+    info!("传输自检: {}", report);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_scenario_survives_faults() {
+        let report = run(&Scenario::default()).unwrap();
+        assert!(report.dropped > 0 && report.delayed > 0 && report.disconnects == 1, "{}", report);
+        report.verify().unwrap();
+    }
+
+    #[test]
+    fn verify_rejects_out_of_order_frames() {
+        let report = HarnessReport { out_of_order: 1, ..Default::default() };
+        assert!(report.verify().is_err());
+        let report = HarnessReport { reordered: 3, ..Default::default() };
+        assert!(report.verify().is_ok());
+    }
+}
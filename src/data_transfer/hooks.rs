@@ -0,0 +1,60 @@
+// 数据包钩子 - 固件项目在编译时提供的处理函数(给预览加水印、拦截RAW等)，
+// 启动时注册后即可像内置阶段一样在流水线配置中按名称使用，不必修改流水线代码
+use std::error::Error;
+use std::sync::Mutex;
+
+use log::{info, warn};
+
+use super::pipeline::{PacketContext, PipelineStage, StageAction, StageSpec};
+use crate::ptp_mtp::DataPacket;
+
+/// 钩子函数：可以检查或原地修改数据包，`spec`为配置中该阶段的参数
+pub type PacketHook = fn(&StageSpec, &mut DataPacket, &mut PacketContext) -> Result<StageAction, Box<dyn Error>>;
+
+/// 已注册的钩子(名称, 函数)
+static HOOKS: Mutex<Vec<(&'static str, PacketHook)>> = Mutex::new(Vec::new());
+
+/// 注册钩子，同名钩子会被替换；之后构建的流水线才能使用
+pub fn register_packet_hook(name: &'static str, hook: PacketHook) {
+    let mut hooks = HOOKS.lock().unwrap();
+    match hooks.iter_mut().find(|(n, _)| *n == name) {
+        Some(entry) => {
+            warn!("数据包钩子 {} 已存在，替换为新的实现", name);
+            entry.1 = hook;
+        }
+        None => {
+            info!("已注册数据包钩子 {}", name);
+            hooks.push((name, hook));
+        }
+    }
+}
+
+/// 已注册的钩子
+pub fn registered() -> Vec<(&'static str, PacketHook)> {
+    HOOKS.lock().unwrap().clone()
+}
+
+/// 把钩子包装为流水线阶段
+pub(crate) struct HookStage {
+    spec: StageSpec,
+    hook: PacketHook,
+}
+
+impl HookStage {
+    pub(crate) fn new(spec: &StageSpec, hook: PacketHook) -> Self {
+        HookStage {
+            spec: spec.clone(),
+            hook,
+        }
+    }
+}
+
+impl PipelineStage for HookStage {
+    fn name(&self) -> &str {
+        &self.spec.name
+    }
+
+    fn process(&mut self, packet: &mut DataPacket, ctx: &mut PacketContext) -> Result<StageAction, Box<dyn Error>> {
+        (self.hook)(&self.spec, packet, ctx)
+    }
+}
@@ -0,0 +1,187 @@
+// 链路劣化注入 - 调试用，包装正在使用的DataSender，按配置静默丢弃数据并加入延迟和抖动，
+// 用来在真实硬件上验证客户端的重组和重试逻辑能否应付最差的无线环境
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use embassy_time::{Duration as EmbassyDuration, Timer};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::rng::XorShift;
+use crate::ptp_mtp::DataPacket;
+use crate::wireless::{DataSender, SenderFuture, SenderState};
+
+/// 劣化参数，全部为0时等同于直接发送
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Impairment {
+    pub seed: u32,         // 随机种子，相同种子得到相同的丢包序列
+    pub loss_percent: u32, // 丢弃一次发送的概率
+    pub latency: Duration, // 每次发送前的固定延迟
+    pub jitter: Duration,  // 在固定延迟上随机增减的最大值
+}
+
+impl Default for Impairment {
+    fn default() -> Self {
+        Impairment {
+            seed: 0x1A7E,
+            loss_percent: 0,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+        }
+    }
+}
+
+impl Impairment {
+    /// 是否会改变发送行为
+    pub fn is_active(&self) -> bool {
+        self.loss_percent > 0 || !self.latency.is_zero() || !self.jitter.is_zero()
+    }
+}
+
+impl fmt::Display for Impairment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "丢包 {}%, 延迟 {}ms ±{}ms",
+            self.loss_percent,
+            self.latency.as_millis(),
+            self.jitter.as_millis()
+        )
+    }
+}
+
+/// 运行时共享的劣化设置和统计，控制台修改后对所有被包装的发送器立即生效
+#[derive(Debug, Default)]
+pub struct ImpairmentState {
+    pub settings: Impairment,
+    pub sent: u64,    // 实际发送的次数
+    pub dropped: u64, // 被丢弃的次数
+}
+
+pub type ImpairmentHandle = Arc<Mutex<ImpairmentState>>;
+
+/// 创建共享的劣化设置
+pub fn handle(settings: Impairment) -> ImpairmentHandle {
+    Arc::new(Mutex::new(ImpairmentState {
+        settings,
+        ..Default::default()
+    }))
+}
+
+/// 注入劣化的发送器
+///
+/// 丢弃的数据仍向调用方报告发送成功，与无线链路上的静默丢包一致，只能由客户端发现并重传
+pub struct ImpairedSender {
+    inner: Box<dyn DataSender>,
+    state: ImpairmentHandle,
+    rng: XorShift,
+    seed: u32,
+}
+
+impl ImpairedSender {
+    pub fn new(inner: Box<dyn DataSender>, state: ImpairmentHandle) -> Self {
+        let seed = state.lock().unwrap().settings.seed;
+        ImpairedSender {
+            inner,
+            state,
+            rng: XorShift::new(seed),
+            seed,
+        }
+    }
+
+    /// 本次发送前的等待时间
+    fn delay(&mut self, settings: &Impairment) -> Duration {
+        let jitter_ms = settings.jitter.as_millis() as u64;
+        if jitter_ms == 0 {
+            return settings.latency;
+        }
+        let offset = self.rng.next() as u64 % (2 * jitter_ms + 1);
+        (settings.latency + Duration::from_millis(offset)).saturating_sub(settings.jitter)
+    }
+}
+
+impl DataSender for ImpairedSender {
+    fn connect(&mut self) -> SenderFuture<'_, ()> {
+        self.inner.connect()
+    }
+
+    fn state(&self) -> SenderState {
+        self.inner.state()
+    }
+
+    fn ready(&mut self) -> SenderFuture<'_, ()> {
+        self.inner.ready()
+    }
+
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move {
+            let settings = self.state.lock().unwrap().settings;
+            if !settings.is_active() {
+                return self.inner.send(packet).await;
+            }
+            // 修改种子后重新开始随机序列，便于复现同一组丢包
+            if settings.seed != self.seed {
+                self.seed = settings.seed;
+                self.rng = XorShift::new(settings.seed);
+            }
+
+            if self.rng.chance(settings.loss_percent) {
+                self.state.lock().unwrap().dropped += 1;
+                debug!("劣化注入: 丢弃 {} 字节", packet.data.len());
+                return Ok(packet.data.len());
+            }
+            let delay = self.delay(&settings);
+            if !delay.is_zero() {
+                Timer::after(EmbassyDuration::from_micros(delay.as_micros() as u64)).await;
+            }
+            let sent = self.inner.send(packet).await?;
+            self.state.lock().unwrap().sent += 1;
+            Ok(sent)
+        })
+    }
+
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        self.inner.close()
+    }
+}
+
+/// 注册控制台命令 `impair`
+pub fn register_console_command(console: &mut crate::console::Console, state: ImpairmentHandle) {
+    const USAGE: &str = "用法: impair <丢包%> [延迟ms] [抖动ms] [种子] | impair off | impair";
+    console.register(
+        "impair",
+        "调试用链路劣化: impair <丢包%> [延迟ms] [抖动ms] [种子] | off",
+        Box::new(move |args| {
+            let mut state = state.lock().unwrap();
+            match args {
+                [] => {}
+                ["off"] => {
+                    state.settings = Impairment {
+                        seed: state.settings.seed,
+                        ..Impairment::default()
+                    };
+                }
+                _ => {
+                    let values: Result<Vec<u32>, _> = args.iter().map(|v| v.parse::<u32>()).collect();
+                    let values = match values {
+                        Ok(values) if values.len() <= 4 => values,
+                        _ => return USAGE.to_string(),
+                    };
+                    let arg = |i: usize| values.get(i).copied();
+                    state.settings.loss_percent = arg(0).unwrap_or(0).min(100);
+                    state.settings.latency = Duration::from_millis(arg(1).unwrap_or(0) as u64);
+                    state.settings.jitter = Duration::from_millis(arg(2).unwrap_or(0) as u64);
+                    if let Some(seed) = arg(3) {
+                        state.settings.seed = seed;
+                    }
+                }
+            }
+            let status = if state.settings.is_active() { "已启用" } else { "未启用" };
+            format!(
+                "链路劣化{}: {} (已发送 {}, 已丢弃 {})",
+                status, state.settings, state.sent, state.dropped
+            )
+        }),
+    );
+}
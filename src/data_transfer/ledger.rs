@@ -0,0 +1,404 @@
+// 对象台账 - 记录每个已下载对象的大小和SHA-256摘要，用于增量同步和交付证明
+use std::error::Error;
+use std::sync::mpsc::Sender;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::receipt::{self, Receipt};
+use crate::persist::KvStore;
+use crate::ptp_mtp::PtpDateTime;
+
+/// 保存台账的NVS命名空间
+pub const NVS_NAMESPACE: &str = "ledger";
+
+const INDEX_KEY: &str = "ledger_idx";
+
+/// 标签/相册名的最大长度
+pub const MAX_TAG_LEN: usize = 32;
+
+/// 每个对象记录的抽样区间数及区间长度，供后台完整性复检使用
+const SPOT_COUNT: usize = 4;
+const SPOT_LEN: usize = 4096;
+
+/// 对象中一个抽样区间的摘要
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpotHash {
+    pub offset: u32, // 区间起点(GetPartialObject的偏移是32位)
+    pub len: u32,    // 区间长度
+    pub hash: String, // SHA-256摘要的前16个十六进制字符
+}
+
+/// 台账中的一条对象记录
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub handle: u32,  // 相机对象句柄
+    pub name: String, // 文件名
+    pub size: u64,    // 字节数
+    pub hash: String, // SHA-256摘要(十六进制)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>, // 客户端附加的标签/相册名
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spots: Vec<SpotHash>, // 随机抽样区间的摘要，旧版本记录的条目没有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub captured: Option<PtpDateTime>, // 拍摄时间，相机未提供时为空
+}
+
+impl LedgerEntry {
+    /// 是否带有指定标签
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// 规范化标签：去除首尾空白，拒绝空标签和超长标签
+fn normalize_tag(tag: &str) -> Result<String, Box<dyn Error>> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        return Err("标签不能为空".into());
+    }
+    if tag.len() > MAX_TAG_LEN {
+        return Err(format!("标签过长: {}", tag).into());
+    }
+    Ok(tag.to_string())
+}
+
+/// 计算数据的SHA-256摘要(十六进制)
+pub fn hash_object(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 在对象中随机选取抽样区间并计算摘要；只覆盖前4GB，小于一个区间的对象整体作为一个区间
+fn spot_hashes(data: &[u8]) -> Vec<SpotHash> {
+    let limit = data.len().min(u32::MAX as usize);
+    let len = SPOT_LEN.min(limit);
+    if len == 0 {
+        return Vec::new();
+    }
+    let count = if limit == len { 1 } else { SPOT_COUNT };
+    let random = uuid::Uuid::new_v4().as_u128();
+    (0..count)
+        .map(|i| {
+            let seed = (random >> (i * 32)) as u32 as usize;
+            let offset = seed % (limit - len + 1);
+            SpotHash {
+                offset: offset as u32,
+                len: len as u32,
+                hash: hash_object(&data[offset..offset + len])[..16].to_string(),
+            }
+        })
+        .collect()
+}
+
+/// 对象台账，持久化在键值存储中
+pub struct ObjectLedger {
+    store: Box<dyn KvStore>,
+    handles: Vec<u32>,
+    signing_key: Vec<u8>,
+    confirmations: Option<Sender<u32>>, // 签发回执后通知送达后处理
+}
+
+impl ObjectLedger {
+    /// 打开台账，读取已记录的对象索引
+    pub fn open(mut store: Box<dyn KvStore>) -> Result<Self, Box<dyn Error>> {
+        let handles = match store.get(INDEX_KEY)? {
+            Some(raw) => raw
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect(),
+            None => Vec::new(),
+        };
+        let signing_key = receipt::load_or_create_key(store.as_mut())?;
+        debug!("台账已加载 {} 个对象", handles.len());
+        Ok(ObjectLedger { store, handles, signing_key, confirmations: None })
+    }
+
+    /// 存储中所有台账条目的键名，供数据迁移逐条改写
+    pub fn entry_keys(store: &dyn KvStore) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(store
+            .get(INDEX_KEY)?
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .map(|c| Self::entry_key(u32::from_le_bytes([c[0], c[1], c[2], c[3]])))
+            .collect())
+    }
+
+    fn entry_key(handle: u32) -> String {
+        format!("obj_{:08x}", handle)
+    }
+
+    fn receipt_key(handle: u32) -> String {
+        format!("rcpt_{:08x}", handle)
+    }
+
+    fn save_index(&mut self) -> Result<(), Box<dyn Error>> {
+        let raw: Vec<u8> = self.handles.iter().flat_map(|h| h.to_le_bytes()).collect();
+        self.store.set(INDEX_KEY, &raw)
+    }
+
+    /// 记录一个已下载的对象，返回其台账条目
+    /// 重新记录同一句柄时保留已有的标签和拍摄时间
+    pub fn record(&mut self, handle: u32, name: &str, data: &[u8]) -> Result<LedgerEntry, Box<dyn Error>> {
+        let (tags, captured) = self.get(handle)?.map_or_else(Default::default, |e| (e.tags, e.captured));
+        let entry = LedgerEntry {
+            handle,
+            name: name.to_string(),
+            size: data.len() as u64,
+            hash: hash_object(data),
+            tags,
+            spots: spot_hashes(data),
+            captured,
+        };
+        self.save_entry(&entry)?;
+        if !self.handles.contains(&handle) {
+            self.handles.push(handle);
+            self.save_index()?;
+        }
+        // 条目和索引作为一批提交，对象之间是批量存储的提交点
+        self.store.flush()?;
+        Ok(entry)
+    }
+
+    fn save_entry(&mut self, entry: &LedgerEntry) -> Result<(), Box<dyn Error>> {
+        self.store.set(&Self::entry_key(entry.handle), &serde_json::to_vec(entry)?)
+    }
+
+    /// 查询对象记录
+    pub fn get(&self, handle: u32) -> Result<Option<LedgerEntry>, Box<dyn Error>> {
+        match self.store.get(&Self::entry_key(handle))? {
+            Some(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 删除对象记录
+    pub fn forget(&mut self, handle: u32) -> Result<(), Box<dyn Error>> {
+        self.store.remove(&Self::entry_key(handle))?;
+        self.store.remove(&Self::receipt_key(handle))?;
+        self.handles.retain(|h| *h != handle);
+        self.save_index()?;
+        self.store.flush()
+    }
+
+    /// 记录对象的拍摄时间(来自ObjectInfo)，清单和按时间的同步使用
+    pub fn set_captured(&mut self, handle: u32, captured: PtpDateTime) -> Result<(), Box<dyn Error>> {
+        let mut entry = self
+            .get(handle)?
+            .ok_or_else(|| format!("台账中没有对象 0x{:08x}", handle))?;
+        if entry.captured == Some(captured) {
+            return Ok(());
+        }
+        entry.captured = Some(captured);
+        self.save_entry(&entry)
+    }
+
+    /// 给对象添加标签，返回是否新增
+    pub fn tag(&mut self, handle: u32, tag: &str) -> Result<bool, Box<dyn Error>> {
+        let tag = normalize_tag(tag)?;
+        let mut entry = self
+            .get(handle)?
+            .ok_or_else(|| format!("台账中没有对象 0x{:08x}", handle))?;
+        if entry.has_tag(&tag) {
+            return Ok(false);
+        }
+        entry.tags.push(tag);
+        self.save_entry(&entry)?;
+        Ok(true)
+    }
+
+    /// 移除对象的标签，返回是否存在该标签
+    pub fn untag(&mut self, handle: u32, tag: &str) -> Result<bool, Box<dyn Error>> {
+        let tag = tag.trim();
+        let mut entry = match self.get(handle)? {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let before = entry.tags.len();
+        entry.tags.retain(|t| t != tag);
+        if entry.tags.len() == before {
+            return Ok(false);
+        }
+        self.save_entry(&entry)?;
+        Ok(true)
+    }
+
+    /// 虚拟相册：带有指定标签的所有对象
+    pub fn album(&self, tag: &str) -> Vec<LedgerEntry> {
+        self.entries().into_iter().filter(|e| e.has_tag(tag.trim())).collect()
+    }
+
+    /// 所有已使用的标签(去重，按首次出现顺序)
+    pub fn albums(&self) -> Vec<String> {
+        let mut albums: Vec<String> = Vec::new();
+        for tag in self.entries().into_iter().flat_map(|e| e.tags) {
+            if !albums.contains(&tag) {
+                albums.push(tag);
+            }
+        }
+        albums
+    }
+
+    /// 签发回执后把对象句柄发给`tx`，由持有相机的一方按送达后处理策略处理原文件
+    pub fn set_confirmation_sink(&mut self, tx: Sender<u32>) {
+        self.confirmations = Some(tx);
+    }
+
+    /// 客户端确认完整接收对象后签发回执并保存；同一客户端重复确认时替换旧回执
+    pub fn acknowledge(&mut self, handle: u32, client_id: &str) -> Result<Receipt, Box<dyn Error>> {
+        let entry = self
+            .get(handle)?
+            .ok_or_else(|| format!("台账中没有对象 0x{:08x}", handle))?;
+        let receipt = Receipt::issue(&entry, client_id, &self.signing_key);
+
+        let mut receipts = self.receipts(handle)?;
+        receipts.retain(|r| r.client_id != client_id);
+        receipts.push(receipt.clone());
+        self.store.set(&Self::receipt_key(handle), &serde_json::to_vec(&receipts)?)?;
+        // 回执交给客户端前必须已经落盘
+        self.store.flush()?;
+        debug!("已为客户端 {} 签发对象 0x{:08x} 的回执", client_id, handle);
+        if let Some(tx) = &self.confirmations {
+            // 接收方已退出时不再处理原文件，回执照常签发
+            let _ = tx.send(handle);
+        }
+        Ok(receipt)
+    }
+
+    /// 查询对象的所有回执
+    pub fn receipts(&self, handle: u32) -> Result<Vec<Receipt>, Box<dyn Error>> {
+        match self.store.get(&Self::receipt_key(handle))? {
+            Some(raw) => Ok(serde_json::from_slice(&raw)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// 查询某客户端收到的所有回执
+    pub fn receipts_for_client(&self, client_id: &str) -> Vec<Receipt> {
+        self.handles
+            .iter()
+            .filter_map(|h| self.receipts(*h).ok())
+            .flatten()
+            .filter(|r| r.client_id == client_id)
+            .collect()
+    }
+
+    /// 校验回执是否由本设备签发且未被篡改
+    pub fn verify_receipt(&self, receipt: &Receipt) -> bool {
+        receipt.verify(&self.signing_key)
+    }
+
+    /// 所有对象记录，读取失败的条目会被跳过
+    pub fn entries(&self) -> Vec<LedgerEntry> {
+        self.handles
+            .iter()
+            .filter_map(|h| match self.get(*h) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("读取台账条目 0x{:08x} 失败: {}", h, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// 已记录对象的句柄，按记录顺序
+    pub fn handles(&self) -> &[u32] {
+        &self.handles
+    }
+
+    /// 已记录的对象数量
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// 台账是否为空
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+
+/// 注册控制台命令 `receipt`
+pub fn register_console_command(
+    console: &mut crate::console::Console,
+    ledger: std::sync::Arc<std::sync::Mutex<ObjectLedger>>,
+) {
+    console.register(
+        "receipt",
+        "查询交付回执: receipt <对象句柄> | receipt client <客户端ID>",
+        Box::new(move |args| {
+            let ledger = ledger.lock().unwrap();
+            let receipts = match args {
+                ["client", client_id] => ledger.receipts_for_client(client_id),
+                [handle] => {
+                    let handle = handle.trim_start_matches("0x");
+                    match u32::from_str_radix(handle, 16) {
+                        Ok(handle) => match ledger.receipts(handle) {
+                            Ok(receipts) => receipts,
+                            Err(e) => return format!("读取回执失败: {}", e),
+                        },
+                        Err(_) => return format!("无效的对象句柄: {}", handle),
+                    }
+                }
+                _ => return "用法: receipt <对象句柄> | receipt client <客户端ID>".to_string(),
+            };
+            if receipts.is_empty() {
+                return "暂无回执".to_string();
+            }
+            receipts
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        }),
+    );
+}
+
+/// 注册控制台命令 `tag`
+pub fn register_tag_console_command(
+    console: &mut crate::console::Console,
+    ledger: std::sync::Arc<std::sync::Mutex<ObjectLedger>>,
+) {
+    const USAGE: &str = "用法: tag add <对象句柄> <标签> | tag rm <对象句柄> <标签> | tag ls [标签]";
+    console.register(
+        "tag",
+        "管理对象标签和虚拟相册: tag add|rm|ls",
+        Box::new(move |args| {
+            let mut ledger = ledger.lock().unwrap();
+            let parse_handle = |s: &str| u32::from_str_radix(s.trim_start_matches("0x"), 16);
+            match args {
+                ["add", handle, tag] | ["rm", handle, tag] => {
+                    let handle = match parse_handle(handle) {
+                        Ok(handle) => handle,
+                        Err(_) => return format!("无效的对象句柄: {}", handle),
+                    };
+                    let result = if args[0] == "add" {
+                        ledger.tag(handle, tag)
+                    } else {
+                        ledger.untag(handle, tag)
+                    };
+                    match result {
+                        Ok(true) => "完成".to_string(),
+                        Ok(false) => "无变化".to_string(),
+                        Err(e) => format!("操作失败: {}", e),
+                    }
+                }
+                ["ls"] => {
+                    let albums = ledger.albums();
+                    if albums.is_empty() {
+                        "暂无相册".to_string()
+                    } else {
+                        albums.join("\n")
+                    }
+                }
+                ["ls", tag] => ledger
+                    .album(tag)
+                    .iter()
+                    .map(|e| format!("0x{:08x} {} {} 字节", e.handle, e.name, e.size))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                _ => USAGE.to_string(),
+            }
+        }),
+    );
+}
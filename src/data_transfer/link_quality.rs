@@ -0,0 +1,246 @@
+// 链路质量分级 - 统计最近一段时间发送的实际吞吐和失败率，链路变差时逐级降低发送内容
+// (原图 → 只发预览 → 只发元数据)，恢复后再逐级升回；升降级都要求条件持续一段时间，避免在边界上反复切换
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::events::AppEvent;
+use crate::ptp_mtp::PacketType;
+
+/// 统计窗口内至少有这么多次发送才做判断，流量很少时保持当前级别
+const MIN_SAMPLES: usize = 4;
+
+/// 发送级别，从高到低
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LinkTier {
+    MetadataOnly, // 只发元数据
+    Preview,      // 只发缩略图和元数据
+    Full,         // 发送全部数据
+}
+
+impl LinkTier {
+    /// 事件中使用的名称
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkTier::Full => "full",
+            LinkTier::Preview => "preview",
+            LinkTier::MetadataOnly => "metadata",
+        }
+    }
+
+    /// 该级别下是否发送此类型的数据包，命令和响应总是发送
+    pub fn accepts(self, packet_type: PacketType) -> bool {
+        match self {
+            LinkTier::Full => true,
+            LinkTier::Preview => !matches!(packet_type, PacketType::Image),
+            LinkTier::MetadataOnly => {
+                !matches!(packet_type, PacketType::Image | PacketType::Thumbnail | PacketType::LiveView)
+            }
+        }
+    }
+
+    fn lower(self) -> Option<LinkTier> {
+        match self {
+            LinkTier::Full => Some(LinkTier::Preview),
+            LinkTier::Preview => Some(LinkTier::MetadataOnly),
+            LinkTier::MetadataOnly => None,
+        }
+    }
+
+    fn higher(self) -> Option<LinkTier> {
+        match self {
+            LinkTier::Full => None,
+            LinkTier::Preview => Some(LinkTier::Full),
+            LinkTier::MetadataOnly => Some(LinkTier::Preview),
+        }
+    }
+}
+
+impl fmt::Display for LinkTier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// 分级阈值
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LinkQualityThresholds {
+    pub window: Duration,       // 统计吞吐和失败率的时间窗口
+    pub full_min_bps: u64,      // 吞吐低于此值时从全部数据降到预览
+    pub preview_min_bps: u64,   // 吞吐低于此值时从预览降到元数据
+    pub recover_percent: u32,   // 吞吐达到上一级下限的这个百分比才升级，大于100形成回差
+    pub max_error_percent: u8,  // 失败率高于此值时降级，低于其一半才允许升级
+    pub sustain: Duration,      // 升降级条件需要持续的时间
+    pub probe_after: Duration,  // 降级后链路一直没有失败，经过这么久尝试升一级
+}
+
+impl Default for LinkQualityThresholds {
+    fn default() -> Self {
+        LinkQualityThresholds {
+            window: Duration::from_secs(10),
+            full_min_bps: 200 * 1024,
+            preview_min_bps: 16 * 1024,
+            recover_percent: 150,
+            max_error_percent: 20,
+            sustain: Duration::from_secs(5),
+            probe_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl LinkQualityThresholds {
+    /// 校验阈值之间的关系
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.preview_min_bps >= self.full_min_bps {
+            return Err("预览级别的吞吐下限必须小于原图级别".into());
+        }
+        if self.recover_percent < 100 {
+            return Err("升级的吞吐百分比不能小于100".into());
+        }
+        if self.max_error_percent > 100 {
+            return Err("失败率阈值不能超过100%".into());
+        }
+        if self.window.is_zero() {
+            return Err("链路质量统计窗口不能为0".into());
+        }
+        Ok(())
+    }
+
+    /// 保持在该级别需要的最低吞吐
+    fn floor(&self, tier: LinkTier) -> u64 {
+        match tier {
+            LinkTier::Full => self.full_min_bps,
+            LinkTier::Preview => self.preview_min_bps,
+            LinkTier::MetadataOnly => 0,
+        }
+    }
+}
+
+/// 一次发送的结果
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    bytes: u64,
+    busy: Duration, // 发送占用的时间
+    ok: bool,
+}
+
+/// 链路质量控制器
+#[derive(Debug)]
+pub struct LinkQuality {
+    thresholds: LinkQualityThresholds,
+    tier: LinkTier,
+    samples: VecDeque<Sample>,
+    pending_since: Option<(LinkTier, Instant)>, // 满足切换到某级别条件的起始时间
+    tier_since: Instant,
+    event: Option<AppEvent>, // 尚未取走的级别变化事件
+}
+
+impl LinkQuality {
+    pub fn new(thresholds: LinkQualityThresholds) -> Self {
+        LinkQuality {
+            thresholds,
+            tier: LinkTier::Full,
+            samples: VecDeque::new(),
+            pending_since: None,
+            tier_since: Instant::now(),
+            event: None,
+        }
+    }
+
+    /// 当前级别
+    pub fn tier(&self) -> LinkTier {
+        self.tier
+    }
+
+    /// 记录一次发送：发出的字节数、占用的时间和是否成功
+    pub fn record(&mut self, bytes: usize, busy: Duration, ok: bool) {
+        let now = Instant::now();
+        self.samples.push_back(Sample { at: now, bytes: bytes as u64, busy, ok });
+        self.evaluate(now);
+    }
+
+    /// 窗口内的吞吐(字节/秒，按实际发送占用的时间计算)和失败率(百分比)，样本不足时返回None
+    pub fn stats(&self) -> Option<(u64, u8)> {
+        if self.samples.len() < MIN_SAMPLES {
+            return None;
+        }
+        let bytes: u64 = self.samples.iter().map(|s| s.bytes).sum();
+        let busy: Duration = self.samples.iter().map(|s| s.busy).sum();
+        let failed = self.samples.iter().filter(|s| !s.ok).count();
+        let bps = match busy.as_micros() {
+            0 => u64::MAX,
+            us => (bytes as u128 * 1_000_000 / us).min(u64::MAX as u128) as u64,
+        };
+        Some((bps, (failed * 100 / self.samples.len()) as u8))
+    }
+
+    /// 取走级别变化的事件
+    pub fn take_event(&mut self) -> Option<AppEvent> {
+        self.evaluate(Instant::now());
+        self.event.take()
+    }
+
+    fn evaluate(&mut self, now: Instant) {
+        let t = self.thresholds;
+        while self.samples.front().is_some_and(|s| now.duration_since(s.at) > t.window) {
+            self.samples.pop_front();
+        }
+        let stats = self.stats();
+        let target = match stats {
+            Some((bps, errors)) => {
+                if errors > t.max_error_percent || bps < t.floor(self.tier) {
+                    self.tier.lower()
+                } else {
+                    self.tier.higher().filter(|&higher| {
+                        let recover = t.floor(higher) as u128 * t.recover_percent as u128 / 100;
+                        errors <= t.max_error_percent / 2 && bps as u128 >= recover
+                    })
+                }
+            }
+            None => None,
+        };
+        // 降级后流量变少，吞吐可能一直测不准；没有失败时定期试探升一级，不行会再降回来
+        let probing = target.is_none()
+            && now.duration_since(self.tier_since) >= t.probe_after
+            && stats.map_or(true, |(_, errors)| errors == 0);
+        let Some(target) = target.or_else(|| self.tier.higher().filter(|_| probing)) else {
+            self.pending_since = None;
+            return;
+        };
+        let since = match self.pending_since {
+            Some((pending, since)) if pending == target => since,
+            _ => {
+                self.pending_since = Some((target, now));
+                now
+            }
+        };
+        if !probing && now.duration_since(since) < t.sustain {
+            return;
+        }
+
+        let previous = self.tier;
+        let (bps, errors) = stats.unwrap_or((0, 0));
+        if target < previous {
+            warn!("链路质量下降 (吞吐 {} B/s，失败率 {}%)，发送级别 {} → {}", bps, errors, previous, target);
+        } else {
+            info!("链路质量恢复 (吞吐 {} B/s，失败率 {}%)，发送级别 {} → {}", bps, errors, previous, target);
+        }
+        self.tier = target;
+        self.tier_since = now;
+        self.pending_since = None;
+        // 换级别后发送的内容不同，旧样本不再代表新级别下的链路表现
+        self.samples.clear();
+        self.event = Some(AppEvent::LinkTierChanged {
+            tier: target.name().to_string(),
+            previous: previous.name().to_string(),
+            throughput_bps: bps,
+            error_percent: errors,
+        });
+    }
+}
@@ -1,14 +1,66 @@
 // 数据传输模块 - 负责协调相机数据的接收和无线传输
 use std::error::Error;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use log::{info, error, debug, warn};
-use crate::ptp_mtp::{DataPacket, DataListener, PacketType};
-use crate::wireless::DataSender;
+use crate::ptp_mtp::{BatchReport, DataPacket, DataListener, PacketType, PtpCamera};
+use crate::wireless::{DataSender, SenderState};
+use embassy_futures::block_on;
+use crate::i18n::{self, Language, MessageCode};
+use crate::config::BodyProfile;
+use crate::control::ControlQueue;
+
+pub(crate) mod arbiter;
+pub mod capture;
+#[cfg(feature = "wifi")]
+pub mod cloud;
+pub mod delta;
+#[cfg(test)]
+mod harness;
+pub(crate) mod hooks;
+pub mod impair;
+pub mod ledger;
+pub(crate) mod link_quality;
+pub mod on_demand;
+pub(crate) mod pipeline;
+pub mod prefetch;
+pub(crate) mod profile;
+pub(crate) mod quota;
+pub(crate) mod receipt;
+mod rng;
+pub mod stage_metrics;
+pub mod stream;
+pub mod verify;
+
+pub use arbiter::{DownloadArbiter, DownloadJob, EnqueueOutcome};
+pub use capture::CaptureHandle;
+#[cfg(feature = "wifi")]
+pub use cloud::{CloudUploader, PendingUpload};
+pub use delta::{DeltaPlan, DeltaReport, DeltaTarget, Manifest, TargetInventory};
+pub use hooks::{register_packet_hook, PacketHook};
+pub use impair::{Impairment, ImpairmentHandle};
+pub use ledger::{LedgerEntry, ObjectLedger};
+pub use link_quality::{LinkQuality, LinkQualityThresholds, LinkTier};
+pub use on_demand::{ByteRange, DownloadDestination, OnDemandReport};
+pub use pipeline::{PacketContext, Pipeline, PipelineBuilder, PipelineHandle, PipelineStage, StageAction, StageSpec};
+pub use prefetch::{CacheLocation, IdlePrefetcher, PreviewCache};
+pub use profile::ClientProfile;
+pub use quota::TransferQuota;
+pub use receipt::Receipt;
+pub use stage_metrics::{BottleneckReport, StageMetricsHandle};
+pub use verify::{IntegrityStats, IntegrityVerifier, VerifyOutcome};
 
 // TODO
 // pub mod buffer;
 // pub mod processor;
 
+/// 通过 `set_sender` 设置的默认客户端ID
+const DEFAULT_CLIENT_ID: &str = "default";
+/// 数据报发送器在抓包记录中使用的客户端ID
+const DATAGRAM_CLIENT_ID: &str = "datagram";
+/// 客户端连续发送失败多少个数据包后被移除
+const MAX_CLIENT_FAILURES: u32 = 3;
+
 /// 传输状态
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TransferStatus {
@@ -20,13 +72,72 @@ pub enum TransferStatus {
     Error,      // 错误状态
 }
 
+/// 对象送达并通过校验后，对相机上原文件的处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostTransferAction {
+    #[default]
+    Keep,    // 保留，不做处理
+    Delete,  // 从相机删除，释放存储卡空间
+    Protect, // 设置写保护，防止在相机上被误删
+    MoveTo { storage_id: u32, parent: u32 }, // 移动到相机上的文件夹(例如"已发送")，parent为0表示存储根目录
+}
+
+/// 已连接的客户端
+struct ClientSlot {
+    client_id: String,
+    profile: ClientProfile,
+    sender: Box<dyn DataSender>,
+    failures: u32, // 连续发送失败的数据包数，成功一次后清零
+}
+
+/// `deliver`的结果，每个客户端单独成功或失败
+#[derive(Debug, Default)]
+pub struct DeliveryReport {
+    pub bytes: usize,                           // 各客户端合计发送的字节数
+    pub delivered: Vec<String>,                 // 发送成功的客户端
+    pub failed: Vec<(String, Box<dyn Error>)>,  // 发送失败或未连接的客户端及原因
+}
+
+impl DeliveryReport {
+    /// 是否全部送达
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// 经过流水线、等待发送的数据包
+struct QueuedPacket {
+    packet: DataPacket,
+    route: Option<Vec<String>>, // 流水线指定的客户端，None表示按客户端类型分发
+    queued_at: Instant,
+}
+
+/// 按数据包类型改走的数据报发送器(例如UDP取景)，不经过各客户端的TCP连接
+struct DatagramRoute {
+    sender: Box<dyn DataSender>,
+    types: Vec<PacketType>,
+}
+
 /// 传输管理器 - 负责协调数据从相机到手机的传输
 pub struct TransferManager {
     status: TransferStatus,
-    buffer: Arc<Mutex<Vec<DataPacket>>>,
-    data_sender: Option<Box<dyn DataSender>>,
+    buffer: Arc<Mutex<Vec<QueuedPacket>>>,
+    clients: Vec<ClientSlot>,
     total_bytes_transferred: usize,
     max_buffer_size: usize,
+    max_buffer_bytes: usize, // 发送队列可占用的字节数，由工作模式的资源预算设置
+    body: Option<BodyProfile>,
+    impairment: Option<ImpairmentHandle>,
+    capture: Option<CaptureHandle>,
+    post_transfer: PostTransferAction,
+    verified: Vec<u32>, // 已确认送达、等待按策略处理的对象
+    pipeline: PipelineHandle,
+    metrics: Option<StageMetricsHandle>,
+    quota: Option<TransferQuota>, // 当前链路的传输配额，None表示不限制
+    control: Option<ControlQueue>, // 有紧急控制命令待执行时暂停发送
+    link: Option<LinkQuality>, // 按链路质量决定发送级别，None表示总是发送全部数据
+    datagram: Option<DatagramRoute>,
 }
 
 impl TransferManager {
@@ -35,23 +146,335 @@ impl TransferManager {
         TransferManager {
             status: TransferStatus::Idle,
             buffer: Arc::new(Mutex::new(Vec::new())),
-            data_sender: None,
+            clients: Vec::new(),
             total_bytes_transferred: 0,
             max_buffer_size,
+            max_buffer_bytes: usize::MAX,
+            body: None,
+            impairment: None,
+            capture: None,
+            post_transfer: PostTransferAction::Keep,
+            verified: Vec::new(),
+            pipeline: PipelineHandle::default(),
+            metrics: None,
+            quota: None,
+            control: None,
+            link: None,
+            datagram: None,
         }
     }
     
-    /// 设置数据发送器
+    /// 应用当前连接机身的设置，未配置的机身传入None恢复默认行为
+    pub fn apply_body(&mut self, body: Option<&BodyProfile>) {
+        match body {
+            Some(body) => info!("应用机身设置: {} (前缀 {:?}, RAW策略 {:?})", body.label, body.name_prefix, body.raw_policy),
+            None => debug!("当前机身没有专门的设置，使用默认行为"),
+        }
+        self.body = body.cloned();
+    }
+    
+    /// 当前机身下对象的传输文件名
+    pub fn object_name(&self, filename: &str) -> String {
+        match &self.body {
+            Some(body) => body.object_name(filename),
+            None => filename.to_string(),
+        }
+    }
+    
+    /// 当前机身下是否传输该文件的原图
+    pub fn transfers_original(&self, filename: &str) -> bool {
+        self.body.as_ref().map_or(true, |body| body.transfers_original(filename))
+    }
+    
+    /// 设置数据发送器（作为接收全部数据的默认客户端）
     pub fn set_sender(&mut self, sender: Box<dyn DataSender>) {
-        self.data_sender = Some(sender);
+        self.add_client(DEFAULT_CLIENT_ID, ClientProfile::FullIngest, sender);
+    }
+    
+    /// 启用链路劣化注入(调试用)：包装已有的和之后添加的所有客户端发送器
+    pub fn set_impairment(&mut self, state: ImpairmentHandle) {
+        if self.impairment.is_some() {
+            warn!("链路劣化注入已启用，忽略重复设置");
+            return;
+        }
+        let settings = state.lock().unwrap().settings;
+        if settings.is_active() {
+            warn!("链路劣化注入已启用: {}", settings);
+        } else {
+            debug!("已安装链路劣化注入，当前未启用");
+        }
+        self.clients = self.clients.drain(..).map(|mut slot| {
+            slot.sender = Box::new(impair::ImpairedSender::new(slot.sender, state.clone()));
+            slot
+        }).collect();
+        self.impairment = Some(state);
+    }
+    
+    /// 启用发送流抓包：包装已有的和之后添加的所有客户端发送器，通过`capture`在运行时开始或结束抓包
+    pub fn set_capture(&mut self, capture: CaptureHandle) {
+        if self.capture.is_some() {
+            warn!("发送流抓包已启用，忽略重复设置");
+            return;
+        }
+        self.clients = self.clients.drain(..).map(|mut slot| {
+            slot.sender = Box::new(capture::CapturingSender::new(slot.sender, &slot.client_id, capture.clone()));
+            slot
+        }).collect();
+        self.capture = Some(capture);
+    }
+    
+    /// 设置数据包在缓冲和发送前经过的处理流水线
+    pub fn set_pipeline(&mut self, mut pipeline: Pipeline) {
+        info!("数据流水线: {:?}", pipeline);
+        if let Some(metrics) = &self.metrics {
+            pipeline.set_metrics(metrics.clone());
+        }
+        *self.pipeline.lock().unwrap() = pipeline;
+    }
+    
+    /// 当前流水线，其他数据出口经同一流水线处理数据，之后切换模式重新组装时随之更新
+    pub fn pipeline(&self) -> PipelineHandle {
+        self.pipeline.clone()
+    }
+    
+    /// 设置发送队列的字节预算，超出时立即丢弃最旧的数据包
+    pub fn set_buffer_budget(&mut self, max_bytes: usize) {
+        info!("发送队列预算: {} 字节", max_bytes);
+        self.max_buffer_bytes = max_bytes;
+        let mut buffer = self.buffer.lock().unwrap();
+        Self::trim_buffer(&mut buffer, self.max_buffer_size, max_bytes, 0);
+        buffer.shrink_to_fit();
+    }
+    
+    /// 设置当前链路的传输配额，None表示不限制
+    pub fn set_quota(&mut self, quota: Option<TransferQuota>) {
+        match &quota {
+            Some(q) => info!("传输配额: 每 {} 秒 {} 字节", q.window().as_secs(), q.limit()),
+            None => debug!("传输不限配额"),
+        }
+        self.quota = quota;
+    }
+    
+    /// 传输配额，未设置时返回None
+    pub fn quota(&mut self) -> Option<&mut TransferQuota> {
+        self.quota.as_mut()
+    }
+    
+    /// 取走配额用完或恢复的事件，通常由主循环定期调用并发布
+    pub fn take_quota_event(&mut self) -> Option<crate::events::AppEvent> {
+        self.quota.as_mut().and_then(TransferQuota::take_event)
+    }
+    
+    /// 发送因配额用完或让出给控制命令而积压在队列中的数据包
+    pub fn flush_backlog(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.buffer.lock().unwrap().is_empty() {
+            return Ok(());
+        }
+        self.process_buffer()
+    }
+    
+    /// 启用按链路质量自动切换发送级别，None表示总是发送全部数据
+    pub fn set_link_quality(&mut self, link: Option<LinkQuality>) {
+        if link.is_some() {
+            info!("已启用按链路质量切换发送级别");
+        }
+        self.link = link;
+    }
+    
+    /// 当前的发送级别
+    pub fn link_tier(&self) -> LinkTier {
+        self.link.as_ref().map_or(LinkTier::Full, LinkQuality::tier)
+    }
+    
+    /// 取走发送级别变化的事件，通常由主循环定期调用并发布
+    pub fn take_link_event(&mut self) -> Option<crate::events::AppEvent> {
+        self.link.as_mut().and_then(LinkQuality::take_event)
+    }
+    
+    /// 每个数据包发送前检查控制队列，有紧急命令时把剩余数据包留在队列中并返回，先执行控制命令
+    pub fn set_control_queue(&mut self, control: ControlQueue) {
+        self.control = Some(control);
+    }
+    
+    /// 记录流水线各阶段和发送阶段的指标
+    pub fn set_stage_metrics(&mut self, metrics: StageMetricsHandle) {
+        self.pipeline.lock().unwrap().set_metrics(metrics.clone());
+        self.metrics = Some(metrics);
+    }
+    
+    /// 添加客户端，按其声明的类型路由数据；同ID的客户端会被替换
+    pub fn add_client(&mut self, client_id: &str, profile: ClientProfile, sender: Box<dyn DataSender>) {
+        self.remove_client(client_id);
+        info!("添加客户端 {} ({})", client_id, profile.name());
+        let sender = self.wrap_sender(client_id, sender);
+        self.clients.push(ClientSlot {
+            client_id: client_id.to_string(),
+            profile,
+            sender,
+            failures: 0,
+        });
+    }
+    
+    /// 移除客户端并关闭其发送器
+    /// 指定类型的数据包改由`sender`发送(例如取景画面走UDP)，其余类型仍发给各客户端；传入空列表取消
+    /// 数据报发送失败只记录日志，不中断其他数据包的发送
+    pub fn set_datagram_route(&mut self, sender: Box<dyn DataSender>, types: &[PacketType]) {
+        if let Some(mut old) = self.datagram.take() {
+            let _ = block_on(old.sender.close());
+        }
+        if types.is_empty() {
+            return;
+        }
+        info!("{:?} 数据包改走数据报发送", types);
+        self.datagram = Some(DatagramRoute {
+            sender: self.wrap_sender(DATAGRAM_CLIENT_ID, sender),
+            types: types.to_vec(),
+        });
+    }
+    
+    /// 按已启用的调试功能包装发送器
+    fn wrap_sender(&self, client_id: &str, sender: Box<dyn DataSender>) -> Box<dyn DataSender> {
+        let sender: Box<dyn DataSender> = match &self.impairment {
+            Some(state) => Box::new(impair::ImpairedSender::new(sender, state.clone())),
+            None => sender,
+        };
+        // 抓包在最外层，记录的是交给发送器的全部数据
+        match &self.capture {
+            Some(capture) => Box::new(capture::CapturingSender::new(sender, client_id, capture.clone())),
+            None => sender,
+        }
+    }
+    
+    pub fn remove_client(&mut self, client_id: &str) -> bool {
+        match self.clients.iter().position(|c| c.client_id == client_id) {
+            Some(index) => {
+                let mut slot = self.clients.remove(index);
+                if let Err(e) = block_on(slot.sender.close()) {
+                    warn!("关闭客户端 {} 的发送器失败: {}", client_id, e);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+    
+    /// 把应用事件以一行JSON发给所有客户端，返回成功发送的客户端数
+    pub fn notify_clients(&mut self, event: &crate::events::AppEvent) -> usize {
+        let mut line = match serde_json::to_vec(event) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("事件序列化失败: {}", e);
+                return 0;
+            }
+        };
+        line.push(b'\n');
+        let packet = DataPacket::new(PacketType::Metadata, line);
+        let mut notified = 0;
+        for client in &mut self.clients {
+            match block_on(send_packet(client.sender.as_mut(), &packet)) {
+                Ok(_) => notified += 1,
+                Err(e) => warn!("向客户端 {} 发送事件 {} 失败: {}", client.client_id, event.name(), e),
+            }
+        }
+        notified
+    }
+    
+    /// 将下载完成的对象发送给指定的客户端（用于仲裁器的共享读取）
+    /// 每个客户端的结果单独记录，一个客户端失败不影响其他客户端；只有配额用完时返回错误
+    pub fn deliver(&mut self, client_ids: &[String], packet: &DataPacket) -> Result<DeliveryReport, Box<dyn Error>> {
+        if self.quota.as_mut().is_some_and(|q| !q.try_consume(packet.data.len() as u64)) {
+            return Err("传输配额已用完".into());
+        }
+        let mut report = DeliveryReport::default();
+        for client_id in client_ids {
+            let Some(client) = self.clients.iter_mut().find(|c| &c.client_id == client_id) else {
+                report.failed.push((client_id.clone(), format!("客户端 {} 未连接", client_id).into()));
+                continue;
+            };
+            match block_on(send_packet(client.sender.as_mut(), packet)) {
+                Ok(sent) => {
+                    client.failures = 0;
+                    report.bytes += sent;
+                    report.delivered.push(client_id.clone());
+                }
+                Err(e) => {
+                    client.failures += 1;
+                    warn!("向客户端 {} 发送对象数据失败: {}", client_id, e);
+                    report.failed.push((client_id.clone(), e));
+                }
+            }
+        }
+        self.total_bytes_transferred += report.bytes;
+        self.evict_failed_clients();
+        Ok(report)
+    }
+    
+    /// 设置对象送达后的处理策略
+    pub fn set_post_transfer(&mut self, action: PostTransferAction) {
+        info!("送达后处理策略: {:?}", action);
+        self.post_transfer = action;
+        if action == PostTransferAction::Keep {
+            self.verified.clear();
+        }
+    }
+    
+    /// 记录已确认送达的对象(例如客户端确认并签发回执后)，之后由`apply_post_transfer`统一处理
+    pub fn mark_verified(&mut self, handle: u32) {
+        if self.post_transfer != PostTransferAction::Keep && !self.verified.contains(&handle) {
+            self.verified.push(handle);
+        }
+    }
+    
+    /// 等待按策略处理的对象
+    pub fn pending_post_transfer(&self) -> &[u32] {
+        &self.verified
+    }
+    
+    /// 按策略删除或保护已确认送达的对象
+    /// 因超时或USB错误失败的对象留在队列中下次重试，相机拒绝的对象(例如写保护)不再重试
+    pub async fn apply_post_transfer(&mut self, camera: &mut PtpCamera, timeout: Option<std::time::Duration>) -> BatchReport {
+        let handles = std::mem::take(&mut self.verified);
+        let report = match self.post_transfer {
+            PostTransferAction::Keep => BatchReport::default(),
+            PostTransferAction::Delete => camera.delete_objects(&handles, timeout).await,
+            PostTransferAction::Protect => camera.protect_objects(&handles, timeout).await,
+            PostTransferAction::MoveTo { storage_id, parent } => {
+                camera.move_objects(&handles, storage_id, parent, timeout).await
+            }
+        };
+        for (handle, e) in &report.failed {
+            if matches!(e, crate::ptp_mtp::Error::Timeout(_) | crate::ptp_mtp::Error::USB(_)) {
+                self.verified.push(*handle);
+            }
+        }
+        report
+    }
+    
+    /// 修改客户端的类型，发送器和连接保持不变；客户端不存在时返回false
+    pub fn set_client_profile(&mut self, client_id: &str, profile: ClientProfile) -> bool {
+        match self.clients.iter_mut().find(|c| c.client_id == client_id) {
+            Some(slot) => {
+                if slot.profile != profile {
+                    info!("客户端 {} 改为 {}", client_id, profile.name());
+                    slot.profile = profile;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+    
+    /// 获取客户端声明的类型
+    pub fn client_profile(&self, client_id: &str) -> Option<ClientProfile> {
+        self.clients.iter().find(|c| c.client_id == client_id).map(|c| c.profile)
     }
     
     /// 启动传输
     pub fn start(&mut self) -> Result<(), Box<dyn Error>> {
         match self.status {
             TransferStatus::Idle | TransferStatus::Paused => {
-                if self.data_sender.is_none() {
-                    return Err("未设置数据发送器".into());
+                if self.clients.is_empty() {
+                    return Err(i18n::tr(MessageCode::NoSenderConfigured).into());
                 }
                 
                 debug!("启动数据传输...");
@@ -59,7 +482,7 @@ impl TransferManager {
                 
                 // 启动传输处理线程
                 // let buffer_clone = Arc::clone(&self.buffer);
-                // let sender = self.data_sender.as_ref().unwrap();
+                // let clients = &self.clients;
                 
                 // 这里应该启动一个单独的线程来处理数据发送
                 // 在ESP32上可能需要使用任务或其他机制来实现
@@ -100,9 +523,12 @@ impl TransferManager {
                 buffer.clear();
             }
             
-            // 关闭发送器
-            if let Some(sender) = &mut self.data_sender {
-                sender.close()?;
+            // 关闭所有客户端的发送器
+            for client in &mut self.clients {
+                block_on(client.sender.close())?;
+            }
+            if let Some(datagram) = &mut self.datagram {
+                block_on(datagram.sender.close())?;
             }
             
             self.status = TransferStatus::Idle;
@@ -114,43 +540,69 @@ impl TransferManager {
         }
     }
     
+    /// 从错误状态恢复到空闲状态，丢弃未发送的数据包，之后可重新启动
+    pub fn reset(&mut self) {
+        if self.status == TransferStatus::Error {
+            self.buffer.lock().unwrap().clear();
+            self.status = TransferStatus::Idle;
+            info!("传输已从错误状态恢复");
+        }
+    }
+    
     /// 获取当前传输状态
     pub fn get_status(&self) -> TransferStatus {
         self.status
     }
     
+    /// 获取当前传输状态的用户可见描述
+    pub fn status_message(&self, lang: Language) -> &'static str {
+        i18n::message(self.status.into(), lang)
+    }
+    
     /// 获取已传输的总字节数
     pub fn get_bytes_transferred(&self) -> usize {
         self.total_bytes_transferred
     }
     
+    /// 已连接的客户端数
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+    
     /// 添加数据包到传输缓冲区
-    fn add_packet_to_buffer(&mut self, packet: DataPacket) -> Result<(), Box<dyn Error>> {
+    fn add_packet_to_buffer(&mut self, packet: QueuedPacket) -> Result<(), Box<dyn Error>> {
         let mut buffer = self.buffer.lock().unwrap();
         
-        // 检查缓冲区大小
-        if buffer.len() >= self.max_buffer_size {
-            // 缓冲区已满，移除最旧的数据包
-            buffer.remove(0);
-            warn!("缓冲区已满，移除最旧的数据包");
-        }
+        // 检查缓冲区大小，为新数据包腾出空间
+        Self::trim_buffer(&mut buffer, self.max_buffer_size.saturating_sub(1), self.max_buffer_bytes, packet.packet.data.len());
         
         // 添加新数据包
         buffer.push(packet);
         Ok(())
     }
     
-    /// 处理发送数据包
+    /// 从最旧的数据包开始丢弃，直到包数不超过`max_packets`且加上`incoming`字节后不超过`max_bytes`
+    fn trim_buffer(buffer: &mut Vec<QueuedPacket>, max_packets: usize, max_bytes: usize, incoming: usize) {
+        let mut queued: usize = buffer.iter().map(|p| p.packet.data.len()).sum();
+        let mut dropped = 0;
+        while !buffer.is_empty() && (buffer.len() > max_packets || queued + incoming > max_bytes) {
+            queued -= buffer.remove(0).packet.data.len();
+            dropped += 1;
+        }
+        if dropped > 0 {
+            warn!("缓冲区已满，移除最旧的 {} 个数据包", dropped);
+        }
+    }
+    
+    /// 处理发送数据包；单个客户端发送失败只影响该客户端，没有客户端时返回错误
     fn process_buffer(&mut self) -> Result<(), Box<dyn Error>> {
         if self.status != TransferStatus::Running {
             return Ok(());
         }
         
-        // 使用可变引用获取 sender
-        let sender = match &mut self.data_sender {
-            Some(s) => s,
-            None => return Err("未设置数据发送器".into()),
-        };
+        if self.clients.is_empty() {
+            return Err(i18n::tr(MessageCode::NoSenderConfigured).into());
+        }
         
         // 获取缓冲区中的数据包
         let mut packets_to_send = Vec::new();
@@ -162,8 +614,32 @@ impl TransferManager {
             }
         }
         
-        // 发送数据包
-        for packet in packets_to_send {
+        // 发送数据包；有紧急控制命令或配额用完时剩余的数据包放回队列头部，稍后再发送
+        let mut packets_to_send = packets_to_send.into_iter();
+        let mut failed: Vec<String> = Vec::new();
+        while let Some(QueuedPacket { packet, route, queued_at }) = packets_to_send.next() {
+            // 命令和响应是控制流量，不受配额限制，也不需要让出
+            let metered = !matches!(packet.packet_type, PacketType::Command | PacketType::Response);
+            let preempted = metered && self.control.as_ref().is_some_and(ControlQueue::has_urgent);
+            if preempted || (metered && self.quota.as_mut().is_some_and(|q| !q.try_consume(packet.data.len() as u64))) {
+                let mut buffer = self.buffer.lock().unwrap();
+                let rest: Vec<QueuedPacket> = std::iter::once(QueuedPacket { packet, route, queued_at })
+                    .chain(packets_to_send)
+                    .collect();
+                if preempted {
+                    debug!("有紧急控制命令待执行，{} 个数据包留在队列中", rest.len());
+                } else {
+                    debug!("传输配额已用完，{} 个数据包留在队列中", rest.len());
+                }
+                buffer.splice(0..0, rest);
+                break;
+            }
+            // 链路质量不足时只发送当前级别允许的数据，其余直接丢弃，不在队列中积压
+            if !self.link_tier().accepts(packet.packet_type) {
+                debug!("发送级别 {} 不发送 {:?} 数据包，已丢弃", self.link_tier(), packet.packet_type);
+                continue;
+            }
+            let started = Instant::now();
             // 根据包类型进行不同处理
             match packet.packet_type {
                 PacketType::Image => {
@@ -180,13 +656,87 @@ impl TransferManager {
                 }
             }
             
-            // 使用可变引用调用 send_data 方法
-            let bytes_sent = sender.send_data(&packet.data)?;
-            self.total_bytes_transferred += bytes_sent;
+            // 改走数据报的类型不再发给各客户端，丢包由接收端容忍
+            if let Some(datagram) = self.datagram.as_mut().filter(|d| d.types.contains(&packet.packet_type)) {
+                match block_on(send_packet(datagram.sender.as_mut(), &packet)) {
+                    Ok(sent) => self.total_bytes_transferred += sent,
+                    Err(e) => warn!("数据报发送 {:?} 数据包失败: {}", packet.packet_type, e),
+                }
+                continue;
+            }
+            
+            // 只发送给声明接收此类数据、且在流水线指定路由中的客户端
+            // 一个客户端发送失败不影响其他客户端，本批中不再向它发送，连续失败多次后移除
+            let targets = self.clients.iter_mut().filter(|c| {
+                c.profile.accepts(packet.packet_type)
+                    && route.as_ref().map_or(true, |ids| ids.contains(&c.client_id))
+                    && !failed.contains(&c.client_id)
+            });
+            let mut packet_sent = 0;
+            let mut newly_failed = Vec::new();
+            for client in targets {
+                match block_on(send_packet(client.sender.as_mut(), &packet)) {
+                    Ok(bytes_sent) => {
+                        packet_sent += bytes_sent;
+                        client.failures = 0;
+                    }
+                    Err(e) => {
+                        client.failures += 1;
+                        warn!(
+                            "向客户端 {} 发送 {:?} 数据包失败(连续第 {} 次): {}",
+                            client.client_id, packet.packet_type, client.failures, e
+                        );
+                        newly_failed.push(client.client_id.clone());
+                    }
+                }
+            }
+            if !newly_failed.is_empty() {
+                if let Some(link) = &mut self.link {
+                    link.record(packet_sent, started.elapsed(), false);
+                }
+                failed.append(&mut newly_failed);
+            }
+            self.total_bytes_transferred += packet_sent;
+            if let Some(link) = self.link.as_mut().filter(|_| packet_sent > 0) {
+                link.record(packet_sent, started.elapsed(), true);
+            }
+            if let Some(metrics) = &self.metrics {
+                metrics.lock().unwrap().record(
+                    stage_metrics::STAGE_SEND,
+                    packet_sent,
+                    started.elapsed(),
+                    started.duration_since(queued_at),
+                );
+            }
         }
+        self.evict_failed_clients();
         
         Ok(())
     }
+    
+    /// 移除连续发送失败次数达到上限的客户端
+    fn evict_failed_clients(&mut self) {
+        let evicted: Vec<String> = self
+            .clients
+            .iter()
+            .filter(|c| c.failures >= MAX_CLIENT_FAILURES)
+            .map(|c| c.client_id.clone())
+            .collect();
+        for client_id in evicted {
+            warn!("客户端 {} 连续 {} 个数据包发送失败，已移除", client_id, MAX_CLIENT_FAILURES);
+            self.remove_client(&client_id);
+        }
+    }
+}
+
+/// 发送一个数据包：连接已断开时先重连，再等待发送器就绪(背压)后发送
+pub(crate) async fn send_packet(sender: &mut dyn DataSender, packet: &DataPacket) -> Result<usize, Box<dyn Error>> {
+    if sender.state() == SenderState::Disconnected {
+        debug!("发送器连接已断开，重新连接");
+        sender.connect().await?;
+    }
+    sender.ready().await?;
+    sender.send(packet).await
 }
 
 // 实现数据监听器接口，接收从相机来的数据
@@ -196,13 +746,22 @@ impl DataListener for TransferManager {
             return;
         }
         
+        // 经过流水线处理后再缓冲，被丢弃的数据包不再发送
+        let queued = match self.pipeline.lock().unwrap().run(packet.clone()) {
+            Ok(Some((packet, ctx))) => QueuedPacket { packet, route: ctx.route, queued_at: Instant::now() },
+            Ok(None) => return,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+        
         // 将数据包添加到缓冲区
-        match self.add_packet_to_buffer(packet.clone()) {
+        match self.add_packet_to_buffer(queued) {
             Ok(_) => {
-                // 触发处理缓冲区数据
+                // 触发处理缓冲区数据；没有客户端时数据包留在队列中，等客户端连接后再发送
                 if let Err(e) = self.process_buffer() {
-                    error!("处理数据包错误: {}", e);
-                    self.status = TransferStatus::Error;
+                    warn!("处理数据包错误: {}", e);
                 }
             },
             Err(e) => {
@@ -217,3 +776,86 @@ impl DataListener for TransferManager {
         self.status = TransferStatus::Error;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wireless::SenderFuture;
+
+    /// 记录收到的数据包数，前`failures`次发送失败
+    struct FakeSender {
+        sent: Arc<Mutex<usize>>,
+        failures: u32,
+    }
+
+    impl DataSender for FakeSender {
+        fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+            Box::pin(async move {
+                if self.failures > 0 {
+                    self.failures -= 1;
+                    return Err("连接已重置".into());
+                }
+                *self.sent.lock().unwrap() += 1;
+                Ok(packet.data.len())
+            })
+        }
+
+        fn close(&mut self) -> SenderFuture<'_, ()> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn add_fake(transfer: &mut TransferManager, client_id: &str, failures: u32) -> Arc<Mutex<usize>> {
+        let sent = Arc::new(Mutex::new(0));
+        transfer.add_client(client_id, ClientProfile::FullIngest, Box::new(FakeSender { sent: sent.clone(), failures }));
+        sent
+    }
+
+    fn image() -> DataPacket {
+        DataPacket::new(PacketType::Image, vec![0; 8])
+    }
+
+    #[test]
+    fn failing_client_does_not_stop_others() {
+        let mut transfer = TransferManager::new(16);
+        let good = add_fake(&mut transfer, "good", 0);
+        add_fake(&mut transfer, "broken", u32::MAX);
+        transfer.start().unwrap();
+
+        for _ in 0..MAX_CLIENT_FAILURES + 1 {
+            transfer.on_data_received(&image());
+        }
+        assert_eq!(*good.lock().unwrap(), MAX_CLIENT_FAILURES as usize + 1);
+        assert_eq!(transfer.get_status(), TransferStatus::Running);
+        assert!(transfer.client_profile("broken").is_none());
+        assert!(transfer.client_profile("good").is_some());
+    }
+
+    #[test]
+    fn success_resets_failure_count() {
+        let mut transfer = TransferManager::new(16);
+        let sent = add_fake(&mut transfer, "flaky", MAX_CLIENT_FAILURES - 1);
+        transfer.start().unwrap();
+        for _ in 0..MAX_CLIENT_FAILURES {
+            transfer.on_data_received(&image());
+        }
+        assert_eq!(*sent.lock().unwrap(), 1);
+        assert_eq!(transfer.clients[0].failures, 0);
+        assert!(transfer.client_profile("flaky").is_some());
+    }
+
+    #[test]
+    fn deliver_reports_each_client() {
+        let mut transfer = TransferManager::new(16);
+        let good = add_fake(&mut transfer, "good", 0);
+        add_fake(&mut transfer, "broken", u32::MAX);
+        let ids = ["good".to_string(), "broken".to_string(), "gone".to_string()];
+        let report = transfer.deliver(&ids, &image()).unwrap();
+        assert_eq!(report.bytes, 8);
+        assert_eq!(report.delivered, vec!["good".to_string()]);
+        let failed: Vec<&str> = report.failed.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(failed, vec!["broken", "gone"]);
+        assert!(!report.is_complete());
+        assert_eq!(*good.lock().unwrap(), 1);
+    }
+}
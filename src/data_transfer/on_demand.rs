@@ -0,0 +1,176 @@
+// 按需下载 - 客户端点开缩略图时立即读取原图(或其中一段)，不经过自动同步的排队；
+// 仍由下载仲裁器分配名额，不会和正在进行的下载重复读取同一对象
+use std::error::Error;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use super::arbiter::{DownloadArbiter, EnqueueOutcome};
+use super::TransferManager;
+use crate::events::AppEvent;
+use crate::ptp_mtp::{DataPacket, PacketType, ProtocolHandler};
+
+/// 写入文件的下载在仲裁器中使用的客户端ID
+const FILE_OWNER: &str = "sd";
+
+/// 对象中的字节区间
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ByteRange {
+    #[serde(default)]
+    pub offset: u64,
+    #[serde(default)]
+    pub length: Option<u64>, // None表示到对象末尾
+}
+
+impl ByteRange {
+    /// 整个对象
+    pub const FULL: ByteRange = ByteRange { offset: 0, length: None };
+
+    /// 是否是整个对象
+    pub fn is_full(&self) -> bool {
+        self.offset == 0 && self.length.is_none()
+    }
+
+    /// 解析HTTP Range头中的单个区间，如`bytes=0-1023`、`bytes=4096-`；不支持`-500`这样的后缀区间
+    pub fn parse(value: &str) -> Result<ByteRange, Box<dyn Error>> {
+        let value = value.trim();
+        let spec = value.strip_prefix("bytes=").unwrap_or(value);
+        let (start, end) = spec.split_once('-').ok_or_else(|| format!("无效的区间: {}", value))?;
+        let offset: u64 = start.trim().parse().map_err(|_| format!("无效的区间起点: {}", value))?;
+        let length = match end.trim() {
+            "" => None,
+            end => {
+                let end: u64 = end.parse().map_err(|_| format!("无效的区间终点: {}", value))?;
+                if end < offset {
+                    return Err(format!("区间终点小于起点: {}", value).into());
+                }
+                Some(end - offset + 1)
+            }
+        };
+        Ok(ByteRange { offset, length })
+    }
+}
+
+/// 下载的目的地
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadDestination {
+    Client(String), // 通过已连接客户端的发送器推送
+    File(PathBuf),  // 写入SD卡上的文件
+}
+
+impl DownloadDestination {
+    /// 在仲裁器中代表这次下载的客户端ID
+    fn owner(&self) -> &str {
+        match self {
+            DownloadDestination::Client(client_id) => client_id,
+            DownloadDestination::File(_) => FILE_OWNER,
+        }
+    }
+}
+
+/// 按需下载的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OnDemandReport {
+    pub handle: u32,
+    pub bytes: u64,                // 本次读取的字节数，共享读取时为0
+    pub shared: bool,              // 对象正在下载，已加入共享读取
+    pub elapsed: Duration,
+    pub client_id: Option<String>, // 接收数据的客户端
+}
+
+impl OnDemandReport {
+    /// 发给客户端的完整对象对应的完成事件；区间下载和写入文件不产生事件
+    pub fn event(&self, range: ByteRange) -> Option<AppEvent> {
+        if self.shared || !range.is_full() {
+            return None;
+        }
+        Some(AppEvent::TransferComplete {
+            handle: self.handle,
+            bytes: self.bytes,
+            client_id: self.client_id.clone()?,
+        })
+    }
+}
+
+/// 立即下载对象或其中一段到目的地
+///
+/// 下载名额已满，或同一对象正在下载而本次请求不能共享其结果(区间下载或写入文件)时返回错误，由客户端稍后重试
+pub fn download(
+    protocol: &mut dyn ProtocolHandler,
+    arbiter: &mut DownloadArbiter,
+    transfer: &mut TransferManager,
+    handle: u32,
+    range: ByteRange,
+    destination: &DownloadDestination,
+) -> Result<OnDemandReport, Box<dyn Error>> {
+    let client_id = match destination {
+        DownloadDestination::Client(client_id) => {
+            if transfer.client_profile(client_id).is_none() {
+                return Err(format!("客户端 {} 未连接", client_id).into());
+            }
+            Some(client_id.clone())
+        }
+        DownloadDestination::File(_) => None,
+    };
+
+    let owner = destination.owner();
+    let shareable = range.is_full() && client_id.is_some();
+    let started = Instant::now();
+    if arbiter.begin_direct(owner, handle, shareable)? == EnqueueOutcome::Joined {
+        info!("对象 0x{:08x} 正在下载，客户端 {} 共享读取结果", handle, owner);
+        return Ok(OnDemandReport {
+            handle,
+            bytes: 0,
+            shared: true,
+            elapsed: started.elapsed(),
+            client_id,
+        });
+    }
+
+    let result = match destination {
+        DownloadDestination::Client(client_id) => {
+            let clients = [client_id.clone()];
+            protocol.read_object(handle, range.offset, range.length, &mut |chunk| {
+                let packet = DataPacket {
+                    data: chunk.to_vec(),
+                    timestamp: SystemTime::now(),
+                    packet_type: PacketType::Image,
+                };
+                let mut report = transfer.deliver(&clients, &packet)?;
+                match report.failed.pop() {
+                    Some((_, e)) => Err(e),
+                    None => Ok(()),
+                }
+            })
+        }
+        DownloadDestination::File(path) => read_to_file(protocol, handle, range, path),
+    };
+    arbiter.complete(handle);
+    let bytes = result?;
+    info!(
+        "按需下载对象 0x{:08x} 的 {} 字节到 {:?}，用时 {}ms",
+        handle,
+        bytes,
+        destination,
+        started.elapsed().as_millis()
+    );
+    Ok(OnDemandReport {
+        handle,
+        bytes,
+        shared: false,
+        elapsed: started.elapsed(),
+        client_id,
+    })
+}
+
+fn read_to_file(protocol: &mut dyn ProtocolHandler, handle: u32, range: ByteRange, path: &Path) -> Result<u64, Box<dyn Error>> {
+    let mut file = File::create(path)?;
+    let bytes = protocol.read_object(handle, range.offset, range.length, &mut |chunk| file.write_all(chunk).map_err(Into::into))?;
+    file.sync_all()?;
+    Ok(bytes)
+}
@@ -0,0 +1,464 @@
+// 数据流水线 - 在配置中按顺序声明相机数据到发送之间的处理阶段(分析、过滤、路由等)及其参数，
+// 启动时由构建器组装，不同部署无需改代码即可调整数据路径。内置过滤、分析、路由、归档(需要`sd`)、压缩和加密阶段，
+// 固件项目自己的处理函数通过`hooks::register_packet_hook`注册
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "sd")]
+use std::fs;
+#[cfg(feature = "sd")]
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(feature = "sd")]
+use std::time::UNIX_EPOCH;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::ChaCha20Poly1305;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::hooks::{self, HookStage};
+use super::ledger::hash_object;
+use super::receipt::decode_hex;
+use super::stage_metrics::StageMetricsHandle;
+use crate::ptp_mtp::{DataPacket, PacketType};
+
+/// 配置中的一个阶段
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageSpec {
+    pub name: String,                     // 已注册的阶段名称
+    #[serde(default)]
+    pub params: BTreeMap<String, String>, // 阶段参数
+}
+
+impl StageSpec {
+    pub fn new(name: &str) -> Self {
+        StageSpec {
+            name: name.to_string(),
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// 添加参数
+    pub fn param(mut self, key: &str, value: &str) -> Self {
+        self.params.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// 读取参数
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params.get(key).map(String::as_str)
+    }
+
+    /// 读取数值参数，格式错误时返回错误而不是静默使用默认值
+    pub fn get_u64(&self, key: &str) -> Result<Option<u64>, Box<dyn Error>> {
+        match self.get(key) {
+            Some(v) => v
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("阶段 {} 的参数 {} 不是数字: {}", self.name, key, v).into()),
+            None => Ok(None),
+        }
+    }
+
+    /// 读取逗号分隔的列表参数
+    pub fn get_list(&self, key: &str) -> Vec<String> {
+        self.get(key)
+            .map(|v| v.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// 数据包在流水线中携带的附加信息
+#[derive(Debug, Clone, Default)]
+pub struct PacketContext {
+    pub hash: Option<String>,       // 分析阶段计算的SHA-256摘要
+    pub route: Option<Vec<String>>, // 路由阶段指定的客户端，None表示按客户端类型分发
+}
+
+/// 阶段对数据包的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageAction {
+    Continue, // 交给下一阶段
+    Drop,     // 丢弃，不再发送
+}
+
+/// 流水线阶段
+pub trait PipelineStage: Send {
+    /// 阶段名称
+    fn name(&self) -> &str;
+
+    /// 处理数据包，可以原地修改数据(例如压缩或加密)
+    fn process(&mut self, packet: &mut DataPacket, ctx: &mut PacketContext) -> Result<StageAction, Box<dyn Error>>;
+
+    /// 处理后数据长度是否不变；压缩、加密等改变长度的阶段返回false，流式输出据此改为分块封装
+    fn preserves_length(&self) -> bool {
+        true
+    }
+}
+
+/// 按配置创建阶段的工厂函数
+pub type StageFactory = Box<dyn Fn(&StageSpec) -> Result<Box<dyn PipelineStage>, Box<dyn Error>> + Send>;
+
+/// 传输管理器与其他数据出口(如 /sync/stream)共用的流水线，切换模式时原地替换
+pub type PipelineHandle = Arc<Mutex<Pipeline>>;
+
+/// 组装好的流水线
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn PipelineStage>>,
+    metrics: Option<StageMetricsHandle>,
+}
+
+impl Pipeline {
+    /// 记录每个阶段的处理耗时
+    pub fn set_metrics(&mut self, metrics: StageMetricsHandle) {
+        self.metrics = Some(metrics);
+    }
+
+    /// 依次执行所有阶段，数据包被丢弃时返回None
+    pub fn run(&mut self, mut packet: DataPacket) -> Result<Option<(DataPacket, PacketContext)>, Box<dyn Error>> {
+        let mut ctx = PacketContext::default();
+        for stage in &mut self.stages {
+            let started = Instant::now();
+            let action = stage.process(&mut packet, &mut ctx);
+            if let Some(metrics) = &self.metrics {
+                metrics.lock().unwrap().record(stage.name(), packet.data.len(), started.elapsed(), Duration::ZERO);
+            }
+            match action {
+                Ok(StageAction::Continue) => {}
+                Ok(StageAction::Drop) => {
+                    debug!("数据包被阶段 {} 丢弃", stage.name());
+                    return Ok(None);
+                }
+                Err(e) => return Err(format!("流水线阶段 {} 出错: {}", stage.name(), e).into()),
+            }
+        }
+        Ok(Some((packet, ctx)))
+    }
+
+    /// 阶段名称列表
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|s| s.name()).collect()
+    }
+
+    /// 所有阶段都不改变数据长度
+    pub fn preserves_length(&self) -> bool {
+        self.stages.iter().all(|s| s.preserves_length())
+    }
+
+    /// 是否没有任何阶段(直接透传)
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.stage_names()).finish()
+    }
+}
+
+/// 流水线构建器 - 记录可用的阶段，按配置顺序组装
+pub struct PipelineBuilder {
+    factories: HashMap<String, StageFactory>,
+}
+
+impl PipelineBuilder {
+    /// 包含内置阶段(filter、analyze、route、compress、encrypt，启用`sd`时还有archive)和已注册钩子的构建器
+    pub fn new() -> Self {
+        let mut builder = PipelineBuilder {
+            factories: HashMap::new(),
+        };
+        builder.register("filter", Box::new(|spec| Ok(Box::new(FilterStage::from_spec(spec)?))));
+        builder.register("analyze", Box::new(|spec| Ok(Box::new(AnalyzeStage::from_spec(spec)))));
+        builder.register("route", Box::new(|spec| Ok(Box::new(RouteStage::from_spec(spec)?))));
+        #[cfg(feature = "sd")]
+        builder.register("archive", Box::new(|spec| Ok(Box::new(ArchiveStage::from_spec(spec)?))));
+        builder.register("compress", Box::new(|spec| Ok(Box::new(CompressStage::from_spec(spec)?))));
+        builder.register("encrypt", Box::new(|spec| Ok(Box::new(EncryptStage::from_spec(spec)?))));
+        for (name, hook) in hooks::registered() {
+            if builder.factories.contains_key(name) {
+                warn!("数据包钩子 {} 与内置阶段同名，使用钩子", name);
+            }
+            builder.register(name, Box::new(move |spec| Ok(Box::new(HookStage::new(spec, hook)))));
+        }
+        builder
+    }
+
+    /// 注册阶段，同名阶段会被替换
+    pub fn register(&mut self, name: &str, factory: StageFactory) {
+        self.factories.insert(name.to_string(), factory);
+    }
+
+    /// 按配置顺序组装流水线，任何阶段未注册或参数错误时整体失败，避免以不完整的数据路径启动
+    pub fn build(&self, specs: &[StageSpec]) -> Result<Pipeline, Box<dyn Error>> {
+        let mut stages = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let factory = self
+                .factories
+                .get(&spec.name)
+                .ok_or_else(|| format!("未知的流水线阶段: {}", spec.name))?;
+            stages.push(factory(spec)?);
+        }
+        Ok(Pipeline { stages, metrics: None })
+    }
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_packet_type(name: &str) -> Option<PacketType> {
+    match name {
+        "image" => Some(PacketType::Image),
+        "thumbnail" => Some(PacketType::Thumbnail),
+        "live_view" => Some(PacketType::LiveView),
+        "metadata" => Some(PacketType::Metadata),
+        "command" => Some(PacketType::Command),
+        "response" => Some(PacketType::Response),
+        _ => None,
+    }
+}
+
+/// 读取 `types` 参数中的数据包类型列表
+fn packet_types(spec: &StageSpec) -> Result<Vec<PacketType>, Box<dyn Error>> {
+    spec.get_list("types")
+        .iter()
+        .map(|t| parse_packet_type(t).ok_or_else(|| format!("未知的数据包类型: {}", t).into()))
+        .collect()
+}
+
+/// 过滤阶段 - 参数 `types`(逗号分隔的数据包类型) 和 `max_bytes`
+struct FilterStage {
+    types: Vec<PacketType>,
+    max_bytes: Option<u64>,
+}
+
+impl FilterStage {
+    fn from_spec(spec: &StageSpec) -> Result<Self, Box<dyn Error>> {
+        let types = packet_types(spec)?;
+        Ok(FilterStage {
+            types,
+            max_bytes: spec.get_u64("max_bytes")?,
+        })
+    }
+}
+
+impl PipelineStage for FilterStage {
+    fn name(&self) -> &str {
+        "filter"
+    }
+
+    fn process(&mut self, packet: &mut DataPacket, _ctx: &mut PacketContext) -> Result<StageAction, Box<dyn Error>> {
+        if !self.types.is_empty() && !self.types.contains(&packet.packet_type) {
+            return Ok(StageAction::Drop);
+        }
+        if self.max_bytes.is_some_and(|max| packet.data.len() as u64 > max) {
+            return Ok(StageAction::Drop);
+        }
+        Ok(StageAction::Continue)
+    }
+}
+
+/// 分析阶段 - 计算摘要供后续阶段和台账使用，参数 `log=true` 时记录每个数据包
+struct AnalyzeStage {
+    log: bool,
+    packets: u64,
+    bytes: u64,
+}
+
+impl AnalyzeStage {
+    fn from_spec(spec: &StageSpec) -> Self {
+        AnalyzeStage {
+            log: spec.get("log") == Some("true"),
+            packets: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl PipelineStage for AnalyzeStage {
+    fn name(&self) -> &str {
+        "analyze"
+    }
+
+    fn process(&mut self, packet: &mut DataPacket, ctx: &mut PacketContext) -> Result<StageAction, Box<dyn Error>> {
+        let hash = hash_object(&packet.data);
+        self.packets += 1;
+        self.bytes += packet.data.len() as u64;
+        if self.log {
+            info!(
+                "数据包 #{} {:?} {} 字节 sha256={} (累计 {} 字节)",
+                self.packets,
+                packet.packet_type,
+                packet.data.len(),
+                hash,
+                self.bytes
+            );
+        }
+        ctx.hash = Some(hash);
+        Ok(StageAction::Continue)
+    }
+}
+
+/// 路由阶段 - 把指定类型(`types`，为空表示全部)的数据包只发给 `clients` 中的客户端
+struct RouteStage {
+    types: Vec<PacketType>,
+    clients: Vec<String>,
+}
+
+impl RouteStage {
+    fn from_spec(spec: &StageSpec) -> Result<Self, Box<dyn Error>> {
+        let clients = spec.get_list("clients");
+        if clients.is_empty() {
+            return Err("route 阶段需要 clients 参数".into());
+        }
+        let types = packet_types(spec)?;
+        Ok(RouteStage { types, clients })
+    }
+}
+
+impl PipelineStage for RouteStage {
+    fn name(&self) -> &str {
+        "route"
+    }
+
+    fn process(&mut self, packet: &mut DataPacket, ctx: &mut PacketContext) -> Result<StageAction, Box<dyn Error>> {
+        if self.types.is_empty() || self.types.contains(&packet.packet_type) {
+            ctx.route = Some(self.clients.clone());
+        }
+        Ok(StageAction::Continue)
+    }
+}
+
+/// 归档阶段 - 把指定类型(`types`，默认image)的数据包写入SD卡目录 `dir`；`forward=false` 时归档后不再发送
+#[cfg(feature = "sd")]
+struct ArchiveStage {
+    dir: PathBuf,
+    types: Vec<PacketType>,
+    forward: bool,
+    written: u64,
+}
+
+#[cfg(feature = "sd")]
+impl ArchiveStage {
+    fn from_spec(spec: &StageSpec) -> Result<Self, Box<dyn Error>> {
+        let dir = PathBuf::from(spec.get("dir").ok_or("archive 阶段需要 dir 参数")?);
+        fs::create_dir_all(&dir).map_err(|e| format!("无法创建归档目录 {}: {}", dir.display(), e))?;
+        let mut types = packet_types(spec)?;
+        if types.is_empty() {
+            types.push(PacketType::Image);
+        }
+        Ok(ArchiveStage {
+            dir,
+            types,
+            forward: spec.get("forward") != Some("false"),
+            written: 0,
+        })
+    }
+}
+
+#[cfg(feature = "sd")]
+impl PipelineStage for ArchiveStage {
+    fn name(&self) -> &str {
+        "archive"
+    }
+
+    fn process(&mut self, packet: &mut DataPacket, _ctx: &mut PacketContext) -> Result<StageAction, Box<dyn Error>> {
+        if !self.types.contains(&packet.packet_type) {
+            return Ok(if self.forward { StageAction::Continue } else { StageAction::Drop });
+        }
+        // 数据包不带文件名，按时间戳和序号命名，保证按拍摄顺序排列且不重名
+        let millis = packet.timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let path = self.dir.join(format!("{:013}-{:06}.bin", millis, self.written));
+        fs::write(&path, &packet.data)?;
+        self.written += 1;
+        debug!("已归档 {} 字节到 {}", packet.data.len(), path.display());
+        Ok(if self.forward { StageAction::Continue } else { StageAction::Drop })
+    }
+}
+
+/// 默认压缩级别
+const DEFAULT_COMPRESS_LEVEL: u64 = 6;
+
+/// 压缩阶段 - 把指定类型(`types`，为空表示全部)的数据包单独压缩成raw deflate，参数 `level` 为0-10
+struct CompressStage {
+    types: Vec<PacketType>,
+    level: u8,
+}
+
+impl CompressStage {
+    fn from_spec(spec: &StageSpec) -> Result<Self, Box<dyn Error>> {
+        let level = spec.get_u64("level")?.unwrap_or(DEFAULT_COMPRESS_LEVEL);
+        if level > 10 {
+            return Err(format!("compress 阶段的 level 应为0-10: {}", level).into());
+        }
+        Ok(CompressStage {
+            types: packet_types(spec)?,
+            level: level as u8,
+        })
+    }
+}
+
+impl PipelineStage for CompressStage {
+    fn name(&self) -> &str {
+        "compress"
+    }
+
+    fn process(&mut self, packet: &mut DataPacket, _ctx: &mut PacketContext) -> Result<StageAction, Box<dyn Error>> {
+        if self.types.is_empty() || self.types.contains(&packet.packet_type) {
+            packet.data = miniz_oxide::deflate::compress_to_vec(&packet.data, self.level);
+        }
+        Ok(StageAction::Continue)
+    }
+
+    fn preserves_length(&self) -> bool {
+        false
+    }
+}
+
+/// ChaCha20-Poly1305的nonce长度
+const NONCE_LEN: usize = 12;
+
+/// 加密阶段 - 用ChaCha20-Poly1305单独加密每个数据包，参数 `key` 为64个十六进制字符；
+/// 输出为12字节随机nonce + 密文 + 16字节认证标签，接收端可按数据包单独解密
+struct EncryptStage {
+    cipher: ChaCha20Poly1305,
+}
+
+impl EncryptStage {
+    fn from_spec(spec: &StageSpec) -> Result<Self, Box<dyn Error>> {
+        let key = spec
+            .get("key")
+            .and_then(decode_hex)
+            .filter(|k| k.len() == 32)
+            .ok_or("encrypt 阶段需要 key 参数(64个十六进制字符)")?;
+        Ok(EncryptStage {
+            cipher: ChaCha20Poly1305::new_from_slice(&key).map_err(|_| "encrypt 阶段的密钥无效")?,
+        })
+    }
+}
+
+impl PipelineStage for EncryptStage {
+    fn name(&self) -> &str {
+        "encrypt"
+    }
+
+    fn process(&mut self, packet: &mut DataPacket, _ctx: &mut PacketContext) -> Result<StageAction, Box<dyn Error>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = self.cipher.encrypt(&nonce, packet.data.as_slice()).map_err(|_| "数据包加密失败")?;
+        let mut data = Vec::with_capacity(NONCE_LEN + sealed.len());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&sealed);
+        packet.data = data;
+        Ok(StageAction::Continue)
+    }
+
+    fn preserves_length(&self) -> bool {
+        false
+    }
+}
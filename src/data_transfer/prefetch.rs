@@ -0,0 +1,295 @@
+// 空闲预取 - 链路空闲且没有排队任务时，逐个读取尚未同步对象的缩略图和EXIF放入有界缓存(SD卡目录或内存/PSRAM)，
+// 客户端连接后相册可以立即显示，不必等待相机逐个响应GetThumb
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use super::ledger::ObjectLedger;
+use crate::ptp_mtp::{ObjectFormat, PtpCamera, PtpObjectInfo};
+
+/// 读取EXIF时从对象开头读取的字节数，EXIF(APP1)段不超过64KB
+const EXIF_PROBE_BYTES: u32 = 64 * 1024;
+
+/// SD卡上的默认缓存目录
+pub const DEFAULT_CACHE_DIR: &str = "/sdcard/previews";
+/// 缓存在SD卡上时的默认容量
+pub const DEFAULT_DIR_CACHE_BYTES: usize = 16 * 1024 * 1024;
+/// 缓存在内存中时的默认容量
+pub const DEFAULT_MEMORY_CACHE_BYTES: usize = 256 * 1024;
+
+/// 缓存的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheLocation {
+    Memory,       // 内存(启用PSRAM时分配在PSRAM中)，重启后丢失
+    Dir(PathBuf), // SD卡上的目录，重启后保留
+}
+
+/// 预取的对象预览
+#[derive(Debug, Clone)]
+pub struct ObjectPreview {
+    pub handle: u32,
+    pub info: PtpObjectInfo,
+    pub thumb: Vec<u8>,        // 缩略图，相机未提供时为空
+    pub exif: Option<Vec<u8>>, // JPEG的EXIF(APP1)段内容
+}
+
+impl ObjectPreview {
+    fn size(&self) -> usize {
+        self.thumb.len() + self.exif.as_ref().map_or(0, Vec::len)
+    }
+}
+
+/// 有界的预览缓存，超出容量时淘汰最早加入的预览
+pub struct PreviewCache {
+    location: CacheLocation,
+    max_bytes: usize,
+    used: usize,
+    order: VecDeque<u32>,
+    sizes: HashMap<u32, usize>,
+    memory: HashMap<u32, ObjectPreview>,
+}
+
+impl PreviewCache {
+    /// 打开缓存；目录缓存会载入之前保存的预览
+    pub fn open(location: CacheLocation, max_bytes: usize) -> Result<Self, Box<dyn Error>> {
+        let mut cache = PreviewCache {
+            location,
+            max_bytes,
+            used: 0,
+            order: VecDeque::new(),
+            sizes: HashMap::new(),
+            memory: HashMap::new(),
+        };
+        if let CacheLocation::Dir(dir) = &cache.location {
+            fs::create_dir_all(dir)?;
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                if path.extension().and_then(|e| e.to_str()) != Some("inf") {
+                    continue;
+                }
+                let Ok(handle) = u32::from_str_radix(stem, 16) else {
+                    continue;
+                };
+                let size = ["thm", "exf"]
+                    .iter()
+                    .filter_map(|ext| fs::metadata(path.with_extension(ext)).ok())
+                    .map(|m| m.len() as usize)
+                    .sum();
+                cache.order.push_back(handle);
+                cache.sizes.insert(handle, size);
+                cache.used += size;
+            }
+            debug!("预览缓存已载入 {} 项 ({} 字节)", cache.order.len(), cache.used);
+        }
+        Ok(cache)
+    }
+
+    fn file(&self, handle: u32, ext: &str) -> Option<PathBuf> {
+        match &self.location {
+            CacheLocation::Dir(dir) => Some(dir.join(format!("{:08x}.{}", handle, ext))),
+            CacheLocation::Memory => None,
+        }
+    }
+
+    /// 是否已缓存
+    pub fn contains(&self, handle: u32) -> bool {
+        self.sizes.contains_key(&handle)
+    }
+
+    /// 读取缓存的预览
+    pub fn get(&self, handle: u32) -> Result<Option<ObjectPreview>, Box<dyn Error>> {
+        if !self.contains(handle) {
+            return Ok(None);
+        }
+        let (Some(inf), Some(thm), Some(exf)) = (self.file(handle, "inf"), self.file(handle, "thm"), self.file(handle, "exf")) else {
+            return Ok(self.memory.get(&handle).cloned());
+        };
+        Ok(Some(ObjectPreview {
+            handle,
+            info: serde_json::from_slice(&fs::read(inf)?)?,
+            thumb: fs::read(thm).unwrap_or_default(),
+            exif: fs::read(exf).ok(),
+        }))
+    }
+
+    /// 加入预览，超出容量时先淘汰最早的预览；单个预览超过总容量时不缓存
+    pub fn put(&mut self, preview: ObjectPreview) -> Result<bool, Box<dyn Error>> {
+        let size = preview.size();
+        if size > self.max_bytes {
+            return Ok(false);
+        }
+        self.remove(preview.handle)?;
+        while self.used + size > self.max_bytes {
+            let Some(oldest) = self.order.front().copied() else {
+                break;
+            };
+            self.remove(oldest)?;
+        }
+        if let (Some(inf), Some(thm), Some(exf)) = (
+            self.file(preview.handle, "inf"),
+            self.file(preview.handle, "thm"),
+            self.file(preview.handle, "exf"),
+        ) {
+            fs::write(thm, &preview.thumb)?;
+            if let Some(exif) = &preview.exif {
+                fs::write(exf, exif)?;
+            }
+            // 信息文件最后写入，载入时以它判断预览是否完整
+            fs::write(inf, serde_json::to_vec(&preview.info)?)?;
+        } else {
+            self.memory.insert(preview.handle, preview.clone());
+        }
+        self.order.push_back(preview.handle);
+        self.sizes.insert(preview.handle, size);
+        self.used += size;
+        Ok(true)
+    }
+
+    /// 移除预览(例如对象已同步或已从相机删除)
+    pub fn remove(&mut self, handle: u32) -> Result<(), Box<dyn Error>> {
+        let Some(size) = self.sizes.remove(&handle) else {
+            return Ok(());
+        };
+        self.used -= size;
+        self.order.retain(|h| *h != handle);
+        self.memory.remove(&handle);
+        for ext in ["inf", "thm", "exf"] {
+            if let Some(path) = self.file(handle, ext) {
+                let _ = fs::remove_file(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// 已缓存的预览数
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// 缓存是否为空
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// 已用字节数
+    pub fn used_bytes(&self) -> usize {
+        self.used
+    }
+}
+
+/// 从JPEG开头的数据中取出EXIF(APP1)段内容，遇到图像数据(SOS)前没有找到时返回None
+pub fn extract_exif(jpeg: &[u8]) -> Option<&[u8]> {
+    if jpeg.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut pos = 2;
+    while pos + 4 <= jpeg.len() {
+        if jpeg[pos] != 0xFF {
+            return None;
+        }
+        let marker = jpeg[pos + 1];
+        if marker == 0xDA {
+            return None;
+        }
+        let len = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+        let segment = jpeg.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            return Some(segment);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+/// 空闲预取器
+pub struct IdlePrefetcher {
+    cache: PreviewCache,
+    pending: VecDeque<u32>,
+    timeout: Option<Duration>,
+}
+
+impl IdlePrefetcher {
+    pub fn new(cache: PreviewCache, timeout: Option<Duration>) -> Self {
+        IdlePrefetcher {
+            cache,
+            pending: VecDeque::new(),
+            timeout,
+        }
+    }
+
+    /// 根据相机上的对象列表安排预取：跳过台账中已同步的和已缓存的对象，返回待预取数
+    pub fn plan(&mut self, handles: &[u32], ledger: &ObjectLedger) -> usize {
+        let synced: HashSet<u32> = ledger.entries().iter().map(|e| e.handle).collect();
+        self.pending = handles
+            .iter()
+            .copied()
+            .filter(|h| !synced.contains(h) && !self.cache.contains(*h))
+            .collect();
+        if !self.pending.is_empty() {
+            info!("安排预取 {} 个对象的预览", self.pending.len());
+        }
+        self.pending.len()
+    }
+
+    /// 相机新增的对象加入预取队列，已缓存或已在队列中时忽略
+    pub fn enqueue(&mut self, handle: u32) {
+        if !self.cache.contains(handle) && !self.pending.contains(&handle) {
+            self.pending.push_back(handle);
+        }
+    }
+
+    /// 是否还有待预取的对象
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// 链路空闲时(通常取`DownloadArbiter::is_idle`)预取一个对象，返回预取的对象句柄；不空闲或没有待预取对象时返回None
+    /// 每次只处理一个对象，调用方在两次调用之间重新判断是否空闲，客户端的请求不会被预取长时间阻塞
+    pub async fn step(&mut self, camera: &mut PtpCamera, idle: bool) -> Result<Option<u32>, Box<dyn Error>> {
+        if !idle {
+            return Ok(None);
+        }
+        let Some(handle) = self.pending.pop_front() else {
+            return Ok(None);
+        };
+        let info = camera.get_objectinfo(handle, self.timeout).await?;
+        let format = info.format();
+        if format.is_association() {
+            return Ok(None);
+        }
+        let thumb = if info.ThumbCompressedSize > 0 {
+            camera.get_thumb(handle, self.timeout).await?
+        } else {
+            Vec::new()
+        };
+        let exif = if format == ObjectFormat::ExifJpeg {
+            let head = camera.get_partialobject(handle, 0, EXIF_PROBE_BYTES, self.timeout).await?;
+            extract_exif(&head).map(<[u8]>::to_vec)
+        } else {
+            None
+        };
+        let preview = ObjectPreview { handle, info, thumb, exif };
+        if !self.cache.put(preview)? {
+            warn!("对象 0x{:08x} 的预览超过缓存容量，不缓存", handle);
+        }
+        debug!("已预取对象 0x{:08x} 的预览，缓存 {} 项", handle, self.cache.len());
+        Ok(Some(handle))
+    }
+
+    /// 预览缓存
+    pub fn cache(&self) -> &PreviewCache {
+        &self.cache
+    }
+
+    /// 可修改的预览缓存(例如对象同步后移除预览)
+    pub fn cache_mut(&mut self) -> &mut PreviewCache {
+        &mut self.cache
+    }
+}
@@ -0,0 +1,47 @@
+// 客户端能力配置 - 客户端在握手时声明，TransferManager据此决定向其路由哪些数据
+use crate::ptp_mtp::PacketType;
+
+/// 客户端类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClientProfile {
+    ThumbnailsOnly, // 只要缩略图的手机
+    #[default]
+    FullIngest,     // 接收原图的导入工作站
+    MonitorOnly,    // 只看状态的浏览器
+}
+
+impl ClientProfile {
+    /// 从握手中的名称解析
+    pub fn from_name(name: &str) -> Option<ClientProfile> {
+        match name {
+            "thumbnails" => Some(ClientProfile::ThumbnailsOnly),
+            "ingest" => Some(ClientProfile::FullIngest),
+            "monitor" => Some(ClientProfile::MonitorOnly),
+            _ => None,
+        }
+    }
+
+    /// 握手中使用的名称
+    pub fn name(self) -> &'static str {
+        match self {
+            ClientProfile::ThumbnailsOnly => "thumbnails",
+            ClientProfile::FullIngest => "ingest",
+            ClientProfile::MonitorOnly => "monitor",
+        }
+    }
+
+    /// 该类客户端是否接收此类型的数据包
+    pub fn accepts(self, packet_type: PacketType) -> bool {
+        match self {
+            ClientProfile::ThumbnailsOnly => matches!(
+                packet_type,
+                PacketType::Thumbnail | PacketType::Metadata | PacketType::Response
+            ),
+            ClientProfile::FullIngest => true,
+            ClientProfile::MonitorOnly => {
+                matches!(packet_type, PacketType::Metadata | PacketType::Response)
+            }
+        }
+    }
+}
+
@@ -0,0 +1,120 @@
+// 传输配额 - 限制滑动时间窗口内发送的字节数，链路是按流量计费的手机热点等场景使用；
+// 配额用完后数据包留在发送队列中，窗口内较早的流量过期后继续发送
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::events::AppEvent;
+
+/// 统计粒度，窗口内的流量按分钟分桶过期
+const BUCKET: Duration = Duration::from_secs(60);
+/// 按小时计的配额使用的窗口
+pub const HOUR: Duration = Duration::from_secs(3600);
+
+/// 滑动窗口传输配额
+#[derive(Debug)]
+pub struct TransferQuota {
+    limit: u64,
+    window: Duration,
+    buckets: VecDeque<(Instant, u64)>, // (分桶开始时间, 字节数)
+    used: u64,
+    exhausted: bool,
+    event: Option<AppEvent>, // 尚未取走的状态变化事件
+}
+
+impl TransferQuota {
+    pub fn new(limit_bytes: u64, window: Duration) -> Self {
+        TransferQuota {
+            limit: limit_bytes,
+            window: window.max(BUCKET),
+            buckets: VecDeque::new(),
+            used: 0,
+            exhausted: false,
+            event: None,
+        }
+    }
+
+    /// 每小时`mb`兆字节的配额
+    pub fn per_hour(mb: u32) -> Self {
+        Self::new(mb as u64 * 1024 * 1024, HOUR)
+    }
+
+    /// 窗口内的配额(字节)
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// 配额的时间窗口
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// 窗口内已使用的字节数
+    pub fn used(&mut self) -> u64 {
+        self.expire(Instant::now());
+        self.used
+    }
+
+    /// 配额是否已用完
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// 距离最早一桶流量过期还有多久，即最快何时可以继续发送
+    pub fn resets_in(&self) -> Duration {
+        self.buckets
+            .front()
+            .map_or(Duration::ZERO, |(start, _)| (*start + self.window).saturating_duration_since(Instant::now()))
+    }
+
+    /// 申请发送`bytes`字节，配额不足时返回false且不计入
+    /// 窗口内还没有任何流量时总是允许，单个超过配额的数据包不会永远卡在队列中
+    pub fn try_consume(&mut self, bytes: u64) -> bool {
+        let now = Instant::now();
+        self.expire(now);
+        if self.used > 0 && self.used + bytes > self.limit {
+            if !self.exhausted {
+                self.exhausted = true;
+                let resets_in = self.resets_in();
+                warn!("传输配额已用完 ({} 字节)，{} 秒后恢复", self.limit, resets_in.as_secs());
+                self.event = Some(AppEvent::QuotaExhausted {
+                    limit_bytes: self.limit,
+                    window_secs: self.window.as_secs(),
+                    resets_in_secs: resets_in.as_secs(),
+                });
+            }
+            return false;
+        }
+        match self.buckets.back_mut() {
+            Some((start, used)) if now.duration_since(*start) < BUCKET => *used += bytes,
+            _ => self.buckets.push_back((now, bytes)),
+        }
+        self.used += bytes;
+        true
+    }
+
+    /// 取走配额状态变化的事件(用完或恢复)
+    pub fn take_event(&mut self) -> Option<AppEvent> {
+        self.expire(Instant::now());
+        self.event.take()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some((start, bytes)) = self.buckets.front().copied() {
+            if now.duration_since(start) < self.window {
+                break;
+            }
+            self.buckets.pop_front();
+            self.used -= bytes;
+        }
+        if self.exhausted && self.used < self.limit {
+            self.exhausted = false;
+            info!("传输配额已恢复，窗口内已用 {} 字节", self.used);
+            self.event = Some(AppEvent::QuotaRestored {
+                used_bytes: self.used,
+                limit_bytes: self.limit,
+            });
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// 交付回执 - 客户端确认完整接收后生成带HMAC签名的回执，证明指定对象已送达
+use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use super::ledger::LedgerEntry;
+use crate::persist::KvStore;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const SIGNING_KEY: &str = "rcpt_key";
+
+/// 交付回执
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub handle: u32,       // 对象句柄
+    pub hash: String,      // 对象SHA-256摘要
+    pub size: u64,         // 对象字节数
+    pub timestamp: u64,    // 确认时间(Unix秒)
+    pub client_id: String, // 确认接收的客户端
+    pub signature: String, // HMAC-SHA256签名(十六进制)
+}
+
+impl Receipt {
+    /// 参与签名的规范化内容
+    fn signed_payload(handle: u32, hash: &str, size: u64, timestamp: u64, client_id: &str) -> String {
+        format!("{:08x}\n{}\n{}\n{}\n{}", handle, hash, size, timestamp, client_id)
+    }
+
+    /// 为台账条目签发回执
+    pub fn issue(entry: &LedgerEntry, client_id: &str, key: &[u8]) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let payload = Self::signed_payload(entry.handle, &entry.hash, entry.size, timestamp, client_id);
+        Receipt {
+            handle: entry.handle,
+            hash: entry.hash.clone(),
+            size: entry.size,
+            timestamp,
+            client_id: client_id.to_string(),
+            signature: sign(key, &payload),
+        }
+    }
+
+    /// 校验回执签名
+    pub fn verify(&self, key: &[u8]) -> bool {
+        let payload = Self::signed_payload(self.handle, &self.hash, self.size, self.timestamp, &self.client_id);
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC可以接受任意长度的密钥");
+        mac.update(payload.as_bytes());
+        match decode_hex(&self.signature) {
+            Some(sig) => mac.verify_slice(&sig).is_ok(),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for Receipt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:08x} {} 字节 sha256={} 客户端={} 时间={} 签名={}",
+            self.handle, self.size, self.hash, self.client_id, self.timestamp, self.signature
+        )
+    }
+}
+
+fn sign(key: &[u8], payload: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC可以接受任意长度的密钥");
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// 读取设备的回执签名密钥，首次使用时随机生成并持久化
+pub fn load_or_create_key(store: &mut dyn KvStore) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Some(key) = store.get(SIGNING_KEY)? {
+        return Ok(key);
+    }
+    let mut key = Vec::with_capacity(32);
+    key.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+    key.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+    store.set(SIGNING_KEY, &key)?;
+    Ok(key)
+}
@@ -0,0 +1,24 @@
+// 确定性伪随机数 - 故障注入和链路劣化共用，相同种子得到相同的序列，便于复现
+/// 确定性的伪随机数发生器(xorshift32)
+#[derive(Debug, Clone)]
+pub(crate) struct XorShift(u32);
+
+impl XorShift {
+    pub(crate) fn new(seed: u32) -> Self {
+        XorShift(seed.max(1))
+    }
+
+    pub(crate) fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    /// 以 percent% 的概率返回true
+    pub(crate) fn chance(&mut self, percent: u32) -> bool {
+        self.next() % 100 < percent
+    }
+}
@@ -0,0 +1,141 @@
+// 分阶段指标 - 记录数据路径上每个阶段(USB读取、封装、流水线各阶段、发送)处理的字节数、处理耗时和排队等待时间，
+// 按统计窗口找出当前限制端到端速度的阶段，随实时指标一起推送
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// 从相机读取对象数据
+pub const STAGE_USB_READ: &str = "usb_read";
+/// 封装(multipart分段、摘要计算)
+pub const STAGE_FRAMING: &str = "framing";
+/// 交给发送器或写到连接上
+pub const STAGE_SEND: &str = "send";
+
+/// 参与瓶颈判断的最少样本数，样本太少时不下结论
+const MIN_SAMPLES: u64 = 4;
+
+#[derive(Debug, Clone, Default)]
+struct StageCounters {
+    packets: u64,
+    bytes: u64,
+    busy: Duration, // 阶段处理耗时
+    wait: Duration, // 数据在阶段前排队等待的时间
+}
+
+/// 单个阶段在统计窗口内的指标，字段名尽量短以减小指标帧长度
+#[derive(Debug, Clone, Serialize)]
+pub struct StageReport {
+    #[serde(rename = "n")]
+    pub name: String,
+    #[serde(rename = "p")]
+    pub packets: u64,
+    #[serde(rename = "b")]
+    pub bytes: u64,
+    #[serde(rename = "bps")]
+    pub throughput: u64, // 按窗口时长计算的实际吞吐量(字节/秒)
+    #[serde(rename = "cap")]
+    pub capacity: u64, // 按处理耗时计算的最大处理能力(字节/秒)
+    #[serde(rename = "util")]
+    pub utilization: u8, // 处理耗时占窗口时长的百分比
+    #[serde(rename = "wait")]
+    pub avg_wait_ms: u64, // 平均排队等待时间
+}
+
+/// 一个统计窗口的报告
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BottleneckReport {
+    #[serde(rename = "win")]
+    pub window_ms: u64,
+    #[serde(rename = "stg")]
+    pub stages: Vec<StageReport>,
+    #[serde(rename = "bn", skip_serializing_if = "Option::is_none")]
+    pub bottleneck: Option<String>, // 处理耗时最多的阶段，即处理能力最低、限制端到端速度的阶段
+}
+
+/// 分阶段指标，阶段按首次记录的顺序排列(即数据经过的顺序)
+pub struct StageMetrics {
+    stages: Vec<(String, StageCounters)>,
+    window_start: Instant,
+}
+
+pub type StageMetricsHandle = Arc<Mutex<StageMetrics>>;
+
+/// 创建共享的分阶段指标
+pub fn handle() -> StageMetricsHandle {
+    Arc::new(Mutex::new(StageMetrics::new()))
+}
+
+impl StageMetrics {
+    pub fn new() -> Self {
+        StageMetrics {
+            stages: Vec::new(),
+            window_start: Instant::now(),
+        }
+    }
+
+    /// 记录阶段处理的一份数据
+    pub fn record(&mut self, stage: &str, bytes: usize, busy: Duration, wait: Duration) {
+        let index = match self.stages.iter().position(|(name, _)| name == stage) {
+            Some(index) => index,
+            None => {
+                self.stages.push((stage.to_string(), StageCounters::default()));
+                self.stages.len() - 1
+            }
+        };
+        let counters = &mut self.stages[index].1;
+        counters.packets += 1;
+        counters.bytes += bytes as u64;
+        counters.busy += busy;
+        counters.wait += wait;
+    }
+
+    /// 当前窗口的报告
+    pub fn report(&self) -> BottleneckReport {
+        let window = self.window_start.elapsed();
+        let window_us = window.as_micros().max(1) as u64;
+        let stages: Vec<StageReport> = self
+            .stages
+            .iter()
+            .map(|(name, c)| {
+                let busy_us = c.busy.as_micros() as u64;
+                StageReport {
+                    name: name.clone(),
+                    packets: c.packets,
+                    bytes: c.bytes,
+                    throughput: c.bytes * 1_000_000 / window_us,
+                    capacity: c.bytes * 1_000_000 / busy_us.max(1),
+                    utilization: (busy_us * 100 / window_us).min(100) as u8,
+                    avg_wait_ms: c.wait.as_millis() as u64 / c.packets.max(1),
+                }
+            })
+            .collect();
+        let bottleneck = self
+            .stages
+            .iter()
+            .filter(|(_, c)| c.packets >= MIN_SAMPLES && !c.busy.is_zero())
+            .max_by_key(|(_, c)| c.busy)
+            .map(|(name, _)| name.clone());
+        BottleneckReport {
+            window_ms: window.as_millis() as u64,
+            stages,
+            bottleneck,
+        }
+    }
+
+    /// 返回当前窗口的报告并开始新窗口
+    pub fn take_report(&mut self) -> BottleneckReport {
+        let report = self.report();
+        for (_, counters) in &mut self.stages {
+            *counters = StageCounters::default();
+        }
+        self.window_start = Instant::now();
+        report
+    }
+}
+
+impl Default for StageMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
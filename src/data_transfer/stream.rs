@@ -0,0 +1,343 @@
+// 对象流式输出 - 把待同步对象逐个写成multipart流(清单条目 + 对象内容)，数据从相机直接写到连接上，不经过SD卡
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+
+use super::ledger::{LedgerEntry, ObjectLedger};
+use super::stage_metrics::{StageMetricsHandle, STAGE_FRAMING, STAGE_SEND, STAGE_USB_READ};
+use super::pipeline::PipelineHandle;
+use crate::ptp_mtp::{read_shared_object, DataPacket, PacketType, SharedCamera};
+
+/// multipart分隔符，不会出现在JSON清单中；对象内容按Content-Length或分块长度读取，与分隔符无关
+pub const MULTIPART_BOUNDARY: &str = "rcamera-object-boundary";
+
+/// 流水线改变数据长度时对象内容部分带这个头，内容为若干块(4字节小端长度 + 数据)，以长度为0的块结束
+pub const CHUNKED_HEADER: &str = "X-Rcamera-Chunked: u32le";
+
+/// 流式响应的Content-Type
+pub fn content_type() -> String {
+    format!("multipart/mixed; boundary={}", MULTIPART_BOUNDARY)
+}
+
+/// 接收对象数据块的回调
+pub type ChunkSink<'a> = dyn FnMut(&[u8]) -> Result<(), Box<dyn Error>> + 'a;
+
+/// 对象内容的来源，按块把数据交给sink
+pub trait ObjectSource {
+    /// 读取大小为`size`的对象，返回读取的字节数；sink返回错误时中止读取
+    fn read_object(
+        &mut self,
+        handle: u32,
+        size: u64,
+        sink: &mut ChunkSink<'_>,
+    ) -> Result<u64, Box<dyn Error>>;
+}
+
+/// 从共用的相机分块读取对象，每块只短暂锁住相机，输出期间主循环和其他服务仍可访问相机
+pub struct CameraObjectSource {
+    camera: SharedCamera,
+    timeout: Option<Duration>,
+}
+
+impl CameraObjectSource {
+    pub fn new(camera: SharedCamera, timeout: Option<Duration>) -> Self {
+        CameraObjectSource { camera, timeout }
+    }
+}
+
+impl ObjectSource for CameraObjectSource {
+    fn read_object(
+        &mut self,
+        handle: u32,
+        size: u64,
+        sink: &mut ChunkSink<'_>,
+    ) -> Result<u64, Box<dyn Error>> {
+        read_shared_object(&self.camera, handle, 0, size, self.timeout, sink)
+    }
+}
+
+/// 某客户端尚未确认接收的对象；指定相册时只包含带该标签的对象
+pub fn pending_for_client(ledger: &ObjectLedger, client_id: &str, album: Option<&str>) -> Vec<LedgerEntry> {
+    let delivered: Vec<u32> = ledger.receipts_for_client(client_id).iter().map(|r| r.handle).collect();
+    let entries = match album {
+        Some(tag) => ledger.album(tag),
+        None => ledger.entries(),
+    };
+    entries.into_iter().filter(|e| !delivered.contains(&e.handle)).collect()
+}
+
+/// 单个对象的输出结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartOutcome {
+    Verified, // 内容完整且摘要与台账一致
+    Mismatch, // 已输出，但大小或摘要与台账不一致
+    Skipped,  // 流水线丢弃了该对象，没有输出
+}
+
+/// 流式输出结果
+#[derive(Debug, Clone, Default)]
+pub struct StreamReport {
+    pub objects: usize,    // 已输出的对象数
+    pub mismatched: usize, // 大小或摘要与台账不一致的对象数
+    pub skipped: usize,    // 被流水线丢弃的对象数
+    pub bytes: u64,        // 输出的对象字节数
+}
+
+/// multipart流写入器，`W`把字节写到连接上
+pub struct MultipartWriter<W>
+where
+    W: FnMut(&[u8]) -> Result<(), Box<dyn Error>>,
+{
+    write: W,
+    report: StreamReport,
+    metrics: Option<StageMetricsHandle>,
+    pipeline: Option<PipelineHandle>,
+}
+
+impl<W> MultipartWriter<W>
+where
+    W: FnMut(&[u8]) -> Result<(), Box<dyn Error>>,
+{
+    pub fn new(write: W) -> Self {
+        MultipartWriter {
+            write,
+            report: StreamReport::default(),
+            metrics: None,
+            pipeline: None,
+        }
+    }
+
+    /// 记录USB读取、封装和写连接三个阶段的指标
+    pub fn with_metrics(mut self, metrics: StageMetricsHandle) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// 对象数据块按图像数据包依次经过流水线再输出，与推送给客户端的数据走同一数据路径
+    pub fn with_pipeline(mut self, pipeline: PipelineHandle) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    fn part_header(&mut self, content_type: &str, length: Option<u64>, extra: &str) -> Result<(), Box<dyn Error>> {
+        let length = match length {
+            Some(length) => format!("Content-Length: {}", length),
+            None => CHUNKED_HEADER.to_string(),
+        };
+        let header = format!(
+            "--{}\r\nContent-Type: {}\r\n{}\r\n{}\r\n",
+            MULTIPART_BOUNDARY, content_type, length, extra
+        );
+        (self.write)(header.as_bytes())
+    }
+
+    /// 输出一个对象：先是清单条目(JSON)，再是对象内容
+    /// 连接写入失败时返回错误；对象内容与台账不一致时仍会输出，由客户端按清单中的摘要判断。
+    /// 流水线丢弃第一块数据时整个对象不输出；已开始输出后再丢弃时返回错误。
+    /// 流水线含压缩、加密等改变长度的阶段时，对象内容按块输出(见`CHUNKED_HEADER`)；
+    /// 其他阶段改变了数据长度时返回错误，因为已声明的Content-Length无法再更改
+    pub fn write_object(&mut self, entry: &LedgerEntry, source: &mut dyn ObjectSource) -> Result<PartOutcome, Box<dyn Error>> {
+        let manifest = serde_json::to_vec(entry)?;
+        let disposition = format!("Content-Disposition: attachment; filename=\"{}\"\r\n", entry.name.replace('"', "_"));
+
+        let mut hasher = Sha256::new();
+        let mut written = 0u64;
+        let mut started = false;
+        let mut skipped = false;
+        let metrics = self.metrics.clone();
+        let pipeline = self.pipeline.clone();
+        let chunked = pipeline.as_ref().is_some_and(|p| !p.lock().unwrap().preserves_length());
+        // 两次回调之间的时间即为从相机读取一块数据的时间
+        let mut read_started = Instant::now();
+        let result = source.read_object(entry.handle, entry.size, &mut |chunk| {
+            let framing_started = Instant::now();
+            // 不能超过已声明的Content-Length，否则客户端会错位解析后续部分
+            let room = entry.size.saturating_sub(written) as usize;
+            let chunk = &chunk[..chunk.len().min(room)];
+            hasher.update(chunk);
+            let processed = match &pipeline {
+                Some(pipeline) => match pipeline.lock().unwrap().run(DataPacket::new(PacketType::Image, chunk.to_vec()))? {
+                    Some((packet, _)) if chunked || packet.data.len() == chunk.len() => Some(packet.data),
+                    Some(_) => return Err(format!("流水线改变了对象 0x{:08x} 的数据长度，无法流式输出", entry.handle).into()),
+                    None if !started => {
+                        skipped = true;
+                        return Err("对象被流水线丢弃".into());
+                    }
+                    None => return Err(format!("流水线在输出中途丢弃了对象 0x{:08x} 的数据", entry.handle).into()),
+                },
+                None => None,
+            };
+            if !started {
+                started = true;
+                self.start_part(&manifest, (!chunked).then_some(entry.size), &disposition)?;
+            }
+            written += chunk.len() as u64;
+            let send_started = Instant::now();
+            let data = processed.as_deref().unwrap_or(chunk);
+            if chunked {
+                (self.write)(&(data.len() as u32).to_le_bytes())?;
+            }
+            let result = (self.write)(data);
+            if let Some(metrics) = &metrics {
+                let mut metrics = metrics.lock().unwrap();
+                metrics.record(STAGE_USB_READ, chunk.len(), framing_started - read_started, Duration::ZERO);
+                metrics.record(STAGE_FRAMING, chunk.len(), send_started - framing_started, Duration::ZERO);
+                metrics.record(STAGE_SEND, chunk.len(), send_started.elapsed(), Duration::ZERO);
+            }
+            read_started = Instant::now();
+            result
+        });
+        if skipped {
+            debug!("对象 0x{:08x} 被流水线丢弃", entry.handle);
+            self.report.skipped += 1;
+            return Ok(PartOutcome::Skipped);
+        }
+        result?;
+        if !started {
+            self.start_part(&manifest, (!chunked).then_some(entry.size), &disposition)?;
+        }
+        if written < entry.size {
+            return Err(format!("对象 0x{:08x} 读取不完整: {}/{} 字节", entry.handle, written, entry.size).into());
+        }
+        if chunked {
+            (self.write)(&0u32.to_le_bytes())?;
+        }
+        (self.write)(b"\r\n")?;
+
+        let hash: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+        self.report.objects += 1;
+        self.report.bytes += written;
+        if hash == entry.hash {
+            debug!("已输出对象 0x{:08x} ({} 字节)", entry.handle, written);
+            Ok(PartOutcome::Verified)
+        } else {
+            warn!("对象 0x{:08x} 的内容与台账摘要不一致", entry.handle);
+            self.report.mismatched += 1;
+            Ok(PartOutcome::Mismatch)
+        }
+    }
+
+    /// 输出清单条目和对象内容部分的头，`size`为None时对象内容按块输出
+    fn start_part(&mut self, manifest: &[u8], size: Option<u64>, disposition: &str) -> Result<(), Box<dyn Error>> {
+        self.part_header("application/json", Some(manifest.len() as u64), "")?;
+        (self.write)(manifest)?;
+        (self.write)(b"\r\n")?;
+        self.part_header("application/octet-stream", size, disposition)
+    }
+
+    /// 写入结束分隔符，返回统计结果
+    pub fn finish(mut self) -> Result<StreamReport, Box<dyn Error>> {
+        (self.write)(format!("--{}--\r\n", MULTIPART_BOUNDARY).as_bytes())?;
+        Ok(self.report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_transfer::ledger::hash_object;
+    use crate::data_transfer::{PipelineBuilder, StageSpec};
+    use std::sync::{Arc, Mutex};
+
+    /// 按固定块大小输出内存中的对象
+    struct MemorySource(Vec<u8>);
+
+    impl ObjectSource for MemorySource {
+        fn read_object(&mut self, _handle: u32, _size: u64, sink: &mut ChunkSink<'_>) -> Result<u64, Box<dyn Error>> {
+            for chunk in self.0.chunks(4) {
+                sink(chunk)?;
+            }
+            Ok(self.0.len() as u64)
+        }
+    }
+
+    fn entry(data: &[u8]) -> LedgerEntry {
+        LedgerEntry {
+            handle: 1,
+            name: "IMG_0001.JPG".to_string(),
+            size: data.len() as u64,
+            hash: hash_object(data),
+            tags: Vec::new(),
+            spots: Vec::new(),
+            captured: None,
+        }
+    }
+
+    #[test]
+    fn pipeline_drop_skips_whole_object() {
+        let data = b"0123456789".to_vec();
+        let mut out = Vec::new();
+        let pipeline = PipelineBuilder::new()
+            .build(&[StageSpec::new("filter").param("types", "thumbnail")])
+            .unwrap();
+        let mut writer = MultipartWriter::new(|buf: &[u8]| {
+            out.extend_from_slice(buf);
+            Ok(())
+        })
+        .with_pipeline(Arc::new(Mutex::new(pipeline)));
+        let outcome = writer.write_object(&entry(&data), &mut MemorySource(data.clone())).unwrap();
+        assert_eq!(outcome, PartOutcome::Skipped);
+        let report = writer.finish().unwrap();
+        assert_eq!((report.objects, report.skipped), (0, 1));
+        assert_eq!(out, format!("--{}--\r\n", MULTIPART_BOUNDARY).into_bytes());
+    }
+
+    #[test]
+    fn pipeline_pass_through_verifies_object() {
+        let data = b"0123456789".to_vec();
+        let mut out = Vec::new();
+        let pipeline = PipelineBuilder::new().build(&[StageSpec::new("filter").param("types", "image")]).unwrap();
+        let mut writer = MultipartWriter::new(|buf: &[u8]| {
+            out.extend_from_slice(buf);
+            Ok(())
+        })
+        .with_pipeline(Arc::new(Mutex::new(pipeline)));
+        let outcome = writer.write_object(&entry(&data), &mut MemorySource(data.clone())).unwrap();
+        assert_eq!(outcome, PartOutcome::Verified);
+        writer.finish().unwrap();
+        assert!(out.windows(data.len()).any(|w| w == data.as_slice()));
+    }
+
+    #[test]
+    fn length_changing_stages_output_chunks() {
+        use chacha20poly1305::aead::{Aead, KeyInit};
+        use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+        let data = b"0123456789".to_vec();
+        let key = "11".repeat(32);
+        let mut out = Vec::new();
+        let pipeline = PipelineBuilder::new()
+            .build(&[StageSpec::new("compress"), StageSpec::new("encrypt").param("key", &key)])
+            .unwrap();
+        let mut writer = MultipartWriter::new(|buf: &[u8]| {
+            out.extend_from_slice(buf);
+            Ok(())
+        })
+        .with_pipeline(Arc::new(Mutex::new(pipeline)));
+        let outcome = writer.write_object(&entry(&data), &mut MemorySource(data.clone())).unwrap();
+        assert_eq!(outcome, PartOutcome::Verified);
+        writer.finish().unwrap();
+
+        let marker = format!("{}\r\n", CHUNKED_HEADER).into_bytes();
+        let header_end = out.windows(marker.len()).position(|w| w == marker.as_slice()).unwrap() + marker.len();
+        let body_start = header_end + out[header_end..].windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let cipher = ChaCha20Poly1305::new_from_slice(&[0x11; 32]).unwrap();
+        let mut body = &out[body_start..];
+        let mut restored = Vec::new();
+        loop {
+            let len = u32::from_le_bytes(body[..4].try_into().unwrap()) as usize;
+            body = &body[4..];
+            if len == 0 {
+                break;
+            }
+            let (nonce, sealed) = body[..len].split_at(12);
+            let compressed = cipher.decrypt(Nonce::from_slice(nonce), sealed).unwrap();
+            restored.extend(miniz_oxide::inflate::decompress_to_vec(&compressed).unwrap());
+            body = &body[len..];
+        }
+        assert_eq!(restored, data);
+        assert!(body.starts_with(b"\r\n--"));
+    }
+}
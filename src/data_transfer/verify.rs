@@ -0,0 +1,139 @@
+// 完整性复检 - 链路空闲时随机挑选最近交付的对象，从相机重新读取台账中记录的抽样区间并比对摘要，
+// 摘要不符说明传输时数据被线缆或电磁干扰损坏；损坏比例上升时发出事件，赶在用户导入时发现坏文件之前提醒
+use std::collections::VecDeque;
+use std::error::Error;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use super::ledger::{hash_object, ObjectLedger};
+use crate::events::AppEvent;
+use crate::ptp_mtp::PtpCamera;
+
+/// 默认只复检最近交付的这么多个对象
+pub const DEFAULT_RECENT: usize = 32;
+/// 统计损坏比例的滑动窗口(最近的复检次数)
+const WINDOW: usize = 32;
+/// 窗口内损坏次数达到此值时视为损坏趋势
+const TREND_THRESHOLD: usize = 2;
+
+/// 一次复检的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    Match { handle: u32 },
+    Mismatch { handle: u32, offset: u32 },
+    Skipped { handle: u32 }, // 对象已被删除或替换、或台账条目没有抽样区间
+}
+
+/// 复检统计，随实时指标推送，字段名尽量短
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct IntegrityStats {
+    #[serde(rename = "n")]
+    pub checked: u64, // 累计复检次数(不含跳过)
+    #[serde(rename = "bad")]
+    pub mismatches: u64, // 累计摘要不符次数
+    #[serde(rename = "win")]
+    pub recent_mismatches: u32, // 滑动窗口内的摘要不符次数
+}
+
+/// 后台完整性复检任务
+pub struct IntegrityVerifier {
+    recent: usize,
+    timeout: Option<Duration>,
+    window: VecDeque<bool>, // 最近的复检结果，true表示摘要不符
+    stats: IntegrityStats,
+    degraded: bool,
+}
+
+impl IntegrityVerifier {
+    pub fn new(recent: usize, timeout: Option<Duration>) -> Self {
+        IntegrityVerifier {
+            recent: recent.max(1),
+            timeout,
+            window: VecDeque::with_capacity(WINDOW),
+            stats: IntegrityStats::default(),
+            degraded: false,
+        }
+    }
+
+    /// 复检统计
+    pub fn stats(&self) -> IntegrityStats {
+        self.stats
+    }
+
+    /// 链路空闲时(通常取`DownloadArbiter::is_idle`)复检一个区间；不空闲或台账为空时返回None
+    /// 每次只读一个小区间，优先级低于所有客户端请求
+    pub async fn step(
+        &mut self,
+        camera: &mut PtpCamera,
+        ledger: &ObjectLedger,
+        idle: bool,
+    ) -> Result<Option<VerifyOutcome>, Box<dyn Error>> {
+        let handles = ledger.handles();
+        if !idle || handles.is_empty() {
+            return Ok(None);
+        }
+        let random = uuid::Uuid::new_v4().as_u128();
+        let recent = &handles[handles.len().saturating_sub(self.recent)..];
+        let handle = recent[random as usize % recent.len()];
+        let Some(entry) = ledger.get(handle)? else {
+            return Ok(None);
+        };
+        if entry.spots.is_empty() {
+            return Ok(Some(VerifyOutcome::Skipped { handle }));
+        }
+
+        // 句柄可能已被删除或重新分配，文件名和大小都一致才比对
+        let info = match camera.get_objectinfo(handle, self.timeout).await {
+            Ok(info) => info,
+            Err(e) => {
+                debug!("对象 0x{:08x} 无法读取，跳过复检: {}", handle, e);
+                return Ok(Some(VerifyOutcome::Skipped { handle }));
+            }
+        };
+        if info.Filename != entry.name || info.ObjectCompressedSize as u64 != entry.size {
+            return Ok(Some(VerifyOutcome::Skipped { handle }));
+        }
+
+        let spot = &entry.spots[(random >> 64) as usize % entry.spots.len()];
+        let data = camera.get_partialobject(handle, spot.offset, spot.len, self.timeout).await?;
+        let corrupt = data.len() != spot.len as usize || !hash_object(&data).starts_with(&spot.hash);
+        self.record(corrupt);
+        if corrupt {
+            warn!("对象 {} 在偏移 {} 处的摘要与台账不符", entry.name, spot.offset);
+            Ok(Some(VerifyOutcome::Mismatch { handle, offset: spot.offset }))
+        } else {
+            debug!("对象 {} 偏移 {} 复检通过", entry.name, spot.offset);
+            Ok(Some(VerifyOutcome::Match { handle }))
+        }
+    }
+
+    fn record(&mut self, corrupt: bool) {
+        if self.window.len() == WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(corrupt);
+        self.stats.checked += 1;
+        self.stats.mismatches += corrupt as u64;
+        self.stats.recent_mismatches = self.window.iter().filter(|c| **c).count() as u32;
+    }
+
+    /// 损坏趋势的变化：窗口内损坏次数达到阈值时返回一次事件，回落到0后才会再次告警
+    pub fn trend_event(&mut self) -> Option<AppEvent> {
+        let recent = self.stats.recent_mismatches as usize;
+        if !self.degraded && recent >= TREND_THRESHOLD {
+            self.degraded = true;
+            warn!("最近 {} 次复检中 {} 次摘要不符，请检查USB线缆和周边干扰", self.window.len(), recent);
+            return Some(AppEvent::IntegrityDegraded {
+                checked: self.window.len() as u32,
+                mismatches: recent as u32,
+            });
+        }
+        if self.degraded && recent == 0 {
+            self.degraded = false;
+            info!("复检已恢复正常");
+        }
+        None
+    }
+}
@@ -0,0 +1,146 @@
+// 应用事件模块 - 各子系统发布的事件，由已注册的监听器（推送、Webhook等）处理
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use log::{debug, warn};
+use serde::Serialize;
+
+/// 应用事件
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AppEvent {
+    /// 对象已完整发送给客户端
+    TransferComplete {
+        handle: u32,
+        bytes: u64,
+        client_id: String,
+    },
+    /// 传输出错
+    TransferError { message: String },
+    /// 内存不足，已暂停实时取景并缩小传输分块
+    LoadShed {
+        free_bytes: u64,
+        live_view_paused: bool,
+        chunk_size: u32,
+    },
+    /// 内存恢复，已恢复实时取景和传输分块
+    LoadRestored {
+        free_bytes: u64,
+        live_view_resumed: bool,
+        chunk_size: u32,
+    },
+    /// 工作模式已切换
+    ModeChanged { mode: String, previous: String },
+    /// 相机存储剩余空间低于阈值，或相机返回了StoreFull(此时不知道是哪张卡)
+    StorageLow {
+        storage_id: Option<u32>,
+        free_bytes: Option<u64>,
+        free_images: Option<u32>,
+        store_full: bool,
+    },
+    /// 后台复检发现最近交付的对象中有多次摘要不符，可能是线缆或电磁干扰导致的数据损坏
+    IntegrityDegraded { checked: u32, mismatches: u32 },
+    /// 传输配额已用完，数据暂停发送直到窗口内较早的流量过期
+    QuotaExhausted {
+        limit_bytes: u64,
+        window_secs: u64,
+        resets_in_secs: u64,
+    },
+    /// 传输配额已恢复，积压的数据继续发送
+    QuotaRestored { used_bytes: u64, limit_bytes: u64 },
+    /// 链路质量变化，发送级别已切换(full/preview/metadata)
+    LinkTierChanged {
+        tier: String,
+        previous: String,
+        throughput_bps: u64,
+        error_percent: u8,
+    },
+    /// 网络连接断开，传输已暂停，正在自动重连
+    ConnectionLost { link: String },
+    /// 网络连接已恢复，传输继续
+    ConnectionRestored {
+        link: String,
+        attempts: u32,
+        downtime_secs: u64,
+    },
+    /// 批量数据改走另一条链路(wifi/ble)，蓝牙控制通道保持不变
+    TransportChanged { bulk: String, previous: String },
+}
+
+impl AppEvent {
+    /// 事件名称，与JSON中的 `event` 字段一致
+    pub fn name(&self) -> &'static str {
+        match self {
+            AppEvent::TransferComplete { .. } => "transfer_complete",
+            AppEvent::TransferError { .. } => "transfer_error",
+            AppEvent::LoadShed { .. } => "load_shed",
+            AppEvent::LoadRestored { .. } => "load_restored",
+            AppEvent::ModeChanged { .. } => "mode_changed",
+            AppEvent::StorageLow { .. } => "storage_low",
+            AppEvent::IntegrityDegraded { .. } => "integrity_degraded",
+            AppEvent::QuotaExhausted { .. } => "quota_exhausted",
+            AppEvent::QuotaRestored { .. } => "quota_restored",
+            AppEvent::LinkTierChanged { .. } => "link_tier_changed",
+            AppEvent::ConnectionLost { .. } => "connection_lost",
+            AppEvent::ConnectionRestored { .. } => "connection_restored",
+            AppEvent::TransportChanged { .. } => "transport_changed",
+        }
+    }
+}
+
+/// 事件监听器特性
+pub trait EventListener: Send {
+    /// 处理事件
+    fn on_event(&mut self, event: &AppEvent);
+}
+
+/// 把事件转发到通道，由持有接收端的线程(例如主循环)再发给已连接的客户端
+pub struct ChannelListener {
+    tx: Sender<AppEvent>,
+}
+
+impl EventListener for ChannelListener {
+    fn on_event(&mut self, event: &AppEvent) {
+        if self.tx.send(event.clone()).is_err() {
+            warn!("事件接收端已关闭，丢弃事件 {}", event.name());
+        }
+    }
+}
+
+/// 创建转发监听器和对应的接收端
+pub fn channel() -> (ChannelListener, Receiver<AppEvent>) {
+    let (tx, rx) = mpsc::channel();
+    (ChannelListener { tx }, rx)
+}
+
+/// 事件总线 - 将事件分发给所有监听器
+pub struct EventBus {
+    listeners: Vec<Box<dyn EventListener>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    /// 创建新的事件总线
+    pub fn new() -> Self {
+        EventBus {
+            listeners: Vec::new(),
+        }
+    }
+
+    /// 添加事件监听器
+    pub fn subscribe(&mut self, listener: Box<dyn EventListener>) {
+        self.listeners.push(listener);
+    }
+
+    /// 发布事件
+    pub fn publish(&mut self, event: AppEvent) {
+        debug!("发布事件: {}", event.name());
+        for listener in &mut self.listeners {
+            listener.on_event(&event);
+        }
+    }
+}
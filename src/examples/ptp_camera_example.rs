@@ -6,13 +6,12 @@ use log::{info, debug, error, warn};
 
 use esp_idf_hal::delay::FreeRtos;
 use esp_idf_svc::sys;
-use embassy_executor::Executor;
-use embassy_executor::_export::StaticCell;
 use embassy_time::Timer;
 
 use crate::usb_host::embassy::create_embassy_usb_host;
 use crate::ptp_mtp::adapter::PtpCameraAdapter;
 use crate::ptp_mtp::adapter::scan_and_list_ptp_devices;
+use crate::runtime;
 
 /// 主要相机示例任务
 /// 此任务演示了如何扫描、连接和控制PTP相机
@@ -165,13 +164,9 @@ async fn connect_to_camera(vid: u16, pid: u16) {
 pub fn run_ptp_camera_example() {
     info!("初始化PTP相机示例...");
     
-    // 创建静态Embassy执行器
-    static EXECUTOR: StaticCell<Executor> = StaticCell::new();
-    let executor = EXECUTOR.init(Executor::new());
-    
-    // 运行执行器并启动相机任务
+    // 在当前任务中运行Embassy执行器并启动相机任务
     info!("启动Embassy执行器...");
-    executor.run(|spawner| {
+    runtime::run_executor(|spawner| {
         // 生成相机任务
         if let Err(e) = spawner.spawn(camera_task()) {
             error!("无法启动相机任务: {:?}", e);
@@ -0,0 +1,164 @@
+// 用户可见消息目录 - 按错误码/状态码索引，支持多语言
+// 状态包和Web界面只携带消息码，由此模块按客户端语言渲染文本
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+use crate::data_transfer::TransferStatus;
+use crate::ptp_mtp::StandardResponseCode;
+
+/// 支持的语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[repr(u8)]
+pub enum Language {
+    ZhCn = 0, // 简体中文
+    En = 1,   // 英文
+}
+
+impl Language {
+    /// 从语言标签解析，例如 "zh-CN"、"en-US"
+    pub fn from_tag(tag: &str) -> Option<Language> {
+        let tag = tag.to_ascii_lowercase();
+        if tag.starts_with("zh") {
+            Some(Language::ZhCn)
+        } else if tag.starts_with("en") {
+            Some(Language::En)
+        } else {
+            None
+        }
+    }
+
+    /// 语言标签
+    pub fn tag(self) -> &'static str {
+        match self {
+            Language::ZhCn => "zh-CN",
+            Language::En => "en",
+        }
+    }
+
+    fn from_u8(v: u8) -> Language {
+        match v {
+            1 => Language::En,
+            _ => Language::ZhCn,
+        }
+    }
+}
+
+/// 消息码，数值会出现在状态包中
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u16)]
+pub enum MessageCode {
+    // 传输状态 0x01xx
+    TransferIdle = 0x0100,
+    TransferStarting = 0x0101,
+    TransferRunning = 0x0102,
+    TransferPaused = 0x0103,
+    TransferStopping = 0x0104,
+    TransferError = 0x0105,
+
+    // 相机相关 0x02xx
+    CameraConnected = 0x0200,
+    CameraDisconnected = 0x0201,
+    CameraNotFound = 0x0202,
+    CameraBusy = 0x0203,
+    StoreFull = 0x0204,
+    CameraError = 0x0205,
+
+    // 无线相关 0x03xx
+    WirelessConnected = 0x0300,
+    WirelessDisconnected = 0x0301,
+    NoSenderConfigured = 0x0302,
+
+    // 未分类
+    Unknown = 0xFFFF,
+}
+
+impl MessageCode {
+    /// 消息码数值
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// 将PTP响应码映射为用户可见消息
+    pub fn from_response(code: u16) -> MessageCode {
+        match code {
+            StandardResponseCode::DeviceBusy => MessageCode::CameraBusy,
+            StandardResponseCode::StoreFull => MessageCode::StoreFull,
+            _ => MessageCode::CameraError,
+        }
+    }
+}
+
+impl From<TransferStatus> for MessageCode {
+    fn from(status: TransferStatus) -> Self {
+        match status {
+            TransferStatus::Idle => MessageCode::TransferIdle,
+            TransferStatus::Starting => MessageCode::TransferStarting,
+            TransferStatus::Running => MessageCode::TransferRunning,
+            TransferStatus::Paused => MessageCode::TransferPaused,
+            TransferStatus::Stopping => MessageCode::TransferStopping,
+            TransferStatus::Error => MessageCode::TransferError,
+        }
+    }
+}
+
+/// 按指定语言返回消息文本
+pub fn message(code: MessageCode, lang: Language) -> &'static str {
+    use self::MessageCode::*;
+    match lang {
+        Language::ZhCn => match code {
+            TransferIdle => "空闲",
+            TransferStarting => "正在启动传输",
+            TransferRunning => "正在传输",
+            TransferPaused => "传输已暂停",
+            TransferStopping => "正在停止传输",
+            TransferError => "传输出错",
+            CameraConnected => "相机已连接",
+            CameraDisconnected => "相机已断开",
+            CameraNotFound => "未找到相机",
+            CameraBusy => "相机忙",
+            StoreFull => "存储卡已满",
+            CameraError => "相机错误",
+            WirelessConnected => "无线已连接",
+            WirelessDisconnected => "无线已断开",
+            NoSenderConfigured => "未设置数据发送器",
+            Unknown => "未知",
+        },
+        Language::En => match code {
+            TransferIdle => "Idle",
+            TransferStarting => "Starting transfer",
+            TransferRunning => "Transferring",
+            TransferPaused => "Transfer paused",
+            TransferStopping => "Stopping transfer",
+            TransferError => "Transfer error",
+            CameraConnected => "Camera connected",
+            CameraDisconnected => "Camera disconnected",
+            CameraNotFound => "Camera not found",
+            CameraBusy => "Camera busy",
+            StoreFull => "Memory card full",
+            CameraError => "Camera error",
+            WirelessConnected => "Wireless connected",
+            WirelessDisconnected => "Wireless disconnected",
+            NoSenderConfigured => "No data sender configured",
+            Unknown => "Unknown",
+        },
+    }
+}
+
+// 设备默认语言，由配置设置
+static DEFAULT_LANGUAGE: AtomicU8 = AtomicU8::new(Language::ZhCn as u8);
+
+/// 设置设备默认语言
+pub fn set_default_language(lang: Language) {
+    DEFAULT_LANGUAGE.store(lang as u8, Ordering::Relaxed);
+}
+
+/// 获取设备默认语言
+pub fn default_language() -> Language {
+    Language::from_u8(DEFAULT_LANGUAGE.load(Ordering::Relaxed))
+}
+
+/// 按设备默认语言返回消息文本
+pub fn tr(code: MessageCode) -> &'static str {
+    message(code, default_language())
+}
@@ -1,10 +1,18 @@
+// 顶层模块供固件入口使用；模块内部的传输层、GATT分片等实现细节为pub(crate)，下游项目应使用prelude
+#[cfg(target_os = "espidf")]
 pub mod camera_connection;
 pub mod ptp_mtp;
 pub mod wireless;
 pub mod data_transfer;
-
-// 重导出常用模块
-pub use camera_connection::*;
-pub use ptp_mtp::*;
-pub use wireless::*;
-pub use data_transfer::*;
+pub mod orchestrator;
+pub mod config;
+pub mod i18n;
+pub mod control;
+pub mod persist;
+pub mod console;
+pub mod events;
+pub mod runtime;
+pub mod prelude;
+// USB主机驱动只供PTP传输层内部使用
+#[cfg(target_os = "espidf")]
+mod usb_host;
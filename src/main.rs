@@ -21,65 +21,816 @@ fn main() {
 
 /// 主系统流程
 fn run_system() -> Result<(), Box<dyn std::error::Error>> {
-    use rcamera::camera_connection::CameraDevice;
-    use rcamera::ptp_mtp::{create_protocol_handler, ProtocolType, DataProcessor};
-    use rcamera::wireless::{WirelessManager, ConnectionType, ConnectionConfig};
-    use rcamera::data_transfer::TransferManager;
+    use rcamera::prelude::*;
+    use rcamera::ptp_mtp::DataListener;
+    use rcamera::orchestrator::memory::{MemoryMonitor, MemoryThresholds};
+    use rcamera::orchestrator::mode::ModeButton;
     
+    // 加载保存在NVS中的设备配置（语言等全局设置），没有保存过时使用默认配置
+    // NVS分区只能取得一次，之后交给无线驱动和其他存储共用
+    let nvs = esp_idf_svc::nvs::EspDefaultNvsPartition::take()?;
+    // 固件升级后先把各命名空间中的旧格式数据迁移到当前格式，再交给各模块读取
+    use rcamera::persist::migrate;
+    let config_store = rcamera::config::bundle::ConfigStore::open(Box::new(migrate::prepare(rcamera::persist::NvsStore::open(
+        nvs.clone(),
+        rcamera::config::bundle::NVS_NAMESPACE,
+    )?)?))?;
+    let config = config_store.config().clone();
+    config.apply();
+    // 导入的配置在重启后生效
+    let config_store = std::sync::Arc::new(std::sync::Mutex::new(config_store));
+    // 删除、格式化和配置修改记入审计日志
+    let audit = std::sync::Arc::new(std::sync::Mutex::new(rcamera::control::AuditLog::open(
+        Box::new(migrate::prepare(rcamera::persist::NvsStore::open(nvs.clone(), rcamera::control::audit::NVS_NAMESPACE)?)?),
+        rcamera::control::audit::DEFAULT_CAPACITY,
+    )?));
+    // 对象台账：交付回执和标签；写入按对象批量提交，重放上次未完成的提交后再迁移
+    let ledger_store = rcamera::persist::BatchedStore::open(Box::new(rcamera::persist::NvsStore::open(
+        nvs.clone(),
+        rcamera::data_transfer::ledger::NVS_NAMESPACE,
+    )?))?;
+    let ledger = std::sync::Arc::new(std::sync::Mutex::new(rcamera::data_transfer::ObjectLedger::open(Box::new(
+        migrate::prepare(ledger_store)?,
+    ))?));
+    
+    // 根据编译时启用的子系统决定启动流程
+    let mut orchestrator = Orchestrator::new();
+    let mut events = EventBus::new();
+    // 事件同时推送给已连接的客户端(蓝牙/WiFi)
+    let (client_events, client_events_rx) = rcamera::events::channel();
+    events.subscribe(Box::new(client_events));
+    // 订阅的事件另外POST给配置的Webhook地址
+    #[cfg(feature = "wifi")]
+    if !config.webhooks.is_empty() {
+        events.subscribe(Box::new(rcamera::wireless::webhook::WebhookNotifier::spawn(config.webhooks.clone())?));
+    }
+
     // 步骤1：连接相机
     log::info!("正在连接相机设备...");
     // 这里需要替换为实际相机的VID和PID
-    let mut camera = CameraDevice::new(0x04A9, 0x326F); // 示例: 佳能相机
-    camera.connect()?;
+    let camera = embassy_futures::block_on(rcamera::ptp_mtp::connect_camera(0x04A9, 0x326F, None))?; // 示例: 佳能相机
     
     // 步骤2：初始化PTP/MTP协议
     log::info!("正在初始化PTP协议...");
-    let camera_handle = camera.get_handle().ok_or("相机未连接")?;
-    let mut protocol = create_protocol_handler(ProtocolType::PTP, camera_handle);
+    let mut protocol = create_camera_protocol_handler(ProtocolType::PTP, camera);
+    // 校准、FTP、云端上传、会话保活和后台维护都与协议处理器共用同一个相机
+    let shared_camera = protocol.shared_camera().ok_or("协议处理器没有可共用的相机")?;
+    // PTP事务追踪默认不记录，由控制台命令开启
+    let tracer = rcamera::ptp_mtp::trace::handle(rcamera::ptp_mtp::trace::DEFAULT_TRACE_DEPTH, rcamera::ptp_mtp::trace::DEFAULT_TRACE_PAYLOAD);
+    protocol.set_tracer(Some(tracer.clone()));
     protocol.init_session()?;
     
     // 获取相机信息
     let device_info = protocol.get_device_info()?;
     log::info!("已连接的相机: {} {}", device_info.manufacturer, device_info.model);
+    let capabilities = protocol.capabilities()?;
+    log::info!("相机能力: {:?}", capabilities);
+    let body = config.body_for(&device_info.serial_number);
+    // 事务超时：机身设置中有校准结果时直接使用，其次是按型号配置的超时，都没有时用几次探测推算超时，结果保存到机身设置中
+    {
+        let mut camera = shared_camera.lock().unwrap();
+        // 机身设置中的兼容性处理合并到按VID/PID查到的结果中
+        if let Some(body) = body.filter(|b| !b.quirks.is_empty()) {
+            let quirks = camera.ptp().quirks().with(&body.quirks);
+            camera.ptp().set_quirks(quirks);
+        }
+        match config.body_timeouts(&device_info.serial_number, &device_info.model) {
+            Some(timeouts) => camera.ptp().set_timeouts(timeouts),
+            None => match embassy_futures::block_on(rcamera::ptp_mtp::calibrate::calibrate(camera.ptp())) {
+                Ok(calibration) => {
+                    calibration.apply(camera.ptp());
+                    let mut updated = config.clone();
+                    updated.record_calibration(&device_info.serial_number, calibration);
+                    if let Err(e) = config_store.lock().unwrap().save(updated) {
+                        log::warn!("保存链路校准结果失败: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("链路校准失败，事务不设超时: {}", e),
+            },
+        }
+    }
     
     // 步骤3：设置无线连接
-    log::info!("正在初始化WiFi...");
-    let mut wireless = WirelessManager::new(ConnectionType::WiFi);
+    let conn_type = orchestrator.select_connection(&config.network).ok_or("固件未启用任何无线子系统")?;
+    log::info!("正在初始化无线连接: {:?}", conn_type);
+    let mut wireless = WirelessManager::new(conn_type);
+    #[cfg(any(feature = "wifi", feature = "ble"))]
+    wireless.set_nvs_partition(nvs.clone());
+    // WiFi和蓝牙双通道：蓝牙一直保持控制通道，批量数据在WiFi可用时走WiFi
+    #[cfg(all(feature = "wifi", feature = "ble"))]
+    let dual = match config.dual_transport {
+        Some(dual) if conn_type == ConnectionType::WiFi => Some(dual),
+        Some(_) => {
+            log::warn!("双通道模式需要以WiFi作为主连接，当前为 {:?}，只使用一种连接", conn_type);
+            None
+        }
+        None => None,
+    };
+    #[cfg(all(feature = "wifi", feature = "ble"))]
+    if dual.is_some() {
+        wireless.initialize_dual()?;
+    } else {
+        wireless.initialize()?;
+    }
+    #[cfg(not(all(feature = "wifi", feature = "ble")))]
     wireless.initialize()?;
+    #[cfg(any(feature = "wifi", feature = "ethernet"))]
+    wireless.set_link_tls(config.link_tls.as_ref())?;
+    // 配网保存的WiFi凭证，与WiFi驱动共用NVS分区
+    #[cfg(feature = "http")]
+    let mut credentials = match wireless.nvs_partition() {
+        Some(partition) => {
+            let store = rcamera::persist::NvsStore::open(partition, rcamera::wireless::provision::NVS_NAMESPACE)?;
+            Some(rcamera::wireless::provision::CredentialStore::open(Box::new(store)))
+        }
+        None => None,
+    };
+    // SoftAP设置，AP模式和配网时使用
+    #[cfg(feature = "wifi")]
+    let ap = rcamera::wireless::SoftApSettings {
+        ssid: config.device_name.clone(),
+        password: config.ap_password.clone(),
+        max_clients: config.ap_max_clients,
+        address: config.ap_address.clone(),
+    };
     
-    // 配置ESP32作为接入点
-    let wifi_config = ConnectionConfig::WiFi(
-        "ESP32Camera".into(), // SSID
-        "123456".into()  // 密码
-    );
-    wireless.connect(&wifi_config)?;
+    let wireless_config = match conn_type {
+        // 按配置的模式作为接入点、连接已有网络或两者同时
+        #[cfg(feature = "wifi")]
+        ConnectionType::WiFi => {
+            // 配网保存的网络优先于配置文件中的网络
+            #[cfg(feature = "http")]
+            let upstream = credentials.as_ref().and_then(|c| c.load()).or_else(|| config.upstream_wifi.clone());
+            #[cfg(not(feature = "http"))]
+            let upstream = config.upstream_wifi.clone();
+            match (config.wifi_mode, upstream) {
+                (rcamera::wireless::WirelessMode::Sta, Some(up)) => ConnectionConfig::WiFi(up),
+                (rcamera::wireless::WirelessMode::ApSta, Some(up)) => ConnectionConfig::ApSta {
+                    ap: ap.clone(),
+                    upstream: up,
+                    bridge: config.ap_bridge,
+                },
+                (rcamera::wireless::WirelessMode::Ap, _) => ConnectionConfig::SoftAp { ap: ap.clone(), channel: config.wifi_channel },
+                // 没有可连接的网络时进入配网，完成后重启
+                #[cfg(feature = "http")]
+                (_, None) => {
+                    let credentials = credentials.as_mut().ok_or("NVS分区不可用，无法配网")?;
+                    match rcamera::wireless::provision::run(&mut wireless, ap, credentials)? {}
+                }
+                #[cfg(not(feature = "http"))]
+                (mode, None) => return Err(format!("WiFi模式 {:?} 需要配置要连接的网络", mode).into()),
+            }
+        }
+        #[cfg(feature = "ble")]
+        ConnectionType::Bluetooth => ConnectionConfig::Bluetooth(config.device_name.clone()),
+        #[cfg(feature = "ethernet")]
+        ConnectionType::Ethernet => match &config.network {
+            rcamera::config::NetworkInterface::Ethernet(eth) => ConnectionConfig::Ethernet(eth.clone()),
+            rcamera::config::NetworkInterface::Wireless => return Err("未配置以太网PHY".into()),
+        },
+    };
+    let connected = wireless.connect(wireless_config);
+    // STA模式下保存的网络连不上时进入配网，完成后重启
+    #[cfg(feature = "http")]
+    if conn_type == ConnectionType::WiFi && config.wifi_mode == rcamera::wireless::WirelessMode::Sta {
+        let up = match &connected {
+            Ok(()) => wireless.wait_sta_up(STA_CONNECT_TIMEOUT)?,
+            Err(e) => {
+                log::warn!("连接WiFi网络失败: {}", e);
+                false
+            }
+        };
+        if !up {
+            let credentials = credentials.as_mut().ok_or("NVS分区不可用，无法配网")?;
+            match rcamera::wireless::provision::run(&mut wireless, ap, credentials)? {}
+        }
+    }
+    connected?;
+    
+    // 在局域网内广播服务，手机应用不需要知道设备的IP
+    #[cfg(feature = "mdns")]
+    let _mdns = match rcamera::wireless::mdns::ServiceAdvertiser::start(&config.device_name, config.http_port, Some(&device_info.model)) {
+        Ok(advertiser) => Some(advertiser),
+        Err(e) => {
+            log::warn!("mDNS服务广播启动失败: {}", e);
+            None
+        }
+    };
+    
+    // 各控制通道共用的鉴权：配对得到的预共享令牌换取会话令牌后才能执行命令
+    let auth_guard = std::sync::Arc::new(std::sync::Mutex::new(rcamera::control::AuthGuard::new(Box::new(
+        rcamera::control::TokenAuthenticator::new(SESSION_TTL),
+    ))));
+    // 控制台生成配对二维码，手机通过蓝牙PAIR命令或HTTP /pair提交其中的令牌
+    let pairing = std::sync::Arc::new(std::sync::Mutex::new(rcamera::control::PairingManager::new(PAIRING_VALIDITY)));
+    
+    // 手机通过蓝牙发来的控制命令（如远程快门）由高优先级的调度任务排队，交给主循环执行
+    // 取消命令在调度任务中直接置位相机的取消令牌，不必等主循环轮到它，进行中的读取在下一块之前中止
+    let cancel = shared_camera.lock().unwrap().ptp().cancel_token();
+    let (command_tx, command_rx) = rcamera::control::dispatch::spawn(Some(cancel))?;
+    #[cfg(feature = "ble")]
+    if conn_type == ConnectionType::Bluetooth {
+        wireless.set_command_sink(command_tx.clone(), auth_guard.clone())?;
+        wireless.set_pairing(pairing.clone())?;
+    }
+    #[cfg(all(feature = "wifi", feature = "ble"))]
+    if dual.is_some() {
+        wireless.set_command_sink(command_tx.clone(), auth_guard.clone())?;
+        wireless.set_pairing(pairing.clone())?;
+        wireless.start_ble_control(&config.device_name)?;
+    }
     
     // 步骤4：创建数据传输管理器
     log::info!("正在初始化数据传输...");
     let mut transfer = TransferManager::new(10); // 缓冲区最多10个数据包
+    transfer.apply_body(body);
+    // 链路劣化注入始终安装，未配置时不改变发送行为，可以在控制台开启
+    let impairment = rcamera::data_transfer::impair::handle(config.impairment.unwrap_or_default());
+    transfer.set_impairment(impairment.clone());
+    #[cfg(feature = "sd")]
+    let stream_capture = {
+        use rcamera::data_transfer::capture;
+        let stream_capture = capture::handle();
+        if config.capture_stream {
+            stream_capture.lock().unwrap().start_in(std::path::Path::new(capture::DEFAULT_CAPTURE_DIR))?;
+        }
+        transfer.set_capture(stream_capture.clone());
+        stream_capture
+    };
+    let quota_mb = match conn_type {
+        #[cfg(feature = "wifi")]
+        ConnectionType::WiFi => config.quotas.wifi_mb_per_hour,
+        #[cfg(feature = "ble")]
+        ConnectionType::Bluetooth => config.quotas.ble_mb_per_hour,
+        #[cfg(feature = "ethernet")]
+        ConnectionType::Ethernet => None,
+    };
+    transfer.set_quota(quota_mb.map(rcamera::data_transfer::TransferQuota::per_hour));
+    let stage_metrics = rcamera::data_transfer::stage_metrics::handle();
+    transfer.set_stage_metrics(stage_metrics.clone());
+    transfer.set_control_queue(command_rx.clone());
+    transfer.set_link_quality(config.link_tiers.map(rcamera::data_transfer::LinkQuality::new));
+    // 客户端确认送达(签发回执)的对象按配置删除、保护或移动相机上的原文件
+    transfer.set_post_transfer(config.post_transfer);
+    let (confirmed_tx, confirmed_rx) = std::sync::mpsc::channel();
+    ledger.lock().unwrap().set_confirmation_sink(confirmed_tx);
+    // 后台维护：链路空闲时刷新相册摘要、预取新对象的预览、复检已交付的对象，与主循环共用同一个相机
+    let summaries = rcamera::control::gallery::summary_handle();
+    let mut sync_cursor = rcamera::ptp_mtp::SyncCursor::open(Box::new(rcamera::persist::NvsStore::open(
+        nvs.clone(),
+        rcamera::ptp_mtp::sync_cursor::NVS_NAMESPACE,
+    )?))?;
+    let prefetcher = {
+        use rcamera::data_transfer::prefetch::{self, CacheLocation, IdlePrefetcher, PreviewCache};
+        #[cfg(feature = "sd")]
+        let cache = PreviewCache::open(CacheLocation::Dir(prefetch::DEFAULT_CACHE_DIR.into()), prefetch::DEFAULT_DIR_CACHE_BYTES)?;
+        #[cfg(not(feature = "sd"))]
+        let cache = PreviewCache::open(CacheLocation::Memory, prefetch::DEFAULT_MEMORY_CACHE_BYTES)?;
+        std::sync::Arc::new(std::sync::Mutex::new(IdlePrefetcher::new(cache, None)))
+    };
+    // 只为上次同步之后新拍的对象安排预取，不必枚举比对全部对象
+    {
+        let mut camera = shared_camera.lock().unwrap();
+        let fresh = embassy_futures::block_on(async {
+            let mut fresh = Vec::new();
+            for storage_id in camera.ptp().get_storageids(None).await? {
+                fresh.extend(sync_cursor.new_objects(camera.ptp(), storage_id, None).await?);
+            }
+            Ok::<_, rcamera::ptp_mtp::Error>(fresh)
+        });
+        match fresh {
+            Ok(fresh) => {
+                prefetcher.lock().unwrap().plan(&fresh, &ledger.lock().unwrap());
+            }
+            Err(e) => log::warn!("查找新对象失败: {}", e),
+        }
+    }
+    let mut verifier = rcamera::data_transfer::verify::IntegrityVerifier::new(rcamera::data_transfer::verify::DEFAULT_RECENT, None);
+    let mut next_verify = std::time::Instant::now() + VERIFY_INTERVAL;
+    // 取景画面偶尔丢帧可以接受，改走UDP避免TCP重传带来的延迟
+    #[cfg(any(feature = "wifi", feature = "ethernet"))]
+    if let Some(udp) = &config.live_view_udp {
+        let sender = rcamera::wireless::udp::UdpSender::connect(&udp.target, udp.rtp)?;
+        transfer.set_datagram_route(Box::new(sender), &[rcamera::ptp_mtp::PacketType::LiveView]);
+    }
+    // 多个手机可同时连接设备，各自按订阅接收取景、原图或事件
+    #[cfg(any(feature = "wifi", feature = "ethernet"))]
+    let tcp_server = match &config.tcp_server {
+        Some(server_config) => {
+            use rcamera::wireless::server;
+            let server = server::handle(server::TcpServer::bind(server_config, auth_guard.clone())?);
+            let sender = Box::new(server::ServerSender::new(server.clone()));
+            transfer.add_client(server::SERVER_CLIENT_ID, rcamera::data_transfer::ClientProfile::FullIngest, sender);
+            Some(server)
+        }
+        None => None,
+    };
+    // 桌面工具通过只读FTP拉取照片，与主循环共用同一个相机，服务端随返回值释放时停止
+    #[cfg(any(feature = "wifi", feature = "ethernet"))]
+    let _ftp_server = match &config.ftp {
+        Some(ftp_config) => {
+            use rcamera::wireless::ftp;
+            let fs = ftp::handle(ftp::CameraFs::new(shared_camera.clone(), None));
+            Some(ftp::FtpServer::start(ftp_config, fs)?)
+        }
+        None => None,
+    };
+    // 相机休眠唤醒后自动重开会话，链路空闲时定期保活
+    let keepalive = (config.session_keepalive_secs > 0).then(|| std::time::Duration::from_secs(config.session_keepalive_secs as u64));
+    rcamera::ptp_mtp::SessionGuard::new(shared_camera.clone(), keepalive).spawn()?;
+    // 拍摄完成的对象在后台直接上传到云端，与主循环共用同一个相机
+    #[cfg(feature = "wifi")]
+    let cloud_uploads = match &config.cloud_upload {
+        Some(upload_config) => {
+            use rcamera::data_transfer::cloud;
+            let store = rcamera::persist::NvsStore::open(nvs.clone(), cloud::NVS_NAMESPACE)?;
+            let uploader = cloud::CloudUploader::open(upload_config, config.cloud.as_ref(), Box::new(store), None)?;
+            Some(uploader.spawn(shared_camera.clone())?)
+        }
+        None => None,
+    };
+    // 多台ESP32之间经ESP-NOW中继：节点把数据包发给网关，网关和开启中继的节点把收到的数据包当作本机数据发送
+    #[cfg(feature = "espnow")]
+    let espnow = match &config.espnow {
+        Some(espnow_config) if conn_type == ConnectionType::WiFi => {
+            use rcamera::wireless::espnow;
+            let softap = config.wifi_mode == rcamera::wireless::WirelessMode::Ap;
+            let mut link = espnow::EspNowLink::start(espnow_config, softap)?;
+            if let rcamera::config::EspNowRole::Node { gateway } = &espnow_config.role {
+                let retries = espnow_config.retries.unwrap_or(espnow::DEFAULT_RETRIES);
+                let sender = link.sender(rcamera::config::parse_mac(gateway)?, retries)?;
+                transfer.add_client(espnow::ESPNOW_CLIENT_ID, rcamera::data_transfer::ClientProfile::FullIngest, Box::new(sender));
+            }
+            Some(link)
+        }
+        Some(_) => return Err("ESP-NOW中继需要使用WiFi连接".into()),
+        None => None,
+    };
+    // 蓝牙带宽有限，手机只接收状态，对象按请求单独下载
+    #[cfg(feature = "ble")]
+    if conn_type == ConnectionType::Bluetooth {
+        let sender = wireless.create_sender(&ConnectionConfig::Bluetooth(config.device_name.clone()))?;
+        transfer.add_client(rcamera::control::BLE_CLIENT_ID, rcamera::data_transfer::ClientProfile::MonitorOnly, sender);
+    }
+    // 双通道模式下蓝牙客户端的类型随WiFi状态变化：WiFi可用时只收状态，断开时改收缩略图和元数据
+    #[cfg(all(feature = "wifi", feature = "ble"))]
+    let mut transport = match dual {
+        Some(dual) => {
+            use rcamera::orchestrator::transport::TransportPolicy;
+            let settle = std::time::Duration::from_secs(dual.settle_secs as u64);
+            let policy = TransportPolicy::new(settle, wireless.wifi_link_up(), std::time::Instant::now());
+            let sender = wireless.create_ble_sender(&config.device_name)?;
+            transfer.add_client(rcamera::control::BLE_CLIENT_ID, policy.ble_profile(), Box::new(sender));
+            Some(policy)
+        }
+        None => None,
+    };
+    
+    // 局域网HTTP接口：/login、/status 和 /handshake，运行时状态由主循环定期更新
+    #[cfg(feature = "http")]
+    let runtime_status = std::sync::Arc::new(std::sync::Mutex::new(rcamera::wireless::http::RuntimeStatus::default()));
+    #[cfg(feature = "http")]
+    let metrics_sample = std::sync::Arc::new(std::sync::Mutex::new(rcamera::wireless::metrics::MetricsSample::default()));
+    #[cfg(feature = "http")]
+    let (http_api, push) = if conn_type.carries_ip() {
+        use rcamera::wireless::http::{HttpApi, StatusProvider};
+        // TCP服务端是明文的，启用时不能向客户端声明链路已加密
+        let link = rcamera::control::LinkCapabilities {
+            compression: false,
+            encryption: config.link_tls.is_some() && config.tcp_server.is_none(),
+        };
+        let status = runtime_status.clone();
+        let provider: StatusProvider = std::sync::Arc::new(move || status.lock().unwrap().clone());
+        let mut api = HttpApi::start(config.http_port, provider, link, auth_guard.clone())?;
+        api.serve_config(config_store.clone(), auth_guard.clone(), audit.clone())?;
+        api.serve_pairing(pairing.clone(), auth_guard.clone())?;
+        // 有线局域网导入：对象直接从相机分块输出，与推送给客户端的数据经过同一流水线
+        api.set_stage_metrics(stage_metrics.clone());
+        api.serve_sync_stream(ledger.clone(), shared_camera.clone(), transfer.pipeline(), auth_guard.clone())?;
+        api.serve_gallery(ledger.clone(), Some(summaries.clone()), None, auth_guard.clone())?;
+        api.serve_previews(prefetcher.clone(), auth_guard.clone())?;
+        api.serve_ptp_trace(tracer.clone(), auth_guard.clone())?;
+        // 实时指标：主循环更新的采样加上推送时读取的信号强度和分阶段指标
+        let sample = metrics_sample.clone();
+        let metrics_stages = stage_metrics.clone();
+        let provider: rcamera::wireless::metrics::MetricsProvider = std::sync::Arc::new(move || {
+            let mut sample = sample.lock().unwrap().clone();
+            sample.stages = Some(metrics_stages.lock().unwrap().take_report());
+            #[cfg(feature = "wifi")]
+            {
+                sample.rssi = rcamera::wireless::metrics::wifi_rssi();
+                sample.channel = rcamera::wireless::channel::current_channel();
+            }
+            sample
+        });
+        api.serve_metrics(provider, METRICS_INTERVAL, auth_guard.clone())?;
+        // 取景画面、下载进度和传输事件经 /ws/push 推送给手机应用
+        let push = api.serve_push(auth_guard.clone())?;
+        events.subscribe(Box::new(push.clone()));
+        let progress = push.clone();
+        protocol.set_progress_listener(Some(Box::new(move |p| progress.publish_progress(&p))));
+        (Some(api), Some(push))
+    } else {
+        (None, None)
+    };
     
-    // 开始数据流传输
-    log::info!("正在启动相机实时数据流...");
-    protocol.start_live_stream()?;
+    // 按启动模式组装流水线并启动实时取景
+    let mut live_view_running = false;
+    switch_mode(config.mode, &config, &mut orchestrator, &mut events, &mut transfer, protocol.as_mut(), &mut live_view_running)
+        .map_err(|e| format!("应用 {} 模式失败: {}", config.mode.name(), e))?;
+    let mut mode_button = config.mode_button.map(ModeButton::new).transpose()?;
     
     // 开始数据传输
     transfer.start()?;
     log::info!("已开始边拍边传...");
     
-    // 这里应该添加主循环逻辑，例如等待用户输入或事件
-    // 在实际应用中，可能需要一个事件循环或任务调度器
+    // 主循环：执行控制命令并监视内存，运行一段时间后退出
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+    let memory_poll = std::time::Duration::from_secs(1);
+    let mut memory = MemoryMonitor::new(MemoryThresholds::default());
+    let mut storage = rcamera::ptp_mtp::StorageMonitor::new(config.storage_alert);
+    // 按需下载不经过自动同步的队列，但仍由仲裁器分配下载名额
+    let mut arbiter = rcamera::data_transfer::DownloadArbiter::new(DOWNLOAD_QUEUE_PER_CLIENT);
+    let frame_interval = std::time::Duration::from_millis(1000 / config.live_view_fps.max(1) as u64);
+    let mut next_frame = std::time::Instant::now();
+    let mut commands_open = true;
+    #[cfg(feature = "wifi")]
+    let mut paused_for_link = false;
     
-    // 示例：睡眠一段时间模拟系统运行
-    std::thread::sleep(std::time::Duration::from_secs(60));
+    // 串口调试控制台在独立任务中读取命令
+    let mut console = rcamera::console::Console::new();
+    rcamera::data_transfer::impair::register_console_command(&mut console, impairment);
+    rcamera::data_transfer::ledger::register_console_command(&mut console, ledger.clone());
+    rcamera::data_transfer::ledger::register_tag_console_command(&mut console, ledger.clone());
+    #[cfg(feature = "sd")]
+    rcamera::data_transfer::capture::register_console_command(&mut console, stream_capture);
+    rcamera::orchestrator::mode::register_console_command(&mut console, command_tx.clone());
+    rcamera::config::bundle::register_console_command(&mut console, config_store.clone(), audit.clone());
+    rcamera::control::audit::register_console_command(&mut console, audit.clone());
+    #[cfg(feature = "wifi")]
+    rcamera::control::pairing::register_console_command(&mut console, pairing, Some(ap.ssid.clone()), Some(ap.password.clone()).filter(|p| !p.is_empty()));
+    #[cfg(not(feature = "wifi"))]
+    rcamera::control::pairing::register_console_command(&mut console, pairing, None, None);
+    rcamera::ptp_mtp::trace::register_console_command(&mut console, tracer);
+    rcamera::runtime::spawn(rcamera::runtime::CONSOLE, move || console.run_blocking())?;
+    drop(command_tx);
+    while let Some(remaining) = deadline.checked_duration_since(std::time::Instant::now()) {
+        // 内存不足时先牺牲实时取景，压力解除后再恢复
+        if let Some(change) = memory.poll() {
+            let plan = orchestrator.on_memory_change(change, live_view_running, &mut events);
+            protocol.set_chunk_size(plan.chunk_size);
+            if plan.stop_live_view {
+                match protocol.stop_live_stream() {
+                    Ok(()) => live_view_running = false,
+                    Err(e) => log::error!("停止实时取景失败: {}", e),
+                }
+            }
+            if plan.resume_live_view {
+                match protocol.start_live_stream() {
+                    Ok(()) => live_view_running = true,
+                    Err(e) => log::error!("恢复实时取景失败: {}", e),
+                }
+            }
+        }
+        
+        // 存储剩余空间不足时提醒用户换卡
+        if storage.due() {
+            match protocol.storage_info() {
+                Ok(infos) => {
+                    for event in storage.update(&infos) {
+                        events.publish(event);
+                    }
+                }
+                Err(e) => log::warn!("读取存储信息失败: {}", e),
+            }
+        }
+        // 配额恢复后发送积压的数据
+        if let Some(event) = transfer.take_quota_event() {
+            events.publish(event);
+        }
+        // 链路质量变化时通知客户端当前的发送级别
+        if let Some(event) = transfer.take_link_event() {
+            events.publish(event);
+        }
+        // WiFi断开时暂停传输并自动重连，恢复后继续；双通道模式下不暂停，改由蓝牙传输缩略图和元数据
+        #[cfg(feature = "wifi")]
+        if let Some(event) = wireless.poll_link() {
+            #[cfg(all(feature = "wifi", feature = "ble"))]
+            let pause_on_loss = transport.is_none();
+            #[cfg(not(all(feature = "wifi", feature = "ble")))]
+            let pause_on_loss = true;
+            match &event {
+                rcamera::events::AppEvent::ConnectionLost { .. } if pause_on_loss => match transfer.pause() {
+                    Ok(()) => paused_for_link = true,
+                    Err(e) => log::warn!("暂停传输失败: {}", e),
+                },
+                rcamera::events::AppEvent::ConnectionRestored { .. } if paused_for_link => {
+                    paused_for_link = false;
+                    if let Err(e) = transfer.start() {
+                        log::error!("恢复传输失败: {}", e);
+                    }
+                }
+                _ => {}
+            }
+            events.publish(event);
+        }
+        #[cfg(all(feature = "wifi", feature = "ble"))]
+        if let Some(policy) = &mut transport {
+            if let Some(event) = policy.observe(wireless.wifi_link_up(), std::time::Instant::now()) {
+                transfer.set_client_profile(rcamera::control::BLE_CLIENT_ID, policy.ble_profile());
+                events.publish(event);
+            }
+        }
+        // 接受新的手机连接并继续发送各客户端队列中的数据
+        #[cfg(any(feature = "wifi", feature = "ethernet"))]
+        if let Some(server) = &tcp_server {
+            server.lock().unwrap().poll();
+        }
+        // 其他节点经ESP-NOW发来的数据包与本机相机的数据一样处理
+        #[cfg(feature = "espnow")]
+        if let Some(link) = &espnow {
+            for packet in link.poll() {
+                transfer.on_data_received(&packet);
+            }
+        }
+        if let Err(e) = transfer.flush_backlog() {
+            log::error!("发送积压数据失败: {}", e);
+        }
+        for event in client_events_rx.try_iter() {
+            transfer.notify_clients(&event);
+        }
+        for handle in confirmed_rx.try_iter() {
+            transfer.mark_verified(handle);
+            if let Err(e) = prefetcher.lock().unwrap().cache_mut().remove(handle) {
+                log::warn!("移除对象 0x{:08x} 的预览失败: {}", handle, e);
+            }
+            // 送达后处理可能删除原文件，先读取对象信息推进同步游标
+            let info = embassy_futures::block_on(shared_camera.lock().unwrap().ptp().get_objectinfo(handle, None));
+            match info {
+                Ok(info) => {
+                    if let Err(e) = sync_cursor.advance(info.StorageID, handle, &info.CaptureDate) {
+                        log::warn!("推进同步游标失败: {}", e);
+                    }
+                }
+                Err(e) => log::warn!("读取对象 0x{:08x} 的信息失败，同步游标未推进: {}", handle, e),
+            }
+        }
+        if !transfer.pending_post_transfer().is_empty() {
+            let report = {
+                let mut camera = shared_camera.lock().unwrap();
+                embassy_futures::block_on(transfer.apply_post_transfer(camera.ptp(), None))
+            };
+            // 超时或USB错误的对象留在队列中，下一轮重试
+            for (handle, e) in &report.failed {
+                log::warn!("送达后处理对象 0x{:08x} 失败: {}", handle, e);
+            }
+        }
+        // 链路空闲时每轮只做一项后台工作，客户端的请求不会被长时间阻塞
+        if arbiter.is_idle() {
+            let mut camera = shared_camera.lock().unwrap();
+            let stale = summaries.lock().unwrap().is_stale();
+            let prefetch_pending = prefetcher.lock().unwrap().has_pending();
+            if stale {
+                if let Err(e) = embassy_futures::block_on(summaries.lock().unwrap().refresh(&mut *camera, None)) {
+                    log::warn!("读取相册摘要失败: {}", e);
+                }
+            } else if prefetch_pending {
+                if let Err(e) = embassy_futures::block_on(prefetcher.lock().unwrap().step(camera.ptp(), true)) {
+                    log::warn!("预取对象预览失败: {}", e);
+                }
+            } else if std::time::Instant::now() >= next_verify {
+                next_verify = std::time::Instant::now() + VERIFY_INTERVAL;
+                if let Err(e) = embassy_futures::block_on(verifier.step(camera.ptp(), &ledger.lock().unwrap(), true)) {
+                    log::warn!("完整性复检失败: {}", e);
+                }
+                if let Some(event) = verifier.trend_event() {
+                    events.publish(event);
+                }
+            }
+        }
+        #[cfg(feature = "http")]
+        {
+            *runtime_status.lock().unwrap() = rcamera::wireless::http::RuntimeStatus {
+                transfer_status: format!("{:?}", transfer.get_status()),
+                bytes_transferred: transfer.get_bytes_transferred(),
+                clients: transfer.client_count(),
+            };
+            let mut sample = metrics_sample.lock().unwrap();
+            sample.queue_depth = arbiter.total_queued();
+            sample.bytes_transferred = transfer.get_bytes_transferred() as u64;
+            sample.integrity = Some(verifier.stats());
+            sample.control = Some(command_rx.latency());
+        }
+        
+        // 相机新增对象时更新新对象计数，订阅了计数通知的手机据此唤醒应用开始同步
+        match protocol.poll_event() {
+            Ok(Some(rcamera::ptp_mtp::PtpEvent::ObjectAdded(handle))) => {
+                log::debug!("相机新增对象 0x{:08x}", handle);
+                prefetcher.lock().unwrap().enqueue(handle);
+                summaries.lock().unwrap().mark_stale();
+                #[cfg(feature = "wifi")]
+                if let Some(uploads) = &cloud_uploads {
+                    uploads.object_added(handle);
+                }
+                #[cfg(feature = "ble")]
+                wireless.object_added();
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("读取相机事件失败: {}", e),
+        }
+        #[cfg(feature = "ble")]
+        if let Err(e) = wireless.flush_new_objects() {
+            log::warn!("通知新对象计数失败: {}", e);
+        }
+        #[cfg(feature = "ble")]
+        if let Err(e) = wireless.flush_replies() {
+            log::warn!("发送蓝牙应答失败: {}", e);
+        }
+        
+        // 按配置的帧率读取取景画面，经流水线推送给客户端
+        if live_view_running && std::time::Instant::now() >= next_frame {
+            next_frame = std::time::Instant::now() + frame_interval;
+            match protocol.poll_live_frame() {
+                Ok(Some(frame)) => {
+                    #[cfg(feature = "http")]
+                    if let Some(push) = &push {
+                        push.publish_frame(&frame.data);
+                    }
+                    transfer.on_data_received(&frame)
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("读取取景画面失败: {}", e),
+            }
+        }
+        
+        // 每次按下按键切换到下一个模式
+        if mode_button.as_mut().is_some_and(|b| b.poll()) {
+            // 跳过固件不支持的模式(例如未启用sd时的备份模式)
+            let mut next = orchestrator.mode().next();
+            while !orchestrator.supports_mode(next) {
+                next = next.next();
+            }
+            if let Err(e) = switch_mode(next, &config, &mut orchestrator, &mut events, &mut transfer, protocol.as_mut(), &mut live_view_running) {
+                log::error!("切换到 {} 模式失败: {}", next.name(), e);
+            }
+        }
+        
+        let poll = if mode_button.is_some() { BUTTON_POLL } else { memory_poll };
+        let poll = if live_view_running { poll.min(next_frame.saturating_duration_since(std::time::Instant::now())) } else { poll };
+        let wait = remaining.min(poll);
+        if !commands_open {
+            std::thread::sleep(wait);
+            continue;
+        }
+        let (command, client_id) = match command_rx.recv_timeout(wait) {
+            Ok(command) => command,
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
+            // 发送端全部释放后只需继续监视内存直到运行时间结束
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                commands_open = false;
+                continue;
+            }
+        };
+        // 有结果数据的命令把数据放在reply中，随蓝牙应答返回
+        let mut reply = Vec::new();
+        let result = match &command {
+            ControlCommand::TriggerCapture => protocol.trigger_capture(),
+            ControlCommand::TerminateCapture => protocol.terminate_capture(),
+            ControlCommand::CancelTransfer => protocol.cancel_transfer(),
+            ControlCommand::SetMode(mode) => {
+                switch_mode(*mode, &config, &mut orchestrator, &mut events, &mut transfer, protocol.as_mut(), &mut live_view_running)
+            }
+            ControlCommand::Download { handle, range, destination } => {
+                rcamera::data_transfer::on_demand::download(protocol.as_mut(), &mut arbiter, &mut transfer, *handle, *range, destination)
+                    .map(|report| {
+                        if let Some(event) = report.event(*range) {
+                            events.publish(event);
+                        }
+                    })
+            }
+            ControlCommand::ListObjects => protocol.list_objects().map(|handles| {
+                reply = rcamera::control::encode_handles(&handles);
+            }),
+            ControlCommand::SetProperty { code, value } => protocol.set_device_prop(*code, value),
+            ControlCommand::DeleteObject(handle) => protocol.delete_object(*handle),
+            ControlCommand::FormatStore { storage_id, confirmation: None } => protocol
+                .confirm_danger(rcamera::ptp_mtp::DangerousOperation::FormatStore(*storage_id))
+                .map(|code| {
+                    reply = code.to_le_bytes().to_vec();
+                }),
+            ControlCommand::FormatStore { storage_id, confirmation: Some(code) } => protocol.format_store(*storage_id, *code),
+            ControlCommand::StartLiveView if !live_view_running => protocol.start_live_stream().map(|()| {
+                live_view_running = true;
+            }),
+            ControlCommand::StopLiveView if live_view_running => protocol.stop_live_stream().map(|()| {
+                live_view_running = false;
+            }),
+            ControlCommand::StartLiveView | ControlCommand::StopLiveView => Ok(()),
+            other => {
+                log::warn!("主循环不处理命令 {:?}", other);
+                Ok(())
+            }
+        };
+        // 删除和格式化无论成败都按发出命令的客户端记入审计日志
+        if let Some((action, target)) = rcamera::control::AuditAction::from_command(&command) {
+            let outcome = result.as_ref().map(|_| ()).map_err(|e| e.as_ref());
+            if let Err(e) = audit.lock().unwrap().record(action, target, &client_id, outcome) {
+                log::warn!("写入审计日志失败: {}", e);
+            }
+        }
+        // 蓝牙命令都有应答，手机按命令顺序对应
+        #[cfg(feature = "ble")]
+        if let (ConnectionType::Bluetooth, Some(opcode)) = (conn_type, command.ble_opcode()) {
+            let message = result.as_ref().err().map(|e| e.to_string());
+            let response = rcamera::control::ble_response(opcode, message.as_deref().map_or(Ok(reply.as_slice()), Err));
+            if let Err(e) = wireless.send_bluetooth_data(&response) {
+                log::warn!("发送蓝牙应答失败: {}", e);
+            }
+        }
+        if let Err(e) = result {
+            log::error!("执行命令 {:?} 失败: {}", command, e);
+            if let Some(event) = storage.observe_error(e.as_ref()) {
+                events.publish(event);
+            }
+        }
+    }
     
     // 停止传输
+    let report = stage_metrics.lock().unwrap().report();
+    log::info!("各阶段指标: {:?}，瓶颈阶段: {:?}", report.stages, report.bottleneck);
+    let latency = command_rx.latency();
+    log::info!(
+        "控制命令 {} 条，平均等待 {}us，最长 {}us (紧急命令 {}us)",
+        latency.count, latency.avg_us(), latency.max_us, latency.urgent_max_us
+    );
+    // 先停止HTTP接口，关闭期间不再接受请求
+    #[cfg(feature = "http")]
+    drop(http_api);
     log::info!("正在停止传输...");
     transfer.stop()?;
-    protocol.stop_live_stream()?;
+    if live_view_running {
+        protocol.stop_live_stream()?;
+    }
     protocol.close_session()?;
     wireless.disconnect()?;
-    camera.disconnect();
     
     log::info!("系统已安全关闭");
     Ok(())
 }
+
+/// 每个客户端排队等待下载的对象数上限
+const DOWNLOAD_QUEUE_PER_CLIENT: usize = 32;
+
+/// 配对信息(二维码)的有效期
+const PAIRING_VALIDITY: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+/// 会话令牌的有效期，过期后客户端需重新登录
+const SESSION_TTL: std::time::Duration = std::time::Duration::from_secs(12 * 3600);
+
+/// STA模式启动时等待获得地址的时间，超时后进入配网
+#[cfg(feature = "http")]
+const STA_CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// 链路空闲时两次完整性复检的最短间隔
+const VERIFY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// /ws/metrics 的推送间隔
+#[cfg(feature = "http")]
+const METRICS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// 带模式切换按键时主循环的轮询间隔
+const BUTTON_POLL: std::time::Duration = std::time::Duration::from_millis(20);
+
+/// 切换工作模式：重新组装流水线，应用资源预算，并按模式启停实时取景
+fn switch_mode(
+    mode: rcamera::orchestrator::mode::OperatingMode,
+    config: &rcamera::config::DeviceConfig,
+    orchestrator: &mut rcamera::orchestrator::Orchestrator,
+    events: &mut rcamera::events::EventBus,
+    transfer: &mut rcamera::data_transfer::TransferManager,
+    protocol: &mut dyn rcamera::ptp_mtp::ProtocolHandler,
+    live_view_running: &mut bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !orchestrator.supports_mode(mode) {
+        return Err(format!("固件未启用 {} 模式需要的子系统", mode.name()).into());
+    }
+    let switch = orchestrator.set_mode(mode, &config.pipeline, *live_view_running, events);
+    let pipeline = rcamera::data_transfer::PipelineBuilder::new().build(&switch.pipeline)?;
+    transfer.set_pipeline(pipeline);
+    transfer.set_buffer_budget(switch.budget.max_buffer_bytes);
+    protocol.set_chunk_size(orchestrator.transfer_chunk_size());
+    if switch.stop_live_view {
+        protocol.stop_live_stream()?;
+        *live_view_running = false;
+    }
+    if switch.start_live_view {
+        if protocol.capabilities()?.supports_live_view {
+            log::info!("正在启动相机实时数据流...");
+            protocol.start_live_stream()?;
+            *live_view_running = true;
+        } else {
+            log::warn!("相机不支持实时取景，{:?} 模式下不启动实时数据流", mode);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// 内存监视 - 周期性采样空闲堆，低于阈值时报告内存压力，恢复到更高的阈值后才解除，避免在边界上反复切换
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::sys::{heap_caps_get_free_size, heap_caps_get_largest_free_block, MALLOC_CAP_8BIT};
+use log::{info, warn};
+
+/// 一次堆采样
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemorySample {
+    pub free_bytes: usize,    // 空闲堆总量
+    pub largest_block: usize, // 最大连续空闲块，决定能否分配大的传输缓冲区
+}
+
+impl MemorySample {
+    /// 采样当前的8位可访问堆
+    #[cfg(target_os = "espidf")]
+    pub fn current() -> Self {
+        unsafe {
+            MemorySample {
+                free_bytes: heap_caps_get_free_size(MALLOC_CAP_8BIT),
+                largest_block: heap_caps_get_largest_free_block(MALLOC_CAP_8BIT),
+            }
+        }
+    }
+
+    /// 主机上没有堆统计，视为内存充足
+    #[cfg(not(target_os = "espidf"))]
+    pub fn current() -> Self {
+        MemorySample { free_bytes: usize::MAX, largest_block: usize::MAX }
+    }
+}
+
+/// 内存压力阈值
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryThresholds {
+    pub enter_free: usize,    // 空闲堆低于此值时进入压力状态
+    pub exit_free: usize,     // 空闲堆回到此值以上时解除
+    pub min_block: usize,     // 最大连续块低于此值时同样视为压力(碎片化)
+}
+
+impl Default for MemoryThresholds {
+    fn default() -> Self {
+        MemoryThresholds {
+            enter_free: 40 * 1024,
+            exit_free: 64 * 1024,
+            min_block: 16 * 1024,
+        }
+    }
+}
+
+/// 压力状态的变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureChange {
+    Entered(MemorySample), // 进入压力状态
+    Cleared(MemorySample), // 压力解除
+}
+
+/// 内存监视器
+pub struct MemoryMonitor {
+    thresholds: MemoryThresholds,
+    under_pressure: bool,
+}
+
+impl MemoryMonitor {
+    pub fn new(thresholds: MemoryThresholds) -> Self {
+        MemoryMonitor {
+            thresholds,
+            under_pressure: false,
+        }
+    }
+
+    /// 当前是否处于压力状态
+    pub fn under_pressure(&self) -> bool {
+        self.under_pressure
+    }
+
+    /// 采样并返回状态变化
+    pub fn poll(&mut self) -> Option<PressureChange> {
+        self.evaluate(MemorySample::current())
+    }
+
+    /// 根据一次采样更新状态，只有状态变化时返回
+    pub fn evaluate(&mut self, sample: MemorySample) -> Option<PressureChange> {
+        let t = &self.thresholds;
+        if !self.under_pressure {
+            if sample.free_bytes < t.enter_free || sample.largest_block < t.min_block {
+                self.under_pressure = true;
+                warn!("内存压力: 空闲 {} 字节，最大块 {} 字节", sample.free_bytes, sample.largest_block);
+                return Some(PressureChange::Entered(sample));
+            }
+        } else if sample.free_bytes >= t.exit_free && sample.largest_block >= t.min_block {
+            self.under_pressure = false;
+            info!("内存压力已解除: 空闲 {} 字节", sample.free_bytes);
+            return Some(PressureChange::Cleared(sample));
+        }
+        None
+    }
+}
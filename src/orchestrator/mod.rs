@@ -0,0 +1,322 @@
+// 系统编排模块 - 根据编译时启用的子系统，在运行时决定启动哪些服务
+use log::{info, warn};
+
+use crate::config::NetworkInterface;
+use crate::data_transfer::StageSpec;
+use crate::events::{AppEvent, EventBus};
+use crate::ptp_mtp::DEFAULT_STREAM_CHUNK_SIZE;
+use crate::wireless::ConnectionType;
+
+pub mod memory;
+pub mod mode;
+pub mod transport;
+
+use memory::PressureChange;
+use mode::{ModeSwitch, OperatingMode, ResourceBudget};
+
+/// 内存压力下的传输分块大小
+pub const SHED_CHUNK_SIZE: u32 = 8 * 1024;
+/// 按预算缩小分块时的下限，保持为USB包大小的整数倍
+const MIN_CHUNK_SIZE: u32 = 4 * 1024;
+
+/// 可通过cargo feature裁剪的子系统
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    WiFi,        // WiFi连接与发送
+    Ble,         // 蓝牙GATT服务
+    Sd,          // SD卡存储
+    Http,        // HTTP接口
+    Mdns,        // mDNS服务发现
+    LiveView,    // 实时取景
+    VendorCanon, // 佳能厂商扩展
+    Ethernet,    // 有线以太网
+}
+
+impl Subsystem {
+    /// 所有子系统
+    pub const ALL: [Subsystem; 8] = [
+        Subsystem::WiFi,
+        Subsystem::Ble,
+        Subsystem::Sd,
+        Subsystem::Http,
+        Subsystem::Mdns,
+        Subsystem::LiveView,
+        Subsystem::VendorCanon,
+        Subsystem::Ethernet,
+    ];
+
+    /// 对应的cargo feature名称
+    pub fn feature_name(self) -> &'static str {
+        match self {
+            Subsystem::WiFi => "wifi",
+            Subsystem::Ble => "ble",
+            Subsystem::Sd => "sd",
+            Subsystem::Http => "http",
+            Subsystem::Mdns => "mdns",
+            Subsystem::LiveView => "live-view",
+            Subsystem::VendorCanon => "vendor-canon",
+            Subsystem::Ethernet => "ethernet",
+        }
+    }
+
+    /// 该子系统是否被编译进当前固件
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Subsystem::WiFi => cfg!(feature = "wifi"),
+            Subsystem::Ble => cfg!(feature = "ble"),
+            Subsystem::Sd => cfg!(feature = "sd"),
+            Subsystem::Http => cfg!(feature = "http"),
+            Subsystem::Mdns => cfg!(feature = "mdns"),
+            Subsystem::LiveView => cfg!(feature = "live-view"),
+            Subsystem::VendorCanon => cfg!(feature = "vendor-canon"),
+            Subsystem::Ethernet => cfg!(feature = "ethernet"),
+        }
+    }
+}
+
+/// 返回当前固件中启用的所有子系统
+pub fn enabled_subsystems() -> Vec<Subsystem> {
+    Subsystem::ALL
+        .iter()
+        .copied()
+        .filter(|s| s.is_enabled())
+        .collect()
+}
+
+/// 内存压力变化后上层需要执行的动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShedPlan {
+    pub stop_live_view: bool,   // 停止实时取景
+    pub resume_live_view: bool, // 重新启动之前因压力停止的实时取景
+    pub chunk_size: u32,        // 之后下载使用的分块大小
+}
+
+/// 系统编排器 - 按编译时启用的子系统为上层选择可用的服务
+pub struct Orchestrator {
+    shedding: bool,          // 是否处于内存压力下的降级状态
+    live_view_paused: bool,  // 实时取景是否因内存压力被暂停
+    mode: OperatingMode,     // 当前工作模式
+}
+
+impl Default for Orchestrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Orchestrator {
+    /// 创建新的编排器
+    pub fn new() -> Self {
+        info!(
+            "已启用的子系统: {:?}",
+            enabled_subsystems().iter().map(|s| s.feature_name()).collect::<Vec<_>>()
+        );
+        Orchestrator {
+            shedding: false,
+            live_view_paused: false,
+            mode: OperatingMode::default(),
+        }
+    }
+
+    /// 选择首选的无线连接类型
+    /// WiFi带宽更高，优先使用；仅编译了蓝牙时退回蓝牙；都没有则返回None
+    pub fn preferred_connection(&self) -> Option<ConnectionType> {
+        #[cfg(feature = "wifi")]
+        {
+            Some(ConnectionType::WiFi)
+        }
+        #[cfg(all(feature = "ble", not(feature = "wifi")))]
+        {
+            Some(ConnectionType::Bluetooth)
+        }
+        #[cfg(not(any(feature = "wifi", feature = "ble")))]
+        {
+            warn!("未启用任何无线子系统");
+            None
+        }
+    }
+
+    /// 按配置选择数据链路：配置了以太网且固件支持时使用以太网，否则退回无线
+    pub fn select_connection(&self, network: &NetworkInterface) -> Option<ConnectionType> {
+        if let NetworkInterface::Ethernet(_) = network {
+            #[cfg(feature = "ethernet")]
+            return Some(ConnectionType::Ethernet);
+            #[cfg(not(feature = "ethernet"))]
+            warn!("配置了以太网，但固件未启用ethernet子系统，改用无线连接");
+        }
+        self.preferred_connection()
+    }
+
+    /// 是否启动实时取景：固件支持且当前模式需要
+    pub fn live_view_enabled(&self) -> bool {
+        Subsystem::LiveView.is_enabled() && self.budget().live_view
+    }
+
+    /// 模式需要的子系统是否都已编译进固件；备份模式把数据归档到SD卡，需要`sd`
+    pub fn supports_mode(&self, mode: OperatingMode) -> bool {
+        mode != OperatingMode::Backup || Subsystem::Sd.is_enabled()
+    }
+
+    /// 当前模式的资源预算
+    pub fn budget(&self) -> ResourceBudget {
+        self.mode.preset().budget
+    }
+
+    /// 是否可以再开始一个下载任务，`active_jobs`为正在进行的任务数
+    pub fn admit_job(&self, active_jobs: usize) -> bool {
+        active_jobs < self.budget().max_jobs
+    }
+
+    /// 当前工作模式
+    pub fn mode(&self) -> OperatingMode {
+        self.mode
+    }
+
+    /// 切换工作模式，返回需要重新组装的流水线(模式预设阶段加上`configured`)和实时取景的启停
+    /// 内存压力下不启动实时取景，压力解除后再按模式恢复
+    pub fn set_mode(
+        &mut self,
+        mode: OperatingMode,
+        configured: &[StageSpec],
+        live_view_running: bool,
+        events: &mut EventBus,
+    ) -> ModeSwitch {
+        let previous = std::mem::replace(&mut self.mode, mode);
+        let wants_live_view = self.live_view_enabled();
+        let start = wants_live_view && !live_view_running && !self.shedding;
+        self.live_view_paused = wants_live_view && self.shedding;
+        let preset = mode.preset();
+        let switch = ModeSwitch {
+            mode,
+            pipeline: preset.pipeline(configured),
+            budget: preset.budget,
+            start_live_view: start,
+            stop_live_view: live_view_running && !wants_live_view,
+        };
+        info!("工作模式 {} -> {}，资源预算 {:?}", previous.name(), mode.name(), switch.budget);
+        events.publish(AppEvent::ModeChanged {
+            mode: mode.name().to_string(),
+            previous: previous.name().to_string(),
+        });
+        switch
+    }
+
+    /// 当前下载应使用的分块大小，内存压力下缩小以降低峰值占用，且不超过模式预算中每个任务的份额
+    pub fn transfer_chunk_size(&self) -> u32 {
+        let base = if self.shedding {
+            SHED_CHUNK_SIZE
+        } else {
+            DEFAULT_STREAM_CHUNK_SIZE
+        };
+        let share = self.budget().buffer_per_job().min(u32::MAX as usize) as u32;
+        base.min(share / MIN_CHUNK_SIZE * MIN_CHUNK_SIZE).max(MIN_CHUNK_SIZE)
+    }
+
+    /// 处理内存压力变化：先停止实时取景并缩小分块，压力解除后恢复，并发布事件告知客户端原因
+    /// `live_view_running` 为当前实时取景是否在运行
+    pub fn on_memory_change(&mut self, change: PressureChange, live_view_running: bool, events: &mut EventBus) -> ShedPlan {
+        match change {
+            PressureChange::Entered(sample) => {
+                self.shedding = true;
+                self.live_view_paused = live_view_running;
+                let plan = ShedPlan {
+                    stop_live_view: live_view_running,
+                    resume_live_view: false,
+                    chunk_size: self.transfer_chunk_size(),
+                };
+                warn!("内存不足，降级运行: {:?}", plan);
+                events.publish(AppEvent::LoadShed {
+                    free_bytes: sample.free_bytes as u64,
+                    live_view_paused: plan.stop_live_view,
+                    chunk_size: plan.chunk_size,
+                });
+                plan
+            }
+            PressureChange::Cleared(sample) => {
+                self.shedding = false;
+                let resume = std::mem::take(&mut self.live_view_paused) && self.live_view_enabled();
+                let plan = ShedPlan {
+                    stop_live_view: false,
+                    resume_live_view: resume,
+                    chunk_size: self.transfer_chunk_size(),
+                };
+                info!("内存恢复，退出降级: {:?}", plan);
+                events.publish(AppEvent::LoadRestored {
+                    free_bytes: sample.free_bytes as u64,
+                    live_view_resumed: plan.resume_live_view,
+                    chunk_size: plan.chunk_size,
+                });
+                plan
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memory::MemorySample;
+    use std::sync::mpsc::Receiver;
+
+    const LOW: MemorySample = MemorySample { free_bytes: 16 * 1024, largest_block: 8 * 1024 };
+    const HIGH: MemorySample = MemorySample { free_bytes: 128 * 1024, largest_block: 64 * 1024 };
+
+    fn bus() -> (EventBus, Receiver<AppEvent>) {
+        let (listener, rx) = crate::events::channel();
+        let mut events = EventBus::new();
+        events.subscribe(Box::new(listener));
+        (events, rx)
+    }
+
+    #[test]
+    fn backup_mode_needs_sd() {
+        let orchestrator = Orchestrator::new();
+        assert_eq!(orchestrator.supports_mode(OperatingMode::Backup), Subsystem::Sd.is_enabled());
+        for mode in [OperatingMode::Tether, OperatingMode::Event, OperatingMode::Monitor] {
+            assert!(orchestrator.supports_mode(mode));
+        }
+    }
+
+    #[test]
+    fn set_mode_assembles_pipeline_and_switches_live_view() {
+        let (mut events, rx) = bus();
+        let mut orchestrator = Orchestrator::new();
+        let configured = [StageSpec::new("checksum")];
+
+        let switch = orchestrator.set_mode(OperatingMode::Event, &configured, true, &mut events);
+        assert_eq!(orchestrator.mode(), OperatingMode::Event);
+        assert_eq!(switch.pipeline.len(), 2);
+        assert_eq!(switch.pipeline[0].name, "filter");
+        assert_eq!(switch.pipeline[1], configured[0]);
+        assert!(switch.stop_live_view && !switch.start_live_view);
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            AppEvent::ModeChanged { mode: "event".into(), previous: "tether".into() }
+        );
+
+        let switch = orchestrator.set_mode(OperatingMode::Monitor, &[], false, &mut events);
+        assert_eq!(switch.start_live_view, Subsystem::LiveView.is_enabled());
+        assert!(!switch.stop_live_view);
+    }
+
+    #[test]
+    fn memory_pressure_sheds_and_restores() {
+        let (mut events, rx) = bus();
+        let mut orchestrator = Orchestrator::new();
+
+        let plan = orchestrator.on_memory_change(PressureChange::Entered(LOW), true, &mut events);
+        assert_eq!(plan, ShedPlan { stop_live_view: true, resume_live_view: false, chunk_size: SHED_CHUNK_SIZE });
+        assert_eq!(
+            rx.try_recv().unwrap(),
+            AppEvent::LoadShed { free_bytes: LOW.free_bytes as u64, live_view_paused: true, chunk_size: SHED_CHUNK_SIZE }
+        );
+
+        // 压力下切换模式不启动实时取景，压力解除后再恢复
+        let switch = orchestrator.set_mode(OperatingMode::Monitor, &[], false, &mut events);
+        assert!(!switch.start_live_view);
+        let plan = orchestrator.on_memory_change(PressureChange::Cleared(HIGH), false, &mut events);
+        assert_eq!(plan.resume_live_view, Subsystem::LiveView.is_enabled());
+        assert_eq!(plan.chunk_size, 32 * 1024);
+        assert!(matches!(rx.try_iter().last(), Some(AppEvent::LoadRestored { .. })));
+    }
+}
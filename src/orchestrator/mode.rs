@@ -0,0 +1,204 @@
+// 工作模式 - 把常见的使用场景预设为流水线阶段和实时取景开关的组合，可在运行时通过按键、控制台或控制协议切换
+#[cfg(target_os = "espidf")]
+use std::error::Error;
+#[cfg(target_os = "espidf")]
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::gpio::{AnyIOPin, Input, PinDriver, Pull};
+use serde::{Deserialize, Serialize};
+
+use crate::control::{CommandSender, ControlCommand};
+use crate::data_transfer::StageSpec;
+
+/// Backup模式默认的归档目录
+pub const DEFAULT_ARCHIVE_DIR: &str = "/sdcard/archive";
+
+/// 按键消抖时间
+#[cfg(target_os = "espidf")]
+const BUTTON_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// 工作模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OperatingMode {
+    #[default]
+    Tether,  // 联机拍摄：自动同步全尺寸原图，同时提供实时取景
+    Event,   // 活动跟拍：只推送缩略图，原图由客户端按需下载
+    Backup,  // 备份：只归档到SD卡，不向客户端发送
+    Monitor, // 监看：只有实时取景
+}
+
+impl OperatingMode {
+    /// 所有模式，按键按此顺序循环切换
+    pub const ALL: [OperatingMode; 4] = [
+        OperatingMode::Tether,
+        OperatingMode::Event,
+        OperatingMode::Backup,
+        OperatingMode::Monitor,
+    ];
+
+    /// 模式名称，用于控制台、配置和事件
+    pub fn name(self) -> &'static str {
+        match self {
+            OperatingMode::Tether => "tether",
+            OperatingMode::Event => "event",
+            OperatingMode::Backup => "backup",
+            OperatingMode::Monitor => "monitor",
+        }
+    }
+
+    /// 从名称解析，忽略大小写
+    pub fn from_name(name: &str) -> Option<OperatingMode> {
+        Self::ALL.iter().copied().find(|m| m.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// 在控制协议中的编号
+    pub fn index(self) -> u8 {
+        Self::ALL.iter().position(|m| *m == self).unwrap_or(0) as u8
+    }
+
+    /// 按编号取模式
+    pub fn from_index(index: u8) -> Option<OperatingMode> {
+        Self::ALL.get(index as usize).copied()
+    }
+
+    /// 按键切换到的下一个模式
+    pub fn next(self) -> OperatingMode {
+        Self::ALL[(self.index() as usize + 1) % Self::ALL.len()]
+    }
+
+    /// 模式对应的预设
+    pub fn preset(self) -> ModePreset {
+        match self {
+            OperatingMode::Tether => ModePreset {
+                stages: Vec::new(),
+                budget: ResourceBudget {
+                    max_buffer_bytes: 512 * 1024,
+                    max_jobs: 2,
+                    live_view: true,
+                },
+            },
+            // 只传缩略图，缓冲区压到最小，把内存留给蓝牙缩略图通道
+            OperatingMode::Event => ModePreset {
+                stages: vec![StageSpec::new("filter").param("types", "thumbnail,metadata,command,response")],
+                budget: ResourceBudget {
+                    max_buffer_bytes: 32 * 1024,
+                    max_jobs: 1,
+                    live_view: false,
+                },
+            },
+            OperatingMode::Backup => ModePreset {
+                stages: vec![StageSpec::new("archive")
+                    .param("dir", DEFAULT_ARCHIVE_DIR)
+                    .param("forward", "false")],
+                budget: ResourceBudget {
+                    max_buffer_bytes: 256 * 1024,
+                    max_jobs: 1,
+                    live_view: false,
+                },
+            },
+            OperatingMode::Monitor => ModePreset {
+                stages: vec![StageSpec::new("filter").param("types", "metadata,command,response")],
+                budget: ResourceBudget {
+                    max_buffer_bytes: 32 * 1024,
+                    max_jobs: 1,
+                    live_view: true,
+                },
+            },
+        }
+    }
+}
+
+/// 模式的资源预算，由编排器在切换模式时下发给传输层
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceBudget {
+    pub max_buffer_bytes: usize, // 传输缓冲区(下载分块和发送队列)可占用的堆内存
+    pub max_jobs: usize,         // 同时进行的下载任务数
+    pub live_view: bool,         // 是否允许运行实时取景
+}
+
+impl ResourceBudget {
+    /// 每个下载任务可用的缓冲区大小
+    pub fn buffer_per_job(&self) -> usize {
+        self.max_buffer_bytes / self.max_jobs.max(1)
+    }
+}
+
+/// 模式预设
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModePreset {
+    pub stages: Vec<StageSpec>, // 放在配置的流水线阶段之前执行
+    pub budget: ResourceBudget, // 资源预算
+}
+
+impl ModePreset {
+    /// 预设阶段加上配置中的阶段
+    pub fn pipeline(&self, configured: &[StageSpec]) -> Vec<StageSpec> {
+        self.stages.iter().chain(configured).cloned().collect()
+    }
+}
+
+/// 切换模式后上层需要执行的动作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeSwitch {
+    pub mode: OperatingMode,
+    pub pipeline: Vec<StageSpec>, // 需要重新组装的流水线
+    pub budget: ResourceBudget,   // 新模式的资源预算
+    pub start_live_view: bool,
+    pub stop_live_view: bool,
+}
+
+/// 模式切换按键，按下时接地(使用内部上拉)
+#[cfg(target_os = "espidf")]
+pub struct ModeButton {
+    pin: PinDriver<'static, AnyIOPin, Input>,
+    pressed: bool,
+    changed_at: Instant,
+}
+
+#[cfg(target_os = "espidf")]
+impl ModeButton {
+    /// 在指定GPIO上创建按键
+    pub fn new(gpio: i32) -> Result<Self, Box<dyn Error>> {
+        // 引脚编号来自配置，由使用者保证未被其他外设占用
+        let mut pin = PinDriver::input(unsafe { AnyIOPin::new(gpio) })?;
+        pin.set_pull(Pull::Up)?;
+        Ok(ModeButton {
+            pin,
+            pressed: false,
+            changed_at: Instant::now(),
+        })
+    }
+
+    /// 轮询按键，消抖后检测到一次按下时返回true
+    pub fn poll(&mut self) -> bool {
+        let low = self.pin.is_low();
+        if low == self.pressed || self.changed_at.elapsed() < BUTTON_DEBOUNCE {
+            return false;
+        }
+        self.pressed = low;
+        self.changed_at = Instant::now();
+        low
+    }
+}
+
+/// 注册控制台命令 `mode`，切换请求交给主循环执行
+pub fn register_console_command(console: &mut crate::console::Console, commands: CommandSender) {
+    console.register(
+        "mode",
+        "切换工作模式: mode tether | mode event | mode backup | mode monitor",
+        Box::new(move |args| match args {
+            [name] => match OperatingMode::from_name(name) {
+                Some(mode) => match commands.send((ControlCommand::SetMode(mode), crate::console::CONSOLE_CLIENT_ID.to_string())) {
+                    Ok(()) => format!("正在切换到 {} 模式", mode.name()),
+                    Err(_) => "主循环已退出，无法切换模式".to_string(),
+                },
+                None => format!("未知的模式: {}", name),
+            },
+            _ => format!(
+                "可用模式: {}",
+                OperatingMode::ALL.iter().map(|m| m.name()).collect::<Vec<_>>().join(", ")
+            ),
+        }),
+    );
+}
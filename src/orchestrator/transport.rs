@@ -0,0 +1,82 @@
+// 传输选择 - WiFi和蓝牙同时开启时，蓝牙始终承载控制命令和状态，批量数据在WiFi可用时走WiFi；
+// WiFi断开后立即让蓝牙客户端接收缩略图和元数据，WiFi恢复并持续可用一段时间后再切回，避免链路抖动时反复切换。
+// 策略本身不操作驱动，只根据轮询到的WiFi链路状态决定批量数据的去向
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::data_transfer::ClientProfile;
+use crate::events::AppEvent;
+
+/// 批量数据(原图)的去向
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkRoute {
+    WiFi,    // 原图走WiFi，蓝牙只传控制和状态
+    BleOnly, // WiFi不可用，蓝牙传缩略图和元数据
+}
+
+impl BulkRoute {
+    /// 事件中使用的名称
+    pub fn name(self) -> &'static str {
+        match self {
+            BulkRoute::WiFi => "wifi",
+            BulkRoute::BleOnly => "ble",
+        }
+    }
+}
+
+/// WiFi/蓝牙双通道的传输策略
+#[derive(Debug)]
+pub struct TransportPolicy {
+    route: BulkRoute,
+    settle: Duration,           // WiFi恢复后需要持续可用的时间
+    up_since: Option<Instant>,  // WiFi本次恢复的时刻，断开时为None
+}
+
+impl TransportPolicy {
+    /// 按启动时的WiFi状态选择初始去向，启动时不需要等待稳定
+    pub fn new(settle: Duration, wifi_up: bool, now: Instant) -> Self {
+        TransportPolicy {
+            route: if wifi_up { BulkRoute::WiFi } else { BulkRoute::BleOnly },
+            settle,
+            up_since: wifi_up.then_some(now),
+        }
+    }
+
+    /// 当前批量数据的去向
+    pub fn route(&self) -> BulkRoute {
+        self.route
+    }
+
+    /// 蓝牙客户端在当前去向下的类型
+    pub fn ble_profile(&self) -> ClientProfile {
+        match self.route {
+            BulkRoute::WiFi => ClientProfile::MonitorOnly,
+            BulkRoute::BleOnly => ClientProfile::ThumbnailsOnly,
+        }
+    }
+
+    /// 记录轮询到的WiFi链路状态，去向改变时返回事件
+    pub fn observe(&mut self, wifi_up: bool, now: Instant) -> Option<AppEvent> {
+        let next = if wifi_up {
+            let since = *self.up_since.get_or_insert(now);
+            if now.duration_since(since) >= self.settle {
+                BulkRoute::WiFi
+            } else {
+                self.route
+            }
+        } else {
+            self.up_since = None;
+            BulkRoute::BleOnly
+        };
+        if next == self.route {
+            return None;
+        }
+        let previous = std::mem::replace(&mut self.route, next);
+        info!("批量数据改走 {}(之前 {})", next.name(), previous.name());
+        Some(AppEvent::TransportChanged {
+            bulk: next.name().to_string(),
+            previous: previous.name().to_string(),
+        })
+    }
+}
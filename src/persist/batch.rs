@@ -0,0 +1,173 @@
+// 批量写入 - 合并台账、断点等频繁更新，按对象边界或时间间隔一次性提交，减少NVS擦写；
+// 多个键的提交先整体写入日志键，掉电后重启时重放日志，保证一批更新要么全部生效要么全部丢失
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use super::KvStore;
+
+/// 提交日志的键名
+const JOURNAL_KEY: &str = "batch_wal";
+
+/// 默认的最长缓存时间，超过后下一次写入时提交
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(5);
+/// 默认的最大缓存字节数，超过后立即提交
+pub const DEFAULT_MAX_PENDING_BYTES: usize = 4 * 1024;
+
+/// 提交统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchStats {
+    pub commits: u64,  // 提交次数
+    pub writes: u64,   // 实际写入底层存储的键数(不含日志)
+    pub coalesced: u64, // 被同一批次中后续写入覆盖的写入数
+    pub unchanged: u64, // 与已存储的值相同而跳过的写入数
+}
+
+/// 带写缓存的键值存储
+/// 写入先缓存在内存中，调用`flush`(通常在一个对象处理完成时)或超过缓存时间/大小时提交；
+/// 掉电最多丢失最近一次提交之后的更新，即正在处理的那一个对象
+pub struct BatchedStore {
+    inner: Box<dyn KvStore>,
+    pending: BTreeMap<String, Option<Vec<u8>>>, // None表示删除
+    pending_bytes: usize,
+    oldest: Option<Instant>, // 最早一条未提交写入的时间
+    max_delay: Duration,
+    max_pending_bytes: usize,
+    stats: BatchStats,
+}
+
+impl BatchedStore {
+    /// 包装底层存储；上次提交中途掉电留下的日志会先被重放
+    pub fn open(mut inner: Box<dyn KvStore>) -> Result<Self, Box<dyn Error>> {
+        if let Some(raw) = inner.get(JOURNAL_KEY)? {
+            match serde_json::from_slice::<Vec<(String, Option<Vec<u8>>)>>(&raw) {
+                Ok(batch) => {
+                    info!("重放未完成的批量提交({} 个键)", batch.len());
+                    apply(inner.as_mut(), &batch)?;
+                }
+                // 日志本身是原子写入的，无法解析说明不是本模块写入的，丢弃
+                Err(e) => warn!("提交日志无法解析，丢弃: {}", e),
+            }
+            inner.remove(JOURNAL_KEY)?;
+        }
+        Ok(BatchedStore {
+            inner,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            oldest: None,
+            max_delay: DEFAULT_MAX_DELAY,
+            max_pending_bytes: DEFAULT_MAX_PENDING_BYTES,
+            stats: BatchStats::default(),
+        })
+    }
+
+    /// 设置自动提交的时间和大小阈值
+    pub fn set_limits(&mut self, max_delay: Duration, max_pending_bytes: usize) {
+        self.max_delay = max_delay;
+        self.max_pending_bytes = max_pending_bytes;
+    }
+
+    /// 未提交的键数
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// 提交统计
+    pub fn stats(&self) -> BatchStats {
+        self.stats
+    }
+
+    /// 提交所有缓存的写入
+    pub fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        // 与已存储的值相同的写入不擦写闪存
+        let mut batch = Vec::with_capacity(self.pending.len());
+        for (key, value) in std::mem::take(&mut self.pending) {
+            if self.inner.get(&key)? == value {
+                self.stats.unchanged += 1;
+            } else {
+                batch.push((key, value));
+            }
+        }
+        self.pending_bytes = 0;
+        self.oldest = None;
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        // 单个键的写入本身是原子的；多个键先写日志，日志写入成功即视为提交
+        let journaled = batch.len() > 1;
+        if journaled {
+            self.inner.set(JOURNAL_KEY, &serde_json::to_vec(&batch)?)?;
+        }
+        apply(self.inner.as_mut(), &batch)?;
+        if journaled {
+            self.inner.remove(JOURNAL_KEY)?;
+        }
+        self.stats.commits += 1;
+        self.stats.writes += batch.len() as u64;
+        debug!("批量提交 {} 个键", batch.len());
+        Ok(())
+    }
+
+    fn stage(&mut self, key: &str, value: Option<Vec<u8>>) -> Result<(), Box<dyn Error>> {
+        let size = key.len() + value.as_ref().map_or(0, |v| v.len());
+        if let Some(old) = self.pending.insert(key.to_string(), value) {
+            self.stats.coalesced += 1;
+            self.pending_bytes -= key.len() + old.map_or(0, |v| v.len());
+        }
+        self.pending_bytes += size;
+        let oldest = *self.oldest.get_or_insert_with(Instant::now);
+        if self.pending_bytes > self.max_pending_bytes || oldest.elapsed() >= self.max_delay {
+            self.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// 把一批写入应用到底层存储
+fn apply(store: &mut dyn KvStore, batch: &[(String, Option<Vec<u8>>)]) -> Result<(), Box<dyn Error>> {
+    for (key, value) in batch {
+        match value {
+            Some(value) => store.set(key, value)?,
+            None => store.remove(key)?,
+        }
+    }
+    Ok(())
+}
+
+impl KvStore for BatchedStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        match self.pending.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => self.inner.get(key),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Box<dyn Error>> {
+        if key.len() > super::MAX_KEY_LEN {
+            return Err(format!("NVS键名过长: {}", key).into());
+        }
+        self.stage(key, Some(value.to_vec()))
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
+        self.stage(key, None)
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.commit()
+    }
+}
+
+impl Drop for BatchedStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.commit() {
+            warn!("关闭存储时提交失败: {}", e);
+        }
+    }
+}
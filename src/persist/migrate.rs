@@ -0,0 +1,164 @@
+// 数据迁移 - 持久化数据(配置、台账、提交日志等)带有格式版本号，固件升级后启动时按顺序执行未执行过的迁移，
+// 新格式不必清空用户的同步记录和设置
+use std::error::Error;
+
+use log::{info, warn};
+use serde_json::Value;
+
+use super::KvStore;
+
+/// 数据格式版本的键名
+const VERSION_KEY: &str = "schema_ver";
+
+/// 迁移函数，就地改写存储中的数据
+pub type MigrateFn = fn(&mut dyn KvStore) -> Result<(), Box<dyn Error>>;
+
+/// 一次数据格式变更
+pub struct Migration {
+    pub version: u32,              // 迁移完成后的版本号，按升序排列
+    pub description: &'static str, // 写入日志的说明
+    pub apply: MigrateFn,
+}
+
+/// 固件内置的迁移，新增格式变更时追加到末尾
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    description: "开始记录数据格式版本",
+    apply: |_| Ok(()),
+}];
+
+/// 当前固件使用的数据格式版本
+pub fn latest_version(migrations: &[Migration]) -> u32 {
+    migrations.last().map_or(0, |m| m.version)
+}
+
+/// 存储中数据的格式版本，从未记录过时为0(首个带版本号的固件之前的格式)
+pub fn stored_version(store: &dyn KvStore) -> Result<u32, Box<dyn Error>> {
+    match store.get(VERSION_KEY)? {
+        Some(raw) => {
+            let bytes: [u8; 4] = raw.as_slice().try_into().map_err(|_| "数据格式版本损坏")?;
+            Ok(u32::from_le_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
+
+/// 执行尚未执行的迁移，返回迁移后的版本
+///
+/// 每个迁移完成后立即记录版本号并提交，中途掉电时下次启动从未完成的迁移重新开始，
+/// 因此迁移应能重复执行；存储是`BatchedStore`时迁移的写入和版本号在同一批提交。
+/// 应在`BatchedStore`重放提交日志之后、各模块打开存储之前调用；
+/// 数据由更新的固件写入时(降级)返回错误，由调用方决定是否清空
+pub fn run(store: &mut dyn KvStore, migrations: &[Migration]) -> Result<u32, Box<dyn Error>> {
+    if migrations.windows(2).any(|w| w[0].version >= w[1].version) {
+        return Err("迁移列表的版本号必须递增".into());
+    }
+    let latest = latest_version(migrations);
+    let mut current = stored_version(store)?;
+    if current > latest {
+        return Err(format!("数据格式版本 {} 高于固件支持的 {}，可能由更新的固件写入", current, latest).into());
+    }
+    let pending = migrations.iter().position(|m| m.version > current).unwrap_or(migrations.len());
+    for migration in &migrations[pending..] {
+        info!("数据迁移 {} -> {}: {}", current, migration.version, migration.description);
+        (migration.apply)(store).map_err(|e| format!("数据迁移到版本 {} 失败: {}", migration.version, e))?;
+        store.set(VERSION_KEY, &migration.version.to_le_bytes())?;
+        store.flush()?;
+        current = migration.version;
+    }
+    Ok(current)
+}
+
+/// 对刚打开的存储执行固件内置的迁移后返回，各子系统的数据格式版本分别记录在各自的命名空间中
+pub fn prepare<S: KvStore>(mut store: S) -> Result<S, Box<dyn Error>> {
+    run(&mut store, MIGRATIONS)?;
+    Ok(store)
+}
+
+/// 迁移辅助：重命名键，目标键已存在时保留目标键并删除源键
+pub fn rename_key(store: &mut dyn KvStore, from: &str, to: &str) -> Result<(), Box<dyn Error>> {
+    let Some(value) = store.get(from)? else {
+        return Ok(());
+    };
+    if store.get(to)?.is_some() {
+        warn!("迁移: {} 已存在，丢弃 {}", to, from);
+    } else {
+        store.set(to, &value)?;
+    }
+    store.remove(from)
+}
+
+/// 迁移辅助：修改一个JSON格式的值，键不存在时不做任何事
+pub fn update_json(
+    store: &mut dyn KvStore,
+    key: &str,
+    f: impl FnOnce(&mut Value) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let Some(raw) = store.get(key)? else {
+        return Ok(());
+    };
+    let mut value: Value = serde_json::from_slice(&raw).map_err(|e| format!("迁移: {} 不是JSON: {}", key, e))?;
+    f(&mut value)?;
+    store.set(key, &serde_json::to_vec(&value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::MemoryStore;
+
+    const RENAME: &[Migration] = &[
+        Migration { version: 1, description: "初始版本", apply: |_| Ok(()) },
+        Migration { version: 2, description: "重命名", apply: |s| rename_key(s, "old", "new") },
+    ];
+
+    #[test]
+    fn runs_pending_migrations_and_records_version() {
+        let mut store = MemoryStore::new();
+        store.set("old", b"v").unwrap();
+        assert_eq!(run(&mut store, RENAME).unwrap(), 2);
+        assert_eq!(stored_version(&store).unwrap(), 2);
+        assert_eq!(store.get("new").unwrap().as_deref(), Some(&b"v"[..]));
+        assert_eq!(store.get("old").unwrap(), None);
+    }
+
+    #[test]
+    fn skips_migrations_already_applied() {
+        let mut store = MemoryStore::new();
+        store.set(VERSION_KEY, &2u32.to_le_bytes()).unwrap();
+        store.set("old", b"v").unwrap();
+        assert_eq!(run(&mut store, RENAME).unwrap(), 2);
+        assert_eq!(store.get("old").unwrap().as_deref(), Some(&b"v"[..]));
+    }
+
+    #[test]
+    fn failed_migration_keeps_previous_version() {
+        const FAILING: &[Migration] = &[
+            Migration { version: 1, description: "初始版本", apply: |_| Ok(()) },
+            Migration { version: 2, description: "失败", apply: |_| Err("测试".into()) },
+        ];
+        let mut store = MemoryStore::new();
+        assert!(run(&mut store, FAILING).is_err());
+        assert_eq!(stored_version(&store).unwrap(), 1);
+    }
+
+    #[test]
+    fn rejects_newer_data_and_unordered_list() {
+        let mut store = MemoryStore::new();
+        store.set(VERSION_KEY, &3u32.to_le_bytes()).unwrap();
+        assert!(run(&mut store, RENAME).is_err());
+
+        const UNORDERED: &[Migration] = &[
+            Migration { version: 2, description: "二", apply: |_| Ok(()) },
+            Migration { version: 1, description: "一", apply: |_| Ok(()) },
+        ];
+        assert!(run(&mut MemoryStore::new(), UNORDERED).is_err());
+    }
+
+    #[test]
+    fn corrupt_version_is_error() {
+        let mut store = MemoryStore::new();
+        store.set(VERSION_KEY, b"x").unwrap();
+        assert!(stored_version(&store).is_err());
+    }
+}
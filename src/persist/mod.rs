@@ -0,0 +1,104 @@
+// 持久化模块 - 为各子系统提供统一的键值存储接口（NVS/内存）
+pub(crate) mod batch;
+pub mod migrate;
+
+use std::collections::HashMap;
+use std::error::Error;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+#[cfg(target_os = "espidf")]
+use log::debug;
+
+pub use batch::{BatchStats, BatchedStore};
+pub use migrate::{MigrateFn, Migration};
+
+/// NVS键名的最大长度
+pub const MAX_KEY_LEN: usize = 15;
+
+/// 键值存储特性
+pub trait KvStore: Send {
+    /// 读取键对应的值
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>>;
+
+    /// 写入键值
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Box<dyn Error>>;
+
+    /// 删除键
+    fn remove(&mut self, key: &str) -> Result<(), Box<dyn Error>>;
+
+    /// 提交缓存的写入，不缓存写入的存储无需处理
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+/// 基于ESP-IDF NVS的键值存储
+#[cfg(target_os = "espidf")]
+pub struct NvsStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+#[cfg(target_os = "espidf")]
+impl NvsStore {
+    /// 打开指定命名空间的NVS存储
+    pub fn open(partition: EspDefaultNvsPartition, namespace: &str) -> Result<Self, Box<dyn Error>> {
+        let nvs = EspNvs::new(partition, namespace, true)?;
+        debug!("已打开NVS命名空间: {}", namespace);
+        Ok(NvsStore { nvs })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl KvStore for NvsStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let len = match self.nvs.blob_len(key)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        let mut buf = vec![0u8; len];
+        Ok(self.nvs.get_blob(key, &mut buf)?.map(|v| v.to_vec()))
+    }
+
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Box<dyn Error>> {
+        if key.len() > MAX_KEY_LEN {
+            return Err(format!("NVS键名过长: {}", key).into());
+        }
+        self.nvs.set_blob(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
+        self.nvs.remove(key)?;
+        Ok(())
+    }
+}
+
+/// 内存键值存储，用于未挂载NVS或主机环境
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryStore {
+    /// 创建空的内存存储
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KvStore for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.entries.insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<(), Box<dyn Error>> {
+        self.entries.remove(key);
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+// 稳定接口 - 下游固件项目应通过 `use rcamera::prelude::*` 使用本库
+// 这里列出的类型遵循semver，只在主版本号变化时做不兼容修改；
+// 其余模块路径(各子模块的内部类型、传输层细节等)随实现调整，次版本号之间也可能变化
+pub use crate::config::{ChannelPolicy, DeviceConfig, NetworkInterface};
+pub use crate::control::{
+    ble_opcode, AuthLevel, ClientHello, ControlChannel, ControlCommand, DeviceHello, HandshakeError,
+    NegotiatedSession,
+};
+pub use crate::data_transfer::{
+    register_packet_hook, PacketContext, PacketHook, PipelineBuilder, PostTransferAction, StageAction, StageSpec,
+    TransferManager, TransferStatus,
+};
+pub use crate::events::{AppEvent, EventBus, EventListener};
+pub use crate::orchestrator::mode::OperatingMode;
+pub use crate::orchestrator::Orchestrator;
+pub use crate::persist::KvStore;
+pub use crate::ptp_mtp::{
+    create_camera_protocol_handler, CancelToken, Error as PtpError, ProtocolHandler, ProtocolType, PtpCamera,
+};
+pub use crate::runtime::{TaskOverride, TaskSpec};
+pub use crate::wireless::{ConnectionConfig, ConnectionType, DataSender, SenderFuture, SenderState, WirelessManager};
@@ -0,0 +1,71 @@
+// 缓冲区池 - 复用数据阶段的读取缓冲区，大文件传输时每个事务不再重新分配堆内存；
+// 缓冲区用完后自动归还，保留已分配的容量供下一次读取使用
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+
+/// 共享的缓冲区池，克隆后指向同一个池
+#[derive(Clone)]
+pub struct BufferPool {
+    free: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_buffers: usize,
+    capacity: usize,
+}
+
+impl BufferPool {
+    /// 创建缓冲区池，最多保留`max_buffers`个空闲缓冲区，新缓冲区预分配`capacity`字节
+    pub fn new(max_buffers: usize, capacity: usize) -> Self {
+        BufferPool {
+            free: Arc::new(Mutex::new(Vec::with_capacity(max_buffers))),
+            max_buffers,
+            capacity,
+        }
+    }
+
+    /// 取出一个空的缓冲区，池中没有空闲缓冲区时新分配
+    pub fn acquire(&self) -> PooledBuffer {
+        let buf = self.free.lock().unwrap().pop().unwrap_or_else(|| Vec::with_capacity(self.capacity));
+        PooledBuffer {
+            buf,
+            pool: self.clone(),
+        }
+    }
+
+    /// 空闲缓冲区数
+    pub fn idle(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    fn release(&self, mut buf: Vec<u8>) {
+        buf.clear();
+        let mut free = self.free.lock().unwrap();
+        if free.len() < self.max_buffers {
+            free.push(buf);
+        }
+    }
+}
+
+/// 从池中取出的缓冲区，释放时归还到池中
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.release(std::mem::take(&mut self.buf));
+    }
+}
@@ -0,0 +1,125 @@
+// 链路校准 - 连接相机后用几次GetPartialObject探测往返延迟和持续吞吐量，
+// 据此推算该机身的事务超时和传输分块大小，结果保存在机身设置中，下次连接直接使用
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::ptp_mtp::camera::{PtpCamera, TransactionTimeouts};
+use crate::ptp_mtp::error::Error;
+
+/// 测延迟的探测大小，一个USB包以内
+const LATENCY_PROBE_SIZE: usize = 512;
+/// 测吞吐量的探测大小
+const THROUGHPUT_PROBE_SIZE: usize = 256 * 1024;
+const LATENCY_PROBES: usize = 3;
+const THROUGHPUT_PROBES: usize = 2;
+/// 查找探测对象时最多检查的对象数
+const MAX_CANDIDATES: usize = 16;
+/// 校准期间每个阶段的超时
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 分块目标时长：一个分块大约传输这么久，兼顾进度粒度和事务开销
+const TARGET_CHUNK_TIME: Duration = Duration::from_millis(250);
+const MIN_CHUNK_SIZE: u32 = 16 * 1024;
+const MAX_CHUNK_SIZE: u32 = 1024 * 1024;
+/// 推算超时时相对测量值的余量倍数
+const SAFETY_FACTOR: u32 = 8;
+
+/// 校准结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub latency: Duration,       // 小数据事务的往返时间(中位数)
+    pub throughput_bps: u64,     // 数据阶段的持续吞吐量(字节/秒)
+    pub timeouts: TransactionTimeouts, // 推算的分阶段超时
+    pub chunk_size: u32,         // 推算的分块大小
+}
+
+impl Calibration {
+    /// 由测量值推算超时和分块大小
+    pub fn derive(latency: Duration, throughput_bps: u64) -> Self {
+        let throughput_bps = throughput_bps.max(1);
+        let chunk_size = (throughput_bps as u128 * TARGET_CHUNK_TIME.as_millis() / 1000)
+            .clamp(MIN_CHUNK_SIZE as u128, MAX_CHUNK_SIZE as u128) as u32
+            / MIN_CHUNK_SIZE
+            * MIN_CHUNK_SIZE;
+        let chunk_time = Duration::from_millis(chunk_size as u64 * 1000 / throughput_bps);
+        let defaults = TransactionTimeouts::default();
+        let timeouts = TransactionTimeouts {
+            command: (latency * SAFETY_FACTOR).clamp(Duration::from_secs(1), defaults.command),
+            // 没有测量主机到相机的方向，保持默认值
+            data_out: defaults.data_out,
+            // 下限留给相机准备大文件数据阶段的时间
+            data_in: ((latency + chunk_time) * SAFETY_FACTOR).clamp(Duration::from_secs(5), defaults.data_in),
+            response: (latency * SAFETY_FACTOR).clamp(Duration::from_secs(2), defaults.response),
+        };
+        Calibration {
+            latency,
+            throughput_bps,
+            timeouts,
+            chunk_size,
+        }
+    }
+
+    /// 把推算的超时设置到相机上(分块大小由调用方在读取对象时使用)
+    pub fn apply(&self, camera: &mut PtpCamera) {
+        camera.set_timeouts(self.timeouts);
+    }
+}
+
+/// 校准链路：相机上至少需要有一个对象作为探测目标，没有对象时返回NotFound
+pub async fn calibrate(camera: &mut PtpCamera) -> Result<Calibration, Error> {
+    let (handle, size) = find_probe_object(camera).await?;
+    let timeout = Some(PROBE_TIMEOUT);
+    let mut buf = vec![0u8; THROUGHPUT_PROBE_SIZE.min(size).max(LATENCY_PROBE_SIZE)];
+
+    let mut samples = Vec::with_capacity(LATENCY_PROBES);
+    for _ in 0..LATENCY_PROBES {
+        let started = Instant::now();
+        let want = LATENCY_PROBE_SIZE.min(buf.len());
+        camera.get_partialobject_into(handle, 0, &mut buf[..want], timeout).await?;
+        samples.push(started.elapsed());
+    }
+    samples.sort();
+    let latency = samples[samples.len() / 2];
+
+    let mut bytes = 0u64;
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..THROUGHPUT_PROBES {
+        let started = Instant::now();
+        bytes += camera.get_partialobject_into(handle, 0, &mut buf, timeout).await? as u64;
+        // 扣除事务本身的往返开销，只计数据阶段
+        elapsed += started.elapsed().saturating_sub(latency);
+    }
+    let throughput_bps = (bytes as u128 * 1000 / elapsed.as_millis().max(1)) as u64;
+
+    let calibration = Calibration::derive(latency, throughput_bps);
+    info!(
+        "链路校准: 延迟 {}ms，吞吐量 {} KB/s，分块 {} 字节，超时 {:?}",
+        latency.as_millis(),
+        throughput_bps / 1024,
+        calibration.chunk_size,
+        calibration.timeouts
+    );
+    Ok(calibration)
+}
+
+/// 找一个足够大的对象作为探测目标，返回(句柄, 大小)
+async fn find_probe_object(camera: &mut PtpCamera) -> Result<(u32, usize), Error> {
+    let timeout = Some(PROBE_TIMEOUT);
+    let mut best: Option<(u32, usize)> = None;
+    for storage_id in camera.get_storageids(timeout).await? {
+        let handles = camera.get_objecthandles_all(storage_id, None, timeout).await?;
+        for handle in handles.into_iter().take(MAX_CANDIDATES) {
+            let size = camera.get_objectinfo(handle, timeout).await?.ObjectCompressedSize as usize;
+            if size >= THROUGHPUT_PROBE_SIZE {
+                debug!("使用对象 0x{:08x} ({} 字节) 校准", handle, size);
+                return Ok((handle, size));
+            }
+            if size > best.map_or(0, |(_, s)| s) {
+                best = Some((handle, size));
+            }
+        }
+    }
+    best.ok_or_else(|| Error::NotFound("相机上没有可用于校准的对象".into()))
+}
@@ -1,20 +1,79 @@
 #![allow(non_snake_case)]
 
 use std::cmp::min;
-use std::slice;
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 use std::io::Cursor;
 
 // Embassy相关导入
-use embassy_usb::host::{UsbDevice, ConfigDescriptor};
+#[cfg(target_os = "espidf")]
+use embassy_usb::host::UsbDevice;
 use embassy_time::{Duration as EmbassyDuration, Timer};
 
 use crate::ptp_mtp::error::Error;
-use crate::ptp_mtp::standard_codes::{CommandCode, StandardCommandCode, StandardResponseCode, PtpContainerType};
-use crate::ptp_mtp::device_info::{PtpDeviceInfo, PtpObjectInfo, PtpStorageInfo};
+use crate::ptp_mtp::standard_codes::{CommandCode, StandardCommandCode, StandardResponseCode, PtpContainerType, ObjectFormat};
+use crate::ptp_mtp::device_info::{PtpDeviceInfo, PtpObjectInfo, PtpPropInfo, PtpStorageInfo};
 use crate::ptp_mtp::data_types::PtpRead;
-use crate::camera_connection::CameraError;
+use crate::ptp_mtp::event::PtpEvent;
+use crate::ptp_mtp::buffer_pool::{BufferPool, PooledBuffer};
+use crate::ptp_mtp::capabilities::PartialObject64;
+use crate::ptp_mtp::mtp::{MtpCommandCode, MtpObjectPropCode};
+use crate::ptp_mtp::quirks::{self, Quirks};
+use crate::ptp_mtp::trace::{TraceDirection, TraceHandle};
+use crate::ptp_mtp::transport::{DefaultTransport, PtpTransport};
+#[cfg(target_os = "espidf")]
+use crate::ptp_mtp::usb_transport::{PtpUsbTransport, UsbCameraLink};
+
+/// `stream_object`默认的块大小，兼顾ESP32的内存和USB传输效率
+pub const DEFAULT_STREAM_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// 发送数据阶段(SendObject等)时每次批量写入的默认字节数，会向下对齐到输出端点的包大小
+pub const DEFAULT_WRITE_CHUNK_SIZE: usize = 4 * 1024;
+
+/// 默认允许的数据阶段载荷上限，超过时取消事务，避免格式错误的长度字段耗尽内存
+pub const DEFAULT_MAX_PAYLOAD_LEN: usize = 32 * 1024 * 1024;
+
+/// 对象读取进度
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObjectProgress {
+    pub handle: u32,
+    pub bytes_done: u64, // 已读取字节数
+    pub total: u64,      // 对象总大小(来自ObjectInfo)
+}
+
+impl ObjectProgress {
+    /// 完成百分比
+    pub fn percent(&self) -> u8 {
+        if self.total == 0 {
+            return 100;
+        }
+        (self.bytes_done.min(self.total) * 100 / self.total) as u8
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.bytes_done >= self.total
+    }
+}
+
+/// 批量操作(删除、保护)的结果，部分对象失败时不影响其余对象
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub done: Vec<u32>,             // 成功的对象句柄
+    pub failed: Vec<(u32, Error)>,  // 失败的对象句柄及原因
+}
+
+impl BatchReport {
+    /// 是否全部成功
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// SetObjectProtection的保护状态
+const PROTECTION_NONE: u32 = 0x0000;
+const PROTECTION_READ_ONLY: u32 = 0x0001;
 
 /// PTP容器信息结构体
 #[derive(Debug)]
@@ -35,6 +94,18 @@ struct PtpContainerInfo {
 /// PTP容器信息头大小(字节)
 const PTP_CONTAINER_INFO_SIZE: usize = 12;
 
+/// 每个容器首次读取的字节数，等于高速批量端点的包大小，足以容纳容器头和响应参数
+const FIRST_READ_SIZE: usize = 512;
+
+/// 长度未知的数据阶段每次扩大缓冲区的字节数
+const UNKNOWN_LEN_STEP: usize = 64 * 1024;
+
+/// 容器开始前允许连续读到的零长度包数，超过视为读取停滞
+const MAX_EMPTY_READS: u32 = 2;
+
+/// MTP数据容器长度未知时使用的长度值
+const MTP_UNKNOWN_CONTAINER_LEN: u32 = 0xFFFFFFFF;
+
 impl PtpContainerInfo {
     /// 从数据流解析PTP容器信息
     pub fn parse<R: ReadBytesExt>(mut r: R) -> Result<PtpContainerInfo, Error> {
@@ -45,11 +116,21 @@ impl PtpContainerInfo {
         let code = r.read_u16::<LittleEndian>()?;
         let tid = r.read_u32::<LittleEndian>()?;
 
+        // MTP设备传输超过4GB的对象时，数据容器长度字段为0xFFFFFFFF，
+        // 实际长度只能通过读取到短包为止来确定
+        let payload_len = if len == MTP_UNKNOWN_CONTAINER_LEN {
+            usize::MAX
+        } else if (len as usize) < PTP_CONTAINER_INFO_SIZE {
+            return Err(Error::Malformed(format!("容器长度 {} 小于头部长度", len)));
+        } else {
+            len as usize - PTP_CONTAINER_INFO_SIZE
+        };
+
         Ok(PtpContainerInfo {
-            payload_len: len as usize - PTP_CONTAINER_INFO_SIZE,
-            kind: kind,
-            tid: tid,
-            code: code,
+            payload_len,
+            kind,
+            tid,
+            code,
         })
     }
 
@@ -59,88 +140,488 @@ impl PtpContainerInfo {
     }
 }
 
+/// 数据阶段载荷的目标缓冲区
+enum PayloadSink<'a> {
+    Vec(&'a mut Vec<u8>),  // 按载荷长度调整大小，已有容量可以复用
+    Slice(&'a mut [u8]),   // 调用方提供的固定缓冲区
+}
+
+impl PayloadSink<'_> {
+    /// 开始新的事务前清空
+    fn reset(&mut self) {
+        if let PayloadSink::Vec(v) = self {
+            v.clear();
+        }
+    }
+
+    /// 为`len`字节的载荷准备空间，长度未知(usize::MAX)时先准备一部分，不超过`limit`；固定缓冲区最多提供其自身长度
+    fn prepare(&mut self, len: usize, limit: usize) -> &mut [u8] {
+        match self {
+            PayloadSink::Vec(v) => {
+                let len = if len == usize::MAX { v.capacity().max(UNKNOWN_LEN_STEP).min(limit) } else { len };
+                v.resize(len, 0);
+                &mut v[..]
+            }
+            PayloadSink::Slice(out) => {
+                let len = len.min(out.len());
+                &mut out[..len]
+            }
+        }
+    }
+
+    /// 扩大长度未知的载荷的空间，不超过`limit`；固定缓冲区或已达上限时无法扩大
+    fn grow(&mut self, limit: usize) -> Option<&mut [u8]> {
+        match self {
+            PayloadSink::Vec(v) if v.len() < limit => {
+                let len = (v.len() + UNKNOWN_LEN_STEP).min(limit);
+                v.resize(len, 0);
+                Some(&mut v[..])
+            }
+            PayloadSink::Vec(_) => None,
+            PayloadSink::Slice(_) => None,
+        }
+    }
+
+    /// 载荷读取完成，截去未使用的空间
+    fn finish(&mut self, len: usize) {
+        if let PayloadSink::Vec(v) = self {
+            v.truncate(len);
+        }
+    }
+
+    /// 已读取的载荷
+    fn filled(&self, len: usize) -> &[u8] {
+        match self {
+            PayloadSink::Vec(v) => &v[..len],
+            PayloadSink::Slice(out) => &out[..len],
+        }
+    }
+}
+
+/// 读取停滞检测：连续读到零长度包，或超过超时时间没有收到任何数据
+struct StallGuard {
+    timeout: Duration,
+    last_progress: Instant,
+    empty_reads: u32,
+}
+
+impl StallGuard {
+    fn new(timeout: Duration) -> Self {
+        StallGuard {
+            timeout,
+            last_progress: Instant::now(),
+            empty_reads: 0,
+        }
+    }
+
+    /// 记录一次读取的字节数，停滞时返回超时错误以便按重试策略重试
+    fn check(&mut self, got: usize) -> Result<(), Error> {
+        if got > 0 {
+            self.last_progress = Instant::now();
+            self.empty_reads = 0;
+            return Ok(());
+        }
+        self.empty_reads += 1;
+        // 超时为0表示无限等待，只按连续空读的次数判断停滞
+        let timed_out = !self.timeout.is_zero() && self.last_progress.elapsed() > self.timeout;
+        if self.empty_reads > MAX_EMPTY_READS || timed_out {
+            return Err(Error::Timeout(format!(
+                "读取停滞: 连续 {} 次没有收到数据，距上次收到数据 {}ms",
+                self.empty_reads,
+                self.last_progress.elapsed().as_millis()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// 传输层接口使用的毫秒超时
+fn millis(timeout: Duration) -> Option<u64> {
+    Some(timeout.as_millis() as u64)
+}
+
+/// 把写入块大小向下对齐到包大小的整数倍，至少要能放下容器头
+fn align_chunk(bytes: usize, packet_size: usize) -> usize {
+    let min_packets = PTP_CONTAINER_INFO_SIZE.div_ceil(packet_size);
+    (bytes / packet_size).max(min_packets) * packet_size
+}
+
+/// 事务重试策略
+/// 相机返回DeviceBusy或传输超时时按指数退避重试整个事务，每次重试使用新的事务ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,        // 总尝试次数，1表示不重试
+    pub initial_backoff: Duration, // 第一次重试前的等待时间
+    pub max_backoff: Duration,     // 等待时间上限
+    pub retry_on_timeout: bool,    // 超时是否重试；超时的命令可能已被相机执行，默认不重试，只应对只读命令开启
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+            // 超时后端点中可能还有上一事务的残留数据，删除、拍摄等命令重发还可能执行两次
+            retry_on_timeout: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// 不重试
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// 错误是否值得重试
+    pub fn should_retry(&self, error: &Error) -> bool {
+        match error {
+            Error::Response(code) => *code == StandardResponseCode::DeviceBusy,
+            Error::Timeout(_) => self.retry_on_timeout,
+            _ => false,
+        }
+    }
+
+    /// 第`attempt`次重试(从1开始)前的等待时间
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// 事务各阶段的超时，0表示无限等待
+/// 大对象的数据阶段可能需要数十秒，而命令和响应阶段通常很快，因此分开设置；
+/// `Default`是按机型配置和链路校准的参考值，相机在设置超时之前各阶段无限等待
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TransactionTimeouts {
+    pub command: Duration,  // 写出命令容器
+    pub data_out: Duration, // 写出主机数据阶段
+    pub data_in: Duration,  // 等待并读取相机的数据阶段
+    pub response: Duration, // 读取响应容器
+}
+
+impl Default for TransactionTimeouts {
+    fn default() -> Self {
+        TransactionTimeouts {
+            command: Duration::from_secs(5),
+            data_out: Duration::from_secs(30),
+            data_in: Duration::from_secs(60),
+            response: Duration::from_secs(10),
+        }
+    }
+}
+
+impl TransactionTimeouts {
+    /// 所有阶段无限等待
+    pub const INFINITE: TransactionTimeouts = TransactionTimeouts {
+        command: Duration::ZERO,
+        data_out: Duration::ZERO,
+        data_in: Duration::ZERO,
+        response: Duration::ZERO,
+    };
+
+    /// 所有阶段使用相同的超时
+    pub fn uniform(timeout: Duration) -> Self {
+        TransactionTimeouts {
+            command: timeout,
+            data_out: timeout,
+            data_in: timeout,
+            response: timeout,
+        }
+    }
+}
+
+/// OpenSession默认使用的会话ID
+pub const DEFAULT_SESSION_ID: u32 = 1;
+
+/// 数据阶段每读取这么多字节检查一次取消请求
+const CANCEL_CHECK_SIZE: usize = 64 * 1024;
+/// 取消后清空输入端点时单次读取的超时，超时说明相机已停止发送
+const CANCEL_FLUSH_TIMEOUT: Duration = Duration::from_millis(100);
+/// 取消后等待相机恢复空闲的最长时间
+const CANCEL_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 取消句柄，克隆后可在其他任务或线程中中止相机正在进行的对象读取
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<Mutex<CancelState>>);
+
+#[derive(Debug, Default)]
+struct CancelState {
+    reads: u32,             // 进行中的对象读取层数
+    pending: Option<bool>,  // 未处理的取消请求，true表示在读取进行中发出
+}
+
+impl CancelToken {
+    /// 请求取消当前的读取；没有进行中的读取时对下一次读取生效
+    pub fn cancel(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.pending = Some(state.reads > 0);
+    }
+
+    /// 是否有未处理的取消请求
+    pub fn is_cancelled(&self) -> bool {
+        self.0.lock().unwrap().pending.is_some()
+    }
+
+    /// 标记一次对象读取开始，返回的守卫释放时读取结束
+    /// 读取期间发出但没有被处理的取消请求属于这次读取，随读取结束丢弃，不会中止下一次读取
+    pub fn begin_read(&self) -> ReadScope {
+        self.0.lock().unwrap().reads += 1;
+        ReadScope(self.clone())
+    }
+
+    /// 取出取消请求，返回之前是否已请求取消
+    fn take(&self) -> bool {
+        self.0.lock().unwrap().pending.take().is_some()
+    }
+}
+
+/// 进行中的对象读取，由`CancelToken::begin_read`返回
+#[derive(Debug)]
+pub struct ReadScope(CancelToken);
+
+impl Drop for ReadScope {
+    fn drop(&mut self) {
+        let mut state = self.0 .0.lock().unwrap();
+        state.reads -= 1;
+        if state.reads == 0 && state.pending == Some(true) {
+            log::debug!("读取已结束，丢弃读取期间的取消请求");
+            state.pending = None;
+        }
+    }
+}
+
+/// 确认令牌的有效期，超时后需要重新确认
+const DANGER_TOKEN_TTL: Duration = Duration::from_secs(30);
+
+/// 会清除数据的相机操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DangerousOperation {
+    FormatStore(u32), // 格式化存储(存储ID)
+    ResetDevice,      // 复位相机，关闭所有会话
+}
+
+/// 危险操作的确认令牌
+///
+/// 只能由`PtpCamera::confirm_danger`签发，不能克隆，只能使用一次；
+/// 只对签发时指定的操作有效，签发新令牌或超过有效期后作废
+#[derive(Debug)]
+pub struct DangerToken {
+    operation: DangerousOperation,
+    serial: u64,
+    issued: Instant,
+}
+
+impl DangerToken {
+    /// 令牌确认的操作
+    pub fn operation(&self) -> DangerousOperation {
+        self.operation
+    }
+}
+
 /// PTP相机类
-pub struct PtpCamera {
-    iface: u8,                      // 接口号
-    ep_in: u8,                      // 输入端点
-    ep_out: u8,                     // 输出端点
-    _ep_int: u8,                    // 中断端点
+///
+/// 容器的拼装与解析、事务状态和重试都在这里完成，字节的收发交给传输层`T`；
+/// 默认是ESP32上的USB链路，主机上可以换成`MockTransport`或`ReplayTransport`
+pub struct PtpCamera<T: PtpTransport = DefaultTransport> {
+    transport: T,                   // PTP传输层
+    write_chunk_size: usize,        // 数据阶段每次批量写入的字节数，为包大小的整数倍
+    read_chunk_size: u32,           // 分块读取对象时每块的字节数
+    max_payload_len: usize,         // 允许的数据阶段载荷上限
     current_tid: u32,               // 当前事务ID
-    handle: UsbDevice<'static>,     // Embassy-USB设备句柄
-    transport: Arc<Mutex<PtpUsbTransport>>, // PTP传输层
+    session_id: u32,                // OpenSession使用的会话ID
+    retry: RetryPolicy,             // 事务重试策略
+    timeouts: TransactionTimeouts,  // 默认的分阶段超时
+    auto_reopen: bool,              // 收到SessionNotOpen时重开会话并重放命令
+    last_activity: Instant,         // 最近一次成功事务的时间
+    properties: BTreeMap<u16, PtpPropInfo>, // 会话打开后缓存的设备属性描述
+    tracer: Option<TraceHandle>,    // 事务追踪，None表示不记录
+    response_params: Vec<u32>,      // 最近一次成功事务的响应参数
+    cancel: CancelToken,            // 中止进行中的对象读取
+    partial64: Option<PartialObject64>, // 相机支持的64位偏移分块读取命令
+    operations: Option<Vec<u16>>,   // 最近一次读取的DeviceInfo中列出的操作
+    check_operations: bool,         // 发送命令前按操作列表检查
+    danger_serial: u64,             // 最近一次签发的确认令牌序号，0表示没有有效令牌
+    quirks: Quirks,                 // 机型兼容表中该相机的兼容性问题
 }
 
+#[cfg(target_os = "espidf")]
 impl PtpCamera {
     /// 创建新的PTP相机实例
     /// 
     /// 使用异步API从USB设备初始化PTP相机
     pub async fn new(device: UsbDevice<'static>, transport: PtpUsbTransport) -> Result<PtpCamera, Error> {
-        // 获取配置描述符
-        let config = device.current_config_descriptor().await;
-        
-        // 查找PTP/MTP接口（类代码为6）
-        let mut interface_number = 0;
-        let mut interface_found = false;
-        let mut ep_in = 0;
-        let mut ep_out = 0;
-        let mut ep_int = 0;
-        
-        // 遍历所有接口查找PTP/MTP接口
-        for iface in config.interfaces() {
-            for alt_setting in iface.alt_settings() {
-                if alt_setting.class_code() == 6 {  // PTP/MTP类代码
-                    interface_number = iface.interface_number();
-                    interface_found = true;
-                    
-                    // 查找端点
-                    for endpoint in alt_setting.endpoints() {
-                        let addr = endpoint.address();
-                        
-                        // 根据端点类型和方向分配
-                        if endpoint.transfer_type() == embassy_usb::host::TransferType::Bulk {
-                            if endpoint.direction() == embassy_usb::host::Direction::In {
-                                ep_in = addr;
-                            } else {
-                                ep_out = addr;
-                            }
-                        } else if endpoint.transfer_type() == embassy_usb::host::TransferType::Interrupt
-                                  && endpoint.direction() == embassy_usb::host::Direction::In {
-                            ep_int = addr;
-                        }
-                    }
-                    break;
-                }
-            }
-            if interface_found {
-                break;
-            }
+        Ok(PtpCamera::with_transport(UsbCameraLink::open(device, transport).await?))
+    }
+}
+
+impl<T: PtpTransport> PtpCamera<T> {
+    /// 使用任意传输层创建PTP相机实例
+    /// USB链路按VID/PID从机型兼容表中取得该相机的兼容性问题
+    pub fn with_transport(transport: T) -> PtpCamera<T> {
+        let quirks = transport.usb_id().map(|(vid, pid)| quirks::lookup(vid, pid)).unwrap_or_default();
+        if !quirks.is_empty() {
+            log::info!("相机有已知的兼容性问题: {:?}", quirks);
         }
-        
-        if !interface_found {
-            return Err(Error::NotFound("未找到PTP/MTP接口".into()));
+        PtpCamera {
+            write_chunk_size: align_chunk(DEFAULT_WRITE_CHUNK_SIZE, transport.max_packet_size()),
+            read_chunk_size: DEFAULT_STREAM_CHUNK_SIZE,
+            max_payload_len: DEFAULT_MAX_PAYLOAD_LEN,
+            transport,
+            current_tid: 0,
+            session_id: DEFAULT_SESSION_ID,
+            retry: RetryPolicy::default(),
+            timeouts: TransactionTimeouts::INFINITE,
+            auto_reopen: false,
+            last_activity: Instant::now(),
+            properties: BTreeMap::new(),
+            tracer: None,
+            response_params: Vec::new(),
+            cancel: CancelToken::default(),
+            partial64: None,
+            operations: None,
+            check_operations: false,
+            danger_serial: 0,
+            quirks,
         }
-        
-        // 确保找到了必要的端点
-        if ep_in == 0 || ep_out == 0 {
-            return Err(Error::NotFound("未找到必要的端点".into()));
+    }
+
+    /// 访问传输层
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// 设置事务重试策略
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// 当前的事务重试策略
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// 设置默认的分阶段超时(通常按机型配置或链路校准)，未设置时各阶段无限等待
+    pub fn set_timeouts(&mut self, timeouts: TransactionTimeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// 当前的分阶段超时
+    pub fn timeouts(&self) -> TransactionTimeouts {
+        self.timeouts
+    }
+
+    /// 设置数据阶段每次批量写入的字节数，向下对齐到输出端点的包大小(至少一个包)
+    /// 越大写入效率越高，但需要同样大小的临时缓冲区
+    pub fn set_write_chunk_size(&mut self, bytes: usize) {
+        self.write_chunk_size = align_chunk(bytes, self.transport.max_packet_size());
+    }
+
+    /// 数据阶段每次批量写入的字节数
+    pub fn write_chunk_size(&self) -> usize {
+        self.write_chunk_size
+    }
+
+    /// 设置下载和流式读取对象时每块的字节数(例如内存压力下缩小)，之后开始的读取生效
+    pub fn set_read_chunk_size(&mut self, bytes: u32) {
+        self.read_chunk_size = bytes.max(1);
+    }
+
+    /// 下载和流式读取对象时每块的字节数
+    pub fn read_chunk_size(&self) -> u32 {
+        self.read_chunk_size
+    }
+
+    /// 设置允许的数据阶段载荷上限；相机声明的长度超过上限，或长度未知的数据超过上限时取消事务并返回错误
+    /// 读入调用方缓冲区的数据阶段(分块读取对象)不受影响，缓冲区本身限制了长度
+    pub fn set_max_payload_len(&mut self, bytes: usize) {
+        self.max_payload_len = bytes.max(FIRST_READ_SIZE);
+    }
+
+    /// 允许的数据阶段载荷上限
+    pub fn max_payload_len(&self) -> usize {
+        self.max_payload_len
+    }
+
+    /// 设置OpenSession使用的会话ID，多个主机或多个相机共用一条链路时用于区分会话；0无效，会被忽略
+    pub fn set_session_id(&mut self, session_id: u32) {
+        if session_id == 0 {
+            log::warn!("会话ID不能为0，保持 {}", self.session_id);
+            return;
         }
-        
-        // 声明接口
-        device.claim_interface(interface_number).await
-              .map_err(|e| Error::USB(format!("无法声明接口: {:?}", e)))?;
-        
-        log::debug!("已找到并声明PTP/MTP接口 {}", interface_number);
-        
-        // 创建PTP相机实例
-        Ok(PtpCamera {
-            iface: interface_number,
-            ep_in,
-            ep_out,
-            _ep_int: ep_int,
-            current_tid: 0,
-            handle: device,
-            transport: Arc::new(Mutex::new(transport)),
-        })
+        self.session_id = session_id;
+    }
+
+    /// OpenSession使用的会话ID
+    pub fn session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// 设置是否在相机报告会话未打开(例如相机休眠唤醒后)时自动重开会话并重放命令
+    pub fn set_auto_reopen(&mut self, enabled: bool) {
+        self.auto_reopen = enabled;
+    }
+
+    /// 设置是否在发送命令前检查DeviceInfo的操作列表，未列出的操作直接返回`Error::Unsupported`，
+    /// 不必等相机返回OperationNotSupported；尚未读取过DeviceInfo时不检查
+    pub fn set_check_operations(&mut self, enabled: bool) {
+        self.check_operations = enabled;
+    }
+
+    /// 最近一次读取的DeviceInfo是否列出了该操作；尚未读取过DeviceInfo时返回None
+    pub fn supports_operation(&self, code: CommandCode) -> Option<bool> {
+        self.operations.as_ref().map(|ops| ops.contains(&code))
+    }
+
+    /// 覆盖从机型兼容表中取得的兼容性问题
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// 该相机的兼容性问题
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// 能否用GetPartialObject分块读取；兼容表标记了不支持或DeviceInfo没有列出该操作时只能整体读取
+    pub fn supports_partial_reads(&self) -> bool {
+        !self.quirks.no_partial_object && self.supports_operation(StandardCommandCode::GetPartialObject) != Some(false)
+    }
+
+    /// 相机对GetPartialObject返回OperationNotSupported时记下，之后的读取直接整体读取
+    pub fn disable_partial_reads(&mut self) {
+        log::warn!("相机不支持GetPartialObject，改为用GetObject整体读取对象");
+        self.quirks.no_partial_object = true;
+    }
+
+    /// 设置事务追踪器，之后收发的容器都会交给追踪器(追踪器自身可随时开关)
+    pub fn set_tracer(&mut self, tracer: Option<TraceHandle>) {
+        self.tracer = tracer;
+    }
+
+    fn trace(&self, direction: TraceDirection, kind: PtpContainerType, code: u16, tid: u32, payload: &[u8]) {
+        if let Some(tracer) = &self.tracer {
+            tracer.lock().unwrap().record(direction, kind, code, tid, payload);
+        }
+    }
+
+    /// 取消句柄，调用`cancel`后正在进行的数据阶段会被中止(PTP取消请求)，读取返回`Error::Cancelled`
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// 最近一次成功事务的响应参数(例如CopyObject返回的新句柄)
+    pub fn response_params(&self) -> &[u32] {
+        &self.response_params
+    }
+
+    /// 距最近一次成功事务的时间
+    pub fn idle_time(&self) -> Duration {
+        self.last_activity.elapsed()
     }
 
     /// 执行PTP事务
@@ -149,19 +630,113 @@ impl PtpCamera {
     ///  - 命令数据阶段 (可选，如果`data`为Some)
     ///  - 响应数据阶段 (可选，如果响应包含有效载荷)
     ///  - 响应状态阶段
-    /// 注意: 每个阶段都涉及一个独立的USB传输，`timeout`用于每个阶段，
-    /// 所以总时间可能会超过`timeout`。
+    ///
+    /// 注意: 每个阶段都涉及一个独立的USB传输，各阶段使用`timeouts`中对应的超时，
+    /// `timeouts`为None时使用`set_timeouts`设置的默认值，没有设置过时无限等待。
+    /// DeviceBusy和超时按重试策略自动重试，重试用尽后返回最后一次的错误。
+    /// 启用`set_auto_reopen`时，SessionNotOpen会触发一次重开会话并重放命令
     pub async fn command(
         &mut self,
         code: CommandCode,
         params: &[u32],
         data: Option<&[u8]>,
-        timeout: Option<Duration>
+        timeouts: Option<TransactionTimeouts>
     ) -> Result<Vec<u8>, Error> {
+        let mut payload = Vec::new();
+        self.transact(code, params, data, timeouts, &mut PayloadSink::Vec(&mut payload)).await?;
+        Ok(payload)
+    }
+
+    /// 执行PTP事务，数据阶段的载荷直接读入`out`，返回载荷字节数
+    /// 载荷超过`out`的长度时返回错误；大文件分块读取时重复使用同一个缓冲区，不产生堆分配
+    pub async fn command_into(
+        &mut self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeouts: Option<TransactionTimeouts>,
+        out: &mut [u8],
+    ) -> Result<usize, Error> {
+        self.transact(code, params, data, timeouts, &mut PayloadSink::Slice(out)).await
+    }
+
+    /// 执行PTP事务，数据阶段的载荷读入从`pool`取出的缓冲区
+    pub async fn command_pooled(
+        &mut self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeouts: Option<TransactionTimeouts>,
+        pool: &BufferPool,
+    ) -> Result<PooledBuffer, Error> {
+        let mut payload = pool.acquire();
+        self.transact(code, params, data, timeouts, &mut PayloadSink::Vec(&mut payload)).await?;
+        Ok(payload)
+    }
 
-        // 超时为0表示无限超时
-        let timeout = timeout.unwrap_or(Duration::new(0, 0));
+    /// 按重试策略执行事务
+    async fn transact(
+        &mut self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeouts: Option<TransactionTimeouts>,
+        sink: &mut PayloadSink<'_>,
+    ) -> Result<usize, Error> {
+        // 读取DeviceInfo和开关会话的命令总是发送，否则无法刷新操作列表
+        let always_allowed = matches!(
+            code,
+            StandardCommandCode::GetDeviceInfo | StandardCommandCode::OpenSession | StandardCommandCode::CloseSession
+        );
+        if self.check_operations && !always_allowed && self.supports_operation(code) == Some(false) {
+            return Err(Error::Unsupported(code));
+        }
+        let timeouts = timeouts.unwrap_or(self.timeouts);
+        let policy = self.retry;
+        let mut attempt = 1;
+        let mut reopened = false;
+        loop {
+            match self.command_once(code, params, data, &timeouts, sink).await {
+                Err(e) if attempt < policy.max_attempts && policy.should_retry(&e) => {
+                    let backoff = policy.backoff(attempt);
+                    log::debug!("0x{:04x} 失败({})，{}ms 后第 {} 次重试", code, e, backoff.as_millis(), attempt);
+                    Timer::after(EmbassyDuration::from_millis(backoff.as_millis() as u64)).await;
+                    attempt += 1;
+                }
+                Err(Error::Response(StandardResponseCode::SessionNotOpen))
+                    if self.auto_reopen && !reopened && code != StandardCommandCode::OpenSession =>
+                {
+                    log::warn!("0x{:04x} 返回会话未打开，重开会话后重放", code);
+                    reopened = true;
+                    // 新会话的OpenSession事务ID从0开始
+                    self.current_tid = 0;
+                    let mut reply = Vec::new();
+                    let session_id = self.session_id;
+                    match self.command_once(StandardCommandCode::OpenSession, &[session_id], None, &timeouts, &mut PayloadSink::Vec(&mut reply)).await {
+                        // 会话其实仍然打开，直接重放
+                        Ok(_) | Err(Error::Response(StandardResponseCode::SessionAlreadyOpen)) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
+                result => {
+                    if result.is_ok() {
+                        self.last_activity = Instant::now();
+                    }
+                    return result;
+                }
+            }
+        }
+    }
 
+    /// 执行一次PTP事务，不重试；数据阶段的载荷写入`sink`，返回载荷字节数
+    async fn command_once(
+        &mut self,
+        code: CommandCode,
+        params: &[u32],
+        data: Option<&[u8]>,
+        timeouts: &TransactionTimeouts,
+        sink: &mut PayloadSink<'_>,
+    ) -> Result<usize, Error> {
         // 获取事务ID并增加计数器
         let tid = self.current_tid;
         self.current_tid += 1;
@@ -173,30 +748,46 @@ impl PtpCamera {
         }
 
         // 写入事务的命令阶段
-        self.write_txn_phase(PtpContainerType::Command, code, tid, &request_payload, timeout).await?;
+        self.write_txn_phase(PtpContainerType::Command, code, tid, &request_payload, timeouts.command).await?;
 
         // 如果有数据，写入数据阶段
         if let Some(data) = data {
-            self.write_txn_phase(PtpContainerType::Data, code, tid, data, timeout).await?;
+            self.write_txn_phase(PtpContainerType::Data, code, tid, data, timeouts.data_out).await?;
         }
 
         // 命令阶段之后是数据阶段(可选)和响应阶段
-        // 读取这两个阶段，检查响应的状态，并返回数据载荷(如果有)
-        let mut data_phase_payload = vec![];
+        // 读取这两个阶段，检查响应的状态，并返回数据载荷的长度(如果有)
+        // 主机没有发送数据时，相机可能先花较长时间准备数据阶段，首次读取使用数据阶段的超时
+        let mut read_timeout = if data.is_some() { timeouts.response } else { timeouts.data_in };
+        let mut head = [0u8; FIRST_READ_SIZE];
+        let mut data_phase_len = 0;
+        let mut truncated = false;
+        sink.reset();
         loop {
-            let (container, payload) = self.read_txn_phase(timeout).await?;
+            let (container, len, cut) = self.read_txn_phase(read_timeout, &mut head, sink).await?;
+            read_timeout = timeouts.response;
             if !container.belongs_to(tid) {
                 return Err(Error::Malformed(format!("事务ID不匹配，收到{}，期望{}", container.tid, tid)));
             }
             match container.kind {
                 PtpContainerType::Data => {
-                    data_phase_payload = payload;
+                    self.trace(TraceDirection::In, container.kind, container.code, container.tid, sink.filled(len));
+                    data_phase_len = len;
+                    truncated = cut;
                 },
                 PtpContainerType::Response => {
+                    let params = &head[PTP_CONTAINER_INFO_SIZE..PTP_CONTAINER_INFO_SIZE + len];
+                    self.trace(TraceDirection::In, container.kind, container.code, container.tid, params);
                     if container.code != StandardResponseCode::Ok {
                         return Err(Error::Response(container.code));
                     }
-                    return Ok(data_phase_payload);
+                    if truncated {
+                        return Err(Error::Malformed(format!("数据阶段超过缓冲区的 {} 字节", data_phase_len)));
+                    }
+                    self.response_params = params.chunks_exact(4)
+                        .map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]]))
+                        .collect();
+                    return Ok(data_phase_len);
                 },
                 _ => {}
             }
@@ -206,12 +797,13 @@ impl PtpCamera {
     /// 写入事务阶段
     async fn write_txn_phase(&mut self, kind: PtpContainerType, code: CommandCode, tid: u32, payload: &[u8], timeout: Duration) -> Result<(), Error> {
         log::trace!("写入 {:?} - 0x{:04x} ({}), tid:{}", kind, code, StandardCommandCode::name(code).unwrap_or("未知"), tid);
+        self.trace(TraceDirection::Out, kind, code, tid, payload);
 
-        // 块大小，必须是端点包大小的倍数
-        const CHUNK_SIZE: usize = 1024 * 1024; // 1MB
+        // 块大小是端点包大小的倍数，除最后一块外每块都是满包，相机不会把中间的块当作传输结束
+        let chunk_size = self.write_chunk_size;
 
         // 第一个块包含头信息，其载荷必须被复制到临时缓冲区
-        let first_chunk_payload_bytes = min(payload.len(), CHUNK_SIZE - PTP_CONTAINER_INFO_SIZE);
+        let first_chunk_payload_bytes = min(payload.len(), chunk_size - PTP_CONTAINER_INFO_SIZE);
         let mut buf = Vec::with_capacity(first_chunk_payload_bytes + PTP_CONTAINER_INFO_SIZE);
         
         // 写入PTP头信息
@@ -223,101 +815,547 @@ impl PtpCamera {
         // 添加载荷的第一部分
         buf.extend_from_slice(&payload[..first_chunk_payload_bytes]);
         
-        // 转换为Embassy Duration
-        let embassy_timeout = EmbassyDuration::from_millis(timeout.as_millis() as u64);
-        
-        // 使用Embassy的异步API进行批量写入
-        self.handle.bulk_out(self.ep_out, &buf, embassy_timeout).await
-            .map_err(|e| Error::USB(format!("批量写入失败: {:?}", e)))?;
+        self.transport.bulk_write(&buf, millis(timeout)).await?;
 
         // 写入后续块，直接从源切片读取
-        for chunk in payload[first_chunk_payload_bytes..].chunks(CHUNK_SIZE) {
-            self.handle.bulk_out(self.ep_out, chunk, embassy_timeout).await
-                .map_err(|e| Error::USB(format!("批量写入失败: {:?}", e)))?;
+        for chunk in payload[first_chunk_payload_bytes..].chunks(chunk_size) {
+            self.transport.bulk_write(chunk, millis(timeout)).await?;
+        }
+
+        // 容器总长度恰好是包大小的整数倍时，最后一个包也是满包，需要补发零长度包结束传输；
+        // 收到零长度包会卡住的相机按容器头中的长度判断结束，不补发
+        if (payload.len() + PTP_CONTAINER_INFO_SIZE) % self.transport.max_packet_size() == 0 && !self.quirks.no_zero_length_packet {
+            self.transport.bulk_write(&[], millis(timeout)).await?;
         }
 
         Ok(())
     }
 
-    /// 读取事务阶段的辅助方法
-    async fn read_txn_phase(&mut self, timeout: Duration) -> Result<(PtpContainerInfo, Vec<u8>), Error> {
-        // 为读取分配缓冲区
-        let mut buffer = [0u8; 8 * 1024]; // 8KB缓冲区
-        
-        // 转换为Embassy Duration
-        let embassy_timeout = EmbassyDuration::from_millis(timeout.as_millis() as u64);
-        
-        // 使用Embassy的异步API进行批量读取
-        let n = self.handle.bulk_in(self.ep_in, &mut buffer, embassy_timeout).await
-            .map_err(|e| Error::USB(format!("批量读取失败: {:?}", e)))?;
-            
-        // 复制数据以便进一步处理
-        let buf = &buffer[..n];
+    /// 读取一个容器
+    /// 容器可以分多次读取到达：长度已知时一直读到声明的长度为止，中途的短包只是分段，零长度包说明相机提前结束了传输；
+    /// 长度未知(0xFFFFFFFF)的数据阶段读到短包为止。容器头和首包读入`head`，数据容器的载荷写入`sink`，
+    /// 首包之后的部分直接从端点读入目标缓冲区，其他容器的载荷(响应参数)留在`head`中。
+    /// 返回容器信息、写入的载荷字节数，以及载荷是否因缓冲区不足被截断
+    async fn read_txn_phase(
+        &mut self,
+        timeout: Duration,
+        head: &mut [u8; FIRST_READ_SIZE],
+        sink: &mut PayloadSink<'_>,
+    ) -> Result<(PtpContainerInfo, usize, bool), Error> {
+        let mut stall = StallGuard::new(timeout);
+
+        // 数据阶段长度恰好是包大小的整数倍时相机会补发一个零长度包，容器开始前的零长度包跳过，连续多次则视为停滞；
+        // 容器头开始之后再读到零长度包说明传输已结束，容器头不完整
+        let mut n = 0;
+        let mut short = false;
+        while n < PTP_CONTAINER_INFO_SIZE {
+            let want = FIRST_READ_SIZE - n;
+            let got = self.transport.bulk_read(&mut head[n..], millis(timeout)).await?;
+            if got == 0 && n > 0 {
+                return Err(Error::Malformed(format!("容器头不完整，只收到 {} 字节", n)));
+            }
+            stall.check(got)?;
+            n += got;
+            short = got < want;
+        }
 
         // 解析容器信息
-        let cinfo = PtpContainerInfo::parse(&buf[..])?;
-        trace!("容器 {:?}", cinfo);
-
-        // 没有载荷？结束了
-        if cinfo.payload_len == 0 {
-            return Ok((cinfo, vec![]));
-        }
-
-        // 分配足够的空间，多分配1个避免为尾部短包再读一次
-        let mut payload = Vec::with_capacity(cinfo.payload_len + 1);
-        payload.extend_from_slice(&buf[PTP_CONTAINER_INFO_SIZE..]);
-
-        // 如果响应没有完全放入原始buf，或者初始读取刚好满足，可能还需要读取零长度包
-        if payload.len() < cinfo.payload_len || buf.len() == unintialized_buf.len() {
-            unsafe {
-                let p = payload.as_mut_ptr().offset(payload.len() as isize);
-                let pslice = slice::from_raw_parts_mut(p, payload.capacity() - payload.len());
-                let n = self.handle.read_bulk(self.ep_in, pslice, timeout)?;
-                let sz = payload.len();
-                payload.set_len(sz + n);
-                trace!("  bulk rx {}, ({}/{})", n, payload.len(), payload.capacity());
+        let cinfo = PtpContainerInfo::parse(&head[..n])?;
+        log::trace!("容器 {:?}", cinfo);
+        let known_len = cinfo.payload_len != usize::MAX;
+        // 长度已知时只有零长度包结束传输，长度未知时短包结束传输
+        let mut ended = !known_len && short;
+
+        if cinfo.kind != PtpContainerType::Data {
+            // 响应和事件最多5个参数，整个容器都应在head中
+            let total = PTP_CONTAINER_INFO_SIZE.saturating_add(cinfo.payload_len);
+            if total > FIRST_READ_SIZE {
+                return Err(Error::Malformed(format!("{:?}容器长度 {} 超出范围", cinfo.kind, total)));
             }
+            while n < total {
+                let got = self.transport.bulk_read(&mut head[n..total], millis(timeout)).await?;
+                if got == 0 {
+                    return Err(Error::Malformed(format!("{:?}容器不完整，收到 {}/{} 字节", cinfo.kind, n, total)));
+                }
+                n += got;
+            }
+            let len = cinfo.payload_len;
+            return Ok((cinfo, len, false));
+        }
+
+        // 相机声明的长度超过上限时不分配缓冲区，取消事务让相机停止发送
+        let limit = self.max_payload_len;
+        let growable = matches!(sink, PayloadSink::Vec(_));
+        if known_len && cinfo.payload_len > limit && growable {
+            self.cancel_transaction(cinfo.tid).await?;
+            return Err(Error::Malformed(format!("数据阶段长度 {} 超过上限 {} 字节", cinfo.payload_len, limit)));
         }
 
-        Ok((cinfo, payload))
+        // 首包中的载荷复制到目标缓冲区，其余载荷直接读入
+        let first_len = (n - PTP_CONTAINER_INFO_SIZE).min(cinfo.payload_len);
+        let mut buf = sink.prepare(cinfo.payload_len, limit);
+        let mut done = first_len.min(buf.len());
+        buf[..done].copy_from_slice(&head[PTP_CONTAINER_INFO_SIZE..PTP_CONTAINER_INFO_SIZE + done]);
+        let mut truncated = done < first_len;
+        loop {
+            while !ended && done < buf.len() && done < cinfo.payload_len {
+                if self.cancel.take() {
+                    self.cancel_transaction(cinfo.tid).await?;
+                    return Err(Error::Cancelled);
+                }
+                // 分段读取，两段之间检查取消请求
+                let end = buf.len().min(done + CANCEL_CHECK_SIZE);
+                let want = end - done;
+                let got = self.transport.bulk_read(&mut buf[done..end], millis(timeout)).await?;
+                done += got;
+                ended = if known_len { got == 0 } else { got < want };
+            }
+            if ended || done >= cinfo.payload_len {
+                break;
+            }
+            // 目标缓冲区已满：长度未知时扩大后继续读到短包为止
+            match sink.grow(limit) {
+                Some(more) => buf = more,
+                None if !known_len && growable => {
+                    self.cancel_transaction(cinfo.tid).await?;
+                    return Err(Error::Malformed(format!("长度未知的数据阶段超过上限 {} 字节", limit)));
+                }
+                None => {
+                    truncated = true;
+                    break;
+                }
+            }
+        }
+        if truncated {
+            // 固定缓冲区放不下完整载荷：读完剩余数据以保持事务同步，由调用方读取响应后报告错误
+            let mut left = cinfo.payload_len.saturating_sub(done.max(first_len));
+            while !ended && left > 0 {
+                let want = left.min(FIRST_READ_SIZE);
+                let got = self.transport.bulk_read(&mut head[..want], millis(timeout)).await?;
+                left -= got;
+                ended = if known_len { got == 0 } else { got < want };
+            }
+        } else if known_len && done < cinfo.payload_len {
+            return Err(Error::Malformed(format!("数据阶段提前结束，收到 {}/{} 字节", done, cinfo.payload_len)));
+        }
+        sink.finish(done);
+        log::trace!("  bulk rx {} 字节", done);
+        Ok((cinfo, done, truncated))
+    }
+
+    /// 取消进行中的事务：请求设备取消(USB上为控制端点的类请求)，清空输入端点中剩余的数据，
+    /// 再读取设备状态直到相机不再忙；取消后相机不发送响应阶段，会话保持打开
+    async fn cancel_transaction(&mut self, tid: u32) -> Result<(), Error> {
+        log::info!("取消事务 {}", tid);
+        self.transport.request_cancel(tid).await?;
+
+        // 丢弃取消前已进入端点的数据，读到超时或零长度包为止
+        let mut scratch = [0u8; FIRST_READ_SIZE];
+        let mut flushed = 0;
+        loop {
+            match self.transport.bulk_read(&mut scratch, millis(CANCEL_FLUSH_TIMEOUT)).await {
+                Ok(0) | Err(Error::Timeout(_)) => break,
+                Ok(n) => flushed += n,
+                Err(e) => return Err(e),
+            }
+        }
+        log::debug!("取消后丢弃 {} 字节", flushed);
+
+        // 设备状态: 长度(u16) + 响应码(u16) + 参数
+        let deadline = Instant::now() + CANCEL_IDLE_TIMEOUT;
+        loop {
+            let code = self.transport.device_status().await?;
+            if code != StandardResponseCode::DeviceBusy {
+                log::debug!("取消完成，设备状态 0x{:04x}", code);
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout(format!("取消事务 {} 后相机一直忙", tid)));
+            }
+            Timer::after(EmbassyDuration::from_millis(50)).await;
+        }
+    }
+
+    /// 读取一个PTP事件
+    /// 在超时时间内没有事件时返回None；相机没有中断端点时返回错误
+    pub async fn poll_event(&mut self, timeout: Option<Duration>) -> Result<Option<PtpEvent>, Error> {
+        if !self.transport.has_events() {
+            return Err(Error::NotFound("相机没有中断端点，无法接收事件".into()));
+        }
+
+        // 事件容器最长为 12字节头 + 3个参数
+        let mut buffer = [0u8; 64];
+        let timeout = timeout.unwrap_or(Duration::from_millis(100));
+        let n = self.transport.read_interrupt_event(&mut buffer, millis(timeout)).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+
+        let event = PtpEvent::decode(&buffer[..n])?;
+        log::debug!("收到PTP事件 {}: {:?}", event.name().unwrap_or("厂商事件"), event);
+        Ok(Some(event))
+    }
+
+    /// 等待下一个PTP事件，期间按poll_interval轮询中断端点
+    pub async fn wait_event(&mut self, poll_interval: Duration) -> Result<PtpEvent, Error> {
+        loop {
+            if let Some(event) = self.poll_event(Some(poll_interval)).await? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// 订阅PTP事件，持续读取事件并交给回调处理，直到回调返回false或出错
+    pub async fn subscribe_events<F>(&mut self, poll_interval: Duration, mut on_event: F) -> Result<(), Error>
+    where
+        F: FnMut(PtpEvent) -> bool,
+    {
+        loop {
+            let event = self.wait_event(poll_interval).await?;
+            if !on_event(event) {
+                return Ok(());
+            }
+        }
     }
 
     /// 获取对象信息
-    pub fn get_objectinfo(&mut self, handle: u32, timeout: Option<Duration>) -> Result<PtpObjectInfo, Error> {
-        let data = self.command(StandardCommandCode::GetObjectInfo, &[handle], None, timeout)?;
-        Ok(PtpObjectInfo::decode(&data)?)
+    pub async fn get_objectinfo(&mut self, handle: u32, timeout: Option<Duration>) -> Result<PtpObjectInfo, Error> {
+        let data = self.command(StandardCommandCode::GetObjectInfo, &[handle], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        PtpObjectInfo::decode(&data)
+    }
+
+    /// 获取完整对象到内存
+    /// 只适用于缩略图等小对象；RAW等大文件请使用`stream_object`，否则会耗尽内存
+    pub async fn get_object(&mut self, handle: u32, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
+        let mut data = Vec::new();
+        self.stream_object(handle, DEFAULT_STREAM_CHUNK_SIZE, timeout, |chunk, _| {
+            data.extend_from_slice(chunk);
+            Ok(())
+        }).await?;
+        Ok(data)
     }
 
-    /// 获取完整对象
-    pub fn get_object(&mut self, handle: u32, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
-        self.command(StandardCommandCode::GetObject, &[handle], None, timeout)
+    /// 获取对象的缩略图(通常为JPEG)
+    pub async fn get_thumb(&mut self, handle: u32, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
+        self.command(StandardCommandCode::GetThumb, &[handle], None, timeout.map(TransactionTimeouts::uniform)).await
     }
 
     /// 获取部分对象
-    pub fn get_partialobject(&mut self, handle: u32, offset: u32, max: u32, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
-        self.command(StandardCommandCode::GetPartialObject, &[handle, offset, max], None, timeout)
+    pub async fn get_partialobject(&mut self, handle: u32, offset: u32, max: u32, timeout: Option<Duration>) -> Result<Vec<u8>, Error> {
+        if !self.supports_partial_reads() {
+            return Err(Error::Unsupported(StandardCommandCode::GetPartialObject));
+        }
+        self.command(StandardCommandCode::GetPartialObject, &[handle, offset, max], None, timeout.map(TransactionTimeouts::uniform)).await
+    }
+
+    /// 读取对象从`offset`开始的最多`out.len()`字节到`out`，返回实际读取的字节数
+    pub async fn get_partialobject_into(&mut self, handle: u32, offset: u32, out: &mut [u8], timeout: Option<Duration>) -> Result<usize, Error> {
+        if !self.supports_partial_reads() {
+            return Err(Error::Unsupported(StandardCommandCode::GetPartialObject));
+        }
+        let max = out.len() as u32;
+        self.command_into(StandardCommandCode::GetPartialObject, &[handle, offset, max], None, timeout.map(TransactionTimeouts::uniform), out).await
+    }
+
+    /// 64位偏移的分块读取，读取范围在4GB以内时使用标准GetPartialObject
+    /// 超出4GB且相机不支持64位命令时返回错误
+    pub async fn get_partialobject64_into(&mut self, handle: u32, offset: u64, out: &mut [u8], timeout: Option<Duration>) -> Result<usize, Error> {
+        if offset + out.len() as u64 <= u32::MAX as u64 {
+            return self.get_partialobject_into(handle, offset as u32, out, timeout).await;
+        }
+        let op = self.partial64.ok_or_else(|| {
+            Error::Malformed(format!("相机不支持64位偏移读取，无法读取对象 0x{:08x} 4GB之后的部分", handle))
+        })?;
+        let params = op.params(handle, offset, out.len() as u32);
+        self.command_into(op.code(), &params, None, timeout.map(TransactionTimeouts::uniform), out).await
+    }
+
+    /// 相机支持的64位偏移分块读取命令，会话打开时由设备信息确定
+    pub fn partial_object_64(&self) -> Option<PartialObject64> {
+        self.partial64
+    }
+
+    /// 对象大小；ObjectInfo中的32位大小为0xFFFFFFFF(超过4GB)时用MTP的ObjectSize属性读取实际大小
+    pub async fn get_object_size(&mut self, handle: u32, info: &PtpObjectInfo, timeout: Option<Duration>) -> Result<u64, Error> {
+        if info.ObjectCompressedSize != u32::MAX {
+            return Ok(info.ObjectCompressedSize as u64);
+        }
+        let data = self
+            .command(MtpCommandCode::GetObjectPropValue, &[handle, MtpObjectPropCode::ObjectSize as u32], None, timeout.map(TransactionTimeouts::uniform))
+            .await
+            .map_err(|e| Error::Malformed(format!("对象 0x{:08x} 超过4GB，且无法读取实际大小: {}", handle, e)))?;
+        Cursor::new(data).read_ptp_u64()
+    }
+
+    /// 分块读取对象，每读到一块就交给回调，内存占用不超过一个块
+    /// 总大小取自ObjectInfo；回调返回错误时中止读取并返回该错误。返回读取的总字节数
+    pub async fn stream_object<F>(
+        &mut self,
+        handle: u32,
+        chunk_size: u32,
+        timeout: Option<Duration>,
+        on_chunk: F,
+    ) -> Result<u64, Error>
+    where
+        F: FnMut(&[u8], ObjectProgress) -> Result<(), Error>,
+    {
+        self.stream_object_from(handle, 0, chunk_size, timeout, on_chunk).await
+    }
+
+    /// 从指定偏移开始分块读取对象，用于断点续传
+    /// 超过4GB的对象需要相机支持64位偏移的分块读取
+    pub async fn stream_object_from<F>(
+        &mut self,
+        handle: u32,
+        offset: u64,
+        chunk_size: u32,
+        timeout: Option<Duration>,
+        on_chunk: F,
+    ) -> Result<u64, Error>
+    where
+        F: FnMut(&[u8], ObjectProgress) -> Result<(), Error>,
+    {
+        self.stream_object_range(handle, offset, None, chunk_size, timeout, on_chunk).await
+    }
+
+    /// 分块读取对象从`offset`开始的`length`字节，`length`为None或超出对象末尾时读到末尾
+    /// 偏移超出对象大小时返回错误。返回读取结束时在对象中的位置
+    pub async fn stream_object_range<F>(
+        &mut self,
+        handle: u32,
+        offset: u64,
+        length: Option<u64>,
+        chunk_size: u32,
+        timeout: Option<Duration>,
+        mut on_chunk: F,
+    ) -> Result<u64, Error>
+    where
+        F: FnMut(&[u8], ObjectProgress) -> Result<(), Error>,
+    {
+        let info = self.get_objectinfo(handle, timeout).await?;
+        let total = self.get_object_size(handle, &info, timeout).await?;
+        if total > u32::MAX as u64 && self.partial64.is_none() {
+            return Err(Error::Malformed(format!("对象 0x{:08x} 超过4GB，相机不支持64位偏移读取", handle)));
+        }
+        if offset > total {
+            return Err(Error::Malformed(format!("偏移 {} 超出对象 0x{:08x} 的大小 {}", offset, handle, total)));
+        }
+        let end = length.map_or(total, |len| offset.saturating_add(len).min(total));
+        let chunk_size = chunk_size.max(1) as u64;
+
+        let mut progress = ObjectProgress {
+            handle,
+            bytes_done: offset,
+            total,
+        };
+        let _read = self.cancel.begin_read();
+        if !self.supports_partial_reads() {
+            return self.stream_whole_object(end, chunk_size as usize, timeout, progress, on_chunk).await;
+        }
+        // 整个对象复用同一个块缓冲区，数据阶段直接读入其中
+        let mut buffer = vec![0u8; min(chunk_size, end - offset) as usize];
+        let start = offset;
+        let mut offset = offset;
+        while offset < end {
+            if self.cancel.take() {
+                log::info!("已取消读取对象 0x{:08x}，完成 {}/{} 字节", handle, offset, total);
+                return Err(Error::Cancelled);
+            }
+            let want = min(chunk_size, end - offset) as usize;
+            let n = match self.get_partialobject64_into(handle, offset, &mut buffer[..want], timeout).await {
+                // 还没有交给回调任何数据，可以改为整体读取
+                Err(Error::Response(StandardResponseCode::OperationNotSupported)) if offset == start => {
+                    self.disable_partial_reads();
+                    return self.stream_whole_object(end, chunk_size as usize, timeout, progress, on_chunk).await;
+                }
+                result => result?,
+            };
+            if n == 0 {
+                return Err(Error::Malformed(format!("对象 0x{:08x} 在偏移 {} 处提前结束", handle, offset)));
+            }
+            offset += n as u64;
+            progress.bytes_done = offset;
+            on_chunk(&buffer[..n], progress)?;
+        }
+        Ok(progress.bytes_done)
+    }
+
+    /// 不支持分块读取的相机：用GetObject整体读取后按块交给回调，对象大小受载荷上限限制
+    async fn stream_whole_object<F>(
+        &mut self,
+        end: u64,
+        chunk_size: usize,
+        timeout: Option<Duration>,
+        mut progress: ObjectProgress,
+        mut on_chunk: F,
+    ) -> Result<u64, Error>
+    where
+        F: FnMut(&[u8], ObjectProgress) -> Result<(), Error>,
+    {
+        let handle = progress.handle;
+        let data = self.command(StandardCommandCode::GetObject, &[handle], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        let start = (progress.bytes_done as usize).min(data.len());
+        let end = (end as usize).min(data.len());
+        for chunk in data[start..end].chunks(chunk_size) {
+            progress.bytes_done += chunk.len() as u64;
+            on_chunk(chunk, progress)?;
+        }
+        Ok(progress.bytes_done)
     }
 
     /// 删除对象
-    pub fn delete_object(&mut self, handle: u32, timeout: Option<Duration>) -> Result<(), Error> {
-        self.command(StandardCommandCode::DeleteObject, &[handle], None, timeout).map(|_| ())
+    pub async fn delete_object(&mut self, handle: u32, timeout: Option<Duration>) -> Result<(), Error> {
+        self.command(StandardCommandCode::DeleteObject, &[handle], None, timeout.map(TransactionTimeouts::uniform)).await.map(|_| ())
+    }
+
+    /// 逐个删除对象，返回每个对象的结果
+    /// 文件夹中只删除了部分内容时相机返回PartialDeletion，该对象记为失败
+    pub async fn delete_objects(&mut self, handles: &[u32], timeout: Option<Duration>) -> BatchReport {
+        let mut report = BatchReport::default();
+        for &handle in handles {
+            match self.delete_object(handle, timeout).await {
+                Ok(()) => report.done.push(handle),
+                Err(e) => {
+                    log::warn!("删除对象 0x{:08x} 失败: {}", handle, e);
+                    report.failed.push((handle, e));
+                }
+            }
+        }
+        log::info!("已删除 {}/{} 个对象", report.done.len(), handles.len());
+        report
+    }
+
+    /// 设置或取消对象的写保护
+    pub async fn set_object_protection(&mut self, handle: u32, protected: bool, timeout: Option<Duration>) -> Result<(), Error> {
+        let status = if protected { PROTECTION_READ_ONLY } else { PROTECTION_NONE };
+        self.command(StandardCommandCode::SetObjectProtection, &[handle, status], None, timeout.map(TransactionTimeouts::uniform)).await.map(|_| ())
+    }
+
+    /// 逐个为对象设置写保护，返回每个对象的结果
+    pub async fn protect_objects(&mut self, handles: &[u32], timeout: Option<Duration>) -> BatchReport {
+        let mut report = BatchReport::default();
+        for &handle in handles {
+            match self.set_object_protection(handle, true, timeout).await {
+                Ok(()) => report.done.push(handle),
+                Err(e) => {
+                    log::warn!("保护对象 0x{:08x} 失败: {}", handle, e);
+                    report.failed.push((handle, e));
+                }
+            }
+        }
+        report
+    }
+
+    /// 把对象移动到`storage_id`存储的`parent`文件夹下，`parent`为0表示存储根目录
+    pub async fn move_object(&mut self, handle: u32, storage_id: u32, parent: u32, timeout: Option<Duration>) -> Result<(), Error> {
+        self.command(StandardCommandCode::MoveObject, &[handle, storage_id, parent], None, timeout.map(TransactionTimeouts::uniform)).await.map(|_| ())
+    }
+
+    /// 逐个移动对象，返回每个对象的结果
+    pub async fn move_objects(&mut self, handles: &[u32], storage_id: u32, parent: u32, timeout: Option<Duration>) -> BatchReport {
+        let mut report = BatchReport::default();
+        for &handle in handles {
+            match self.move_object(handle, storage_id, parent, timeout).await {
+                Ok(()) => report.done.push(handle),
+                Err(e) => {
+                    log::warn!("移动对象 0x{:08x} 失败: {}", handle, e);
+                    report.failed.push((handle, e));
+                }
+            }
+        }
+        log::info!("已移动 {}/{} 个对象到 0x{:08x}", report.done.len(), handles.len(), parent);
+        report
+    }
+
+    /// 把对象复制到`storage_id`存储的`parent`文件夹下，返回副本的句柄
+    pub async fn copy_object(&mut self, handle: u32, storage_id: u32, parent: u32, timeout: Option<Duration>) -> Result<u32, Error> {
+        self.command(StandardCommandCode::CopyObject, &[handle, storage_id, parent], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        self.response_params.first().copied()
+            .ok_or_else(|| Error::Malformed("CopyObject响应缺少新对象句柄".into()))
+    }
+
+    /// 确认要执行危险操作，返回执行该操作所需的令牌；之前签发的令牌随之作废
+    pub fn confirm_danger(&mut self, operation: DangerousOperation) -> DangerToken {
+        self.danger_serial += 1;
+        log::warn!("已确认危险操作 {:?}，{}秒内有效", operation, DANGER_TOKEN_TTL.as_secs());
+        DangerToken {
+            operation,
+            serial: self.danger_serial,
+            issued: Instant::now(),
+        }
+    }
+
+    /// 消耗令牌，检查它是否是当前有效的、针对`operation`的令牌
+    fn redeem(&mut self, token: DangerToken, operation: DangerousOperation) -> Result<(), Error> {
+        if token.serial == 0 || token.serial != self.danger_serial {
+            return Err(Error::Unconfirmed(format!("{:?} 的确认令牌已作废", operation)));
+        }
+        // 无论是否匹配，令牌都只能使用一次
+        self.danger_serial = 0;
+        if token.operation != operation {
+            return Err(Error::Unconfirmed(format!("确认令牌针对 {:?}，不能用于 {:?}", token.operation, operation)));
+        }
+        if token.issued.elapsed() > DANGER_TOKEN_TTL {
+            return Err(Error::Unconfirmed(format!("{:?} 的确认令牌已过期", operation)));
+        }
+        Ok(())
+    }
+
+    /// 格式化存储，清除其中的全部对象；需要先用`confirm_danger`取得针对该存储的令牌
+    pub async fn format_store(&mut self, storage_id: u32, token: DangerToken, timeout: Option<Duration>) -> Result<(), Error> {
+        self.redeem(token, DangerousOperation::FormatStore(storage_id))?;
+        log::warn!("格式化存储 0x{:08x}", storage_id);
+        // 第二个参数为文件系统格式，0表示由相机决定
+        self.command(StandardCommandCode::FormatStore, &[storage_id, 0], None, timeout.map(TransactionTimeouts::uniform)).await.map(|_| ())
+    }
+
+    /// 复位相机，相机关闭所有会话；需要先用`confirm_danger`取得令牌
+    /// 复位后本地的会话状态一并清除，之后需要重新打开会话(启用自动重开时由下一条命令重开)
+    pub async fn reset_device(&mut self, token: DangerToken, timeout: Option<Duration>) -> Result<(), Error> {
+        self.redeem(token, DangerousOperation::ResetDevice)?;
+        log::warn!("复位相机");
+        self.command(StandardCommandCode::ResetDevice, &[], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        // 新会话的OpenSession事务ID从0开始，旧会话中读取的属性和分块读取能力都不再可信
+        self.current_tid = 0;
+        self.properties.clear();
+        self.partial64 = None;
+        self.response_params.clear();
+        Ok(())
     }
 
     /// 关机
-    pub fn power_down(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
-        self.command(StandardCommandCode::PowerDown, &[], None, timeout).map(|_| ())
+    pub async fn power_down(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
+        self.command(StandardCommandCode::PowerDown, &[], None, timeout.map(TransactionTimeouts::uniform)).await.map(|_| ())
     }
 
     /// 获取对象句柄
-    pub fn get_objecthandles(&mut self,
-                             storage_id: u32,
-                             handle_id: u32,
-                             filter: Option<u32>,
-                             timeout: Option<Duration>)
-                             -> Result<Vec<u32>, Error> {
+    /// `filter` 只返回指定格式的对象；相机不支持按格式筛选时，逐个读取对象信息在本地筛选
+    pub async fn get_objecthandles(&mut self,
+                                   storage_id: u32,
+                                   handle_id: u32,
+                                   filter: Option<ObjectFormat>,
+                                   timeout: Option<Duration>)
+                                   -> Result<Vec<u32>, Error> {
+        match (self.read_objecthandles(storage_id, handle_id, filter, timeout).await, filter) {
+            (Err(Error::Response(StandardResponseCode::SpecificationByFormatUnsupported)), Some(format)) => {
+                log::debug!("相机不支持按格式筛选，改为在本地筛选");
+                self.filter_handles_locally(storage_id, handle_id, format, timeout).await
+            }
+            (result, _) => result,
+        }
+    }
+
+    /// 执行GetObjectHandles并解析句柄数组
+    async fn read_objecthandles(&mut self,
+                                storage_id: u32,
+                                handle_id: u32,
+                                filter: Option<ObjectFormat>,
+                                timeout: Option<Duration>)
+                                -> Result<Vec<u32>, Error> {
+        let format = filter.map(u16::from).unwrap_or(0) as u32;
         let data = self.command(StandardCommandCode::GetObjectHandles,
-                                    &[storage_id, filter.unwrap_or(0x0), handle_id],
-                                    None, timeout)?;
+                                &[storage_id, format, handle_id],
+                                None, timeout.map(TransactionTimeouts::uniform)).await?;
         // 解析对象句柄数组
         let mut cur = std::io::Cursor::new(data);
         let value = cur.read_ptp_u32_vec()?;
@@ -326,34 +1364,61 @@ impl PtpCamera {
         Ok(value)
     }
 
+    /// 获取全部句柄后按对象信息中的格式筛选
+    async fn filter_handles_locally(&mut self,
+                                    storage_id: u32,
+                                    handle_id: u32,
+                                    format: ObjectFormat,
+                                    timeout: Option<Duration>)
+                                    -> Result<Vec<u32>, Error> {
+        let handles = self.read_objecthandles(storage_id, handle_id, None, timeout).await?;
+        let mut matched = Vec::new();
+        for handle in handles {
+            let info = self.get_objectinfo(handle, timeout).await?;
+            if info.format() == format {
+                matched.push(handle);
+            }
+        }
+        Ok(matched)
+    }
+
     /// 获取根目录中的对象句柄
-    pub fn get_objecthandles_root(&mut self,
-                                  storage_id: u32,
-                                  filter: Option<u32>,
-                                  timeout: Option<Duration>)
-                                  -> Result<Vec<u32>, Error> {
-        self.get_objecthandles(storage_id, 0xFFFFFFFF, filter, timeout)
+    pub async fn get_objecthandles_root(&mut self,
+                                        storage_id: u32,
+                                        filter: Option<ObjectFormat>,
+                                        timeout: Option<Duration>)
+                                        -> Result<Vec<u32>, Error> {
+        self.get_objecthandles(storage_id, 0xFFFFFFFF, filter, timeout).await
     }
 
     /// 获取所有对象句柄
-    pub fn get_objecthandles_all(&mut self,
-                                 storage_id: u32,
-                                 filter: Option<u32>,
-                                 timeout: Option<Duration>)
-                                 -> Result<Vec<u32>, Error> {
-        self.get_objecthandles(storage_id, 0x0, filter, timeout)
+    pub async fn get_objecthandles_all(&mut self,
+                                       storage_id: u32,
+                                       filter: Option<ObjectFormat>,
+                                       timeout: Option<Duration>)
+                                       -> Result<Vec<u32>, Error> {
+        self.get_objecthandles(storage_id, 0x0, filter, timeout).await
     }
 
     /// 获取对象数量
-    pub fn get_numobjects(&mut self,
-                          storage_id: u32,
-                          handle_id: u32,
-                          filter: Option<u32>,
-                          timeout: Option<Duration>)
-                          -> Result<u32, Error> {
-        let data = self.command(StandardCommandCode::GetNumObjects,
-                                    &[storage_id, filter.unwrap_or(0x0), handle_id],
-                                    None, timeout)?;
+    /// 相机不支持按格式筛选时，按本地筛选后的句柄数计算
+    pub async fn get_numobjects(&mut self,
+                                storage_id: u32,
+                                handle_id: u32,
+                                filter: Option<ObjectFormat>,
+                                timeout: Option<Duration>)
+                                -> Result<u32, Error> {
+        let format = filter.map(u16::from).unwrap_or(0) as u32;
+        let result = self.command(StandardCommandCode::GetNumObjects,
+                                  &[storage_id, format, handle_id],
+                                  None, timeout.map(TransactionTimeouts::uniform)).await;
+        let data = match (result, filter) {
+            (Err(Error::Response(StandardResponseCode::SpecificationByFormatUnsupported)), Some(format)) => {
+                let handles = self.filter_handles_locally(storage_id, handle_id, format, timeout).await?;
+                return Ok(handles.len() as u32);
+            }
+            (result, _) => result?,
+        };
 
         // 解析对象数量
         let mut cur = std::io::Cursor::new(data);
@@ -364,8 +1429,8 @@ impl PtpCamera {
     }
 
     /// 获取存储信息
-    pub fn get_storage_info(&mut self, storage_id: u32, timeout: Option<Duration>) -> Result<PtpStorageInfo, Error> {
-        let data = self.command(StandardCommandCode::GetStorageInfo, &[storage_id], None, timeout)?;
+    pub async fn get_storage_info(&mut self, storage_id: u32, timeout: Option<Duration>) -> Result<PtpStorageInfo, Error> {
+        let data = self.command(StandardCommandCode::GetStorageInfo, &[storage_id], None, timeout.map(TransactionTimeouts::uniform)).await?;
 
         // 解析存储信息
         let mut cur = std::io::Cursor::new(data);
@@ -376,8 +1441,8 @@ impl PtpCamera {
     }
 
     /// 获取存储ID列表
-    pub fn get_storageids(&mut self, timeout: Option<Duration>) -> Result<Vec<u32>, Error> {
-        let data = self.command(StandardCommandCode::GetStorageIDs, &[], None, timeout)?;
+    pub async fn get_storageids(&mut self, timeout: Option<Duration>) -> Result<Vec<u32>, Error> {
+        let data = self.command(StandardCommandCode::GetStorageIDs, &[], None, timeout.map(TransactionTimeouts::uniform)).await?;
 
         // 解析存储ID数组
         let mut cur = std::io::Cursor::new(data);
@@ -388,50 +1453,384 @@ impl PtpCamera {
     }
 
     /// 获取根目录对象数量
-    pub fn get_numobjects_roots(&mut self,
-                                storage_id: u32,
-                                filter: Option<u32>,
-                                timeout: Option<Duration>)
-                                -> Result<u32, Error> {
-        self.get_numobjects(storage_id, 0xFFFFFFFF, filter, timeout)
+    pub async fn get_numobjects_roots(&mut self,
+                                      storage_id: u32,
+                                      filter: Option<ObjectFormat>,
+                                      timeout: Option<Duration>)
+                                      -> Result<u32, Error> {
+        self.get_numobjects(storage_id, 0xFFFFFFFF, filter, timeout).await
     }
 
     /// 获取所有对象数量
-    pub fn get_numobjects_all(&mut self, storage_id: u32, filter: Option<u32>, timeout: Option<Duration>) -> Result<u32, Error> {
-        self.get_numobjects(storage_id, 0x0, filter, timeout)
+    pub async fn get_numobjects_all(&mut self, storage_id: u32, filter: Option<ObjectFormat>, timeout: Option<Duration>) -> Result<u32, Error> {
+        self.get_numobjects(storage_id, 0x0, filter, timeout).await
     }
 
     /// 获取设备信息
     pub async fn get_device_info(&mut self, timeout: Option<Duration>) -> Result<PtpDeviceInfo, Error> {
-        let response = self.command(StandardCommandCode::GetDeviceInfo, &[], None, timeout).await?;
+        let response = self.command(StandardCommandCode::GetDeviceInfo, &[], None, timeout.map(TransactionTimeouts::uniform)).await?;
 
         let device_info = PtpDeviceInfo::decode(&response)?;
-        debug!("设备信息 {:?}", device_info);
+        log::debug!("设备信息 {:?}", device_info);
+        self.operations = Some(device_info.OperationsSupported.clone());
         Ok(device_info)
     }
 
-    /// 打开会话
+    /// 打开会话，并缓存相机支持的全部设备属性描述；属性读取失败不影响会话
+    /// 非正常断开后部分机身仍保留旧会话并返回SessionAlreadyOpen，此时先关闭旧会话再重新打开
     pub async fn open_session(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
-        let session_id = 1; // 会话ID = 1
-
-        let _response = self.command(StandardCommandCode::OpenSession,
-                              &[session_id], // 会话ID = 1
-                              None,
-                              timeout).await?;
+        let session_id = self.session_id;
+        match self.command(StandardCommandCode::OpenSession, &[session_id], None, timeout.map(TransactionTimeouts::uniform)).await {
+            Ok(_) => {}
+            Err(Error::Response(StandardResponseCode::SessionAlreadyOpen)) => {
+                log::warn!("相机保留了上一次的会话，关闭后重新打开会话 {}", session_id);
+                if let Err(e) = self.command(StandardCommandCode::CloseSession, &[], None, timeout.map(TransactionTimeouts::uniform)).await {
+                    log::debug!("关闭旧会话失败: {}", e);
+                }
+                // 新会话的OpenSession事务ID从0开始
+                self.current_tid = 0;
+                self.command(StandardCommandCode::OpenSession, &[session_id], None, timeout.map(TransactionTimeouts::uniform)).await?;
+            }
+            Err(e) => return Err(e),
+        }
+        if let Some(delay) = self.quirks.open_session_delay() {
+            log::debug!("等待 {}ms 后再发送命令", delay.as_millis());
+            Timer::after(EmbassyDuration::from_millis(delay.as_millis() as u64)).await;
+        }
+        if let Err(e) = self.load_properties(timeout).await {
+            log::warn!("读取设备属性描述失败: {}", e);
+        }
         Ok(())
     }
 
     /// 关闭会话
     pub async fn close_session(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
-        let _response = self.command(StandardCommandCode::CloseSession, &[], None, timeout).await?;
+        let _response = self.command(StandardCommandCode::CloseSession, &[], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        self.properties.clear();
+        Ok(())
+    }
+
+    /// 获取设备属性描述(不经过缓存)
+    pub async fn get_device_prop_desc(&mut self, code: u16, timeout: Option<Duration>) -> Result<PtpPropInfo, Error> {
+        let response = self.command(StandardCommandCode::GetDevicePropDesc, &[code as u32], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        PtpPropInfo::decode(&mut Cursor::new(response))
+    }
+
+    /// 重新读取DevicePropertiesSupported中的全部属性描述并替换缓存，返回缓存的属性数
+    /// 同时按操作列表更新可用的64位偏移读取命令
+    /// 部分相机会列出实际不响应的属性，这些属性记录日志后跳过
+    pub async fn load_properties(&mut self, timeout: Option<Duration>) -> Result<usize, Error> {
+        let info = self.get_device_info(timeout).await?;
+        self.partial64 = PartialObject64::from_device_info(&info);
+        self.properties.clear();
+        for code in info.DevicePropertiesSupported {
+            match self.get_device_prop_desc(code, timeout).await {
+                Ok(info) => {
+                    self.properties.insert(code, info);
+                }
+                Err(e) => log::warn!("读取属性 0x{:04x} 的描述失败: {}", code, e),
+            }
+        }
+        log::debug!("已缓存 {} 个设备属性描述", self.properties.len());
+        Ok(self.properties.len())
+    }
+
+    /// 重新读取单个属性的描述(例如修改属性值或收到DevicePropChanged事件后)
+    pub async fn refresh_property(&mut self, code: u16, timeout: Option<Duration>) -> Result<&PtpPropInfo, Error> {
+        let info = self.get_device_prop_desc(code, timeout).await?;
+        self.properties.insert(code, info);
+        Ok(&self.properties[&code])
+    }
+
+    /// 设置设备属性值，`value`为按属性数据类型编码的PTP数据；设置成功后刷新该属性的缓存
+    pub async fn set_device_prop_value(&mut self, code: u16, value: &[u8], timeout: Option<Duration>) -> Result<(), Error> {
+        self.command(StandardCommandCode::SetDevicePropValue, &[code as u32], Some(value), timeout.map(TransactionTimeouts::uniform)).await?;
+        if self.properties.contains_key(&code) {
+            if let Err(e) = self.refresh_property(code, timeout).await {
+                log::warn!("刷新属性 0x{:04x} 失败: {}", code, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 缓存的属性描述
+    pub fn property(&self, code: u16) -> Option<&PtpPropInfo> {
+        self.properties.get(&code)
+    }
+
+    /// 缓存的全部属性描述，按属性代码排序
+    pub fn properties(&self) -> impl Iterator<Item = &PtpPropInfo> {
+        self.properties.values()
+    }
+
+    /// 把缓存的全部属性(取值范围/可选值/当前值)序列化为JSON，供手机应用动态生成相机设置界面
+    pub fn property_schema_json(&self) -> String {
+        serde_json::json!({
+            "properties": self.properties().map(PtpPropInfo::to_json).collect::<Vec<_>>(),
+        })
+        .to_string()
+    }
+
+    /// 触发拍摄，返回本次拍摄的事务ID
+    /// 拍摄结果通过事件通道上报ObjectAdded和CaptureComplete；`storage_id`和`format`为0时由相机决定
+    pub async fn initiate_capture(&mut self, storage_id: u32, format: u16, timeout: Option<Duration>) -> Result<u32, Error> {
+        self.command(StandardCommandCode::InitiateCapture, &[storage_id, format as u32], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        // DeviceBusy重试和重开会话都会换新的事务ID，成功的总是最后一次
+        Ok(self.current_tid.wrapping_sub(1))
+    }
+
+    /// 结束开放式拍摄（B门、连拍等），`capture_tid`为开始拍摄时的事务ID
+    pub async fn terminate_open_capture(&mut self, capture_tid: u32, timeout: Option<Duration>) -> Result<(), Error> {
+        self.command(StandardCommandCode::TerminateOpenCapture, &[capture_tid], None, timeout.map(TransactionTimeouts::uniform)).await?;
         Ok(())
     }
 
     /// 断开连接
     pub async fn disconnect(&mut self, timeout: Option<Duration>) -> Result<(), Error> {
         self.close_session(timeout).await?;
-        self.handle.release_interface(self.iface).await
-            .map_err(|e| Error::USB(format!("无法释放接口: {:?}", e)))?;
-        Ok(())
+        self.transport.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ptp_mtp::mock_transport::MockTransport;
+    use embassy_futures::block_on;
+
+    fn header(len: u32, kind: u16, code: u16, tid: u32) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(&kind.to_le_bytes());
+        buf.extend_from_slice(&code.to_le_bytes());
+        buf.extend_from_slice(&tid.to_le_bytes());
+        buf
+    }
+
+    fn ptp_str(s: &str) -> Vec<u8> {
+        if s.is_empty() {
+            return vec![0];
+        }
+        let units: Vec<u16> = s.encode_utf16().chain(std::iter::once(0)).collect();
+        let mut buf = vec![units.len() as u8];
+        buf.extend(units.iter().flat_map(|u| u.to_le_bytes()));
+        buf
+    }
+
+    fn u16_array(values: &[u16]) -> Vec<u8> {
+        let mut buf = (values.len() as u32).to_le_bytes().to_vec();
+        buf.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+        buf
+    }
+
+    fn device_info_payload(operations: &[u16]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&100u16.to_le_bytes());
+        buf.extend_from_slice(&0x0000_000Bu32.to_le_bytes());
+        buf.extend_from_slice(&100u16.to_le_bytes());
+        buf.extend(ptp_str("canon.com: 1.0"));
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend(u16_array(operations));
+        buf.extend(u16_array(&[0x4002, 0x400D]));
+        buf.extend(u16_array(&[0x5001]));
+        buf.extend(u16_array(&[0x3801]));
+        buf.extend(u16_array(&[0x3801, 0x3000]));
+        buf.extend(ptp_str("Canon Inc."));
+        buf.extend(ptp_str("Canon EOS R6"));
+        buf.extend(ptp_str("1-1.8.1"));
+        buf.extend(ptp_str(""));
+        buf
+    }
+
+    /// 重试时不等待，测试不依赖时钟
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+            retry_on_timeout: false,
+        }
+    }
+
+    #[test]
+    fn container_header_parses_fields() {
+        let info = PtpContainerInfo::parse(&header(20, 2, 0x1009, 7)[..]).unwrap();
+        assert_eq!(info.kind, PtpContainerType::Data);
+        assert_eq!(info.code, 0x1009);
+        assert_eq!(info.payload_len, 8);
+        assert!(info.belongs_to(7));
+        assert!(!info.belongs_to(8));
+    }
+
+    #[test]
+    fn container_header_unknown_length_reads_to_short_packet() {
+        let info = PtpContainerInfo::parse(&header(MTP_UNKNOWN_CONTAINER_LEN, 2, 0x101B, 1)[..]).unwrap();
+        assert_eq!(info.payload_len, usize::MAX);
+    }
+
+    #[test]
+    fn container_header_rejects_malformed() {
+        assert!(matches!(PtpContainerInfo::parse(&header(8, 3, 0x2001, 0)[..]), Err(Error::Malformed(_))));
+        assert!(matches!(PtpContainerInfo::parse(&header(12, 9, 0x2001, 0)[..]), Err(Error::Malformed(_))));
+        assert!(PtpContainerInfo::parse(&header(12, 3, 0x2001, 0)[..6]).is_err());
+    }
+
+    #[test]
+    fn device_info_decodes_strings_and_arrays() {
+        let info = PtpDeviceInfo::decode(&device_info_payload(&[0x1001, 0x1002, 0x1009])).unwrap();
+        assert_eq!(info.Version, 100);
+        assert_eq!(info.VendorExID, 0x0B);
+        assert_eq!(info.VendorExtensionDesc, "canon.com: 1.0");
+        assert_eq!(info.OperationsSupported, vec![0x1001, 0x1002, 0x1009]);
+        assert_eq!(info.EventsSupported, vec![0x4002, 0x400D]);
+        assert_eq!(info.ImageFormats, vec![0x3801, 0x3000]);
+        assert_eq!(info.Manufacturer, "Canon Inc.");
+        assert_eq!(info.Model, "Canon EOS R6");
+        assert_eq!(info.DeviceVersion, "1-1.8.1");
+        assert_eq!(info.SerialNumber, "");
+    }
+
+    #[test]
+    fn device_info_truncated_is_error() {
+        let payload = device_info_payload(&[0x1001]);
+        assert!(PtpDeviceInfo::decode(&payload[..payload.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn get_device_info_records_operations() {
+        let mut transport = MockTransport::new();
+        transport
+            .push_data(StandardCommandCode::GetDeviceInfo, 0, &device_info_payload(&[0x1001, 0x1004]))
+            .push_response(StandardResponseCode::Ok, 0, &[]);
+        let mut camera = PtpCamera::with_transport(transport);
+        let info = block_on(camera.get_device_info(None)).unwrap();
+        assert_eq!(info.Model, "Canon EOS R6");
+        assert_eq!(camera.supports_operation(StandardCommandCode::GetStorageIDs), Some(true));
+        assert_eq!(camera.supports_operation(StandardCommandCode::DeleteObject), Some(false));
+        let commands = camera.transport().commands().unwrap();
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].code, StandardCommandCode::GetDeviceInfo);
+        assert!(camera.transport().is_drained());
+    }
+
+    #[test]
+    fn partial_reads_follow_device_info_and_quirks() {
+        let mut transport = MockTransport::new();
+        transport
+            .push_data(StandardCommandCode::GetDeviceInfo, 0, &device_info_payload(&[0x1001, 0x1009, 0x101B]))
+            .push_response(StandardResponseCode::Ok, 0, &[]);
+        let mut camera = PtpCamera::with_transport(transport);
+        assert!(camera.supports_partial_reads());
+        block_on(camera.get_device_info(None)).unwrap();
+        assert!(camera.supports_partial_reads());
+        camera.disable_partial_reads();
+        assert!(!camera.supports_partial_reads());
+
+        let mut transport = MockTransport::new();
+        transport
+            .push_data(StandardCommandCode::GetDeviceInfo, 0, &device_info_payload(&[0x1001, 0x1009]))
+            .push_response(StandardResponseCode::Ok, 0, &[]);
+        let mut camera = PtpCamera::with_transport(transport);
+        block_on(camera.get_device_info(None)).unwrap();
+        assert!(!camera.supports_partial_reads());
+    }
+
+    #[test]
+    fn reset_device_requires_fresh_token_and_restarts_transactions() {
+        let mut transport = MockTransport::new();
+        transport
+            .push_response(StandardResponseCode::Ok, 0, &[])
+            .push_response(StandardResponseCode::Ok, 1, &[]);
+        let mut camera = PtpCamera::with_transport(transport);
+        block_on(camera.command(StandardCommandCode::GetStorageIDs, &[], None, None)).unwrap();
+
+        let stale = camera.confirm_danger(DangerousOperation::ResetDevice);
+        let token = camera.confirm_danger(DangerousOperation::ResetDevice);
+        assert!(matches!(block_on(camera.reset_device(stale, None)), Err(Error::Unconfirmed(_))));
+        block_on(camera.reset_device(token, None)).unwrap();
+        assert_eq!(camera.current_tid, 0);
+        assert_eq!(camera.transport().commands().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn device_busy_is_retried_with_new_transaction() {
+        let mut transport = MockTransport::new();
+        transport
+            .push_response(StandardResponseCode::DeviceBusy, 0, &[])
+            .push_data(StandardCommandCode::GetStorageIDs, 1, &[1, 0, 0, 0, 0x01, 0x00, 0x01, 0x00])
+            .push_response(StandardResponseCode::Ok, 1, &[]);
+        let mut camera = PtpCamera::with_transport(transport);
+        camera.set_retry_policy(fast_retry());
+        assert_eq!(block_on(camera.get_storageids(None)).unwrap(), vec![0x0001_0001]);
+        let tids: Vec<u32> = camera.transport().commands().unwrap().iter().map(|c| c.tid).collect();
+        assert_eq!(tids, vec![0, 1]);
+    }
+
+    #[test]
+    fn initiate_capture_returns_tid_of_successful_attempt() {
+        let mut transport = MockTransport::new();
+        transport
+            .push_response(StandardResponseCode::DeviceBusy, 0, &[])
+            .push_response(StandardResponseCode::Ok, 1, &[]);
+        let mut camera = PtpCamera::with_transport(transport);
+        camera.set_retry_policy(fast_retry());
+        assert_eq!(block_on(camera.initiate_capture(0, 0, None)).unwrap(), 1);
+    }
+
+    #[test]
+    fn cancel_before_read_waits_for_next_read() {
+        let token = CancelToken::default();
+        token.cancel();
+        drop(token.begin_read());
+        assert!(token.is_cancelled());
+        let _read = token.begin_read();
+        assert!(token.take());
+    }
+
+    #[test]
+    fn unhandled_cancel_is_dropped_with_its_read() {
+        let token = CancelToken::default();
+        let outer = token.begin_read();
+        let inner = token.begin_read();
+        token.cancel();
+        drop(inner);
+        assert!(token.is_cancelled());
+        drop(outer);
+        assert!(!token.is_cancelled());
+    }
+
+    #[test]
+    fn retries_stop_after_max_attempts() {
+        let mut transport = MockTransport::new();
+        for tid in 0..3 {
+            transport.push_response(StandardResponseCode::DeviceBusy, tid, &[]);
+        }
+        let mut camera = PtpCamera::with_transport(transport);
+        camera.set_retry_policy(fast_retry());
+        let result = block_on(camera.command(StandardCommandCode::GetStorageIDs, &[], None, None));
+        assert!(matches!(result, Err(Error::Response(StandardResponseCode::DeviceBusy))));
+        assert_eq!(camera.transport().commands().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn timeout_is_not_retried_by_default() {
+        assert!(!RetryPolicy::default().should_retry(&Error::Timeout("测试".into())));
+        let mut transport = MockTransport::new();
+        transport.push_read_error(Error::Timeout("测试".into()));
+        let mut camera = PtpCamera::with_transport(transport);
+        camera.set_retry_policy(RetryPolicy { initial_backoff: Duration::ZERO, ..Default::default() });
+        let result = block_on(camera.command(StandardCommandCode::GetStorageIDs, &[], None, None));
+        assert!(matches!(result, Err(Error::Timeout(_))));
+        assert_eq!(camera.transport().commands().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn mismatched_transaction_id_is_malformed() {
+        let mut transport = MockTransport::new();
+        transport.push_response(StandardResponseCode::Ok, 5, &[]);
+        let mut camera = PtpCamera::with_transport(transport);
+        camera.set_retry_policy(RetryPolicy::none());
+        let result = block_on(camera.command(StandardCommandCode::GetStorageIDs, &[], None, None));
+        assert!(matches!(result, Err(Error::Malformed(_))));
     }
 }
@@ -0,0 +1,100 @@
+// 相机能力 - 由DeviceInfo中的操作列表推算的常用能力标志，上层按此选择策略(例如没有GetThumb时不取缩略图)，
+// 不必各自解析原始的操作码列表
+use crate::ptp_mtp::device_info::PtpDeviceInfo;
+use crate::ptp_mtp::mtp::MtpCommandCode;
+use crate::ptp_mtp::standard_codes::{CommandCode, StandardCommandCode, StandardEventCode};
+use crate::ptp_mtp::vendor::Vendor;
+
+/// Nikon的64位偏移GetPartialObjectEx
+const NIKON_GET_PARTIAL_OBJECT_EX: CommandCode = 0x9431;
+/// 各厂商读取取景画面的私有命令
+const CANON_EOS_GET_VIEWFINDER_DATA: CommandCode = 0x9153;
+const NIKON_GET_LIVE_VIEW_IMAGE: CommandCode = 0x9203;
+
+/// 64位偏移的分块读取命令，读取超过4GB的视频时使用
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialObject64 {
+    Mtp,   // GetPartialObject64: 句柄, 偏移低32位, 偏移高32位, 最大字节数
+    Nikon, // GetPartialObjectEx: 句柄, 偏移低32位, 偏移高32位, 最大字节数低32位, 最大字节数高32位
+}
+
+impl PartialObject64 {
+    /// 从操作列表中选择，同时支持时优先使用MTP标准扩展
+    pub fn from_device_info(info: &PtpDeviceInfo) -> Option<Self> {
+        let supports = |op: CommandCode| info.OperationsSupported.contains(&op);
+        if supports(MtpCommandCode::GetPartialObject64) {
+            Some(PartialObject64::Mtp)
+        } else if supports(NIKON_GET_PARTIAL_OBJECT_EX) && Vendor::from_device_info(info) == Vendor::Nikon {
+            Some(PartialObject64::Nikon)
+        } else {
+            None
+        }
+    }
+
+    /// 命令码
+    pub fn code(self) -> CommandCode {
+        match self {
+            PartialObject64::Mtp => MtpCommandCode::GetPartialObject64,
+            PartialObject64::Nikon => NIKON_GET_PARTIAL_OBJECT_EX,
+        }
+    }
+
+    /// 读取`offset`开始最多`max`字节的命令参数
+    pub fn params(self, handle: u32, offset: u64, max: u32) -> Vec<u32> {
+        let mut params = vec![handle, offset as u32, (offset >> 32) as u32, max];
+        if self == PartialObject64::Nikon {
+            params.push(0);
+        }
+        params
+    }
+}
+
+/// 相机能力
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CameraCapabilities {
+    pub vendor: Vendor,
+    pub supports_thumbnail: bool,      // GetThumb
+    pub supports_partial_object: bool, // GetPartialObject或其64位版本，断点续传和分块读取需要
+    pub partial_object_64: Option<PartialObject64>, // 64位偏移的分块读取，没有时无法分块读取超过4GB的对象
+    pub supports_capture: bool,        // InitiateCapture
+    pub supports_live_view: bool,      // 厂商的取景画面命令
+    pub supports_events: bool,         // 会报告ObjectAdded事件，不支持时需要轮询对象列表
+}
+
+impl CameraCapabilities {
+    /// 没有任何可选能力(未建立会话时使用)
+    pub fn none() -> Self {
+        CameraCapabilities {
+            vendor: Vendor::Unknown,
+            supports_thumbnail: false,
+            supports_partial_object: false,
+            partial_object_64: None,
+            supports_capture: false,
+            supports_live_view: false,
+            supports_events: false,
+        }
+    }
+}
+
+impl From<&PtpDeviceInfo> for CameraCapabilities {
+    fn from(info: &PtpDeviceInfo) -> Self {
+        let vendor = Vendor::from_device_info(info);
+        let partial_object_64 = PartialObject64::from_device_info(info);
+        let supports = |op: CommandCode| info.OperationsSupported.contains(&op);
+        let supports_live_view = match vendor {
+            Vendor::Canon => supports(CANON_EOS_GET_VIEWFINDER_DATA),
+            Vendor::Nikon => supports(NIKON_GET_LIVE_VIEW_IMAGE),
+            // 其他厂商的取景命令不在操作列表中报告，无法判断
+            _ => false,
+        };
+        CameraCapabilities {
+            vendor,
+            supports_thumbnail: supports(StandardCommandCode::GetThumb),
+            supports_partial_object: supports(StandardCommandCode::GetPartialObject) || partial_object_64.is_some(),
+            partial_object_64,
+            supports_capture: supports(StandardCommandCode::InitiateCapture),
+            supports_live_view,
+            supports_events: info.EventsSupported.contains(&StandardEventCode::ObjectAdded),
+        }
+    }
+}
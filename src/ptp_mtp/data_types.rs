@@ -173,105 +173,105 @@ impl PtpDataType {
     pub fn encode(&self) -> Vec<u8> {
         use self::PtpDataType::*;
         let mut out = vec![];
-        match self {
+        match *self {
             // UNDEF => {},
-            &INT8(val) => {
+            INT8(val) => {
                 out.write_i8(val).ok();
             }
-            &UINT8(val) => {
+            UINT8(val) => {
                 out.write_u8(val).ok();
             }
-            &INT16(val) => {
+            INT16(val) => {
                 out.write_i16::<LittleEndian>(val).ok();
             }
-            &UINT16(val) => {
+            UINT16(val) => {
                 out.write_u16::<LittleEndian>(val).ok();
             }
-            &INT32(val) => {
+            INT32(val) => {
                 out.write_i32::<LittleEndian>(val).ok();
             }
-            &UINT32(val) => {
+            UINT32(val) => {
                 out.write_u32::<LittleEndian>(val).ok();
             }
-            &INT64(val) => {
+            INT64(val) => {
                 out.write_i64::<LittleEndian>(val).ok();
             }
-            &UINT64(val) => {
+            UINT64(val) => {
                 out.write_u64::<LittleEndian>(val).ok();
             }
-            &INT128((hi, lo)) => {
+            INT128((hi, lo)) => {
                 out.write_u64::<LittleEndian>(lo).ok();
                 out.write_u64::<LittleEndian>(hi).ok();
             }
-            &UINT128((hi, lo)) => {
+            UINT128((hi, lo)) => {
                 out.write_u64::<LittleEndian>(lo).ok();
                 out.write_u64::<LittleEndian>(hi).ok();
             }
-            &AINT8(ref val) => {
+            AINT8(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for item in val {
                     out.write_i8(*item).ok();
                 }
             }
-            &AUINT8(ref val) => {
+            AUINT8(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for item in val {
                     out.write_u8(*item).ok();
                 }
             }
-            &AINT16(ref val) => {
+            AINT16(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for item in val {
                     out.write_i16::<LittleEndian>(*item).ok();
                 }
             }
-            &AUINT16(ref val) => {
+            AUINT16(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for item in val {
                     out.write_u16::<LittleEndian>(*item).ok();
                 }
             }
-            &AINT32(ref val) => {
+            AINT32(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for item in val {
                     out.write_i32::<LittleEndian>(*item).ok();
                 }
             }
-            &AUINT32(ref val) => {
+            AUINT32(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for item in val {
                     out.write_u32::<LittleEndian>(*item).ok();
                 }
             }
-            &AINT64(ref val) => {
+            AINT64(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for item in val {
                     out.write_i64::<LittleEndian>(*item).ok();
                 }
             }
-            &AUINT64(ref val) => {
+            AUINT64(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for item in val {
                     out.write_u64::<LittleEndian>(*item).ok();
                 }
             }
-            &AINT128(ref val) => {
+            AINT128(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for &(hi, lo) in val {
                     out.write_u64::<LittleEndian>(lo).ok();
                     out.write_u64::<LittleEndian>(hi).ok();
                 }
             }
-            &AUINT128(ref val) => {
+            AUINT128(ref val) => {
                 out.write_u32::<LittleEndian>(val.len() as u32).ok();
                 for &(hi, lo) in val {
                     out.write_u64::<LittleEndian>(lo).ok();
                     out.write_u64::<LittleEndian>(hi).ok();
                 }
             }
-            &STR(ref val) => {
+            STR(ref val) => {
                 out.write_u8(((val.len() as u8) * 2) + 1).ok();
-                if val.len() > 0 {
+                if !val.is_empty() {
                     for e in val.encode_utf16() {
                         out.write_u16::<LittleEndian>(e).ok();
                     }
@@ -283,6 +283,37 @@ impl PtpDataType {
         out
     }
 
+    /// 转换为JSON值：整数为数字，数组为JSON数组，128位整数为十六进制字符串，UNDEF为null
+    pub fn to_json(&self) -> serde_json::Value {
+        use self::PtpDataType::*;
+        use serde_json::Value;
+        fn wide((hi, lo): (u64, u64)) -> Value {
+            Value::String(format!("0x{:016x}{:016x}", hi, lo))
+        }
+        match self {
+            UNDEF => Value::Null,
+            INT8(v) => (*v).into(),
+            UINT8(v) => (*v).into(),
+            INT16(v) => (*v).into(),
+            UINT16(v) => (*v).into(),
+            INT32(v) => (*v).into(),
+            UINT32(v) => (*v).into(),
+            INT64(v) => (*v).into(),
+            UINT64(v) => (*v).into(),
+            INT128(v) | UINT128(v) => wide(*v),
+            AINT8(v) => v.clone().into(),
+            AUINT8(v) => v.clone().into(),
+            AINT16(v) => v.clone().into(),
+            AUINT16(v) => v.clone().into(),
+            AINT32(v) => v.clone().into(),
+            AUINT32(v) => v.clone().into(),
+            AINT64(v) => v.clone().into(),
+            AUINT64(v) => v.clone().into(),
+            AINT128(v) | AUINT128(v) => Value::Array(v.iter().copied().map(wide).collect()),
+            STR(v) => v.clone().into(),
+        }
+    }
+
     /// 根据类型ID从数据流中读取PTP数据类型
     pub fn read_type<T: PtpRead>(kind: u16, reader: &mut T) -> Result<PtpDataType, Error> {
         use self::PtpDataType::*;
@@ -315,49 +346,49 @@ impl PtpDataType {
 }
 
 // 从基本类型转换为PtpDataType的实现
-impl<'a> From<i8> for PtpDataType {
+impl From<i8> for PtpDataType {
     fn from(value: i8) -> Self {
         PtpDataType::INT8(value)
     }
 }
 
-impl<'a> From<u8> for PtpDataType {
+impl From<u8> for PtpDataType {
     fn from(value: u8) -> Self {
         PtpDataType::UINT8(value)
     }
 }
 
-impl<'a> From<i16> for PtpDataType {
+impl From<i16> for PtpDataType {
     fn from(value: i16) -> Self {
         PtpDataType::INT16(value)
     }
 }
 
-impl<'a> From<u16> for PtpDataType {
+impl From<u16> for PtpDataType {
     fn from(value: u16) -> Self {
         PtpDataType::UINT16(value)
     }
 }
 
-impl<'a> From<i32> for PtpDataType {
+impl From<i32> for PtpDataType {
     fn from(value: i32) -> Self {
         PtpDataType::INT32(value)
     }
 }
 
-impl<'a> From<u32> for PtpDataType {
+impl From<u32> for PtpDataType {
     fn from(value: u32) -> Self {
         PtpDataType::UINT32(value)
     }
 }
 
-impl<'a> From<i64> for PtpDataType {
+impl From<i64> for PtpDataType {
     fn from(value: i64) -> Self {
         PtpDataType::INT64(value)
     }
 }
 
-impl<'a> From<u64> for PtpDataType {
+impl From<u64> for PtpDataType {
     fn from(value: u64) -> Self {
         PtpDataType::UINT64(value)
     }
@@ -369,7 +400,7 @@ impl<'a> From<&'a str> for PtpDataType {
     }
 }
 
-impl<'a> From<String> for PtpDataType {
+impl From<String> for PtpDataType {
     fn from(value: String) -> Self {
         PtpDataType::STR(value)
     }
@@ -0,0 +1,233 @@
+// PTP日期时间 - 解析和格式化ObjectInfo等数据集中的日期字符串"YYYYMMDDThhmmss[.s][Z|±hhmm]"
+use std::cmp::Ordering;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::ptp_mtp::error::Error;
+
+/// PTP日期时间
+/// 没有时区后缀时是相机的本地时间，换算为时间戳时按UTC处理
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PtpDateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub tenths: u8,              // 十分之一秒
+    pub utc_offset: Option<i16>, // 相对UTC的分钟数，None表示未指定时区
+}
+
+impl PtpDateTime {
+    /// 解析PTP日期时间字符串
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let bad = || Error::Malformed(format!("无效的PTP日期时间: {:?}", s));
+        let s = s.trim_end_matches('\0');
+        let b = s.as_bytes();
+        if b.len() < 15 || b[8] != b'T' {
+            return Err(bad());
+        }
+        let num = |range: std::ops::Range<usize>| -> Result<u16, Error> {
+            let digits = s.get(range).ok_or_else(bad)?;
+            if !digits.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(bad());
+            }
+            digits.parse().map_err(|_| bad())
+        };
+        let mut dt = PtpDateTime {
+            year: num(0..4)?,
+            month: num(4..6)? as u8,
+            day: num(6..8)? as u8,
+            hour: num(9..11)? as u8,
+            minute: num(11..13)? as u8,
+            second: num(13..15)? as u8,
+            tenths: 0,
+            utc_offset: None,
+        };
+        let mut rest = &s[15..];
+        if let Some(frac) = rest.strip_prefix('.') {
+            dt.tenths = num(16..17)? as u8;
+            rest = &frac[1..];
+        }
+        dt.utc_offset = match rest.as_bytes() {
+            [] => None,
+            [b'Z'] => Some(0),
+            [sign @ (b'+' | b'-'), ..] if rest.len() == 5 => {
+                let start = s.len() - 4;
+                let minutes = (num(start..start + 2)? * 60 + num(start + 2..start + 4)?) as i16;
+                Some(if *sign == b'-' { -minutes } else { minutes })
+            }
+            _ => return Err(bad()),
+        };
+        if !(1..=12).contains(&dt.month)
+            || dt.day == 0
+            || dt.day > days_in_month(dt.year, dt.month)
+            || dt.hour > 23
+            || dt.minute > 59
+            || dt.second > 60
+        {
+            return Err(bad());
+        }
+        Ok(dt)
+    }
+
+    /// 解析，空字符串(相机未提供日期)或格式错误时返回None
+    pub fn parse_opt(s: &str) -> Option<Self> {
+        if s.is_empty() {
+            return None;
+        }
+        Self::parse(s).ok()
+    }
+
+    /// 由UNIX时间戳构造UTC时间
+    pub fn from_unix(secs: i64) -> Self {
+        let days = secs.div_euclid(86400);
+        let of_day = secs.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        PtpDateTime {
+            year: year as u16,
+            month,
+            day,
+            hour: (of_day / 3600) as u8,
+            minute: (of_day / 60 % 60) as u8,
+            second: (of_day % 60) as u8,
+            tenths: 0,
+            utc_offset: Some(0),
+        }
+    }
+
+    /// 当前UTC时间
+    pub fn now() -> Self {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Self::from_unix(secs as i64)
+    }
+
+    /// UNIX时间戳(秒)，未指定时区时按UTC换算
+    pub fn to_unix(&self) -> i64 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64
+            - self.utc_offset.unwrap_or(0) as i64 * 60
+    }
+
+    /// 转换为SystemTime，早于1970年时返回None
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        let secs = u64::try_from(self.to_unix()).ok()?;
+        Some(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(self.tenths as u64 * 100))
+    }
+
+    /// ISO 8601格式，例如"2024-05-01T12:34:56.5+08:00"，未指定时区时不带时区后缀
+    pub fn to_iso8601(&self) -> String {
+        let mut s = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        );
+        if self.tenths > 0 {
+            s.push_str(&format!(".{}", self.tenths));
+        }
+        match self.utc_offset {
+            None => {}
+            Some(0) => s.push('Z'),
+            Some(m) => s.push_str(&format!("{}{:02}:{:02}", if m < 0 { '-' } else { '+' }, m.abs() / 60, m.abs() % 60)),
+        }
+        s
+    }
+}
+
+impl fmt::Display for PtpDateTime {
+    /// PTP格式
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}{:02}{:02}T{:02}{:02}{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if self.tenths > 0 {
+            write!(f, ".{}", self.tenths)?;
+        }
+        match self.utc_offset {
+            None => Ok(()),
+            Some(0) => write!(f, "Z"),
+            Some(m) => write!(f, "{}{:02}{:02}", if m < 0 { '-' } else { '+' }, m.abs() / 60, m.abs() % 60),
+        }
+    }
+}
+
+impl PartialOrd for PtpDateTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PtpDateTime {
+    /// 按换算后的时间戳比较
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.to_unix(), self.tenths)
+            .cmp(&(other.to_unix(), other.tenths))
+            .then_with(|| self.utc_offset.cmp(&other.utc_offset))
+    }
+}
+
+impl Serialize for PtpDateTime {
+    /// 序列化为ISO 8601字符串，便于客户端直接使用
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_iso8601())
+    }
+}
+
+impl<'de> Deserialize<'de> for PtpDateTime {
+    /// 接受ISO 8601或PTP格式
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        // ISO格式去掉日期时间部分的分隔符和时区中的':'后与PTP格式相同
+        let ptp = match (s.get(..19), s.get(19..)) {
+            (Some(head), Some(zone)) if s.as_bytes()[4] == b'-' => {
+                let mut ptp: String = head.chars().filter(|c| *c != '-' && *c != ':').collect();
+                ptp.push_str(&zone.replace(':', ""));
+                ptp
+            }
+            _ => s,
+        };
+        PtpDateTime::parse(&ptp).map_err(serde::de::Error::custom)
+    }
+}
+
+fn is_leap(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// 公历日期到1970-01-01起的天数
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// 1970-01-01起的天数到公历日期
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -1,8 +1,11 @@
 #![allow(non_snake_case)]
 
 use std::io::Cursor;
+use serde::{Deserialize, Serialize};
 use crate::ptp_mtp::error::Error;
 use crate::ptp_mtp::data_types::PtpRead;
+use crate::ptp_mtp::datetime::PtpDateTime;
+use crate::ptp_mtp::standard_codes::{ObjectFormat, StandardDevicePropCode};
 
 /// PTP设备信息结构体
 #[allow(non_snake_case)]
@@ -50,7 +53,7 @@ impl PtpDeviceInfo {
 
 /// PTP对象信息结构体
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PtpObjectInfo {
     pub StorageID: u32,               // 存储ID
     pub ObjectFormat: u16,            // 对象格式
@@ -74,6 +77,21 @@ pub struct PtpObjectInfo {
 }
 
 impl PtpObjectInfo {
+    /// 对象格式
+    pub fn format(&self) -> ObjectFormat {
+        ObjectFormat::from(self.ObjectFormat)
+    }
+
+    /// 解析后的拍摄时间，相机未提供或格式错误时返回None
+    pub fn capture_date(&self) -> Option<PtpDateTime> {
+        PtpDateTime::parse_opt(&self.CaptureDate)
+    }
+
+    /// 解析后的修改时间
+    pub fn modification_date(&self) -> Option<PtpDateTime> {
+        PtpDateTime::parse_opt(&self.ModificationDate)
+    }
+
     /// 从字节缓冲区解码PTP对象信息
     pub fn decode(buf: &[u8]) -> Result<PtpObjectInfo, Error> {
         let mut cur = Cursor::new(buf);
@@ -153,7 +171,7 @@ pub enum PtpFormData {
 pub struct PtpPropInfo {
     pub PropertyCode: u16,                        // 属性代码
     pub DataType: u16,                            // 数据类型
-    pub GetSet: u8,                               // 读写权限（1=只读，2=读写）
+    pub GetSet: u8,                               // 读写权限（0=只读，1=读写）
     pub IsEnable: u8,                             // 是否启用
     pub FactoryDefault: crate::ptp_mtp::data_types::PtpDataType, // 出厂默认值
     pub Current: crate::ptp_mtp::data_types::PtpDataType,        // 当前值
@@ -164,7 +182,7 @@ impl PtpPropInfo {
     /// 从数据流中解码PTP属性信息
     pub fn decode<T: PtpRead>(cur: &mut T) -> Result<PtpPropInfo, Error> {
         use crate::ptp_mtp::data_types::PtpDataType;
-        use byteorder::{ReadBytesExt, LittleEndian};
+        use byteorder::LittleEndian;
         
         let data_type;
         Ok(PtpPropInfo {
@@ -206,6 +224,46 @@ impl PtpPropInfo {
     }
 }
 
+impl PtpFormData {
+    /// 转换为JSON，供客户端渲染取值控件
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            PtpFormData::None => serde_json::json!({ "type": "none" }),
+            PtpFormData::Range { minValue, maxValue, step } => serde_json::json!({
+                "type": "range",
+                "min": minValue.to_json(),
+                "max": maxValue.to_json(),
+                "step": step.to_json(),
+            }),
+            PtpFormData::Enumeration { array } => serde_json::json!({
+                "type": "enum",
+                "values": array.iter().map(|v| v.to_json()).collect::<Vec<_>>(),
+            }),
+        }
+    }
+}
+
+impl PtpPropInfo {
+    /// 是否可写
+    pub fn is_writable(&self) -> bool {
+        self.GetSet == 1
+    }
+
+    /// 转换为JSON，包含属性名称(已知的标准属性)、读写权限、当前值、默认值和取值范围
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "code": self.PropertyCode,
+            "name": StandardDevicePropCode::name(self.PropertyCode),
+            "dataType": self.DataType,
+            "writable": self.is_writable(),
+            "enabled": self.IsEnable != 0,
+            "current": self.Current.to_json(),
+            "default": self.FactoryDefault.to_json(),
+            "form": self.Form.to_json(),
+        })
+    }
+}
+
 /// PTP对象树结构体
 #[derive(Debug, Clone)]
 pub struct PtpObjectTree {
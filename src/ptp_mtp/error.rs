@@ -3,6 +3,8 @@
 use std::fmt;
 use std::io;
 
+use crate::ptp_mtp::standard_codes::{CommandCode, StandardCommandCode};
+
 /// PTP 命令错误类型
 #[derive(Debug)]
 pub enum Error {
@@ -20,6 +22,18 @@ pub enum Error {
 
     /// IO 操作错误
     Io(io::Error),
+
+    /// 传输在超时时间内没有完成
+    Timeout(String),
+
+    /// 读取被取消句柄中止
+    Cancelled,
+
+    /// 相机的DeviceInfo没有列出该操作，命令未发送
+    Unsupported(CommandCode),
+
+    /// 危险操作没有有效的确认，命令未发送
+    Unconfirmed(String),
 }
 
 impl fmt::Display for Error {
@@ -30,6 +44,10 @@ impl fmt::Display for Error {
             Error::Io(e) => write!(f, "IO 错误: {}", e),
             Error::Malformed(e) => write!(f, "{}", e),
             Error::NotFound(e) => write!(f, "未找到: {}", e),
+            Error::Timeout(e) => write!(f, "超时: {}", e),
+            Error::Cancelled => write!(f, "传输已取消"),
+            Error::Unsupported(c) => write!(f, "相机不支持操作 {} (0x{:04x})", StandardCommandCode::name(*c).unwrap_or("未知操作"), c),
+            Error::Unconfirmed(e) => write!(f, "未确认: {}", e),
         }
     }
 }
@@ -60,8 +78,51 @@ impl From<&str> for Error {
 impl From<io::Error> for Error {
     fn from(e: io::Error) -> Error {
         match e.kind() {
-            io::ErrorKind::UnexpectedEof => Error::Malformed(format!("意外的消息结束")),
+            io::ErrorKind::UnexpectedEof => Error::Malformed("意外的消息结束".into()),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout(e.to_string()),
             _ => Error::Io(e),
         }
     }
 }
+
+/// 分块读取时调用方回调返回的错误
+/// 回调的错误在读取回调里转成PTP错误以中止读取，原始错误保存下来返回给调用方
+#[derive(Default)]
+pub struct SinkError(Option<Box<dyn ::std::error::Error>>);
+
+impl SinkError {
+    /// 保存回调的错误，返回用于中止读取的PTP错误
+    pub fn capture(&mut self, result: Result<(), Box<dyn ::std::error::Error>>) -> Result<(), Error> {
+        result.map_err(|e| {
+            let msg = e.to_string();
+            self.0 = Some(e);
+            Error::Malformed(msg)
+        })
+    }
+
+    /// 合并读取结果，回调的错误优先
+    pub fn finish<T>(self, result: Result<T, Error>) -> Result<T, Box<dyn ::std::error::Error>> {
+        match (result, self.0) {
+            (_, Some(e)) => Err(e),
+            (Ok(value), None) => Ok(value),
+            (Err(e), None) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sink_error_takes_precedence_over_read_error() {
+        let mut sink_error = SinkError::default();
+        let aborted = sink_error.capture(Err("磁盘已满".into()));
+        assert!(matches!(aborted, Err(Error::Malformed(_))));
+        let result = sink_error.finish::<u64>(Err(Error::Cancelled));
+        assert_eq!(result.unwrap_err().to_string(), "磁盘已满");
+
+        let result = SinkError::default().finish::<u64>(Err(Error::Cancelled));
+        assert_eq!(result.unwrap_err().to_string(), "传输已取消");
+    }
+}
@@ -0,0 +1,115 @@
+#![allow(non_snake_case)]
+
+use std::io::Cursor;
+
+use crate::ptp_mtp::data_types::PtpRead;
+use crate::ptp_mtp::error::Error;
+use crate::ptp_mtp::standard_codes::{EventCode, PtpContainerType, StandardEventCode};
+
+/// 从中断端点收到的PTP事件
+#[derive(Debug, Clone, PartialEq)]
+pub enum PtpEvent {
+    /// 相机上新增了对象(对象句柄)
+    ObjectAdded(u32),
+    /// 对象已被删除(对象句柄)
+    ObjectRemoved(u32),
+    /// 插入了存储卡(存储ID)
+    StoreAdded(u32),
+    /// 存储卡已移除(存储ID)
+    StoreRemoved(u32),
+    /// 设备信息已改变，需要重新读取DeviceInfo
+    DeviceInfoChanged,
+    /// 拍摄完成(触发拍摄的InitiateCapture的事务ID，取自容器头，该事件没有参数)
+    CaptureComplete(u32),
+    /// 设备属性已改变(属性码)
+    DevicePropChanged(u16),
+    /// 存储已满(存储ID)
+    StoreFull(u32),
+    /// 其他事件
+    Other { code: EventCode, params: Vec<u32> },
+}
+
+impl PtpEvent {
+    /// 解析中断端点收到的事件容器
+    /// 事件容器格式: 长度(u32) 类型(u16=4) 事件码(u16) 事务ID(u32) 参数(最多3个u32)
+    pub fn decode(buf: &[u8]) -> Result<PtpEvent, Error> {
+        let mut cur = Cursor::new(buf);
+        let len = cur.read_ptp_u32()? as usize;
+        let kind = cur.read_ptp_u16()?;
+        if PtpContainerType::from_u16(kind) != Some(PtpContainerType::Event) {
+            return Err(Error::Malformed(format!("不是事件容器，类型 {:x}", kind)));
+        }
+        let code = cur.read_ptp_u16()?;
+        let tid = cur.read_ptp_u32()?;
+
+        let len = len.min(buf.len());
+        let mut params = Vec::with_capacity(3);
+        while (cur.position() as usize) + 4 <= len && params.len() < 3 {
+            params.push(cur.read_ptp_u32()?);
+        }
+        let param = |i: usize| params.get(i).copied().unwrap_or(0);
+
+        Ok(match code {
+            StandardEventCode::ObjectAdded => PtpEvent::ObjectAdded(param(0)),
+            StandardEventCode::ObjectRemoved => PtpEvent::ObjectRemoved(param(0)),
+            StandardEventCode::StoreAdded => PtpEvent::StoreAdded(param(0)),
+            StandardEventCode::StoreRemoved => PtpEvent::StoreRemoved(param(0)),
+            StandardEventCode::DeviceInfoChanged => PtpEvent::DeviceInfoChanged,
+            StandardEventCode::CaptureComplete => PtpEvent::CaptureComplete(tid),
+            StandardEventCode::DevicePropChanged => PtpEvent::DevicePropChanged(param(0) as u16),
+            StandardEventCode::StoreFull => PtpEvent::StoreFull(param(0)),
+            _ => PtpEvent::Other { code, params },
+        })
+    }
+
+    /// 事件码
+    pub fn code(&self) -> EventCode {
+        match self {
+            PtpEvent::ObjectAdded(_) => StandardEventCode::ObjectAdded,
+            PtpEvent::ObjectRemoved(_) => StandardEventCode::ObjectRemoved,
+            PtpEvent::StoreAdded(_) => StandardEventCode::StoreAdded,
+            PtpEvent::StoreRemoved(_) => StandardEventCode::StoreRemoved,
+            PtpEvent::DeviceInfoChanged => StandardEventCode::DeviceInfoChanged,
+            PtpEvent::CaptureComplete(_) => StandardEventCode::CaptureComplete,
+            PtpEvent::DevicePropChanged(_) => StandardEventCode::DevicePropChanged,
+            PtpEvent::StoreFull(_) => StandardEventCode::StoreFull,
+            PtpEvent::Other { code, .. } => *code,
+        }
+    }
+
+    /// 事件名称，厂商扩展事件返回None
+    pub fn name(&self) -> Option<&'static str> {
+        StandardEventCode::name(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(code: EventCode, tid: u32, params: &[u32]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(12 + params.len() as u32 * 4).to_le_bytes());
+        buf.extend_from_slice(&(PtpContainerType::Event as u16).to_le_bytes());
+        buf.extend_from_slice(&code.to_le_bytes());
+        buf.extend_from_slice(&tid.to_le_bytes());
+        buf.extend(params.iter().flat_map(|p| p.to_le_bytes()));
+        buf
+    }
+
+    #[test]
+    fn capture_complete_uses_container_transaction_id() {
+        let event = PtpEvent::decode(&container(StandardEventCode::CaptureComplete, 7, &[])).unwrap();
+        assert_eq!(event, PtpEvent::CaptureComplete(7));
+        // 不符合规范的参数被忽略
+        let event = PtpEvent::decode(&container(StandardEventCode::CaptureComplete, 7, &[0x1234])).unwrap();
+        assert_eq!(event, PtpEvent::CaptureComplete(7));
+    }
+
+    #[test]
+    fn object_added_reads_handle_parameter() {
+        let event = PtpEvent::decode(&container(StandardEventCode::ObjectAdded, 7, &[0x0001_0002])).unwrap();
+        assert_eq!(event, PtpEvent::ObjectAdded(0x0001_0002));
+        assert_eq!(event.name(), Some("新增对象"));
+    }
+}
@@ -0,0 +1,403 @@
+// PTP/IP传输层 - 通过相机自带的Wi-Fi用TCP与相机通信(佳能、尼康、索尼等)
+//
+// PTP/IP使用两条TCP连接：命令连接承载操作请求/数据/响应，事件连接承载事件。
+// 本模块把 `PtpCamera` 写出的USB风格容器翻译成PTP/IP报文，再把相机的报文还原成USB风格容器，
+// 因此上层的事务逻辑无需区分USB和PTP/IP
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use log::{debug, info, trace};
+
+use crate::ptp_mtp::error::Error;
+use crate::ptp_mtp::standard_codes::PtpContainerType;
+use crate::ptp_mtp::transport::PtpTransport;
+
+/// PTP/IP标准端口
+pub const PTPIP_PORT: u16 = 15740;
+
+/// PTP/IP协议版本 1.0
+const PROTOCOL_VERSION: u32 = 0x0001_0000;
+
+/// USB风格容器头长度
+const CONTAINER_HEADER_LEN: usize = 12;
+
+/// 报文长度上限，防止异常长度导致大量分配
+const MAX_PACKET_LEN: usize = 1024 * 1024;
+
+/// 没有事件时轮询事件连接的等待时间
+const EVENT_POLL_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// PTP/IP报文类型(完整列出规范定义的类型，部分暂未使用)
+#[allow(dead_code)]
+pub mod packet {
+    pub const INIT_COMMAND_REQUEST: u32 = 1;
+    pub const INIT_COMMAND_ACK: u32 = 2;
+    pub const INIT_EVENT_REQUEST: u32 = 3;
+    pub const INIT_EVENT_ACK: u32 = 4;
+    pub const INIT_FAIL: u32 = 5;
+    pub const OPERATION_REQUEST: u32 = 6;
+    pub const OPERATION_RESPONSE: u32 = 7;
+    pub const EVENT: u32 = 8;
+    pub const START_DATA: u32 = 9;
+    pub const DATA: u32 = 10;
+    pub const CANCEL: u32 = 11;
+    pub const END_DATA: u32 = 12;
+    pub const PROBE_REQUEST: u32 = 13;
+    pub const PROBE_RESPONSE: u32 = 14;
+}
+
+/// 操作请求中的数据阶段信息
+const DATA_PHASE_NONE_OR_IN: u32 = 1; // 无数据或数据由相机发出
+const DATA_PHASE_OUT: u32 = 2; // 数据由主机发出
+
+/// 读取一个报文，返回(类型, 负载)
+fn read_packet(stream: &mut TcpStream) -> Result<(u32, Vec<u8>), Error> {
+    let len = stream.read_u32::<LittleEndian>()? as usize;
+    let kind = stream.read_u32::<LittleEndian>()?;
+    if !(8..=MAX_PACKET_LEN).contains(&len) {
+        return Err(Error::Malformed(format!("PTP/IP报文长度无效: {}", len)));
+    }
+    let mut payload = vec![0u8; len - 8];
+    stream.read_exact(&mut payload)?;
+    trace!("PTP/IP 收到报文 {} ({} 字节)", kind, payload.len());
+    Ok((kind, payload))
+}
+
+/// 写出一个报文
+fn write_packet(stream: &mut TcpStream, kind: u32, payload: &[u8]) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(8 + payload.len());
+    buf.write_u32::<LittleEndian>((8 + payload.len()) as u32)?;
+    buf.write_u32::<LittleEndian>(kind)?;
+    buf.extend_from_slice(payload);
+    stream.write_all(&buf)?;
+    trace!("PTP/IP 发送报文 {} ({} 字节)", kind, payload.len());
+    Ok(())
+}
+
+/// 写入以0结尾的UTF-16LE字符串(握手报文中的名称字段)
+fn put_utf16z(buf: &mut Vec<u8>, s: &str) {
+    for unit in s.encode_utf16().chain(std::iter::once(0)) {
+        buf.extend_from_slice(&unit.to_le_bytes());
+    }
+}
+
+/// 读取以0结尾的UTF-16LE字符串
+fn read_utf16z(buf: &[u8]) -> String {
+    let units: Vec<u16> = buf
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|u| *u != 0)
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> Result<u32, Error> {
+    buf.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| Error::Malformed("PTP/IP报文过短".to_string()))
+}
+
+/// 拼装USB风格的容器头
+fn container_header(buf: &mut VecDeque<u8>, total_len: usize, kind: PtpContainerType, code: u16, tid: u32) {
+    buf.extend((total_len as u32).to_le_bytes());
+    buf.extend((kind as u16).to_le_bytes());
+    buf.extend(code.to_le_bytes());
+    buf.extend(tid.to_le_bytes());
+}
+
+/// 握手得到的相机信息
+#[derive(Debug, Clone)]
+pub struct PtpIpPeer {
+    pub connection_number: u32,
+    pub guid: [u8; 16],
+    pub name: String,
+    pub protocol_version: u32,
+}
+
+/// 等待发出的操作请求：只有看到下一个容器才知道是否有主机数据阶段
+struct PendingCommand {
+    code: u16,
+    tid: u32,
+    params: Vec<u8>,
+}
+
+/// 主机正在写出的数据阶段
+struct OutgoingData {
+    tid: u32,
+    remaining: usize,
+}
+
+/// PTP/IP传输层
+pub struct PtpIpTransport {
+    command: TcpStream,
+    event: TcpStream,
+    peer: PtpIpPeer,
+    pending: Option<PendingCommand>,
+    outgoing: Option<OutgoingData>,
+    current_code: u16,
+    rx: VecDeque<u8>,
+}
+
+impl PtpIpTransport {
+    /// 连接相机并完成PTP/IP握手
+    /// `guid` 标识本设备，相机通常会记住配对过的GUID；`host_name` 显示在相机的连接界面上
+    pub fn connect(addr: SocketAddr, guid: [u8; 16], host_name: &str, timeout: Duration) -> Result<Self, Error> {
+        info!("通过PTP/IP连接相机 {}", addr);
+        let mut command = TcpStream::connect_timeout(&addr, timeout)?;
+        command.set_nodelay(true)?;
+        command.set_read_timeout(Some(timeout))?;
+
+        let mut request = Vec::with_capacity(16 + host_name.len() * 2 + 6);
+        request.extend_from_slice(&guid);
+        put_utf16z(&mut request, host_name);
+        request.write_u32::<LittleEndian>(PROTOCOL_VERSION)?;
+        write_packet(&mut command, packet::INIT_COMMAND_REQUEST, &request)?;
+
+        let peer = match read_packet(&mut command)? {
+            (packet::INIT_COMMAND_ACK, payload) => {
+                if payload.len() < 20 {
+                    return Err(Error::Malformed("InitCommandAck过短".to_string()));
+                }
+                let connection_number = u32_at(&payload, 0)?;
+                let mut guid = [0u8; 16];
+                guid.copy_from_slice(&payload[4..20]);
+                let name = read_utf16z(&payload[20..]);
+                // 版本号位于名称之后的最后4个字节
+                let protocol_version = u32_at(&payload, payload.len() - 4).unwrap_or(0);
+                PtpIpPeer { connection_number, guid, name, protocol_version }
+            }
+            (packet::INIT_FAIL, payload) => {
+                return Err(Error::NotFound(format!(
+                    "相机拒绝了连接(原因 {})，请在相机上确认配对",
+                    u32_at(&payload, 0).unwrap_or(0)
+                )));
+            }
+            (kind, _) => return Err(Error::Malformed(format!("握手时收到意外的报文类型 {}", kind))),
+        };
+        debug!("PTP/IP命令连接已建立: {} (连接号 {})", peer.name, peer.connection_number);
+
+        let mut event = TcpStream::connect_timeout(&addr, timeout)?;
+        event.set_nodelay(true)?;
+        event.set_read_timeout(Some(timeout))?;
+        write_packet(&mut event, packet::INIT_EVENT_REQUEST, &peer.connection_number.to_le_bytes())?;
+        match read_packet(&mut event)? {
+            (packet::INIT_EVENT_ACK, _) => {}
+            (packet::INIT_FAIL, payload) => {
+                return Err(Error::NotFound(format!(
+                    "相机拒绝了事件连接(原因 {})",
+                    u32_at(&payload, 0).unwrap_or(0)
+                )));
+            }
+            (kind, _) => return Err(Error::Malformed(format!("建立事件连接时收到意外的报文类型 {}", kind))),
+        }
+        info!("已通过PTP/IP连接 {}", peer.name);
+
+        Ok(PtpIpTransport {
+            command,
+            event,
+            peer,
+            pending: None,
+            outgoing: None,
+            current_code: 0,
+            rx: VecDeque::new(),
+        })
+    }
+
+    /// 握手时相机报告的信息
+    pub fn peer(&self) -> &PtpIpPeer {
+        &self.peer
+    }
+
+    /// 发出操作请求
+    fn send_operation(&mut self, command: PendingCommand, data_phase: u32) -> Result<(), Error> {
+        let mut payload = Vec::with_capacity(10 + command.params.len());
+        payload.write_u32::<LittleEndian>(data_phase)?;
+        payload.write_u16::<LittleEndian>(command.code)?;
+        payload.write_u32::<LittleEndian>(command.tid)?;
+        payload.extend_from_slice(&command.params);
+        self.current_code = command.code;
+        write_packet(&mut self.command, packet::OPERATION_REQUEST, &payload)
+    }
+
+    /// 发出尚未发送的无主机数据的操作请求
+    fn flush_pending(&mut self) -> Result<(), Error> {
+        match self.pending.take() {
+            Some(command) => self.send_operation(command, DATA_PHASE_NONE_OR_IN),
+            None => Ok(()),
+        }
+    }
+
+    /// 写出主机数据阶段的一段，写完最后一段时发送EndData
+    fn write_outgoing(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        let outgoing = self
+            .outgoing
+            .as_mut()
+            .ok_or_else(|| Error::Malformed("没有进行中的数据阶段".to_string()))?;
+        if chunk.len() > outgoing.remaining {
+            return Err(Error::Malformed("写出的数据超过数据阶段声明的长度".to_string()));
+        }
+        outgoing.remaining -= chunk.len();
+        let tid = outgoing.tid;
+        let kind = if outgoing.remaining == 0 {
+            self.outgoing = None;
+            packet::END_DATA
+        } else {
+            packet::DATA
+        };
+        let mut payload = Vec::with_capacity(4 + chunk.len());
+        payload.write_u32::<LittleEndian>(tid)?;
+        payload.extend_from_slice(chunk);
+        write_packet(&mut self.command, kind, &payload)
+    }
+
+    /// 读取命令连接上的下一个报文，还原为USB风格容器放入接收队列
+    fn receive(&mut self) -> Result<(), Error> {
+        let (kind, payload) = read_packet(&mut self.command)?;
+        match kind {
+            packet::START_DATA => {
+                let tid = u32_at(&payload, 0)?;
+                let total = (&payload[4..]).read_u64::<LittleEndian>()? as usize;
+                container_header(&mut self.rx, CONTAINER_HEADER_LEN + total, PtpContainerType::Data, self.current_code, tid);
+            }
+            packet::DATA | packet::END_DATA => {
+                if payload.len() < 4 {
+                    return Err(Error::Malformed("Data报文过短".to_string()));
+                }
+                self.rx.extend(&payload[4..]);
+            }
+            packet::OPERATION_RESPONSE => {
+                if payload.len() < 6 {
+                    return Err(Error::Malformed("OperationResponse报文过短".to_string()));
+                }
+                let code = u16::from_le_bytes([payload[0], payload[1]]);
+                let tid = u32_at(&payload, 2)?;
+                let params = &payload[6..];
+                container_header(
+                    &mut self.rx,
+                    CONTAINER_HEADER_LEN + params.len(),
+                    PtpContainerType::Response,
+                    code,
+                    tid,
+                );
+                self.rx.extend(params);
+            }
+            packet::PROBE_REQUEST => write_packet(&mut self.command, packet::PROBE_RESPONSE, &[])?,
+            other => debug!("忽略命令连接上的报文类型 {}", other),
+        }
+        Ok(())
+    }
+}
+
+impl PtpTransport for PtpIpTransport {
+    async fn bulk_write(&mut self, data: &[u8], _timeout_ms: Option<u64>) -> Result<usize, Error> {
+        // 数据阶段的后续块不带容器头
+        if self.outgoing.is_some() {
+            self.write_outgoing(data)?;
+            return Ok(data.len());
+        }
+
+        if data.len() < CONTAINER_HEADER_LEN {
+            return Err(Error::Malformed(format!("容器过短: {} 字节", data.len())));
+        }
+        let total_len = u32_at(data, 0)? as usize;
+        let kind = u16::from_le_bytes([data[4], data[5]]);
+        let code = u16::from_le_bytes([data[6], data[7]]);
+        let tid = u32_at(data, 8)?;
+        let body = &data[CONTAINER_HEADER_LEN..];
+
+        match PtpContainerType::from_u16(kind) {
+            Some(PtpContainerType::Command) => {
+                self.flush_pending()?;
+                self.pending = Some(PendingCommand { code, tid, params: body.to_vec() });
+            }
+            Some(PtpContainerType::Data) => {
+                match self.pending.take() {
+                    Some(command) if command.tid == tid => self.send_operation(command, DATA_PHASE_OUT)?,
+                    _ => return Err(Error::Malformed(format!("数据阶段(tid {})前没有对应的操作请求", tid))),
+                }
+                let data_len = total_len.saturating_sub(CONTAINER_HEADER_LEN);
+                let mut start = Vec::with_capacity(12);
+                start.write_u32::<LittleEndian>(tid)?;
+                start.write_u64::<LittleEndian>(data_len as u64)?;
+                write_packet(&mut self.command, packet::START_DATA, &start)?;
+                self.outgoing = Some(OutgoingData { tid, remaining: data_len });
+                if data_len == 0 {
+                    self.write_outgoing(&[])?;
+                } else {
+                    self.write_outgoing(body)?;
+                }
+            }
+            _ => return Err(Error::Malformed(format!("主机不能发送类型为 {} 的容器", kind))),
+        }
+        Ok(data.len())
+    }
+
+    async fn bulk_read(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        self.flush_pending()?;
+        let timeout = timeout_ms.filter(|t| *t > 0).map(Duration::from_millis);
+        self.command.set_read_timeout(timeout)?;
+        while self.rx.is_empty() {
+            self.receive()?;
+        }
+        let n = buffer.len().min(self.rx.len());
+        for (dst, src) in buffer.iter_mut().zip(self.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    async fn read_interrupt_event(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        let timeout = timeout_ms.filter(|t| *t > 0).map_or(EVENT_POLL_TIMEOUT, Duration::from_millis);
+        self.event.set_read_timeout(Some(timeout))?;
+        let mut len_bytes = [0u8; 4];
+        match self.event.read(&mut len_bytes[..1]) {
+            Ok(0) => return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "相机关闭了事件连接"))),
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(0),
+            Err(e) => return Err(e.into()),
+        }
+        // 已经收到报文的第一个字节，剩余部分用正常的超时读取
+        self.event.set_read_timeout(self.command.read_timeout()?)?;
+        self.event.read_exact(&mut len_bytes[1..])?;
+        let len = u32::from_le_bytes(len_bytes) as usize;
+        if !(8..=MAX_PACKET_LEN).contains(&len) {
+            return Err(Error::Malformed(format!("PTP/IP报文长度无效: {}", len)));
+        }
+        let kind = self.event.read_u32::<LittleEndian>()?;
+        let mut payload = vec![0u8; len - 8];
+        self.event.read_exact(&mut payload)?;
+
+        match kind {
+            packet::EVENT if payload.len() >= 6 => {
+                let code = u16::from_le_bytes([payload[0], payload[1]]);
+                let tid = u32_at(&payload, 2)?;
+                let params = &payload[6..];
+                let mut container = VecDeque::with_capacity(CONTAINER_HEADER_LEN + params.len());
+                container_header(
+                    &mut container,
+                    CONTAINER_HEADER_LEN + params.len(),
+                    PtpContainerType::Event,
+                    code,
+                    tid,
+                );
+                container.extend(params);
+                let n = buffer.len().min(container.len());
+                for (dst, src) in buffer.iter_mut().zip(container.drain(..n)) {
+                    *dst = src;
+                }
+                Ok(n)
+            }
+            packet::PROBE_REQUEST => {
+                write_packet(&mut self.event, packet::PROBE_RESPONSE, &[])?;
+                Ok(0)
+            }
+            other => {
+                debug!("忽略事件连接上的报文类型 {}", other);
+                Ok(0)
+            }
+        }
+    }
+}
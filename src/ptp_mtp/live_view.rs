@@ -0,0 +1,253 @@
+// 实时取景 - 按配置的帧率轮询厂商的取景命令，从返回数据中按JPEG的SOI/EOI标记切出完整的帧，
+// 包装为实时取景数据包，由主循环经无线链路推送给客户端
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{info, warn};
+
+use crate::ptp_mtp::camera::{PtpCamera, TransactionTimeouts};
+use crate::ptp_mtp::error::Error;
+use crate::ptp_mtp::standard_codes::{CommandCode, StandardResponseCode};
+#[cfg(feature = "vendor-canon")]
+use crate::ptp_mtp::vendor::canon::CanonEos;
+use crate::ptp_mtp::vendor::Vendor;
+use crate::ptp_mtp::{DataPacket, PacketType};
+
+/// 默认帧率
+pub const DEFAULT_FPS: u8 = 10;
+/// 单帧的最大字节数，缓存超过时丢弃并重新寻找帧起点
+const MAX_FRAME_LEN: usize = 512 * 1024;
+
+const NIKON_START_LIVE_VIEW: CommandCode = 0x9201;
+const NIKON_END_LIVE_VIEW: CommandCode = 0x9202;
+const NIKON_GET_LIVE_VIEW_IMAGE: CommandCode = 0x9203;
+
+/// JPEG帧切分器
+///
+/// 输入可以带有厂商头部，一帧可以分多次送入，一次也可以包含多帧；
+/// SOS之前按段长度跳过(APP1中的EXIF缩略图自带的EOI不会被误认为帧结束)，之后在熵编码数据中寻找EOI
+#[derive(Debug, Default)]
+pub struct JpegFramer {
+    buf: Vec<u8>,
+    dropped: u64, // 结构损坏或超长而丢弃的帧数
+}
+
+impl JpegFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 追加数据，返回其中所有完整的帧
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.buf.extend_from_slice(data);
+        let mut frames = Vec::new();
+        loop {
+            // 丢弃SOI之前的厂商头部等数据，末尾的0xFF可能是下一个SOI的一半
+            match self.buf.windows(2).position(|w| w == [0xFF, 0xD8]) {
+                Some(start) => {
+                    self.buf.drain(..start);
+                }
+                None => {
+                    let keep = usize::from(self.buf.last() == Some(&0xFF));
+                    self.buf.drain(..self.buf.len() - keep);
+                    break;
+                }
+            }
+            match frame_end(&self.buf) {
+                Ok(Some(end)) => frames.push(self.buf.drain(..end).collect()),
+                Ok(None) if self.buf.len() <= MAX_FRAME_LEN => break,
+                result => {
+                    if result.is_ok() {
+                        warn!("取景帧超过 {} 字节仍未结束，丢弃", MAX_FRAME_LEN);
+                    }
+                    // 跳过这个SOI，从下一个SOI重新同步
+                    self.dropped += 1;
+                    self.buf.drain(..2);
+                }
+            }
+        }
+        frames
+    }
+
+    /// 丢弃的帧数
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// 清空缓存的不完整数据(例如重新开始取景时)
+    pub fn reset(&mut self) {
+        self.buf.clear();
+    }
+}
+
+/// 从SOI开始解析JPEG结构，返回EOI之后的位置；数据不完整时返回Ok(None)，结构损坏时返回Err
+fn frame_end(buf: &[u8]) -> Result<Option<usize>, ()> {
+    let mut i = 2;
+    let mut in_scan = false;
+    loop {
+        if in_scan {
+            // 熵编码数据中0xFF后跟0x00是字节填充，后跟RSTn是重同步标记，都不是段的边界
+            while i + 1 < buf.len() && (buf[i] != 0xFF || matches!(buf[i + 1], 0x00 | 0xD0..=0xD7)) {
+                i += 1;
+            }
+            if i + 1 >= buf.len() {
+                return Ok(None);
+            }
+        }
+        let Some(&[prefix, marker]) = buf.get(i..i + 2) else {
+            return Ok(None);
+        };
+        if prefix != 0xFF {
+            return Err(());
+        }
+        match marker {
+            0xD9 => return Ok(Some(i + 2)),
+            // 填充字节
+            0xFF => i += 1,
+            // 没有长度字段的标记
+            0x01 | 0xD0..=0xD7 => i += 2,
+            0x00 | 0xD8 => return Err(()),
+            _ => {
+                let Some(&[hi, lo]) = buf.get(i + 2..i + 4) else {
+                    return Ok(None);
+                };
+                let len = u16::from_be_bytes([hi, lo]) as usize;
+                if len < 2 {
+                    return Err(());
+                }
+                i += 2 + len;
+                // SOS之后是熵编码数据；渐进式JPEG有多个扫描，每个SOS之后都是
+                in_scan = marker == 0xDA;
+            }
+        }
+    }
+}
+
+/// 实时取景
+pub struct LiveView {
+    vendor: Vendor,
+    interval: Duration,
+    next_poll: Instant,
+    timeout: Option<Duration>,
+    framer: JpegFramer,
+    frames: u64,
+}
+
+impl LiveView {
+    /// 是否支持该厂商的取景命令
+    pub fn is_supported(vendor: Vendor) -> bool {
+        match vendor {
+            #[cfg(feature = "vendor-canon")]
+            Vendor::Canon => true,
+            Vendor::Nikon => true,
+            _ => false,
+        }
+    }
+
+    pub fn new(vendor: Vendor, fps: u8, timeout: Option<Duration>) -> Result<Self, Error> {
+        if !Self::is_supported(vendor) {
+            return Err(Error::NotFound(format!("不支持 {:?} 相机的实时取景", vendor)));
+        }
+        let mut live_view = LiveView {
+            vendor,
+            interval: Duration::ZERO,
+            next_poll: Instant::now(),
+            timeout,
+            framer: JpegFramer::new(),
+            frames: 0,
+        };
+        live_view.set_fps(fps);
+        Ok(live_view)
+    }
+
+    /// 设置帧率
+    pub fn set_fps(&mut self, fps: u8) {
+        self.interval = Duration::from_millis(1000 / fps.max(1) as u64);
+    }
+
+    /// 已输出的帧数
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// 丢弃的帧数
+    pub fn dropped(&self) -> u64 {
+        self.framer.dropped()
+    }
+
+    /// 让相机开始向主机输出取景画面
+    pub async fn start(&mut self, camera: &mut PtpCamera) -> Result<(), Error> {
+        match self.vendor {
+            #[cfg(feature = "vendor-canon")]
+            Vendor::Canon => {
+                let mut eos = CanonEos::new(camera, self.timeout);
+                eos.init().await?;
+                eos.start_live_view().await?;
+            }
+            _ => {
+                camera.command(NIKON_START_LIVE_VIEW, &[], None, self.timeouts()).await?;
+            }
+        }
+        self.framer.reset();
+        self.next_poll = Instant::now();
+        info!("实时取景已开始，每帧间隔 {}ms", self.interval.as_millis());
+        Ok(())
+    }
+
+    /// 停止输出取景画面
+    pub async fn stop(&mut self, camera: &mut PtpCamera) -> Result<(), Error> {
+        match self.vendor {
+            #[cfg(feature = "vendor-canon")]
+            Vendor::Canon => CanonEos::new(camera, self.timeout).stop_live_view().await?,
+            _ => {
+                camera.command(NIKON_END_LIVE_VIEW, &[], None, self.timeouts()).await?;
+            }
+        }
+        info!("实时取景已停止，共 {} 帧，丢弃 {} 帧", self.frames(), self.dropped());
+        Ok(())
+    }
+
+    /// 按帧率读取一帧，距上一次读取不足一帧间隔时返回None
+    pub async fn poll_frame(&mut self, camera: &mut PtpCamera) -> Result<Option<DataPacket>, Error> {
+        let now = Instant::now();
+        if now < self.next_poll {
+            return Ok(None);
+        }
+        self.next_poll = now + self.interval;
+        self.next_frame(camera).await
+    }
+
+    /// 立即读取一帧，相机画面尚未就绪或数据中没有完整的帧时返回None
+    pub async fn next_frame(&mut self, camera: &mut PtpCamera) -> Result<Option<DataPacket>, Error> {
+        let Some(data) = self.fetch(camera).await? else {
+            return Ok(None);
+        };
+        // 每次读取都是一帧完整画面，积压的旧帧没有意义，只取最新的一帧
+        let Some(frame) = self.framer.push(&data).pop() else {
+            return Ok(None);
+        };
+        self.frames += 1;
+        Ok(Some(DataPacket {
+            data: frame,
+            timestamp: SystemTime::now(),
+            packet_type: PacketType::LiveView,
+        }))
+    }
+
+    async fn fetch(&mut self, camera: &mut PtpCamera) -> Result<Option<Vec<u8>>, Error> {
+        let result = match self.vendor {
+            #[cfg(feature = "vendor-canon")]
+            Vendor::Canon => return CanonEos::new(camera, self.timeout).get_live_view_image().await,
+            _ => camera.command(NIKON_GET_LIVE_VIEW_IMAGE, &[], None, self.timeouts()).await,
+        };
+        match result {
+            Ok(data) => Ok(Some(data)),
+            // 取景刚开始时相机需要一段时间准备画面
+            Err(Error::Response(StandardResponseCode::DeviceBusy)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn timeouts(&self) -> Option<TransactionTimeouts> {
+        self.timeout.map(TransactionTimeouts::uniform)
+    }
+}
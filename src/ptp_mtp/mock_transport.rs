@@ -0,0 +1,250 @@
+// 模拟传输层 - 按预先排好的顺序向PtpCamera返回设备端的容器字节流，并记录主机写出的数据，
+// 不需要ESP32和相机即可在主机上检查容器解析、设备信息解码和事务状态机
+use std::collections::VecDeque;
+
+use crate::ptp_mtp::error::Error;
+use crate::ptp_mtp::standard_codes::{PtpContainerType, StandardResponseCode};
+use crate::ptp_mtp::transport::{PtpTransport, DEFAULT_PACKET_SIZE};
+
+/// 容器头长度(字节)
+const CONTAINER_HEADER_LEN: usize = 12;
+
+/// 设备端的下一次批量传输
+enum Incoming {
+    Bytes(Vec<u8>),
+    Error(Error),
+}
+
+/// 主机写出的一个容器
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentContainer {
+    pub kind: u16,
+    pub code: u16,
+    pub tid: u32,
+    pub payload: Vec<u8>,
+}
+
+impl SentContainer {
+    /// 载荷按u32参数解析(命令容器)
+    pub fn params(&self) -> Vec<u32> {
+        self.payload
+            .chunks_exact(4)
+            .map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]]))
+            .collect()
+    }
+}
+
+/// 按完整容器拼装字节：长度(u32) 类型(u16) 代码(u16) 事务ID(u32) 载荷
+pub fn container(kind: PtpContainerType, code: u16, tid: u32, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(CONTAINER_HEADER_LEN + payload.len());
+    buf.extend_from_slice(&((CONTAINER_HEADER_LEN + payload.len()) as u32).to_le_bytes());
+    buf.extend_from_slice(&(kind as u16).to_le_bytes());
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&tid.to_le_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+fn params_payload(params: &[u32]) -> Vec<u8> {
+    params.iter().flat_map(|p| p.to_le_bytes()).collect()
+}
+
+/// 模拟传输层
+///
+/// 每个排队的容器是一次批量传输：读取按缓冲区大小分段返回，不会跨到下一个容器；
+/// 容器长度是包大小的整数倍且最后一次读取恰好读满时，和真实设备一样再返回一个零长度包。
+/// 队列读空后读取返回超时。收到取消请求时丢弃正在读取的传输的剩余部分，之后排队的容器不受影响
+pub struct MockTransport {
+    incoming: VecDeque<Incoming>,
+    current: Option<(Vec<u8>, usize)>, // 正在读取的传输及已读位置
+    zlp_pending: bool,
+    cancelled: bool, // 收到取消请求，下一次读取超时结束主机清空端点
+    events: VecDeque<Vec<u8>>,
+    written: Vec<Vec<u8>>,
+    packet_size: usize,
+    has_events: bool,
+    cancels: Vec<u32>,
+    device_status: VecDeque<u16>,
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        MockTransport {
+            incoming: VecDeque::new(),
+            current: None,
+            zlp_pending: false,
+            cancelled: false,
+            events: VecDeque::new(),
+            written: Vec::new(),
+            packet_size: DEFAULT_PACKET_SIZE,
+            has_events: true,
+            cancels: Vec::new(),
+            device_status: VecDeque::new(),
+        }
+    }
+
+    /// 设置输出端点的包大小(默认512，全速设备为64)
+    pub fn set_packet_size(&mut self, packet_size: usize) -> &mut Self {
+        self.packet_size = packet_size.max(1);
+        self
+    }
+
+    /// 模拟没有中断端点的相机
+    pub fn set_has_events(&mut self, has_events: bool) -> &mut Self {
+        self.has_events = has_events;
+        self
+    }
+
+    /// 排队一段原样返回的字节流，可以是格式错误的容器
+    pub fn push_bytes(&mut self, bytes: Vec<u8>) -> &mut Self {
+        self.incoming.push_back(Incoming::Bytes(bytes));
+        self
+    }
+
+    /// 排队一个数据容器
+    pub fn push_data(&mut self, code: u16, tid: u32, payload: &[u8]) -> &mut Self {
+        self.push_bytes(container(PtpContainerType::Data, code, tid, payload))
+    }
+
+    /// 排队一个响应容器
+    pub fn push_response(&mut self, code: u16, tid: u32, params: &[u32]) -> &mut Self {
+        self.push_bytes(container(PtpContainerType::Response, code, tid, &params_payload(params)))
+    }
+
+    /// 排队一次读取错误，例如`Error::Timeout`触发重试
+    pub fn push_read_error(&mut self, error: Error) -> &mut Self {
+        self.incoming.push_back(Incoming::Error(error));
+        self
+    }
+
+    /// 排队一个中断端点上的事件
+    pub fn push_event(&mut self, code: u16, tid: u32, params: &[u32]) -> &mut Self {
+        self.events.push_back(container(PtpContainerType::Event, code, tid, &params_payload(params)));
+        self
+    }
+
+    /// 排队取消后设备状态请求依次返回的响应码，用完后返回Ok
+    pub fn push_device_status(&mut self, code: u16) -> &mut Self {
+        self.device_status.push_back(code);
+        self
+    }
+
+    /// 排队的设备数据是否都已被读取
+    pub fn is_drained(&self) -> bool {
+        self.incoming.is_empty() && self.current.is_none()
+    }
+
+    /// 主机每次批量写入的原始数据(包括零长度包)
+    pub fn written(&self) -> &[Vec<u8>] {
+        &self.written
+    }
+
+    /// 主机请求取消的事务ID
+    pub fn cancels(&self) -> &[u32] {
+        &self.cancels
+    }
+
+    /// 把主机写出的数据按容器长度重新拼装；分块写入的数据阶段合并为一个容器，零长度包被忽略
+    pub fn sent(&self) -> Result<Vec<SentContainer>, Error> {
+        let stream: Vec<u8> = self.written.concat();
+        let mut containers = Vec::new();
+        let mut rest = &stream[..];
+        while !rest.is_empty() {
+            if rest.len() < CONTAINER_HEADER_LEN {
+                return Err(Error::Malformed(format!("主机写出的数据末尾有 {} 字节不完整的容器头", rest.len())));
+            }
+            let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            if len < CONTAINER_HEADER_LEN || len > rest.len() {
+                return Err(Error::Malformed(format!("主机写出的容器长度 {} 无效", len)));
+            }
+            containers.push(SentContainer {
+                kind: u16::from_le_bytes([rest[4], rest[5]]),
+                code: u16::from_le_bytes([rest[6], rest[7]]),
+                tid: u32::from_le_bytes([rest[8], rest[9], rest[10], rest[11]]),
+                payload: rest[CONTAINER_HEADER_LEN..len].to_vec(),
+            });
+            rest = &rest[len..];
+        }
+        Ok(containers)
+    }
+
+    /// 主机发出的命令容器
+    pub fn commands(&self) -> Result<Vec<SentContainer>, Error> {
+        Ok(self
+            .sent()?
+            .into_iter()
+            .filter(|c| c.kind == PtpContainerType::Command as u16)
+            .collect())
+    }
+}
+
+impl PtpTransport for MockTransport {
+    async fn bulk_write(&mut self, data: &[u8], _timeout_ms: Option<u64>) -> Result<usize, Error> {
+        self.written.push(data.to_vec());
+        Ok(data.len())
+    }
+
+    async fn bulk_read(&mut self, buffer: &mut [u8], _timeout_ms: Option<u64>) -> Result<usize, Error> {
+        if std::mem::take(&mut self.cancelled) {
+            return Err(Error::Timeout("取消后端点中没有剩余数据".into()));
+        }
+        if self.current.is_none() {
+            if std::mem::take(&mut self.zlp_pending) {
+                return Ok(0);
+            }
+            match self.incoming.pop_front() {
+                Some(Incoming::Bytes(bytes)) => self.current = Some((bytes, 0)),
+                Some(Incoming::Error(e)) => return Err(e),
+                None => return Err(Error::Timeout("模拟传输层没有更多设备数据".into())),
+            }
+        }
+        let Some((bytes, pos)) = self.current.as_mut() else {
+            return Ok(0);
+        };
+        let n = (bytes.len() - *pos).min(buffer.len());
+        buffer[..n].copy_from_slice(&bytes[*pos..*pos + n]);
+        *pos += n;
+        if *pos == bytes.len() {
+            self.zlp_pending = n == buffer.len() && bytes.len() % self.packet_size == 0;
+            self.current = None;
+        }
+        Ok(n)
+    }
+
+    async fn read_interrupt_event(&mut self, buffer: &mut [u8], _timeout_ms: Option<u64>) -> Result<usize, Error> {
+        match self.events.pop_front() {
+            Some(event) => {
+                let n = event.len().min(buffer.len());
+                buffer[..n].copy_from_slice(&event[..n]);
+                Ok(n)
+            }
+            None => Ok(0),
+        }
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.packet_size
+    }
+
+    fn has_events(&self) -> bool {
+        self.has_events
+    }
+
+    async fn request_cancel(&mut self, tid: u32) -> Result<(), Error> {
+        self.cancels.push(tid);
+        self.current = None;
+        self.zlp_pending = false;
+        self.cancelled = true;
+        Ok(())
+    }
+
+    async fn device_status(&mut self) -> Result<u16, Error> {
+        Ok(self.device_status.pop_front().unwrap_or(StandardResponseCode::Ok))
+    }
+}
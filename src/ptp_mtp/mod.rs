@@ -4,19 +4,48 @@
 mod error;
 mod standard_codes;
 mod data_types;
+mod datetime;
 mod device_info;
 mod camera;
+mod event;
+mod mtp;
+#[cfg(target_os = "espidf")]
+mod usb_transport;
+pub(crate) mod buffer_pool;
+pub mod calibrate;
+pub(crate) mod capabilities;
+pub(crate) mod ip_transport;
+#[cfg(feature = "live-view")]
+pub(crate) mod live_view;
+#[cfg(any(test, feature = "mock-transport"))]
+pub mod mock_transport;
+pub(crate) mod object_tree;
+pub(crate) mod quirks;
+pub(crate) mod replay;
+pub(crate) mod resume;
+pub(crate) mod session_guard;
+pub(crate) mod storage_monitor;
+pub mod sync_cursor;
+pub mod trace;
+pub(crate) mod transport;
+pub mod vendor;
 
 // 重导出所有公共项
-pub use error::Error;
+pub use error::{Error, SinkError};
 pub use standard_codes::{
     PtpContainerType,
     StandardResponseCode, 
     StandardCommandCode,
     CommandCode,
-    ResponseCode
+    ResponseCode,
+    StandardDevicePropCode,
+    DevicePropCode,
+    StandardEventCode,
+    EventCode,
+    ObjectFormat
 };
 pub use data_types::{PtpRead, PtpDataType};
+pub use datetime::PtpDateTime;
 pub use device_info::{
     PtpDeviceInfo, 
     PtpObjectInfo, 
@@ -25,12 +54,40 @@ pub use device_info::{
     PtpPropInfo, 
     PtpObjectTree
 };
-pub use camera::PtpCamera;
+pub use camera::{PtpCamera, BatchReport, CancelToken, DangerToken, DangerousOperation, ObjectProgress, RetryPolicy, TransactionTimeouts, DEFAULT_MAX_PAYLOAD_LEN, DEFAULT_SESSION_ID, DEFAULT_STREAM_CHUNK_SIZE, DEFAULT_WRITE_CHUNK_SIZE};
+pub use buffer_pool::{BufferPool, PooledBuffer};
+pub use calibrate::Calibration;
+pub use capabilities::{CameraCapabilities, PartialObject64};
+pub use trace::{TraceHandle, TransactionTracer};
+pub use transport::{DefaultTransport, PtpTransport};
+#[cfg(target_os = "espidf")]
+pub use usb_transport::{connect_camera, PtpUsbTransport, UsbCameraLink};
+pub use ip_transport::{PtpIpPeer, PtpIpTransport, PTPIP_PORT};
+pub use replay::{RecordingTransport, ReplayTransport, TraceHeader};
+#[cfg(any(test, feature = "mock-transport"))]
+pub use mock_transport::{MockTransport, SentContainer};
+pub use resume::{DownloadCheckpoint, ResumableDownload};
+pub use session_guard::SessionGuard;
+pub use storage_monitor::{StorageMonitor, StorageThresholds};
+pub use sync_cursor::{StorageCursor, SyncCursor};
+pub use object_tree::{ObjectInfoCache, TreeOptions};
+pub use quirks::{register_quirks, CameraQuirk, QuirkEntry, Quirks};
+pub use event::PtpEvent;
+pub use mtp::{
+    MtpCamera,
+    SharedCamera,
+    read_shared_object,
+    MtpProtocolHandler,
+    MtpPropListEntry,
+    MtpObjectSummary,
+    MtpCommandCode,
+    MtpObjectPropCode,
+    ObjectPropCode
+};
 
 // 导入必要的依赖
 use log::{error, debug};
 use std::error::Error as StdError;
-use libusb;
 use std::time::SystemTime;
 
 /// 支持的传输协议类型
@@ -40,6 +97,12 @@ pub enum ProtocolType {
     MTP,
 }
 
+/// 对象读取时接收每块数据的回调，返回错误时中止读取
+pub type ObjectSink<'a> = dyn FnMut(&[u8]) -> Result<(), Box<dyn StdError>> + 'a;
+
+/// 对象读取进度的接收方，例如推送给手机的进度条
+pub type ProgressListener = Box<dyn FnMut(ObjectProgress) + Send>;
+
 /// 协议处理器特性
 pub trait ProtocolHandler {
     /// 初始化协议会话
@@ -48,12 +111,76 @@ pub trait ProtocolHandler {
     /// 获取设备信息
     fn get_device_info(&self) -> Result<DeviceInfo, Box<dyn StdError>>;
     
+    /// 会话建立时由设备信息推算的相机能力
+    fn capabilities(&self) -> Result<CameraCapabilities, Box<dyn StdError>>;
+    
+    /// 重新读取设备信息和相机能力(例如收到DeviceInfoChanged事件或切换了相机模式后)
+    fn refresh_device_info(&mut self) -> Result<DeviceInfo, Box<dyn StdError>>;
+    
     /// 开始实时数据流传输
     fn start_live_stream(&mut self) -> Result<(), Box<dyn StdError>>;
     
     /// 停止实时数据流传输
     fn stop_live_stream(&mut self) -> Result<(), Box<dyn StdError>>;
     
+    /// 实时数据流运行时读取一帧取景画面，画面尚未就绪时返回None
+    fn poll_live_frame(&mut self) -> Result<Option<DataPacket>, Box<dyn StdError>>;
+    
+    /// 触发拍摄 (InitiateCapture)
+    fn trigger_capture(&mut self) -> Result<(), Box<dyn StdError>>;
+    
+    /// 结束进行中的开放式拍摄 (TerminateOpenCapture)
+    fn terminate_capture(&mut self) -> Result<(), Box<dyn StdError>>;
+    
+    /// 中止正在进行的对象读取 (PTP取消请求)
+    fn cancel_transfer(&mut self) -> Result<(), Box<dyn StdError>>;
+    
+    /// 读取所有存储的信息，返回(存储ID, 存储信息)
+    fn storage_info(&mut self) -> Result<Vec<(u32, PtpStorageInfo)>, Box<dyn StdError>>;
+    
+    /// 列出所有存储中的全部对象句柄
+    fn list_objects(&mut self) -> Result<Vec<u32>, Box<dyn StdError>>;
+    
+    /// 设置设备属性 (SetDevicePropValue)，`value`为按属性数据类型编码的PTP数据
+    fn set_device_prop(&mut self, code: u16, value: &[u8]) -> Result<(), Box<dyn StdError>>;
+    
+    /// 删除对象 (DeleteObject)
+    fn delete_object(&mut self, handle: u32) -> Result<(), Box<dyn StdError>>;
+    
+    /// 请求确认危险操作，返回确认码；客户端需在有效期内带着确认码再次发出命令才会执行，
+    /// 之前返回的确认码随之作废
+    fn confirm_danger(&mut self, operation: DangerousOperation) -> Result<u32, Box<dyn StdError>>;
+    
+    /// 格式化存储 (FormatStore)，清除其中的全部对象；`confirmation`为`confirm_danger`返回的确认码
+    fn format_store(&mut self, storage_id: u32, confirmation: u32) -> Result<(), Box<dyn StdError>>;
+    
+    /// 记录之后的PTP事务，None表示停止记录；不经过PTP容器的实现忽略
+    fn set_tracer(&mut self, _tracer: Option<TraceHandle>) {}
+    
+    /// 设置之后下载和流式读取对象的分块大小，不分块读取的实现忽略
+    fn set_chunk_size(&mut self, _chunk_size: u32) {}
+    
+    /// `read_object`每读完一块回报一次进度，None表示停止回报；不分块读取的实现忽略
+    fn set_progress_listener(&mut self, _listener: Option<ProgressListener>) {}
+    
+    /// 与FTP等服务共用的相机，不直接操作PTP相机的实现返回None
+    fn shared_camera(&self) -> Option<SharedCamera> {
+        None
+    }
+    
+    /// 分块读取对象从`offset`开始的`length`字节(None表示读到末尾)，每块交给sink；
+    /// sink返回错误时中止读取并返回该错误。返回读取的字节数
+    fn read_object(
+        &mut self,
+        handle: u32,
+        offset: u64,
+        length: Option<u64>,
+        sink: &mut ObjectSink<'_>,
+    ) -> Result<u64, Box<dyn StdError>>;
+    
+    /// 读取一个相机事件(如ObjectAdded)，没有待处理的事件或相机不支持事件时返回None
+    fn poll_event(&mut self) -> Result<Option<PtpEvent>, Box<dyn StdError>>;
+    
     /// 关闭会话
     fn close_session(&mut self) -> Result<(), Box<dyn StdError>>;
 }
@@ -69,6 +196,19 @@ pub struct DeviceInfo {
     pub supported_operations: Vec<u16>, // 支持的操作
 }
 
+impl From<&PtpDeviceInfo> for DeviceInfo {
+    fn from(info: &PtpDeviceInfo) -> Self {
+        DeviceInfo {
+            device_name: format!("{} {}", info.Manufacturer, info.Model),
+            manufacturer: info.Manufacturer.clone(),
+            model: info.Model.clone(),
+            serial_number: info.SerialNumber.clone(),
+            protocol_version: format!("{}.{:02}", info.Version / 100, info.Version % 100),
+            supported_operations: info.OperationsSupported.clone(),
+        }
+    }
+}
+
 /// 传输数据包
 #[derive(Debug, Clone)]
 pub struct DataPacket {
@@ -77,60 +217,62 @@ pub struct DataPacket {
     pub packet_type: PacketType,  // 数据包类型
 }
 
+impl DataPacket {
+    /// 以当前时间创建数据包
+    pub fn new(packet_type: PacketType, data: Vec<u8>) -> Self {
+        DataPacket {
+            data,
+            timestamp: SystemTime::now(),
+            packet_type,
+        }
+    }
+}
+
 /// 数据包类型
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PacketType {
     Image,      // 图像数据
     Thumbnail,  // 缩略图数据
+    LiveView,   // 实时取景画面
     Metadata,   // 元数据
     Command,    // 命令
     Response,   // 响应
 }
 
-/// 创建协议处理器
-/// 注意: 此函数目前需要更新实现
-pub fn create_protocol_handler(_protocol_type: ProtocolType, _device_handle: &libusb::DeviceHandle) -> Box<dyn ProtocolHandler> {
-    // 暂时使用模拟实现，实际应根据PTP或MTP创建相应的处理器
-    Box::new(MockProtocolHandler {})
-}
-
-// 临时的模拟处理器实现
-struct MockProtocolHandler {}
-
-impl ProtocolHandler for MockProtocolHandler {
-    fn init_session(&mut self) -> Result<(), Box<dyn StdError>> {
-        debug!("初始化会话");
-        Ok(())
-    }
-    
-    fn get_device_info(&self) -> Result<DeviceInfo, Box<dyn StdError>> {
-        debug!("获取设备信息");
-        Ok(DeviceInfo {
-            device_name: "模拟相机".to_string(),
-            manufacturer: "RCamera".to_string(),
-            model: "模拟型号".to_string(),
-            serial_number: "12345678".to_string(),
-            protocol_version: "1.0".to_string(),
-            supported_operations: vec![],
-        })
-    }
-    
-    fn start_live_stream(&mut self) -> Result<(), Box<dyn StdError>> {
-        debug!("开始实时数据流");
-        Ok(())
-    }
-    
-    fn stop_live_stream(&mut self) -> Result<(), Box<dyn StdError>> {
-        debug!("停止实时数据流");
-        Ok(())
+impl PacketType {
+    /// 二进制记录头中使用的类型编号
+    pub fn code(self) -> u8 {
+        match self {
+            PacketType::Image => 1,
+            PacketType::Thumbnail => 2,
+            PacketType::LiveView => 3,
+            PacketType::Metadata => 4,
+            PacketType::Command => 5,
+            PacketType::Response => 6,
+        }
     }
-    
-    fn close_session(&mut self) -> Result<(), Box<dyn StdError>> {
-        debug!("关闭会话");
-        Ok(())
+
+    /// 按类型编号还原，未知编号返回None
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(PacketType::Image),
+            2 => Some(PacketType::Thumbnail),
+            3 => Some(PacketType::LiveView),
+            4 => Some(PacketType::Metadata),
+            5 => Some(PacketType::Command),
+            6 => Some(PacketType::Response),
+            _ => None,
+        }
     }
 }
 
+/// 使用已连接的相机创建协议处理器
+/// PTP相机同样使用MTP处理器，相机不支持的MTP扩展命令会退回标准PTP命令
+pub fn create_camera_protocol_handler(protocol_type: ProtocolType, camera: PtpCamera) -> Box<dyn ProtocolHandler> {
+    debug!("创建{:?}协议处理器", protocol_type);
+    Box::new(MtpProtocolHandler::new(camera))
+}
+
 /// 数据监听器特性
 pub trait DataListener {
     /// 处理接收到的数据包
@@ -145,6 +287,12 @@ pub struct DataProcessor {
     listeners: Vec<Box<dyn DataListener>>,
 }
 
+impl Default for DataProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DataProcessor {
     /// 创建新的数据处理器
     pub fn new() -> Self {
@@ -0,0 +1,653 @@
+#![allow(non_snake_case)]
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use embassy_futures::block_on;
+use log::debug;
+use serde::Serialize;
+
+use crate::ptp_mtp::quirks::CameraQuirk;
+use crate::ptp_mtp::camera::{DangerToken, DangerousOperation, PtpCamera, TransactionTimeouts};
+use crate::ptp_mtp::data_types::{PtpDataType, PtpRead};
+use crate::ptp_mtp::datetime::PtpDateTime;
+use crate::ptp_mtp::device_info::{PtpObjectInfo, PtpPropInfo, PtpStorageInfo};
+use crate::ptp_mtp::error::{Error, SinkError};
+use crate::ptp_mtp::event::PtpEvent;
+#[cfg(feature = "live-view")]
+use crate::ptp_mtp::live_view::{self, LiveView};
+use crate::ptp_mtp::standard_codes::{CommandCode, ObjectFormat, StandardResponseCode};
+use crate::ptp_mtp::{CameraCapabilities, DataPacket, DeviceInfo, ObjectSink, ProgressListener, ProtocolHandler, TraceHandle};
+
+/// 主循环读取相机事件的等待时间，没有事件时不能拖慢主循环
+const EVENT_POLL_TIMEOUT: Duration = Duration::from_millis(10);
+/// 事件上报慢的相机使用的轮询等待时间
+const SLOW_EVENT_POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// MTP扩展命令码定义
+#[allow(non_upper_case_globals)]
+pub mod MtpCommandCode {
+    use super::CommandCode;
+
+    pub const GetObjectPropsSupported: CommandCode = 0x9801;
+    pub const GetObjectPropDesc: CommandCode = 0x9802;
+    pub const GetObjectPropValue: CommandCode = 0x9803;
+    pub const SetObjectPropValue: CommandCode = 0x9804;
+    pub const GetObjectPropList: CommandCode = 0x9805;
+    /// Android MTP扩展：64位偏移的GetPartialObject
+    pub const GetPartialObject64: CommandCode = 0x95C1;
+
+    /// 根据命令码返回对应的名称
+    pub fn name(v: CommandCode) -> Option<&'static str> {
+        match v {
+            GetObjectPropsSupported => Some("获取支持的对象属性"),
+            GetObjectPropDesc => Some("获取对象属性描述"),
+            GetObjectPropValue => Some("获取对象属性值"),
+            SetObjectPropValue => Some("设置对象属性值"),
+            GetObjectPropList => Some("获取对象属性列表"),
+            GetPartialObject64 => Some("获取部分对象(64位偏移)"),
+            _ => None,
+        }
+    }
+}
+
+/// 对象属性码类型
+pub type ObjectPropCode = u16;
+
+/// 常用的MTP对象属性码
+#[allow(non_upper_case_globals)]
+pub mod MtpObjectPropCode {
+    use super::ObjectPropCode;
+
+    pub const StorageID: ObjectPropCode = 0xDC01;
+    pub const ObjectFormat: ObjectPropCode = 0xDC02;
+    pub const ProtectionStatus: ObjectPropCode = 0xDC03;
+    pub const ObjectSize: ObjectPropCode = 0xDC04;
+    pub const ObjectFileName: ObjectPropCode = 0xDC07;
+    pub const DateCreated: ObjectPropCode = 0xDC08;
+    pub const DateModified: ObjectPropCode = 0xDC09;
+    pub const ParentObject: ObjectPropCode = 0xDC0B;
+    pub const PersistentUniqueObjectIdentifier: ObjectPropCode = 0xDC41;
+    pub const Name: ObjectPropCode = 0xDC44;
+}
+
+/// GetObjectPropList返回的一个元素
+#[derive(Debug, PartialEq)]
+pub struct MtpPropListEntry {
+    pub handle: u32,              // 对象句柄
+    pub prop_code: ObjectPropCode, // 属性码
+    pub data_type: u16,           // 数据类型
+    pub value: PtpDataType,       // 属性值
+}
+
+/// 解码GetObjectPropList的数据阶段
+pub fn decode_prop_list(buf: &[u8]) -> Result<Vec<MtpPropListEntry>, Error> {
+    let mut cur = Cursor::new(buf);
+    let count = cur.read_ptp_u32()? as usize;
+    let mut entries = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let handle = cur.read_ptp_u32()?;
+        let prop_code = cur.read_ptp_u16()?;
+        let data_type = cur.read_ptp_u16()?;
+        let value = PtpDataType::read_type(data_type, &mut cur)?;
+        entries.push(MtpPropListEntry {
+            handle,
+            prop_code,
+            data_type,
+            value,
+        });
+    }
+    cur.expect_end()?;
+    Ok(entries)
+}
+
+/// GetObjectPropList等参数中表示"全部"的值
+const ALL: u32 = 0xFFFFFFFF;
+
+/// 相册列表需要的对象元数据
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MtpObjectSummary {
+    pub handle: u32,
+    pub storage_id: u32,
+    pub parent: u32,
+    pub format: u16,
+    pub size: u64,
+    pub filename: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<PtpDateTime>, // 创建时间，相机没有提供时使用修改时间
+}
+
+impl MtpObjectSummary {
+    /// 由GetObjectInfo的结果生成(不支持GetObjectPropList的相机)
+    pub fn from_info(handle: u32, info: &PtpObjectInfo) -> Self {
+        MtpObjectSummary {
+            handle,
+            storage_id: info.StorageID,
+            parent: info.ParentObject,
+            format: info.ObjectFormat,
+            size: info.ObjectCompressedSize as u64,
+            filename: info.Filename.clone(),
+            date: info.capture_date().or_else(|| PtpDateTime::parse_opt(&info.ModificationDate)),
+        }
+    }
+
+    /// 是否为文件夹
+    pub fn is_association(&self) -> bool {
+        ObjectFormat::from(self.format).is_association()
+    }
+}
+
+/// 把GetObjectPropList的结果按句柄归并为对象摘要，按句柄首次出现的顺序排列；不需要的属性被忽略
+pub fn summarize_prop_list(entries: &[MtpPropListEntry]) -> Vec<MtpObjectSummary> {
+    let mut summaries: Vec<MtpObjectSummary> = Vec::new();
+    let mut index: HashMap<u32, usize> = HashMap::new();
+    for entry in entries {
+        let i = *index.entry(entry.handle).or_insert_with(|| {
+            summaries.push(MtpObjectSummary { handle: entry.handle, ..Default::default() });
+            summaries.len() - 1
+        });
+        let summary = &mut summaries[i];
+        match (entry.prop_code, &entry.value) {
+            (MtpObjectPropCode::StorageID, PtpDataType::UINT32(v)) => summary.storage_id = *v,
+            (MtpObjectPropCode::ParentObject, PtpDataType::UINT32(v)) => summary.parent = *v,
+            (MtpObjectPropCode::ObjectFormat, PtpDataType::UINT16(v)) => summary.format = *v,
+            (MtpObjectPropCode::ObjectSize, PtpDataType::UINT64(v)) => summary.size = *v,
+            // 部分相机把大小报告为UINT32
+            (MtpObjectPropCode::ObjectSize, PtpDataType::UINT32(v)) => summary.size = *v as u64,
+            (MtpObjectPropCode::ObjectFileName, PtpDataType::STR(v)) => summary.filename = v.clone(),
+            (MtpObjectPropCode::DateCreated, PtpDataType::STR(v)) => {
+                if let Some(date) = PtpDateTime::parse_opt(v) {
+                    summary.date = Some(date);
+                }
+            }
+            (MtpObjectPropCode::DateModified, PtpDataType::STR(v)) if summary.date.is_none() => {
+                summary.date = PtpDateTime::parse_opt(v);
+            }
+            _ => {}
+        }
+    }
+    summaries
+}
+
+/// 协议处理器和FTP等服务共用的相机，只在单次操作期间持有锁
+pub type SharedCamera = Arc<Mutex<MtpCamera>>;
+
+/// 从`offset`开始分块读取共用相机上大小为`size`的对象，每读一块锁一次相机，sink在锁外执行，
+/// 慢速的接收方不会长时间占用相机；只能整体读取对象的相机在读取期间一直占用相机。返回读取结束时在对象中的位置
+pub fn read_shared_object(
+    camera: &SharedCamera,
+    handle: u32,
+    offset: u64,
+    size: u64,
+    timeout: Option<Duration>,
+    sink: &mut ObjectSink<'_>,
+) -> Result<u64, Box<dyn StdError>> {
+    if offset > size {
+        return Err(format!("偏移 {} 超出对象 0x{:08x} 的大小 {}", offset, handle, size).into());
+    }
+    if !camera.lock().unwrap().ptp().supports_partial_reads() {
+        let mut camera = camera.lock().unwrap();
+        let chunk_size = camera.ptp().read_chunk_size();
+        let mut sink_error = SinkError::default();
+        let result = block_on(camera.ptp().stream_object_from(handle, offset, chunk_size, timeout, |chunk, _| {
+            sink_error.capture(sink(chunk))
+        }));
+        return sink_error.finish(result);
+    }
+    let (chunk_size, cancel) = {
+        let mut locked = camera.lock().unwrap();
+        (locked.ptp().read_chunk_size(), locked.ptp().cancel_token())
+    };
+    // 每块的数据阶段检查取消请求，整个对象算作一次读取
+    let _read = cancel.begin_read();
+    let mut buffer = vec![0u8; (chunk_size as u64).min(size - offset) as usize];
+    let mut position = offset;
+    while position < size {
+        let want = (buffer.len() as u64).min(size - position) as usize;
+        let n = {
+            let mut locked = camera.lock().unwrap();
+            match block_on(locked.ptp().get_partialobject64_into(handle, position, &mut buffer[..want], timeout)) {
+                // 还没有交给sink任何数据，可以改为整体读取
+                Err(Error::Response(StandardResponseCode::OperationNotSupported)) if position == offset => {
+                    locked.ptp().disable_partial_reads();
+                    drop(locked);
+                    return read_shared_object(camera, handle, offset, size, timeout, sink);
+                }
+                result => result?,
+            }
+        };
+        if n == 0 {
+            return Err(format!("对象 0x{:08x} 在偏移 {} 处提前结束", handle, position).into());
+        }
+        sink(&buffer[..n])?;
+        position += n as u64;
+    }
+    Ok(position)
+}
+
+/// MTP相机 - 在PTP相机之上增加MTP对象属性操作
+pub struct MtpCamera {
+    camera: PtpCamera,
+}
+
+impl MtpCamera {
+    /// 包装已连接的PTP相机
+    pub fn new(camera: PtpCamera) -> Self {
+        MtpCamera { camera }
+    }
+
+    /// 访问底层PTP相机
+    pub fn ptp(&mut self) -> &mut PtpCamera {
+        &mut self.camera
+    }
+
+    /// 取回底层PTP相机
+    pub fn into_inner(self) -> PtpCamera {
+        self.camera
+    }
+
+    /// 获取某种对象格式支持的属性码
+    pub async fn get_object_props_supported(&mut self, format: u16, timeout: Option<Duration>) -> Result<Vec<ObjectPropCode>, Error> {
+        let data = self.camera.command(MtpCommandCode::GetObjectPropsSupported, &[format as u32], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        let mut cur = Cursor::new(data);
+        let value = cur.read_ptp_u16_vec()?;
+        cur.expect_end()?;
+        Ok(value)
+    }
+
+    /// 获取对象属性描述，格式与设备属性描述相同
+    pub async fn get_object_prop_desc(&mut self, prop_code: ObjectPropCode, format: u16, timeout: Option<Duration>) -> Result<PtpPropInfo, Error> {
+        let data = self.camera.command(MtpCommandCode::GetObjectPropDesc, &[prop_code as u32, format as u32], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        let mut cur = Cursor::new(data);
+        PtpPropInfo::decode(&mut cur)
+    }
+
+    /// 获取对象属性值，data_type为该属性的数据类型(可从属性描述得到)
+    pub async fn get_object_prop_value(&mut self, handle: u32, prop_code: ObjectPropCode, data_type: u16, timeout: Option<Duration>) -> Result<PtpDataType, Error> {
+        let data = self.camera.command(MtpCommandCode::GetObjectPropValue, &[handle, prop_code as u32], None, timeout.map(TransactionTimeouts::uniform)).await?;
+        let mut cur = Cursor::new(data);
+        let value = PtpDataType::read_type(data_type, &mut cur)?;
+        cur.expect_end()?;
+        Ok(value)
+    }
+
+    /// 设置对象属性值
+    pub async fn set_object_prop_value(&mut self, handle: u32, prop_code: ObjectPropCode, value: &PtpDataType, timeout: Option<Duration>) -> Result<(), Error> {
+        let data = value.encode();
+        self.camera.command(MtpCommandCode::SetObjectPropValue, &[handle, prop_code as u32], Some(&data), timeout.map(TransactionTimeouts::uniform)).await?;
+        Ok(())
+    }
+
+    /// 批量获取对象属性
+    /// handle为0xFFFFFFFF表示全部对象；prop_code为0xFFFFFFFF表示全部属性；depth为0表示只查询handle本身
+    pub async fn get_object_prop_list(
+        &mut self,
+        handle: u32,
+        format: u32,
+        prop_code: u32,
+        group_code: u32,
+        depth: u32,
+        timeout: Option<Duration>
+    ) -> Result<Vec<MtpPropListEntry>, Error> {
+        let data = self.camera.command(
+            MtpCommandCode::GetObjectPropList,
+            &[handle, format, prop_code, group_code, depth],
+            None,
+            timeout.map(TransactionTimeouts::uniform)
+        ).await?;
+        let entries = decode_prop_list(&data)?;
+        debug!("GetObjectPropList 返回 {} 个属性", entries.len());
+        Ok(entries)
+    }
+
+    /// 用一次GetObjectPropList读取全部对象的文件名、大小、日期和格式，用于快速构建相册
+    /// `storage_id`为0xFFFFFFFF表示全部存储；结果不包括文件夹
+    pub async fn get_object_summaries(&mut self, storage_id: u32, timeout: Option<Duration>) -> Result<Vec<MtpObjectSummary>, Error> {
+        let entries = self.get_object_prop_list(ALL, 0, ALL, 0, ALL, timeout).await?;
+        let summaries: Vec<MtpObjectSummary> = summarize_prop_list(&entries)
+            .into_iter()
+            .filter(|s| (storage_id == ALL || s.storage_id == storage_id) && !s.is_association())
+            .collect();
+        debug!("GetObjectPropList 读取了 {} 个对象的摘要", summaries.len());
+        Ok(summaries)
+    }
+
+    /// 读取对象摘要；相机不支持GetObjectPropList或兼容表要求不用它时退回逐个读取GetObjectInfo
+    pub async fn list_object_summaries(&mut self, storage_id: u32, timeout: Option<Duration>) -> Result<Vec<MtpObjectSummary>, Error> {
+        let quirks = self.camera.quirks();
+        if quirks.has(CameraQuirk::NoObjectPropList) || quirks.has(CameraQuirk::ForcePtp) {
+            debug!("兼容表要求不使用GetObjectPropList，逐个读取对象信息");
+        } else {
+            match self.get_object_summaries(storage_id, timeout).await {
+                Err(Error::Unsupported(_)) | Err(Error::Response(StandardResponseCode::OperationNotSupported)) => {
+                    debug!("相机不支持GetObjectPropList，逐个读取对象信息");
+                }
+                result => return result,
+            }
+        }
+        let handles = self.camera.get_objecthandles_all(storage_id, None, timeout).await?;
+        let mut summaries = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let info = self.camera.get_objectinfo(handle, timeout).await?;
+            let summary = MtpObjectSummary::from_info(handle, &info);
+            if !summary.is_association() {
+                summaries.push(summary);
+            }
+        }
+        Ok(summaries)
+    }
+}
+
+/// MTP协议处理器
+pub struct MtpProtocolHandler {
+    camera: SharedCamera,
+    timeout: Option<Duration>,       // 统一超时，None时使用相机按阶段设置的超时
+    device_info: Option<DeviceInfo>, // 会话建立时读取的设备信息
+    capabilities: Option<CameraCapabilities>, // 由设备信息推算的相机能力
+    capture_tid: Option<u32>,        // 进行中拍摄的事务ID
+    pending_danger: Option<(u32, DangerToken)>, // 已发给客户端的确认码和对应的令牌
+    progress: Option<ProgressListener>, // 对象读取进度的接收方
+    #[cfg(feature = "live-view")]
+    live_view: Option<LiveView>,     // 进行中的实时取景
+}
+
+impl MtpProtocolHandler {
+    /// 使用已连接的相机创建MTP协议处理器
+    pub fn new(mut camera: PtpCamera) -> Self {
+        // 相机未列出的操作直接返回Unsupported，不必等待一次往返
+        camera.set_check_operations(true);
+        MtpProtocolHandler {
+            camera: Arc::new(Mutex::new(MtpCamera::new(camera))),
+            timeout: None,
+            device_info: None,
+            capabilities: None,
+            capture_tid: None,
+            pending_danger: None,
+            progress: None,
+            #[cfg(feature = "live-view")]
+            live_view: None,
+        }
+    }
+
+    /// 锁住MTP相机，每次操作结束后释放，FTP等其他使用方在两次操作之间访问相机
+    pub fn camera(&self) -> MutexGuard<'_, MtpCamera> {
+        self.camera.lock().unwrap()
+    }
+
+    fn load_device_info(&mut self) -> Result<DeviceInfo, Box<dyn StdError>> {
+        let info = block_on(self.camera().ptp().get_device_info(self.timeout))?;
+        let mut capabilities = CameraCapabilities::from(&info);
+        // 取景命令由live_view模块实现，固件未启用时不支持
+        #[cfg(feature = "live-view")]
+        {
+            capabilities.supports_live_view &= LiveView::is_supported(capabilities.vendor);
+        }
+        #[cfg(not(feature = "live-view"))]
+        {
+            capabilities.supports_live_view = false;
+        }
+        debug!("相机能力: {:?}", capabilities);
+        let device_info = DeviceInfo::from(&info);
+        self.device_info = Some(device_info.clone());
+        self.capabilities = Some(capabilities);
+        Ok(device_info)
+    }
+}
+
+impl ProtocolHandler for MtpProtocolHandler {
+    fn init_session(&mut self) -> Result<(), Box<dyn StdError>> {
+        block_on(self.camera().ptp().open_session(self.timeout))?;
+        self.load_device_info()?;
+        Ok(())
+    }
+
+    fn get_device_info(&self) -> Result<DeviceInfo, Box<dyn StdError>> {
+        self.device_info.clone().ok_or_else(|| "会话未建立，尚未读取设备信息".into())
+    }
+
+    fn capabilities(&self) -> Result<CameraCapabilities, Box<dyn StdError>> {
+        self.capabilities.ok_or_else(|| "会话未建立，尚未读取设备信息".into())
+    }
+
+    fn refresh_device_info(&mut self) -> Result<DeviceInfo, Box<dyn StdError>> {
+        self.load_device_info()
+    }
+
+    #[cfg(feature = "live-view")]
+    fn start_live_stream(&mut self) -> Result<(), Box<dyn StdError>> {
+        let capabilities = self.capabilities()?;
+        if !capabilities.supports_live_view {
+            return Err("相机不支持实时取景".into());
+        }
+        if self.live_view.is_some() {
+            return Ok(());
+        }
+        // 帧率由调用方读取帧的节奏决定
+        let mut live = LiveView::new(capabilities.vendor, live_view::DEFAULT_FPS, self.timeout)?;
+        block_on(live.start(self.camera().ptp()))?;
+        self.live_view = Some(live);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "live-view"))]
+    fn start_live_stream(&mut self) -> Result<(), Box<dyn StdError>> {
+        Err("固件未启用实时取景".into())
+    }
+
+    #[cfg(feature = "live-view")]
+    fn stop_live_stream(&mut self) -> Result<(), Box<dyn StdError>> {
+        if let Some(mut live) = self.live_view.take() {
+            block_on(live.stop(self.camera().ptp()))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "live-view"))]
+    fn stop_live_stream(&mut self) -> Result<(), Box<dyn StdError>> {
+        Ok(())
+    }
+
+    fn poll_live_frame(&mut self) -> Result<Option<DataPacket>, Box<dyn StdError>> {
+        #[cfg(feature = "live-view")]
+        if let Some(live) = self.live_view.as_mut() {
+            return Ok(block_on(live.poll_frame(self.camera.lock().unwrap().ptp()))?);
+        }
+        Ok(None)
+    }
+
+    fn trigger_capture(&mut self) -> Result<(), Box<dyn StdError>> {
+        let tid = block_on(self.camera().ptp().initiate_capture(0, 0, self.timeout))?;
+        debug!("已触发拍摄，事务ID {}", tid);
+        self.capture_tid = Some(tid);
+        Ok(())
+    }
+
+    fn terminate_capture(&mut self) -> Result<(), Box<dyn StdError>> {
+        let tid = self.capture_tid.take().ok_or("没有进行中的拍摄")?;
+        block_on(self.camera().ptp().terminate_open_capture(tid, self.timeout))?;
+        Ok(())
+    }
+
+    fn cancel_transfer(&mut self) -> Result<(), Box<dyn StdError>> {
+        self.camera().ptp().cancel_token().cancel();
+        Ok(())
+    }
+
+    fn storage_info(&mut self) -> Result<Vec<(u32, PtpStorageInfo)>, Box<dyn StdError>> {
+        let mut storages = Vec::new();
+        let mut camera = self.camera();
+        for id in block_on(camera.ptp().get_storageids(self.timeout))? {
+            storages.push((id, block_on(camera.ptp().get_storage_info(id, self.timeout))?));
+        }
+        Ok(storages)
+    }
+
+    fn list_objects(&mut self) -> Result<Vec<u32>, Box<dyn StdError>> {
+        let mut handles = Vec::new();
+        let mut camera = self.camera();
+        for id in block_on(camera.ptp().get_storageids(self.timeout))? {
+            handles.extend(block_on(camera.ptp().get_objecthandles_all(id, None, self.timeout))?);
+        }
+        Ok(handles)
+    }
+
+    fn set_device_prop(&mut self, code: u16, value: &[u8]) -> Result<(), Box<dyn StdError>> {
+        block_on(self.camera().ptp().set_device_prop_value(code, value, self.timeout))?;
+        Ok(())
+    }
+
+    fn delete_object(&mut self, handle: u32) -> Result<(), Box<dyn StdError>> {
+        block_on(self.camera().ptp().delete_object(handle, self.timeout))?;
+        Ok(())
+    }
+
+    fn confirm_danger(&mut self, operation: DangerousOperation) -> Result<u32, Box<dyn StdError>> {
+        let token = self.camera().ptp().confirm_danger(operation);
+        // 确认码只用来把两次请求对应起来，令牌本身留在设备上
+        let code = (uuid::Uuid::new_v4().as_u128() as u32).max(1);
+        self.pending_danger = Some((code, token));
+        Ok(code)
+    }
+
+    fn format_store(&mut self, storage_id: u32, confirmation: u32) -> Result<(), Box<dyn StdError>> {
+        let token = match self.pending_danger.take() {
+            Some((code, token)) if code == confirmation => token,
+            _ => return Err(Error::Unconfirmed(format!("格式化存储 0x{:08x} 的确认码无效", storage_id)).into()),
+        };
+        block_on(self.camera().ptp().format_store(storage_id, token, self.timeout))?;
+        Ok(())
+    }
+
+    fn set_tracer(&mut self, tracer: Option<TraceHandle>) {
+        self.camera().ptp().set_tracer(tracer);
+    }
+
+    fn set_chunk_size(&mut self, chunk_size: u32) {
+        self.camera().ptp().set_read_chunk_size(chunk_size);
+    }
+
+    fn set_progress_listener(&mut self, listener: Option<ProgressListener>) {
+        self.progress = listener;
+    }
+
+    fn shared_camera(&self) -> Option<SharedCamera> {
+        Some(self.camera.clone())
+    }
+
+    fn read_object(
+        &mut self,
+        handle: u32,
+        offset: u64,
+        length: Option<u64>,
+        sink: &mut ObjectSink<'_>,
+    ) -> Result<u64, Box<dyn StdError>> {
+        let mut camera = self.camera.lock().unwrap();
+        let chunk_size = camera.ptp().read_chunk_size();
+        let mut sink_error = SinkError::default();
+        let progress = &mut self.progress;
+        let result = block_on(camera.ptp().stream_object_range(
+            handle,
+            offset,
+            length,
+            chunk_size,
+            self.timeout,
+            |chunk, done| {
+                sink_error.capture(sink(chunk))?;
+                if let Some(listener) = progress.as_mut() {
+                    listener(done);
+                }
+                Ok(())
+            },
+        ));
+        Ok(sink_error.finish(result)? - offset)
+    }
+
+    fn poll_event(&mut self) -> Result<Option<PtpEvent>, Box<dyn StdError>> {
+        if !self.capabilities.as_ref().is_some_and(|c| c.supports_events) {
+            return Ok(None);
+        }
+        let event = {
+            let mut camera = self.camera();
+            let timeout = if camera.ptp().quirks().has(CameraQuirk::SlowEvents) { SLOW_EVENT_POLL_TIMEOUT } else { EVENT_POLL_TIMEOUT };
+            block_on(camera.ptp().poll_event(Some(timeout)))?
+        };
+        // 单张拍摄完成后不需要再TerminateOpenCapture
+        if let Some(PtpEvent::CaptureComplete(tid)) = event {
+            if self.capture_tid == Some(tid) {
+                debug!("事务 {} 触发的拍摄已完成", tid);
+                self.capture_tid = None;
+            }
+        }
+        Ok(event)
+    }
+
+    fn close_session(&mut self) -> Result<(), Box<dyn StdError>> {
+        #[cfg(feature = "live-view")]
+        {
+            self.live_view = None;
+        }
+        block_on(self.camera().ptp().close_session(self.timeout))?;
+        self.device_info = None;
+        self.capabilities = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(handle: u32, prop_code: ObjectPropCode, value: PtpDataType) -> MtpPropListEntry {
+        MtpPropListEntry { handle, prop_code, data_type: 0, value }
+    }
+
+    #[test]
+    fn summarize_groups_by_handle_in_order() {
+        let entries = [
+            entry(7, MtpObjectPropCode::ObjectFileName, PtpDataType::STR("IMG_0007.JPG".into())),
+            entry(3, MtpObjectPropCode::ObjectFileName, PtpDataType::STR("IMG_0003.CR3".into())),
+            entry(7, MtpObjectPropCode::StorageID, PtpDataType::UINT32(0x0001_0001)),
+            entry(7, MtpObjectPropCode::ParentObject, PtpDataType::UINT32(0x10)),
+            entry(7, MtpObjectPropCode::ObjectFormat, PtpDataType::UINT16(0x3801)),
+            entry(7, MtpObjectPropCode::ObjectSize, PtpDataType::UINT64(5_000_000_000)),
+            entry(3, MtpObjectPropCode::ObjectSize, PtpDataType::UINT32(1234)),
+        ];
+        let summaries = summarize_prop_list(&entries);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].handle, 7);
+        assert_eq!(summaries[0].filename, "IMG_0007.JPG");
+        assert_eq!(summaries[0].storage_id, 0x0001_0001);
+        assert_eq!(summaries[0].parent, 0x10);
+        assert_eq!(summaries[0].format, 0x3801);
+        assert_eq!(summaries[0].size, 5_000_000_000);
+        assert_eq!(summaries[1].handle, 3);
+        assert_eq!(summaries[1].size, 1234);
+    }
+
+    #[test]
+    fn summarize_prefers_creation_date() {
+        let entries = [
+            entry(1, MtpObjectPropCode::DateModified, PtpDataType::STR("20240102T030405".into())),
+            entry(1, MtpObjectPropCode::DateCreated, PtpDataType::STR("20230102T030405".into())),
+            entry(2, MtpObjectPropCode::DateModified, PtpDataType::STR("20240102T030405".into())),
+            entry(2, MtpObjectPropCode::DateCreated, PtpDataType::STR("".into())),
+        ];
+        let summaries = summarize_prop_list(&entries);
+        assert_eq!(summaries[0].date.map(|d| d.year), Some(2023));
+        assert_eq!(summaries[1].date.map(|d| d.year), Some(2024));
+    }
+
+    #[test]
+    fn summarize_ignores_unexpected_types() {
+        let entries = [
+            entry(1, MtpObjectPropCode::StorageID, PtpDataType::UINT16(1)),
+            entry(1, MtpObjectPropCode::ObjectFileName, PtpDataType::UINT32(1)),
+        ];
+        let summaries = summarize_prop_list(&entries);
+        assert_eq!(summaries[0].storage_id, 0);
+        assert!(summaries[0].filename.is_empty());
+    }
+}
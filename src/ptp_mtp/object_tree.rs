@@ -0,0 +1,309 @@
+// 对象树构建 - 从存储根目录开始按关联(文件夹)逐层枚举对象，填充PtpObjectTree；
+// 超过深度限制的文件夹保持未加载状态，之后可按需加载。最近使用的对象信息可缓存在NVS中，减少重连后的GetObjectInfo次数
+use std::error::Error as StdError;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::persist::KvStore;
+use crate::ptp_mtp::camera::PtpCamera;
+use crate::ptp_mtp::device_info::{PtpObjectInfo, PtpObjectTree};
+use crate::ptp_mtp::error::Error;
+
+/// GetObjectHandles中表示存储根目录的父对象句柄，也用作树根节点的句柄
+pub const ROOT_HANDLE: u32 = 0xFFFFFFFF;
+
+const ASSOCIATION_FORMAT: u16 = 0x3001;
+const CACHE_INDEX_KEY: &str = "oinf_idx";
+
+/// 对象信息缓存默认最多保留的对象数；每个对象在NVS中占一个键，NVS分区只有几十KB
+pub const DEFAULT_CACHE_CAPACITY: usize = 32;
+
+/// 构建对象树的选项
+#[derive(Debug, Clone, Default)]
+pub struct TreeOptions {
+    pub max_depth: Option<u32>,    // 最多加载的层数，根目录下的对象为第1层；None表示不限
+    pub timeout: Option<Duration>, // 单个PTP事务的超时
+}
+
+/// 持久化的对象信息缓存，超过容量时淘汰最久未使用的对象
+///
+/// 句柄只在同一张存储卡上稳定，收到存储卡插拔事件后应调用`clear`
+pub struct ObjectInfoCache {
+    store: Box<dyn KvStore>,
+    handles: Vec<u32>, // 按最近使用排序，最久未使用的在前
+    capacity: usize,
+}
+
+impl ObjectInfoCache {
+    /// 以默认容量打开缓存
+    pub fn open(store: Box<dyn KvStore>) -> Result<Self, Box<dyn StdError>> {
+        Self::with_capacity(store, DEFAULT_CACHE_CAPACITY)
+    }
+
+    /// 打开缓存并读取已缓存的句柄索引；存放在SD卡等较大的存储上时可以调大容量
+    pub fn with_capacity(store: Box<dyn KvStore>, capacity: usize) -> Result<Self, Box<dyn StdError>> {
+        let handles = match store.get(CACHE_INDEX_KEY)? {
+            Some(raw) => raw
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut cache = ObjectInfoCache { store, handles, capacity: capacity.max(1) };
+        // 容量比上次小时先淘汰多出的旧对象
+        if cache.handles.len() > cache.capacity {
+            cache.evict()?;
+            cache.save_index()?;
+        }
+        debug!("对象信息缓存已加载 {} 项，容量 {}", cache.handles.len(), cache.capacity);
+        Ok(cache)
+    }
+
+    fn entry_key(handle: u32) -> String {
+        format!("oinf_{:08x}", handle)
+    }
+
+    fn save_index(&mut self) -> Result<(), Box<dyn StdError>> {
+        let raw: Vec<u8> = self.handles.iter().flat_map(|h| h.to_le_bytes()).collect();
+        self.store.set(CACHE_INDEX_KEY, &raw)
+    }
+
+    /// 把已缓存的句柄移到最近使用的位置，未缓存时返回false
+    fn touch(&mut self, handle: u32) -> bool {
+        let Some(pos) = self.handles.iter().position(|&h| h == handle) else {
+            return false;
+        };
+        self.handles.remove(pos);
+        self.handles.push(handle);
+        true
+    }
+
+    /// 淘汰最久未使用的对象直到不超过容量
+    fn evict(&mut self) -> Result<(), Box<dyn StdError>> {
+        while self.handles.len() > self.capacity {
+            let oldest = self.handles.remove(0);
+            self.store.remove(&Self::entry_key(oldest))?;
+        }
+        Ok(())
+    }
+
+    /// 读取缓存的对象信息，存储ID不符时视为未命中
+    ///
+    /// 命中后的使用顺序只在下次`put`时写回存储，避免每次读取都擦写闪存
+    pub fn get(&mut self, handle: u32, storage_id: u32) -> Result<Option<PtpObjectInfo>, Box<dyn StdError>> {
+        if !self.touch(handle) {
+            return Ok(None);
+        }
+        let Some(raw) = self.store.get(&Self::entry_key(handle))? else {
+            return Ok(None);
+        };
+        let info: PtpObjectInfo = serde_json::from_slice(&raw)?;
+        Ok((info.StorageID == storage_id).then_some(info))
+    }
+
+    /// 读取缓存的对象信息，不校验存储ID(用于只知道句柄的场合，例如按台账浏览)
+    pub fn peek(&self, handle: u32) -> Result<Option<PtpObjectInfo>, Box<dyn StdError>> {
+        if !self.handles.contains(&handle) {
+            return Ok(None);
+        }
+        match self.store.get(&Self::entry_key(handle))? {
+            Some(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 缓存对象信息，超过容量时淘汰最久未使用的对象
+    pub fn put(&mut self, handle: u32, info: &PtpObjectInfo) -> Result<(), Box<dyn StdError>> {
+        self.store.set(&Self::entry_key(handle), &serde_json::to_vec(info)?)?;
+        if !self.touch(handle) {
+            self.handles.push(handle);
+        }
+        self.evict()?;
+        self.save_index()
+    }
+
+    /// 清空缓存
+    pub fn clear(&mut self) -> Result<(), Box<dyn StdError>> {
+        for handle in std::mem::take(&mut self.handles) {
+            self.store.remove(&Self::entry_key(handle))?;
+        }
+        self.store.remove(CACHE_INDEX_KEY)
+    }
+
+    /// 已缓存的对象数
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// 缓存是否为空
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+}
+
+/// 树根节点的信息：文件名为空，使walk()得到的路径从根目录下的对象开始
+fn root_info(storage_id: u32) -> PtpObjectInfo {
+    PtpObjectInfo {
+        StorageID: storage_id,
+        ObjectFormat: ASSOCIATION_FORMAT,
+        ProtectionStatus: 0,
+        ObjectCompressedSize: 0,
+        ThumbFormat: 0,
+        ThumbCompressedSize: 0,
+        ThumbPixWidth: 0,
+        ThumbPixHeight: 0,
+        ImagePixWidth: 0,
+        ImagePixHeight: 0,
+        ImageBitDepth: 0,
+        ParentObject: 0,
+        AssociationType: 0,
+        AssociationDesc: 0,
+        SequenceNumber: 0,
+        Filename: String::new(),
+        CaptureDate: String::new(),
+        ModificationDate: String::new(),
+        Keywords: String::new(),
+    }
+}
+
+/// 读取对象信息，优先使用缓存；缓存读写失败不影响枚举
+async fn object_info(
+    camera: &mut PtpCamera,
+    cache: Option<&mut ObjectInfoCache>,
+    handle: u32,
+    storage_id: u32,
+    timeout: Option<Duration>,
+) -> Result<PtpObjectInfo, Error> {
+    let Some(cache) = cache else {
+        return camera.get_objectinfo(handle, timeout).await;
+    };
+    match cache.get(handle, storage_id) {
+        Ok(Some(info)) => return Ok(info),
+        Ok(None) => {}
+        Err(e) => warn!("读取对象 0x{:08x} 的缓存失败: {}", handle, e),
+    }
+    let info = camera.get_objectinfo(handle, timeout).await?;
+    if let Err(e) = cache.put(handle, &info) {
+        warn!("缓存对象 0x{:08x} 的信息失败: {}", handle, e);
+    }
+    Ok(info)
+}
+
+impl PtpObjectTree {
+    /// 枚举存储中的全部对象，返回以存储根目录为根的对象树
+    pub async fn build(camera: &mut PtpCamera, storage_id: u32) -> Result<Self, Error> {
+        Self::build_with(camera, storage_id, &TreeOptions::default(), None).await
+    }
+
+    /// 按选项枚举存储中的对象，可限制深度并使用对象信息缓存
+    pub async fn build_with(
+        camera: &mut PtpCamera,
+        storage_id: u32,
+        options: &TreeOptions,
+        cache: Option<&mut ObjectInfoCache>,
+    ) -> Result<Self, Error> {
+        let mut root = PtpObjectTree {
+            handle: ROOT_HANDLE,
+            info: root_info(storage_id),
+            children: None,
+        };
+        root.load_children(camera, options, cache).await?;
+        debug!("存储 0x{:08x} 的对象树已构建，共 {} 个节点", storage_id, root.walk().len() - 1);
+        Ok(root)
+    }
+
+    /// 是否为关联(文件夹)或存储根目录
+    pub fn is_association(&self) -> bool {
+        self.handle == ROOT_HANDLE || self.info.format().is_association()
+    }
+
+    /// 子节点是否已加载；普通文件没有子节点，始终视为已加载
+    pub fn is_loaded(&self) -> bool {
+        !self.is_association() || self.children.is_some()
+    }
+
+    /// 按句柄查找节点，用于按需加载某个文件夹
+    pub fn find_mut(&mut self, handle: u32) -> Option<&mut PtpObjectTree> {
+        if self.handle == handle {
+            return Some(self);
+        }
+        self.children
+            .as_mut()?
+            .iter_mut()
+            .find_map(|child| child.find_mut(handle))
+    }
+
+    /// 加载当前节点的子节点，`options.max_depth`相对当前节点计算
+    pub async fn load_children(
+        &mut self,
+        camera: &mut PtpCamera,
+        options: &TreeOptions,
+        cache: Option<&mut ObjectInfoCache>,
+    ) -> Result<(), Error> {
+        self.load(camera, options, cache, 1).await
+    }
+
+    async fn load(
+        &mut self,
+        camera: &mut PtpCamera,
+        options: &TreeOptions,
+        mut cache: Option<&mut ObjectInfoCache>,
+        depth: u32,
+    ) -> Result<(), Error> {
+        if !self.is_association() {
+            return Ok(());
+        }
+        let storage_id = self.info.StorageID;
+        let handles = camera.get_objecthandles(storage_id, self.handle, None, options.timeout).await?;
+        let mut children = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let info = object_info(camera, cache.as_deref_mut(), handle, storage_id, options.timeout).await?;
+            children.push(PtpObjectTree {
+                handle,
+                info,
+                children: None,
+            });
+        }
+
+        if options.max_depth.map_or(true, |max| depth < max) {
+            for child in children.iter_mut().filter(|c| c.is_association()) {
+                Box::pin(child.load(camera, options, cache.as_deref_mut(), depth + 1)).await?;
+            }
+        }
+        self.children = Some(children);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persist::MemoryStore;
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let mut cache = ObjectInfoCache::with_capacity(Box::new(MemoryStore::new()), 2).unwrap();
+        cache.put(1, &root_info(7)).unwrap();
+        cache.put(2, &root_info(7)).unwrap();
+        assert!(cache.get(1, 7).unwrap().is_some());
+        cache.put(3, &root_info(7)).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.peek(2).unwrap().is_none());
+        assert!(cache.store.get(&ObjectInfoCache::entry_key(2)).unwrap().is_none());
+        assert!(cache.peek(1).unwrap().is_some());
+        assert!(cache.peek(3).unwrap().is_some());
+    }
+
+    #[test]
+    fn reopening_with_smaller_capacity_trims_oldest() {
+        let mut cache = ObjectInfoCache::with_capacity(Box::new(MemoryStore::new()), 4).unwrap();
+        for handle in 1..=4 {
+            cache.put(handle, &root_info(7)).unwrap();
+        }
+        let cache = ObjectInfoCache::with_capacity(cache.store, 2).unwrap();
+        assert_eq!(cache.handles, vec![3, 4]);
+        assert!(cache.store.get(&ObjectInfoCache::entry_key(1)).unwrap().is_none());
+    }
+}
@@ -0,0 +1,156 @@
+// 机型兼容表 - 按USB VID/PID记录个别相机偏离标准的行为(打开会话后需要等待、不支持分块读取、
+// 端点收到零长度包会卡住等)，PtpCamera创建时查表并据此调整收发方式；条目可在运行时注册，
+// 运行时注册的条目优先于内置条目。机身设置中按序列号登记的兼容性处理在识别机身后合并进来
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+/// 单项兼容性处理，机身设置中按名称登记
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraQuirk {
+    ForcePtp,           // 即使支持MTP也使用PTP
+    NoObjectPropList,   // GetObjectPropList不可靠，逐个读取属性
+    SlowEvents,         // 事件上报慢，加长轮询间隔
+    NoPartialObject,    // 不支持GetPartialObject，只能整体读取对象
+    NoZeroLengthPacket, // 端点收到零长度包会卡住
+}
+
+/// 相机的兼容性问题
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quirks {
+    #[serde(default)]
+    pub open_session_delay_ms: u32, // OpenSession成功后等待多久再发送下一条命令
+    #[serde(default)]
+    pub no_partial_object: bool,    // 不支持GetPartialObject，只能整体读取对象
+    #[serde(default)]
+    pub no_zero_length_packet: bool, // 端点收到零长度包会卡住，容器长度是包大小的整数倍时也不补发
+    #[serde(default)]
+    pub force_ptp: bool,            // 即使支持MTP也使用PTP
+    #[serde(default)]
+    pub no_object_prop_list: bool,  // GetObjectPropList不可靠，逐个读取对象信息
+    #[serde(default)]
+    pub slow_events: bool,          // 事件上报慢，加长轮询间隔
+}
+
+impl Quirks {
+    /// OpenSession之后的等待时间
+    pub fn open_session_delay(&self) -> Option<Duration> {
+        (self.open_session_delay_ms > 0).then(|| Duration::from_millis(self.open_session_delay_ms as u64))
+    }
+
+    /// 是否有某项兼容性问题
+    pub fn has(&self, quirk: CameraQuirk) -> bool {
+        match quirk {
+            CameraQuirk::ForcePtp => self.force_ptp,
+            CameraQuirk::NoObjectPropList => self.no_object_prop_list,
+            CameraQuirk::SlowEvents => self.slow_events,
+            CameraQuirk::NoPartialObject => self.no_partial_object,
+            CameraQuirk::NoZeroLengthPacket => self.no_zero_length_packet,
+        }
+    }
+
+    /// 加上若干项兼容性处理
+    pub fn with(mut self, quirks: &[CameraQuirk]) -> Self {
+        for quirk in quirks {
+            let flag = match quirk {
+                CameraQuirk::ForcePtp => &mut self.force_ptp,
+                CameraQuirk::NoObjectPropList => &mut self.no_object_prop_list,
+                CameraQuirk::SlowEvents => &mut self.slow_events,
+                CameraQuirk::NoPartialObject => &mut self.no_partial_object,
+                CameraQuirk::NoZeroLengthPacket => &mut self.no_zero_length_packet,
+            };
+            *flag = true;
+        }
+        self
+    }
+
+    /// 是否没有任何兼容性问题
+    pub fn is_empty(&self) -> bool {
+        *self == Quirks::default()
+    }
+}
+
+/// 兼容表中的一项
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuirkEntry {
+    pub vid: u16,
+    pub pid: Option<u16>, // None表示该厂商的全部机型
+    #[serde(flatten)]
+    pub quirks: Quirks,
+}
+
+/// 内置条目，来自libmtp设备表中标记为GetObjectPropList不可靠的安卓MTP设备
+const BUILTIN: [QuirkEntry; 2] = [
+    // 三星Galaxy系列的MTP模式
+    QuirkEntry { vid: 0x04e8, pid: Some(0x6860), quirks: Quirks { no_object_prop_list: true, ..NONE } },
+    // Google Nexus/Pixel的MTP模式
+    QuirkEntry { vid: 0x18d1, pid: Some(0x4ee1), quirks: Quirks { no_object_prop_list: true, ..NONE } },
+];
+
+const NONE: Quirks = Quirks {
+    open_session_delay_ms: 0,
+    no_partial_object: false,
+    no_zero_length_packet: false,
+    force_ptp: false,
+    no_object_prop_list: false,
+    slow_events: false,
+};
+
+/// 运行时注册的条目
+static ENTRIES: Mutex<Vec<QuirkEntry>> = Mutex::new(Vec::new());
+
+/// 注册机型的兼容性问题，同一VID/PID的条目会被替换；之后创建的PtpCamera生效
+pub fn register_quirks(entry: QuirkEntry) {
+    let mut entries = ENTRIES.lock().unwrap();
+    match entries.iter_mut().find(|e| e.vid == entry.vid && e.pid == entry.pid) {
+        Some(existing) => {
+            warn!("{} 的兼容性条目已存在，替换为新的设置", describe(entry.vid, entry.pid));
+            *existing = entry;
+        }
+        None => {
+            info!("已注册 {} 的兼容性条目: {:?}", describe(entry.vid, entry.pid), entry.quirks);
+            entries.push(entry);
+        }
+    }
+}
+
+/// 查找机型的兼容性问题，精确匹配PID的条目优先于只匹配厂商的条目，同等匹配时运行时注册的条目优先于内置条目；
+/// 没有条目时返回空
+pub fn lookup(vid: u16, pid: u16) -> Quirks {
+    let registered = ENTRIES.lock().unwrap();
+    let entries = || registered.iter().chain(BUILTIN.iter());
+    entries()
+        .find(|e| e.vid == vid && e.pid == Some(pid))
+        .or_else(|| entries().find(|e| e.vid == vid && e.pid.is_none()))
+        .map(|e| e.quirks)
+        .unwrap_or_default()
+}
+
+fn describe(vid: u16, pid: Option<u16>) -> String {
+    match pid {
+        Some(pid) => format!("{:04x}:{:04x}", vid, pid),
+        None => format!("{:04x}:*", vid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_entries_override_builtin_ones() {
+        assert!(lookup(0x04e8, 0x6860).has(CameraQuirk::NoObjectPropList));
+        register_quirks(QuirkEntry { vid: 0x04e8, pid: Some(0x6860), quirks: Quirks { slow_events: true, ..NONE } });
+        let quirks = lookup(0x04e8, 0x6860);
+        assert!(quirks.has(CameraQuirk::SlowEvents) && !quirks.has(CameraQuirk::NoObjectPropList));
+    }
+
+    #[test]
+    fn body_quirks_merge_into_table_quirks() {
+        let quirks = Quirks { open_session_delay_ms: 500, ..NONE }.with(&[CameraQuirk::ForcePtp, CameraQuirk::NoPartialObject]);
+        assert!(quirks.force_ptp && quirks.no_partial_object && !quirks.slow_events);
+        assert_eq!(quirks.open_session_delay(), Some(Duration::from_millis(500)));
+    }
+}
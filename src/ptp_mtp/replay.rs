@@ -0,0 +1,323 @@
+// 录制与回放传输层 - 把真实相机的完整传输过程录到SD卡/Flash，之后脱离硬件回放以复现特定机型的问题
+//
+// 录制文件格式(小端序):
+//   文件头: "PTPTRACE" 版本(u16) VID(u16) PID(u16) 型号长度(u16) 型号(UTF-8)
+//   记录:   类型(u8) 长度(u32) 数据
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use log::{debug, info, warn};
+
+use crate::ptp_mtp::error::Error;
+use crate::ptp_mtp::transport::PtpTransport;
+
+const TRACE_MAGIC: &[u8; 8] = b"PTPTRACE";
+const TRACE_VERSION: u16 = 1;
+
+/// 记录类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum RecordKind {
+    BulkOut = 1,    // 主机写出的数据
+    BulkIn = 2,     // 设备返回的数据
+    Interrupt = 3,  // 中断端点事件
+    WriteError = 4, // 写入失败(数据为错误描述)
+    ReadError = 5,  // 读取失败(数据为错误描述)
+}
+
+impl RecordKind {
+    fn from_u8(v: u8) -> Option<RecordKind> {
+        match v {
+            1 => Some(RecordKind::BulkOut),
+            2 => Some(RecordKind::BulkIn),
+            3 => Some(RecordKind::Interrupt),
+            4 => Some(RecordKind::WriteError),
+            5 => Some(RecordKind::ReadError),
+            _ => None,
+        }
+    }
+}
+
+/// 录制文件头，标明录制时使用的相机
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceHeader {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub model: String,
+}
+
+impl TraceHeader {
+    fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        w.write_all(TRACE_MAGIC)?;
+        w.write_u16::<LittleEndian>(TRACE_VERSION)?;
+        w.write_u16::<LittleEndian>(self.vendor_id)?;
+        w.write_u16::<LittleEndian>(self.product_id)?;
+        w.write_u16::<LittleEndian>(self.model.len() as u16)?;
+        w.write_all(self.model.as_bytes())?;
+        Ok(())
+    }
+
+    fn read_from<R: Read>(r: &mut R) -> Result<TraceHeader, Error> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != TRACE_MAGIC {
+            return Err(Error::Malformed("不是PTP录制文件".to_string()));
+        }
+        let version = r.read_u16::<LittleEndian>()?;
+        if version != TRACE_VERSION {
+            return Err(Error::Malformed(format!("不支持的录制文件版本: {}", version)));
+        }
+        let vendor_id = r.read_u16::<LittleEndian>()?;
+        let product_id = r.read_u16::<LittleEndian>()?;
+        let len = r.read_u16::<LittleEndian>()? as usize;
+        let mut model = vec![0u8; len];
+        r.read_exact(&mut model)?;
+        Ok(TraceHeader {
+            vendor_id,
+            product_id,
+            model: String::from_utf8_lossy(&model).into_owned(),
+        })
+    }
+}
+
+/// 录制传输层 - 包装真实传输层，把每次传输原样写入录制文件
+///
+/// 录制文件写入失败不会影响与相机的通信，只会停止录制
+pub struct RecordingTransport<T: PtpTransport, W: Write> {
+    inner: T,
+    writer: W,
+    records: usize,
+    failed: bool,
+}
+
+impl<T: PtpTransport> RecordingTransport<T, BufWriter<File>> {
+    /// 录制到文件(例如 /sdcard/traces/eos_r6.trace)
+    pub fn to_file(inner: T, path: &Path, header: &TraceHeader) -> Result<Self, Error> {
+        let file = File::create(path)?;
+        info!("开始录制PTP传输到 {}", path.display());
+        Self::new(inner, BufWriter::new(file), header)
+    }
+}
+
+impl<T: PtpTransport, W: Write> RecordingTransport<T, W> {
+    /// 包装传输层并写入文件头
+    pub fn new(inner: T, mut writer: W, header: &TraceHeader) -> Result<Self, Error> {
+        header.write_to(&mut writer)?;
+        Ok(RecordingTransport {
+            inner,
+            writer,
+            records: 0,
+            failed: false,
+        })
+    }
+
+    /// 已录制的记录数
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// 结束录制，返回内部传输层和写入器
+    pub fn finish(mut self) -> Result<(T, W), Error> {
+        self.writer.flush()?;
+        debug!("录制结束，共 {} 条记录", self.records);
+        Ok((self.inner, self.writer))
+    }
+
+    fn record(&mut self, kind: RecordKind, data: &[u8]) {
+        if self.failed {
+            return;
+        }
+        let result = (|| -> Result<(), Error> {
+            self.writer.write_u8(kind as u8)?;
+            self.writer.write_u32::<LittleEndian>(data.len() as u32)?;
+            self.writer.write_all(data)?;
+            Ok(())
+        })();
+        match result {
+            Ok(()) => self.records += 1,
+            Err(e) => {
+                warn!("写入录制文件失败，停止录制: {}", e);
+                self.failed = true;
+            }
+        }
+    }
+}
+
+impl<T: PtpTransport, W: Write> PtpTransport for RecordingTransport<T, W> {
+    async fn bulk_write(&mut self, data: &[u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        match self.inner.bulk_write(data, timeout_ms).await {
+            Ok(n) => {
+                self.record(RecordKind::BulkOut, &data[..n]);
+                Ok(n)
+            }
+            Err(e) => {
+                self.record(RecordKind::WriteError, e.to_string().as_bytes());
+                Err(e)
+            }
+        }
+    }
+
+    async fn bulk_read(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        match self.inner.bulk_read(buffer, timeout_ms).await {
+            Ok(n) => {
+                self.record(RecordKind::BulkIn, &buffer[..n]);
+                Ok(n)
+            }
+            Err(e) => {
+                self.record(RecordKind::ReadError, e.to_string().as_bytes());
+                Err(e)
+            }
+        }
+    }
+
+    async fn read_interrupt_event(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        let n = self.inner.read_interrupt_event(buffer, timeout_ms).await?;
+        // 没有事件的轮询不录制，否则录制文件会被空轮询填满
+        if n > 0 {
+            self.record(RecordKind::Interrupt, &buffer[..n]);
+        }
+        Ok(n)
+    }
+
+    fn max_packet_size(&self) -> usize {
+        self.inner.max_packet_size()
+    }
+
+    fn has_events(&self) -> bool {
+        self.inner.has_events()
+    }
+
+    fn usb_id(&self) -> Option<(u16, u16)> {
+        self.inner.usb_id()
+    }
+
+    async fn request_cancel(&mut self, tid: u32) -> Result<(), Error> {
+        self.inner.request_cancel(tid).await
+    }
+
+    async fn device_status(&mut self) -> Result<u16, Error> {
+        self.inner.device_status().await
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        self.inner.close().await
+    }
+}
+
+/// 回放传输层 - 按录制顺序提供设备数据，并校验主机写出的数据与录制时一致
+pub struct ReplayTransport {
+    header: TraceHeader,
+    bulk: VecDeque<(RecordKind, Vec<u8>)>,
+    interrupts: VecDeque<Vec<u8>>,
+    position: usize,
+}
+
+impl ReplayTransport {
+    /// 从录制文件加载
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::load(File::open(path)?)
+    }
+
+    /// 从任意数据源加载录制内容
+    pub fn load<R: Read>(mut r: R) -> Result<Self, Error> {
+        let header = TraceHeader::read_from(&mut r)?;
+        let mut bulk = VecDeque::new();
+        let mut interrupts = VecDeque::new();
+        loop {
+            let kind = match r.read_u8() {
+                Ok(kind) => kind,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+            let kind = RecordKind::from_u8(kind)
+                .ok_or_else(|| Error::Malformed(format!("未知的录制记录类型: {}", kind)))?;
+            let len = r.read_u32::<LittleEndian>()? as usize;
+            let mut data = vec![0u8; len];
+            r.read_exact(&mut data)?;
+            match kind {
+                RecordKind::Interrupt => interrupts.push_back(data),
+                _ => bulk.push_back((kind, data)),
+            }
+        }
+        debug!(
+            "已加载 {} 的录制: {} 条批量记录，{} 个事件",
+            header.model,
+            bulk.len(),
+            interrupts.len()
+        );
+        Ok(ReplayTransport {
+            header,
+            bulk,
+            interrupts,
+            position: 0,
+        })
+    }
+
+    /// 录制时使用的相机
+    pub fn header(&self) -> &TraceHeader {
+        &self.header
+    }
+
+    /// 是否已回放完所有批量记录
+    pub fn is_finished(&self) -> bool {
+        self.bulk.is_empty()
+    }
+
+    fn next_bulk(&mut self, expected: &str) -> Result<(RecordKind, Vec<u8>), Error> {
+        self.position += 1;
+        self.bulk
+            .pop_front()
+            .ok_or_else(|| Error::Malformed(format!("回放已结束，但主机仍在{}", expected)))
+    }
+
+    fn divergence(&self, msg: String) -> Error {
+        Error::Malformed(format!("回放在第 {} 条记录处与录制不一致: {}", self.position, msg))
+    }
+}
+
+impl PtpTransport for ReplayTransport {
+    async fn bulk_write(&mut self, data: &[u8], _timeout_ms: Option<u64>) -> Result<usize, Error> {
+        match self.next_bulk("写入")? {
+            (RecordKind::BulkOut, recorded) if recorded == data => Ok(data.len()),
+            (RecordKind::BulkOut, recorded) => Err(self.divergence(format!(
+                "写入了 {} 字节，录制时为 {} 字节且内容不同",
+                data.len(),
+                recorded.len()
+            ))),
+            (RecordKind::WriteError, msg) => Err(Error::USB(String::from_utf8_lossy(&msg).into_owned())),
+            (kind, _) => Err(self.divergence(format!("主机写入，但录制的下一条记录是 {:?}", kind))),
+        }
+    }
+
+    async fn bulk_read(&mut self, buffer: &mut [u8], _timeout_ms: Option<u64>) -> Result<usize, Error> {
+        match self.next_bulk("读取")? {
+            (RecordKind::BulkIn, recorded) => {
+                if recorded.len() > buffer.len() {
+                    return Err(self.divergence(format!(
+                        "读取缓冲区 {} 字节，小于录制的 {} 字节",
+                        buffer.len(),
+                        recorded.len()
+                    )));
+                }
+                buffer[..recorded.len()].copy_from_slice(&recorded);
+                Ok(recorded.len())
+            }
+            (RecordKind::ReadError, msg) => Err(Error::USB(String::from_utf8_lossy(&msg).into_owned())),
+            (kind, _) => Err(self.divergence(format!("主机读取，但录制的下一条记录是 {:?}", kind))),
+        }
+    }
+
+    async fn read_interrupt_event(&mut self, buffer: &mut [u8], _timeout_ms: Option<u64>) -> Result<usize, Error> {
+        match self.interrupts.pop_front() {
+            Some(event) => {
+                let n = event.len().min(buffer.len());
+                buffer[..n].copy_from_slice(&event[..n]);
+                Ok(n)
+            }
+            None => Ok(0),
+        }
+    }
+}
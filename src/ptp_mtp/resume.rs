@@ -0,0 +1,170 @@
+// 断点续传 - 在NVS中记录正在下载的对象句柄和偏移，相机或无线客户端重连后用GetPartialObject从断点继续
+use std::error::Error;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::persist::KvStore;
+use crate::ptp_mtp::camera::{ObjectProgress, PtpCamera};
+use crate::ptp_mtp::error::SinkError;
+
+const CHECKPOINT_KEY: &str = "dl_ckpt";
+
+/// 默认每下载1MB保存一次断点，避免频繁写Flash
+pub const DEFAULT_PERSIST_INTERVAL: u64 = 1024 * 1024;
+
+/// 持久化的下载断点
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadCheckpoint {
+    pub handle: u32,      // 对象句柄
+    pub filename: String, // 文件名，重连后用于确认句柄仍指向同一对象
+    pub total: u64,       // 对象总大小
+    pub offset: u64,      // 接收端已确认的字节数
+}
+
+/// 可续传的对象下载
+///
+/// 每个数据块交给回调后才推进偏移：回调返回错误(例如无线发送失败)时，
+/// 下一次下载从最后保存的断点重新开始，接收端可能会再次收到断点之后的部分数据
+pub struct ResumableDownload {
+    store: Box<dyn KvStore>,
+    checkpoint: Option<DownloadCheckpoint>,
+    chunk_size: u32,
+    persist_interval: u64,
+}
+
+impl ResumableDownload {
+    /// 打开续传记录，读取上次未完成的下载
+    pub fn open(store: Box<dyn KvStore>, chunk_size: u32) -> Result<Self, Box<dyn Error>> {
+        let checkpoint = match store.get(CHECKPOINT_KEY)? {
+            Some(raw) => match serde_json::from_slice::<DownloadCheckpoint>(&raw) {
+                Ok(checkpoint) => {
+                    info!(
+                        "发现未完成的下载: {} ({}/{} 字节)",
+                        checkpoint.filename, checkpoint.offset, checkpoint.total
+                    );
+                    Some(checkpoint)
+                }
+                Err(e) => {
+                    warn!("断点记录损坏，已忽略: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        Ok(ResumableDownload {
+            store,
+            checkpoint,
+            chunk_size: chunk_size.max(1),
+            persist_interval: DEFAULT_PERSIST_INTERVAL,
+        })
+    }
+
+    /// 设置保存断点的间隔(字节)
+    pub fn set_persist_interval(&mut self, bytes: u64) {
+        self.persist_interval = bytes.max(self.chunk_size as u64);
+    }
+
+    /// 设置之后下载使用的分块大小(例如内存压力下缩小)
+    pub fn set_chunk_size(&mut self, chunk_size: u32) {
+        self.chunk_size = chunk_size.max(1);
+        self.persist_interval = self.persist_interval.max(self.chunk_size as u64);
+    }
+
+    /// 未完成的下载
+    pub fn pending(&self) -> Option<&DownloadCheckpoint> {
+        self.checkpoint.as_ref()
+    }
+
+    /// 放弃未完成的下载
+    pub fn clear(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.checkpoint.take().is_some() {
+            self.store.remove(CHECKPOINT_KEY)?;
+            self.store.flush()?;
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(checkpoint) = &self.checkpoint {
+            self.store.set(CHECKPOINT_KEY, &serde_json::to_vec(checkpoint)?)?;
+            self.store.flush()?;
+        }
+        Ok(())
+    }
+
+    /// 下载对象；若断点记录的是同一对象则从断点继续，否则从头开始并覆盖旧断点
+    /// `on_chunk` 收到数据块及其在对象中的偏移，返回错误时保存断点并中止。返回对象总大小
+    pub async fn download<F>(
+        &mut self,
+        camera: &mut PtpCamera,
+        handle: u32,
+        timeout: Option<Duration>,
+        mut on_chunk: F,
+    ) -> Result<u64, Box<dyn Error>>
+    where
+        F: FnMut(u64, &[u8], ObjectProgress) -> Result<(), Box<dyn Error>>,
+    {
+        let info = camera.get_objectinfo(handle, timeout).await?;
+        let total = camera.get_object_size(handle, &info, timeout).await?;
+
+        // 重连后句柄可能被相机重新分配，文件名和大小都一致才认为是同一对象
+        let offset = match &self.checkpoint {
+            Some(c) if c.handle == handle && c.filename == info.Filename && c.total == total => {
+                info!("从 {} 字节处继续下载 {}", c.offset, c.filename);
+                c.offset
+            }
+            Some(c) => {
+                debug!("断点 {} 与对象 0x{:08x} 不符，重新开始", c.filename, handle);
+                0
+            }
+            None => 0,
+        };
+        self.checkpoint = Some(DownloadCheckpoint {
+            handle,
+            filename: info.Filename.clone(),
+            total,
+            offset,
+        });
+        self.persist()?;
+
+        let mut confirmed = offset;
+        let mut persisted = offset;
+        let mut sink_error = SinkError::default();
+        let interval = self.persist_interval;
+        let store = &mut self.store;
+        let checkpoint = &mut self.checkpoint;
+        let result = camera
+            .stream_object_from(handle, offset, self.chunk_size, timeout, |chunk, progress| {
+                let chunk_offset = progress.bytes_done - chunk.len() as u64;
+                sink_error.capture(on_chunk(chunk_offset, chunk, progress))?;
+                confirmed = progress.bytes_done;
+                if confirmed - persisted >= interval {
+                    if let Some(c) = checkpoint.as_mut() {
+                        c.offset = confirmed;
+                        // 断点写入失败只影响续传位置，不中断下载
+                        match serde_json::to_vec(c).map_err(Box::<dyn Error>::from).and_then(|raw| store.set(CHECKPOINT_KEY, &raw)) {
+                            Ok(()) => persisted = confirmed,
+                            Err(e) => warn!("保存下载断点失败: {}", e),
+                        }
+                    }
+                }
+                Ok(())
+            })
+            .await;
+
+        if let Err(e) = sink_error.finish(result) {
+            // 中断时记录最后确认的进度
+            if let Some(c) = &mut self.checkpoint {
+                c.offset = confirmed;
+            }
+            self.persist()?;
+            warn!("{} 下载中断于 {}/{} 字节", info.Filename, confirmed, total);
+            return Err(e);
+        }
+        self.clear()?;
+        info!("{} 下载完成 ({} 字节)", info.Filename, total);
+        Ok(total)
+    }
+}
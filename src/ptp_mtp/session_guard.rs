@@ -0,0 +1,70 @@
+// 会话守护 - 相机报告会话未打开(例如休眠唤醒后)时由PtpCamera自动重开会话并重放失败的命令；
+// 另外在链路长时间空闲时发送GetDeviceInfo保活，避免相机因空闲断开会话。
+// 相机与主循环、FTP等共用，守护只在读取空闲时间和发送保活的那一次事务期间持有锁
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use embassy_futures::block_on;
+use log::{debug, info, warn};
+
+use super::error::Error;
+use super::mtp::SharedCamera;
+use crate::runtime;
+
+/// 会话守护
+pub struct SessionGuard {
+    camera: SharedCamera,
+    keepalive: Option<Duration>,
+}
+
+impl SessionGuard {
+    /// 为已打开的会话启用自动重开；`keepalive`为空闲多久后发送保活，None表示不保活
+    pub fn new(camera: SharedCamera, keepalive: Option<Duration>) -> Self {
+        camera.lock().unwrap().ptp().set_auto_reopen(true);
+        info!("会话守护已启用，保活间隔: {:?}", keepalive.map(|k| k.as_secs()));
+        SessionGuard { camera, keepalive }
+    }
+
+    /// 空闲时间达到保活间隔时发送一次保活，返回是否发送
+    pub fn tick(&mut self) -> Result<bool, Error> {
+        let Some(interval) = self.keepalive else {
+            return Ok(false);
+        };
+        // 先只取出空闲时间，链路忙时不必等待其他使用方释放相机
+        let idle = self.camera.lock().unwrap().ptp().idle_time();
+        if idle < interval {
+            return Ok(false);
+        }
+        let mut camera = self.camera.lock().unwrap();
+        // 等待锁的期间其他使用方可能刚完成事务
+        if camera.ptp().idle_time() < interval {
+            return Ok(false);
+        }
+        debug!("链路空闲，发送保活");
+        block_on(camera.ptp().get_device_info(None))?;
+        Ok(true)
+    }
+
+    /// 在后台任务中持续保活，直到保活失败(例如相机已断开)后停用自动重开会话
+    pub fn spawn(mut self) -> Result<Option<JoinHandle<()>>, Box<dyn std::error::Error>> {
+        let Some(interval) = self.keepalive else {
+            return Ok(None);
+        };
+        // 以一半的间隔检查，保证空闲时间不会明显超过保活间隔
+        let period = (interval / 2).max(Duration::from_millis(1));
+        let task = runtime::spawn(runtime::SESSION_GUARD, move || loop {
+            thread::sleep(period);
+            if let Err(e) = self.tick() {
+                warn!("保活失败: {}", e);
+                self.disable();
+                return;
+            }
+        })?;
+        Ok(Some(task))
+    }
+
+    /// 停用自动重开会话
+    pub fn disable(self) {
+        self.camera.lock().unwrap().ptp().set_auto_reopen(false);
+    }
+}
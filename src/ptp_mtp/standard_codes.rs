@@ -1,7 +1,7 @@
 #![allow(non_snake_case)]
 
 // 定义PTP容器类型
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[repr(u16)]
 pub enum PtpContainerType {
     Command = 1,  // 命令容器
@@ -183,3 +183,261 @@ pub mod StandardCommandCode {
         }
     }
 }
+
+/// 设备属性代码类型
+pub type DevicePropCode = u16;
+
+/// 标准PTP设备属性代码定义
+#[allow(non_upper_case_globals)]
+pub mod StandardDevicePropCode {
+    use super::DevicePropCode;
+
+    pub const BatteryLevel: DevicePropCode = 0x5001;
+    pub const FunctionalMode: DevicePropCode = 0x5002;
+    pub const ImageSize: DevicePropCode = 0x5003;
+    pub const CompressionSetting: DevicePropCode = 0x5004;
+    pub const WhiteBalance: DevicePropCode = 0x5005;
+    pub const RGBGain: DevicePropCode = 0x5006;
+    pub const FNumber: DevicePropCode = 0x5007;
+    pub const FocalLength: DevicePropCode = 0x5008;
+    pub const FocusDistance: DevicePropCode = 0x5009;
+    pub const FocusMode: DevicePropCode = 0x500A;
+    pub const ExposureMeteringMode: DevicePropCode = 0x500B;
+    pub const FlashMode: DevicePropCode = 0x500C;
+    pub const ExposureTime: DevicePropCode = 0x500D;
+    pub const ExposureProgramMode: DevicePropCode = 0x500E;
+    pub const ExposureIndex: DevicePropCode = 0x500F;
+    pub const ExposureBiasCompensation: DevicePropCode = 0x5010;
+    pub const DateTime: DevicePropCode = 0x5011;
+    pub const CaptureDelay: DevicePropCode = 0x5012;
+    pub const StillCaptureMode: DevicePropCode = 0x5013;
+    pub const Contrast: DevicePropCode = 0x5014;
+    pub const Sharpness: DevicePropCode = 0x5015;
+    pub const DigitalZoom: DevicePropCode = 0x5016;
+    pub const EffectMode: DevicePropCode = 0x5017;
+    pub const BurstNumber: DevicePropCode = 0x5018;
+    pub const BurstInterval: DevicePropCode = 0x5019;
+    pub const TimelapseNumber: DevicePropCode = 0x501A;
+    pub const TimelapseInterval: DevicePropCode = 0x501B;
+    pub const FocusMeteringMode: DevicePropCode = 0x501C;
+    pub const UploadURL: DevicePropCode = 0x501D;
+    pub const Artist: DevicePropCode = 0x501E;
+    pub const CopyrightInfo: DevicePropCode = 0x501F;
+
+    /// 根据属性代码返回对应的名称
+    pub fn name(v: DevicePropCode) -> Option<&'static str> {
+        match v {
+            BatteryLevel => Some("电池电量"),
+            FunctionalMode => Some("功能模式"),
+            ImageSize => Some("图像尺寸"),
+            CompressionSetting => Some("压缩设置"),
+            WhiteBalance => Some("白平衡"),
+            RGBGain => Some("RGB增益"),
+            FNumber => Some("光圈"),
+            FocalLength => Some("焦距"),
+            FocusDistance => Some("对焦距离"),
+            FocusMode => Some("对焦模式"),
+            ExposureMeteringMode => Some("测光模式"),
+            FlashMode => Some("闪光模式"),
+            ExposureTime => Some("快门速度"),
+            ExposureProgramMode => Some("曝光程序"),
+            ExposureIndex => Some("ISO感光度"),
+            ExposureBiasCompensation => Some("曝光补偿"),
+            DateTime => Some("日期时间"),
+            CaptureDelay => Some("拍摄延时"),
+            StillCaptureMode => Some("拍摄模式"),
+            Contrast => Some("对比度"),
+            Sharpness => Some("锐度"),
+            DigitalZoom => Some("数码变焦"),
+            EffectMode => Some("效果模式"),
+            BurstNumber => Some("连拍张数"),
+            BurstInterval => Some("连拍间隔"),
+            TimelapseNumber => Some("延时拍摄张数"),
+            TimelapseInterval => Some("延时拍摄间隔"),
+            FocusMeteringMode => Some("对焦测光模式"),
+            UploadURL => Some("上传地址"),
+            Artist => Some("作者"),
+            CopyrightInfo => Some("版权信息"),
+            _ => None,
+        }
+    }
+}
+
+/// 事件码类型
+pub type EventCode = u16;
+
+/// 标准PTP事件码定义
+#[allow(non_upper_case_globals)]
+pub mod StandardEventCode {
+    use super::EventCode;
+
+    pub const Undefined: EventCode = 0x4000;
+    pub const CancelTransaction: EventCode = 0x4001;
+    pub const ObjectAdded: EventCode = 0x4002;
+    pub const ObjectRemoved: EventCode = 0x4003;
+    pub const StoreAdded: EventCode = 0x4004;
+    pub const StoreRemoved: EventCode = 0x4005;
+    pub const DevicePropChanged: EventCode = 0x4006;
+    pub const ObjectInfoChanged: EventCode = 0x4007;
+    pub const DeviceInfoChanged: EventCode = 0x4008;
+    pub const RequestObjectTransfer: EventCode = 0x4009;
+    pub const StoreFull: EventCode = 0x400A;
+    pub const DeviceReset: EventCode = 0x400B;
+    pub const StorageInfoChanged: EventCode = 0x400C;
+    pub const CaptureComplete: EventCode = 0x400D;
+    pub const UnreportedStatus: EventCode = 0x400E;
+
+    /// 根据事件码返回对应的名称
+    pub fn name(v: EventCode) -> Option<&'static str> {
+        match v {
+            Undefined => Some("未定义"),
+            CancelTransaction => Some("取消事务"),
+            ObjectAdded => Some("新增对象"),
+            ObjectRemoved => Some("对象已删除"),
+            StoreAdded => Some("新增存储"),
+            StoreRemoved => Some("存储已移除"),
+            DevicePropChanged => Some("设备属性已改变"),
+            ObjectInfoChanged => Some("对象信息已改变"),
+            DeviceInfoChanged => Some("设备信息已改变"),
+            RequestObjectTransfer => Some("请求传输对象"),
+            StoreFull => Some("存储已满"),
+            DeviceReset => Some("设备已重置"),
+            StorageInfoChanged => Some("存储信息已改变"),
+            CaptureComplete => Some("拍摄完成"),
+            UnreportedStatus => Some("有未报告的状态"),
+            _ => None,
+        }
+    }
+}
+
+/// 对象格式代码(PTP标准格式、MTP扩展格式和常见的厂商RAW格式)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectFormat {
+    Undefined,      // 0x3000 未定义的非图像对象，部分机身的RAW也报告为此格式
+    Association,    // 0x3001 关联(文件夹)
+    Script,         // 0x3002
+    Text,           // 0x3004
+    Html,           // 0x3005
+    Dpof,           // 0x3006 打印指令
+    Wav,            // 0x3008
+    Mp3,            // 0x3009
+    Avi,            // 0x300A
+    Mpeg,           // 0x300B
+    UndefinedImage, // 0x3800
+    ExifJpeg,       // 0x3801
+    TiffEp,         // 0x3802 TIFF/EP，部分机身的RAW使用此格式
+    Bmp,            // 0x3804
+    Ciff,           // 0x3805 佳能早期的RAW(CRW)
+    Gif,            // 0x3807
+    Jfif,           // 0x3808
+    Png,            // 0x380B
+    Tiff,           // 0x380D
+    Jp2,            // 0x380F
+    Dng,            // 0x3811
+    Heif,           // 0x3812
+    Mp4,            // 0xB982 MTP MP4容器
+    ThreeGp,        // 0xB984 MTP 3GP容器
+    CanonCrw,       // 0xB101
+    CanonCr2,       // 0xB103
+    CanonMov,       // 0xB104
+    CanonCr3,       // 0xB108
+    Other(u16),     // 其他格式代码
+}
+
+impl From<u16> for ObjectFormat {
+    fn from(v: u16) -> Self {
+        use self::ObjectFormat::*;
+        match v {
+            0x3000 => Undefined,
+            0x3001 => Association,
+            0x3002 => Script,
+            0x3004 => Text,
+            0x3005 => Html,
+            0x3006 => Dpof,
+            0x3008 => Wav,
+            0x3009 => Mp3,
+            0x300A => Avi,
+            0x300B => Mpeg,
+            0x3800 => UndefinedImage,
+            0x3801 => ExifJpeg,
+            0x3802 => TiffEp,
+            0x3804 => Bmp,
+            0x3805 => Ciff,
+            0x3807 => Gif,
+            0x3808 => Jfif,
+            0x380B => Png,
+            0x380D => Tiff,
+            0x380F => Jp2,
+            0x3811 => Dng,
+            0x3812 => Heif,
+            0xB982 => Mp4,
+            0xB984 => ThreeGp,
+            0xB101 => CanonCrw,
+            0xB103 => CanonCr2,
+            0xB104 => CanonMov,
+            0xB108 => CanonCr3,
+            other => Other(other),
+        }
+    }
+}
+
+impl From<ObjectFormat> for u16 {
+    fn from(f: ObjectFormat) -> Self {
+        use self::ObjectFormat::*;
+        match f {
+            Undefined => 0x3000,
+            Association => 0x3001,
+            Script => 0x3002,
+            Text => 0x3004,
+            Html => 0x3005,
+            Dpof => 0x3006,
+            Wav => 0x3008,
+            Mp3 => 0x3009,
+            Avi => 0x300A,
+            Mpeg => 0x300B,
+            UndefinedImage => 0x3800,
+            ExifJpeg => 0x3801,
+            TiffEp => 0x3802,
+            Bmp => 0x3804,
+            Ciff => 0x3805,
+            Gif => 0x3807,
+            Jfif => 0x3808,
+            Png => 0x380B,
+            Tiff => 0x380D,
+            Jp2 => 0x380F,
+            Dng => 0x3811,
+            Heif => 0x3812,
+            Mp4 => 0xB982,
+            ThreeGp => 0xB984,
+            CanonCrw => 0xB101,
+            CanonCr2 => 0xB103,
+            CanonMov => 0xB104,
+            CanonCr3 => 0xB108,
+            Other(code) => code,
+        }
+    }
+}
+
+impl ObjectFormat {
+    /// 是否为关联(文件夹)
+    pub fn is_association(self) -> bool {
+        self == ObjectFormat::Association
+    }
+
+    /// 是否为RAW格式；报告为Undefined/TiffEp的RAW无法仅凭格式代码识别，需要结合文件名判断
+    pub fn is_raw(self) -> bool {
+        use self::ObjectFormat::*;
+        matches!(self, Ciff | Dng | CanonCrw | CanonCr2 | CanonCr3)
+    }
+
+    /// 是否为视频
+    pub fn is_video(self) -> bool {
+        use self::ObjectFormat::*;
+        matches!(self, Avi | Mpeg | Mp4 | ThreeGp | CanonMov)
+    }
+
+    /// 是否为图像(含RAW)，PTP的图像格式代码都在0x38xx段
+    pub fn is_image(self) -> bool {
+        u16::from(self) & 0xF800 == 0x3800 || self.is_raw()
+    }
+}
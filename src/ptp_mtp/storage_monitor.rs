@@ -0,0 +1,124 @@
+// 存储空间监视 - 周期性读取各存储的剩余空间，低于阈值或相机返回StoreFull时产生应用事件提醒用户换卡
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::events::AppEvent;
+use crate::ptp_mtp::device_info::PtpStorageInfo;
+use crate::ptp_mtp::error::Error;
+use crate::ptp_mtp::standard_codes::StandardResponseCode;
+
+/// 相机不支持按张数估算剩余空间时FreeSpaceInImages的取值
+const FREE_IMAGES_UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// 剩余空间告警阈值，0表示不检查该项
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageThresholds {
+    pub min_free_bytes: u64,  // 剩余字节数低于此值时告警
+    pub min_free_images: u32, // 剩余可拍张数低于此值时告警
+    pub poll_interval: Duration, // 轮询间隔
+}
+
+impl Default for StorageThresholds {
+    fn default() -> Self {
+        StorageThresholds {
+            min_free_bytes: 1024 * 1024 * 1024,
+            min_free_images: 50,
+            poll_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// 存储空间监视器
+pub struct StorageMonitor {
+    thresholds: StorageThresholds,
+    last_poll: Option<Instant>,
+    free: BTreeMap<u32, (u64, u32)>, // 存储ID -> (剩余字节数, 剩余张数)
+    low: BTreeSet<u32>,              // 已告警、尚未恢复的存储
+}
+
+impl StorageMonitor {
+    pub fn new(thresholds: StorageThresholds) -> Self {
+        StorageMonitor {
+            thresholds,
+            last_poll: None,
+            free: BTreeMap::new(),
+            low: BTreeSet::new(),
+        }
+    }
+
+    /// 是否到了下一次轮询的时间
+    pub fn due(&self) -> bool {
+        self.last_poll.map_or(true, |t| t.elapsed() >= self.thresholds.poll_interval)
+    }
+
+    /// 各存储最近一次读取到的剩余空间
+    pub fn free_space(&self) -> &BTreeMap<u32, (u64, u32)> {
+        &self.free
+    }
+
+    /// 根据一次轮询的结果更新状态，返回新进入低空间状态的存储对应的事件
+    /// 同一存储只在跌破阈值时告警一次，空间回到阈值以上(例如换卡)后重新启用告警
+    pub fn update(&mut self, storages: &[(u32, PtpStorageInfo)]) -> Vec<AppEvent> {
+        self.last_poll = Some(Instant::now());
+        // 已移除的存储不再跟踪
+        self.free.retain(|id, _| storages.iter().any(|(s, _)| s == id));
+        self.low.retain(|id| storages.iter().any(|(s, _)| s == id));
+
+        let mut events = Vec::new();
+        for (storage_id, info) in storages {
+            self.free.insert(*storage_id, (info.FreeSpaceInBytes, info.FreeSpaceInImages));
+            if !self.is_low(info) {
+                if self.low.remove(storage_id) {
+                    info!("存储 0x{:08x} 剩余空间已恢复: {} 字节", storage_id, info.FreeSpaceInBytes);
+                }
+                continue;
+            }
+            if self.low.insert(*storage_id) {
+                warn!(
+                    "存储 0x{:08x} 剩余空间不足: {} 字节，约 {} 张",
+                    storage_id, info.FreeSpaceInBytes, info.FreeSpaceInImages
+                );
+                events.push(AppEvent::StorageLow {
+                    storage_id: Some(*storage_id),
+                    free_bytes: Some(info.FreeSpaceInBytes),
+                    free_images: known_images(info.FreeSpaceInImages),
+                    store_full: false,
+                });
+            }
+        }
+        events
+    }
+
+    /// 检查命令的错误，相机返回StoreFull时立即产生事件(响应中不含存储ID)
+    pub fn observe_error(&mut self, error: &(dyn StdError + 'static)) -> Option<AppEvent> {
+        match error.downcast_ref::<Error>() {
+            Some(Error::Response(code)) if *code == StandardResponseCode::StoreFull => {
+                warn!("相机报告存储已满");
+                // 下一次主循环立即轮询，确定是哪张卡
+                self.last_poll = None;
+                Some(AppEvent::StorageLow {
+                    storage_id: None,
+                    free_bytes: None,
+                    free_images: None,
+                    store_full: true,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn is_low(&self, info: &PtpStorageInfo) -> bool {
+        let t = &self.thresholds;
+        (t.min_free_bytes > 0 && info.FreeSpaceInBytes < t.min_free_bytes)
+            || (t.min_free_images > 0
+                && known_images(info.FreeSpaceInImages).is_some_and(|n| n < t.min_free_images))
+    }
+}
+
+fn known_images(free_images: u32) -> Option<u32> {
+    (free_images != FREE_IMAGES_UNKNOWN).then_some(free_images)
+}
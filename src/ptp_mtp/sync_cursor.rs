@@ -0,0 +1,149 @@
+// 增量同步 - 在NVS中记录每个存储已传输的最大对象句柄和拍摄时间，
+// 重启或重连后只取真正新拍的照片，不必重新枚举并比对全部对象
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::persist::KvStore;
+use crate::ptp_mtp::camera::PtpCamera;
+use crate::ptp_mtp::datetime::PtpDateTime;
+use crate::ptp_mtp::error::Error;
+
+/// 同步游标使用的NVS命名空间
+pub const NVS_NAMESPACE: &str = "sync_cursor";
+
+const CURSOR_KEY: &str = "sync_cursor";
+
+/// 无法解析的日期按"YYYYMMDDThhmmss"前缀比较
+const DATE_PREFIX_LEN: usize = 15;
+
+/// 一个存储的同步位置
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageCursor {
+    pub max_handle: u32,      // 已传输的最大对象句柄
+    pub capture_date: String, // 已传输对象中最晚的拍摄时间(PTP日期时间字符串)，未知时为空
+}
+
+/// 增量同步游标
+///
+/// 相机按拍摄顺序递增分配句柄，句柄大于游标的对象即为新对象；
+/// 换卡或格式化后相机会从头编号，此时改用拍摄时间判断
+pub struct SyncCursor {
+    store: Box<dyn KvStore>,
+    cursors: BTreeMap<u32, StorageCursor>, // 存储ID -> 同步位置
+}
+
+impl SyncCursor {
+    /// 读取上次保存的同步位置
+    pub fn open(store: Box<dyn KvStore>) -> Result<Self, Box<dyn StdError>> {
+        let cursors = match store.get(CURSOR_KEY)? {
+            Some(raw) => serde_json::from_slice(&raw).unwrap_or_else(|e| {
+                warn!("同步游标损坏，将重新同步全部对象: {}", e);
+                BTreeMap::new()
+            }),
+            None => BTreeMap::new(),
+        };
+        Ok(SyncCursor { store, cursors })
+    }
+
+    /// 存储的同步位置，从未同步过时返回None
+    pub fn get(&self, storage_id: u32) -> Option<&StorageCursor> {
+        self.cursors.get(&storage_id)
+    }
+
+    /// 对象是否在游标之后(尚未传输)
+    pub fn is_new(&self, storage_id: u32, handle: u32, capture_date: &str) -> bool {
+        match self.cursors.get(&storage_id) {
+            None => true,
+            Some(c) if handle > c.max_handle => true,
+            // 句柄不大于游标时只有拍摄时间更晚才算新对象(相机重新编号)
+            Some(c) => !c.capture_date.is_empty() && compare_dates(capture_date, &c.capture_date) == Ordering::Greater,
+        }
+    }
+
+    /// 记录对象已传输，推进游标并立即持久化
+    pub fn advance(&mut self, storage_id: u32, handle: u32, capture_date: &str) -> Result<(), Box<dyn StdError>> {
+        let cursor = self.cursors.entry(storage_id).or_default();
+        let mut changed = false;
+        if handle > cursor.max_handle {
+            cursor.max_handle = handle;
+            changed = true;
+        }
+        if !capture_date.is_empty() && compare_dates(capture_date, &cursor.capture_date) == Ordering::Greater {
+            cursor.capture_date = capture_date.to_string();
+            changed = true;
+        }
+        if changed {
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    /// 清除同步位置(None表示全部存储)，之后会重新传输全部对象
+    pub fn reset(&mut self, storage_id: Option<u32>) -> Result<(), Box<dyn StdError>> {
+        match storage_id {
+            Some(id) => {
+                self.cursors.remove(&id);
+            }
+            None => self.cursors.clear(),
+        }
+        self.persist()
+    }
+
+    /// 列出存储上游标之后的对象句柄(按句柄升序)
+    pub async fn new_objects(
+        &self,
+        camera: &mut PtpCamera,
+        storage_id: u32,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<u32>, Error> {
+        let mut handles = camera.get_objecthandles_all(storage_id, None, timeout).await?;
+        handles.sort_unstable();
+        let Some(cursor) = self.cursors.get(&storage_id) else {
+            return Ok(handles);
+        };
+
+        // 相机上的最大句柄比游标还小，说明换了卡或格式化过，句柄不可比，逐个按拍摄时间判断
+        if handles.last().is_some_and(|h| *h < cursor.max_handle) && !cursor.capture_date.is_empty() {
+            info!("存储 0x{:08x} 的对象已重新编号，按拍摄时间查找新对象", storage_id);
+            let mut fresh = Vec::new();
+            for handle in handles {
+                let info = camera.get_objectinfo(handle, timeout).await?;
+                if self.is_new(storage_id, handle, &info.CaptureDate) {
+                    fresh.push(handle);
+                }
+            }
+            return Ok(fresh);
+        }
+
+        let total = handles.len();
+        handles.retain(|h| *h > cursor.max_handle);
+        debug!("存储 0x{:08x}: {} 个对象中 {} 个为新对象", storage_id, total, handles.len());
+        Ok(handles)
+    }
+
+    fn persist(&mut self) -> Result<(), Box<dyn StdError>> {
+        if self.cursors.is_empty() {
+            self.store.remove(CURSOR_KEY)?;
+        } else {
+            self.store.set(CURSOR_KEY, &serde_json::to_vec(&self.cursors)?)?;
+        }
+        self.store.flush()
+    }
+}
+
+/// 比较两个PTP日期时间字符串，都能解析时按时间比较(考虑时区)，否则按字符串前缀比较
+fn compare_dates(a: &str, b: &str) -> Ordering {
+    match (PtpDateTime::parse_opt(a), PtpDateTime::parse_opt(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => date_key(a).cmp(date_key(b)),
+    }
+}
+
+fn date_key(date: &str) -> &str {
+    date.get(..DATE_PREFIX_LEN).unwrap_or(date)
+}
@@ -0,0 +1,198 @@
+// PTP事务追踪 - 可选地把最近N个命令/数据/响应容器(代码、事务ID、截断后的载荷)记录在环形缓冲区中，
+// 通过API或调试控制台取出，用于在现场诊断特定机型的协议问题
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::ptp_mtp::standard_codes::{PtpContainerType, StandardCommandCode};
+
+/// 默认记录的容器数
+pub const DEFAULT_TRACE_DEPTH: usize = 64;
+/// 默认每个容器保留的载荷字节数
+pub const DEFAULT_TRACE_PAYLOAD: usize = 64;
+
+/// 容器方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum TraceDirection {
+    Out, // 主机发往相机
+    In,  // 相机发往主机
+}
+
+/// 一个被记录的容器
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEntry {
+    pub seq: u64,                  // 记录序号，缓冲区回绕后仍连续递增
+    pub at_ms: u64,                // 相对追踪开始的时间
+    pub direction: TraceDirection,
+    pub kind: &'static str,        // 容器类型
+    pub code: u16,                 // 命令码或响应码
+    pub tid: u32,                  // 事务ID
+    pub length: usize,             // 载荷的完整长度
+    #[serde(serialize_with = "serialize_hex")]
+    pub payload: Vec<u8>,          // 截断后的载荷
+}
+
+fn serialize_hex<S: serde::Serializer>(payload: &[u8], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&hex(payload))
+}
+
+fn hex(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 3);
+    for (i, b) in data.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
+        }
+        let _ = write!(out, "{:02x}", b);
+    }
+    out
+}
+
+fn kind_name(kind: PtpContainerType) -> &'static str {
+    match kind {
+        PtpContainerType::Command => "command",
+        PtpContainerType::Data => "data",
+        PtpContainerType::Response => "response",
+        PtpContainerType::Event => "event",
+    }
+}
+
+/// 事务追踪器
+pub struct TransactionTracer {
+    enabled: bool,
+    depth: usize,
+    max_payload: usize,
+    entries: VecDeque<TraceEntry>,
+    next_seq: u64,
+    started: Instant,
+}
+
+pub type TraceHandle = Arc<Mutex<TransactionTracer>>;
+
+/// 创建未启用的追踪器
+pub fn handle(depth: usize, max_payload: usize) -> TraceHandle {
+    Arc::new(Mutex::new(TransactionTracer::new(depth, max_payload)))
+}
+
+impl TransactionTracer {
+    pub fn new(depth: usize, max_payload: usize) -> Self {
+        TransactionTracer {
+            enabled: false,
+            depth: depth.max(1),
+            max_payload,
+            entries: VecDeque::with_capacity(depth.max(1)),
+            next_seq: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// 开始或停止记录，已记录的容器保留
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 记录一个容器，未启用时忽略
+    pub fn record(&mut self, direction: TraceDirection, kind: PtpContainerType, code: u16, tid: u32, payload: &[u8]) {
+        if !self.enabled {
+            return;
+        }
+        if self.entries.len() >= self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry {
+            seq: self.next_seq,
+            at_ms: self.started.elapsed().as_millis() as u64,
+            direction,
+            kind: kind_name(kind),
+            code,
+            tid,
+            length: payload.len(),
+            payload: payload[..payload.len().min(self.max_payload)].to_vec(),
+        });
+        self.next_seq += 1;
+    }
+
+    /// 已记录的容器，从旧到新
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    /// 清空记录
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 最近`last`个容器的文本转储，每个容器一行
+    pub fn dump(&self, last: usize) -> String {
+        let skip = self.entries.len().saturating_sub(last);
+        let mut out = String::new();
+        for e in self.entries.iter().skip(skip) {
+            let arrow = match e.direction {
+                TraceDirection::Out => "->",
+                TraceDirection::In => "<-",
+            };
+            let name = match e.kind {
+                "command" | "data" => StandardCommandCode::name(e.code).unwrap_or(""),
+                _ => "",
+            };
+            let _ = write!(
+                out,
+                "#{} +{}ms {} {} 0x{:04x} {} tid={} len={}",
+                e.seq, e.at_ms, arrow, e.kind, e.code, name, e.tid, e.length
+            );
+            if !e.payload.is_empty() {
+                let _ = write!(out, " [{}]", hex(&e.payload));
+                if e.length > e.payload.len() {
+                    out.push_str(" ...");
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// 注册控制台命令 `ptptrace`
+pub fn register_console_command(console: &mut crate::console::Console, tracer: TraceHandle) {
+    console.register(
+        "ptptrace",
+        "PTP事务追踪: ptptrace on | ptptrace off | ptptrace clear | ptptrace [条数]",
+        Box::new(move |args| {
+            let mut tracer = tracer.lock().unwrap();
+            match args {
+                ["on"] => {
+                    tracer.set_enabled(true);
+                    "PTP事务追踪已开启".to_string()
+                }
+                ["off"] => {
+                    tracer.set_enabled(false);
+                    "PTP事务追踪已关闭".to_string()
+                }
+                ["clear"] => {
+                    tracer.clear();
+                    "已清空追踪记录".to_string()
+                }
+                [] | [_] => {
+                    let last = match args.first().map(|n| n.parse()) {
+                        Some(Ok(n)) => n,
+                        Some(Err(_)) => return format!("无效的条数: {}", args[0]),
+                        None => usize::MAX,
+                    };
+                    let dump = tracer.dump(last);
+                    if dump.is_empty() {
+                        "没有追踪记录".to_string()
+                    } else {
+                        dump
+                    }
+                }
+                _ => "用法: ptptrace on | ptptrace off | ptptrace clear | ptptrace [条数]".to_string(),
+            }
+        }),
+    );
+}
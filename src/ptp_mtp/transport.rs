@@ -0,0 +1,59 @@
+// PTP传输层抽象 - 相机与传输介质(USB、录制回放等)之间的最小接口
+use crate::ptp_mtp::error::Error;
+use crate::ptp_mtp::standard_codes::StandardResponseCode;
+
+/// 没有端点描述符时假定的包大小(高速批量端点)
+pub const DEFAULT_PACKET_SIZE: usize = 512;
+
+/// `PtpCamera`默认使用的传输层：ESP32上是USB链路，主机上是PTP/IP
+#[cfg(target_os = "espidf")]
+pub type DefaultTransport = crate::ptp_mtp::usb_transport::UsbCameraLink;
+/// `PtpCamera`默认使用的传输层：ESP32上是USB链路，主机上是PTP/IP
+#[cfg(not(target_os = "espidf"))]
+pub type DefaultTransport = crate::ptp_mtp::ip_transport::PtpIpTransport;
+
+/// PTP传输层
+///
+/// 只负责搬运字节：批量输出、批量输入和中断事件，容器的拼装与解析由 `PtpCamera` 完成。
+/// 超时参数为None时使用传输层自己的默认值，超时应返回 `Error::Timeout` 以便相机按重试策略重试
+#[allow(async_fn_in_trait)]
+pub trait PtpTransport {
+    /// 批量写入(主机到设备)，返回写入的字节数
+    async fn bulk_write(&mut self, data: &[u8], timeout_ms: Option<u64>) -> Result<usize, Error>;
+
+    /// 批量读取(设备到主机)，返回读取的字节数；读到短包或缓冲区满时返回
+    async fn bulk_read(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error>;
+
+    /// 读取中断端点上的事件，超时内没有事件时返回0
+    async fn read_interrupt_event(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error>;
+
+    /// 批量输出的包大小；数据阶段按它分块，容器长度是它的整数倍时补发零长度包
+    fn max_packet_size(&self) -> usize {
+        DEFAULT_PACKET_SIZE
+    }
+
+    /// USB设备的(VID, PID)，用于查找机型兼容表；不是USB链路时返回None
+    fn usb_id(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// 是否能接收事件(USB相机可能没有中断端点)
+    fn has_events(&self) -> bool {
+        true
+    }
+
+    /// 请求设备取消事务(USB静态图像类的Cancel请求)
+    async fn request_cancel(&mut self, _tid: u32) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// 读取设备状态(USB静态图像类的GetDeviceStatus请求)，返回响应码
+    async fn device_status(&mut self) -> Result<u16, Error> {
+        Ok(StandardResponseCode::Ok)
+    }
+
+    /// 断开时释放传输层占用的资源
+    async fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -1,14 +1,18 @@
 // PTP/MTP 协议的USB传输层实现
 use std::sync::Arc;
 use log::{error, debug, info, warn};
-use embassy_usb::host::{DeviceInfo, Device, Interface, UsbHostError, UsbHost};
+use embassy_usb::host::{DeviceInfo, Device, Interface, UsbDevice, UsbHostError, UsbHost};
 use embassy_futures::join::join;
 use embassy_time::{Duration, Timer};
 use embassy_sync::{mutex::Mutex, blocking_mutex::raw::NoopRawMutex};
 use esp_idf_svc::hal::usb::UsbHostDriver;
 
 use crate::usb_host::EspUsbHostController;
+use crate::usb_host::embassy::{create_embassy_usb_host, wait_for_usb_device};
+use crate::usb_host::filters::device_by_vid_pid;
+use crate::ptp_mtp::camera::PtpCamera;
 use crate::ptp_mtp::error::Error;
+use crate::ptp_mtp::transport::{PtpTransport, DEFAULT_PACKET_SIZE};
 
 // PTP协议常量
 const PTP_CLASS: u8 = 6;         // 图像类
@@ -18,6 +22,12 @@ const PTP_PROTOCOL: u8 = 1;      // 图片传输协议
 // 端点传输超时
 const EP_TRANSFER_TIMEOUT_MS: u64 = 5000;
 
+// PTP over USB的类请求：取消请求和读取设备状态
+const PTP_REQUEST_CANCEL: u8 = 0x64;
+const PTP_REQUEST_GET_DEVICE_STATUS: u8 = 0x67;
+// 取消请求数据中的取消码
+const PTP_CANCELLATION_CODE: u16 = 0x4001;
+
 /// PTP/MTP USB传输管理器
 /// 负责与USB设备的低级通信，为PTP/MTP协议提供传输层支持
 pub struct PtpUsbTransport {
@@ -124,11 +134,13 @@ impl PtpUsbTransport {
     
     /// 执行批量写入操作 (主机到设备)
     /// data - 要写入的数据
-    pub async fn bulk_write(&mut self, data: &[u8]) -> Result<usize, Error> {
+    /// timeout_ms - 超时时间 (毫秒)
+    pub async fn bulk_write(&mut self, data: &[u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
         let ep_addr = self.bulk_out_ep.ok_or("批量输出端点未配置")?;
+        let timeout = Duration::from_millis(timeout_ms.unwrap_or(EP_TRANSFER_TIMEOUT_MS));
         debug!("批量写入 {} 字节数据到端点 0x{:02x}", data.len(), ep_addr);
         
-        match self.interface.write_bulk(ep_addr, data, Duration::from_millis(EP_TRANSFER_TIMEOUT_MS)).await {
+        match self.interface.write_bulk(ep_addr, data, timeout).await {
             Ok(transferred) => {
                 debug!("成功写入 {} 字节", transferred);
                 Ok(transferred)
@@ -200,13 +212,14 @@ impl PtpUsbTransport {
     
     /// 从中断端点读取事件 (非阻塞)
     /// buffer - 事件数据缓冲区
-    pub async fn read_interrupt_event(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+    /// timeout_ms - 超时时间 (毫秒)，默认使用短超时以保持非阻塞特性
+    pub async fn read_interrupt_event(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
         let ep_addr = self.intr_ep.ok_or("中断端点未配置")?;
         
         match self.interface.read_interrupt(
             ep_addr,
             buffer,
-            Duration::from_millis(100) // 使用短超时以保持非阻塞特性
+            Duration::from_millis(timeout_ms.unwrap_or(100))
         ).await {
             Ok(transferred) => {
                 if transferred > 0 {
@@ -259,6 +272,28 @@ impl PtpUsbTransport {
     }
 }
 
+impl PtpTransport for PtpUsbTransport {
+    async fn bulk_write(&mut self, data: &[u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        PtpUsbTransport::bulk_write(self, data, timeout_ms).await
+    }
+    
+    async fn bulk_read(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        PtpUsbTransport::bulk_read(self, buffer, timeout_ms).await
+    }
+    
+    async fn read_interrupt_event(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        PtpUsbTransport::read_interrupt_event(self, buffer, timeout_ms).await
+    }
+    
+    fn has_events(&self) -> bool {
+        self.intr_ep.is_some()
+    }
+    
+    fn usb_id(&self) -> Option<(u16, u16)> {
+        Some((self.vendor_id, self.product_id))
+    }
+}
+
 /// 查找并打开PTP/MTP设备
 /// usb_host - USB主机控制器
 /// vendor_id - 可选的厂商ID过滤器
@@ -348,3 +383,167 @@ pub async fn monitor_ptp_devices(
         }
     }
 }
+
+/// 等待指定VID/PID的相机接入，打开其PTP/MTP接口并建立PtpCamera
+/// timeout_ms为None时一直等待
+pub async fn connect_camera(vendor_id: u16, product_id: u16, timeout_ms: Option<u64>) -> Result<PtpCamera, Error> {
+    let usb_host = create_embassy_usb_host().map_err(Error::USB)?;
+    let device_info = wait_for_usb_device(&usb_host, timeout_ms, device_by_vid_pid(vendor_id, product_id))
+        .await
+        .ok_or_else(|| Error::Timeout(format!("等待相机 {:04x}:{:04x} 接入超时", vendor_id, product_id)))?;
+    let transport = find_ptp_device(&usb_host, Some(vendor_id), Some(product_id)).await?;
+    PtpCamera::new(device_info.device(), transport).await
+}
+
+/// 把USB主机错误转换为PTP错误，超时单独区分以便重试
+fn usb_error(context: &str, e: UsbHostError) -> Error {
+    match e {
+        UsbHostError::Timeout => Error::Timeout(context.to_string()),
+        e => Error::USB(format!("{}: {:?}", context, e)),
+    }
+}
+
+/// 超时参数转换为Embassy Duration，None时使用默认的端点超时
+fn ep_timeout(timeout_ms: Option<u64>) -> Duration {
+    Duration::from_millis(timeout_ms.unwrap_or(EP_TRANSFER_TIMEOUT_MS))
+}
+
+/// PtpCamera使用的USB链路
+/// 批量和中断传输直接通过Embassy-USB设备句柄的端点进行，类请求(取消、设备状态)通过PtpUsbTransport的控制传输发送
+pub struct UsbCameraLink {
+    handle: UsbDevice<'static>, // Embassy-USB设备句柄
+    control: PtpUsbTransport,   // 控制传输
+    iface: u8,                  // 接口号
+    ep_in: u8,                  // 批量输入端点
+    ep_out: u8,                 // 批量输出端点
+    ep_int: u8,                 // 中断端点(事件)，0表示没有
+    out_packet_size: usize,     // 输出端点的最大包大小
+}
+
+impl UsbCameraLink {
+    /// 在设备的当前配置中查找PTP/MTP接口及其端点，并声明接口
+    pub async fn open(device: UsbDevice<'static>, control: PtpUsbTransport) -> Result<Self, Error> {
+        // 获取配置描述符
+        let config = device.current_config_descriptor().await;
+        
+        // 查找PTP/MTP接口（类代码为6）
+        let mut interface_number = 0;
+        let mut interface_found = false;
+        let mut ep_in = 0;
+        let mut ep_out = 0;
+        let mut out_packet_size = DEFAULT_PACKET_SIZE;
+        let mut ep_int = 0;
+        
+        // 遍历所有接口查找PTP/MTP接口
+        for iface in config.interfaces() {
+            for alt_setting in iface.alt_settings() {
+                if alt_setting.class_code() == PTP_CLASS {
+                    interface_number = iface.interface_number();
+                    interface_found = true;
+                    
+                    // 查找端点
+                    for endpoint in alt_setting.endpoints() {
+                        let addr = endpoint.address();
+                        
+                        // 根据端点类型和方向分配
+                        if endpoint.transfer_type() == embassy_usb::host::TransferType::Bulk {
+                            if endpoint.direction() == embassy_usb::host::Direction::In {
+                                ep_in = addr;
+                            } else {
+                                ep_out = addr;
+                                if endpoint.max_packet_size() > 0 {
+                                    out_packet_size = endpoint.max_packet_size() as usize;
+                                }
+                            }
+                        } else if endpoint.transfer_type() == embassy_usb::host::TransferType::Interrupt
+                                  && endpoint.direction() == embassy_usb::host::Direction::In {
+                            ep_int = addr;
+                        }
+                    }
+                    break;
+                }
+            }
+            if interface_found {
+                break;
+            }
+        }
+        
+        if !interface_found {
+            return Err(Error::NotFound("未找到PTP/MTP接口".into()));
+        }
+        
+        // 确保找到了必要的端点
+        if ep_in == 0 || ep_out == 0 {
+            return Err(Error::NotFound("未找到必要的端点".into()));
+        }
+        
+        // 声明接口
+        device.claim_interface(interface_number).await
+              .map_err(|e| Error::USB(format!("无法声明接口: {:?}", e)))?;
+        
+        debug!("已找到并声明PTP/MTP接口 {}", interface_number);
+        
+        Ok(UsbCameraLink {
+            handle: device,
+            control,
+            iface: interface_number,
+            ep_in,
+            ep_out,
+            ep_int,
+            out_packet_size,
+        })
+    }
+}
+
+impl PtpTransport for UsbCameraLink {
+    async fn bulk_write(&mut self, data: &[u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        self.handle.bulk_out(self.ep_out, data, ep_timeout(timeout_ms)).await
+            .map_err(|e| usb_error("批量写入失败", e))?;
+        Ok(data.len())
+    }
+    
+    async fn bulk_read(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        self.handle.bulk_in(self.ep_in, buffer, ep_timeout(timeout_ms)).await
+            .map_err(|e| usb_error("批量读取失败", e))
+    }
+    
+    async fn read_interrupt_event(&mut self, buffer: &mut [u8], timeout_ms: Option<u64>) -> Result<usize, Error> {
+        match self.handle.interrupt_in(self.ep_int, buffer, ep_timeout(timeout_ms)).await {
+            Ok(n) => Ok(n),
+            Err(UsbHostError::Timeout) => Ok(0),
+            Err(e) => Err(Error::USB(format!("中断读取失败: {:?}", e))),
+        }
+    }
+    
+    fn max_packet_size(&self) -> usize {
+        self.out_packet_size
+    }
+    
+    fn has_events(&self) -> bool {
+        self.ep_int != 0
+    }
+    
+    fn usb_id(&self) -> Option<(u16, u16)> {
+        Some((self.control.vendor_id(), self.control.product_id()))
+    }
+    
+    async fn request_cancel(&mut self, tid: u32) -> Result<(), Error> {
+        let mut request = [0u8; 6];
+        request[..2].copy_from_slice(&PTP_CANCELLATION_CODE.to_le_bytes());
+        request[2..].copy_from_slice(&tid.to_le_bytes());
+        self.control.control_transfer(0x21, PTP_REQUEST_CANCEL, 0, self.iface as u16, &mut request).await?;
+        Ok(())
+    }
+    
+    async fn device_status(&mut self) -> Result<u16, Error> {
+        // 设备状态: 长度(u16) + 响应码(u16) + 参数
+        let mut status = [0u8; 4];
+        self.control.control_transfer(0xA1, PTP_REQUEST_GET_DEVICE_STATUS, 0, self.iface as u16, &mut status).await?;
+        Ok(u16::from_le_bytes([status[2], status[3]]))
+    }
+    
+    async fn close(&mut self) -> Result<(), Error> {
+        self.handle.release_interface(self.iface).await
+            .map_err(|e| Error::USB(format!("无法释放接口: {:?}", e)))
+    }
+}
@@ -0,0 +1,135 @@
+// 任务运行时 - 统一各子系统创建后台任务的方式：每个任务是一个带名称、优先级和栈大小的FreeRTOS任务(std线程)，
+// 异步子系统在自己的任务中运行独立的embassy执行器；栈大小和优先级可以在配置中按任务名覆盖
+use std::error::Error;
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use embassy_executor::{Executor, Spawner};
+#[cfg(target_os = "espidf")]
+use esp_idf_hal::task::thread::ThreadSpawnConfiguration;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+/// 栈大小的下限，低于此值的覆盖配置会被拒绝
+pub const MIN_STACK_SIZE: usize = 3 * 1024;
+/// FreeRTOS可用的最高优先级(configMAX_PRIORITIES - 1)
+pub const MAX_PRIORITY: u8 = 24;
+
+/// 任务的创建参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskSpec {
+    pub name: &'static str, // FreeRTOS任务名，同时是配置中覆盖参数使用的名称
+    pub stack_size: usize,  // 栈大小(字节)
+    pub priority: u8,       // FreeRTOS优先级，ESP-IDF中pthread默认为5
+}
+
+/// 控制命令调度，优先级高于数据发送
+pub const CONTROL: TaskSpec = TaskSpec { name: "control", stack_size: 4 * 1024, priority: 10 };
+/// Webhook通知发送
+pub const WEBHOOK: TaskSpec = TaskSpec { name: "webhook", stack_size: 8 * 1024, priority: 5 };
+/// 实时指标推送
+pub const WS_METRICS: TaskSpec = TaskSpec { name: "ws-metrics", stack_size: 6 * 1024, priority: 4 };
+/// 取景画面和传输事件推送
+pub const WS_PUSH: TaskSpec = TaskSpec { name: "ws-push", stack_size: 6 * 1024, priority: 4 };
+/// 配网期间把所有域名解析到设备的DNS服务
+pub const CAPTIVE_DNS: TaskSpec = TaskSpec { name: "captive-dns", stack_size: 4 * 1024, priority: 5 };
+/// FTP服务端接受连接
+pub const FTP: TaskSpec = TaskSpec { name: "ftp", stack_size: 4 * 1024, priority: 5 };
+/// FTP会话，TLS握手需要较大的栈
+pub const FTP_SESSION: TaskSpec = TaskSpec { name: "ftp-session", stack_size: 10 * 1024, priority: 4 };
+/// 后台云端上传，HTTPS/S3请求需要较大的栈
+pub const CLOUD_UPLOAD: TaskSpec = TaskSpec { name: "cloud-upload", stack_size: 10 * 1024, priority: 3 };
+/// PTP会话保活
+pub const SESSION_GUARD: TaskSpec = TaskSpec { name: "session-guard", stack_size: 4 * 1024, priority: 4 };
+/// 串口调试控制台
+pub const CONSOLE: TaskSpec = TaskSpec { name: "console", stack_size: 6 * 1024, priority: 2 };
+/// 相机USB通信的embassy执行器
+pub const CAMERA: TaskSpec = TaskSpec { name: "camera", stack_size: 16 * 1024, priority: 6 };
+
+/// 配置中对某个任务参数的覆盖
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskOverride {
+    pub name: String, // 任务名
+    #[serde(default)]
+    pub stack_size: Option<usize>,
+    #[serde(default)]
+    pub priority: Option<u8>,
+}
+
+impl TaskOverride {
+    /// 校验参数范围
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.stack_size.is_some_and(|s| s < MIN_STACK_SIZE) {
+            return Err(format!("任务 {} 的栈大小不能小于 {} 字节", self.name, MIN_STACK_SIZE).into());
+        }
+        if self.priority.is_some_and(|p| p > MAX_PRIORITY) {
+            return Err(format!("任务 {} 的优先级不能超过 {}", self.name, MAX_PRIORITY).into());
+        }
+        Ok(())
+    }
+}
+
+static OVERRIDES: Mutex<Vec<TaskOverride>> = Mutex::new(Vec::new());
+
+/// 设置任务参数的覆盖，之后创建的任务生效
+pub fn configure(overrides: &[TaskOverride]) {
+    *OVERRIDES.lock().unwrap() = overrides.to_vec();
+}
+
+/// 应用配置中的覆盖后的任务参数
+pub fn resolve(spec: TaskSpec) -> TaskSpec {
+    let overrides = OVERRIDES.lock().unwrap();
+    match overrides.iter().find(|o| o.name == spec.name) {
+        Some(o) => TaskSpec {
+            stack_size: o.stack_size.unwrap_or(spec.stack_size),
+            priority: o.priority.unwrap_or(spec.priority),
+            ..spec
+        },
+        None => spec,
+    }
+}
+
+/// 创建后台任务
+pub fn spawn<F>(spec: TaskSpec, f: F) -> Result<JoinHandle<()>, Box<dyn Error>>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let spec = resolve(spec);
+    // FreeRTOS任务名需要以NUL结尾的静态字符串；任务数量固定，泄漏的名称只有几个字节
+    #[cfg(target_os = "espidf")]
+    {
+        let name: &'static [u8] = Box::leak(format!("{}\0", spec.name).into_bytes().into_boxed_slice());
+        ThreadSpawnConfiguration {
+            name: Some(name),
+            stack_size: spec.stack_size,
+            priority: spec.priority,
+            ..Default::default()
+        }
+        .set()?;
+    }
+    let handle = thread::Builder::new()
+        .name(spec.name.into())
+        .stack_size(spec.stack_size)
+        .spawn(f);
+    // 恢复默认参数，不影响之后直接创建的线程
+    #[cfg(target_os = "espidf")]
+    ThreadSpawnConfiguration::default().set()?;
+    debug!("已创建任务 {} (栈 {} 字节，优先级 {})", spec.name, spec.stack_size, spec.priority);
+    Ok(handle?)
+}
+
+/// 创建运行独立embassy执行器的任务，`init`在执行器中生成异步任务
+pub fn spawn_executor<F>(spec: TaskSpec, init: F) -> Result<JoinHandle<()>, Box<dyn Error>>
+where
+    F: FnOnce(Spawner) + Send + 'static,
+{
+    spawn(spec, move || run_executor(init))
+}
+
+/// 在当前任务中运行embassy执行器，不会返回
+pub fn run_executor<F: FnOnce(Spawner)>(init: F) -> ! {
+    // 执行器要求'static生命周期，且与运行它的任务同生命周期
+    let executor: &'static mut Executor = Box::leak(Box::new(Executor::new()));
+    info!("embassy执行器已在任务 {:?} 中启动", thread::current().name());
+    executor.run(init)
+}
@@ -0,0 +1,277 @@
+// 蓝牙分片协议 - 一次indication或写入只能携带(MTU-3)字节，较长的消息拆成多个分片，对端按序号重组并校验
+//
+// 分片头全部小端：
+//   flags  u8   FIRST(0x01)表示消息的第一个分片，LAST(0x02)表示最后一个分片，单分片消息两者都置位
+//   seq    u8   消息序号，每条消息加一(回绕)，同一消息的分片相同
+//   index  u16  分片在消息中的序号，从0开始
+// 第一个分片在分片头之后还带有：
+//   length u32  消息总长度
+//   crc32  u32  整条消息的CRC32(IEEE)
+// 分片必须按序到达(indication逐个确认，写入请求逐个应答)，乱序或校验失败时整条消息丢弃
+use std::error::Error;
+use std::fmt;
+
+use super::framing::crc32;
+
+/// 未协商MTU时的默认ATT MTU
+pub const DEFAULT_ATT_MTU: u16 = 23;
+/// 本机接受的最大ATT MTU
+pub const MAX_ATT_MTU: u16 = 517;
+/// ATT通知/写入的操作码和句柄占用的字节数
+const ATT_OVERHEAD: usize = 3;
+/// 分片头长度
+pub const HEADER_LEN: usize = 4;
+/// 第一个分片的分片头长度(含消息长度和CRC)
+pub const FIRST_HEADER_LEN: usize = HEADER_LEN + 8;
+/// 接收方向允许的最大消息长度，手机发来的只有命令，不需要很大
+pub const MAX_INCOMING_LEN: usize = 4096;
+
+const FLAG_FIRST: u8 = 0x01;
+const FLAG_LAST: u8 = 0x02;
+
+/// 把一条消息按`mtu`拆成分片
+pub fn fragment(seq: u8, data: &[u8], mtu: u16) -> Vec<Vec<u8>> {
+    let capacity = mtu.max(DEFAULT_ATT_MTU) as usize - ATT_OVERHEAD;
+    let first_len = (capacity - FIRST_HEADER_LEN).min(data.len());
+    let (first, rest) = data.split_at(first_len);
+    let mut chunks = vec![first];
+    chunks.extend(rest.chunks(capacity - HEADER_LEN));
+
+    let count = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut flags = 0;
+            if index == 0 {
+                flags |= FLAG_FIRST;
+            }
+            if index + 1 == count {
+                flags |= FLAG_LAST;
+            }
+            let mut fragment = Vec::with_capacity(FIRST_HEADER_LEN + chunk.len());
+            fragment.push(flags);
+            fragment.push(seq);
+            fragment.extend_from_slice(&(index as u16).to_le_bytes());
+            if index == 0 {
+                fragment.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                fragment.extend_from_slice(&crc32(data).to_le_bytes());
+            }
+            fragment.extend_from_slice(chunk);
+            fragment
+        })
+        .collect()
+}
+
+/// 分片重组错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FragmentError {
+    Truncated(usize),                          // 分片头不完整
+    TooLarge(usize),                           // 消息超过接收上限
+    Unexpected { seq: u8, index: u16 },        // 没有正在重组的消息，或消息序号不符
+    OutOfOrder { expected: u16, got: u16 },    // 分片序号不连续
+    LengthMismatch { expected: usize, got: usize },
+    CrcMismatch,
+}
+
+impl fmt::Display for FragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FragmentError::Truncated(n) => write!(f, "分片头不完整: {} 字节", n),
+            FragmentError::TooLarge(n) => write!(f, "消息过长: {} 字节", n),
+            FragmentError::Unexpected { seq, index } => write!(f, "意外的分片: 消息 {} 分片 {}", seq, index),
+            FragmentError::OutOfOrder { expected, got } => write!(f, "分片乱序: 期望 {}，收到 {}", expected, got),
+            FragmentError::LengthMismatch { expected, got } => {
+                write!(f, "消息长度不符: 声明 {} 字节，收到 {} 字节", expected, got)
+            }
+            FragmentError::CrcMismatch => write!(f, "消息CRC校验失败"),
+        }
+    }
+}
+
+impl Error for FragmentError {}
+
+/// 正在重组的消息
+#[derive(Debug, Clone)]
+struct Partial {
+    seq: u8,
+    next_index: u16,
+    length: usize,
+    crc32: u32,
+    data: Vec<u8>,
+}
+
+/// 按连接重组对端发来的分片
+#[derive(Debug, Clone)]
+pub struct Reassembler {
+    max_len: usize,
+    partial: Option<Partial>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(MAX_INCOMING_LEN)
+    }
+}
+
+impl Reassembler {
+    pub fn new(max_len: usize) -> Self {
+        Reassembler { max_len, partial: None }
+    }
+
+    /// 放入一个分片，消息完整时返回消息内容；出错时丢弃正在重组的消息
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>, FragmentError> {
+        let result = self.accept(fragment);
+        if result.is_err() {
+            self.partial = None;
+        }
+        result
+    }
+
+    fn accept(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>, FragmentError> {
+        if fragment.len() < HEADER_LEN {
+            return Err(FragmentError::Truncated(fragment.len()));
+        }
+        let (flags, seq) = (fragment[0], fragment[1]);
+        let index = u16::from_le_bytes([fragment[2], fragment[3]]);
+
+        let payload = if flags & FLAG_FIRST != 0 {
+            if fragment.len() < FIRST_HEADER_LEN {
+                return Err(FragmentError::Truncated(fragment.len()));
+            }
+            if index != 0 {
+                return Err(FragmentError::OutOfOrder { expected: 0, got: index });
+            }
+            let length = u32::from_le_bytes(fragment[4..8].try_into().unwrap()) as usize;
+            if length > self.max_len {
+                return Err(FragmentError::TooLarge(length));
+            }
+            // 新消息开始，之前未完成的消息作废
+            self.partial = Some(Partial {
+                seq,
+                next_index: 0,
+                length,
+                crc32: u32::from_le_bytes(fragment[8..12].try_into().unwrap()),
+                data: Vec::with_capacity(length),
+            });
+            &fragment[FIRST_HEADER_LEN..]
+        } else {
+            &fragment[HEADER_LEN..]
+        };
+
+        let partial = match self.partial.as_mut() {
+            Some(partial) if partial.seq == seq => partial,
+            _ => return Err(FragmentError::Unexpected { seq, index }),
+        };
+        if index != partial.next_index {
+            return Err(FragmentError::OutOfOrder {
+                expected: partial.next_index,
+                got: index,
+            });
+        }
+        if partial.data.len() + payload.len() > partial.length {
+            return Err(FragmentError::LengthMismatch {
+                expected: partial.length,
+                got: partial.data.len() + payload.len(),
+            });
+        }
+        partial.next_index = partial.next_index.wrapping_add(1);
+        partial.data.extend_from_slice(payload);
+
+        if flags & FLAG_LAST == 0 {
+            return Ok(None);
+        }
+        let partial = self.partial.take().unwrap();
+        if partial.data.len() != partial.length {
+            return Err(FragmentError::LengthMismatch {
+                expected: partial.length,
+                got: partial.data.len(),
+            });
+        }
+        if crc32(&partial.data) != partial.crc32 {
+            return Err(FragmentError::CrcMismatch);
+        }
+        Ok(Some(partial.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7) as u8).collect()
+    }
+
+    fn reassemble(fragments: &[Vec<u8>]) -> Result<Option<Vec<u8>>, FragmentError> {
+        let mut reassembler = Reassembler::default();
+        let mut result = Ok(None);
+        for fragment in fragments {
+            result = reassembler.push(fragment);
+        }
+        result
+    }
+
+    #[test]
+    fn round_trip_single_and_multiple_fragments() {
+        for len in [0, 1, 8, 9, 200, MAX_INCOMING_LEN] {
+            let data = message(len);
+            let fragments = fragment(3, &data, DEFAULT_ATT_MTU);
+            assert!(fragments.iter().all(|f| f.len() <= DEFAULT_ATT_MTU as usize - ATT_OVERHEAD));
+            assert_eq!(reassemble(&fragments), Ok(Some(data)), "长度 {}", len);
+        }
+    }
+
+    #[test]
+    fn larger_mtu_uses_fewer_fragments() {
+        let data = message(1000);
+        assert!(fragment(0, &data, MAX_ATT_MTU).len() < fragment(0, &data, DEFAULT_ATT_MTU).len());
+        assert_eq!(reassemble(&fragment(0, &data, MAX_ATT_MTU)), Ok(Some(data)));
+    }
+
+    #[test]
+    fn out_of_order_fragment_discards_message() {
+        let mut fragments = fragment(1, &message(100), DEFAULT_ATT_MTU);
+        fragments.swap(1, 2);
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        assert_eq!(reassembler.push(&fragments[1]), Err(FragmentError::OutOfOrder { expected: 1, got: 2 }));
+        // 消息已作废，后续分片没有对应的消息
+        assert!(matches!(reassembler.push(&fragments[2]), Err(FragmentError::Unexpected { .. })));
+    }
+
+    #[test]
+    fn corrupted_payload_fails_crc() {
+        let mut fragments = fragment(1, &message(100), DEFAULT_ATT_MTU);
+        let last = fragments.last_mut().unwrap();
+        *last.last_mut().unwrap() ^= 0xFF;
+        assert_eq!(reassemble(&fragments), Err(FragmentError::CrcMismatch));
+    }
+
+    #[test]
+    fn rejects_oversized_truncated_and_foreign_fragments() {
+        let mut reassembler = Reassembler::new(16);
+        let fragments = fragment(0, &message(17), MAX_ATT_MTU);
+        assert_eq!(reassembler.push(&fragments[0]), Err(FragmentError::TooLarge(17)));
+        assert_eq!(reassembler.push(&[FLAG_FIRST, 0]), Err(FragmentError::Truncated(2)));
+
+        let fragments = fragment(5, &message(40), DEFAULT_ATT_MTU);
+        let other = fragment(6, &message(40), DEFAULT_ATT_MTU);
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        assert_eq!(reassembler.push(&other[1]), Err(FragmentError::Unexpected { seq: 6, index: 1 }));
+    }
+
+    #[test]
+    fn new_first_fragment_restarts_reassembly() {
+        let abandoned = fragment(1, &message(100), DEFAULT_ATT_MTU);
+        let data = message(30);
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.push(&abandoned[0]), Ok(None));
+        let mut result = Ok(None);
+        for f in fragment(2, &data, DEFAULT_ATT_MTU) {
+            result = reassembler.push(&f);
+        }
+        assert_eq!(result, Ok(Some(data)));
+    }
+}
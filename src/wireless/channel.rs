@@ -0,0 +1,248 @@
+// WiFi信道选择 - SoftAP启动前扫描周边接入点，选择最不拥挤的2.4GHz信道；
+// 场馆等拥挤环境中默认信道常常不可用，链路持续丢包时还可以重新扫描并切换信道
+use std::time::{Duration, Instant};
+
+use embedded_svc::wifi::{AccessPointConfiguration, AccessPointInfo, AuthMethod, ClientConfiguration, Configuration};
+use esp_idf_svc::wifi::EspWifi;
+use log::{debug, info};
+
+use super::sta;
+use crate::config::{ApAddress, UpstreamWifi};
+
+/// 候选信道上限：12、13信道在部分地区不可用，手机可能搜不到
+const MAX_CHANNEL: u8 = 11;
+/// 2.4GHz相邻信道的频谱重叠范围(信道差小于此值时互相干扰)
+const OVERLAP: u8 = 5;
+/// 两次切换信道的最小间隔，避免来回切换导致客户端频繁重连
+const HOP_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// 扫描到的一个接入点
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApSample {
+    pub channel: u8,
+    pub rssi: i8,
+}
+
+/// 计算每个候选信道的拥挤程度：按信号强度和频谱重叠程度累加周边接入点的干扰
+pub fn congestion(samples: &[ApSample], channel: u8) -> u32 {
+    samples
+        .iter()
+        .filter_map(|ap| {
+            let distance = ap.channel.abs_diff(channel);
+            (distance < OVERLAP).then(|| {
+                // -100dBm以下视为没有干扰，重叠越多权重越大
+                let strength = (ap.rssi as i32 + 100).max(0) as u32;
+                strength * (OVERLAP - distance) as u32
+            })
+        })
+        .sum()
+}
+
+/// 选择最不拥挤的信道，`exclude`为当前信道(切换时不再选回)；拥挤程度相同时优先1/6/11
+pub fn pick_channel(samples: &[ApSample], exclude: Option<u8>) -> u8 {
+    (1..=MAX_CHANNEL)
+        .filter(|c| Some(*c) != exclude)
+        .min_by_key(|c| (congestion(samples, *c), ![1, 6, 11].contains(c), *c))
+        .unwrap_or(1)
+}
+
+/// 扫描周边接入点，需要WiFi已在STA或AP+STA模式下启动
+pub fn scan(wifi: &mut EspWifi<'static>) -> Result<Vec<ApSample>, Box<dyn std::error::Error>> {
+    let samples: Vec<ApSample> = wifi
+        .scan()?
+        .iter()
+        .map(|ap| ApSample {
+            channel: ap.channel,
+            rssi: ap.signal_strength,
+        })
+        .collect();
+    debug!("扫描到 {} 个接入点", samples.len());
+    Ok(samples)
+}
+
+/// 扫描到的WiFi网络，供手机应用配网时选择
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedNetwork {
+    pub ssid: String,
+    pub rssi: i8,
+    pub channel: u8,
+    pub auth_method: AuthMethod, // AuthMethod::None表示开放网络
+}
+
+/// 扫描结束后如何恢复WiFi的状态
+pub enum ScanRestore {
+    Keep,                       // 扫描前已可扫描(STA或AP+STA模式)
+    Stop,                       // 扫描前WiFi未启动
+    Reconfigure(Configuration), // 扫描前是纯AP模式，临时切到了AP+STA模式
+}
+
+/// 让WiFi进入可扫描的状态：未启动时以STA模式启动，纯AP模式时临时切到AP+STA模式(SoftAP客户端可能短暂断开)
+pub fn prepare_scan(wifi: &mut EspWifi<'static>) -> Result<ScanRestore, Box<dyn std::error::Error>> {
+    if !wifi.is_started()? {
+        wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
+        wifi.start()?;
+        return Ok(ScanRestore::Stop);
+    }
+    match wifi.get_configuration()? {
+        Configuration::AccessPoint(ap) => {
+            wifi.set_configuration(&Configuration::Mixed(ClientConfiguration::default(), ap.clone()))?;
+            Ok(ScanRestore::Reconfigure(Configuration::AccessPoint(ap)))
+        }
+        _ => Ok(ScanRestore::Keep),
+    }
+}
+
+/// 扫描结束后恢复WiFi的状态
+pub fn finish_scan(wifi: &mut EspWifi<'static>, restore: ScanRestore) -> Result<(), Box<dyn std::error::Error>> {
+    match restore {
+        ScanRestore::Keep => {}
+        ScanRestore::Stop => wifi.stop()?,
+        ScanRestore::Reconfigure(configuration) => wifi.set_configuration(&configuration)?,
+    }
+    Ok(())
+}
+
+/// 整理扫描结果：忽略隐藏网络，同名网络只保留信号最强的接入点，按信号强度从强到弱排列
+pub fn collect_networks(infos: &[AccessPointInfo]) -> Vec<ScannedNetwork> {
+    let mut networks: Vec<ScannedNetwork> = Vec::new();
+    for info in infos.iter().filter(|info| !info.ssid.is_empty()) {
+        let network = ScannedNetwork {
+            ssid: info.ssid.to_string(),
+            rssi: info.signal_strength,
+            channel: info.channel,
+            auth_method: info.auth_method.unwrap_or(AuthMethod::None),
+        };
+        match networks.iter_mut().find(|n| n.ssid == network.ssid) {
+            Some(existing) if existing.rssi < network.rssi => *existing = network,
+            Some(_) => {}
+            None => networks.push(network),
+        }
+    }
+    networks.sort_by(|a, b| b.rssi.cmp(&a.rssi));
+    networks
+}
+
+/// SoftAP的名称、密码和同时连接的客户端上限
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SoftApSettings {
+    pub ssid: String,
+    pub password: String, // 为空表示开放网络
+    pub max_clients: u16,
+    pub address: Option<ApAddress>, // SoftAP的地址和DHCP地址池，None表示默认网段
+}
+
+impl SoftApSettings {
+    fn configuration(&self, channel: u8) -> Result<AccessPointConfiguration, Box<dyn std::error::Error>> {
+        Ok(AccessPointConfiguration {
+            ssid: self.ssid.as_str().try_into().map_err(|_| "SSID过长")?,
+            password: self.password.as_str().try_into().map_err(|_| "密码过长")?,
+            auth_method: if self.password.is_empty() { AuthMethod::None } else { AuthMethod::WPA2Personal },
+            channel,
+            max_connections: self.max_clients,
+            ..Default::default()
+        })
+    }
+}
+
+/// 以纯AP模式启动SoftAP，返回使用的信道
+/// `channel`为None时先临时切到AP+STA模式(STA接口不连接)扫描并选择最不拥挤的信道，`exclude`为切换前的信道
+pub fn start_soft_ap(
+    wifi: &mut EspWifi<'static>,
+    ap: &SoftApSettings,
+    channel: Option<u8>,
+    exclude: Option<u8>,
+) -> Result<u8, Box<dyn std::error::Error>> {
+    let channel = match channel {
+        Some(channel) => channel,
+        None => {
+            wifi.set_configuration(&Configuration::Mixed(
+                ClientConfiguration::default(),
+                ap.configuration(exclude.unwrap_or(1))?,
+            ))?;
+            if !wifi.is_started()? {
+                wifi.start()?;
+            }
+            let samples = scan(wifi)?;
+            let channel = pick_channel(&samples, exclude);
+            info!(
+                "信道扫描: {} 个接入点，选择信道 {} (拥挤度 {})",
+                samples.len(),
+                channel,
+                congestion(&samples, channel)
+            );
+            wifi.stop()?;
+            channel
+        }
+    };
+    wifi.set_configuration(&Configuration::AccessPoint(ap.configuration(channel)?))?;
+    wifi.start()?;
+    info!("SoftAP {} 已在信道 {} 上启动，最多 {} 个客户端", ap.ssid, channel, ap.max_clients);
+    Ok(channel)
+}
+
+/// 以AP+STA模式启动：STA接口连接已有网络，SoftAP同时供手机直连，返回使用的信道
+/// ESP32只有一个射频，SoftAP只能跟随上级网络的信道，不能自选或切换
+pub fn start_ap_sta(
+    wifi: &mut EspWifi<'static>,
+    ap: &SoftApSettings,
+    upstream: &UpstreamWifi,
+) -> Result<u8, Box<dyn std::error::Error>> {
+    let client = sta::client_configuration(upstream)?;
+    // SoftAP的信道在STA连接后被上级网络的信道覆盖
+    wifi.set_configuration(&Configuration::Mixed(client, ap.configuration(1)?))?;
+    sta::configure_enterprise(upstream)?;
+    wifi.start()?;
+    wifi.connect()?;
+    let channel = current_channel().unwrap_or(1);
+    info!("已连接到WiFi网络 {}，SoftAP {} 跟随信道 {}", upstream.ssid, ap.ssid, channel);
+    Ok(channel)
+}
+
+/// 当前SoftAP的主信道，WiFi未启动时返回None
+pub fn current_channel() -> Option<u8> {
+    let mut primary = 0u8;
+    let mut second = esp_idf_svc::sys::wifi_second_chan_t_WIFI_SECOND_CHAN_NONE;
+    let err = unsafe { esp_idf_svc::sys::esp_wifi_get_channel(&mut primary, &mut second) };
+    (err == esp_idf_svc::sys::ESP_OK && primary != 0).then_some(primary)
+}
+
+/// 持续丢包检测：连续`windows`次采样的丢包率都不低于阈值时建议切换信道
+pub struct LossHopper {
+    threshold_percent: u8,
+    windows: u32,
+    consecutive: u32,
+    last_hop: Option<Instant>,
+}
+
+impl LossHopper {
+    pub fn new(threshold_percent: u8, windows: u32) -> Self {
+        LossHopper {
+            threshold_percent,
+            windows: windows.max(1),
+            consecutive: 0,
+            last_hop: None,
+        }
+    }
+
+    /// 记录一次丢包率采样，返回是否应该切换信道
+    pub fn record(&mut self, loss_percent: u8) -> bool {
+        if loss_percent < self.threshold_percent {
+            self.consecutive = 0;
+            return false;
+        }
+        self.consecutive += 1;
+        if self.consecutive < self.windows || self.last_hop.is_some_and(|t| t.elapsed() < HOP_COOLDOWN) {
+            return false;
+        }
+        self.consecutive = 0;
+        self.last_hop = Some(Instant::now());
+        true
+    }
+}
+
+impl Default for LossHopper {
+    /// 连续5次采样丢包率不低于20%时切换
+    fn default() -> Self {
+        LossHopper::new(20, 5)
+    }
+}
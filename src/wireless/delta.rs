@@ -0,0 +1,72 @@
+// 增量同步的HTTP目标 - 把清单POST到目标端，目标端回复已有对象的摘要
+use std::error::Error;
+use std::time::Duration;
+
+use embedded_svc::http::client::Client;
+use embedded_svc::http::Method;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+use log::debug;
+
+use crate::data_transfer::delta::{DeltaTarget, Manifest, TargetInventory};
+
+/// 通过HTTP交换清单的同步目标
+pub struct HttpDeltaTarget {
+    url: String,
+    authorization: Option<String>,
+    timeout: Duration,
+}
+
+impl HttpDeltaTarget {
+    /// 创建同步目标
+    pub fn new(url: &str, authorization: Option<String>, timeout: Duration) -> Self {
+        HttpDeltaTarget {
+            url: url.to_string(),
+            authorization,
+            timeout,
+        }
+    }
+}
+
+impl DeltaTarget for HttpDeltaTarget {
+    fn exchange_manifest(&mut self, manifest: &Manifest) -> Result<TargetInventory, Box<dyn Error>> {
+        let body = serde_json::to_vec(manifest)?;
+        let connection = EspHttpConnection::new(&HttpConfiguration {
+            timeout: Some(self.timeout),
+            ..Default::default()
+        })?;
+        let mut client = Client::wrap(connection);
+
+        let content_length = body.len().to_string();
+        let mut headers = vec![
+            ("Content-Type", "application/json"),
+            ("Content-Length", content_length.as_str()),
+        ];
+        if let Some(auth) = &self.authorization {
+            headers.push(("Authorization", auth.as_str()));
+        }
+
+        let mut request = client.request(Method::Post, &self.url, &headers)?;
+        request.write_all(&body)?;
+        request.flush()?;
+        let mut response = request.submit()?;
+
+        let status = response.status();
+        if !(200..300).contains(&status) {
+            return Err(format!("HTTP状态码 {}", status).into());
+        }
+
+        let mut reply = Vec::new();
+        let mut buf = [0u8; 512];
+        loop {
+            let n = response.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            reply.extend_from_slice(&buf[..n]);
+        }
+        let inventory: TargetInventory = serde_json::from_slice(&reply)?;
+        debug!("目标端已有 {} 个对象", inventory.have.len());
+        Ok(inventory)
+    }
+}
@@ -0,0 +1,425 @@
+// ESP-NOW中继 - 多台ESP32之间不经过WiFi网络直接收发数据包，例如每台相机一个节点，全部汇总到一个网关
+//
+// ESP-NOW一帧最多携带250字节，数据包拆成分片发送。分片头全部小端：
+//   magic  u8   0xE5，过滤同一信道上的其他ESP-NOW流量
+//   type   u8   数据包类型编号(PacketType::code)
+//   msg_id u16  消息序号，每个数据包加一(回绕)
+//   index  u16  分片序号，从0开始
+//   count  u16  分片总数
+// 单播帧由对端在MAC层确认，发送回调报告失败或超时时重发该分片，超过重试次数则整个数据包发送失败。
+// 重发可能让对端收到重复的分片，接收端按(节点, 消息序号)重组，并忽略刚完成的消息的重复分片。
+// 所有节点必须在同一信道上；传输不加密，只适合封闭场地内的设备之间
+// 分片和重组不依赖ESP-IDF，主机上的单元测试只编译这一部分
+use std::collections::HashMap;
+use std::error::Error;
+#[cfg(feature = "espnow")]
+use std::sync::mpsc::{self, Receiver, TrySendError};
+#[cfg(feature = "espnow")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "espnow")]
+use embassy_time::{Duration as EmbassyDuration, Timer};
+#[cfg(feature = "espnow")]
+use esp_idf_svc::espnow::{EspNow, PeerInfo, ReceiveInfo, SendStatus};
+#[cfg(feature = "espnow")]
+use esp_idf_svc::sys::{wifi_interface_t, wifi_interface_t_WIFI_IF_AP, wifi_interface_t_WIFI_IF_STA};
+#[cfg(feature = "espnow")]
+use log::{debug, info};
+use log::warn;
+
+#[cfg(feature = "espnow")]
+use super::{DataSender, SenderFuture, SenderState};
+#[cfg(feature = "espnow")]
+use crate::config::{parse_mac, EspNowConfig, EspNowRole};
+use crate::ptp_mtp::{DataPacket, PacketType};
+
+/// 在TransferManager中登记的客户端ID(节点发往网关)
+pub const ESPNOW_CLIENT_ID: &str = "espnow-gateway";
+/// 未配置时分片的重试次数
+pub const DEFAULT_RETRIES: u8 = 3;
+/// ESP-NOW一帧的最大字节数
+pub const MAX_FRAME_LEN: usize = 250;
+/// 分片头长度
+pub const HEADER_LEN: usize = 8;
+/// 单个数据包的最大长度，对象分块最大64KiB，留出余量
+pub const MAX_MESSAGE_LEN: usize = 128 * 1024;
+/// 分片头的标识
+const MAGIC: u8 = 0xE5;
+/// 等待发送回调的超时
+#[cfg(feature = "espnow")]
+const SEND_TIMEOUT: Duration = Duration::from_millis(100);
+/// 重试前的等待时间，按重试次数递增
+#[cfg(feature = "espnow")]
+const RETRY_BACKOFF_MS: u64 = 20;
+/// 未完成的消息超过此时间没有新分片时丢弃
+const PARTIAL_TIMEOUT: Duration = Duration::from_secs(5);
+/// 同时重组的消息上限，超过时丢弃最旧的
+const MAX_PARTIALS: usize = 8;
+/// 重组完成、等待主循环取走的数据包上限
+#[cfg(feature = "espnow")]
+const INCOMING_PACKETS: usize = 8;
+
+/// 按常见格式显示MAC地址
+pub fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+/// 把数据包拆成分片
+pub fn fragment(msg_id: u16, packet: &DataPacket) -> Result<Vec<Vec<u8>>, Box<dyn Error>> {
+    if packet.data.len() > MAX_MESSAGE_LEN {
+        return Err(format!("数据包过长，不能经ESP-NOW发送: {} 字节", packet.data.len()).into());
+    }
+    let chunks: Vec<&[u8]> = if packet.data.is_empty() {
+        vec![&[]]
+    } else {
+        packet.data.chunks(MAX_FRAME_LEN - HEADER_LEN).collect()
+    };
+    let count = chunks.len() as u16;
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut frame = Vec::with_capacity(HEADER_LEN + chunk.len());
+            frame.push(MAGIC);
+            frame.push(packet.packet_type.code());
+            frame.extend_from_slice(&msg_id.to_le_bytes());
+            frame.extend_from_slice(&(index as u16).to_le_bytes());
+            frame.extend_from_slice(&count.to_le_bytes());
+            frame.extend_from_slice(chunk);
+            frame
+        })
+        .collect())
+}
+
+/// 正在重组的消息
+struct Partial {
+    packet_type: PacketType,
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    updated: Instant,
+}
+
+/// 按节点重组收到的分片，分片可以乱序或重复
+#[derive(Default)]
+pub struct Reassembler {
+    partials: HashMap<([u8; 6], u16), Partial>,
+    completed: HashMap<[u8; 6], u16>, // 各节点最近完成的消息序号
+}
+
+impl Reassembler {
+    /// 放入一个分片，消息完整时返回数据包；不是本协议的帧或分片头无效时返回错误
+    pub fn push(&mut self, peer: [u8; 6], frame: &[u8]) -> Result<Option<DataPacket>, Box<dyn Error>> {
+        if frame.len() < HEADER_LEN || frame[0] != MAGIC {
+            return Err("不是中继分片".into());
+        }
+        let packet_type = PacketType::from_code(frame[1]).ok_or_else(|| format!("未知的数据包类型 {}", frame[1]))?;
+        let msg_id = u16::from_le_bytes([frame[2], frame[3]]);
+        let index = u16::from_le_bytes([frame[4], frame[5]]) as usize;
+        let count = u16::from_le_bytes([frame[6], frame[7]]) as usize;
+        if index >= count || count > MAX_MESSAGE_LEN.div_ceil(MAX_FRAME_LEN - HEADER_LEN) {
+            return Err(format!("无效的分片 {}/{}", index, count).into());
+        }
+        if self.completed.get(&peer) == Some(&msg_id) {
+            // 对端没收到确认而重发的分片
+            return Ok(None);
+        }
+
+        let now = Instant::now();
+        if !self.partials.contains_key(&(peer, msg_id)) {
+            self.evict(now);
+        }
+        let partial = self.partials.entry((peer, msg_id)).or_insert_with(|| Partial {
+            packet_type,
+            fragments: vec![None; count],
+            received: 0,
+            updated: now,
+        });
+        if partial.fragments.len() != count || partial.packet_type != packet_type {
+            self.partials.remove(&(peer, msg_id));
+            return Err(format!("消息 {} 的分片头不一致", msg_id).into());
+        }
+        partial.updated = now;
+        if partial.fragments[index].is_none() {
+            partial.fragments[index] = Some(frame[HEADER_LEN..].to_vec());
+            partial.received += 1;
+        }
+        if partial.received < count {
+            return Ok(None);
+        }
+
+        let partial = self.partials.remove(&(peer, msg_id)).unwrap();
+        self.completed.insert(peer, msg_id);
+        let data = partial.fragments.into_iter().flatten().flatten().collect();
+        Ok(Some(DataPacket::new(partial.packet_type, data)))
+    }
+
+    /// 丢弃超时的消息，仍然太多时丢弃最旧的
+    fn evict(&mut self, now: Instant) {
+        self.partials.retain(|(peer, msg_id), partial| {
+            let alive = now.duration_since(partial.updated) < PARTIAL_TIMEOUT;
+            if !alive {
+                warn!("来自 {} 的消息 {} 未收齐 ({}/{})，已丢弃", format_mac(peer), msg_id, partial.received, partial.fragments.len());
+            }
+            alive
+        });
+        while self.partials.len() >= MAX_PARTIALS {
+            let oldest = *self.partials.iter().min_by_key(|(_, p)| p.updated).map(|(key, _)| key).unwrap();
+            self.partials.remove(&oldest);
+            warn!("同时重组的消息过多，丢弃来自 {} 的消息 {}", format_mac(&oldest.0), oldest.1);
+        }
+    }
+}
+
+/// 添加单播对端，已存在时不做任何事；信道0表示使用WiFi当前的信道
+#[cfg(feature = "espnow")]
+fn add_peer(espnow: &EspNow<'static>, interface: wifi_interface_t, mac: [u8; 6]) -> Result<(), Box<dyn Error>> {
+    if espnow.peer_exists(mac)? {
+        return Ok(());
+    }
+    espnow.add_peer(PeerInfo {
+        peer_addr: mac,
+        channel: 0,
+        ifidx: interface,
+        encrypt: false,
+        ..Default::default()
+    })?;
+    info!("已添加ESP-NOW对端 {}", format_mac(&mac));
+    Ok(())
+}
+
+/// ESP-NOW链路：注册收发回调并管理对端
+#[cfg(feature = "espnow")]
+pub struct EspNowLink {
+    espnow: Arc<EspNow<'static>>,
+    interface: wifi_interface_t,
+    status: Option<Receiver<bool>>,
+    incoming: Receiver<DataPacket>,
+}
+
+#[cfg(feature = "espnow")]
+impl EspNowLink {
+    /// 在已启动的WiFi上开启ESP-NOW；`softap`表示本机工作在AP模式，对端通过SoftAP接口通信
+    pub fn start(config: &EspNowConfig, softap: bool) -> Result<Self, Box<dyn Error>> {
+        config.validate()?;
+        let interface = if softap { wifi_interface_t_WIFI_IF_AP } else { wifi_interface_t_WIFI_IF_STA };
+        let espnow = EspNow::take()?;
+
+        // 发送结果由WiFi任务回调，交给正在等待的发送器
+        let (status_tx, status_rx) = mpsc::sync_channel(1);
+        espnow.register_send_cb(move |_mac: &[u8], status: SendStatus| {
+            let _ = status_tx.try_send(matches!(status, SendStatus::SUCCESS));
+        })?;
+
+        // 网关和开启中继的节点接收其他节点的数据包，在回调中直接重组，避免缓存大量分片
+        let (packet_tx, packet_rx) = mpsc::sync_channel(INCOMING_PACKETS);
+        if config.role == EspNowRole::Gateway || config.relay {
+            let allowed = config.peers.iter().map(|p| parse_mac(p)).collect::<Result<Vec<_>, _>>()?;
+            let mut reassembler = Reassembler::default();
+            espnow.register_recv_cb(move |info: &ReceiveInfo, data: &[u8]| {
+                let peer = *info.src_addr;
+                if !allowed.is_empty() && !allowed.contains(&peer) {
+                    return;
+                }
+                match reassembler.push(peer, data) {
+                    Ok(Some(packet)) => {
+                        if let Err(TrySendError::Full(packet)) = packet_tx.try_send(packet) {
+                            warn!("主循环未及时处理，丢弃来自 {} 的 {:?} 数据包", format_mac(&peer), packet.packet_type);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => debug!("忽略来自 {} 的ESP-NOW帧: {}", format_mac(&peer), e),
+                }
+            })?;
+        }
+        info!("ESP-NOW已开启，角色: {:?}，中继: {}", config.role, config.relay);
+        Ok(EspNowLink {
+            espnow: Arc::new(espnow),
+            interface,
+            status: Some(status_rx),
+            incoming: packet_rx,
+        })
+    }
+
+    /// 添加对端
+    pub fn add_peer(&self, mac: [u8; 6]) -> Result<(), Box<dyn Error>> {
+        add_peer(&self.espnow, self.interface, mac)
+    }
+
+    /// 移除对端，不存在时不做任何事
+    pub fn remove_peer(&self, mac: [u8; 6]) -> Result<(), Box<dyn Error>> {
+        if self.espnow.peer_exists(mac)? {
+            self.espnow.del_peer(mac)?;
+            info!("已移除ESP-NOW对端 {}", format_mac(&mac));
+        }
+        Ok(())
+    }
+
+    /// 创建发往网关的发送器；发送回调只有一个，因此只能创建一次
+    pub fn sender(&mut self, gateway: [u8; 6], retries: u8) -> Result<EspNowSender, Box<dyn Error>> {
+        let status = self.status.take().ok_or("ESP-NOW发送器已经创建")?;
+        self.add_peer(gateway)?;
+        // 序号从随机值开始，重启后网关不会把新消息当作重复
+        let seed = uuid::Uuid::new_v4();
+        Ok(EspNowSender {
+            espnow: self.espnow.clone(),
+            interface: self.interface,
+            gateway,
+            status,
+            retries,
+            msg_id: u16::from_le_bytes([seed.as_bytes()[0], seed.as_bytes()[1]]),
+            failed: false,
+        })
+    }
+
+    /// 取出其他节点发来的完整数据包，由主循环定期调用
+    pub fn poll(&self) -> Vec<DataPacket> {
+        self.incoming.try_iter().collect()
+    }
+}
+
+/// 节点把数据包经ESP-NOW发给网关
+#[cfg(feature = "espnow")]
+pub struct EspNowSender {
+    espnow: Arc<EspNow<'static>>,
+    interface: wifi_interface_t,
+    gateway: [u8; 6],
+    status: Receiver<bool>,
+    retries: u8,
+    msg_id: u16,
+    failed: bool, // 最近一个数据包重试后仍未送达，视为与网关断开
+}
+
+#[cfg(feature = "espnow")]
+impl EspNowSender {
+    /// 发送一个分片，直到网关确认或用完重试次数
+    async fn send_frame(&mut self, frame: &[u8]) -> Result<(), Box<dyn Error>> {
+        for attempt in 0..=self.retries {
+            if attempt > 0 {
+                Timer::after(EmbassyDuration::from_millis(RETRY_BACKOFF_MS * attempt as u64)).await;
+            }
+            // 清掉上次超时后迟到的发送结果
+            while self.status.try_recv().is_ok() {}
+            if let Err(e) = self.espnow.send(self.gateway, frame) {
+                debug!("ESP-NOW发送队列已满或出错: {}", e);
+                continue;
+            }
+            match self.status.recv_timeout(SEND_TIMEOUT) {
+                Ok(true) => return Ok(()),
+                Ok(false) => debug!("网关 {} 未确认分片(第 {} 次)", format_mac(&self.gateway), attempt + 1),
+                Err(_) => debug!("等待ESP-NOW发送结果超时(第 {} 次)", attempt + 1),
+            }
+        }
+        Err(format!("网关 {} 未确认分片，已重试 {} 次", format_mac(&self.gateway), self.retries).into())
+    }
+}
+
+#[cfg(feature = "espnow")]
+impl DataSender for EspNowSender {
+    fn connect(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            add_peer(&self.espnow, self.interface, self.gateway)?;
+            self.failed = false;
+            Ok(())
+        })
+    }
+
+    fn state(&self) -> SenderState {
+        if self.failed || !self.espnow.peer_exists(self.gateway).unwrap_or(false) {
+            SenderState::Disconnected
+        } else {
+            SenderState::Ready
+        }
+    }
+
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move {
+            let msg_id = self.msg_id;
+            self.msg_id = self.msg_id.wrapping_add(1);
+            for frame in fragment(msg_id, packet)? {
+                if let Err(e) = self.send_frame(&frame).await {
+                    self.failed = true;
+                    return Err(e);
+                }
+            }
+            Ok(packet.data.len())
+        })
+    }
+
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            if self.espnow.peer_exists(self.gateway)? {
+                self.espnow.del_peer(self.gateway)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: [u8; 6] = [0x24, 0x6F, 0x28, 0x01, 0x02, 0x03];
+
+    fn packet(len: usize) -> DataPacket {
+        DataPacket::new(PacketType::Image, (0..len).map(|i| i as u8).collect())
+    }
+
+    #[test]
+    fn fragments_reassemble_out_of_order() {
+        let original = packet(600);
+        let frames = fragment(7, &original).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|f| f.len() <= MAX_FRAME_LEN && f[0] == MAGIC));
+        assert_eq!(&frames[2][4..8], &[2, 0, 3, 0]);
+
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.push(PEER, &frames[2]).unwrap().is_none());
+        assert!(reassembler.push(PEER, &frames[0]).unwrap().is_none());
+        // 重发的分片不重复计数
+        assert!(reassembler.push(PEER, &frames[0]).unwrap().is_none());
+        let packet = reassembler.push(PEER, &frames[1]).unwrap().unwrap();
+        assert_eq!(packet.packet_type, PacketType::Image);
+        assert_eq!(packet.data, original.data);
+
+        let empty = fragment(8, &DataPacket::new(PacketType::Metadata, Vec::new())).unwrap();
+        assert_eq!(empty.len(), 1);
+        assert_eq!(reassembler.push(PEER, &empty[0]).unwrap().unwrap().data, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn duplicate_after_completion_is_ignored() {
+        let frames = fragment(1, &packet(300)).unwrap();
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.push(PEER, &frames[0]).unwrap().is_none());
+        assert!(reassembler.push(PEER, &frames[1]).unwrap().is_some());
+        assert!(reassembler.push(PEER, &frames[1]).unwrap().is_none());
+        assert!(reassembler.push(PEER, &frames[0]).unwrap().is_none());
+        // 其他节点的同号消息单独重组
+        assert!(reassembler.push([0; 6], &frames[0]).unwrap().is_none());
+    }
+
+    #[test]
+    fn inconsistent_header_drops_partial_message() {
+        let frames = fragment(5, &packet(600)).unwrap();
+        let mut reassembler = Reassembler::default();
+        assert!(reassembler.push(PEER, &frames[0]).unwrap().is_none());
+
+        let mut wrong_count = frames[1].clone();
+        wrong_count[6] = 2;
+        assert!(reassembler.push(PEER, &wrong_count).is_err());
+        // 之前收到的分片已随消息一起丢弃
+        assert!(reassembler.push(PEER, &frames[1]).unwrap().is_none());
+        assert!(reassembler.push(PEER, &frames[2]).unwrap().is_none());
+
+        let mut wrong_type = frames[0].clone();
+        wrong_type[1] = PacketType::Metadata.code();
+        assert!(reassembler.push(PEER, &wrong_type).is_err());
+
+        let mut bad_index = frames[0].clone();
+        bad_index[4] = 3;
+        assert!(reassembler.push(PEER, &bad_index).is_err());
+        assert!(reassembler.push(PEER, &[0x00; HEADER_LEN]).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+// 以太网链路 - 驱动W5500(SPI)或RMII PHY，网络就绪后TCP/HTTP发送器与WiFi下的用法完全相同
+use std::error::Error;
+
+use esp_idf_hal::gpio::AnyIOPin;
+use esp_idf_hal::peripherals::Peripherals;
+use esp_idf_hal::prelude::*;
+use esp_idf_hal::spi::{config::DriverConfig, Dma, SpiDriver};
+use esp_idf_svc::eth::{BlockingEth, EspEth, EthDriver, SpiEth, SpiEthChipset};
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use log::{debug, info};
+
+use crate::config::{EthernetConfig, EthernetPhy};
+
+/// 已启动的以太网接口
+pub enum EthernetLink {
+    Spi(BlockingEth<EspEth<'static, SpiEth<SpiDriver<'static>>>>),
+    #[cfg(esp32)]
+    Rmii(BlockingEth<EspEth<'static, esp_idf_svc::eth::RmiiEth>>),
+}
+
+impl EthernetLink {
+    /// 初始化PHY并等待链路与DHCP就绪
+    pub fn start(config: &EthernetConfig) -> Result<Self, Box<dyn Error>> {
+        let sys_loop = EspSystemEventLoop::take()?;
+        let peripherals = Peripherals::take()?;
+
+        let mut link = match &config.phy {
+            EthernetPhy::W5500 { sclk, mosi, miso, cs, int, rst, baudrate_hz } => {
+                debug!("初始化W5500以太网 (SPI {}Hz)", baudrate_hz);
+                // 引脚来自运行时配置，由配置保证与板上其他外设不冲突
+                let (sclk, mosi, miso, cs, int) = unsafe {
+                    (
+                        AnyIOPin::new(*sclk),
+                        AnyIOPin::new(*mosi),
+                        AnyIOPin::new(*miso),
+                        AnyIOPin::new(*cs),
+                        AnyIOPin::new(*int),
+                    )
+                };
+                let rst = rst.map(|pin| unsafe { AnyIOPin::new(pin) });
+                let spi = SpiDriver::new(
+                    peripherals.spi2,
+                    sclk,
+                    mosi,
+                    Some(miso),
+                    &DriverConfig::new().dma(Dma::Auto(4096)),
+                )?;
+                let driver = EthDriver::new_spi(
+                    spi,
+                    int,
+                    Some(cs),
+                    rst,
+                    SpiEthChipset::W5500,
+                    baudrate_hz.Hz(),
+                    None,
+                    None,
+                    sys_loop.clone(),
+                )?;
+                EthernetLink::Spi(BlockingEth::wrap(EspEth::wrap(driver)?, sys_loop)?)
+            }
+            #[cfg(esp32)]
+            EthernetPhy::Rmii { mdc, mdio, phy_addr } => {
+                use esp_idf_hal::gpio::{Gpio0, Gpio16, Gpio17};
+                use esp_idf_svc::eth::{RmiiClockConfig, RmiiEthChipset};
+
+                debug!("初始化RMII以太网");
+                let pins = peripherals.pins;
+                let (mdc, mdio) = unsafe { (AnyIOPin::new(*mdc), AnyIOPin::new(*mdio)) };
+                let driver = EthDriver::new_rmii(
+                    peripherals.mac,
+                    pins.gpio25,
+                    pins.gpio26,
+                    pins.gpio27,
+                    mdc,
+                    pins.gpio22,
+                    pins.gpio21,
+                    pins.gpio19,
+                    mdio,
+                    RmiiClockConfig::<Gpio0, Gpio16, Gpio17>::Input(pins.gpio0),
+                    None::<AnyIOPin>,
+                    RmiiEthChipset::LAN87XX,
+                    *phy_addr,
+                    sys_loop.clone(),
+                )?;
+                EthernetLink::Rmii(BlockingEth::wrap(EspEth::wrap(driver)?, sys_loop)?)
+            }
+            #[cfg(not(esp32))]
+            EthernetPhy::Rmii { .. } => {
+                return Err("当前芯片没有EMAC，不支持RMII以太网，请使用W5500".into());
+            }
+        };
+
+        link.start_and_wait()?;
+        Ok(link)
+    }
+
+    /// 启动接口并等待DHCP分配地址
+    fn start_and_wait(&mut self) -> Result<(), Box<dyn Error>> {
+        match self {
+            EthernetLink::Spi(eth) => {
+                eth.start()?;
+                eth.wait_netif_up()?;
+                info!("以太网已连接，IP: {}", eth.eth().netif().get_ip_info()?.ip);
+            }
+            #[cfg(esp32)]
+            EthernetLink::Rmii(eth) => {
+                eth.start()?;
+                eth.wait_netif_up()?;
+                info!("以太网已连接，IP: {}", eth.eth().netif().get_ip_info()?.ip);
+            }
+        }
+        Ok(())
+    }
+
+    /// 停止以太网接口
+    pub fn stop(&mut self) -> Result<(), Box<dyn Error>> {
+        match self {
+            EthernetLink::Spi(eth) => eth.stop()?,
+            #[cfg(esp32)]
+            EthernetLink::Rmii(eth) => eth.stop()?,
+        }
+        info!("以太网已断开");
+        Ok(())
+    }
+}
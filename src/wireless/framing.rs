@@ -0,0 +1,498 @@
+// TCP分帧协议 - 数据链路上的每段数据都带固定长度的帧头，手机端据此重组文件并校验完整性
+//
+// 帧头32字节，全部小端：
+//   magic   [u8;4]  "RCAM"
+//   version u8      协议版本
+//   kind    u8      帧类型(FrameKind)
+//   flags   u16     保留，为0
+//   seq     u32     帧序号，每个连接从0开始递增
+//   handle  u32     对象句柄，非对象数据为0
+//   offset  u64     内容在对象中的偏移
+//   length  u32     帧头之后的内容长度
+//   crc32   u32     内容的CRC32(IEEE)
+//
+// 手机收到帧后回复不带内容的Ack帧(seq为已按序收到的最后一帧，累计确认)，校验失败时回复Nack帧(seq为需要重发的第一帧)；
+// 发送端最多保留`MAX_IN_FLIGHT`个未确认的帧，窗口满时等待确认，收到Nack时从该帧开始重发
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use log::{debug, warn};
+
+/// 帧头魔数
+pub const MAGIC: [u8; 4] = *b"RCAM";
+/// 协议版本
+pub const VERSION: u8 = 1;
+/// 帧头长度
+pub const HEADER_LEN: usize = 32;
+/// 单帧内容的最大长度，较长的数据拆成多帧
+pub const MAX_PAYLOAD: usize = 16 * 1024;
+/// 未确认帧的上限
+pub const MAX_IN_FLIGHT: usize = 8;
+/// 同一帧的最大重发次数
+const MAX_RETRANSMITS: u32 = 3;
+
+/// 帧类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    Message = 0x01,     // 不属于对象的数据(例如事件JSON)
+    ObjectBegin = 0x02, // 对象开始，内容为对象总大小(u64)
+    ObjectData = 0x03,  // 对象内容
+    ObjectEnd = 0x04,   // 对象结束，内容为整个对象的CRC32(u32)
+    Ack = 0x10,         // 手机确认
+    Nack = 0x11,        // 手机要求重发
+}
+
+impl FrameKind {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0x01 => Some(FrameKind::Message),
+            0x02 => Some(FrameKind::ObjectBegin),
+            0x03 => Some(FrameKind::ObjectData),
+            0x04 => Some(FrameKind::ObjectEnd),
+            0x10 => Some(FrameKind::Ack),
+            0x11 => Some(FrameKind::Nack),
+            _ => None,
+        }
+    }
+}
+
+/// 帧头解析错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    UnknownKind(u8),
+    Truncated(usize),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::BadMagic => write!(f, "帧头魔数不符"),
+            FrameError::UnsupportedVersion(v) => write!(f, "不支持的协议版本: {}", v),
+            FrameError::UnknownKind(k) => write!(f, "未知的帧类型: 0x{:02x}", k),
+            FrameError::Truncated(n) => write!(f, "帧头不完整: {} 字节", n),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+/// 帧头
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub kind: FrameKind,
+    pub seq: u32,
+    pub handle: u32,
+    pub offset: u64,
+    pub length: u32,
+    pub crc32: u32,
+}
+
+impl FrameHeader {
+    /// 为内容生成帧头
+    pub fn for_payload(kind: FrameKind, seq: u32, handle: u32, offset: u64, payload: &[u8]) -> Self {
+        FrameHeader {
+            kind,
+            seq,
+            handle,
+            offset,
+            length: payload.len() as u32,
+            crc32: crc32(payload),
+        }
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut buf = [0u8; HEADER_LEN];
+        buf[0..4].copy_from_slice(&MAGIC);
+        buf[4] = VERSION;
+        buf[5] = self.kind as u8;
+        // 6..8 flags保留
+        buf[8..12].copy_from_slice(&self.seq.to_le_bytes());
+        buf[12..16].copy_from_slice(&self.handle.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.offset.to_le_bytes());
+        buf[24..28].copy_from_slice(&self.length.to_le_bytes());
+        buf[28..32].copy_from_slice(&self.crc32.to_le_bytes());
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, FrameError> {
+        if buf.len() < HEADER_LEN {
+            return Err(FrameError::Truncated(buf.len()));
+        }
+        if buf[0..4] != MAGIC {
+            return Err(FrameError::BadMagic);
+        }
+        if buf[4] != VERSION {
+            return Err(FrameError::UnsupportedVersion(buf[4]));
+        }
+        let kind = FrameKind::from_u8(buf[5]).ok_or(FrameError::UnknownKind(buf[5]))?;
+        let u32_at = |i: usize| u32::from_le_bytes(buf[i..i + 4].try_into().unwrap());
+        Ok(FrameHeader {
+            kind,
+            seq: u32_at(8),
+            handle: u32_at(12),
+            offset: u64::from_le_bytes(buf[16..24].try_into().unwrap()),
+            length: u32_at(24),
+            crc32: u32_at(28),
+        })
+    }
+}
+
+/// CRC32(IEEE 802.3，与zlib相同)的查找表
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// 可分段计算的CRC32
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32(0xFFFF_FFFF)
+    }
+}
+
+impl Crc32 {
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.0 = CRC_TABLE[((self.0 ^ b as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+/// 计算一段数据的CRC32
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::default();
+    crc.update(data);
+    crc.finish()
+}
+
+/// 正在发送的对象
+#[derive(Debug)]
+struct ObjectState {
+    handle: u32,
+    size: u64,
+    offset: u64,
+    crc: Crc32,
+}
+
+/// 分帧发送端：给数据加帧头，保留未确认的帧并处理手机的确认和重发请求
+pub struct FramedSender<S: Read + Write> {
+    stream: S,
+    next_seq: u32,
+    in_flight: VecDeque<(u32, Vec<u8>)>, // (序号, 编码后的完整帧)
+    retransmits: u32,                    // 当前最早未确认帧的重发次数
+    retransmitted: u64,                  // 累计重发的帧数
+    message_offset: u64,                 // 非对象数据的累计偏移
+    object: Option<ObjectState>,
+}
+
+impl<S: Read + Write> FramedSender<S> {
+    pub fn new(stream: S) -> Self {
+        FramedSender {
+            stream,
+            next_seq: 0,
+            in_flight: VecDeque::new(),
+            retransmits: 0,
+            retransmitted: 0,
+            message_offset: 0,
+            object: None,
+        }
+    }
+
+    /// 尚未被确认的帧数
+    pub fn unacked(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// 连接建立以来累计重发的帧数
+    pub fn retransmitted(&self) -> u64 {
+        self.retransmitted
+    }
+
+    /// 未确认的帧已达到窗口上限，再发送需要先等待确认
+    pub fn window_full(&self) -> bool {
+        self.in_flight.len() >= MAX_IN_FLIGHT
+    }
+
+    /// 等待确认直到窗口有空位
+    pub fn wait_window(&mut self) -> Result<(), Box<dyn Error>> {
+        while self.window_full() {
+            self.read_ack()?;
+        }
+        Ok(())
+    }
+
+    /// 开始发送一个对象，之后的`send`都属于该对象
+    pub fn begin_object(&mut self, handle: u32, size: u64) -> Result<(), Box<dyn Error>> {
+        if let Some(object) = &self.object {
+            return Err(format!("对象 0x{:08x} 尚未发送完成", object.handle).into());
+        }
+        self.push_frame(FrameKind::ObjectBegin, handle, 0, &size.to_le_bytes())?;
+        self.object = Some(ObjectState {
+            handle,
+            size,
+            offset: 0,
+            crc: Crc32::default(),
+        });
+        Ok(())
+    }
+
+    /// 结束当前对象，发送整体CRC并等待手机确认全部帧
+    pub fn finish_object(&mut self) -> Result<(), Box<dyn Error>> {
+        let object = self.object.take().ok_or("没有正在发送的对象")?;
+        if object.offset != object.size {
+            warn!("对象 0x{:08x} 只发送了 {}/{} 字节", object.handle, object.offset, object.size);
+        }
+        self.push_frame(FrameKind::ObjectEnd, object.handle, object.offset, &object.crc.finish().to_le_bytes())?;
+        self.flush_acks()
+    }
+
+    /// 发送数据，超过单帧上限时拆成多帧；没有正在发送的对象时作为消息发送
+    pub fn send(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>> {
+        for chunk in data.chunks(MAX_PAYLOAD) {
+            match self.object.as_mut() {
+                Some(object) => {
+                    let (handle, offset) = (object.handle, object.offset);
+                    object.offset += chunk.len() as u64;
+                    object.crc.update(chunk);
+                    self.push_frame(FrameKind::ObjectData, handle, offset, chunk)?;
+                }
+                None => {
+                    let offset = self.message_offset;
+                    self.message_offset += chunk.len() as u64;
+                    self.push_frame(FrameKind::Message, 0, offset, chunk)?;
+                }
+            }
+        }
+        Ok(data.len())
+    }
+
+    /// 等待手机确认全部已发送的帧
+    pub fn flush_acks(&mut self) -> Result<(), Box<dyn Error>> {
+        while !self.in_flight.is_empty() {
+            self.read_ack()?;
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, kind: FrameKind, handle: u32, offset: u64, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.wait_window()?;
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let header = FrameHeader::for_payload(kind, seq, handle, offset, payload);
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.extend_from_slice(&header.encode());
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)?;
+        self.in_flight.push_back((seq, frame));
+        Ok(())
+    }
+
+    /// 读取一个确认帧并据此移除或重发未确认的帧；读取超时由底层连接的读超时决定
+    fn read_ack(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut buf = [0u8; HEADER_LEN];
+        self.stream.read_exact(&mut buf)?;
+        let header = FrameHeader::decode(&buf)?;
+        match header.kind {
+            FrameKind::Ack => {
+                // 累计确认：序号不晚于header.seq的帧都已收到(按回绕后的距离比较)
+                let before = self.in_flight.len();
+                self.in_flight.retain(|(seq, _)| header.seq.wrapping_sub(*seq) > i32::MAX as u32);
+                if self.in_flight.len() != before {
+                    self.retransmits = 0;
+                }
+                Ok(())
+            }
+            FrameKind::Nack => {
+                self.retransmits += 1;
+                if self.retransmits > MAX_RETRANSMITS {
+                    return Err(format!("帧 {} 重发 {} 次仍未成功", header.seq, MAX_RETRANSMITS).into());
+                }
+                debug!("手机要求从帧 {} 开始重发", header.seq);
+                let Self { stream, in_flight, retransmitted, .. } = self;
+                for (_, frame) in in_flight.iter().skip_while(|(seq, _)| *seq != header.seq) {
+                    stream.write_all(frame)?;
+                    *retransmitted += 1;
+                }
+                Ok(())
+            }
+            other => Err(format!("手机发来了意外的帧类型 {:?}", other).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    /// 预先排好手机回复的确认帧，记录发送端写出的全部字节
+    struct Loopback {
+        replies: Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Loopback {
+        fn new(replies: &[(FrameKind, u32)]) -> Self {
+            let replies = replies
+                .iter()
+                .flat_map(|&(kind, seq)| FrameHeader::for_payload(kind, seq, 0, 0, &[]).encode())
+                .collect();
+            Loopback { replies: Cursor::new(replies), written: Vec::new() }
+        }
+
+        /// 按顺序解析写出的帧，返回帧头和内容
+        fn frames(&self) -> Vec<(FrameHeader, Vec<u8>)> {
+            let mut frames = Vec::new();
+            let mut rest = &self.written[..];
+            while !rest.is_empty() {
+                let header = FrameHeader::decode(rest).unwrap();
+                let end = HEADER_LEN + header.length as usize;
+                frames.push((header, rest[HEADER_LEN..end].to_vec()));
+                rest = &rest[end..];
+            }
+            frames
+        }
+    }
+
+    impl Read for Loopback {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Loopback {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = FrameHeader::for_payload(FrameKind::ObjectData, 7, 0x0001_0002, 1 << 33, b"payload");
+        let encoded = header.encode();
+        assert_eq!(&encoded[..4], b"RCAM");
+        assert_eq!(encoded[4], VERSION);
+        assert_eq!(FrameHeader::decode(&encoded), Ok(header));
+        assert_eq!(header.length, 7);
+    }
+
+    #[test]
+    fn decode_rejects_malformed_headers() {
+        let encoded = FrameHeader::for_payload(FrameKind::Message, 0, 0, 0, &[]).encode();
+        assert_eq!(FrameHeader::decode(&encoded[..HEADER_LEN - 1]), Err(FrameError::Truncated(HEADER_LEN - 1)));
+        let mut bad = encoded;
+        bad[0] = b'X';
+        assert_eq!(FrameHeader::decode(&bad), Err(FrameError::BadMagic));
+        let mut bad = encoded;
+        bad[4] = VERSION + 1;
+        assert_eq!(FrameHeader::decode(&bad), Err(FrameError::UnsupportedVersion(VERSION + 1)));
+        let mut bad = encoded;
+        bad[5] = 0x7F;
+        assert_eq!(FrameHeader::decode(&bad), Err(FrameError::UnknownKind(0x7F)));
+    }
+
+    #[test]
+    fn crc_matches_reference_and_detects_corruption() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        let mut crc = Crc32::default();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+
+        let header = FrameHeader::for_payload(FrameKind::ObjectData, 0, 1, 0, b"abcdef");
+        let mut corrupted = b"abcdef".to_vec();
+        corrupted[3] ^= 0x01;
+        assert_ne!(crc32(&corrupted), header.crc32);
+    }
+
+    #[test]
+    fn cumulative_ack_clears_in_flight_frames() {
+        let mut sender = FramedSender::new(Loopback::new(&[(FrameKind::Ack, 2)]));
+        for chunk in [&b"a"[..], b"bb", b"ccc"] {
+            sender.send(chunk).unwrap();
+        }
+        assert_eq!(sender.unacked(), 3);
+        sender.flush_acks().unwrap();
+        assert_eq!(sender.unacked(), 0);
+        assert_eq!(sender.retransmitted(), 0);
+        let offsets: Vec<u64> = sender.stream.frames().iter().map(|(h, _)| h.offset).collect();
+        assert_eq!(offsets, vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn nack_retransmits_from_requested_frame() {
+        let mut sender = FramedSender::new(Loopback::new(&[(FrameKind::Nack, 1), (FrameKind::Ack, 2)]));
+        for chunk in [&b"a"[..], b"b", b"c"] {
+            sender.send(chunk).unwrap();
+        }
+        sender.flush_acks().unwrap();
+        assert_eq!(sender.retransmitted(), 2);
+        let seqs: Vec<u32> = sender.stream.frames().iter().map(|(h, _)| h.seq).collect();
+        assert_eq!(seqs, vec![0, 1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn repeated_nacks_give_up() {
+        let nacks = [(FrameKind::Nack, 0); MAX_RETRANSMITS as usize + 1];
+        let mut sender = FramedSender::new(Loopback::new(&nacks));
+        sender.send(b"a").unwrap();
+        assert!(sender.flush_acks().is_err());
+        assert_eq!(sender.retransmitted(), MAX_RETRANSMITS as u64);
+    }
+
+    #[test]
+    fn full_window_waits_for_ack() {
+        let mut sender = FramedSender::new(Loopback::new(&[(FrameKind::Ack, 0)]));
+        for _ in 0..MAX_IN_FLIGHT {
+            sender.send(b"x").unwrap();
+        }
+        assert!(sender.window_full());
+        sender.send(b"y").unwrap();
+        assert_eq!(sender.unacked(), MAX_IN_FLIGHT);
+    }
+
+    #[test]
+    fn object_frames_carry_offsets_and_whole_crc() {
+        let data = vec![0x5Au8; MAX_PAYLOAD + 10];
+        let mut sender = FramedSender::new(Loopback::new(&[(FrameKind::Ack, 3)]));
+        sender.begin_object(0x42, data.len() as u64).unwrap();
+        assert!(sender.begin_object(0x43, 1).is_err());
+        sender.send(&data).unwrap();
+        sender.finish_object().unwrap();
+        let frames = sender.stream.frames();
+        let kinds: Vec<FrameKind> = frames.iter().map(|(h, _)| h.kind).collect();
+        assert_eq!(kinds, vec![FrameKind::ObjectBegin, FrameKind::ObjectData, FrameKind::ObjectData, FrameKind::ObjectEnd]);
+        assert_eq!(frames[0].1, (data.len() as u64).to_le_bytes());
+        assert_eq!(frames[2].0.offset, MAX_PAYLOAD as u64);
+        assert!(frames.iter().all(|(h, payload)| h.handle == 0x42 && h.crc32 == crc32(payload)));
+        assert_eq!(frames[3].1, crc32(&data).to_le_bytes());
+    }
+}
@@ -0,0 +1,814 @@
+// FTP服务端 - 把相机的存储和关联(文件夹)映射成只读的目录树，桌面工具和联机拍摄软件可以直接用FTP拉取照片
+//
+// 根目录下每个存储一个目录(存储ID的十六进制)，其下按PtpObjectTree的结构展开，文件夹在第一次进入时才枚举。
+// 只支持被动模式(PASV/EPSV)和二进制传输，支持REST断点续传，写入类命令一律拒绝。
+// 默认要求用户名和密码登录。配置了证书时支持显式FTPS(AUTH TLS)，PROT P后数据连接也加密；数据连接只接受来自控制连接同一地址的客户端
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use embassy_futures::block_on;
+use log::{debug, info, warn};
+
+use super::tls::{ServerIdentity, TlsServerStream};
+use crate::config::FtpConfig;
+use crate::ptp_mtp::{read_shared_object, ObjectInfoCache, PtpCamera, PtpDateTime, PtpObjectTree, SharedCamera, TreeOptions};
+use crate::runtime;
+
+/// 控制连接空闲多久后断开
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// 等待客户端建立数据连接的时间
+const DATA_ACCEPT_TIMEOUT: Duration = Duration::from_secs(15);
+/// 非阻塞accept的轮询间隔
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// 一行命令的最大长度，超过时视为异常客户端
+const MAX_COMMAND_LEN: usize = 512;
+/// 连续登录失败多少次后断开
+const MAX_LOGIN_FAILURES: u32 = 3;
+/// 列目录时月份的缩写
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// 存储在根目录下对应的目录名
+pub fn storage_dir(storage_id: u32) -> String {
+    format!("{:08X}", storage_id)
+}
+
+/// 把客户端给出的路径解析为相对根目录的各级名称，`..`不会超出根目录
+pub fn resolve_path(cwd: &[String], path: &str) -> Vec<String> {
+    let mut segments = if path.starts_with('/') { Vec::new() } else { cwd.to_vec() };
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            name => segments.push(name.to_string()),
+        }
+    }
+    segments
+}
+
+/// 以`/`开头的绝对路径
+pub fn display_path(segments: &[String]) -> String {
+    format!("/{}", segments.join("/"))
+}
+
+/// 目录项
+#[derive(Debug, Clone)]
+pub struct FsEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,                     // ObjectInfo中记录的大小，超过4GB的对象不准确
+    pub modified: Option<PtpDateTime>, // 修改时间，相机未提供时使用拍摄时间
+}
+
+impl FsEntry {
+    fn directory(name: String) -> Self {
+        FsEntry { name, is_dir: true, size: 0, modified: None }
+    }
+
+    fn from_node(node: &PtpObjectTree) -> Self {
+        let info = &node.info;
+        FsEntry {
+            name: info.Filename.clone(),
+            is_dir: node.is_association(),
+            size: info.ObjectCompressedSize as u64,
+            modified: PtpDateTime::parse_opt(&info.ModificationDate).or_else(|| PtpDateTime::parse_opt(&info.CaptureDate)),
+        }
+    }
+
+    /// LIST使用的ls -l格式，只显示日期(设备时钟不一定准确，无法判断是否为今年)
+    pub fn list_line(&self) -> String {
+        let (kind, mode) = if self.is_dir { ('d', "r-xr-xr-x") } else { ('-', "r--r--r--") };
+        let date = match self.modified {
+            Some(dt) => format!("{} {:>2}  {}", MONTHS[(dt.month as usize).clamp(1, 12) - 1], dt.day, dt.year),
+            None => "Jan  1  1970".to_string(),
+        };
+        format!("{}{} 1 camera camera {:>12} {} {}\r\n", kind, mode, self.size, date, self.name)
+    }
+
+    /// MLSD/MLST使用的事实列表，时间为UTC
+    pub fn facts(&self) -> String {
+        let mut facts = if self.is_dir {
+            "type=dir;".to_string()
+        } else {
+            format!("type=file;size={};", self.size)
+        };
+        if let Some(dt) = self.modified {
+            facts.push_str(&format!("modify={};", mdtm(dt)));
+        }
+        facts
+    }
+}
+
+/// MDTM和MLSD使用的时间格式YYYYMMDDhhmmss(UTC)
+fn mdtm(dt: PtpDateTime) -> String {
+    let utc = PtpDateTime::from_unix(dt.to_unix());
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}", utc.year, utc.month, utc.day, utc.hour, utc.minute, utc.second)
+}
+
+/// 相机对象映射成的只读文件系统，多个FTP会话共享
+///
+/// 相机与协议处理器共用，每次枚举或读取一块数据时才锁住相机
+pub struct CameraFs {
+    camera: SharedCamera,
+    options: TreeOptions,
+    cache: Option<ObjectInfoCache>,
+    storages: Option<Vec<u32>>,
+    trees: HashMap<u32, PtpObjectTree>,
+}
+
+/// FTP会话共享的文件系统
+pub type CameraFsHandle = Arc<Mutex<CameraFs>>;
+
+/// 创建共享的文件系统
+pub fn handle(fs: CameraFs) -> CameraFsHandle {
+    Arc::new(Mutex::new(fs))
+}
+
+impl CameraFs {
+    pub fn new(camera: SharedCamera, timeout: Option<Duration>) -> Self {
+        CameraFs {
+            camera,
+            // 每次只枚举一层，文件夹在进入时才加载
+            options: TreeOptions { max_depth: Some(1), timeout },
+            cache: None,
+            storages: None,
+            trees: HashMap::new(),
+        }
+    }
+
+    /// 使用对象信息缓存，减少枚举时的GetObjectInfo
+    pub fn with_cache(mut self, cache: ObjectInfoCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// 丢弃已枚举的目录，相机新增或删除对象、换卡后调用
+    pub fn invalidate(&mut self) {
+        self.storages = None;
+        self.trees.clear();
+    }
+
+    /// 列出目录
+    pub fn list(&mut self, path: &[String]) -> Result<Vec<FsEntry>, Box<dyn Error>> {
+        let shared = self.camera.clone();
+        let mut camera = shared.lock().unwrap();
+        let camera = camera.ptp();
+        block_on(async {
+            let Some((storage, rest)) = path.split_first() else {
+                return Ok(self.storages(camera).await?.iter().map(|id| FsEntry::directory(storage_dir(*id))).collect());
+            };
+            let node = self.node(camera, storage, rest).await?;
+            if !node.is_association() {
+                return Err(format!("{} 不是目录", display_path(path)).into());
+            }
+            Ok(node.children.iter().flatten().map(FsEntry::from_node).collect())
+        })
+    }
+
+    /// 读取文件或目录的信息
+    pub fn stat(&mut self, path: &[String]) -> Result<FsEntry, Box<dyn Error>> {
+        let shared = self.camera.clone();
+        let mut camera = shared.lock().unwrap();
+        let camera = camera.ptp();
+        block_on(async {
+            match path.split_first() {
+                None => Ok(FsEntry::directory(String::new())),
+                Some((storage, [])) => {
+                    self.node(camera, storage, &[]).await?;
+                    Ok(FsEntry::directory(storage.clone()))
+                }
+                Some((storage, rest)) => Ok(FsEntry::from_node(self.node(camera, storage, rest).await?)),
+            }
+        })
+    }
+
+    /// 文件的实际大小，超过4GB时向相机查询
+    pub fn size(&mut self, path: &[String]) -> Result<u64, Box<dyn Error>> {
+        Ok(self.open(path)?.size)
+    }
+
+    /// 打开文件用于读取，之后的读取不再需要文件系统的锁
+    pub fn open(&mut self, path: &[String]) -> Result<CameraFile, Box<dyn Error>> {
+        let shared = self.camera.clone();
+        let mut camera = shared.lock().unwrap();
+        let camera = camera.ptp();
+        let timeout = self.options.timeout;
+        block_on(async {
+            let (handle, info) = self.file(camera, path).await?;
+            let size = camera.get_object_size(handle, &info, timeout).await?;
+            Ok(CameraFile { camera: self.camera.clone(), handle, size, timeout })
+        })
+    }
+
+    async fn storages(&mut self, camera: &mut PtpCamera) -> Result<Vec<u32>, Box<dyn Error>> {
+        if self.storages.is_none() {
+            self.storages = Some(camera.get_storageids(self.options.timeout).await?);
+        }
+        Ok(self.storages.clone().unwrap_or_default())
+    }
+
+    /// 查找普通文件，返回句柄和对象信息
+    async fn file(&mut self, camera: &mut PtpCamera, path: &[String]) -> Result<(u32, crate::ptp_mtp::PtpObjectInfo), Box<dyn Error>> {
+        let Some((storage, rest)) = path.split_first().filter(|(_, rest)| !rest.is_empty()) else {
+            return Err(format!("{} 不是文件", display_path(path)).into());
+        };
+        let node = self.node(camera, storage, rest).await?;
+        if node.is_association() {
+            return Err(format!("{} 不是文件", display_path(path)).into());
+        }
+        Ok((node.handle, node.info.clone()))
+    }
+
+    /// 按路径查找节点，沿途未加载的文件夹逐层加载；返回的文件夹节点的子节点已加载
+    async fn node(&mut self, camera: &mut PtpCamera, storage: &str, path: &[String]) -> Result<&mut PtpObjectTree, Box<dyn Error>> {
+        let storage_id = self
+            .storages(camera)
+            .await?
+            .into_iter()
+            .find(|id| storage_dir(*id) == storage)
+            .ok_or_else(|| format!("存储 {} 不存在", storage))?;
+        let CameraFs { options, cache, trees, .. } = self;
+        let mut node = match trees.entry(storage_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(Box::pin(PtpObjectTree::build_with(camera, storage_id, options, cache.as_mut())).await?)
+            }
+        };
+        // 枚举的future较大，放在堆上，会话任务的栈只需容纳TLS
+        for name in path {
+            if !node.is_loaded() {
+                Box::pin(node.load_children(camera, options, cache.as_mut())).await?;
+            }
+            node = node
+                .children
+                .as_mut()
+                .and_then(|children| children.iter_mut().find(|c| c.info.Filename == *name))
+                .ok_or_else(|| format!("{} 不存在", name))?;
+        }
+        if !node.is_loaded() {
+            Box::pin(node.load_children(camera, options, cache.as_mut())).await?;
+        }
+        Ok(node)
+    }
+}
+
+/// 打开的文件，每读一块锁一次相机，慢速客户端不会长时间占用相机
+pub struct CameraFile {
+    camera: SharedCamera,
+    handle: u32,
+    size: u64,
+    timeout: Option<Duration>,
+}
+
+impl CameraFile {
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// 从`offset`开始分块读取文件，每块交给sink；sink返回错误时中止读取。返回读取结束时在文件中的位置
+    pub fn read(
+        &self,
+        offset: u64,
+        sink: &mut dyn FnMut(&[u8]) -> Result<(), Box<dyn Error>>,
+    ) -> Result<u64, Box<dyn Error>> {
+        read_shared_object(&self.camera, self.handle, offset, self.size, self.timeout, sink)
+    }
+}
+
+/// 控制连接或数据连接，明文或TLS
+enum FtpStream {
+    Plain(TcpStream),
+    Tls(TlsServerStream),
+}
+
+impl Read for FtpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            FtpStream::Plain(stream) => stream.read(buf),
+            FtpStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for FtpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FtpStream::Plain(stream) => stream.write(buf),
+            FtpStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FtpStream::Plain(stream) => stream.flush(),
+            FtpStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// 服务端和各会话共享的状态
+struct Shared {
+    config: FtpConfig,
+    identity: Option<ServerIdentity>,
+    fs: CameraFsHandle,
+    stop: AtomicBool,
+    sessions: AtomicUsize,
+    next_port: AtomicUsize, // 下一次被动模式优先尝试的端口序号，轮换使用避免刚关闭的端口
+}
+
+/// FTP服务端，随对象释放停止接受新连接，已登录的会话在下一条命令后结束
+pub struct FtpServer {
+    shared: Arc<Shared>,
+}
+
+impl FtpServer {
+    /// 在所有接口上监听配置的端口
+    pub fn start(config: &FtpConfig, fs: CameraFsHandle) -> Result<Self, Box<dyn Error>> {
+        config.validate()?;
+        let identity = match (&config.tls_cert_pem, &config.tls_key_pem) {
+            (Some(cert), Some(key)) => Some(ServerIdentity::load(cert, key)?),
+            _ => None,
+        };
+        let listener = TcpListener::bind(("0.0.0.0", config.port))?;
+        listener.set_nonblocking(true)?;
+        let shared = Arc::new(Shared {
+            config: config.clone(),
+            identity,
+            fs,
+            stop: AtomicBool::new(false),
+            sessions: AtomicUsize::new(0),
+            next_port: AtomicUsize::new(0),
+        });
+        let running = shared.clone();
+        runtime::spawn(runtime::FTP, move || {
+            while !running.stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, peer)) => accept_session(&running, stream, peer.ip()),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(ACCEPT_POLL_INTERVAL),
+                    Err(e) => warn!("接受FTP连接失败: {}", e),
+                }
+            }
+        })?;
+        info!(
+            "FTP服务端监听端口 {}{}",
+            config.port,
+            if shared.identity.is_some() { "，支持FTPS" } else { "" }
+        );
+        Ok(FtpServer { shared })
+    }
+
+    /// 当前连接的会话数
+    pub fn session_count(&self) -> usize {
+        self.shared.sessions.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for FtpServer {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// 为新连接创建会话任务，超过会话上限时拒绝
+fn accept_session(shared: &Arc<Shared>, mut stream: TcpStream, peer: IpAddr) {
+    if shared.sessions.fetch_add(1, Ordering::Relaxed) >= shared.config.max_sessions {
+        shared.sessions.fetch_sub(1, Ordering::Relaxed);
+        warn!("FTP会话已达上限 {}，拒绝 {}", shared.config.max_sessions, peer);
+        let _ = stream.write_all(b"421 Too many connections\r\n");
+        return;
+    }
+    let local = match stream.local_addr().map(|a| a.ip()) {
+        Ok(IpAddr::V4(ip)) => ip,
+        _ => Ipv4Addr::UNSPECIFIED,
+    };
+    let session_shared = shared.clone();
+    let spawned = runtime::spawn(runtime::FTP_SESSION, move || {
+        info!("FTP客户端 {} 已连接", peer);
+        let mut session = Session::new(&session_shared, peer, local);
+        match session.run(stream) {
+            Ok(()) => info!("FTP客户端 {} 已断开", peer),
+            Err(e) => info!("FTP客户端 {} 已断开: {}", peer, e),
+        }
+        session_shared.sessions.fetch_sub(1, Ordering::Relaxed);
+    });
+    if let Err(e) = spawned {
+        shared.sessions.fetch_sub(1, Ordering::Relaxed);
+        warn!("创建FTP会话任务失败: {}", e);
+    }
+}
+
+/// 一个客户端的会话状态
+struct Session<'a> {
+    shared: &'a Shared,
+    peer: IpAddr,
+    local: Ipv4Addr,
+    secure: bool,          // 控制连接已加密
+    protect_data: bool,    // PROT P，数据连接也加密
+    user: Option<String>,  // USER给出的用户名
+    logged_in: bool,
+    login_failures: u32,
+    cwd: Vec<String>,
+    passive: Option<TcpListener>,
+    rest: u64,             // REST给出的下一次RETR的起始偏移
+    inbox: Vec<u8>,        // 尚未读到换行的命令
+}
+
+/// 命令处理后控制连接的去向
+enum Next {
+    Continue,
+    UpgradeTls,
+    Quit,
+}
+
+impl<'a> Session<'a> {
+    fn new(shared: &'a Shared, peer: IpAddr, local: Ipv4Addr) -> Self {
+        Session {
+            shared,
+            peer,
+            local,
+            secure: false,
+            protect_data: false,
+            user: None,
+            logged_in: false,
+            login_failures: 0,
+            cwd: Vec::new(),
+            passive: None,
+            rest: 0,
+            inbox: Vec::new(),
+        }
+    }
+
+    fn run(&mut self, stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+        let mut control = FtpStream::Plain(stream);
+        reply(&mut control, 220, "rcamera FTP ready")?;
+        while !self.shared.stop.load(Ordering::Relaxed) {
+            let Some(line) = self.read_line(&mut control)? else {
+                return Ok(());
+            };
+            let (command, arg) = match line.split_once(' ') {
+                Some((command, arg)) => (command.to_ascii_uppercase(), arg.trim()),
+                None => (line.to_ascii_uppercase(), ""),
+            };
+            debug!("FTP {} -> {} {}", self.peer, command, if command == "PASS" { "***" } else { arg });
+            match self.handle(&mut control, &command, arg)? {
+                Next::Continue => {}
+                Next::Quit => return Ok(()),
+                Next::UpgradeTls => {
+                    let identity = self.shared.identity.as_ref().ok_or("未配置证书")?;
+                    control = match control {
+                        FtpStream::Plain(stream) => FtpStream::Tls(TlsServerStream::accept(stream, identity)?),
+                        tls => tls,
+                    };
+                    self.secure = true;
+                }
+            }
+        }
+        reply(&mut control, 421, "Server shutting down")?;
+        Ok(())
+    }
+
+    /// 读取一行命令，连接关闭时返回None
+    fn read_line(&mut self, control: &mut FtpStream) -> Result<Option<String>, Box<dyn Error>> {
+        loop {
+            if let Some(end) = self.inbox.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = self.inbox.drain(..=end).collect();
+                return Ok(Some(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string()));
+            }
+            if self.inbox.len() > MAX_COMMAND_LEN {
+                return Err("命令过长".into());
+            }
+            let mut buf = [0u8; 128];
+            match control.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(n) => self.inbox.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn handle(&mut self, control: &mut FtpStream, command: &str, arg: &str) -> Result<Next, Box<dyn Error>> {
+        let config = &self.shared.config;
+        match command {
+            "QUIT" => {
+                reply(control, 221, "Goodbye")?;
+                return Ok(Next::Quit);
+            }
+            "NOOP" => reply(control, 200, "OK")?,
+            "SYST" => reply(control, 215, "UNIX Type: L8")?,
+            "FEAT" => {
+                let tls = if self.shared.identity.is_some() { " AUTH TLS\r\n PBSZ\r\n PROT\r\n" } else { "" };
+                let features = format!(" EPSV\r\n MDTM\r\n MLST type*;size*;modify*;\r\n REST STREAM\r\n SIZE\r\n UTF8\r\n{}", tls);
+                control.write_all(format!("211-Features:\r\n{}211 End\r\n", features).as_bytes())?;
+            }
+            "OPTS" if arg.eq_ignore_ascii_case("UTF8 ON") => reply(control, 200, "UTF8 enabled")?,
+            "OPTS" => reply(control, 501, "Option not supported")?,
+            "AUTH" => {
+                if self.secure {
+                    reply(control, 503, "Already using TLS")?;
+                } else if self.shared.identity.is_none() {
+                    reply(control, 534, "TLS not available")?;
+                } else if matches!(arg.to_ascii_uppercase().as_str(), "TLS" | "TLS-C" | "SSL") {
+                    reply(control, 234, "Proceed with TLS negotiation")?;
+                    return Ok(Next::UpgradeTls);
+                } else {
+                    reply(control, 504, "Unsupported security mechanism")?;
+                }
+            }
+            "PBSZ" if self.secure => reply(control, 200, "PBSZ=0")?,
+            "PROT" if self.secure => match arg.to_ascii_uppercase().as_str() {
+                "P" => {
+                    self.protect_data = true;
+                    reply(control, 200, "Data connections will be encrypted")?;
+                }
+                "C" if config.require_tls => reply(control, 534, "Data protection required")?,
+                "C" => {
+                    self.protect_data = false;
+                    reply(control, 200, "Data connections will be clear")?;
+                }
+                _ => reply(control, 504, "Unsupported protection level")?,
+            },
+            "PBSZ" | "PROT" => reply(control, 503, "AUTH TLS first")?,
+            "USER" => {
+                if config.require_tls && !self.secure {
+                    reply(control, 530, "TLS required")?;
+                } else {
+                    self.user = Some(arg.to_string());
+                    self.logged_in = false;
+                    reply(control, 331, "Password required")?;
+                }
+            }
+            "PASS" => {
+                let Some(user) = self.user.take() else {
+                    reply(control, 503, "USER first")?;
+                    return Ok(Next::Continue);
+                };
+                let anonymous = config.allow_anonymous && config.username.trim().is_empty();
+                if anonymous || (user == config.username && arg == config.password) {
+                    self.logged_in = true;
+                    self.login_failures = 0;
+                    info!("FTP客户端 {} 以 {} 登录", self.peer, if anonymous { "匿名用户" } else { user.as_str() });
+                    reply(control, 230, "Logged in")?;
+                } else {
+                    self.login_failures += 1;
+                    warn!("FTP客户端 {} 登录失败 ({}/{})", self.peer, self.login_failures, MAX_LOGIN_FAILURES);
+                    reply(control, 530, "Login incorrect")?;
+                    if self.login_failures >= MAX_LOGIN_FAILURES {
+                        return Ok(Next::Quit);
+                    }
+                }
+            }
+            _ if !self.logged_in => reply(control, 530, "Not logged in")?,
+            _ => self.handle_logged_in(control, command, arg)?,
+        }
+        Ok(Next::Continue)
+    }
+
+    fn handle_logged_in(&mut self, control: &mut FtpStream, command: &str, arg: &str) -> Result<(), Box<dyn Error>> {
+        match command {
+            "PWD" | "XPWD" => {
+                let path = display_path(&self.cwd).replace('"', "\"\"");
+                reply(control, 257, &format!("\"{}\" is the current directory", path))?;
+            }
+            "CWD" | "XCWD" => self.change_dir(control, resolve_path(&self.cwd, arg))?,
+            "CDUP" | "XCUP" => self.change_dir(control, resolve_path(&self.cwd, ".."))?,
+            "TYPE" => match arg.to_ascii_uppercase().as_str() {
+                // 文件总是按二进制发送，ASCII模式只为兼容只会先发TYPE A的客户端
+                "I" | "L 8" | "A" | "A N" => reply(control, 200, "Type set")?,
+                _ => reply(control, 504, "Unsupported type")?,
+            },
+            "MODE" if arg.eq_ignore_ascii_case("S") => reply(control, 200, "Mode set to S")?,
+            "STRU" if arg.eq_ignore_ascii_case("F") => reply(control, 200, "Structure set to F")?,
+            "MODE" | "STRU" => reply(control, 504, "Unsupported parameter")?,
+            "PASV" => match self.open_passive() {
+                Ok(port) => {
+                    let [a, b, c, d] = self.local.octets();
+                    let text = format!("Entering Passive Mode ({},{},{},{},{},{})", a, b, c, d, port >> 8, port & 0xFF);
+                    reply(control, 227, &text)?;
+                }
+                Err(e) => reply(control, 425, &e.to_string())?,
+            },
+            "EPSV" if arg.eq_ignore_ascii_case("ALL") => reply(control, 200, "EPSV ALL accepted")?,
+            "EPSV" => match self.open_passive() {
+                Ok(port) => reply(control, 229, &format!("Entering Extended Passive Mode (|||{}|)", port))?,
+                Err(e) => reply(control, 425, &e.to_string())?,
+            },
+            "PORT" | "EPRT" => reply(control, 502, "Active mode not supported, use PASV")?,
+            "REST" => match arg.parse::<u64>() {
+                Ok(offset) => {
+                    self.rest = offset;
+                    reply(control, 350, &format!("Restarting at {}", offset))?;
+                }
+                Err(_) => reply(control, 501, "Invalid offset")?,
+            },
+            "SIZE" => {
+                let path = resolve_path(&self.cwd, arg);
+                match self.shared.fs.lock().unwrap().size(&path) {
+                    Ok(size) => reply(control, 213, &size.to_string())?,
+                    Err(e) => reply(control, 550, &e.to_string())?,
+                }
+            }
+            "MDTM" => {
+                let path = resolve_path(&self.cwd, arg);
+                match self.shared.fs.lock().unwrap().stat(&path) {
+                    Ok(FsEntry { modified: Some(dt), .. }) => reply(control, 213, &mdtm(dt))?,
+                    Ok(_) => reply(control, 550, "Modification time not available")?,
+                    Err(e) => reply(control, 550, &e.to_string())?,
+                }
+            }
+            "MLST" => {
+                let path = resolve_path(&self.cwd, arg);
+                match self.shared.fs.lock().unwrap().stat(&path) {
+                    Ok(entry) => {
+                        let text = format!("250-Listing\r\n {} {}\r\n250 End\r\n", entry.facts(), display_path(&path));
+                        control.write_all(text.as_bytes())?;
+                    }
+                    Err(e) => reply(control, 550, &e.to_string())?,
+                }
+            }
+            "LIST" | "NLST" | "MLSD" => {
+                // 忽略ls风格的选项，例如"LIST -la"
+                let target = arg.split_whitespace().filter(|a| !a.starts_with('-')).collect::<Vec<_>>().join(" ");
+                let path = resolve_path(&self.cwd, &target);
+                let entries = self.shared.fs.lock().unwrap().list(&path);
+                match entries {
+                    Ok(entries) => {
+                        let listing: String = entries
+                            .iter()
+                            .map(|entry| match command {
+                                "LIST" => entry.list_line(),
+                                "NLST" => format!("{}\r\n", entry.name),
+                                _ => format!("{} {}\r\n", entry.facts(), entry.name),
+                            })
+                            .collect();
+                        self.transfer(control, |data| Ok(data.write_all(listing.as_bytes())?))?;
+                    }
+                    Err(e) => {
+                        self.passive = None;
+                        reply(control, 550, &e.to_string())?;
+                    }
+                }
+            }
+            "RETR" => {
+                let path = resolve_path(&self.cwd, arg);
+                let offset = std::mem::take(&mut self.rest);
+                // 只在打开文件时持有文件系统的锁，传输期间其他会话仍可列目录
+                let opened = self.shared.fs.lock().unwrap().open(&path);
+                let file = match opened {
+                    Ok(file) => file,
+                    Err(e) => {
+                        self.passive = None;
+                        return reply(control, 550, &e.to_string());
+                    }
+                };
+                info!("FTP客户端 {} 下载 {} (从 {} 字节开始)", self.peer, display_path(&path), offset);
+                self.transfer(control, |data| {
+                    file.read(offset, &mut |chunk| Ok(data.write_all(chunk)?))?;
+                    Ok(())
+                })?;
+            }
+            "ABOR" => {
+                self.passive = None;
+                reply(control, 225, "No transfer in progress")?;
+            }
+            "STOR" | "STOU" | "APPE" | "DELE" | "MKD" | "XMKD" | "RMD" | "XRMD" | "RNFR" | "RNTO" | "SITE" => {
+                reply(control, 550, "Read-only file system")?;
+            }
+            _ => reply(control, 502, "Command not implemented")?,
+        }
+        Ok(())
+    }
+
+    fn change_dir(&mut self, control: &mut FtpStream, path: Vec<String>) -> Result<(), Box<dyn Error>> {
+        let entry = self.shared.fs.lock().unwrap().stat(&path);
+        match entry {
+            Ok(entry) if entry.is_dir => {
+                self.cwd = path;
+                reply(control, 250, "Directory changed")
+            }
+            Ok(_) => reply(control, 550, "Not a directory"),
+            Err(e) => reply(control, 550, &e.to_string()),
+        }
+    }
+
+    /// 在被动模式端口范围内找一个空闲端口监听，返回端口号
+    fn open_passive(&mut self) -> Result<u16, Box<dyn Error>> {
+        let config = &self.shared.config;
+        if config.require_tls && !self.protect_data {
+            return Err("Data protection required, send PROT P".into());
+        }
+        self.passive = None;
+        let count = config.passive_ports as usize;
+        let first = self.shared.next_port.fetch_add(1, Ordering::Relaxed);
+        for i in 0..count {
+            let port = config.passive_port + ((first + i) % count) as u16;
+            if let Ok(listener) = TcpListener::bind((self.local, port)) {
+                listener.set_nonblocking(true)?;
+                self.passive = Some(listener);
+                return Ok(port);
+            }
+        }
+        Err("No passive port available".into())
+    }
+
+    /// 等待客户端连接数据端口，需要时完成TLS握手，执行传输后关闭数据连接并回复结果
+    fn transfer<F>(&mut self, control: &mut FtpStream, send: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnOnce(&mut FtpStream) -> Result<(), Box<dyn Error>>,
+    {
+        let Some(listener) = self.passive.take() else {
+            return reply(control, 425, "Use PASV or EPSV first");
+        };
+        reply(control, 150, "Opening data connection")?;
+        let mut data = match self.accept_data(&listener) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("FTP客户端 {} 的数据连接失败: {}", self.peer, e);
+                return reply(control, 425, "Cannot open data connection");
+            }
+        };
+        match send(&mut data).and_then(|()| Ok(data.flush()?)) {
+            Ok(()) => {
+                drop(data);
+                reply(control, 226, "Transfer complete")
+            }
+            Err(e) => {
+                warn!("FTP客户端 {} 传输中断: {}", self.peer, e);
+                drop(data);
+                reply(control, 451, "Transfer aborted")
+            }
+        }
+    }
+
+    fn accept_data(&self, listener: &TcpListener) -> Result<FtpStream, Box<dyn Error>> {
+        let deadline = Instant::now() + DATA_ACCEPT_TIMEOUT;
+        let stream = loop {
+            match listener.accept() {
+                // 只接受控制连接的客户端，避免其他主机抢先连上数据端口
+                Ok((stream, peer)) if peer.ip() == self.peer => break stream,
+                Ok((_, peer)) => warn!("拒绝来自 {} 的FTP数据连接", peer),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if Instant::now() >= deadline {
+                        return Err("等待数据连接超时".into());
+                    }
+                    std::thread::sleep(ACCEPT_POLL_INTERVAL);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_write_timeout(Some(IDLE_TIMEOUT))?;
+        if !self.protect_data {
+            return Ok(FtpStream::Plain(stream));
+        }
+        let identity = self.shared.identity.as_ref().ok_or("未配置证书")?;
+        Ok(FtpStream::Tls(TlsServerStream::accept(stream, identity)?))
+    }
+}
+
+/// 回复一行响应
+fn reply(control: &mut FtpStream, code: u16, text: &str) -> Result<(), Box<dyn Error>> {
+    control.write_all(format!("{} {}\r\n", code, text).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments(parts: &[&str]) -> Vec<String> {
+        parts.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn relative_path_extends_cwd() {
+        let cwd = segments(&["00010001"]);
+        assert_eq!(resolve_path(&cwd, "DCIM/100CANON"), segments(&["00010001", "DCIM", "100CANON"]));
+        assert_eq!(resolve_path(&cwd, "./DCIM/"), segments(&["00010001", "DCIM"]));
+    }
+
+    #[test]
+    fn absolute_path_ignores_cwd() {
+        let cwd = segments(&["00010001", "DCIM"]);
+        assert_eq!(resolve_path(&cwd, "/00020001//MISC"), segments(&["00020001", "MISC"]));
+        assert_eq!(resolve_path(&cwd, "/"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn parent_never_escapes_root() {
+        let cwd = segments(&["00010001", "DCIM"]);
+        assert_eq!(resolve_path(&cwd, ".."), segments(&["00010001"]));
+        assert_eq!(resolve_path(&cwd, "../../../.."), Vec::<String>::new());
+        assert_eq!(resolve_path(&cwd, "/../00010001"), segments(&["00010001"]));
+        assert_eq!(display_path(&resolve_path(&cwd, "../../..")), "/");
+    }
+}
@@ -0,0 +1,552 @@
+// HTTP接口 - 提供 /login、/status 和 /handshake，客户端用预共享令牌换取会话令牌后查询设备状态并协商协议版本；
+// 可选的 /sync/stream 把待同步对象以multipart流输出，用于有线局域网快速导入；可选的 /ws/metrics 推送实时指标；
+// 可选的 /ws/push 推送取景画面和传输事件；可选的 /gallery 分页列出对象，/gallery/thumb 返回预取的缩略图；可选的 /debug/ptp-trace 返回最近的PTP事务记录；
+// 可选的 /config/export 和 /config/import 需要管理员令牌，用于批量部署时复制设备配置；可选的 /pair 用配对令牌换取预共享令牌
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+use embedded_svc::http::Method;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::server::ws::EspHttpWsConnection;
+use esp_idf_svc::http::server::{Configuration as HttpServerConfiguration, EspHttpServer};
+use esp_idf_svc::io::EspIOError;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::bundle::{ConfigStore, SecretMode};
+use crate::control::gallery::{self, GalleryFilter, SummaryHandle};
+use crate::control::{AuditAction, AuditLog, AuthError, AuthGuard, ControlChannel, ControlCommand, PairingManager};
+use crate::control::handshake::{self, ClientHello, DeviceHello, LinkCapabilities};
+use crate::data_transfer::prefetch::IdlePrefetcher;
+use crate::data_transfer::stream::{self, CameraObjectSource, MultipartWriter, PartOutcome};
+use crate::data_transfer::{ObjectLedger, PipelineHandle, StageMetricsHandle};
+use crate::ptp_mtp::{ObjectInfoCache, SharedCamera, TraceHandle};
+use crate::wireless::metrics::{self, MetricsProvider};
+use crate::wireless::push::{self, PushChannel};
+
+/// 握手请求体的最大长度
+const MAX_HANDSHAKE_BODY: usize = 1024;
+/// 导入的配置文件的最大长度
+const MAX_CONFIG_BODY: usize = 64 * 1024;
+
+/// POST /config/export 的请求体，没有请求体时省略密钥
+#[derive(Debug, Default, Deserialize)]
+struct ExportRequest {
+    passphrase: Option<String>, // 加密密钥的口令
+}
+
+/// POST /config/import 的请求体
+#[derive(Debug, Deserialize)]
+struct ImportRequest {
+    passphrase: Option<String>, // 导出时使用的口令，文件中的密钥未加密时可省略
+    bundle: serde_json::Value,  // 导出的配置文件
+}
+
+/// 运行时状态
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RuntimeStatus {
+    pub transfer_status: String,  // 传输状态
+    pub bytes_transferred: usize, // 已传输字节数
+    pub clients: usize,           // 已连接客户端数
+}
+
+/// /status 返回的内容：握手信息加运行时状态
+#[derive(Debug, Clone, Serialize)]
+struct StatusReport {
+    #[serde(flatten)]
+    device: DeviceHello,
+    #[serde(flatten)]
+    runtime: RuntimeStatus,
+}
+
+/// 运行时状态的来源
+pub type StatusProvider = Arc<dyn Fn() -> RuntimeStatus + Send + Sync>;
+
+/// HTTP接口服务器，随对象释放而停止
+pub struct HttpApi {
+    server: EspHttpServer<'static>,
+    stage_metrics: Option<StageMetricsHandle>,
+}
+
+impl HttpApi {
+    /// 在指定端口启动HTTP服务，`link`为握手中声明和协商的链路能力
+    /// /status 和 /handshake 需要携带 /login 返回的 `Authorization: Bearer 会话令牌`
+    pub fn start(
+        port: u16,
+        status: StatusProvider,
+        link: LinkCapabilities,
+        guard: Arc<Mutex<AuthGuard>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut server = EspHttpServer::new(&HttpServerConfiguration {
+            http_port: port,
+            ..Default::default()
+        })?;
+
+        // POST /login，请求头携带 `Authorization: Bearer 预共享令牌`，返回会话令牌
+        let login_guard = guard.clone();
+        server.fn_handler("/login", Method::Post, move |req| -> Result<(), EspIOError> {
+            let credential = bearer_token(req.header("Authorization"));
+            let (code, reply) = match login_guard.lock().unwrap().login(credential) {
+                Some(session) => (200, serde_json::json!({ "session": session })),
+                None => (401, serde_json::json!({ "error": AuthError::Unauthenticated.to_string() })),
+            };
+            let mut resp = req.into_response(code, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(reply.to_string().as_bytes())?;
+            Ok(())
+        })?;
+
+        let status_guard = guard.clone();
+        server.fn_handler("/status", Method::Get, move |req| -> Result<(), EspIOError> {
+            let token = bearer_token(req.header("Authorization"));
+            if let Err(e) = status_guard.lock().unwrap().authorize(ControlChannel::Http, token, &ControlCommand::GetStatus) {
+                let mut resp = req.into_status_response(auth_status(&e))?;
+                resp.write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+            let report = StatusReport {
+                device: DeviceHello::current(link),
+                runtime: status(),
+            };
+            let body = serde_json::to_vec(&report).unwrap_or_default();
+            let mut resp = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(&body)?;
+            Ok(())
+        })?;
+
+        server.fn_handler("/handshake", Method::Post, move |mut req| -> Result<(), EspIOError> {
+            let token = bearer_token(req.header("Authorization"));
+            if let Err(e) = guard.lock().unwrap().authorize(ControlChannel::Http, token, &ControlCommand::Handshake) {
+                let mut resp = req.into_status_response(auth_status(&e))?;
+                resp.write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+            let mut body = Vec::new();
+            let mut buf = [0u8; 256];
+            loop {
+                let n = req.read(&mut buf)?;
+                if n == 0 || body.len() + n > MAX_HANDSHAKE_BODY {
+                    body.extend_from_slice(&buf[..n.min(MAX_HANDSHAKE_BODY - body.len())]);
+                    break;
+                }
+                body.extend_from_slice(&buf[..n]);
+            }
+
+            let (code, reply) = match serde_json::from_slice::<ClientHello>(&body) {
+                Ok(hello) => match handshake::negotiate(&hello, link) {
+                    Ok(session) => (
+                        200,
+                        serde_json::json!({ "device": DeviceHello::current(link), "session": session }),
+                    ),
+                    Err(e) => (
+                        426,
+                        serde_json::json!({ "device": DeviceHello::current(link), "error": e.to_string() }),
+                    ),
+                },
+                Err(e) => {
+                    warn!("握手请求格式错误: {}", e);
+                    (400, serde_json::json!({ "error": e.to_string() }))
+                }
+            };
+            let mut resp = req.into_response(code, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(reply.to_string().as_bytes())?;
+            Ok(())
+        })?;
+
+        info!("HTTP接口已启动，端口 {}", port);
+        Ok(HttpApi { server, stage_metrics: None })
+    }
+
+    /// 记录之后注册的 /sync/stream 各阶段的指标
+    pub fn set_stage_metrics(&mut self, metrics: StageMetricsHandle) {
+        self.stage_metrics = Some(metrics);
+    }
+
+    /// 注册 GET /sync/stream?client=ID[&album=相册][&ack=1]
+    /// 逐个输出该客户端尚未确认的对象，对象数据经过与推送相同的流水线；
+    /// 需要该客户端的会话令牌，`ack=1` 时每个对象完整输出且摘要一致后即为其签发回执
+    pub fn serve_sync_stream(
+        &mut self,
+        ledger: Arc<Mutex<ObjectLedger>>,
+        camera: SharedCamera,
+        pipeline: PipelineHandle,
+        guard: Arc<Mutex<AuthGuard>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let stage_metrics = self.stage_metrics.clone();
+        self.server.fn_handler("/sync/stream", Method::Get, move |req| -> Result<(), EspIOError> {
+            let uri = req.uri().to_string();
+            let Some(client_id) = query_param(&uri, "client") else {
+                let mut resp = req.into_status_response(400)?;
+                resp.write_all("缺少client参数".as_bytes())?;
+                return Ok(());
+            };
+            let album = query_param(&uri, "album");
+            let ack = query_param(&uri, "ack").as_deref() == Some("1");
+            // 只能同步自己的对象：否则任何人都能读取其他客户端的待同步对象，或让其漏掉对象
+            let token = bearer_token(req.header("Authorization"));
+            let command = ControlCommand::AcknowledgeSync(client_id.clone());
+            match guard.lock().unwrap().authorize(ControlChannel::Http, token, &command) {
+                Ok(principal) if principal.client_id == client_id => {}
+                Ok(principal) => {
+                    warn!("客户端 {} 试图同步 {} 的对象", principal.client_id, client_id);
+                    let mut resp = req.into_status_response(403)?;
+                    resp.write_all("只能同步自己的对象".as_bytes())?;
+                    return Ok(());
+                }
+                Err(e) => {
+                    let mut resp = req.into_status_response(auth_status(&e))?;
+                    resp.write_all(e.to_string().as_bytes())?;
+                    return Ok(());
+                }
+            }
+
+            // 只在生成列表和签发回执时持有台账锁，输出对象期间不阻塞其他台账操作
+            let pending = stream::pending_for_client(&ledger.lock().unwrap(), &client_id, album.as_deref());
+            info!("向客户端 {} 流式输出 {} 个对象", client_id, pending.len());
+
+            let content_type = stream::content_type();
+            let mut resp = req.into_response(200, None, &[("Content-Type", content_type.as_str())])?;
+            let mut writer = MultipartWriter::new(|buf: &[u8]| resp.write_all(buf).map_err(|e| e.into()))
+                .with_pipeline(pipeline.clone());
+            if let Some(metrics) = &stage_metrics {
+                writer = writer.with_metrics(metrics.clone());
+            }
+            // 每读一块才锁一次相机，输出期间主循环仍可执行命令
+            let mut source = CameraObjectSource::new(camera.clone(), None);
+            for entry in &pending {
+                match writer.write_object(entry, &mut source) {
+                    Ok(PartOutcome::Verified) if ack => {
+                        if let Err(e) = ledger.lock().unwrap().acknowledge(entry.handle, &client_id) {
+                            warn!("签发对象 0x{:08x} 的回执失败: {}", entry.handle, e);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // 对象中途失败时multipart已无法继续解析，直接断开让客户端重新请求
+                        warn!("流式输出中断: {}", e);
+                        return Ok(());
+                    }
+                }
+            }
+            match writer.finish() {
+                Ok(report) => info!(
+                    "流式输出完成: {} 个对象，{} 字节，{} 个摘要不一致，{} 个被流水线丢弃",
+                    report.objects, report.bytes, report.mismatched, report.skipped
+                ),
+                Err(e) => warn!("流式输出结束时出错: {}", e),
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// 注册 GET /gallery?[after=游标][&limit=N][&album=相册][&pending_for=客户端ID][&format=格式代码]
+    /// 返回一页对象和下一页的游标，客户端按需滚动加载，不必先下载完整清单
+    /// 格式和拍摄时间优先取自批量读取的对象摘要，尺寸取自对象信息缓存；需要携带会话令牌
+    pub fn serve_gallery(
+        &mut self,
+        ledger: Arc<Mutex<ObjectLedger>>,
+        summaries: Option<SummaryHandle>,
+        info_cache: Option<Arc<Mutex<ObjectInfoCache>>>,
+        guard: Arc<Mutex<AuthGuard>>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.server.fn_handler("/gallery", Method::Get, move |req| -> Result<(), EspIOError> {
+            let uri = req.uri().to_string();
+            let filter = GalleryFilter {
+                album: query_param(&uri, "album"),
+                pending_for: query_param(&uri, "pending_for"),
+                format: query_param(&uri, "format").and_then(|f| f.parse().ok()),
+            };
+            let limit = query_param(&uri, "limit").and_then(|l| l.parse().ok()).unwrap_or(50);
+            let after = query_param(&uri, "after");
+            let token = bearer_token(req.header("Authorization"));
+            let command = ControlCommand::ListGallery { after: after.clone(), limit, filter: filter.clone() };
+            if let Err(e) = guard.lock().unwrap().authorize(ControlChannel::Http, token, &command) {
+                let mut resp = req.into_status_response(auth_status(&e))?;
+                resp.write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+            let result = {
+                let summaries = summaries.as_ref().map(|s| s.lock().unwrap());
+                let cache = info_cache.as_ref().map(|c| c.lock().unwrap());
+                gallery::list(&ledger.lock().unwrap(), summaries.as_deref(), cache.as_deref(), after.as_deref(), limit, &filter)
+            };
+            let (code, body) = match result {
+                Ok(page) => (200, serde_json::to_vec(&page).unwrap_or_default()),
+                Err(e) => (400, serde_json::json!({ "error": e.to_string() }).to_string().into_bytes()),
+            };
+            let mut resp = req.into_response(code, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(&body)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// 注册 GET /gallery/thumb?handle=句柄，返回空闲时预取的缩略图，尚未预取时返回404；需要携带会话令牌
+    pub fn serve_previews(&mut self, prefetcher: Arc<Mutex<IdlePrefetcher>>, guard: Arc<Mutex<AuthGuard>>) -> Result<(), Box<dyn Error>> {
+        self.server.fn_handler("/gallery/thumb", Method::Get, move |req| -> Result<(), EspIOError> {
+            let uri = req.uri().to_string();
+            let Some(handle) = query_param(&uri, "handle").and_then(|h| h.parse::<u32>().ok()) else {
+                let mut resp = req.into_status_response(400)?;
+                resp.write_all("缺少handle参数".as_bytes())?;
+                return Ok(());
+            };
+            let token = bearer_token(req.header("Authorization"));
+            if let Err(e) = guard.lock().unwrap().authorize(ControlChannel::Http, token, &ControlCommand::DownloadObject(handle)) {
+                let mut resp = req.into_status_response(auth_status(&e))?;
+                resp.write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+            let preview = match prefetcher.lock().unwrap().cache().get(handle) {
+                Ok(preview) => preview.filter(|p| !p.thumb.is_empty()),
+                Err(e) => {
+                    warn!("读取对象 0x{:08x} 的预览失败: {}", handle, e);
+                    None
+                }
+            };
+            match preview {
+                Some(preview) => {
+                    let mut resp = req.into_response(200, None, &[("Content-Type", "image/jpeg")])?;
+                    resp.write_all(&preview.thumb)?;
+                }
+                None => {
+                    req.into_status_response(404)?;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// 注册 POST /pair?client=名称，请求头携带 `Authorization: Bearer 配对令牌`(二维码中的tok)，
+    /// 成功时返回该客户端的预共享令牌，之后用它调用 /login
+    pub fn serve_pairing(&mut self, pairing: Arc<Mutex<PairingManager>>, guard: Arc<Mutex<AuthGuard>>) -> Result<(), Box<dyn Error>> {
+        self.server.fn_handler("/pair", Method::Post, move |req| -> Result<(), EspIOError> {
+            let uri = req.uri().to_string();
+            let Some(client_id) = query_param(&uri, "client") else {
+                let mut resp = req.into_status_response(400)?;
+                resp.write_all("缺少client参数".as_bytes())?;
+                return Ok(());
+            };
+            let token = bearer_token(req.header("Authorization"));
+            let result = pairing.lock().unwrap().accept(token, &client_id, &mut guard.lock().unwrap());
+            let (code, reply) = match result {
+                Ok(pre_shared) => (200, serde_json::json!({ "token": pre_shared })),
+                Err(e) => (401, serde_json::json!({ "error": e.to_string() })),
+            };
+            let mut resp = req.into_response(code, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(reply.to_string().as_bytes())?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// 注册 GET /debug/ptp-trace，以JSON数组返回追踪器中的容器记录(从旧到新)
+    /// 记录中含有相机返回的原始数据，与导出配置一样需要管理员的 `Authorization: Bearer 令牌`
+    pub fn serve_ptp_trace(&mut self, tracer: TraceHandle, guard: Arc<Mutex<AuthGuard>>) -> Result<(), Box<dyn Error>> {
+        self.server.fn_handler("/debug/ptp-trace", Method::Get, move |req| -> Result<(), EspIOError> {
+            let token = bearer_token(req.header("Authorization"));
+            if let Err(e) = guard.lock().unwrap().authorize(ControlChannel::Http, token, &ControlCommand::ExportConfig) {
+                let mut resp = req.into_status_response(auth_status(&e))?;
+                resp.write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+            let body = {
+                let tracer = tracer.lock().unwrap();
+                serde_json::to_vec(&tracer.entries().collect::<Vec<_>>()).unwrap_or_default()
+            };
+            let mut resp = req.into_response(200, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(&body)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// 注册 POST /config/export 和 POST /config/import，请求需携带管理员的 `Authorization: Bearer 令牌`
+    /// 口令放在JSON请求体中，不经过可能被代理记录的请求头；导出时没有口令则省略密钥，导入失败时设备上的配置保持不变
+    /// 每次导入无论成败都记入审计日志
+    pub fn serve_config(
+        &mut self,
+        store: Arc<Mutex<ConfigStore>>,
+        guard: Arc<Mutex<AuthGuard>>,
+        audit: Arc<Mutex<AuditLog>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let export_store = store.clone();
+        let export_guard = guard.clone();
+        self.server.fn_handler("/config/export", Method::Post, move |mut req| -> Result<(), EspIOError> {
+            let token = bearer_token(req.header("Authorization"));
+            if let Err(e) = export_guard.lock().unwrap().authorize(ControlChannel::Http, token, &ControlCommand::ExportConfig) {
+                let mut resp = req.into_status_response(auth_status(&e))?;
+                resp.write_all(e.to_string().as_bytes())?;
+                return Ok(());
+            }
+            let Some(body) = read_body(&mut req, MAX_HANDSHAKE_BODY)? else {
+                let mut resp = req.into_status_response(413)?;
+                resp.write_all("请求体过大".as_bytes())?;
+                return Ok(());
+            };
+            let request = if body.is_empty() {
+                Ok(ExportRequest::default())
+            } else {
+                serde_json::from_slice::<ExportRequest>(&body)
+            };
+            let result = request.map_err(Box::<dyn Error>::from).and_then(|request| {
+                let mode = match request.passphrase.as_deref() {
+                    Some(passphrase) => SecretMode::Encrypt(passphrase),
+                    None => SecretMode::Omit,
+                };
+                export_store.lock().unwrap().export(mode)
+            });
+            let (code, body) = match result {
+                Ok(body) => (200, body),
+                Err(e) => (400, serde_json::json!({ "error": e.to_string() }).to_string().into_bytes()),
+            };
+            let mut resp = req.into_response(code, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(&body)?;
+            Ok(())
+        })?;
+
+        self.server.fn_handler("/config/import", Method::Post, move |mut req| -> Result<(), EspIOError> {
+            let token = bearer_token(req.header("Authorization"));
+            let principal = match guard.lock().unwrap().authorize(ControlChannel::Http, token, &ControlCommand::ImportConfig) {
+                Ok(principal) => principal,
+                Err(e) => {
+                    let mut resp = req.into_status_response(auth_status(&e))?;
+                    resp.write_all(e.to_string().as_bytes())?;
+                    return Ok(());
+                }
+            };
+            let Some(body) = read_body(&mut req, MAX_CONFIG_BODY)? else {
+                let mut resp = req.into_status_response(413)?;
+                resp.write_all("配置文件过大".as_bytes())?;
+                return Ok(());
+            };
+            let result = serde_json::from_slice::<ImportRequest>(&body)
+                .map_err(|e| Box::<dyn Error>::from(format!("请求格式错误: {}", e)))
+                .and_then(|request| {
+                    let bundle = serde_json::to_vec(&request.bundle)?;
+                    let mut store = store.lock().unwrap();
+                    let device_name = store.import(&bundle, request.passphrase.as_deref())?.device_name.clone();
+                    Ok(device_name)
+                });
+            let outcome = result.as_ref().map(|_| ()).map_err(|e| e.as_ref());
+            if let Err(e) = audit.lock().unwrap().record(AuditAction::ConfigChange, 0, &principal.client_id, outcome) {
+                warn!("写入审计日志失败: {}", e);
+            }
+            let (code, reply) = match result {
+                Ok(device_name) => (200, serde_json::json!({ "device_name": device_name })),
+                Err(e) => {
+                    warn!("导入配置失败: {}", e);
+                    (400, serde_json::json!({ "error": e.to_string() }))
+                }
+            };
+            let mut resp = req.into_response(code, None, &[("Content-Type", "application/json")])?;
+            resp.write_all(reply.to_string().as_bytes())?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// 注册 /ws/metrics 实时指标通道，升级请求需携带会话令牌
+    pub fn serve_metrics(
+        &mut self,
+        provider: MetricsProvider,
+        interval: std::time::Duration,
+        guard: Arc<Mutex<AuthGuard>>,
+    ) -> Result<(), Box<dyn Error>> {
+        metrics::serve(&mut self.server, provider, interval, guard)
+    }
+
+    /// 注册 /ws/push 实时推送通道，返回的发布端用于推送取景画面、传输进度和事件
+    pub fn serve_push(&mut self, guard: Arc<Mutex<AuthGuard>>) -> Result<PushChannel, Box<dyn Error>> {
+        push::serve(&mut self.server, guard)
+    }
+
+    /// 底层服务器，用于注册其他路由
+    pub fn server_mut(&mut self) -> &mut EspHttpServer<'static> {
+        &mut self.server
+    }
+}
+
+/// 读取完整的请求体，超过`max`字节时返回None
+fn read_body(req: &mut impl Read<Error = EspIOError>, max: usize) -> Result<Option<Vec<u8>>, EspIOError> {
+    let mut body = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let n = req.read(&mut buf)?;
+        if n == 0 {
+            return Ok(Some(body));
+        }
+        if body.len() + n > max {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// 从Authorization请求头取出Bearer令牌
+fn bearer_token(header: Option<&str>) -> &str {
+    header.and_then(|h| h.strip_prefix("Bearer ")).map(str::trim).unwrap_or("")
+}
+
+/// 从WebSocket升级请求的Authorization请求头取出Bearer令牌；连接已经建立或没有该请求头时返回空串
+pub(crate) fn ws_bearer_token(ws: &EspHttpWsConnection) -> String {
+    let EspHttpWsConnection::New(_, raw_req) = ws else {
+        return String::new();
+    };
+    let name = c"Authorization";
+    let len = unsafe { esp_idf_svc::sys::httpd_req_get_hdr_value_len(*raw_req, name.as_ptr()) };
+    if len == 0 {
+        return String::new();
+    }
+    let mut buf = vec![0u8; len + 1];
+    let err = unsafe {
+        esp_idf_svc::sys::httpd_req_get_hdr_value_str(*raw_req, name.as_ptr(), buf.as_mut_ptr().cast(), buf.len())
+    };
+    if err != esp_idf_svc::sys::ESP_OK {
+        return String::new();
+    }
+    buf.truncate(len);
+    String::from_utf8(buf).map(|header| bearer_token(Some(&header)).to_string()).unwrap_or_default()
+}
+
+fn auth_status(error: &AuthError) -> u16 {
+    match error {
+        AuthError::Unauthenticated => 401,
+        AuthError::Forbidden { .. } => 403,
+    }
+}
+
+/// 从URI中取出查询参数并做百分号解码
+fn query_param(uri: &str, key: &str) -> Option<String> {
+    let (_, query) = uri.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| percent_decode(v))
+        .filter(|v| !v.is_empty())
+}
+
+pub(crate) fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
@@ -0,0 +1,96 @@
+// WiFi接口的IPv4地址配置 - STA接口使用DHCP或固定地址，SoftAP可指定自身地址和DHCP地址池
+//
+// esp-netif的地址方式在创建接口时确定，因此改用固定地址时重新创建接口并替换驱动中的接口；
+// 地址池只能在DHCP服务器停止时修改，需要在SoftAP启动后设置
+use std::error::Error;
+use std::ffi::c_void;
+use std::net::Ipv4Addr;
+
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::ipv4::{
+    ClientConfiguration, ClientSettings, Configuration as IpConfiguration, Mask, RouterConfiguration, Subnet,
+};
+use esp_idf_svc::netif::{EspNetif, NetifConfiguration};
+use esp_idf_svc::sys::{
+    dhcps_lease_t, esp, esp_ip4_addr_t, esp_netif_dhcp_option_id_t_ESP_NETIF_REQUESTED_IP_ADDRESS,
+    esp_netif_dhcp_option_mode_t_ESP_NETIF_OP_SET, esp_netif_dhcps_option, esp_netif_dhcps_start,
+    esp_netif_dhcps_stop,
+};
+use esp_idf_svc::wifi::EspWifi;
+use log::info;
+
+use crate::config::{ApAddress, StaAddress};
+
+/// 按配置重新创建STA接口，需在连接前调用
+pub fn configure_sta(wifi: &mut EspWifi<'static>, address: &StaAddress) -> Result<(), Box<dyn Error>> {
+    let client = match address {
+        StaAddress::Dhcp => ClientConfiguration::DHCP(Default::default()),
+        StaAddress::Static(ip) => {
+            info!("STA接口使用固定地址 {}/{}，网关 {}", ip.ip, ip.prefix_len, ip.gateway);
+            ClientConfiguration::Fixed(ClientSettings {
+                ip: ip.ip,
+                subnet: Subnet {
+                    gateway: ip.gateway,
+                    mask: Mask(ip.prefix_len),
+                },
+                dns: ip.dns,
+                secondary_dns: ip.secondary_dns,
+            })
+        }
+    };
+    let netif = EspNetif::new_with_conf(&NetifConfiguration {
+        ip_configuration: Some(IpConfiguration::Client(client)),
+        ..NetifConfiguration::wifi_default_client()
+    })?;
+    wifi.swap_netif_sta(netif)?;
+    Ok(())
+}
+
+/// 按配置重新创建SoftAP接口，None时恢复默认网段；需在SoftAP启动前调用
+pub fn configure_ap(wifi: &mut EspWifi<'static>, address: Option<&ApAddress>) -> Result<(), Box<dyn Error>> {
+    let mut conf = NetifConfiguration::wifi_default_router();
+    if let Some(address) = address {
+        conf.ip_configuration = Some(IpConfiguration::Router(RouterConfiguration {
+            subnet: Subnet {
+                gateway: address.ip,
+                mask: Mask(address.prefix_len),
+            },
+            dhcp_enabled: true,
+            dns: address.dns,
+            secondary_dns: None,
+        }));
+    }
+    wifi.swap_netif_ap(EspNetif::new_with_conf(&conf)?)?;
+    Ok(())
+}
+
+/// 设置SoftAP的DHCP地址池，需在SoftAP启动后调用
+pub fn set_dhcp_pool(wifi: &EspWifi<'static>, address: &ApAddress) -> Result<(), Box<dyn Error>> {
+    let ap = wifi.ap_netif().handle();
+    let mut lease = dhcps_lease_t {
+        enable: true,
+        start_ip: ip4_addr(address.pool_start),
+        end_ip: ip4_addr(address.pool_end),
+    };
+    unsafe {
+        // DHCP服务器运行时不能修改选项；已停止时返回的错误可以忽略
+        let _ = esp_netif_dhcps_stop(ap);
+        esp!(esp_netif_dhcps_option(
+            ap,
+            esp_netif_dhcp_option_mode_t_ESP_NETIF_OP_SET,
+            esp_netif_dhcp_option_id_t_ESP_NETIF_REQUESTED_IP_ADDRESS,
+            &mut lease as *mut dhcps_lease_t as *mut c_void,
+            std::mem::size_of::<dhcps_lease_t>() as u32,
+        ))?;
+        esp!(esp_netif_dhcps_start(ap))?;
+    }
+    info!("SoftAP的DHCP地址池: {} - {}", address.pool_start, address.pool_end);
+    Ok(())
+}
+
+/// lwIP的地址按网络字节序存放
+fn ip4_addr(ip: Ipv4Addr) -> esp_ip4_addr_t {
+    esp_ip4_addr_t {
+        addr: u32::from_ne_bytes(ip.octets()),
+    }
+}
@@ -0,0 +1,154 @@
+// 链路统计 - 汇总当前链路的信号强度、协商的PHY速率、滑动窗口吞吐、重传次数和蓝牙MTU，
+// 传输层据此调整分块大小，界面据此显示信号质量。吞吐和重传由发送器记录到与WirelessManager共享的LinkMeter，
+// 信号强度和速率在查询时从驱动读取
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// 统计吞吐的默认窗口
+#[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(5);
+/// 建议分块大小的范围
+pub const MIN_CHUNK_SIZE: u32 = 4 * 1024;
+pub const MAX_CHUNK_SIZE: u32 = 64 * 1024;
+/// 建议的分块在这么长时间内能发完，链路变慢时分块随之变小，取消和切换对象更及时
+const CHUNK_BUDGET_MS: u64 = 250;
+
+/// 某一时刻的链路统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LinkStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rssi: Option<i8>,           // 信号强度(dBm)，SoftAP取信号最弱的客户端，有线链路为None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phy_rate_kbps: Option<u32>, // 按协商的PHY模式和带宽可达到的最高速率
+    pub throughput_bps: u64,        // 窗口内实际发送的字节/秒
+    pub retransmissions: u64,       // 累计重发的帧数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ble_mtu: Option<u16>,       // 蓝牙连接协商的MTU，多个连接时取最小值
+}
+
+impl LinkStats {
+    /// 按当前吞吐建议的分块大小，还没有吞吐数据时取上限
+    pub fn suggested_chunk_size(&self) -> u32 {
+        if self.throughput_bps == 0 {
+            return MAX_CHUNK_SIZE;
+        }
+        let budget = self.throughput_bps.saturating_mul(CHUNK_BUDGET_MS) / 1000;
+        budget.clamp(MIN_CHUNK_SIZE as u64, MAX_CHUNK_SIZE as u64) as u32
+    }
+}
+
+/// 发送器记录的吞吐和重传
+#[derive(Debug)]
+pub struct LinkMeter {
+    window: Duration,
+    samples: VecDeque<(Instant, usize)>, // 窗口内每次发送的时刻和字节数
+    window_bytes: u64,
+    retransmissions: u64,
+}
+
+/// WirelessManager和发送器共享的统计
+pub type LinkMeterHandle = Arc<Mutex<LinkMeter>>;
+
+/// 创建共享的统计，使用默认窗口
+#[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
+pub fn handle() -> LinkMeterHandle {
+    Arc::new(Mutex::new(LinkMeter::new(DEFAULT_WINDOW)))
+}
+
+impl LinkMeter {
+    pub fn new(window: Duration) -> Self {
+        LinkMeter {
+            window,
+            samples: VecDeque::new(),
+            window_bytes: 0,
+            retransmissions: 0,
+        }
+    }
+
+    /// 记录一次成功发送的字节数
+    pub fn record_sent(&mut self, bytes: usize) {
+        self.record_sent_at(bytes, Instant::now());
+    }
+
+    pub fn record_sent_at(&mut self, bytes: usize, now: Instant) {
+        self.expire(now);
+        self.samples.push_back((now, bytes));
+        self.window_bytes += bytes as u64;
+    }
+
+    /// 记录重发的帧数
+    pub fn record_retransmits(&mut self, frames: u64) {
+        self.retransmissions += frames;
+    }
+
+    /// 累计重发的帧数
+    pub fn retransmissions(&self) -> u64 {
+        self.retransmissions
+    }
+
+    /// 窗口内的平均吞吐(字节/秒)
+    pub fn throughput_bps(&mut self, now: Instant) -> u64 {
+        self.expire(now);
+        self.window_bytes * 1000 / self.window.as_millis().max(1) as u64
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(at, bytes)) = self.samples.front() {
+            if now.duration_since(at) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+            self.window_bytes -= bytes as u64;
+        }
+    }
+}
+
+/// 按PHY模式和带宽估算的最高速率(单流，短保护间隔)
+#[cfg(feature = "wifi")]
+fn phy_rate_kbps(phy_11b: bool, phy_11g: bool, phy_11n: bool, ht40: bool) -> Option<u32> {
+    match (phy_11n, phy_11g, phy_11b) {
+        (true, _, _) if ht40 => Some(150_000),
+        (true, _, _) => Some(72_200),
+        (false, true, _) => Some(54_000),
+        (false, false, true) => Some(11_000),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "wifi")]
+fn is_ht40(interface: esp_idf_svc::sys::wifi_interface_t) -> bool {
+    let mut bandwidth = esp_idf_svc::sys::wifi_bandwidth_t_WIFI_BW_HT20;
+    let err = unsafe { esp_idf_svc::sys::esp_wifi_get_bandwidth(interface, &mut bandwidth) };
+    err == esp_idf_svc::sys::ESP_OK && bandwidth == esp_idf_svc::sys::wifi_bandwidth_t_WIFI_BW_HT40
+}
+
+/// STA连接的接入点的信号强度和速率，未连接时返回None
+#[cfg(feature = "wifi")]
+pub fn sta_link() -> Option<(i8, Option<u32>)> {
+    let mut info = esp_idf_svc::sys::wifi_ap_record_t::default();
+    let err = unsafe { esp_idf_svc::sys::esp_wifi_sta_get_ap_info(&mut info) };
+    if err != esp_idf_svc::sys::ESP_OK {
+        return None;
+    }
+    let ht40 = is_ht40(esp_idf_svc::sys::wifi_interface_t_WIFI_IF_STA);
+    let rate = phy_rate_kbps(info.phy_11b() != 0, info.phy_11g() != 0, info.phy_11n() != 0, ht40);
+    Some((info.rssi, rate))
+}
+
+/// SoftAP上信号最弱的客户端的信号强度和速率，链路质量由最差的客户端决定；没有客户端时返回None
+#[cfg(feature = "wifi")]
+pub fn ap_link() -> Option<(i8, Option<u32>)> {
+    let mut list = esp_idf_svc::sys::wifi_sta_list_t::default();
+    let err = unsafe { esp_idf_svc::sys::esp_wifi_ap_get_sta_list(&mut list) };
+    if err != esp_idf_svc::sys::ESP_OK {
+        return None;
+    }
+    let ht40 = is_ht40(esp_idf_svc::sys::wifi_interface_t_WIFI_IF_AP);
+    list.sta[..list.num.max(0) as usize]
+        .iter()
+        .min_by_key(|sta| sta.rssi)
+        .map(|sta| (sta.rssi, phy_rate_kbps(sta.phy_11b() != 0, sta.phy_11g() != 0, sta.phy_11n() != 0, ht40)))
+}
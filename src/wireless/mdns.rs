@@ -0,0 +1,79 @@
+// mDNS服务发现 - 以_rcamera._tcp.local广播设备，TXT记录带设备名称、协议版本和当前相机型号，
+// 手机应用在局域网内直接发现设备，不需要写死IP；更换相机后更新TXT记录
+//
+// 需要ESP-IDF的espressif/mdns组件(见Cargo.toml中的extra_components)
+use std::error::Error;
+
+use esp_idf_svc::mdns::EspMdns;
+use log::{info, warn};
+
+use crate::control::handshake::PROTOCOL_VERSION;
+
+/// 服务类型和协议
+pub const SERVICE_TYPE: &str = "_rcamera";
+pub const SERVICE_PROTO: &str = "_tcp";
+
+/// 主机名的最大长度(DNS标签的限制)
+const MAX_HOSTNAME_LEN: usize = 63;
+
+/// 由设备名称生成主机名：只保留字母、数字和连字符，全部小写，例如ESP32Camera -> esp32camera.local
+pub fn hostname(device_name: &str) -> String {
+    let name: String = device_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let name = name.trim_matches('-');
+    let name = if name.is_empty() { "rcamera" } else { name };
+    name.chars().take(MAX_HOSTNAME_LEN).collect()
+}
+
+/// mDNS服务广播，随对象释放而停止
+pub struct ServiceAdvertiser {
+    mdns: EspMdns,
+    device_name: String,
+    port: u16,
+    camera_model: Option<String>,
+}
+
+impl ServiceAdvertiser {
+    /// 开始在`port`上广播服务，`camera_model`为当前连接的相机型号
+    pub fn start(device_name: &str, port: u16, camera_model: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let mut mdns = EspMdns::take()?;
+        let host = hostname(device_name);
+        mdns.set_hostname(&host)?;
+        mdns.set_instance_name(device_name)?;
+        let mut advertiser = ServiceAdvertiser {
+            mdns,
+            device_name: device_name.to_string(),
+            port,
+            camera_model: camera_model.map(str::to_string),
+        };
+        advertiser.publish()?;
+        info!("mDNS: {}.local 广播 {}.{}.local 端口 {}", host, SERVICE_TYPE, SERVICE_PROTO, port);
+        Ok(advertiser)
+    }
+
+    /// 更新TXT记录中的相机型号，None表示当前没有连接相机
+    pub fn set_camera_model(&mut self, model: Option<&str>) -> Result<(), Box<dyn Error>> {
+        if self.camera_model.as_deref() == model {
+            return Ok(());
+        }
+        self.camera_model = model.map(str::to_string);
+        // 重新注册服务，让已缓存旧记录的手机收到更新
+        if let Err(e) = self.mdns.remove_service(SERVICE_TYPE, SERVICE_PROTO) {
+            warn!("移除mDNS服务失败: {}", e);
+        }
+        self.publish()
+    }
+
+    fn publish(&mut self) -> Result<(), Box<dyn Error>> {
+        let protocol = PROTOCOL_VERSION.to_string();
+        let mut txt = vec![("name", self.device_name.as_str()), ("protocol", protocol.as_str())];
+        if let Some(model) = &self.camera_model {
+            txt.push(("model", model.as_str()));
+        }
+        self.mdns
+            .add_service(Some(&self.device_name), SERVICE_TYPE, SERVICE_PROTO, self.port, &txt)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+// 实时传输指标 - 通过 /ws/metrics 周期性推送队列深度、吞吐量、各任务进度和链路质量，
+// 与批量数据通道分开，供内置网页和外部看板使用
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::http::server::ws::EspHttpWsDetachedSender;
+use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::sys::{EspError, ESP_FAIL};
+use esp_idf_svc::ws::FrameType;
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use crate::data_transfer::stage_metrics::StageReport;
+use crate::control::{AuthGuard, ControlChannel, ControlCommand, ControlLatency};
+use crate::data_transfer::{BottleneckReport, IntegrityStats};
+use crate::ptp_mtp::ObjectProgress;
+use crate::runtime;
+use crate::wireless::http::ws_bearer_token;
+
+/// 推送间隔的下限，避免占满无线带宽
+const MIN_INTERVAL: Duration = Duration::from_millis(200);
+
+/// 单个下载任务的进度
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct JobProgress {
+    #[serde(rename = "h")]
+    pub handle: u32,
+    #[serde(rename = "d")]
+    pub bytes_done: u64,
+    #[serde(rename = "t")]
+    pub total: u64,
+}
+
+impl From<ObjectProgress> for JobProgress {
+    fn from(p: ObjectProgress) -> Self {
+        JobProgress {
+            handle: p.handle,
+            bytes_done: p.bytes_done,
+            total: p.total,
+        }
+    }
+}
+
+/// 由运行时提供的原始指标
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSample {
+    pub queue_depth: usize,       // 等待下载的对象数
+    pub bytes_transferred: u64,   // 累计发送字节数，吞吐量由相邻两次采样计算
+    pub jobs: Vec<JobProgress>,   // 进行中的任务
+    pub rssi: Option<i8>,         // WiFi信号强度(dBm)，有线或蓝牙链路为None
+    pub channel: Option<u8>,      // WiFi信道，通常取自`channel::current_channel`
+    pub stages: Option<BottleneckReport>, // 分阶段指标，通常取自`StageMetrics::take_report`
+    pub integrity: Option<IntegrityStats>, // 完整性复检统计，通常取自`IntegrityVerifier::stats`
+    pub control: Option<ControlLatency>,   // 控制命令等待时间，通常取自`ControlQueue::latency`
+}
+
+/// 推送给客户端的指标，字段名尽量短以减小帧长度
+#[derive(Debug, Clone, Serialize)]
+struct MetricsFrame<'a> {
+    #[serde(rename = "ts")]
+    uptime_ms: u64,
+    #[serde(rename = "q")]
+    queue_depth: usize,
+    #[serde(rename = "bps")]
+    throughput: u64,
+    #[serde(rename = "jobs")]
+    jobs: &'a [JobProgress],
+    #[serde(rename = "rssi", skip_serializing_if = "Option::is_none")]
+    rssi: Option<i8>,
+    #[serde(rename = "ch", skip_serializing_if = "Option::is_none")]
+    channel: Option<u8>,
+    #[serde(rename = "stg", skip_serializing_if = "<[_]>::is_empty")]
+    stages: &'a [StageReport],
+    #[serde(rename = "bn", skip_serializing_if = "Option::is_none")]
+    bottleneck: Option<&'a str>,
+    #[serde(rename = "ivf", skip_serializing_if = "Option::is_none")]
+    integrity: Option<IntegrityStats>,
+    #[serde(rename = "ctl", skip_serializing_if = "Option::is_none")]
+    control: Option<ControlLatency>,
+}
+
+/// 当前连接的WiFi接入点的信号强度，未连接时返回None
+pub fn wifi_rssi() -> Option<i8> {
+    let mut info = esp_idf_svc::sys::wifi_ap_record_t::default();
+    let err = unsafe { esp_idf_svc::sys::esp_wifi_sta_get_ap_info(&mut info) };
+    (err == esp_idf_svc::sys::ESP_OK).then_some(info.rssi)
+}
+
+/// 指标采样函数
+pub type MetricsProvider = Arc<dyn Fn() -> MetricsSample + Send + Sync>;
+
+/// 已订阅的WebSocket客户端
+type Subscribers = Arc<Mutex<Vec<EspHttpWsDetachedSender>>>;
+
+/// 注册 /ws/metrics，并启动按`interval`推送的后台线程
+/// 升级请求需携带 `Authorization: Bearer 会话令牌`，鉴权失败时关闭连接
+pub fn serve(
+    server: &mut EspHttpServer<'static>,
+    provider: MetricsProvider,
+    interval: Duration,
+    guard: Arc<Mutex<AuthGuard>>,
+) -> Result<(), Box<dyn Error>> {
+    let subscribers: Subscribers = Arc::new(Mutex::new(Vec::new()));
+
+    let subs = subscribers.clone();
+    server.ws_handler("/ws/metrics", move |ws| {
+        if ws.is_new() {
+            let token = ws_bearer_token(ws);
+            if let Err(e) = guard.lock().unwrap().authorize(ControlChannel::WebSocket, &token, &ControlCommand::GetStatus) {
+                warn!("拒绝指标订阅，会话 {}: {}", ws.session(), e);
+                return Err(EspError::from_infallible::<ESP_FAIL>());
+            }
+            subs.lock().unwrap().push(ws.create_detached_sender()?);
+            debug!("指标订阅者加入，会话 {}", ws.session());
+        }
+        // 客户端发来的消息和关闭帧都不需要处理，断开的连接在推送失败时移除
+        Ok::<(), EspError>(())
+    })?;
+
+    let interval = interval.max(MIN_INTERVAL);
+    runtime::spawn(runtime::WS_METRICS, move || publish_loop(subscribers, provider, interval))?;
+    info!("实时指标通道已启动，推送间隔 {}ms", interval.as_millis());
+    Ok(())
+}
+
+fn publish_loop(subscribers: Subscribers, provider: MetricsProvider, interval: Duration) {
+    let started = Instant::now();
+    let mut last_bytes = provider().bytes_transferred;
+    let mut last_at = Instant::now();
+    loop {
+        thread::sleep(interval);
+        // 没有订阅者时也采样，保证吞吐量始终按相邻两次采样计算
+        let sample = provider();
+        let now = Instant::now();
+        let elapsed = now.duration_since(last_at).as_millis().max(1) as u64;
+        let throughput = sample.bytes_transferred.saturating_sub(last_bytes) * 1000 / elapsed;
+        last_bytes = sample.bytes_transferred;
+        last_at = now;
+
+        let mut subs = subscribers.lock().unwrap();
+        if subs.is_empty() {
+            continue;
+        }
+        let frame = MetricsFrame {
+            uptime_ms: started.elapsed().as_millis() as u64,
+            queue_depth: sample.queue_depth,
+            throughput,
+            jobs: &sample.jobs,
+            rssi: sample.rssi,
+            channel: sample.channel,
+            stages: sample.stages.as_ref().map_or(&[], |r| r.stages.as_slice()),
+            bottleneck: sample.stages.as_ref().and_then(|r| r.bottleneck.as_deref()),
+            integrity: sample.integrity,
+            control: sample.control,
+        };
+        let Ok(json) = serde_json::to_vec(&frame) else {
+            continue;
+        };
+        subs.retain_mut(|sender| match sender.send(FrameType::Text(false), &json) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("指标订阅者已断开: {}", e);
+                false
+            }
+        });
+    }
+}
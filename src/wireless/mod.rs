@@ -1,33 +1,165 @@
 // 无线连接模块 - 负责ESP32与手机之间的蓝牙/WiFi通信
-use embedded_svc::wifi::{AuthMethod, ClientConfiguration, Configuration};
+// WiFi部分由 `wifi` feature 控制，蓝牙部分由 `ble` feature 控制，有线以太网由 `ethernet` feature 控制
+// 三者都关闭时(例如主机上的单元测试)只剩下空壳，按连接类型分派的代码随对应的feature一起裁掉
+#[cfg(any(feature = "wifi", feature = "ble"))]
+use embassy_time::{Duration as EmbassyDuration, Timer};
+#[cfg(feature = "wifi")]
+use embedded_svc::wifi::{AccessPointInfo, Configuration};
+#[cfg(feature = "ble")]
 use enumset::enum_set;
+#[cfg(feature = "ble")]
 use esp_idf_svc::bt::ble::gap::{AdvConfiguration, BleGapEvent, EspBleGap};
+#[cfg(feature = "ble")]
 use esp_idf_svc::bt::ble::gatt::server::{ConnectionId, EspGatts, GattsEvent, TransferId};
+#[cfg(feature = "ble")]
 use esp_idf_svc::bt::ble::gatt::{
     AutoResponse, GattCharacteristic, GattDescriptor, GattId, GattInterface, GattResponse,
     GattServiceId, GattStatus, Handle, Permission, Property,
 };
+#[cfg(feature = "ble")]
 use esp_idf_svc::bt::{BdAddr, Ble as EspBle, BtDriver, BtStatus, BtUuid};
+#[cfg(feature = "wifi")]
 use esp_idf_svc::eventloop::EspSystemEventLoop;
+#[cfg(any(feature = "wifi", feature = "ble"))]
 use esp_idf_svc::nvs::EspDefaultNvsPartition;
+#[cfg(feature = "ble")]
 use esp_idf_svc::sys::EspError;
-use esp_idf_svc::wifi::EspWifi;
+#[cfg(feature = "wifi")]
+use esp_idf_svc::wifi::{config::ScanConfig, EspWifi};
+#[cfg(feature = "ble")]
 use heapless::Vec as HVec;
+#[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
 use log::{debug, info, warn};
 use std::env;
 use std::error::Error;
-use std::io::Write;
+use std::future::Future;
+use std::pin::Pin;
+#[cfg(feature = "ble")]
 use std::sync::{Arc, Condvar, Mutex};
+#[cfg(any(feature = "ble", feature = "wifi"))]
+use std::time::Instant;
+
+#[cfg(feature = "wifi")]
+use crate::config::ChannelPolicy;
+use crate::ptp_mtp::DataPacket;
+#[cfg(feature = "wifi")]
+use crate::events::AppEvent;
+#[cfg(feature = "wifi")]
+pub use crate::config::{ApAddress, StaAddress, StaticIp, UpstreamWifi, WifiAuth, WirelessMode};
+#[cfg(feature = "wifi")]
+pub use channel::{ScannedNetwork, SoftApSettings};
+pub use link_stats::{LinkMeter, LinkMeterHandle, LinkStats};
+#[cfg(feature = "ethernet")]
+use crate::config::EthernetConfig;
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
+use crate::config::TlsSettings;
+#[cfg(feature = "ble")]
+use crate::control::{ble_opcode, ble_response, AuthGuard, CommandSender, ControlChannel, ControlCommand, PairingError, PairingManager};
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
+use framing::FramedSender;
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
+use tls::{LinkStream, TlsMaterial};
+#[cfg(feature = "ble")]
+use new_objects::NewObjectCounter;
+
+/// 自定义服务UUID
+#[cfg(feature = "ble")]
+const SERVICE_UUID: u128 = 0xad91b201734740479e173bed82d75f9d;
+/// 新对象计数特征值UUID(读取/通知，小端u32)
+#[cfg(feature = "ble")]
+const NEW_OBJECTS_CHARACTERISTIC_UUID: u128 = 0x7c1e3f0a5b2d4e8f9a61c2d4b8e05f13;
+
+/// AP+STA模式下等待连上上级网络的时间
+#[cfg(feature = "wifi")]
+const STA_UP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+/// TCP发送器等待手机确认的超时
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
+const ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// 异步扫描的超时和轮询间隔
+#[cfg(feature = "wifi")]
+const SCAN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+#[cfg(feature = "wifi")]
+const SCAN_POLL_INTERVAL_MS: u64 = 100;
+/// 蓝牙发送器等待indication确认的轮询间隔
+#[cfg(feature = "ble")]
+const INDICATION_POLL_INTERVAL_MS: u64 = 10;
+
+#[cfg(any(feature = "ble", test))]
+pub(crate) mod ble_frame;
+#[cfg(feature = "wifi")]
+pub mod channel;
+#[cfg(feature = "wifi")]
+pub mod delta;
+#[cfg(any(feature = "espnow", test))]
+pub mod espnow;
+#[cfg(feature = "ethernet")]
+pub mod ethernet;
+#[cfg(any(feature = "wifi", feature = "ethernet", feature = "ble", test))]
+pub(crate) mod framing;
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
+pub mod ftp;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "wifi")]
+pub(crate) mod ip;
+pub(crate) mod link_stats;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(feature = "http")]
+pub mod metrics;
+#[cfg(feature = "wifi")]
+pub(crate) mod nat;
+#[cfg(feature = "ble")]
+pub(crate) mod new_objects;
+#[cfg(any(feature = "http", test))]
+pub mod push;
+#[cfg(feature = "http")]
+pub mod provision;
+#[cfg(feature = "wifi")]
+pub(crate) mod reconnect;
+#[cfg(feature = "wifi")]
+pub mod s3;
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
+pub mod server;
+#[cfg(feature = "sftp")]
+pub mod sftp;
+#[cfg(feature = "wifi")]
+pub(crate) mod sta;
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
+pub mod tls;
+#[cfg(any(feature = "wifi", feature = "ethernet", test))]
+pub mod udp;
+#[cfg(feature = "wifi")]
+pub mod webhook;
 
 /// 无线连接类型
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ConnectionType {
+    #[cfg(feature = "wifi")]
     WiFi,
+    #[cfg(feature = "ble")]
     Bluetooth,
+    #[cfg(feature = "ethernet")]
+    Ethernet,
+}
+
+impl ConnectionType {
+    /// 是否提供IP链路，HTTP接口和TCP服务端只在IP链路上启动
+    pub fn carries_ip(self) -> bool {
+        match self {
+            #[cfg(feature = "wifi")]
+            ConnectionType::WiFi => true,
+            #[cfg(feature = "ble")]
+            ConnectionType::Bluetooth => false,
+            #[cfg(feature = "ethernet")]
+            ConnectionType::Ethernet => true,
+        }
+    }
 }
 
 // WiFi凭证将在运行时从环境变量获取，而不是编译时
 // 使用 env::var 替代 env! 宏
+#[allow(dead_code)]
 fn get_wifi_credentials() -> Result<(String, String), Box<dyn Error>> {
     let ssid = env::var("WIFI_SSID")?;
     let password = env::var("WIFI_PASS")?;
@@ -35,17 +167,26 @@ fn get_wifi_credentials() -> Result<(String, String), Box<dyn Error>> {
 }
 
 /// 蓝牙服务器状态
+#[cfg(feature = "ble")]
 struct BluetoothServerState {
     gatt_if: Option<GattInterface>,
     service_handle: Option<Handle>,
     recv_handle: Option<Handle>,
     ind_handle: Option<Handle>,
     ind_cccd_handle: Option<Handle>,
+    new_objects_handle: Option<Handle>,      // 新对象计数特征值
+    new_objects_cccd_handle: Option<Handle>,
     connections: HVec<Connection, 4>, // 支持最多4个并发连接
     response: GattResponse,
     ind_confirmed: Option<BdAddr>,
+    command_tx: Option<CommandSender>,          // 接收特征值上收到的控制命令
+    auth: Option<Arc<Mutex<AuthGuard>>>,        // 转发命令前鉴权，None时丢弃所有命令
+    pairing: Option<Arc<Mutex<PairingManager>>>, // 接受PAIR命令提交的配对令牌，None时拒绝配对
+    replies: Vec<Vec<u8>>,                      // 登录和被拒绝命令的应答，由flush_replies发送
+    next_message_seq: u8,                       // 下一条发送消息的分片序号
 }
 
+#[cfg(feature = "ble")]
 impl Default for BluetoothServerState {
     fn default() -> Self {
         Self {
@@ -54,74 +195,201 @@ impl Default for BluetoothServerState {
             recv_handle: None,
             ind_handle: None,
             ind_cccd_handle: None,
+            new_objects_handle: None,
+            new_objects_cccd_handle: None,
             connections: HVec::new(),
             response: GattResponse::default(),
             ind_confirmed: None,
+            command_tx: None,
+            auth: None,
+            pairing: None,
+            replies: Vec::new(),
+            next_message_seq: 0,
         }
     }
 }
 
+#[cfg(feature = "ble")]
 #[derive(Debug, Clone)]
 struct Connection {
     peer: BdAddr,
     conn_id: Handle,
     subscribed: bool,
+    new_objects_subscribed: bool, // 订阅了新对象计数的通知
     mtu: Option<u16>,
+    reassembler: ble_frame::Reassembler, // 重组手机写入接收特征值的分片
+    session: Option<String>,             // 该连接登录后的会话令牌
 }
 
-/// 无线连接管理器
+/// 无线连接管理器，连接类型都关闭时没有可用的链路，也无法创建
 pub struct WirelessManager {
+    #[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
     conn_type: ConnectionType,
+    #[cfg(feature = "wifi")]
     wifi_driver: Option<EspWifi<'static>>,
+    #[cfg(feature = "wifi")]
+    wifi_mode: Option<WirelessMode>, // 当前的WiFi工作模式，未连接时为None
+    #[cfg(feature = "wifi")]
+    soft_ap: Option<SoftApSettings>, // 以纯AP模式运行时的SoftAP设置，切换信道时使用
+    #[cfg(feature = "wifi")]
+    ap_channel: Option<u8>,          // SoftAP当前的信道
+    #[cfg(feature = "wifi")]
+    hopper: Option<channel::LossHopper>, // 持续丢包时切换信道，None表示不切换
+    #[cfg(feature = "wifi")]
+    bridging: bool,                  // AP+STA模式下正在把手机的流量转发到上级网络
+    #[cfg(feature = "wifi")]
+    reconnect: reconnect::Reconnector, // STA连接断开后的自动重连
+    #[cfg(any(feature = "wifi", feature = "ble"))]
+    nvs: Option<EspDefaultNvsPartition>, // WiFi/蓝牙驱动使用的NVS分区，分区只能取得一次，其他存储共用
+    #[cfg(feature = "ble")]
     bt_driver: Option<Arc<BtDriver<'static, EspBle>>>,
+    #[cfg(feature = "ble")]
     ble_gap: Option<Arc<EspBleGap<'static, EspBle, Arc<BtDriver<'static, EspBle>>>>>,
+    #[cfg(feature = "ble")]
     ble_gatts: Option<Arc<EspGatts<'static, EspBle, Arc<BtDriver<'static, EspBle>>>>>,
+    #[cfg(feature = "ble")]
     bt_state: Option<Arc<Mutex<BluetoothServerState>>>,
+    #[cfg(feature = "ble")]
     bt_condvar: Option<Arc<Condvar>>,
+    #[cfg(feature = "ble")]
+    new_objects: NewObjectCounter,
+    #[cfg(feature = "ethernet")]
+    eth_link: Option<ethernet::EthernetLink>,
+    #[cfg(any(feature = "wifi", feature = "ethernet"))]
+    link_tls: Option<TlsMaterial>,   // TCP发送器使用的证书，None表示明文
+    #[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
+    meter: LinkMeterHandle,          // 发送器记录的吞吐和重传
     connected: bool,
 }
 
 impl WirelessManager {
     /// 创建新的无线连接管理器
+    #[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
     pub fn new(conn_type: ConnectionType) -> Self {
         WirelessManager {
             conn_type,
+            #[cfg(feature = "wifi")]
             wifi_driver: None,
+            #[cfg(feature = "wifi")]
+            wifi_mode: None,
+            #[cfg(feature = "wifi")]
+            soft_ap: None,
+            #[cfg(feature = "wifi")]
+            ap_channel: None,
+            #[cfg(feature = "wifi")]
+            hopper: None,
+            #[cfg(feature = "wifi")]
+            bridging: false,
+            #[cfg(feature = "wifi")]
+            reconnect: reconnect::Reconnector::new("wifi", reconnect::INITIAL_BACKOFF, reconnect::MAX_BACKOFF),
+            #[cfg(any(feature = "wifi", feature = "ble"))]
+            nvs: None,
+            #[cfg(feature = "ble")]
             bt_driver: None,
+            #[cfg(feature = "ble")]
             ble_gap: None,
+            #[cfg(feature = "ble")]
             ble_gatts: None,
+            #[cfg(feature = "ble")]
             bt_state: None,
+            #[cfg(feature = "ble")]
             bt_condvar: None,
+            #[cfg(feature = "ble")]
+            new_objects: NewObjectCounter::default(),
+            #[cfg(feature = "ethernet")]
+            eth_link: None,
+            #[cfg(any(feature = "wifi", feature = "ethernet"))]
+            link_tls: None,
+            meter: link_stats::handle(),
             connected: false,
         }
     }
 
+    /// 设置之后创建的TCP发送器是否使用TLS，None表示明文
+    #[cfg(any(feature = "wifi", feature = "ethernet"))]
+    pub fn set_link_tls(&mut self, settings: Option<&TlsSettings>) -> Result<(), Box<dyn Error>> {
+        self.link_tls = settings.map(TlsMaterial::load).transpose()?;
+        Ok(())
+    }
+
     /// 初始化无线连接
+    #[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
     pub fn initialize(&mut self) -> Result<(), Box<dyn Error>> {
         match self.conn_type {
+            #[cfg(feature = "wifi")]
             ConnectionType::WiFi => {
                 self.init_wifi()?;
             }
+            #[cfg(feature = "ble")]
             ConnectionType::Bluetooth => {
                 self.init_bluetooth()?;
             }
+            // 以太网的PHY参数在连接配置中，初始化推迟到connect
+            #[cfg(feature = "ethernet")]
+            ConnectionType::Ethernet => {}
         }
         Ok(())
     }
 
     /// 连接到网络或开启服务
     // 修改 connect 方法签名，接收 config 的所有权以避免生命周期问题
+    #[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
     pub fn connect(&mut self, config: ConnectionConfig) -> Result<(), Box<dyn Error>> {
         let conn_type = self.conn_type; // 复制 conn_type 以避免在 match 中借用 self
         match conn_type {
+            #[cfg(feature = "wifi")]
             ConnectionType::WiFi => {
                 // 将 wifi_driver 的可变借用移到 if let 内部
-                if let Some(wifi) = self.wifi_driver.as_mut() {
-                    Self::connect_wifi_static(wifi, &config)?;
-                } else {
+                let Some(wifi) = self.wifi_driver.as_mut() else {
                     return Err("WiFi驱动未初始化".into());
+                };
+                self.reconnect.reset();
+                match &config {
+                    ConnectionConfig::SoftAp { ap, channel: policy } => {
+                        let fixed = match policy {
+                            ChannelPolicy::Fixed(channel) => Some(*channel),
+                            ChannelPolicy::Auto { .. } => None,
+                        };
+                        ip::configure_ap(wifi, ap.address.as_ref())?;
+                        self.ap_channel = Some(channel::start_soft_ap(wifi, ap, fixed, None)?);
+                        if let Some(address) = &ap.address {
+                            ip::set_dhcp_pool(wifi, address)?;
+                        }
+                        self.soft_ap = Some(ap.clone());
+                        self.hopper = matches!(policy, ChannelPolicy::Auto { hop_on_loss: true })
+                            .then(channel::LossHopper::default);
+                        self.wifi_mode = Some(WirelessMode::Ap);
+                    }
+                    ConnectionConfig::ApSta { ap, upstream, bridge } => {
+                        ip::configure_ap(wifi, ap.address.as_ref())?;
+                        ip::configure_sta(wifi, &upstream.address)?;
+                        self.ap_channel = Some(channel::start_ap_sta(wifi, ap, upstream)?);
+                        if let Some(address) = &ap.address {
+                            ip::set_dhcp_pool(wifi, address)?;
+                        }
+                        self.soft_ap = None;
+                        self.hopper = None;
+                        self.wifi_mode = Some(WirelessMode::ApSta);
+                        // 上级网络连不上时SoftAP照常工作，只是手机无法经设备上网
+                        self.bridging = false;
+                        if *bridge {
+                            if nat::wait_sta_up(wifi, STA_UP_TIMEOUT)? {
+                                match nat::enable(wifi) {
+                                    Ok(()) => self.bridging = true,
+                                    Err(e) => warn!("开启流量转发失败: {}", e),
+                                }
+                            } else {
+                                warn!("{}秒内未连上WiFi网络 {}，不转发SoftAP流量", STA_UP_TIMEOUT.as_secs(), upstream.ssid);
+                            }
+                        }
+                    }
+                    _ => {
+                        Self::connect_wifi_static(wifi, &config)?;
+                        self.wifi_mode = Some(WirelessMode::Sta);
+                    }
                 }
             }
+            #[cfg(feature = "ble")]
             ConnectionType::Bluetooth => {
                 // 将 bt_driver 的检查移到 if let 内部
                 if self.bt_driver.is_some() {
@@ -133,6 +401,14 @@ impl WirelessManager {
                     return Err("蓝牙驱动未初始化".into());
                 }
             }
+            #[cfg(feature = "ethernet")]
+            ConnectionType::Ethernet => {
+                if let ConnectionConfig::Ethernet(eth_config) = &config {
+                    self.eth_link = Some(ethernet::EthernetLink::start(eth_config)?);
+                } else {
+                    return Err("无效的以太网配置".into());
+                }
+            }
         }
 
         self.connected = true;
@@ -140,18 +416,34 @@ impl WirelessManager {
     }
 
     /// 断开连接
+    #[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
     pub fn disconnect(&mut self) -> Result<(), Box<dyn Error>> {
         match self.conn_type {
+            #[cfg(feature = "wifi")]
             ConnectionType::WiFi => {
                 if let Some(wifi) = &mut self.wifi_driver {
+                    if self.bridging {
+                        let _ = nat::disable(wifi);
+                        self.bridging = false;
+                    }
                     wifi.stop()?;
                     info!("WiFi连接已断开");
                 }
+                self.wifi_mode = None;
+                self.ap_channel = None;
+                self.reconnect.reset();
             }
+            #[cfg(feature = "ble")]
             ConnectionType::Bluetooth => {
                 // 停止蓝牙服务
                 info!("蓝牙服务已停止");
             }
+            #[cfg(feature = "ethernet")]
+            ConnectionType::Ethernet => {
+                if let Some(link) = &mut self.eth_link {
+                    link.stop()?;
+                }
+            }
         }
 
         self.connected = false;
@@ -163,42 +455,283 @@ impl WirelessManager {
         self.connected
     }
 
+    /// 当前的WiFi工作模式，未连接或不是WiFi连接时返回None
+    #[cfg(feature = "wifi")]
+    pub fn wifi_mode(&self) -> Option<WirelessMode> {
+        self.wifi_mode
+    }
+
+    /// 是否正在把SoftAP上手机的流量转发到上级网络
+    #[cfg(feature = "wifi")]
+    pub fn is_bridging(&self) -> bool {
+        self.bridging
+    }
+
+    /// SoftAP当前的信道，没有运行SoftAP时返回None
+    #[cfg(feature = "wifi")]
+    pub fn wifi_channel(&self) -> Option<u8> {
+        self.ap_channel
+    }
+
+    /// 检查STA连接，断开后按指数退避自动重连；连接刚断开或刚恢复时返回事件，由调用方暂停或恢复传输
+    /// 纯AP模式和未连接时不检查
+    #[cfg(feature = "wifi")]
+    pub fn poll_link(&mut self) -> Option<AppEvent> {
+        if !matches!(self.wifi_mode, Some(WirelessMode::Sta | WirelessMode::ApSta)) {
+            return None;
+        }
+        let wifi = self.wifi_driver.as_mut()?;
+        let up = wifi.is_connected().unwrap_or(false) && wifi.sta_netif().is_up().unwrap_or(false);
+        let now = Instant::now();
+        let event = self.reconnect.observe(up, now);
+        match &event {
+            Some(AppEvent::ConnectionLost { .. }) => {
+                warn!("WiFi连接已断开，{}秒后开始重连", reconnect::INITIAL_BACKOFF.as_secs());
+                self.connected = false;
+            }
+            Some(AppEvent::ConnectionRestored { attempts, downtime_secs, .. }) => {
+                info!("WiFi连接已恢复(重连 {} 次，断开 {} 秒)", attempts, downtime_secs);
+                self.connected = true;
+            }
+            _ => {}
+        }
+        if self.reconnect.retry_due(now) {
+            info!("第 {} 次重连WiFi", self.reconnect.attempts());
+            if let Err(e) = wifi.connect() {
+                warn!("重连WiFi失败: {}", e);
+            }
+        }
+        event
+    }
+
+    /// 等待STA接口获得地址，超时返回false
+    #[cfg(feature = "wifi")]
+    pub fn wait_sta_up(&self, timeout: std::time::Duration) -> Result<bool, Box<dyn Error>> {
+        let wifi = self.wifi_driver.as_ref().ok_or("WiFi驱动未初始化")?;
+        nat::wait_sta_up(wifi, timeout)
+    }
+
+    /// SoftAP接口的地址
+    #[cfg(feature = "wifi")]
+    pub fn ap_ip(&self) -> Result<std::net::Ipv4Addr, Box<dyn Error>> {
+        let wifi = self.wifi_driver.as_ref().ok_or("WiFi驱动未初始化")?;
+        Ok(wifi.ap_netif().get_ip_info()?.ip)
+    }
+
+    /// WiFi驱动使用的NVS分区，用于打开其他命名空间的存储；WiFi初始化前为None
+    #[cfg(feature = "wifi")]
+    pub fn nvs_partition(&self) -> Option<EspDefaultNvsPartition> {
+        self.nvs.clone()
+    }
+
+    /// 使用调用方已取得的NVS分区初始化驱动，分区只能取得一次，启动时先用它读取配置
+    #[cfg(any(feature = "wifi", feature = "ble"))]
+    pub fn set_nvs_partition(&mut self, nvs: EspDefaultNvsPartition) {
+        self.nvs = Some(nvs);
+    }
+
+    /// 驱动使用的NVS分区，调用方没有提供时取得默认分区
+    #[cfg(any(feature = "wifi", feature = "ble"))]
+    fn take_nvs(&mut self) -> Result<EspDefaultNvsPartition, Box<dyn Error>> {
+        if let Some(nvs) = &self.nvs {
+            return Ok(nvs.clone());
+        }
+        let nvs = EspDefaultNvsPartition::take()?;
+        self.nvs = Some(nvs.clone());
+        Ok(nvs)
+    }
+
+    /// 扫描周边的WiFi网络，供手机应用配网时选择；纯AP模式下扫描期间SoftAP客户端可能短暂断开
+    #[cfg(feature = "wifi")]
+    pub fn scan(&mut self) -> Result<Vec<ScannedNetwork>, Box<dyn Error>> {
+        let Some(wifi) = self.wifi_driver.as_mut() else {
+            return Err("WiFi驱动未初始化".into());
+        };
+        let restore = channel::prepare_scan(wifi)?;
+        let result = wifi.scan();
+        channel::finish_scan(wifi, restore)?;
+        let networks = channel::collect_networks(&result?);
+        info!("扫描到 {} 个WiFi网络", networks.len());
+        Ok(networks)
+    }
+
+    /// `scan`的异步版本：扫描期间让出执行器，不阻塞同一执行器上的其他任务
+    #[cfg(feature = "wifi")]
+    pub async fn scan_async(&mut self) -> Result<Vec<ScannedNetwork>, Box<dyn Error>> {
+        let Some(wifi) = self.wifi_driver.as_mut() else {
+            return Err("WiFi驱动未初始化".into());
+        };
+        let restore = channel::prepare_scan(wifi)?;
+        let result = Self::scan_nonblocking(wifi).await;
+        channel::finish_scan(wifi, restore)?;
+        let networks = channel::collect_networks(&result?);
+        info!("扫描到 {} 个WiFi网络", networks.len());
+        Ok(networks)
+    }
+
+    #[cfg(feature = "wifi")]
+    async fn scan_nonblocking(wifi: &mut EspWifi<'static>) -> Result<Vec<AccessPointInfo>, Box<dyn Error>> {
+        wifi.start_scan(&ScanConfig::default(), false)?;
+        let deadline = std::time::Instant::now() + SCAN_TIMEOUT;
+        while !wifi.is_scan_done()? {
+            if std::time::Instant::now() >= deadline {
+                wifi.stop_scan()?;
+                return Err("WiFi扫描超时".into());
+            }
+            Timer::after(EmbassyDuration::from_millis(SCAN_POLL_INTERVAL_MS)).await;
+        }
+        Ok(wifi.get_scan_result()?)
+    }
+
+    /// 当前链路的信号强度、协商速率、滑动窗口吞吐、重传次数和蓝牙MTU
+    /// 吞吐和重传只统计由`create_sender`创建的发送器
+    #[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
+    pub fn link_stats(&self) -> LinkStats {
+        let mut stats = {
+            let mut meter = self.meter.lock().unwrap();
+            LinkStats {
+                throughput_bps: meter.throughput_bps(std::time::Instant::now()),
+                retransmissions: meter.retransmissions(),
+                ..LinkStats::default()
+            }
+        };
+        match self.conn_type {
+            #[cfg(feature = "wifi")]
+            ConnectionType::WiFi => {
+                // AP+STA模式下手机通常连在SoftAP上，没有客户端时看上级网络
+                let link = match self.wifi_mode {
+                    Some(WirelessMode::Ap) => link_stats::ap_link(),
+                    Some(WirelessMode::ApSta) => link_stats::ap_link().or_else(link_stats::sta_link),
+                    Some(WirelessMode::Sta) => link_stats::sta_link(),
+                    None => None,
+                };
+                if let Some((rssi, rate)) = link {
+                    stats.rssi = Some(rssi);
+                    stats.phy_rate_kbps = rate;
+                }
+            }
+            #[cfg(feature = "ble")]
+            ConnectionType::Bluetooth => {
+                stats.ble_mtu = self.bt_state.as_ref().and_then(|state| {
+                    let state = state.lock().unwrap();
+                    state
+                        .connections
+                        .iter()
+                        .map(|conn| conn.mtu.unwrap_or(ble_frame::DEFAULT_ATT_MTU))
+                        .min()
+                });
+            }
+            #[cfg(feature = "ethernet")]
+            ConnectionType::Ethernet => {}
+        }
+        stats
+    }
+
+    /// 报告一次链路丢包率(0-100)；启用了按丢包切换且丢包持续时，重新扫描并把SoftAP切换到其他信道，返回新信道
+    /// 切换信道会让客户端短暂断开并自动重连
+    #[cfg(feature = "wifi")]
+    pub fn report_loss(&mut self, loss_percent: u8) -> Result<Option<u8>, Box<dyn Error>> {
+        if !self.hopper.as_mut().is_some_and(|h| h.record(loss_percent)) {
+            return Ok(None);
+        }
+        let (Some(wifi), Some(ap)) = (self.wifi_driver.as_mut(), self.soft_ap.as_ref()) else {
+            return Ok(None);
+        };
+        warn!("信道 {:?} 持续丢包 {}%，重新选择信道", self.ap_channel, loss_percent);
+        let channel = channel::start_soft_ap(wifi, ap, None, self.ap_channel)?;
+        self.ap_channel = Some(channel);
+        Ok(Some(channel))
+    }
+
     /// 创建数据发送器
+    #[cfg(any(feature = "wifi", feature = "ble", feature = "ethernet"))]
     pub fn create_sender(
         &self,
         config: &ConnectionConfig,
     ) -> Result<Box<dyn DataSender>, Box<dyn Error>> {
         match self.conn_type {
+            #[cfg(feature = "wifi")]
             ConnectionType::WiFi => {
-                if let ConnectionConfig::WiFi(_, _) = config {
-                    let sender = WifiSender::new();
+                if let ConnectionConfig::WiFi(_) | ConnectionConfig::SoftAp { .. } | ConnectionConfig::ApSta { .. } = config {
+                    let sender = WifiSender::with_tls(self.link_tls).with_meter(self.meter.clone());
                     Ok(Box::new(sender))
                 } else {
                     Err("无效的WiFi配置".into())
                 }
             }
+            #[cfg(feature = "ble")]
             ConnectionType::Bluetooth => {
-                if let ConnectionConfig::Bluetooth(_device_name) = config {
-                    // 为了简化，假设我们已经有一个特征引用
-                    // 在实际应用中，我们需要管理GATT服务器并获取正确的特征引用
-
-                    // 这部分是一个简化的示例，实际应用中需要更复杂的逻辑来管理蓝牙GATT服务和特征
-                    return Err(
-                        "蓝牙发送器创建需要正确的GATT特征引用，请先实现完整的GATT服务器管理".into(),
-                    );
-
-                    // 完整实现应该是类似这样：
-                    // let char_ref = self.get_bluetooth_characteristic()?;
-                    // let sender = BluetoothSender::new(device_name.clone(), char_ref);
-                    // Ok(Box::new(sender))
+                if let ConnectionConfig::Bluetooth(device_name) = config {
+                    Ok(Box::new(self.create_ble_sender(device_name)?))
                 } else {
                     Err("无效的蓝牙配置".into())
                 }
             }
+            // 以太网与WiFi共用lwIP协议栈，TCP发送器无需区分链路
+            #[cfg(feature = "ethernet")]
+            ConnectionType::Ethernet => Ok(Box::new(WifiSender::with_tls(self.link_tls).with_meter(self.meter.clone()))),
+        }
+    }
+
+    /// 创建蓝牙发送器，蓝牙作为主连接或在双通道模式下作为控制通道时使用
+    #[cfg(feature = "ble")]
+    pub fn create_ble_sender(&self, device_name: &str) -> Result<BluetoothSender, Box<dyn Error>> {
+        let (Some(state), Some(condvar), Some(gatts), Some(gap)) =
+            (&self.bt_state, &self.bt_condvar, &self.ble_gatts, &self.ble_gap)
+        else {
+            return Err("蓝牙服务未初始化".into());
+        };
+        Ok(BluetoothSender::new(
+            device_name.to_string(),
+            state.clone(),
+            condvar.clone(),
+            gatts.clone(),
+            gap.clone(),
+        )
+        .with_meter(self.meter.clone()))
+    }
+
+    /// 以共存模式同时初始化WiFi和蓝牙，代替`initialize`：WiFi承载批量数据，蓝牙保持控制通道
+    /// 主连接必须是WiFi，蓝牙服务之后通过`start_ble_control`启动
+    #[cfg(all(feature = "wifi", feature = "ble"))]
+    pub fn initialize_dual(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.conn_type != ConnectionType::WiFi {
+            return Err("双通道模式需要以WiFi作为主连接".into());
+        }
+        debug!("以共存模式初始化WiFi和蓝牙...");
+
+        let sys_loop = EspSystemEventLoop::take()?;
+        let nvs = self.take_nvs()?;
+        let peripherals = esp_idf_hal::peripherals::Peripherals::take()?;
+        // 射频由WiFi和蓝牙分时共用(需要开启CONFIG_ESP_COEX_SW_COEXIST_ENABLE)
+        let (wifi_modem, bt_modem) = peripherals.modem.split();
+
+        self.wifi_driver = Some(EspWifi::new(wifi_modem, sys_loop, Some(nvs.clone()))?);
+        self.install_bluetooth(BtDriver::new(bt_modem, Some(nvs))?)?;
+
+        info!("WiFi和蓝牙已以共存模式初始化");
+        Ok(())
+    }
+
+    /// 双通道模式下启动蓝牙GATT服务和广播，蓝牙作为主连接时由`connect`启动
+    #[cfg(all(feature = "wifi", feature = "ble"))]
+    pub fn start_ble_control(&self, device_name: &str) -> Result<(), Box<dyn Error>> {
+        self.start_bluetooth_server(&ConnectionConfig::Bluetooth(device_name.to_string()))
+    }
+
+    /// 手机能否经WiFi接收数据：SoftAP模式下看是否有客户端连着，连接已有网络时看STA连接
+    /// AP+STA模式下手机连在SoftAP上，按SoftAP判断
+    #[cfg(feature = "wifi")]
+    pub fn wifi_link_up(&self) -> bool {
+        match self.wifi_mode {
+            Some(WirelessMode::Ap | WirelessMode::ApSta) => link_stats::ap_link().is_some(),
+            Some(WirelessMode::Sta) => self.connected,
+            None => false,
         }
     }
 
     /// 初始化WiFi
+    #[cfg(feature = "wifi")]
     fn init_wifi(&mut self) -> Result<(), Box<dyn Error>> {
         debug!("初始化WiFi...");
 
@@ -206,7 +739,7 @@ impl WirelessManager {
         let sys_loop = EspSystemEventLoop::take()?;
 
         // 获取非易失性存储分区
-        let nvs = EspDefaultNvsPartition::take()?;
+        let nvs = self.take_nvs()?;
 
         // 获取所有外设
         let peripherals = esp_idf_hal::peripherals::Peripherals::take()?;
@@ -225,47 +758,51 @@ impl WirelessManager {
     }
 
     /// 初始化蓝牙
+    #[cfg(feature = "ble")]
     fn init_bluetooth(&mut self) -> Result<(), Box<dyn Error>> {
         debug!("初始化蓝牙...");
 
-        let nvs = EspDefaultNvsPartition::take()?;
+        let nvs = self.take_nvs()?;
         let peripherals = esp_idf_hal::peripherals::Peripherals::take()?;
 
-        let bt = Arc::new(BtDriver::new(peripherals.modem, Some(nvs.clone()))?);
+        self.install_bluetooth(BtDriver::new(peripherals.modem, Some(nvs))?)?;
+
+        info!("蓝牙初始化成功");
+
+        Ok(())
+    }
 
-        self.bt_driver = Some(bt.clone());
+    /// 在蓝牙驱动上创建GAP和GATT服务
+    #[cfg(feature = "ble")]
+    fn install_bluetooth(&mut self, driver: BtDriver<'static, EspBle>) -> Result<(), Box<dyn Error>> {
+        let bt = Arc::new(driver);
         self.ble_gap = Some(Arc::new(EspBleGap::new(bt.clone())?));
         self.ble_gatts = Some(Arc::new(EspGatts::new(bt.clone())?));
+        self.bt_driver = Some(bt);
         self.bt_state = Some(Arc::new(Mutex::new(BluetoothServerState::default())));
         self.bt_condvar = Some(Arc::new(Condvar::new()));
-
-        info!("蓝牙初始化成功");
-
         Ok(())
     }
 
     // 将 connect_wifi 改为静态方法以解决借用冲突
+    #[cfg(feature = "wifi")]
     fn connect_wifi_static(
         wifi: &mut EspWifi<'static>,
         config: &ConnectionConfig,
     ) -> Result<(), Box<dyn Error>> {
-        if let ConnectionConfig::WiFi(ssid, pass) = config {
-            debug!("连接到WiFi网络: {}", ssid);
-
-            let wifi_configuration = Configuration::Client(ClientConfiguration {
-                ssid: ssid.as_str().try_into().unwrap(),
-                bssid: None,
-                password: pass.as_str().try_into().unwrap(),
-                auth_method: AuthMethod::WPA2Personal,
-                channel: None,
-                ..Default::default()
-            });
+        if let ConnectionConfig::WiFi(upstream) = config {
+            debug!("连接到WiFi网络: {} ({:?})", upstream.ssid, upstream.auth);
+
+            // 先校验凭证，避免带着无效配置启动驱动
+            let wifi_configuration = Configuration::Client(sta::client_configuration(upstream)?);
 
             wifi.set_configuration(&wifi_configuration)?;
+            sta::configure_enterprise(upstream)?;
+            ip::configure_sta(wifi, &upstream.address)?;
             wifi.start()?;
             wifi.connect()?;
 
-            info!("已连接到WiFi网络: {}", ssid);
+            info!("已连接到WiFi网络: {}", upstream.ssid);
             Ok(())
         } else {
             Err("无效的WiFi配置".into())
@@ -273,6 +810,7 @@ impl WirelessManager {
     }
 
     /// 启动蓝牙服务器
+    #[cfg(feature = "ble")]
     fn start_bluetooth_server(&self, config: &ConnectionConfig) -> Result<(), Box<dyn Error>> {
         if let ConnectionConfig::Bluetooth(device_name) = config {
             debug!("启动蓝牙服务: {}", device_name);
@@ -298,9 +836,11 @@ impl WirelessManager {
 
             // 配置设备名称和广播参数
             gap.set_device_name(device_name)?;
+            // 允许手机协商更大的MTU，每个分片能携带更多数据
+            esp_idf_svc::sys::esp!(unsafe { esp_idf_svc::sys::esp_ble_gatt_set_local_mtu(ble_frame::MAX_ATT_MTU) })?;
 
             // 设置广播配置
-            let service_uuid = BtUuid::uuid128(0xad91b201734740479e173bed82d75f9d); // 使用示例中的UUID
+            let service_uuid = BtUuid::uuid128(SERVICE_UUID);
             gap.set_adv_conf(&AdvConfiguration {
                 include_name: true,
                 include_txpower: true,
@@ -331,7 +871,41 @@ impl WirelessManager {
         }
     }
 
+    /// 设置蓝牙控制命令的接收端，手机写入接收特征值的命令经`guard`鉴权后转发到该通道
+    ///
+    /// 每个连接先用LOGIN命令提交预共享令牌，之后的命令按登录的客户端检查权限
+    #[cfg(feature = "ble")]
+    pub fn set_command_sink(&self, tx: CommandSender, guard: Arc<Mutex<AuthGuard>>) -> Result<(), Box<dyn Error>> {
+        let state = self.bt_state.as_ref().ok_or("蓝牙未初始化")?;
+        let mut state = state.lock().unwrap();
+        state.command_tx = Some(tx);
+        state.auth = Some(guard);
+        Ok(())
+    }
+
+    /// 接受手机通过PAIR命令提交的配对令牌，配对得到的预共享令牌登记到`set_command_sink`的鉴权守卫
+    #[cfg(feature = "ble")]
+    pub fn set_pairing(&self, pairing: Arc<Mutex<PairingManager>>) -> Result<(), Box<dyn Error>> {
+        let state = self.bt_state.as_ref().ok_or("蓝牙未初始化")?;
+        state.lock().unwrap().pairing = Some(pairing);
+        Ok(())
+    }
+
+    /// 发送蓝牙回调中产生的应答(登录结果、配对结果、鉴权失败)，回调中不能等待indication确认
+    #[cfg(feature = "ble")]
+    pub fn flush_replies(&self) -> Result<(), Box<dyn Error>> {
+        let Some(state) = &self.bt_state else {
+            return Ok(()); // 未使用蓝牙连接
+        };
+        let replies = std::mem::take(&mut state.lock().unwrap().replies);
+        for reply in replies {
+            self.send_bluetooth_data(&reply)?;
+        }
+        Ok(())
+    }
+
     /// 通过蓝牙发送数据到已连接的客户端
+    #[cfg(feature = "ble")]
     pub fn send_bluetooth_data(&self, data: &[u8]) -> Result<(), Box<dyn Error>> {
         if let (Some(state), Some(condvar)) = (&self.bt_state, &self.bt_condvar) {
             let server = BluetoothServer {
@@ -349,9 +923,36 @@ impl WirelessManager {
             Err("蓝牙服务未初始化".into())
         }
     }
+
+    /// 记录相机新增的对象，计数在下次flush_new_objects时通知订阅的手机
+    #[cfg(feature = "ble")]
+    pub fn object_added(&mut self) {
+        self.new_objects.record(1);
+    }
+
+    /// 新对象计数有变化且已到通知间隔时，更新计数特征值并通知订阅的手机
+    #[cfg(feature = "ble")]
+    pub fn flush_new_objects(&mut self) -> Result<(), Box<dyn Error>> {
+        let (Some(state), Some(condvar)) = (&self.bt_state, &self.bt_condvar) else {
+            return Ok(()); // 未使用蓝牙连接
+        };
+        let Some(count) = self.new_objects.take_due(Instant::now()) else {
+            return Ok(());
+        };
+        let server = BluetoothServer {
+            gap: self.ble_gap.as_ref().unwrap().clone(),
+            gatts: self.ble_gatts.as_ref().unwrap().clone(),
+            state: state.clone(),
+            condvar: condvar.clone(),
+            device_name: "ESP32".to_string(), // 默认设备名
+        };
+        server.notify_new_objects(count)?;
+        Ok(())
+    }
 }
 
 /// 蓝牙服务器实现，管理BLE GATT服务
+#[cfg(feature = "ble")]
 #[derive(Clone)]
 struct BluetoothServer {
     gap: Arc<EspBleGap<'static, EspBle, Arc<BtDriver<'static, EspBle>>>>,
@@ -361,52 +962,72 @@ struct BluetoothServer {
     device_name: String,
 }
 
+#[cfg(feature = "ble")]
 impl BluetoothServer {
-    /// 发送数据到所有已订阅的客户端
+    /// 发送一条消息到所有已订阅的客户端，按各连接协商的MTU拆成分片(见`ble_frame`)
     ///
-    /// 对于使用Indication特性的发送，需要等待确认
+    /// 对于使用Indication特性的发送，每个分片都需要等待确认
     /// 通过Mutex和Condvar实现同步等待
     fn indicate(&self, data: &[u8]) -> Result<(), EspError> {
-        const MAX_CONNECTIONS: usize = 4;
-
-        for peer_index in 0..MAX_CONNECTIONS {
-            // 向所有已连接且订阅的客户端发送数据
+        let (seq, peers) = {
             let mut state = self.state.lock().unwrap();
+            let seq = state.next_message_seq;
+            state.next_message_seq = seq.wrapping_add(1);
+            let peers: Vec<(Handle, BdAddr, u16)> = state
+                .connections
+                .iter()
+                .filter(|conn| conn.subscribed)
+                .map(|conn| (conn.conn_id, conn.peer, conn.mtu.unwrap_or(ble_frame::DEFAULT_ATT_MTU)))
+                .collect();
+            (seq, peers)
+        };
 
-            loop {
-                if state.connections.len() <= peer_index {
-                    // 已向所有连接的客户端发送
-                    break;
+        for (conn_id, peer, mtu) in peers {
+            let fragments = ble_frame::fragment(seq, data, mtu);
+            let count = fragments.len();
+            for fragment in fragments {
+                if !self.indicate_fragment(conn_id, peer, &fragment)? {
+                    break; // 客户端已断开或取消订阅
                 }
+            }
+            debug!("已向客户端 {} 发送 {} 字节 ({} 个分片)", peer, data.len(), count);
+        }
 
-                let Some(gatt_if) = state.gatt_if else {
-                    // GATT接口不存在
-                    break;
-                };
-
-                let Some(ind_handle) = state.ind_handle else {
-                    // Indication特性句柄不存在
-                    break;
-                };
+        Ok(())
+    }
 
-                if state.ind_confirmed.is_none() {
-                    let conn = &state.connections[peer_index];
+    /// 等待上一个indication被确认后发送一个分片，客户端已不再订阅时返回false
+    fn indicate_fragment(&self, conn_id: Handle, peer: BdAddr, value: &[u8]) -> Result<bool, EspError> {
+        let mut state = self.state.lock().unwrap();
+        while state.ind_confirmed.is_some() {
+            state = self.condvar.wait(state).unwrap();
+        }
+        if !state.connections.iter().any(|conn| conn.conn_id == conn_id && conn.subscribed) {
+            return Ok(false);
+        }
+        let (Some(gatt_if), Some(ind_handle)) = (state.gatt_if, state.ind_handle) else {
+            return Ok(false);
+        };
+        self.gatts.indicate(gatt_if, conn_id, ind_handle, value)?;
+        state.ind_confirmed = Some(peer);
+        Ok(true)
+    }
 
-                    // 只向已订阅的客户端发送
-                    if conn.subscribed {
-                        let conn_id = conn.conn_id;
-                        let peer_addr = conn.peer;
+    /// 更新新对象计数特征值并通知订阅了它的客户端
+    ///
+    /// 使用不需要确认的notification，不占用indication的确认窗口
+    fn notify_new_objects(&self, count: u32) -> Result<(), EspError> {
+        let state = self.state.lock().unwrap();
+        let (Some(gatt_if), Some(handle)) = (state.gatt_if, state.new_objects_handle) else {
+            return Ok(());
+        };
 
-                        self.gatts.indicate(gatt_if, conn_id, ind_handle, data)?;
-                        state.ind_confirmed = Some(peer_addr);
-                        debug!("已向客户端 {} 发送数据", peer_addr);
-                    }
-                    break;
-                } else {
-                    // 等待上一个indication被确认
-                    state = self.condvar.wait(state).unwrap();
-                }
-            }
+        let value = NewObjectCounter::encode(count);
+        // 更新由协议栈应答的读取值，未订阅的客户端连接后也能读到最新计数
+        self.gatts.set_attr(handle, &value)?;
+        for conn in state.connections.iter().filter(|conn| conn.new_objects_subscribed) {
+            self.gatts.notify(gatt_if, conn.conn_id, handle, &value)?;
+            debug!("已通知客户端 {} 新对象计数 {}", conn.peer, count);
         }
 
         Ok(())
@@ -517,7 +1138,6 @@ impl BluetoothServer {
         state.gatt_if = Some(gatt_if);
 
         // 创建服务
-        const SERVICE_UUID: u128 = 0xad91b201734740479e173bed82d75f9d; // 自定义服务UUID
         self.gatts.create_service(
             gatt_if,
             &GattServiceId {
@@ -527,7 +1147,7 @@ impl BluetoothServer {
                 },
                 is_primary: true,
             },
-            8, // 属性数量
+            12, // 属性数量
         )?;
 
         Ok(())
@@ -557,7 +1177,7 @@ impl BluetoothServer {
                 uuid: BtUuid::uuid128(RECV_CHARACTERISTIC_UUID),
                 permissions: enum_set!(Permission::Write),
                 properties: enum_set!(Property::Write),
-                max_len: 200, // 最大接收数据长度
+                max_len: ble_frame::MAX_ATT_MTU as usize, // 分片最大不超过MTU
                 auto_rsp: AutoResponse::ByApp,
             },
             &[],
@@ -571,7 +1191,7 @@ impl BluetoothServer {
                 uuid: BtUuid::uuid128(IND_CHARACTERISTIC_UUID),
                 permissions: enum_set!(Permission::Write | Permission::Read),
                 properties: enum_set!(Property::Indicate),
-                max_len: 200, // 最大发送数据长度
+                max_len: ble_frame::MAX_ATT_MTU as usize, // 分片最大不超过MTU
                 auto_rsp: AutoResponse::ByApp,
             },
             &[],
@@ -587,7 +1207,7 @@ impl BluetoothServer {
         attr_handle: Handle,
         char_uuid: BtUuid,
     ) -> Result<(), EspError> {
-        let indicate_char = {
+        let needs_cccd = {
             let mut state = self.state.lock().unwrap();
 
             if state.service_handle != Some(service_handle) {
@@ -600,13 +1220,16 @@ impl BluetoothServer {
                 // IND UUID
                 state.ind_handle = Some(attr_handle);
                 true
+            } else if char_uuid == BtUuid::uuid128(NEW_OBJECTS_CHARACTERISTIC_UUID) {
+                state.new_objects_handle = Some(attr_handle);
+                true
             } else {
                 false
             }
         };
 
-        // 为indication特性添加CCCD描述符（Client Characteristic Configuration Descriptor）
-        if indicate_char {
+        // 为indication/notification特性添加CCCD描述符（Client Characteristic Configuration Descriptor）
+        if needs_cccd {
             self.gatts.add_descriptor(
                 service_handle,
                 &GattDescriptor {
@@ -626,10 +1249,33 @@ impl BluetoothServer {
         attr_handle: Handle,
         descr_uuid: BtUuid,
     ) -> Result<(), EspError> {
-        let mut state = self.state.lock().unwrap();
+        let add_new_objects = {
+            let mut state = self.state.lock().unwrap();
 
-        if descr_uuid == BtUuid::uuid16(0x2902) && state.service_handle == Some(service_handle) {
-            state.ind_cccd_handle = Some(attr_handle);
+            if descr_uuid != BtUuid::uuid16(0x2902) || state.service_handle != Some(service_handle) {
+                false
+            } else if state.ind_cccd_handle.is_none() {
+                state.ind_cccd_handle = Some(attr_handle);
+                true
+            } else {
+                state.new_objects_cccd_handle = Some(attr_handle);
+                false
+            }
+        };
+
+        // 描述符挂在最后添加的特性上，所以新对象计数特性要等indication特性的CCCD添加完再添加
+        if add_new_objects {
+            self.gatts.add_characteristic(
+                service_handle,
+                &GattCharacteristic {
+                    uuid: BtUuid::uuid128(NEW_OBJECTS_CHARACTERISTIC_UUID),
+                    permissions: enum_set!(Permission::Read),
+                    properties: enum_set!(Property::Read | Property::Notify),
+                    max_len: 4,
+                    auto_rsp: AutoResponse::ByGatt, // 读取由协议栈按set_attr设置的值应答
+                },
+                &NewObjectCounter::encode(0),
+            )?;
         }
 
         Ok(())
@@ -661,7 +1307,10 @@ impl BluetoothServer {
                     peer: addr,
                     conn_id,
                     subscribed: false,
+                    new_objects_subscribed: false,
                     mtu: None,
+                    reassembler: ble_frame::Reassembler::default(),
+                    session: None,
                 });
                 true
             } else {
@@ -689,9 +1338,17 @@ impl BluetoothServer {
             .iter()
             .position(|connection| connection.peer == addr)
         {
-            let _ = state.connections.swap_remove(index);
+            let connection = state.connections.swap_remove(index);
+            if let (Some(session), Some(auth)) = (connection.session, &state.auth) {
+                auth.lock().unwrap().logout(&session);
+            }
             info!("客户端已断开连接: {}", addr);
         }
+        // 断开的客户端不会再确认indication，释放确认标志以免发送端一直等待
+        if state.ind_confirmed == Some(addr) {
+            state.ind_confirmed = None;
+            self.condvar.notify_all();
+        }
 
         Ok(())
     }
@@ -714,6 +1371,9 @@ impl BluetoothServer {
 
         let recv_handle = state.recv_handle;
         let ind_cccd_handle = state.ind_cccd_handle;
+        let new_objects_cccd_handle = state.new_objects_cccd_handle;
+        let auth = state.auth.clone();
+        let pairing = state.pairing.clone();
 
         let Some(conn) = state
             .connections
@@ -737,12 +1397,85 @@ impl BluetoothServer {
                     info!("客户端取消订阅了通知: {}", conn.peer);
                 }
             }
+        } else if Some(handle) == new_objects_cccd_handle {
+            // 0x01表示订阅notification
+            if offset == 0 && value.len() == 2 {
+                let subscribed = u16::from_le_bytes([value[0], value[1]]) & 0x01 != 0;
+                if conn.new_objects_subscribed != subscribed {
+                    conn.new_objects_subscribed = subscribed;
+                    info!("客户端{}新对象通知: {}", if subscribed { "订阅了" } else { "取消订阅了" }, conn.peer);
+                }
+            }
         } else if Some(handle) == recv_handle {
             // 处理收到的数据
             info!(
                 "收到客户端 {} 数据: {:?}, 偏移量: {}, MTU: {:?}",
                 addr, value, offset, conn.mtu
             );
+
+            if offset == 0 {
+                // 命令同样按分片发送，收齐一条完整的消息后再解析
+                let message = match conn.reassembler.push(value) {
+                    Ok(Some(message)) => message,
+                    Ok(None) => return Ok(true),
+                    Err(e) => {
+                        warn!("丢弃客户端 {} 的分片: {}", addr, e);
+                        return Ok(true);
+                    }
+                };
+                let Some(auth) = auth else {
+                    warn!("没有命令接收端，丢弃客户端 {} 的蓝牙命令", addr);
+                    return Ok(true);
+                };
+                if let Some((&ble_opcode::PAIR, token)) = message.split_first() {
+                    let client_id = format!("ble-{}", conn.peer);
+                    let result = match (&pairing, std::str::from_utf8(token)) {
+                        (Some(pairing), Ok(token)) => pairing
+                            .lock()
+                            .unwrap()
+                            .accept(token, &client_id, &mut auth.lock().unwrap())
+                            .map_err(|e| e.to_string()),
+                        (Some(_), Err(_)) => Err(PairingError::InvalidToken.to_string()),
+                        (None, _) => Err("设备未开启配对".to_string()),
+                    };
+                    let reply = match &result {
+                        Ok(pre_shared) => ble_response(ble_opcode::PAIR, Ok(pre_shared.as_bytes())),
+                        Err(e) => ble_response(ble_opcode::PAIR, Err(e.as_str())),
+                    };
+                    state.replies.push(reply);
+                    return Ok(true);
+                }
+                if let Some((&ble_opcode::LOGIN, credential)) = message.split_first() {
+                    let session = std::str::from_utf8(credential).ok().and_then(|c| auth.lock().unwrap().login(c));
+                    let reply = match &session {
+                        Some(_) => ble_response(ble_opcode::LOGIN, Ok(&[][..])),
+                        None => ble_response(ble_opcode::LOGIN, Err("预共享令牌无效")),
+                    };
+                    if let Some(old) = std::mem::replace(&mut conn.session, session) {
+                        auth.lock().unwrap().logout(&old);
+                    }
+                    state.replies.push(reply);
+                    return Ok(true);
+                }
+                if let Some(command) = ControlCommand::from_ble(&message) {
+                    let token = conn.session.as_deref().unwrap_or("");
+                    let principal = match auth.lock().unwrap().authorize(ControlChannel::Ble, token, &command) {
+                        Ok(principal) => principal,
+                        Err(e) => {
+                            if let Some(opcode) = command.ble_opcode() {
+                                state.replies.push(ble_response(opcode, Err(e.to_string().as_str())));
+                            }
+                            return Ok(true);
+                        }
+                    };
+                    match &state.command_tx {
+                        Some(tx) if tx.send((command.clone(), principal.client_id)).is_ok() => {
+                            debug!("已转发蓝牙命令 {:?}", command);
+                        }
+                        _ => warn!("没有命令接收端，丢弃蓝牙命令 {:?}", command),
+                    }
+                }
+            }
         } else {
             return Ok(false);
         }
@@ -808,76 +1541,200 @@ impl BluetoothServer {
 
 /// 连接配置
 pub enum ConnectionConfig {
-    WiFi(String, String), // SSID, 密码
+    #[cfg(feature = "wifi")]
+    WiFi(UpstreamWifi), // 连接到已有网络
+    #[cfg(feature = "wifi")]
+    SoftAp { ap: SoftApSettings, channel: ChannelPolicy }, // ESP32作为接入点
+    #[cfg(feature = "wifi")]
+    ApSta { ap: SoftApSettings, upstream: UpstreamWifi, bridge: bool }, // 作为接入点的同时连接已有网络，bridge为true时转发手机的流量
+    #[cfg(feature = "ble")]
     Bluetooth(String),    // 设备名称
+    #[cfg(feature = "ethernet")]
+    Ethernet(EthernetConfig), // PHY与引脚
+}
+
+/// 发送器操作返回的future；发送器以`Box<dyn DataSender>`保存，因此返回装箱的future
+pub type SenderFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn Error>>> + 'a>>;
+
+/// 发送器的连接状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenderState {
+    Disconnected, // 未连接或连接已失效，发送前需要`connect`
+    Ready,        // 可以立即发送
+    Busy,         // 等待对端确认，发送会被阻塞直到`ready`完成
 }
 
 /// 数据发送接口
+///
+/// 生命周期：`connect`建立连接(连接失效后再次调用即重连)，每次发送前用`ready`等待对端腾出窗口，
+/// `state`供调用方在不等待的情况下查询；没有连接概念的发送器使用默认实现，始终就绪
 pub trait DataSender {
-    /// 发送数据
-    fn send_data(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>>;
+    /// 建立或重新建立连接
+    fn connect(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// 当前连接状态
+    fn state(&self) -> SenderState {
+        SenderState::Ready
+    }
+
+    /// 等待发送器可以接受下一个数据包(背压)
+    fn ready(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// 发送数据包，返回发送的字节数
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize>;
 
     /// 关闭连接
-    fn close(&mut self) -> Result<(), Box<dyn Error>>;
+    fn close(&mut self) -> SenderFuture<'_, ()>;
 }
 
-/// WiFi数据发送器
+/// WiFi数据发送器，基于TCP(可选TLS)，在以太网链路上同样可用；数据按`framing`中的帧格式发送，手机逐帧确认
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
 pub struct WifiSender {
     // WiFi发送器的属性
     ssid: String,
-    client: Option<std::net::TcpStream>,
+    tls: Option<TlsMaterial>,
+    address: Option<String>, // 手机的地址(主机:端口)，重连时使用
+    client: Option<FramedSender<LinkStream>>,
+    meter: Option<LinkMeterHandle>, // 记录吞吐和重传，None表示不统计
+    reported_retransmits: u64,      // 当前连接已记入统计的重发帧数
 }
 
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
 impl WifiSender {
     /// 创建新的WiFi发送器
     pub fn new() -> Self {
+        Self::with_tls(None)
+    }
+
+    /// 创建使用指定证书的发送器，None表示明文
+    pub fn with_tls(tls: Option<TlsMaterial>) -> Self {
         WifiSender {
             ssid: String::new(),
+            tls,
+            address: None,
             client: None,
+            meter: None,
+            reported_retransmits: 0,
         }
     }
 
-    /// 连接到指定地址
-    pub fn connect(&mut self, address: &str) -> Result<(), Box<dyn Error>> {
-        match std::net::TcpStream::connect(address) {
-            Ok(stream) => {
-                self.client = Some(stream);
-                Ok(())
-            }
-            Err(e) => Err(Box::new(e)),
+    /// 把发送的字节数和重发次数记入共享的链路统计
+    pub fn with_meter(mut self, meter: LinkMeterHandle) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// 记录一次发送和当前连接新增的重发帧数
+    fn update_meter(&mut self, bytes: usize) {
+        let (Some(meter), Some(client)) = (&self.meter, &self.client) else {
+            return;
+        };
+        let retransmitted = client.retransmitted();
+        let mut meter = meter.lock().unwrap();
+        meter.record_sent(bytes);
+        meter.record_retransmits(retransmitted - self.reported_retransmits);
+        self.reported_retransmits = retransmitted;
+    }
+
+    /// 设置连接的目标地址(主机:端口)，已有的连接被断开，下次`connect`时连接新地址
+    pub fn set_address(&mut self, address: &str) {
+        self.address = Some(address.to_string());
+        self.client = None;
+    }
+
+    /// 开始发送一个对象，之后`send`写入的内容都带有该对象的句柄和偏移
+    pub fn begin_object(&mut self, handle: u32, size: u64) -> Result<(), Box<dyn Error>> {
+        let client = self.client.as_mut().ok_or("WiFi客户端未连接")?;
+        client.begin_object(handle, size)
+    }
+
+    /// 结束当前对象，等待手机确认收到全部数据
+    pub fn finish_object(&mut self) -> Result<(), Box<dyn Error>> {
+        let client = self.client.as_mut().ok_or("WiFi客户端未连接")?;
+        if let Err(e) = client.finish_object() {
+            warn!("对象发送未被确认: {}", e);
+            self.client = None;
+            return Err(e);
         }
+        Ok(())
     }
 }
 
+#[cfg(any(feature = "wifi", feature = "ethernet"))]
 impl DataSender for WifiSender {
-    fn send_data(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>> {
-        // 通过WiFi发送数据
-        if let Some(stream) = &mut self.client {
-            // 使用Write trait将数据写入TCP流
-            // TcpStream 实现了特殊的 Write trait，可以在不可变引用上调用 write
-            match stream.write(data) {
+    /// 连接到设置的地址；等待手机确认超过`ACK_TIMEOUT`时视为连接已失效
+    fn connect(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            let address = self.address.as_deref().ok_or("未设置WiFi客户端地址")?;
+            let stream = LinkStream::connect(address, self.tls.as_ref(), ACK_TIMEOUT)?;
+            debug!("已连接 {} ({})", address, if stream.is_tls() { "TLS" } else { "明文" });
+            self.client = Some(FramedSender::new(stream));
+            self.reported_retransmits = 0;
+            Ok(())
+        })
+    }
+
+    fn state(&self) -> SenderState {
+        match &self.client {
+            None => SenderState::Disconnected,
+            Some(client) if client.window_full() => SenderState::Busy,
+            Some(_) => SenderState::Ready,
+        }
+    }
+
+    fn ready(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            let client = self.client.as_mut().ok_or("WiFi客户端未连接")?;
+            if let Err(e) = client.wait_window() {
+                warn!("等待手机确认失败: {}", e);
+                self.client = None;
+                return Err(e);
+            }
+            Ok(())
+        })
+    }
+
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move {
+            // 通过WiFi发送数据
+            let Some(client) = &mut self.client else {
+                return Err("WiFi客户端未连接".into());
+            };
+            match client.send(&packet.data) {
                 Ok(bytes_written) => {
                     debug!("成功通过WiFi发送{}字节的数据", bytes_written);
+                    self.update_meter(bytes_written);
                     Ok(bytes_written)
                 }
                 Err(e) => {
+                    // 写入失败或等待确认超时后连接不再可用，下次发送前重连
                     warn!("WiFi数据发送失败: {}", e);
-                    Err(Box::new(e))
+                    self.client = None;
+                    Err(e)
                 }
             }
-        } else {
-            Err("WiFi客户端未连接".into())
-        }
+        })
     }
 
-    fn close(&mut self) -> Result<(), Box<dyn Error>> {
-        // 关闭WiFi发送器
-        self.client = None;
-        Ok(())
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            // 关闭前尽量等手机确认已发送的数据
+            if let Some(mut client) = self.client.take() {
+                if let Err(e) = client.flush_acks() {
+                    warn!("关闭前有 {} 帧未被确认: {}", client.unacked(), e);
+                }
+            }
+            Ok(())
+        })
     }
 }
 
 /// 蓝牙数据发送器
+#[cfg(feature = "ble")]
 pub struct BluetoothSender {
     device_name: String,
     // 蓝牙服务器实例，与WirelessManager共享
@@ -885,11 +1742,14 @@ pub struct BluetoothSender {
     bt_condvar: Arc<Condvar>,
     gatts: Arc<EspGatts<'static, EspBle, Arc<BtDriver<'static, EspBle>>>>,
     gap: Arc<EspBleGap<'static, EspBle, Arc<BtDriver<'static, EspBle>>>>,
+    closed: bool, // 已调用close停止广播
+    meter: Option<LinkMeterHandle>, // 记录吞吐，None表示不统计
 }
 
+#[cfg(feature = "ble")]
 impl BluetoothSender {
-    /// 创建新的蓝牙发送器
-    pub fn new(
+    /// 创建新的蓝牙发送器(GATT状态由WirelessManager持有，不对外暴露)
+    pub(crate) fn new(
         device_name: String,
         bt_state: Arc<Mutex<BluetoothServerState>>,
         bt_condvar: Arc<Condvar>,
@@ -902,35 +1762,82 @@ impl BluetoothSender {
             bt_condvar,
             gatts,
             gap,
+            closed: false,
+            meter: None,
         }
     }
+
+    /// 把发送的字节数记入共享的链路统计
+    pub fn with_meter(mut self, meter: LinkMeterHandle) -> Self {
+        self.meter = Some(meter);
+        self
+    }
 }
 
+#[cfg(feature = "ble")]
 impl DataSender for BluetoothSender {
+    /// 重新开始广播，等待手机连接并订阅
+    fn connect(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            self.gap.start_advertising()?;
+            self.closed = false;
+            info!("蓝牙发送器重新开始广播");
+            Ok(())
+        })
+    }
+
+    fn state(&self) -> SenderState {
+        if self.closed {
+            SenderState::Disconnected
+        } else if self.bt_state.lock().unwrap().ind_confirmed.is_some() {
+            SenderState::Busy
+        } else {
+            SenderState::Ready
+        }
+    }
+
+    /// 等待上一个indication被确认
+    fn ready(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            while self.bt_state.lock().unwrap().ind_confirmed.is_some() {
+                Timer::after(EmbassyDuration::from_millis(INDICATION_POLL_INTERVAL_MS)).await;
+            }
+            Ok(())
+        })
+    }
+
     /// 通过蓝牙发送数据
-    fn send_data(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>> {
-        // 创建服务器实例
-        let server = BluetoothServer {
-            gap: self.gap.clone(),
-            gatts: self.gatts.clone(),
-            state: self.bt_state.clone(),
-            condvar: self.bt_condvar.clone(),
-            device_name: self.device_name.clone(),
-        };
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move {
+            // 创建服务器实例
+            let server = BluetoothServer {
+                gap: self.gap.clone(),
+                gatts: self.gatts.clone(),
+                state: self.bt_state.clone(),
+                condvar: self.bt_condvar.clone(),
+                device_name: self.device_name.clone(),
+            };
 
-        // 发送数据
-        server.indicate(data)?;
+            // 发送数据
+            server.indicate(&packet.data)?;
+            if let Some(meter) = &self.meter {
+                meter.lock().unwrap().record_sent(packet.data.len());
+            }
 
-        // 返回发送的字节数
-        Ok(data.len())
+            // 返回发送的字节数
+            Ok(packet.data.len())
+        })
     }
 
     /// 关闭蓝牙连接
-    fn close(&mut self) -> Result<(), Box<dyn Error>> {
-        // 停止广播
-        self.gap.stop_advertising()?;
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            // 停止广播
+            self.gap.stop_advertising()?;
+            self.closed = true;
 
-        info!("蓝牙发送器已关闭");
-        Ok(())
+            info!("蓝牙发送器已关闭");
+            Ok(())
+        })
     }
 }
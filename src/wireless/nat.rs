@@ -0,0 +1,75 @@
+// AP+STA流量转发 - 连着相机热点的手机经ESP32的STA连接访问上级网络(NAPT)，
+// 图片在本地直传手机的同时，设备和手机都能通过家里的WiFi上传到云端
+//
+// 需要在sdkconfig中启用CONFIG_LWIP_IP_FORWARD和CONFIG_LWIP_IPV4_NAPT；
+// 设备自己的出站连接走路由优先级更高的STA接口，不受转发影响
+use std::error::Error;
+use std::ffi::c_void;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::handle::RawHandle;
+use esp_idf_svc::sys::{
+    esp, esp_netif_dhcp_option_id_t_ESP_NETIF_DOMAIN_NAME_SERVER, esp_netif_dhcp_option_mode_t_ESP_NETIF_OP_SET,
+    esp_netif_dhcps_option, esp_netif_dhcps_start, esp_netif_dhcps_stop, esp_netif_dns_info_t,
+    esp_netif_dns_type_t_ESP_NETIF_DNS_MAIN, esp_netif_get_dns_info, esp_netif_napt_disable, esp_netif_napt_enable,
+    esp_netif_set_dns_info,
+};
+use esp_idf_svc::wifi::EspWifi;
+use log::{info, warn};
+
+/// DHCP服务器向客户端下发DNS服务器(dhcpserver.h中的OFFER_DNS)
+const OFFER_DNS: u8 = 0x02;
+/// 等待STA接口获得地址的轮询间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 等待STA接口连上上级网络并获得地址，超时返回false
+pub fn wait_sta_up(wifi: &EspWifi<'static>, timeout: Duration) -> Result<bool, Box<dyn Error>> {
+    let deadline = Instant::now() + timeout;
+    while !wifi.sta_netif().is_up()? {
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(true)
+}
+
+/// 开启SoftAP到STA的转发，并让SoftAP的DHCP服务器下发上级网络的DNS服务器；需要STA接口已获得地址
+pub fn enable(wifi: &EspWifi<'static>) -> Result<(), Box<dyn Error>> {
+    if !wifi.sta_netif().is_up()? {
+        return Err("STA接口尚未获得地址，无法转发SoftAP流量".into());
+    }
+    let ap = wifi.ap_netif().handle();
+    let sta = wifi.sta_netif().handle();
+    unsafe {
+        let mut dns: esp_netif_dns_info_t = std::mem::zeroed();
+        esp!(esp_netif_get_dns_info(sta, esp_netif_dns_type_t_ESP_NETIF_DNS_MAIN, &mut dns))?;
+        // DHCP服务器运行时不能修改选项；已停止时返回的错误可以忽略
+        let _ = esp_netif_dhcps_stop(ap);
+        esp!(esp_netif_set_dns_info(ap, esp_netif_dns_type_t_ESP_NETIF_DNS_MAIN, &mut dns))?;
+        let mut offer = OFFER_DNS;
+        esp!(esp_netif_dhcps_option(
+            ap,
+            esp_netif_dhcp_option_mode_t_ESP_NETIF_OP_SET,
+            esp_netif_dhcp_option_id_t_ESP_NETIF_DOMAIN_NAME_SERVER,
+            &mut offer as *mut u8 as *mut c_void,
+            1,
+        ))?;
+        esp!(esp_netif_dhcps_start(ap))?;
+        esp!(esp_netif_napt_enable(ap))?;
+    }
+    info!("已开启SoftAP到上级网络的转发");
+    Ok(())
+}
+
+/// 关闭SoftAP到STA的转发
+pub fn disable(wifi: &EspWifi<'static>) -> Result<(), Box<dyn Error>> {
+    let ap = wifi.ap_netif().handle();
+    if let Err(e) = unsafe { esp!(esp_netif_napt_disable(ap)) } {
+        warn!("关闭SoftAP转发失败: {}", e);
+        return Err(e.into());
+    }
+    info!("已关闭SoftAP到上级网络的转发");
+    Ok(())
+}
@@ -0,0 +1,59 @@
+// 新对象计数 - 相机每新增一个对象计数加一，通过蓝牙通知特征值推送给订阅的手机；
+// 手机只需订阅这个4字节的特征值，计数变化时再唤醒应用开始同步，平时几乎不耗电
+use std::time::{Duration, Instant};
+
+/// 两次通知之间的最短间隔，连拍时合并为一次通知
+pub const DEFAULT_NOTIFY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 单调递增的新对象计数(溢出后回绕)
+#[derive(Debug)]
+pub struct NewObjectCounter {
+    count: u32,
+    pending: bool,               // 计数已变化但尚未通知
+    last_notify: Option<Instant>,
+    interval: Duration,
+}
+
+impl Default for NewObjectCounter {
+    fn default() -> Self {
+        Self::new(DEFAULT_NOTIFY_INTERVAL)
+    }
+}
+
+impl NewObjectCounter {
+    pub fn new(interval: Duration) -> Self {
+        NewObjectCounter {
+            count: 0,
+            pending: false,
+            last_notify: None,
+            interval,
+        }
+    }
+
+    /// 记录新增的对象
+    pub fn record(&mut self, objects: u32) {
+        if objects == 0 {
+            return;
+        }
+        self.count = self.count.wrapping_add(objects);
+        self.pending = true;
+    }
+
+    /// 计数有变化且距上次通知已超过最短间隔时返回要通知的计数
+    pub fn take_due(&mut self, now: Instant) -> Option<u32> {
+        if !self.pending {
+            return None;
+        }
+        if self.last_notify.is_some_and(|last| now.saturating_duration_since(last) < self.interval) {
+            return None;
+        }
+        self.pending = false;
+        self.last_notify = Some(now);
+        Some(self.count)
+    }
+
+    /// 特征值内容：小端u32
+    pub fn encode(count: u32) -> [u8; 4] {
+        count.to_le_bytes()
+    }
+}
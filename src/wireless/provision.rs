@@ -0,0 +1,348 @@
+// SoftAP配网 - 保存的网络都连不上时以SoftAP启动一个强制门户页面，用户选择扫描到的网络并输入密码，
+// 凭证保存到NVS后重启，以STA方式连接新网络
+//
+// 门户由两部分组成：DNS服务把所有域名解析到设备，手机系统检测网络时就会弹出配网页面；
+// HTTP服务对任意路径都返回配网页面，表单提交到 POST /provision。
+// 凭证以明文保存在NVS中，需要保护时应开启ESP-IDF的NVS加密
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::time::Duration;
+
+use embedded_svc::http::Method;
+use embedded_svc::io::{Read, Write};
+use embedded_svc::wifi::AuthMethod;
+use esp_idf_svc::http::server::{Configuration as HttpServerConfiguration, EspHttpServer};
+use esp_idf_svc::io::EspIOError;
+use log::{debug, info, warn};
+
+use super::http::percent_decode;
+use super::{ConnectionConfig, ScannedNetwork, SoftApSettings, WirelessManager};
+use crate::config::{ChannelPolicy, UpstreamWifi, WifiAuth};
+use crate::persist::KvStore;
+use crate::runtime;
+
+/// 保存配网凭证的NVS命名空间
+pub const NVS_NAMESPACE: &str = "provision";
+/// 配网凭证的键
+const CREDENTIALS_KEY: &str = "wifi_sta";
+/// 等待用户提交的时间，超时后重启，再次尝试已保存的网络
+pub const PORTAL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// 提交后等待响应页面发出再重启
+const RESTART_DELAY: Duration = Duration::from_secs(2);
+/// 配网表单的最大长度
+const MAX_FORM_BODY: usize = 512;
+/// DNS应答中记录的有效期(秒)，配网结束后手机很快会重新解析
+const DNS_TTL: u32 = 60;
+/// DNS服务检查退出标志的间隔
+const DNS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 配网得到的WiFi凭证
+pub struct CredentialStore {
+    store: Box<dyn KvStore>,
+}
+
+impl CredentialStore {
+    pub fn open(store: Box<dyn KvStore>) -> Self {
+        CredentialStore { store }
+    }
+
+    /// 读取保存的凭证，没有或无法解析时返回None
+    pub fn load(&self) -> Option<UpstreamWifi> {
+        let data = match self.store.get(CREDENTIALS_KEY) {
+            Ok(data) => data?,
+            Err(e) => {
+                warn!("读取配网凭证失败: {}", e);
+                return None;
+            }
+        };
+        match serde_json::from_slice(&data) {
+            Ok(upstream) => Some(upstream),
+            Err(e) => {
+                warn!("配网凭证无法解析: {}", e);
+                None
+            }
+        }
+    }
+
+    /// 校验并保存凭证
+    pub fn save(&mut self, upstream: &UpstreamWifi) -> Result<(), Box<dyn Error>> {
+        upstream.validate()?;
+        self.store.set(CREDENTIALS_KEY, &serde_json::to_vec(upstream)?)?;
+        self.store.flush()
+    }
+
+    /// 清除保存的凭证，之后使用配置文件中的网络
+    pub fn clear(&mut self) -> Result<(), Box<dyn Error>> {
+        self.store.remove(CREDENTIALS_KEY)?;
+        self.store.flush()
+    }
+}
+
+/// 构造把查询的域名解析到`ip`的DNS应答；不是标准查询时返回None
+///
+/// 只应答A记录，其他类型返回不带记录的成功应答，避免手机等待IPv6解析超时
+pub fn dns_answer(query: &[u8], ip: Ipv4Addr) -> Option<Vec<u8>> {
+    if query.len() < 12 {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let questions = u16::from_be_bytes([query[4], query[5]]);
+    // QR=0且OPCODE=0的标准查询，只处理一个问题
+    if flags & 0xF800 != 0 || questions != 1 {
+        return None;
+    }
+    let mut pos = 12;
+    loop {
+        let len = *query.get(pos)? as usize;
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        if len & 0xC0 != 0 {
+            return None; // 问题中不应出现压缩指针
+        }
+        pos += 1 + len;
+    }
+    let question_end = pos + 4;
+    let qtype = u16::from_be_bytes([*query.get(pos)?, *query.get(pos + 1)?]);
+    let qclass = u16::from_be_bytes([*query.get(pos + 2)?, *query.get(pos + 3)?]);
+    let answer = qtype == 1 && qclass == 1;
+
+    let mut reply = Vec::with_capacity(question_end + 16);
+    reply.extend_from_slice(&query[..2]);
+    // QR=1、保留RD、RA=1、RCODE=0
+    reply.extend_from_slice(&(0x8080 | (flags & 0x0100)).to_be_bytes());
+    reply.extend_from_slice(&1u16.to_be_bytes());
+    reply.extend_from_slice(&(answer as u16).to_be_bytes());
+    reply.extend_from_slice(&[0, 0, 0, 0]);
+    reply.extend_from_slice(&query[12..question_end]);
+    if answer {
+        reply.extend_from_slice(&[0xC0, 0x0C]); // 指向问题中的域名
+        reply.extend_from_slice(&1u16.to_be_bytes());
+        reply.extend_from_slice(&1u16.to_be_bytes());
+        reply.extend_from_slice(&DNS_TTL.to_be_bytes());
+        reply.extend_from_slice(&4u16.to_be_bytes());
+        reply.extend_from_slice(&ip.octets());
+    }
+    Some(reply)
+}
+
+/// 把所有域名解析到设备的DNS服务，随对象释放而停止
+pub struct CaptiveDns {
+    stop: Arc<AtomicBool>,
+}
+
+impl CaptiveDns {
+    pub fn start(ip: Ipv4Addr) -> Result<Self, Box<dyn Error>> {
+        let socket = UdpSocket::bind(("0.0.0.0", 53))?;
+        socket.set_read_timeout(Some(DNS_POLL_INTERVAL))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = stop.clone();
+        runtime::spawn(runtime::CAPTIVE_DNS, move || {
+            let mut buf = [0u8; 512];
+            while !running.load(Ordering::Relaxed) {
+                let (n, peer) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(_) => continue, // 读取超时
+                };
+                if let Some(reply) = dns_answer(&buf[..n], ip) {
+                    if let Err(e) = socket.send_to(&reply, peer) {
+                        debug!("DNS应答发送失败: {}", e);
+                    }
+                }
+            }
+        })?;
+        info!("配网DNS服务已启动，所有域名解析到 {}", ip);
+        Ok(CaptiveDns { stop })
+    }
+}
+
+impl Drop for CaptiveDns {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// 把扫描结果的认证方式对应到配置中的认证方式；企业级网络需要用户名，不能在门户中配置
+fn auth_for(network: Option<&ScannedNetwork>, password: &str) -> Result<WifiAuth, String> {
+    let Some(network) = network else {
+        // 隐藏网络扫描不到，按是否有密码判断
+        return Ok(if password.is_empty() { WifiAuth::Open } else { WifiAuth::Wpa2Personal });
+    };
+    match network.auth_method {
+        AuthMethod::None => Ok(WifiAuth::Open),
+        AuthMethod::WPA3Personal => Ok(WifiAuth::Wpa3Personal),
+        AuthMethod::WPA2WPA3Personal => Ok(WifiAuth::Wpa2Wpa3Personal),
+        AuthMethod::WPA2Enterprise => Err("企业级网络请通过配置文件设置".to_string()),
+        _ => Ok(WifiAuth::Wpa2Personal),
+    }
+}
+
+/// 解析配网表单(application/x-www-form-urlencoded)
+pub fn parse_form(body: &str, networks: &[ScannedNetwork]) -> Result<UpstreamWifi, String> {
+    let field = |key: &str| {
+        body.split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| percent_decode(v))
+            .unwrap_or_default()
+    };
+    let ssid = field("ssid");
+    let password = field("password");
+    let auth = auth_for(networks.iter().find(|n| n.ssid == ssid), &password)?;
+    let upstream = UpstreamWifi {
+        ssid,
+        password,
+        auth,
+        address: Default::default(),
+    };
+    upstream.validate().map_err(|e| e.to_string())?;
+    Ok(upstream)
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// 配网页面：网络名称可从扫描结果中选择，也可手动输入隐藏网络
+pub fn render_page(device_name: &str, networks: &[ScannedNetwork], error: Option<&str>) -> String {
+    let options: String = networks
+        .iter()
+        .map(|n| format!("<option value=\"{}\">{} dBm</option>", escape_html(&n.ssid), n.rssi))
+        .collect();
+    let error = error
+        .map(|e| format!("<p style=\"color:#c00\">{}</p>", escape_html(e)))
+        .unwrap_or_default();
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width,initial-scale=1\"><title>{name} 配网</title></head>\
+         <body style=\"font-family:sans-serif;max-width:24em;margin:2em auto\"><h2>{name} 连接WiFi</h2>{error}\
+         <form method=\"post\" action=\"/provision\">\
+         <p><label>网络名称<br><input name=\"ssid\" list=\"networks\" required maxlength=\"32\"></label></p>\
+         <datalist id=\"networks\">{options}</datalist>\
+         <p><label>密码<br><input name=\"password\" type=\"password\" maxlength=\"64\"></label></p>\
+         <p><button type=\"submit\">保存并重启</button></p></form></body></html>",
+        name = escape_html(device_name),
+    )
+}
+
+/// 配网门户，随对象释放而停止
+pub struct ProvisioningPortal {
+    _server: EspHttpServer<'static>,
+    _dns: CaptiveDns,
+    submitted: Receiver<UpstreamWifi>,
+}
+
+impl ProvisioningPortal {
+    /// 在SoftAP接口`ip`上启动门户，`networks`为启动SoftAP前扫描到的网络
+    pub fn start(ip: Ipv4Addr, device_name: &str, networks: Vec<ScannedNetwork>) -> Result<Self, Box<dyn Error>> {
+        let dns = CaptiveDns::start(ip)?;
+        let mut server = EspHttpServer::new(&HttpServerConfiguration {
+            uri_match_wildcard: true,
+            ..Default::default()
+        })?;
+        let (tx, submitted) = mpsc::channel();
+
+        let networks = Arc::new(networks);
+        let form_networks = networks.clone();
+        let form_name = device_name.to_string();
+        server.fn_handler("/provision", Method::Post, move |mut req| -> Result<(), EspIOError> {
+            let mut body = Vec::new();
+            let mut buf = [0u8; 128];
+            loop {
+                let n = req.read(&mut buf)?;
+                if n == 0 || body.len() + n > MAX_FORM_BODY {
+                    break;
+                }
+                body.extend_from_slice(&buf[..n]);
+            }
+            let (code, page) = match parse_form(&String::from_utf8_lossy(&body), &form_networks) {
+                Ok(upstream) => {
+                    info!("用户提交了网络 {}", upstream.ssid);
+                    let page = format!(
+                        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"></head>\
+                         <body style=\"font-family:sans-serif\"><p>已保存，{} 正在重启并连接 {}</p></body></html>",
+                        escape_html(&form_name),
+                        escape_html(&upstream.ssid)
+                    );
+                    let _ = tx.send(upstream);
+                    (200, page)
+                }
+                Err(e) => {
+                    warn!("配网表单无效: {}", e);
+                    (400, render_page(&form_name, &form_networks, Some(&e)))
+                }
+            };
+            let mut resp = req.into_response(code, None, &[("Content-Type", "text/html; charset=utf-8")])?;
+            resp.write_all(page.as_bytes())?;
+            Ok(())
+        })?;
+
+        // 手机系统检测网络时访问的任意地址都返回配网页面
+        let page = render_page(device_name, &networks, None);
+        server.fn_handler("/*", Method::Get, move |req| -> Result<(), EspIOError> {
+            let mut resp = req.into_response(200, None, &[("Content-Type", "text/html; charset=utf-8"), ("Cache-Control", "no-store")])?;
+            resp.write_all(page.as_bytes())?;
+            Ok(())
+        })?;
+
+        info!("配网门户已启动: http://{}/", ip);
+        Ok(ProvisioningPortal {
+            _server: server,
+            _dns: dns,
+            submitted,
+        })
+    }
+
+    /// 等待用户提交凭证，超时返回None
+    pub fn wait(&self, timeout: Duration) -> Option<UpstreamWifi> {
+        self.submitted.recv_timeout(timeout).ok()
+    }
+}
+
+/// 配网流程：扫描周边网络，以SoftAP启动门户，用户提交后保存凭证并重启；超时也会重启，重新尝试已保存的网络
+///
+/// 成功时不返回，只有SoftAP或门户无法启动时返回错误
+pub fn run(
+    wireless: &mut WirelessManager,
+    ap: SoftApSettings,
+    credentials: &mut CredentialStore,
+) -> Result<Infallible, Box<dyn Error>> {
+    warn!("无法连接已保存的WiFi网络，进入配网模式");
+    let networks = wireless.scan().unwrap_or_else(|e| {
+        warn!("扫描WiFi网络失败: {}", e);
+        Vec::new()
+    });
+    let device_name = ap.ssid.clone();
+    wireless.connect(ConnectionConfig::SoftAp {
+        ap,
+        channel: ChannelPolicy::default(),
+    })?;
+    let portal = ProvisioningPortal::start(wireless.ap_ip()?, &device_name, networks)?;
+
+    match portal.wait(PORTAL_TIMEOUT) {
+        Some(upstream) => {
+            credentials.save(&upstream)?;
+            info!("已保存WiFi网络 {}，重启后连接", upstream.ssid);
+        }
+        None => info!("{}分钟内未完成配网，重启后重新尝试已保存的网络", PORTAL_TIMEOUT.as_secs() / 60),
+    }
+    std::thread::sleep(RESTART_DELAY);
+    drop(portal);
+    esp_idf_hal::reset::restart();
+}
@@ -0,0 +1,186 @@
+// 实时推送通道 - 通过 /ws/push 把取景画面和传输进度、传输事件推送给手机应用，代替轮询HTTP接口
+// 每条消息是一个二进制帧：1字节类型 + 4字节小端序号 + 内容；序号在所有类型间连续，客户端据此发现丢帧
+//   0x01 取景画面：JPEG数据
+//   0x02 传输进度：句柄u32 + 已完成字节u64 + 总字节u64(小端)
+//   0x03 传输事件：AppEvent的JSON
+// 发送在独立任务中进行，发布方从不阻塞：队列满时丢弃新消息，客户端通过序号发现丢帧
+// 帧编码不依赖ESP-IDF，主机上的单元测试只编译这一部分
+#[cfg(feature = "http")]
+use std::error::Error;
+#[cfg(feature = "http")]
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+#[cfg(feature = "http")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "http")]
+use esp_idf_svc::http::server::ws::EspHttpWsDetachedSender;
+#[cfg(feature = "http")]
+use esp_idf_svc::http::server::EspHttpServer;
+#[cfg(feature = "http")]
+use esp_idf_svc::sys::{EspError, ESP_FAIL};
+#[cfg(feature = "http")]
+use esp_idf_svc::ws::FrameType;
+#[cfg(feature = "http")]
+use log::{debug, info, warn};
+
+#[cfg(feature = "http")]
+use crate::control::{AuthGuard, ControlChannel, ControlCommand};
+#[cfg(feature = "http")]
+use crate::events::{AppEvent, EventListener};
+use crate::ptp_mtp::ObjectProgress;
+#[cfg(feature = "http")]
+use crate::runtime;
+#[cfg(feature = "http")]
+use crate::wireless::http::ws_bearer_token;
+
+/// 帧类型
+pub const FRAME_LIVE_VIEW: u8 = 0x01;
+pub const FRAME_PROGRESS: u8 = 0x02;
+pub const FRAME_EVENT: u8 = 0x03;
+/// 帧头长度
+pub const HEADER_LEN: usize = 5;
+/// 等待发送的消息上限，取景画面较大，只缓存少量
+#[cfg(feature = "http")]
+const QUEUE_LEN: usize = 4;
+
+/// 编码一个推送帧
+pub fn encode_frame(kind: u8, seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.push(kind);
+    frame.extend_from_slice(&seq.to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// 编码传输进度的内容
+pub fn encode_progress(progress: &ObjectProgress) -> [u8; 20] {
+    let mut payload = [0u8; 20];
+    payload[..4].copy_from_slice(&progress.handle.to_le_bytes());
+    payload[4..12].copy_from_slice(&progress.bytes_done.to_le_bytes());
+    payload[12..].copy_from_slice(&progress.total.to_le_bytes());
+    payload
+}
+
+/// 已订阅的WebSocket客户端
+#[cfg(feature = "http")]
+type Subscribers = Arc<Mutex<Vec<EspHttpWsDetachedSender>>>;
+
+/// 推送通道的发布端，可以克隆后交给各子系统
+#[cfg(feature = "http")]
+#[derive(Clone)]
+pub struct PushChannel {
+    tx: SyncSender<(u8, Vec<u8>)>,
+    subscribers: Subscribers,
+}
+
+#[cfg(feature = "http")]
+impl PushChannel {
+    /// 推送一帧取景画面，没有订阅者时不复制画面
+    pub fn publish_frame(&self, jpeg: &[u8]) {
+        if !self.has_subscribers() {
+            return;
+        }
+        self.publish(FRAME_LIVE_VIEW, jpeg.to_vec());
+    }
+
+    /// 推送传输进度
+    pub fn publish_progress(&self, progress: &ObjectProgress) {
+        self.publish(FRAME_PROGRESS, encode_progress(progress).to_vec());
+    }
+
+    /// 推送传输事件
+    pub fn publish_event(&self, event: &AppEvent) {
+        if let Ok(json) = serde_json::to_vec(event) {
+            self.publish(FRAME_EVENT, json);
+        }
+    }
+
+    /// 当前是否有客户端订阅
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.lock().unwrap().is_empty()
+    }
+
+    /// 没有订阅者或队列已满时丢弃，不阻塞主循环和事件总线
+    fn publish(&self, kind: u8, payload: Vec<u8>) {
+        if !self.has_subscribers() {
+            return;
+        }
+        match self.tx.try_send((kind, payload)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => debug!("推送队列已满，丢弃类型 0x{:02x} 的消息", kind),
+            Err(TrySendError::Disconnected(_)) => warn!("推送任务已退出，丢弃类型 0x{:02x} 的消息", kind),
+        }
+    }
+}
+
+/// 订阅事件总线后，传输事件自动推送给客户端
+#[cfg(feature = "http")]
+impl EventListener for PushChannel {
+    fn on_event(&mut self, event: &AppEvent) {
+        self.publish_event(event);
+    }
+}
+
+/// 注册 /ws/push，并启动发送任务；升级请求需携带可读取状态的 `Authorization: Bearer 令牌`
+#[cfg(feature = "http")]
+pub fn serve(server: &mut EspHttpServer<'static>, guard: Arc<Mutex<AuthGuard>>) -> Result<PushChannel, Box<dyn Error>> {
+    let subscribers: Subscribers = Arc::new(Mutex::new(Vec::new()));
+
+    let subs = subscribers.clone();
+    server.ws_handler("/ws/push", move |ws| {
+        if ws.is_new() {
+            let token = ws_bearer_token(ws);
+            if let Err(e) = guard.lock().unwrap().authorize(ControlChannel::WebSocket, &token, &ControlCommand::GetStatus) {
+                warn!("拒绝推送订阅，会话 {}: {}", ws.session(), e);
+                return Err(EspError::from_infallible::<ESP_FAIL>());
+            }
+            subs.lock().unwrap().push(ws.create_detached_sender()?);
+            debug!("推送订阅者加入，会话 {}", ws.session());
+        }
+        // 断开的连接在发送失败时移除
+        Ok::<(), EspError>(())
+    })?;
+
+    let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+    let subs = subscribers.clone();
+    runtime::spawn(runtime::WS_PUSH, move || send_loop(rx, subs))?;
+    info!("实时推送通道已启动");
+    Ok(PushChannel { tx, subscribers })
+}
+
+#[cfg(feature = "http")]
+fn send_loop(rx: Receiver<(u8, Vec<u8>)>, subscribers: Subscribers) {
+    let mut seq: u32 = 0;
+    for (kind, payload) in rx {
+        let frame = encode_frame(kind, seq, &payload);
+        seq = seq.wrapping_add(1);
+        let mut subs = subscribers.lock().unwrap();
+        subs.retain_mut(|sender| match sender.send(FrameType::Binary(false), &frame) {
+            Ok(()) => true,
+            Err(e) => {
+                debug!("推送订阅者已断开: {}", e);
+                false
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_starts_with_kind_and_sequence() {
+        let frame = encode_frame(FRAME_LIVE_VIEW, 0x0102_0304, &[0xFF, 0xD8]);
+        assert_eq!(frame, vec![FRAME_LIVE_VIEW, 0x04, 0x03, 0x02, 0x01, 0xFF, 0xD8]);
+        assert_eq!(encode_frame(FRAME_EVENT, 0, &[]).len(), HEADER_LEN);
+    }
+
+    #[test]
+    fn progress_is_little_endian() {
+        let payload = encode_progress(&ObjectProgress { handle: 0x0001_0002, bytes_done: 5, total: 1 << 40 });
+        assert_eq!(payload[..4], [0x02, 0x00, 0x01, 0x00]);
+        assert_eq!(u64::from_le_bytes(payload[4..12].try_into().unwrap()), 5);
+        assert_eq!(u64::from_le_bytes(payload[12..].try_into().unwrap()), 1 << 40);
+    }
+}
@@ -0,0 +1,88 @@
+// WiFi自动重连 - STA连接断开后按指数退避重试，断开和恢复时各产生一个事件，
+// 由主循环据此暂停和恢复传输；状态机本身不操作驱动，只根据轮询到的链路状态决定何时重试
+use std::time::{Duration, Instant};
+
+use crate::events::AppEvent;
+
+/// 断开后第一次重试前的等待时间
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// 重试间隔上限
+pub const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// 重连状态
+#[derive(Debug)]
+pub struct Reconnector {
+    link: &'static str,          // 事件中的链路名称
+    lost_at: Option<Instant>,    // 连接断开的时刻，None表示连接正常
+    attempts: u32,               // 本次断开后已发起的重连次数
+    next_retry: Option<Instant>,
+    initial: Duration,
+    max: Duration,
+}
+
+impl Reconnector {
+    pub fn new(link: &'static str, initial: Duration, max: Duration) -> Self {
+        Reconnector {
+            link,
+            lost_at: None,
+            attempts: 0,
+            next_retry: None,
+            initial,
+            max,
+        }
+    }
+
+    /// 第`attempt`次重试之后的等待时间，每次翻倍直到上限
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+
+    /// 记录轮询到的链路状态，连接刚断开或刚恢复时返回事件
+    pub fn observe(&mut self, up: bool, now: Instant) -> Option<AppEvent> {
+        match (up, self.lost_at) {
+            (false, None) => {
+                self.lost_at = Some(now);
+                self.attempts = 0;
+                self.next_retry = Some(now + self.initial);
+                Some(AppEvent::ConnectionLost {
+                    link: self.link.to_string(),
+                })
+            }
+            (true, Some(lost_at)) => {
+                let event = AppEvent::ConnectionRestored {
+                    link: self.link.to_string(),
+                    attempts: self.attempts,
+                    downtime_secs: now.saturating_duration_since(lost_at).as_secs(),
+                };
+                self.reset();
+                Some(event)
+            }
+            _ => None,
+        }
+    }
+
+    /// 断开状态下到了重试时间时返回true，并安排下一次重试
+    pub fn retry_due(&mut self, now: Instant) -> bool {
+        match self.next_retry {
+            Some(at) if now >= at => {
+                self.attempts += 1;
+                self.next_retry = Some(now + self.backoff(self.attempts));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// 本次断开后已发起的重连次数
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// 主动断开或重新连接时清除状态
+    pub fn reset(&mut self) {
+        self.lost_at = None;
+        self.attempts = 0;
+        self.next_retry = None;
+    }
+}
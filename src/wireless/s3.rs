@@ -0,0 +1,496 @@
+// S3兼容云存储发送器 - 将完整对象直接上传到S3兼容的存储桶
+// 数据按分片边写边传，不在内存中缓存整个对象；分片进度可导出用于断点续传
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use embedded_svc::http::client::Connection;
+use embedded_svc::http::Method;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::client::EspHttpConnection;
+use hmac::{Hmac, Mac};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::tls::TlsMaterial;
+use super::{DataSender, SenderFuture};
+use crate::ptp_mtp::DataPacket;
+use crate::config::{S3Auth, S3Config};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// 分片上传进度，可持久化后用于续传
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadProgress {
+    pub key: String,                // 对象键
+    pub total_size: u64,            // 对象总大小
+    pub upload_id: Option<String>,  // 分片上传ID，整对象上传时为None
+    pub parts: Vec<(u32, String)>,  // 已完成的分片(序号, ETag)
+}
+
+impl UploadProgress {
+    /// 已确认上传的字节数
+    pub fn committed_bytes(&self, part_size: usize) -> u64 {
+        self.parts.len() as u64 * part_size as u64
+    }
+}
+
+/// 正在上传的对象
+struct ActiveUpload {
+    progress: UploadProgress,
+    written: u64,                          // 对象已写入字节数
+    part_written: usize,                   // 当前请求已写入字节数
+    request: Option<EspHttpConnection>,    // 正在写入的请求
+}
+
+/// S3兼容存储发送器
+pub struct S3Sender {
+    config: S3Config,
+    tls: TlsMaterial,
+    active: Option<ActiveUpload>,
+    presigned_used: bool, // 预签名地址已上传完一个对象，下一个对象需要新的地址；上传失败时可以重试
+}
+
+impl S3Sender {
+    /// 创建新的S3发送器，加载端点使用的证书
+    pub fn new(config: S3Config) -> Result<Self, Box<dyn Error>> {
+        let tls = TlsMaterial::load(&config.tls)?;
+        tls.install_global_ca()?;
+        Ok(S3Sender {
+            config,
+            tls,
+            active: None,
+            presigned_used: false,
+        })
+    }
+
+    /// 为下一个对象设置新的预签名PUT地址；预签名地址只对应一个对象键，每个对象都需要单独签发
+    pub fn set_presigned_url(&mut self, url: &str) -> Result<(), Box<dyn Error>> {
+        if !matches!(self.config.auth, S3Auth::PresignedUrl(_)) {
+            return Err("S3存储使用访问密钥，不需要预签名地址".into());
+        }
+        if self.active.is_some() {
+            return Err("上一个对象尚未上传完成".into());
+        }
+        self.config.auth = S3Auth::PresignedUrl(url.to_string());
+        self.presigned_used = false;
+        Ok(())
+    }
+
+    /// 开始上传一个对象，之后通过 `send` 写入对象内容
+    pub fn begin_object(&mut self, name: &str, total_size: u64) -> Result<(), Box<dyn Error>> {
+        if self.active.is_some() {
+            return Err("上一个对象尚未上传完成".into());
+        }
+        let key = format!("{}{}", self.config.prefix, name);
+
+        let upload_id = match &self.config.auth {
+            S3Auth::PresignedUrl(_) if self.presigned_used => {
+                return Err("预签名地址只能上传一个对象，请先通过 set_presigned_url 设置下一个对象的地址".into());
+            }
+            S3Auth::PresignedUrl(_) => None,
+            S3Auth::Static { .. } => Some(self.create_multipart_upload(&key)?),
+        };
+        info!("开始上传对象 {} ({} 字节)", key, total_size);
+
+        self.active = Some(ActiveUpload {
+            progress: UploadProgress {
+                key,
+                total_size,
+                upload_id,
+                parts: Vec::new(),
+            },
+            written: 0,
+            part_written: 0,
+            request: None,
+        });
+        Ok(())
+    }
+
+    /// 从保存的进度继续上传，调用方需从 `committed_bytes` 处继续写入数据
+    pub fn resume_object(&mut self, progress: UploadProgress) -> Result<(), Box<dyn Error>> {
+        if progress.upload_id.is_none() {
+            return Err("整对象上传不支持续传".into());
+        }
+        let written = progress.committed_bytes(self.config.part_size);
+        info!("继续上传对象 {}，已完成 {} 字节", progress.key, written);
+        self.active = Some(ActiveUpload {
+            progress,
+            written,
+            part_written: 0,
+            request: None,
+        });
+        Ok(())
+    }
+
+    /// 当前上传进度
+    pub fn progress(&self) -> Option<&UploadProgress> {
+        self.active.as_ref().map(|a| &a.progress)
+    }
+
+    /// 当前对象已写入的字节数，续传时从这里继续读取对象
+    pub fn written(&self) -> u64 {
+        self.active.as_ref().map_or(0, |a| a.written)
+    }
+
+    /// 完成当前对象的上传
+    pub fn finish_object(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut active = self.active.take().ok_or("没有正在上传的对象")?;
+        if active.written != active.progress.total_size {
+            let msg = format!(
+                "对象 {} 数据不完整: {}/{}",
+                active.progress.key, active.written, active.progress.total_size
+            );
+            self.active = Some(active);
+            return Err(msg.into());
+        }
+
+        if let Some(request) = active.request.take() {
+            let etag = Self::submit(request)?;
+            let part_number = active.progress.parts.len() as u32 + 1;
+            active.progress.parts.push((part_number, etag));
+        }
+
+        match active.progress.upload_id.clone() {
+            Some(upload_id) => self.complete_multipart_upload(&active.progress.key, &upload_id, &active.progress.parts)?,
+            None => self.presigned_used = true,
+        }
+        info!("对象 {} 上传完成", active.progress.key);
+        Ok(())
+    }
+
+    /// 写入对象数据，必要时切换到下一个分片
+    pub fn write_object_data(&mut self, mut data: &[u8]) -> Result<usize, Box<dyn Error>> {
+        let total = data.len();
+        while !data.is_empty() {
+            let part_size = self.config.part_size;
+            let (remaining_in_part, left, need_request) = {
+                let active = self.active.as_ref().ok_or("请先调用 begin_object")?;
+                let left = active.progress.total_size - active.written;
+                if left == 0 {
+                    return Err("写入的数据超过对象大小".into());
+                }
+                let limit = match active.progress.upload_id {
+                    Some(_) => part_size,
+                    None => active.progress.total_size as usize,
+                };
+                (limit - active.part_written, left, active.request.is_none())
+            };
+
+            if need_request {
+                self.open_part_request()?;
+            }
+
+            // 不超过对象剩余的字节数，多出的数据在下一轮返回错误，不会写进请求体
+            let n = remaining_in_part.min(data.len()).min(left as usize);
+            let active = self.active.as_mut().unwrap();
+            active.request.as_mut().unwrap().write_all(&data[..n])?;
+            active.part_written += n;
+            active.written += n as u64;
+            data = &data[n..];
+
+            // 分片写满，提交并记录ETag
+            if active.progress.upload_id.is_some() && active.part_written == part_size {
+                let etag = Self::submit(active.request.take().unwrap())?;
+                let part_number = active.progress.parts.len() as u32 + 1;
+                debug!("分片 {} 上传完成", part_number);
+                active.progress.parts.push((part_number, etag));
+                active.part_written = 0;
+            }
+        }
+        Ok(total)
+    }
+
+    /// 为当前分片打开PUT请求
+    fn open_part_request(&mut self) -> Result<(), Box<dyn Error>> {
+        let part_size = self.config.part_size as u64;
+        let active = self.active.as_ref().unwrap();
+        let left = active.progress.total_size - active.written;
+
+        let (url, query, length) = match &active.progress.upload_id {
+            Some(upload_id) => {
+                let part_number = active.progress.parts.len() + 1;
+                let query = format!(
+                    "partNumber={}&uploadId={}",
+                    part_number,
+                    uri_encode(upload_id, true)
+                );
+                (self.object_url(&active.progress.key), query, left.min(part_size))
+            }
+            None => (self.object_url(&active.progress.key), String::new(), left),
+        };
+
+        let request = self.open_request(Method::Put, &url, &query, Some(length))?;
+        self.active.as_mut().unwrap().request = Some(request);
+        Ok(())
+    }
+
+    /// 发起分片上传，返回UploadId
+    fn create_multipart_upload(&self, key: &str) -> Result<String, Box<dyn Error>> {
+        let url = self.object_url(key);
+        let conn = self.open_request(Method::Post, &url, "uploads=", Some(0))?;
+        let body = Self::finish_request(conn)?;
+        extract_xml_tag(&body, "UploadId").ok_or_else(|| "响应中缺少UploadId".into())
+    }
+
+    /// 完成分片上传
+    fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: &[(u32, String)],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (number, etag) in parts {
+            body.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                number, etag
+            ));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+
+        let url = self.object_url(key);
+        let query = format!("uploadId={}", uri_encode(upload_id, true));
+        let mut conn = self.open_request(Method::Post, &url, &query, Some(body.len() as u64))?;
+        conn.write_all(body.as_bytes())?;
+        Self::finish_request(conn)?;
+        Ok(())
+    }
+
+    /// 打开请求并写入请求头（静态密钥时附带SigV4签名）
+    fn open_request(
+        &self,
+        method: Method,
+        url: &str,
+        query: &str,
+        content_length: Option<u64>,
+    ) -> Result<EspHttpConnection, Box<dyn Error>> {
+        let mut conn = EspHttpConnection::new(&self.tls.http_configuration())?;
+
+        let length = content_length.map(|l| l.to_string());
+        let mut headers: Vec<(String, String)> = Vec::new();
+        if let Some(length) = &length {
+            headers.push(("Content-Length".into(), length.clone()));
+        }
+
+        let full_url = match &self.config.auth {
+            S3Auth::PresignedUrl(presigned) => presigned.clone(),
+            S3Auth::Static {
+                access_key,
+                secret_key,
+                region,
+            } => {
+                let host = host_of(&self.config.endpoint);
+                let path = &url[self.config.endpoint.trim_end_matches('/').len()..];
+                let amz_date = amz_timestamp(SystemTime::now());
+                let authorization = sign_v4(
+                    method_name(method),
+                    host,
+                    path,
+                    query,
+                    &amz_date,
+                    access_key,
+                    secret_key,
+                    region,
+                );
+                headers.push(("Host".into(), host.to_string()));
+                headers.push(("x-amz-content-sha256".into(), "UNSIGNED-PAYLOAD".into()));
+                headers.push(("x-amz-date".into(), amz_date));
+                headers.push(("Authorization".into(), authorization));
+                if query.is_empty() {
+                    url.to_string()
+                } else {
+                    format!("{}?{}", url, query)
+                }
+            }
+        };
+
+        let header_refs: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        conn.initiate_request(method, &full_url, &header_refs)?;
+        Ok(conn)
+    }
+
+    /// 提交分片请求并返回ETag
+    fn submit(mut conn: EspHttpConnection) -> Result<String, Box<dyn Error>> {
+        conn.initiate_response()?;
+        let status = conn.status();
+        if !(200..300).contains(&status) {
+            return Err(format!("上传分片失败，HTTP状态码 {}", status).into());
+        }
+        Ok(conn.header("ETag").unwrap_or_default().to_string())
+    }
+
+    /// 结束请求并读取响应体
+    fn finish_request(mut conn: EspHttpConnection) -> Result<String, Box<dyn Error>> {
+        conn.initiate_response()?;
+        let status = conn.status();
+
+        let mut body = Vec::new();
+        let mut buf = [0u8; 512];
+        loop {
+            let n = conn.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+        let body = String::from_utf8_lossy(&body).into_owned();
+
+        if !(200..300).contains(&status) {
+            warn!("S3请求失败: {} {}", status, body);
+            return Err(format!("S3请求失败，HTTP状态码 {}", status).into());
+        }
+        Ok(body)
+    }
+
+    /// 对象的路径风格URL
+    fn object_url(&self, key: &str) -> String {
+        let encoded_key = key
+            .split('/')
+            .map(|segment| uri_encode(segment, true))
+            .collect::<Vec<_>>()
+            .join("/");
+        format!(
+            "{}/{}/{}",
+            self.config.endpoint.trim_end_matches('/'),
+            self.config.bucket,
+            encoded_key
+        )
+    }
+}
+
+impl DataSender for S3Sender {
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move { self.write_object_data(&packet.data) })
+    }
+
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            if let Some(active) = self.active.take() {
+                warn!(
+                    "对象 {} 未上传完成，已上传 {}/{} 字节",
+                    active.progress.key, active.written, active.progress.total_size
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+fn method_name(method: Method) -> &'static str {
+    match method {
+        Method::Put => "PUT",
+        Method::Post => "POST",
+        Method::Delete => "DELETE",
+        _ => "GET",
+    }
+}
+
+/// 从URL中取出主机名
+fn host_of(endpoint: &str) -> &str {
+    let without_scheme = endpoint.split("://").nth(1).unwrap_or(endpoint);
+    without_scheme.split('/').next().unwrap_or(without_scheme)
+}
+
+/// 按SigV4规则进行URI编码
+pub(crate) fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if !encode_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// 取出XML中第一个指定标签的内容
+fn extract_xml_tag(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&close)? + start;
+    Some(xml[start..end].to_string())
+}
+
+/// 生成 `YYYYMMDDTHHMMSSZ` 格式的UTC时间
+fn amz_timestamp(now: SystemTime) -> String {
+    let secs = now.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // 由天数计算公历日期
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC可以接受任意长度的密钥");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 计算AWS SigV4的Authorization请求头（载荷不参与签名）
+#[allow(clippy::too_many_arguments)]
+fn sign_v4(
+    method: &str,
+    host: &str,
+    path: &str,
+    query: &str,
+    amz_date: &str,
+    access_key: &str,
+    secret_key: &str,
+    region: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:UNSIGNED-PAYLOAD\nx-amz-date:{}\n\n{}\nUNSIGNED-PAYLOAD",
+        method, path, query, host, amz_date, signed_headers
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let k_date = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let k_region = hmac_sha256(&k_date, region.as_bytes());
+    let k_service = hmac_sha256(&k_region, b"s3");
+    let k_signing = hmac_sha256(&k_service, b"aws4_request");
+    let signature = hex(&hmac_sha256(&k_signing, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, signed_headers, signature
+    )
+}
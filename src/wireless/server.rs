@@ -0,0 +1,360 @@
+// 多客户端TCP服务端 - 手机主动连接设备，多个手机可以同时接收，每个客户端只收到自己订阅的数据
+//
+// 客户端连接后先发送一行文本提交配对得到的预共享令牌，令牌无效时断开连接：
+//   login 预共享令牌
+// 登录前不接收任何数据，登录后只接收事件。之后发送一行文本声明订阅，可随时再发送一行替换订阅：
+//   subscribe live_view images thumbnails events
+// 设备发出的每条记录带5字节头(小端)：类型u8 + 长度u32，之后是数据包内容。
+// 每个客户端有独立的发送队列，慢的客户端不会拖住其他客户端：队列满时丢弃取景画面，
+// 其他数据放不下时断开该客户端，由它重新连接后通过增量同步补齐。连接为明文，不支持TLS
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, info, warn};
+
+use super::{DataSender, SenderFuture};
+use crate::config::TcpServerConfig;
+use crate::control::{AuthGuard, ControlChannel, ControlCommand};
+use crate::ptp_mtp::{DataPacket, PacketType};
+
+/// 在TransferManager中登记的客户端ID
+pub const SERVER_CLIENT_ID: &str = "tcp-server";
+/// 记录头长度
+pub const RECORD_HEADER_LEN: usize = 5;
+/// 订阅命令的最大长度，超过时视为异常客户端
+const MAX_COMMAND_LEN: usize = 256;
+
+/// 客户端订阅的数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subscriptions {
+    pub live_view: bool,  // 实时取景画面
+    pub images: bool,     // 原图
+    pub thumbnails: bool, // 缩略图
+    pub events: bool,     // 状态事件和元数据
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Subscriptions {
+            live_view: false,
+            images: false,
+            thumbnails: false,
+            events: true,
+        }
+    }
+}
+
+impl Subscriptions {
+    /// 不接收任何数据，客户端登录前使用
+    pub fn none() -> Self {
+        Subscriptions {
+            events: false,
+            ..Subscriptions::default()
+        }
+    }
+
+    /// 解析`subscribe`命令中的订阅列表
+    pub fn parse<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut subscriptions = Subscriptions::none();
+        for name in names {
+            match name {
+                "live_view" => subscriptions.live_view = true,
+                "images" => subscriptions.images = true,
+                "thumbnails" => subscriptions.thumbnails = true,
+                "events" => subscriptions.events = true,
+                other => return Err(format!("未知的订阅: {}", other)),
+            }
+        }
+        Ok(subscriptions)
+    }
+
+    /// 是否接收此类型的数据包；命令和响应总是发送
+    pub fn accepts(self, packet_type: PacketType) -> bool {
+        match packet_type {
+            PacketType::LiveView => self.live_view,
+            PacketType::Image => self.images,
+            PacketType::Thumbnail => self.thumbnails,
+            PacketType::Metadata => self.events,
+            PacketType::Command | PacketType::Response => true,
+        }
+    }
+}
+
+/// 一个已连接的客户端
+struct ServerClient {
+    peer: SocketAddr,
+    stream: TcpStream,
+    session: Option<String>,    // 登录得到的会话令牌，None表示尚未登录
+    subscriptions: Subscriptions,
+    inbox: Vec<u8>,             // 尚未读到换行的命令
+    queue: VecDeque<Vec<u8>>,   // 待发送的记录
+    queued_bytes: usize,
+    written: usize,             // 队首记录已写出的字节数
+    dropped_frames: u32,        // 因队列满丢弃的取景画面
+}
+
+impl ServerClient {
+    /// 读取客户端发来的登录和订阅命令，连接已关闭、命令异常或登录失败时返回错误
+    fn read_commands(&mut self, guard: &Mutex<AuthGuard>) -> Result<(), Box<dyn Error>> {
+        let mut buf = [0u8; 128];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return Err("客户端关闭了连接".into()),
+                Ok(n) => self.inbox.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        while let Some(end) = self.inbox.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.inbox.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("login") => {
+                    let session = words.next().and_then(|credential| guard.lock().unwrap().login(credential));
+                    self.logout(guard);
+                    let Some(session) = session else {
+                        return Err("预共享令牌无效".into());
+                    };
+                    info!("客户端 {} 已登录", self.peer);
+                    self.session = Some(session);
+                    self.subscriptions = Subscriptions::default();
+                }
+                Some("subscribe") => {
+                    // 订阅只需要读取权限，会话过期后需要重新登录
+                    let token = self.session.as_deref().unwrap_or("");
+                    if let Err(e) = guard.lock().unwrap().authorize(ControlChannel::Tcp, token, &ControlCommand::GetStatus) {
+                        warn!("客户端 {} 订阅被拒绝: {}", self.peer, e);
+                        continue;
+                    }
+                    match Subscriptions::parse(words) {
+                        Ok(subscriptions) => {
+                            info!("客户端 {} 订阅: {:?}", self.peer, subscriptions);
+                            self.subscriptions = subscriptions;
+                        }
+                        Err(e) => warn!("客户端 {} {}", self.peer, e),
+                    }
+                }
+                Some(other) => warn!("客户端 {} 发来未知命令: {}", self.peer, other),
+                None => {}
+            }
+        }
+        if self.inbox.len() > MAX_COMMAND_LEN {
+            return Err("命令过长".into());
+        }
+        Ok(())
+    }
+
+    /// 注销会话令牌，重新登录和断开连接时调用
+    fn logout(&mut self, guard: &Mutex<AuthGuard>) {
+        if let Some(session) = self.session.take() {
+            guard.lock().unwrap().logout(&session);
+        }
+    }
+
+    /// 把记录放入发送队列，放不下时按数据类型决定丢弃还是断开
+    fn enqueue(&mut self, packet: &DataPacket, limit: usize) -> Result<usize, Box<dyn Error>> {
+        let len = RECORD_HEADER_LEN + packet.data.len();
+        if self.queued_bytes + len > limit {
+            if packet.packet_type == PacketType::LiveView {
+                self.dropped_frames += 1;
+                debug!("客户端 {} 发送队列已满，丢弃取景画面(累计 {})", self.peer, self.dropped_frames);
+                return Ok(0);
+            }
+            return Err(format!("发送队列已满 ({} 字节未发出)", self.queued_bytes).into());
+        }
+        let mut record = Vec::with_capacity(len);
+        record.push(packet.packet_type.code());
+        record.extend_from_slice(&(packet.data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&packet.data);
+        self.queue.push_back(record);
+        self.queued_bytes += len;
+        Ok(len)
+    }
+
+    /// 尽量写出队列中的记录，套接字缓冲区满时留到下次
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        while let Some(record) = self.queue.front() {
+            match self.stream.write(&record[self.written..]) {
+                Ok(0) => return Err("连接已关闭".into()),
+                Ok(n) => {
+                    self.written += n;
+                    if self.written == record.len() {
+                        self.queued_bytes -= record.len();
+                        self.written = 0;
+                        self.queue.pop_front();
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 多客户端TCP服务端
+pub struct TcpServer {
+    listener: TcpListener,
+    guard: Arc<Mutex<AuthGuard>>,
+    max_clients: usize,
+    queue_bytes: usize,
+    clients: Vec<ServerClient>,
+}
+
+/// 主循环和发送器共享的服务端
+pub type TcpServerHandle = Arc<Mutex<TcpServer>>;
+
+/// 创建共享的服务端
+pub fn handle(server: TcpServer) -> TcpServerHandle {
+    Arc::new(Mutex::new(server))
+}
+
+impl TcpServer {
+    /// 在所有接口上监听配置的端口，客户端用配对得到的预共享令牌经`guard`登录
+    pub fn bind(config: &TcpServerConfig, guard: Arc<Mutex<AuthGuard>>) -> Result<Self, Box<dyn Error>> {
+        config.validate()?;
+        let listener = TcpListener::bind(("0.0.0.0", config.port))?;
+        listener.set_nonblocking(true)?;
+        info!("TCP服务端监听端口 {}，最多 {} 个客户端", config.port, config.max_clients);
+        Ok(TcpServer {
+            listener,
+            guard,
+            max_clients: config.max_clients,
+            queue_bytes: config.queue_bytes,
+            clients: Vec::new(),
+        })
+    }
+
+    /// 当前连接的客户端数
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// 接受新连接、读取订阅变化并继续发送各客户端队列中的数据；由主循环定期调用
+    pub fn poll(&mut self) {
+        self.accept();
+        let guard = &self.guard;
+        self.clients.retain_mut(|client| match client.read_commands(guard).and_then(|()| client.flush()) {
+            Ok(()) => true,
+            Err(e) => {
+                info!("客户端 {} 已断开: {}", client.peer, e);
+                client.logout(guard);
+                false
+            }
+        });
+    }
+
+    /// 把数据包放入订阅了该类型的客户端的发送队列，返回放入队列的字节数
+    pub fn broadcast(&mut self, packet: &DataPacket) -> usize {
+        let limit = self.queue_bytes;
+        let guard = &self.guard;
+        let mut queued = 0;
+        self.clients.retain_mut(|client| {
+            // 未登录的客户端连命令和响应也不接收
+            if client.session.is_none() || !client.subscriptions.accepts(packet.packet_type) {
+                return true;
+            }
+            match client.enqueue(packet, limit) {
+                Ok(n) => {
+                    queued += n;
+                    true
+                }
+                Err(e) => {
+                    warn!("客户端 {} 跟不上发送，断开连接: {}", client.peer, e);
+                    let _ = client.stream.shutdown(Shutdown::Both);
+                    client.logout(guard);
+                    false
+                }
+            }
+        });
+        queued
+    }
+
+    /// 断开所有客户端，监听继续
+    pub fn disconnect_all(&mut self) {
+        for mut client in self.clients.drain(..) {
+            if !client.queue.is_empty() {
+                warn!("断开客户端 {} 时还有 {} 字节未发出", client.peer, client.queued_bytes);
+            }
+            let _ = client.stream.shutdown(Shutdown::Both);
+            client.logout(&self.guard);
+        }
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let (stream, peer) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("接受TCP连接失败: {}", e);
+                    return;
+                }
+            };
+            if self.clients.len() >= self.max_clients {
+                warn!("客户端已达上限 {}，拒绝 {}", self.max_clients, peer);
+                let _ = stream.shutdown(Shutdown::Both);
+                continue;
+            }
+            if let Err(e) = stream.set_nonblocking(true).and_then(|()| stream.set_nodelay(true)) {
+                warn!("设置客户端 {} 的连接失败: {}", peer, e);
+                continue;
+            }
+            info!("客户端 {} 已连接 ({}/{})", peer, self.clients.len() + 1, self.max_clients);
+            self.clients.push(ServerClient {
+                peer,
+                stream,
+                session: None,
+                subscriptions: Subscriptions::none(),
+                inbox: Vec::new(),
+                queue: VecDeque::new(),
+                queued_bytes: 0,
+                written: 0,
+                dropped_frames: 0,
+            });
+        }
+    }
+}
+
+/// 在TransferManager中代表服务端全部客户端的发送器，数据包按各客户端的订阅分发
+pub struct ServerSender {
+    server: TcpServerHandle,
+}
+
+impl ServerSender {
+    pub fn new(server: TcpServerHandle) -> Self {
+        ServerSender { server }
+    }
+}
+
+// 没有客户端时数据包直接丢弃，监听一直在进行，不需要连接
+impl DataSender for ServerSender {
+    fn ready(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            self.server.lock().unwrap().poll();
+            Ok(())
+        })
+    }
+
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move {
+            let mut server = self.server.lock().unwrap();
+            let queued = server.broadcast(packet);
+            server.poll();
+            Ok(queued)
+        })
+    }
+
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            self.server.lock().unwrap().disconnect_all();
+            Ok(())
+        })
+    }
+}
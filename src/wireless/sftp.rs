@@ -0,0 +1,214 @@
+// SFTP/SCP上传发送器 - 将对象投递到工作室的SFTP接收目录
+// SSH协议本身由 `SshTransport` 的实现提供（例如基于libssh2组件），固件不自带实现，因此整个模块由 `sftp` feature 控制；
+// 本模块负责连接流程、主机密钥固定和对象写入
+use std::error::Error;
+
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use super::{DataSender, SenderFuture, SenderState};
+use crate::ptp_mtp::DataPacket;
+use crate::config::{SftpAuth, SftpConfig};
+use crate::persist::KvStore;
+
+/// SSH传输层特性
+pub trait SshTransport: Send {
+    /// 建立TCP连接并完成密钥交换，返回服务器主机公钥
+    fn connect(&mut self, host: &str, port: u16) -> Result<Vec<u8>, Box<dyn Error>>;
+
+    /// 用户认证
+    fn authenticate(&mut self, username: &str, auth: &SftpAuth) -> Result<(), Box<dyn Error>>;
+
+    /// 打开远程文件用于写入
+    fn open_write(&mut self, remote_path: &str, size: u64) -> Result<(), Box<dyn Error>>;
+
+    /// 写入已打开的远程文件
+    fn write(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>>;
+
+    /// 关闭远程文件
+    fn close_file(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// 重命名远程文件
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), Box<dyn Error>>;
+
+    /// 断开连接
+    fn disconnect(&mut self);
+}
+
+/// 已固定的主机密钥，保存在NVS中
+pub struct KnownHosts {
+    store: Box<dyn KvStore>,
+}
+
+impl KnownHosts {
+    /// 使用持久化存储创建
+    pub fn new(store: Box<dyn KvStore>) -> Self {
+        KnownHosts { store }
+    }
+
+    /// 主机密钥指纹(SHA-256)
+    pub fn fingerprint(host_key: &[u8]) -> [u8; 32] {
+        Sha256::digest(host_key).into()
+    }
+
+    /// 校验主机密钥；未记录过的主机在允许首次信任时会被固定
+    pub fn verify(&mut self, host: &str, port: u16, host_key: &[u8], trust_on_first_use: bool) -> Result<(), Box<dyn Error>> {
+        let key = Self::store_key(host, port);
+        let fingerprint = Self::fingerprint(host_key);
+
+        match self.store.get(&key)? {
+            Some(pinned) if pinned == fingerprint => Ok(()),
+            Some(_) => {
+                warn!("主机 {}:{} 的密钥与固定的不一致，拒绝连接", host, port);
+                Err(format!("主机 {}:{} 的密钥已改变", host, port).into())
+            }
+            None if trust_on_first_use => {
+                info!("首次连接 {}:{}，固定主机密钥", host, port);
+                self.store.set(&key, &fingerprint)
+            }
+            None => Err(format!("主机 {}:{} 的密钥未固定", host, port).into()),
+        }
+    }
+
+    /// 清除主机的固定密钥（服务器换密钥后手动调用）
+    pub fn forget(&mut self, host: &str, port: u16) -> Result<(), Box<dyn Error>> {
+        self.store.remove(&Self::store_key(host, port))
+    }
+
+    // NVS键名最长15字节，使用主机名哈希
+    fn store_key(host: &str, port: u16) -> String {
+        let digest = Sha256::digest(format!("{}:{}", host, port).as_bytes());
+        format!(
+            "hk_{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            digest[0], digest[1], digest[2], digest[3], digest[4], digest[5]
+        )
+    }
+}
+
+/// SFTP发送器
+pub struct SftpSender {
+    config: SftpConfig,
+    transport: Box<dyn SshTransport>,
+    known_hosts: KnownHosts,
+    connected: bool,
+    current: Option<(String, String)>, // (临时路径, 最终路径)
+}
+
+impl SftpSender {
+    /// 创建SFTP发送器
+    pub fn new(config: SftpConfig, transport: Box<dyn SshTransport>, known_hosts: KnownHosts) -> Self {
+        SftpSender {
+            config,
+            transport,
+            known_hosts,
+            connected: false,
+            current: None,
+        }
+    }
+
+    /// 连接服务器，校验主机密钥并认证
+    fn open_session(&mut self) -> Result<(), Box<dyn Error>> {
+        let host_key = self.transport.connect(&self.config.host, self.config.port)?;
+        if let Err(e) = self.known_hosts.verify(
+            &self.config.host,
+            self.config.port,
+            &host_key,
+            self.config.trust_on_first_use,
+        ) {
+            self.transport.disconnect();
+            return Err(e);
+        }
+        self.transport.authenticate(&self.config.username, &self.config.auth)?;
+        self.connected = true;
+        info!("已连接SFTP服务器 {}:{}", self.config.host, self.config.port);
+        Ok(())
+    }
+
+    /// 开始上传对象，先写入临时文件，完成后再重命名，避免接收端读到半个文件
+    pub fn begin_object(&mut self, name: &str, size: u64) -> Result<(), Box<dyn Error>> {
+        check_object_name(name)?;
+        if !self.connected {
+            self.open_session()?;
+        }
+        let final_path = format!("{}/{}", self.config.remote_dir.trim_end_matches('/'), name);
+        let temp_path = format!("{}.part", final_path);
+        self.transport.open_write(&temp_path, size)?;
+        self.current = Some((temp_path, final_path));
+        Ok(())
+    }
+
+    /// 完成当前对象的上传
+    pub fn finish_object(&mut self) -> Result<(), Box<dyn Error>> {
+        let (temp_path, final_path) = self.current.take().ok_or("没有正在上传的对象")?;
+        self.transport.close_file()?;
+        self.transport.rename(&temp_path, &final_path)?;
+        info!("已上传 {}", final_path);
+        Ok(())
+    }
+}
+
+/// 对象名来自相机上的文件名，只允许接收目录下的单级文件名，不能借助路径分隔符或`..`写到目录之外
+fn check_object_name(name: &str) -> Result<(), Box<dyn Error>> {
+    if name.is_empty() || name == "." || name.contains('/') || name.contains('\\') || name.contains("..") {
+        return Err(format!("对象名无效: {:?}", name).into());
+    }
+    Ok(())
+}
+
+impl DataSender for SftpSender {
+    fn connect(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move { self.open_session() })
+    }
+
+    fn state(&self) -> SenderState {
+        if self.connected {
+            SenderState::Ready
+        } else {
+            SenderState::Disconnected
+        }
+    }
+
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move {
+            if self.current.is_none() {
+                return Err("请先调用 begin_object".into());
+            }
+            match self.transport.write(&packet.data) {
+                Ok(n) => Ok(n),
+                Err(e) => {
+                    // 连接异常时下次重新连接
+                    self.connected = false;
+                    self.current = None;
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async move {
+            if self.current.take().is_some() {
+                let _ = self.transport.close_file();
+            }
+            if self.connected {
+                self.transport.disconnect();
+                self.connected = false;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_name_must_stay_in_remote_dir() {
+        assert!(check_object_name("IMG_0001.CR3").is_ok());
+        assert!(check_object_name("IMG_0001.JPG.xmp").is_ok());
+        for name in ["", ".", "..", "../etc/passwd", "a/b.jpg", "/abs.jpg", "a\\b.jpg", "x..y"] {
+            assert!(check_object_name(name).is_err(), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+// STA连接设置 - 把配置中的认证方式映射为驱动的客户端配置，连接前先校验凭证，
+// WPA2企业级网络还需要在连接前向EAP客户端设置身份、用户名和密码
+use std::error::Error;
+
+use embedded_svc::wifi::{AuthMethod, ClientConfiguration};
+use esp_idf_svc::sys::{
+    esp, esp_eap_client_set_identity, esp_eap_client_set_password, esp_eap_client_set_username,
+    esp_wifi_sta_enterprise_disable, esp_wifi_sta_enterprise_enable,
+};
+use log::debug;
+
+use crate::config::{UpstreamWifi, WifiAuth};
+
+/// 认证方式对应的驱动认证方式，也是接入点要求的最低安全级别
+pub fn auth_method(auth: &WifiAuth) -> AuthMethod {
+    match auth {
+        WifiAuth::Open => AuthMethod::None,
+        WifiAuth::Wpa2Personal => AuthMethod::WPA2Personal,
+        WifiAuth::Wpa3Personal => AuthMethod::WPA3Personal,
+        WifiAuth::Wpa2Wpa3Personal => AuthMethod::WPA2WPA3Personal,
+        WifiAuth::Wpa2Enterprise { .. } => AuthMethod::WPA2Enterprise,
+    }
+}
+
+/// 校验凭证并生成客户端配置；企业级网络的密码不放在客户端配置中，由`configure_enterprise`设置
+pub fn client_configuration(upstream: &UpstreamWifi) -> Result<ClientConfiguration, Box<dyn Error>> {
+    upstream.validate()?;
+    let password = match upstream.auth {
+        WifiAuth::Wpa2Enterprise { .. } => "",
+        _ => upstream.password.as_str(),
+    };
+    Ok(ClientConfiguration {
+        ssid: upstream.ssid.as_str().try_into().map_err(|_| "SSID过长")?,
+        password: password.try_into().map_err(|_| "密码过长")?,
+        auth_method: auth_method(&upstream.auth),
+        ..Default::default()
+    })
+}
+
+/// 企业级网络在连接前设置EAP身份、用户名和密码，其他认证方式关闭企业级认证
+pub fn configure_enterprise(upstream: &UpstreamWifi) -> Result<(), Box<dyn Error>> {
+    let WifiAuth::Wpa2Enterprise { identity, username } = &upstream.auth else {
+        unsafe { esp!(esp_wifi_sta_enterprise_disable())? };
+        return Ok(());
+    };
+    let identity = if identity.is_empty() { username } else { identity };
+    debug!("企业级网络 {} 使用身份 {}", upstream.ssid, identity);
+    // 长度已由validate限制在128字节以内
+    unsafe {
+        esp!(esp_eap_client_set_identity(identity.as_ptr(), identity.len() as i32))?;
+        esp!(esp_eap_client_set_username(username.as_ptr(), username.len() as i32))?;
+        esp!(esp_eap_client_set_password(upstream.password.as_ptr(), upstream.password.len() as i32))?;
+        esp!(esp_wifi_sta_enterprise_enable())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+// TLS连接 - 手机数据链路和云端上传在不可信的网络上加密传输；FTPS等服务端协议在已接受的连接上协商TLS
+//
+// 证书来自配置(TlsSettings)：未指定CA时使用ESP-IDF内置的根证书包(CONFIG_MBEDTLS_CERTIFICATE_BUNDLE)，
+// 指定了CA时只信任该CA；客户端证书和私钥用于需要双向认证的服务端。服务端一侧使用单独配置的证书和私钥。
+// esp-tls要求PEM以NUL结尾且在连接期间有效，因此证书在加载时复制一份并在整个运行期间保留
+use std::error::Error;
+use std::ffi::CStr;
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use esp_idf_svc::http::client::Configuration as HttpConfiguration;
+use esp_idf_svc::sys::{esp, esp_crt_bundle_attach, esp_tls_set_global_ca_store};
+use esp_idf_svc::tls::{self, EspTls, InternalSocket, X509};
+use log::{debug, info};
+
+use crate::config::TlsSettings;
+
+/// 加载后的证书，可在多个连接之间共享
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TlsMaterial {
+    ca: Option<&'static CStr>,
+    client_cert: Option<&'static CStr>,
+    client_key: Option<&'static CStr>,
+}
+
+impl TlsMaterial {
+    /// 校验并加载证书；证书常驻内存，每份配置只应加载一次
+    pub fn load(settings: &TlsSettings) -> Result<Self, Box<dyn Error>> {
+        settings.validate()?;
+        Ok(TlsMaterial {
+            ca: settings.ca_pem.as_deref().map(leak_pem).transpose()?,
+            client_cert: settings.client_cert_pem.as_deref().map(leak_pem).transpose()?,
+            client_key: settings.client_key_pem.as_deref().map(leak_pem).transpose()?,
+        })
+    }
+
+    /// 把自定义CA设为esp-tls的全局CA存储，HTTPS客户端只能通过它使用自定义CA；未指定CA时不做任何事
+    pub fn install_global_ca(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(ca) = self.ca {
+            let pem = ca.to_bytes_with_nul();
+            esp!(unsafe { esp_tls_set_global_ca_store(pem.as_ptr(), pem.len() as u32) })?;
+        }
+        Ok(())
+    }
+
+    /// HTTPS客户端配置，使用自定义CA前需先调用`install_global_ca`
+    pub fn http_configuration(&self) -> HttpConfiguration {
+        HttpConfiguration {
+            use_global_ca_store: self.ca.is_some(),
+            crt_bundle_attach: self.ca.is_none().then_some(esp_crt_bundle_attach as _),
+            client_certificate: self.client_cert.map(|pem| X509::pem_until_nul(pem.to_bytes_with_nul())),
+            private_key: self.client_key.map(|pem| X509::pem_until_nul(pem.to_bytes_with_nul())),
+            ..Default::default()
+        }
+    }
+
+    /// esp-tls连接配置，`timeout`同时作为握手和读取的超时
+    fn tls_config<'a>(&self, host: &'a str, timeout: Duration) -> tls::Config<'a> {
+        tls::Config {
+            common_name: Some(host),
+            ca_cert: self.ca.map(|pem| X509::pem_until_nul(pem.to_bytes_with_nul())),
+            use_crt_bundle_attach: self.ca.is_none(),
+            client_cert: self.client_cert.map(|pem| X509::pem_until_nul(pem.to_bytes_with_nul())),
+            client_key: self.client_key.map(|pem| X509::pem_until_nul(pem.to_bytes_with_nul())),
+            timeout_ms: timeout.as_millis() as u32,
+            ..Default::default()
+        }
+    }
+}
+
+/// 复制PEM并补上结尾的NUL，返回在整个运行期间有效的引用
+fn leak_pem(pem: &str) -> Result<&'static CStr, Box<dyn Error>> {
+    let mut bytes = pem.trim().as_bytes().to_vec();
+    bytes.push(0);
+    let pem: &'static [u8] = Box::leak(bytes.into_boxed_slice());
+    Ok(CStr::from_bytes_with_nul(pem).map_err(|_| "证书中包含NUL字符")?)
+}
+
+/// 基于esp-tls的加密连接
+pub struct TlsStream {
+    tls: EspTls<InternalSocket>,
+}
+
+impl TlsStream {
+    /// 连接`host:port`并完成握手，服务端证书的主机名需与`host`一致
+    pub fn connect(host: &str, port: u16, material: &TlsMaterial, timeout: Duration) -> Result<Self, Box<dyn Error>> {
+        let mut tls = EspTls::new()?;
+        tls.connect(host, port, &material.tls_config(host, timeout))?;
+        info!("已与 {}:{} 建立TLS连接", host, port);
+        Ok(TlsStream { tls })
+    }
+}
+
+impl io::Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.tls.read(buf).map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+impl io::Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tls.write(buf).map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 服务端证书和私钥，可在多个连接之间共享
+#[derive(Debug, Clone, Copy)]
+pub struct ServerIdentity {
+    cert: &'static CStr,
+    key: &'static CStr,
+}
+
+impl ServerIdentity {
+    /// 加载证书和私钥；证书常驻内存，每份配置只应加载一次
+    pub fn load(cert_pem: &str, key_pem: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(ServerIdentity {
+            cert: leak_pem(cert_pem)?,
+            key: leak_pem(key_pem)?,
+        })
+    }
+}
+
+/// 服务端一侧的加密连接
+pub struct TlsServerStream {
+    tls: EspTls<TcpStream>,
+}
+
+impl TlsServerStream {
+    /// 在已接受的连接上完成服务端握手
+    pub fn accept(stream: TcpStream, identity: &ServerIdentity) -> Result<Self, Box<dyn Error>> {
+        let mut tls = EspTls::adopt(stream)?;
+        tls.negotiate_server(&tls::ServerConfig {
+            server_cert: Some(X509::pem_until_nul(identity.cert.to_bytes_with_nul())),
+            server_key: Some(X509::pem_until_nul(identity.key.to_bytes_with_nul())),
+            ..Default::default()
+        })?;
+        Ok(TlsServerStream { tls })
+    }
+}
+
+impl io::Read for TlsServerStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.tls.read(buf).map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+impl io::Write for TlsServerStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tls.write(buf).map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 发送器使用的连接，明文或TLS
+pub enum LinkStream {
+    Plain(TcpStream),
+    Tls(TlsStream),
+}
+
+impl LinkStream {
+    /// 连接`address`(主机:端口)，提供了证书时使用TLS
+    pub fn connect(address: &str, tls: Option<&TlsMaterial>, timeout: Duration) -> Result<Self, Box<dyn Error>> {
+        let Some(material) = tls else {
+            let stream = TcpStream::connect(address)?;
+            // 等待对端确认的超时，超时视为连接已失效
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_nodelay(true)?;
+            return Ok(LinkStream::Plain(stream));
+        };
+        let (host, port) = address.rsplit_once(':').ok_or("地址缺少端口")?;
+        let port = port.parse().map_err(|_| format!("无效的端口: {}", port))?;
+        debug!("以TLS连接 {}", address);
+        Ok(LinkStream::Tls(TlsStream::connect(host.trim_matches(['[', ']']), port, material, timeout)?))
+    }
+
+    /// 是否为加密连接
+    pub fn is_tls(&self) -> bool {
+        matches!(self, LinkStream::Tls(_))
+    }
+}
+
+impl io::Read for LinkStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            LinkStream::Plain(stream) => stream.read(buf),
+            LinkStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl io::Write for LinkStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            LinkStream::Plain(stream) => stream.write(buf),
+            LinkStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LinkStream::Plain(stream) => stream.flush(),
+            LinkStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
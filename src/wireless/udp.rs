@@ -0,0 +1,333 @@
+// UDP取景推送 - 取景画面偶尔丢一帧不影响观看，用UDP发送避免TCP重传造成的卡顿和延迟累积
+// 两种封装：
+//   原始分片：每个数据报带8字节头(帧号u32 + 分片序号u16 + 分片总数u16，小端)，接收端凑齐一帧后显示，缺片的帧整帧丢弃
+//   RTP/MJPEG(RFC 2435)：标准播放器(如VLC、ffplay配合SDP)可以直接播放；只支持基线JPEG、4:2:2或4:2:0采样、无重启间隔
+use std::error::Error;
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use log::{debug, info};
+
+use super::{DataSender, SenderFuture};
+use crate::ptp_mtp::DataPacket;
+
+/// 单个数据报的最大长度，留出IP/UDP头后不超过以太网MTU
+pub const MAX_DATAGRAM: usize = 1400;
+/// 原始分片头长度
+pub const FRAGMENT_HEADER_LEN: usize = 8;
+/// RTP头长度
+const RTP_HEADER_LEN: usize = 12;
+/// RFC 2435 JPEG头长度
+const JPEG_HEADER_LEN: usize = 8;
+/// JPEG的RTP负载类型
+const RTP_PAYLOAD_JPEG: u8 = 26;
+/// RTP视频时钟频率
+const RTP_CLOCK_HZ: u64 = 90_000;
+/// 使用动态量化表(表放在第一个分片中)
+const DYNAMIC_Q: u8 = 255;
+
+/// 按原始分片封装一帧
+pub fn fragment_frame(frame_id: u32, frame: &[u8]) -> Vec<Vec<u8>> {
+    let chunk = MAX_DATAGRAM - FRAGMENT_HEADER_LEN;
+    let count = frame.len().div_ceil(chunk).max(1);
+    (0..count)
+        .map(|index| {
+            let part = &frame[(index * chunk).min(frame.len())..((index + 1) * chunk).min(frame.len())];
+            let mut datagram = Vec::with_capacity(FRAGMENT_HEADER_LEN + part.len());
+            datagram.extend_from_slice(&frame_id.to_le_bytes());
+            datagram.extend_from_slice(&(index as u16).to_le_bytes());
+            datagram.extend_from_slice(&(count as u16).to_le_bytes());
+            datagram.extend_from_slice(part);
+            datagram
+        })
+        .collect()
+}
+
+/// RFC 2435需要的JPEG信息
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JpegScan<'a> {
+    pub kind: u8,          // 0: 4:2:2，1: 4:2:0
+    pub width: u16,
+    pub height: u16,
+    pub tables: Vec<&'a [u8]>, // 8位量化表(zigzag顺序)，按表号排列
+    pub scan: &'a [u8],        // 熵编码数据，不含EOI
+}
+
+/// 解析JPEG，取出尺寸、采样方式、量化表和扫描数据；不支持的格式返回None
+pub fn parse_jpeg(data: &[u8]) -> Option<JpegScan<'_>> {
+    if data.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut tables: [Option<&[u8]>; 4] = [None; 4];
+    let mut size = None;
+    let mut kind = None;
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        if marker == 0xFF {
+            pos += 1; // 填充字节
+            continue;
+        }
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let segment = data.get(pos + 4..pos + 2 + len)?;
+        match marker {
+            // DQT：可能包含多张表，只支持8位精度
+            0xDB => {
+                let mut rest = segment;
+                while !rest.is_empty() {
+                    let (pq, tq) = (rest[0] >> 4, (rest[0] & 0x0F) as usize);
+                    if pq != 0 || tq > 3 {
+                        return None;
+                    }
+                    tables[tq] = Some(rest.get(1..65)?);
+                    rest = &rest[65..];
+                }
+            }
+            // SOF0基线
+            0xC0 => {
+                let height = u16::from_be_bytes([*segment.get(1)?, *segment.get(2)?]);
+                let width = u16::from_be_bytes([*segment.get(3)?, *segment.get(4)?]);
+                kind = match (*segment.get(5)?, *segment.get(7)?) {
+                    (3, 0x21) => Some(0),
+                    (3, 0x22) => Some(1),
+                    _ => return None,
+                };
+                size = Some((width, height));
+            }
+            // 其他SOF(渐进式等)不支持
+            0xC1..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return None,
+            // 有重启间隔时需要RFC 2435的重启头，不支持
+            0xDD if segment.get(..2)? != [0, 0] => return None,
+            0xDA => {
+                let start = pos + 2 + len;
+                let end = if data.ends_with(&[0xFF, 0xD9]) { data.len() - 2 } else { data.len() };
+                let (width, height) = size?;
+                // 尺寸以8像素为单位编码在一个字节中
+                if width == 0 || height == 0 || width > 2040 || height > 2040 {
+                    return None;
+                }
+                return Some(JpegScan {
+                    kind: kind?,
+                    width,
+                    height,
+                    tables: tables.iter().map_while(|t| *t).collect(),
+                    scan: data.get(start..end)?,
+                });
+            }
+            _ => {}
+        }
+        pos += 2 + len;
+    }
+}
+
+/// RTP/MJPEG封装状态
+#[derive(Debug)]
+pub struct RtpPacketizer {
+    seq: u16,
+    ssrc: u32,
+    started: Instant,
+}
+
+impl RtpPacketizer {
+    pub fn new(ssrc: u32) -> Self {
+        RtpPacketizer {
+            seq: 0,
+            ssrc,
+            started: Instant::now(),
+        }
+    }
+
+    /// 把一帧JPEG封装为RTP包，格式不受支持时返回None
+    pub fn packetize(&mut self, jpeg: &[u8]) -> Option<Vec<Vec<u8>>> {
+        let scan = parse_jpeg(jpeg)?;
+        let timestamp = (self.started.elapsed().as_micros() as u64 * RTP_CLOCK_HZ / 1_000_000) as u32;
+        let table_len: usize = scan.tables.iter().map(|t| t.len()).sum();
+        let mut packets = Vec::new();
+        let mut offset = 0usize;
+        while offset < scan.scan.len() || packets.is_empty() {
+            let first = offset == 0;
+            let header_len = RTP_HEADER_LEN + JPEG_HEADER_LEN + if first { 4 + table_len } else { 0 };
+            let room = MAX_DATAGRAM.checked_sub(header_len).filter(|r| *r > 0)?;
+            let end = (offset + room).min(scan.scan.len());
+            let last = end == scan.scan.len();
+
+            let mut packet = Vec::with_capacity(header_len + end - offset);
+            // RTP头：版本2，最后一个分片置marker位
+            packet.push(0x80);
+            packet.push(RTP_PAYLOAD_JPEG | if last { 0x80 } else { 0 });
+            packet.extend_from_slice(&self.seq.to_be_bytes());
+            packet.extend_from_slice(&timestamp.to_be_bytes());
+            packet.extend_from_slice(&self.ssrc.to_be_bytes());
+            // JPEG头：类型相关字段0，24位分片偏移，类型，Q，宽高/8
+            packet.push(0);
+            packet.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+            packet.push(scan.kind);
+            packet.push(DYNAMIC_Q);
+            packet.push(scan.width.div_ceil(8) as u8);
+            packet.push(scan.height.div_ceil(8) as u8);
+            // 量化表头只在第一个分片中
+            if first {
+                packet.push(0); // MBZ
+                packet.push(0); // 精度：全部8位
+                packet.extend_from_slice(&(table_len as u16).to_be_bytes());
+                for table in &scan.tables {
+                    packet.extend_from_slice(table);
+                }
+            }
+            packet.extend_from_slice(&scan.scan[offset..end]);
+            packets.push(packet);
+            self.seq = self.seq.wrapping_add(1);
+            offset = end;
+        }
+        Some(packets)
+    }
+}
+
+/// UDP取景发送器
+pub struct UdpSender {
+    socket: UdpSocket,
+    rtp: Option<RtpPacketizer>,
+    frame_id: u32,
+}
+
+impl UdpSender {
+    /// 向`target`(地址:端口)发送，`rtp`为true时使用RTP/MJPEG封装
+    pub fn connect(target: &str, rtp: bool) -> Result<Self, Box<dyn Error>> {
+        let addr = target.to_socket_addrs()?.next().ok_or("无效的UDP目标地址")?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(addr)?;
+        let ssrc = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+        info!("UDP取景发送到 {} ({})", addr, if rtp { "RTP/MJPEG" } else { "原始分片" });
+        Ok(UdpSender {
+            socket,
+            rtp: rtp.then(|| RtpPacketizer::new(ssrc)),
+            frame_id: 0,
+        })
+    }
+}
+
+impl UdpSender {
+    /// 封装并发送一帧
+    fn send_frame(&mut self, data: &[u8]) -> Result<usize, Box<dyn Error>> {
+        let datagrams = match &mut self.rtp {
+            Some(rtp) => match rtp.packetize(data) {
+                Some(packets) => packets,
+                None => {
+                    debug!("取景画面不是RTP/MJPEG支持的JPEG格式，已丢弃");
+                    return Ok(0);
+                }
+            },
+            None => fragment_frame(self.frame_id, data),
+        };
+        self.frame_id = self.frame_id.wrapping_add(1);
+        let mut sent = 0;
+        for datagram in &datagrams {
+            sent += self.socket.send(datagram)?;
+        }
+        Ok(sent)
+    }
+}
+
+// UDP没有连接和确认，始终就绪
+impl DataSender for UdpSender {
+    fn send<'a>(&'a mut self, packet: &'a DataPacket) -> SenderFuture<'a, usize> {
+        Box::pin(async move { self.send_frame(&packet.data) })
+    }
+
+    fn close(&mut self) -> SenderFuture<'_, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(data: &mut Vec<u8>, marker: u8, body: &[u8]) {
+        data.extend_from_slice(&[0xFF, marker]);
+        data.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+        data.extend_from_slice(body);
+    }
+
+    /// 32x16的三分量JPEG，两张量化表，`extra`插在SOF和SOS之间
+    fn jpeg(sof: u8, sampling: u8, extra: &[(u8, &[u8])], scan_len: usize) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        for table in 0..2u8 {
+            let mut body = vec![table];
+            body.extend_from_slice(&[table + 1; 64]);
+            segment(&mut data, 0xDB, &body);
+        }
+        segment(&mut data, sof, &[8, 0, 16, 0, 32, 3, 1, sampling, 0, 2, 0x11, 1, 3, 0x11, 1]);
+        for (marker, body) in extra {
+            segment(&mut data, *marker, body);
+        }
+        segment(&mut data, 0xDA, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+        data.extend((0..scan_len).map(|i| (i % 0xF0) as u8));
+        data.extend_from_slice(&[0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn parses_baseline_420_and_422() {
+        let data = jpeg(0xC0, 0x22, &[], 100);
+        let scan = parse_jpeg(&data).unwrap();
+        assert_eq!((scan.kind, scan.width, scan.height), (1, 32, 16));
+        assert_eq!(scan.tables.len(), 2);
+        assert_eq!(scan.tables[1], &[2u8; 64][..]);
+        assert_eq!(scan.scan.len(), 100);
+
+        let data = jpeg(0xC0, 0x21, &[], 100);
+        assert_eq!(parse_jpeg(&data).unwrap().kind, 0);
+    }
+
+    #[test]
+    fn rejects_progressive_and_restart_intervals() {
+        assert_eq!(parse_jpeg(&jpeg(0xC2, 0x22, &[], 10)), None);
+        assert_eq!(parse_jpeg(&jpeg(0xC0, 0x22, &[(0xDD, &[0, 4])], 10)), None);
+        // 重启间隔为0等于没有
+        assert!(parse_jpeg(&jpeg(0xC0, 0x22, &[(0xDD, &[0, 0])], 10)).is_some());
+        assert_eq!(parse_jpeg(&jpeg(0xC0, 0x11, &[], 10)), None);
+    }
+
+    #[test]
+    fn packetize_splits_scan_with_tables_in_first_packet() {
+        let data = jpeg(0xC0, 0x22, &[], 3000);
+        let mut packetizer = RtpPacketizer::new(0x1234_5678);
+        let packets = packetizer.packetize(&data).unwrap();
+        assert_eq!(packets.len(), 3);
+
+        let table_len = 2 * 64;
+        let first_room = MAX_DATAGRAM - RTP_HEADER_LEN - JPEG_HEADER_LEN - 4 - table_len;
+        let room = MAX_DATAGRAM - RTP_HEADER_LEN - JPEG_HEADER_LEN;
+        let offsets: Vec<usize> = packets
+            .iter()
+            .map(|p| u32::from_be_bytes([0, p[13], p[14], p[15]]) as usize)
+            .collect();
+        assert_eq!(offsets, vec![0, first_room, first_room + room]);
+
+        for (index, packet) in packets.iter().enumerate() {
+            assert_eq!(packet[0], 0x80);
+            assert_eq!(packet[1] & 0x80 != 0, index == packets.len() - 1);
+            assert_eq!(packet[1] & 0x7F, RTP_PAYLOAD_JPEG);
+            assert_eq!(u16::from_be_bytes([packet[2], packet[3]]), index as u16);
+            assert_eq!(&packet[8..12], &0x1234_5678u32.to_be_bytes());
+            assert_eq!(&packet[16..20], &[1, DYNAMIC_Q, 4, 2]);
+        }
+
+        // 只有第一个分片带量化表头
+        let q = RTP_HEADER_LEN + JPEG_HEADER_LEN;
+        assert_eq!(&packets[0][q..q + 4], &[0, 0, 0, table_len as u8]);
+        assert_eq!(&packets[0][q + 4..q + 4 + 64], &[1u8; 64][..]);
+        assert_eq!(packets[0].len(), MAX_DATAGRAM);
+        assert_eq!(packets[1].len(), MAX_DATAGRAM);
+        assert_eq!(&packets[1][q..], &parse_jpeg(&data).unwrap().scan[first_room..first_room + room]);
+        assert_eq!(packets[2].len(), q + 3000 - first_room - room);
+
+        // 序号跨帧连续
+        let next = packetizer.packetize(&data).unwrap();
+        assert_eq!(u16::from_be_bytes([next[0][2], next[0][3]]), 3);
+    }
+}
@@ -0,0 +1,91 @@
+// Webhook通知 - 在传输完成、出错等事件发生时向配置的HTTP地址POST JSON
+use std::error::Error;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::time::Duration;
+
+use embedded_svc::http::client::Client;
+use embedded_svc::http::Method;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::client::{Configuration as HttpConfiguration, EspHttpConnection};
+use esp_idf_svc::sys::esp_crt_bundle_attach;
+use log::{debug, info, warn};
+
+use crate::config::WebhookConfig;
+use crate::events::{AppEvent, EventListener};
+use crate::runtime;
+
+/// 等待发送的事件数上限，接收方长时间无响应时丢弃新事件，不占用越来越多的内存
+const EVENT_QUEUE_LEN: usize = 16;
+
+/// Webhook通知器，HTTP请求在后台线程中发出，不阻塞传输流程
+pub struct WebhookNotifier {
+    tx: SyncSender<AppEvent>,
+}
+
+impl WebhookNotifier {
+    /// 启动后台发送线程
+    pub fn spawn(hooks: Vec<WebhookConfig>) -> Result<Self, Box<dyn Error>> {
+        let (tx, rx) = mpsc::sync_channel::<AppEvent>(EVENT_QUEUE_LEN);
+
+        runtime::spawn(runtime::WEBHOOK, move || {
+            for event in rx {
+                let body = match serde_json::to_vec(&event) {
+                    Ok(body) => body,
+                    Err(e) => {
+                        warn!("事件序列化失败: {}", e);
+                        continue;
+                    }
+                };
+                for hook in hooks.iter().filter(|h| h.wants(event.name())) {
+                    if let Err(e) = post_json(hook, &body) {
+                        warn!("Webhook {} 调用失败: {}", hook.url, e);
+                    }
+                }
+            }
+            info!("Webhook线程已退出");
+        })?;
+
+        Ok(WebhookNotifier { tx })
+    }
+}
+
+impl EventListener for WebhookNotifier {
+    fn on_event(&mut self, event: &AppEvent) {
+        match self.tx.try_send(event.clone()) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => warn!("Webhook队列已满，丢弃事件 {}", event.name()),
+            Err(TrySendError::Disconnected(_)) => warn!("Webhook线程已停止，丢弃事件 {}", event.name()),
+        }
+    }
+}
+
+/// 发送一次POST请求，HTTPS地址使用ESP-IDF内置的根证书包校验服务端证书
+fn post_json(hook: &WebhookConfig, body: &[u8]) -> Result<(), Box<dyn Error>> {
+    let connection = EspHttpConnection::new(&HttpConfiguration {
+        timeout: Some(Duration::from_millis(hook.timeout_ms)),
+        crt_bundle_attach: Some(esp_crt_bundle_attach as _),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+
+    let content_length = body.len().to_string();
+    let mut headers = vec![
+        ("Content-Type", "application/json"),
+        ("Content-Length", content_length.as_str()),
+    ];
+    if let Some(auth) = &hook.authorization {
+        headers.push(("Authorization", auth.as_str()));
+    }
+
+    let mut request = client.request(Method::Post, &hook.url, &headers)?;
+    request.write_all(body)?;
+    request.flush()?;
+    let response = request.submit()?;
+
+    let status = response.status();
+    if !(200..300).contains(&status) {
+        return Err(format!("HTTP状态码 {}", status).into());
+    }
+    debug!("Webhook {} 调用成功", hook.url);
+    Ok(())
+}